@@ -0,0 +1,399 @@
+//! Combines the events from several recordings' analysis reports into one
+//! report, so a week of recordings can be looked over without opening each
+//! one's report.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use chrono::{DateTime, FixedOffset, TimeDelta};
+use rayhunter::analysis::analyzer::{AnalysisRow, EventType, ReportMetadata};
+use rayhunter::redact::redact_text;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+use crate::analysis::analyze_now;
+use crate::compression::maybe_gzip_response;
+use crate::server::ServerState;
+
+/// The most recordings one aggregate report can cover, since any of them may
+/// need analyzing before the report can be sent
+pub const MAX_AGGREGATE_RECORDINGS: usize = 20;
+const DEFAULT_DEDUP_WINDOW_SECS: u64 = 300;
+/// How much of two events' messages has to match for them to count as the
+/// same event, which leaves out the details (like cell IDs) at their ends
+const DEDUP_MESSAGE_PREFIX_CHARS: usize = 40;
+
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct AggregateRequest {
+    /// The recordings to combine, at most 20
+    pub recording_names: Vec<String>,
+    /// Only include events at least this severe: informational, low, medium
+    /// or high. Defaults to low.
+    pub min_severity: Option<String>,
+    /// Events from the same analyzer whose messages start the same way are
+    /// merged if they're less than this many seconds after the first of
+    /// them. Defaults to 300, and 0 keeps every event.
+    pub dedup_window_secs: Option<u64>,
+    /// Whether to redact IMSIs, IMEIs and phone numbers, overriding the
+    /// redact_identifiers setting
+    pub redact: Option<bool>,
+}
+
+/// An event in an aggregate report
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct AggregateEvent {
+    /// The recording the event was found in
+    pub source_recording: String,
+    #[cfg_attr(feature = "apidocs", schema(value_type = Option<String>))]
+    pub packet_timestamp: Option<DateTime<FixedOffset>>,
+    pub event_type: EventType,
+    /// The name of the analyzer which emitted the event
+    pub analyzer: String,
+    pub message: String,
+    /// How many later events were merged into this one
+    pub duplicates: usize,
+}
+
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    post,
+    path = "/api/analysis-aggregate",
+    tag = "Recordings",
+    request_body = AggregateRequest,
+    responses(
+        (status = StatusCode::OK, description = "Success", body = AggregateEvent, content_type = "application/x-ndjson"),
+        (status = StatusCode::BAD_REQUEST, description = "No recordings, too many recordings or an unknown severity"),
+        (status = StatusCode::NOT_FOUND, description = "A recording wasn't found"),
+        (status = StatusCode::CONFLICT, description = "A recording is queued for analysis or being analyzed")
+    ),
+    summary = "Aggregate analysis report",
+    description = "Combine the events of up to 20 recordings' analysis reports into one NDJSON report, one event per line, sorted by when the message which triggered each event was captured. Recordings which haven't been analyzed are analyzed first. Repeats of an event within the deduplication window are merged into its first occurrence, which counts them. The report is gzip-compressed if the request's Accept-Encoding allows it."
+))]
+pub async fn aggregate_analysis(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    Json(request): Json<AggregateRequest>,
+) -> Result<Response, (StatusCode, String)> {
+    let mut seen = HashSet::new();
+    let names: Vec<&String> = request
+        .recording_names
+        .iter()
+        .filter(|name| seen.insert(name.as_str()))
+        .collect();
+    if names.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "no recordings to aggregate".to_string(),
+        ));
+    }
+    if names.len() > MAX_AGGREGATE_RECORDINGS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "can't aggregate more than {MAX_AGGREGATE_RECORDINGS} recordings at once, got {}",
+                names.len()
+            ),
+        ));
+    }
+    let min_severity = match &request.min_severity {
+        Some(severity) => severity
+            .parse::<EventType>()
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?,
+        None => EventType::Low,
+    };
+    let redact = request.redact.unwrap_or(state.config.redact_identifiers);
+
+    // a report which is being rewritten can't be read yet
+    {
+        let analysis_status = state.analysis_status_lock.read().await;
+        if let Some(name) = names.iter().find(|name| analysis_status.is_pending(name)) {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("{name} is waiting to be analyzed, try again once it's done"),
+            ));
+        }
+    }
+
+    let mut events = Vec::new();
+    for name in names {
+        let recording_events = match read_recording_events(&state, name, min_severity).await? {
+            Some(recording_events) => recording_events,
+            None => {
+                analyze_now(&state, name).await?;
+                read_recording_events(&state, name, min_severity)
+                    .await?
+                    .ok_or((
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("analyzing {name} didn't write a report"),
+                    ))?
+            }
+        };
+        events.extend(recording_events);
+    }
+    if redact {
+        for event in events.iter_mut() {
+            event.message = redact_text(&event.message).into_owned();
+        }
+    }
+
+    let dedup_window_secs = request
+        .dedup_window_secs
+        .unwrap_or(DEFAULT_DEDUP_WINDOW_SECS);
+    let lines = aggregate_events(events, dedup_window_secs)
+        .into_iter()
+        .map(|event| {
+            serde_json::to_string(&event)
+                .map(|line| line + "\n")
+                .map_err(std::io::Error::other)
+        });
+    Ok(maybe_gzip_response(
+        &headers,
+        "application/x-ndjson",
+        futures::stream::iter(lines),
+    ))
+}
+
+// Returns None if the recording needs analyzing first. The current
+// recording's report is written as it records, so it's used as it is.
+async fn read_recording_events(
+    state: &ServerState,
+    name: &str,
+    min_severity: EventType,
+) -> Result<Option<Vec<AggregateEvent>>, (StatusCode, String)> {
+    let qmdl_store = state.qmdl_store_lock.read().await;
+    let (entry_index, _) = qmdl_store.entry_for_name(name).ok_or((
+        StatusCode::NOT_FOUND,
+        format!("couldn't find manifest entry with name {name}"),
+    ))?;
+    let is_current = qmdl_store.current_entry == Some(entry_index);
+    let events = match qmdl_store.open_entry_analysis(entry_index).await {
+        Ok(file) => read_report_events(name, file, min_severity)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:?}")))?,
+        Err(_) => None,
+    };
+    match events {
+        None if is_current => Ok(Some(Vec::new())),
+        events => Ok(events),
+    }
+}
+
+/// Reads the events at least `min_severity` from an analysis report, or
+/// None if the report is empty or has no metadata, i.e. the recording hasn't
+/// been analyzed
+pub async fn read_report_events<R: AsyncRead + Unpin>(
+    recording: &str,
+    report: R,
+    min_severity: EventType,
+) -> std::io::Result<Option<Vec<AggregateEvent>>> {
+    let mut lines = BufReader::new(report).lines();
+    let Some(metadata) = lines
+        .next_line()
+        .await?
+        .and_then(|line| serde_json::from_str::<ReportMetadata>(&line).ok())
+    else {
+        return Ok(None);
+    };
+
+    let mut events = Vec::new();
+    while let Some(line) = lines.next_line().await? {
+        let Ok(row) = serde_json::from_str::<AnalysisRow>(&line) else {
+            continue;
+        };
+        for (index, event) in row.events.into_iter().enumerate() {
+            let Some(event) = event.filter(|event| event.event_type >= min_severity) else {
+                continue;
+            };
+            // reports written before events named their analyzer have them
+            // in the same order as the metadata's analyzers
+            let analyzer = if event.analyzer.is_empty() {
+                metadata
+                    .analyzers
+                    .get(index)
+                    .map(|analyzer| analyzer.name.clone())
+                    .unwrap_or_default()
+            } else {
+                event.analyzer
+            };
+            events.push(AggregateEvent {
+                source_recording: recording.to_string(),
+                packet_timestamp: row.packet_timestamp,
+                event_type: event.event_type,
+                analyzer,
+                message: event.message,
+                duplicates: 0,
+            });
+        }
+    }
+    Ok(Some(events))
+}
+
+/// Sorts events by when they were captured, and merges each event into an
+/// earlier one from the same analyzer with the same start to its message, if
+/// that one was less than `dedup_window_secs` before it. Events without a
+/// timestamp come first and are never merged.
+pub fn aggregate_events(
+    mut events: Vec<AggregateEvent>,
+    dedup_window_secs: u64,
+) -> Vec<AggregateEvent> {
+    // stable, so events from the same message keep their order
+    events.sort_by_key(|event| event.packet_timestamp);
+    let window = i64::try_from(dedup_window_secs)
+        .ok()
+        .and_then(TimeDelta::try_seconds)
+        .unwrap_or(TimeDelta::MAX);
+
+    let mut aggregated: Vec<AggregateEvent> = Vec::new();
+    // the index in `aggregated` of the latest event kept for each key
+    let mut kept: HashMap<(String, String), usize> = HashMap::new();
+    for event in events {
+        let Some(timestamp) = event.packet_timestamp.filter(|_| dedup_window_secs > 0) else {
+            aggregated.push(event);
+            continue;
+        };
+        let key = (
+            event.analyzer.clone(),
+            event
+                .message
+                .chars()
+                .take(DEDUP_MESSAGE_PREFIX_CHARS)
+                .collect(),
+        );
+        if let Some(&index) = kept.get(&key)
+            && let Some(first) = aggregated[index].packet_timestamp
+            && timestamp - first < window
+        {
+            aggregated[index].duplicates += 1;
+            continue;
+        }
+        kept.insert(key, aggregated.len());
+        aggregated.push(event);
+    }
+    aggregated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(recording: &str, secs: Option<i64>, analyzer: &str, message: &str) -> AggregateEvent {
+        AggregateEvent {
+            source_recording: recording.to_string(),
+            packet_timestamp: secs
+                .map(|secs| DateTime::from_timestamp(secs, 0).unwrap().fixed_offset()),
+            event_type: EventType::High,
+            analyzer: analyzer.to_string(),
+            message: message.to_string(),
+            duplicates: 0,
+        }
+    }
+
+    fn summarize(events: &[AggregateEvent]) -> Vec<(&str, Option<i64>, usize)> {
+        events
+            .iter()
+            .map(|event| {
+                (
+                    event.source_recording.as_str(),
+                    event.packet_timestamp.map(|t| t.timestamp()),
+                    event.duplicates,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_aggregate_sorts_and_merges_across_recordings() {
+        let events = vec![
+            event(
+                "b",
+                Some(200),
+                "IMSI Requested",
+                "IMSI requested in NAS message",
+            ),
+            event(
+                "a",
+                Some(100),
+                "IMSI Requested",
+                "IMSI requested in NAS message",
+            ),
+            event("a", Some(150), "Null Cipher", "Cell suggested null cipher"),
+            event(
+                "b",
+                Some(500),
+                "IMSI Requested",
+                "IMSI requested in NAS message",
+            ),
+            event("a", None, "IMSI Requested", "IMSI requested in NAS message"),
+        ];
+        let aggregated = aggregate_events(events, 300);
+        assert_eq!(
+            summarize(&aggregated),
+            [
+                ("a", None, 0),
+                ("a", Some(100), 1),
+                ("a", Some(150), 0),
+                ("b", Some(500), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_compares_message_prefixes() {
+        let prefix = "x".repeat(DEDUP_MESSAGE_PREFIX_CHARS);
+        let events = vec![
+            event("a", Some(0), "Analyzer", &format!("{prefix} cell 1")),
+            event("a", Some(1), "Analyzer", &format!("{prefix} cell 2")),
+            event("a", Some(2), "Other Analyzer", &format!("{prefix} cell 2")),
+            event("a", Some(3), "Analyzer", "something else entirely"),
+        ];
+        let aggregated = aggregate_events(events.clone(), 300);
+        assert_eq!(
+            summarize(&aggregated),
+            [("a", Some(0), 1), ("a", Some(2), 0), ("a", Some(3), 0)]
+        );
+
+        // a window of 0 keeps everything
+        assert_eq!(aggregate_events(events, 0).len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_read_report_events() {
+        let report = concat!(
+            r#"{"analyzers":[{"name":"First","description":"","version":1},{"name":"Second","description":"","version":1}],"rayhunter":{"rayhunter_version":"0.1.0","system_os":"test","arch":"test"},"report_version":2}"#,
+            "\n",
+            r#"{"packet_timestamp":"2024-01-01T00:00:00Z","skipped_message_reason":null,"events":[{"event_type":"Low","message":"low"},null]}"#,
+            "\n",
+            "not json\n",
+            r#"{"packet_timestamp":"2024-01-01T00:00:10Z","skipped_message_reason":null,"events":[null,{"event_type":"High","message":"high"}]}"#,
+            "\n",
+        );
+        let events = read_report_events("rec", report.as_bytes(), EventType::Low)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].analyzer, "First");
+        assert_eq!(events[1].analyzer, "Second");
+        assert_eq!(events[1].source_recording, "rec");
+
+        let events = read_report_events("rec", report.as_bytes(), EventType::Medium)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].message, "high");
+
+        // nothing written yet
+        assert_eq!(
+            read_report_events("rec", &b""[..], EventType::Low)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
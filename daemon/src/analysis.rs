@@ -1,29 +1,132 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::{cmp, future, pin};
+use std::time::{Duration, Instant};
 
 use axum::Json;
+use axum::body::Bytes;
+use axum::response::{IntoResponse, Response};
 use axum::{
     extract::{Path, State},
     http::StatusCode,
 };
-use futures::TryStreamExt;
-use log::{error, info};
-use rayhunter::analysis::analyzer::{AnalyzerConfig, EventType, Harness};
+use chrono::{DateTime, FixedOffset};
+use log::{error, info, warn};
+use rayhunter::analysis::analyzer::{AnalysisRow, AnalyzerConfig, Event, EventType, Harness};
 use rayhunter::diag::{DataType, MessagesContainer};
+use rayhunter::location::GeoPoint;
 use rayhunter::qmdl::QmdlReader;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tokio::fs::File;
 use tokio::io::{AsyncWriteExt, BufWriter};
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::{RwLock, RwLockWriteGuard};
+use tokio::task::JoinSet;
 use tokio_util::task::TaskTracker;
 
-use crate::qmdl_store::RecordingStore;
+use crate::clock_jump::ClockJump;
+use crate::diag_multiplexer::ParseErrorCounter;
+use crate::disk_space::has_room_for_auxiliary_write;
+use crate::event_store::{EventStore, StoredEvent};
+use crate::live_tail::{LiveTail, LiveTailEntry};
+use crate::qmdl_store::{ManifestEntry, PauseInterval, RecordingStore};
 use crate::server::ServerState;
 
+/// How many events of each severity were found
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct SeverityCounts {
+    pub high: usize,
+    pub medium: usize,
+    pub low: usize,
+    pub informational: usize,
+}
+
+impl SeverityCounts {
+    fn add(&mut self, event_type: EventType) {
+        match event_type {
+            EventType::High => self.high += 1,
+            EventType::Medium => self.medium += 1,
+            EventType::Low => self.low += 1,
+            EventType::Informational => self.informational += 1,
+        }
+    }
+}
+
+/// A breakdown of the events found in a recording, saved next to its
+/// analysis report once analysis completes
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct AnalysisSummary {
+    pub total_events: usize,
+    /// Event counts for each analyzer which ran, keyed by analyzer name
+    pub by_analyzer: BTreeMap<String, SeverityCounts>,
+    pub by_severity: SeverityCounts,
+    /// Time spent analyzing the recording, in milliseconds
+    pub analysis_duration_ms: u64,
+}
+
+impl AnalysisSummary {
+    fn new(analyzer_names: &[String]) -> Self {
+        AnalysisSummary {
+            by_analyzer: analyzer_names
+                .iter()
+                .map(|name| (name.clone(), SeverityCounts::default()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    // The i-th event in a row always belongs to the i-th analyzer
+    fn add_row(&mut self, row: &AnalysisRow, analyzer_names: &[String]) {
+        for (event, name) in row.events.iter().zip(analyzer_names) {
+            let Some(event) = event else {
+                continue;
+            };
+            self.total_events += 1;
+            self.by_severity.add(event.event_type);
+            self.by_analyzer
+                .entry(name.clone())
+                .or_default()
+                .add(event.event_type);
+        }
+    }
+}
+
+/// Loads the summary written when the recording was analyzed, if any.
+pub async fn read_analysis_summary(path: &std::path::Path) -> Option<AnalysisSummary> {
+    let contents = tokio::fs::read(path).await.ok()?;
+    match serde_json::from_slice(&contents) {
+        Ok(summary) => Some(summary),
+        Err(e) => {
+            warn!("failed to parse analysis summary {path:?}: {e}");
+            None
+        }
+    }
+}
+
+/// The most severe event [AnalysisWriter::analyze] found in a container
+pub struct MaxEvent {
+    pub event_type: EventType,
+    /// When the message which triggered it was captured
+    pub message_timestamp: Option<DateTime<FixedOffset>>,
+    /// The event itself, unless nothing more severe than informational was
+    /// found
+    pub event: Option<Event>,
+}
+
 pub struct AnalysisWriter {
-    writer: BufWriter<File>,
+    /// None in monitor-only mode, where the analysis isn't kept
+    writer: Option<BufWriter<File>>,
     harness: Harness,
+    analyzer_names: Vec<String>,
+    packet_num: usize,
+    event_store: Option<(Arc<RwLock<EventStore>>, String)>,
+    summary: AnalysisSummary,
+    summary_path: Option<PathBuf>,
+    analysis_time: Duration,
+    live_tail: Option<LiveTail>,
+    parse_errors: Option<ParseErrorCounter>,
 }
 
 // We write our analysis results to a file immediately to minimize the amount of
@@ -33,46 +136,207 @@ pub struct AnalysisWriter {
 // lets us simply append new rows to the end without parsing the entire JSON
 // object beforehand.
 impl AnalysisWriter {
-    pub async fn new(file: File, analyzer_config: &AnalyzerConfig) -> Result<Self, std::io::Error> {
-        let harness = Harness::new_with_config(analyzer_config);
+    pub async fn new(
+        file: File,
+        analyzer_config: &AnalyzerConfig,
+        start_location: Option<GeoPoint>,
+    ) -> Result<Self, std::io::Error> {
+        let mut result = Self::monitor_only(analyzer_config);
+        result.writer = Some(BufWriter::new(file));
 
-        let mut result = Self {
-            writer: BufWriter::new(file),
-            harness,
-        };
-        let metadata = result.harness.get_metadata();
+        let mut metadata = result.harness.get_metadata();
+        metadata.start_location = start_location;
         result.write(&metadata).await?;
         Ok(result)
     }
 
+    /// A writer which runs the analyzers without writing their results
+    /// anywhere, for monitor-only mode. Events still reach the event store,
+    /// live tail and so on if they're set up.
+    pub fn monitor_only(analyzer_config: &AnalyzerConfig) -> Self {
+        let harness = Harness::new_with_config(analyzer_config);
+        let analyzer_names: Vec<String> = harness
+            .get_metadata()
+            .analyzers
+            .iter()
+            .map(|a| a.name.clone())
+            .collect();
+        Self {
+            writer: None,
+            harness,
+            summary: AnalysisSummary::new(&analyzer_names),
+            analyzer_names,
+            packet_num: 0,
+            event_store: None,
+            summary_path: None,
+            analysis_time: Duration::ZERO,
+            live_tail: None,
+            parse_errors: None,
+        }
+    }
+
+    /// Also record every event found in the named recording to the
+    /// long-term event store.
+    pub fn with_event_store(
+        mut self,
+        event_store_lock: Arc<RwLock<EventStore>>,
+        recording_name: &str,
+    ) -> Self {
+        self.event_store = Some((event_store_lock, recording_name.to_string()));
+        self
+    }
+
+    /// Write an [AnalysisSummary] to the given path when the writer is closed.
+    pub fn with_summary(mut self, summary_path: PathBuf) -> Self {
+        self.summary_path = Some(summary_path);
+        self
+    }
+
+    /// Also add a summary of every decoded message to the live tail, while
+    /// anyone is watching it.
+    pub fn with_live_tail(mut self, live_tail: LiveTail) -> Self {
+        if live_tail.is_enabled() {
+            self.live_tail = Some(live_tail);
+        }
+        self
+    }
+
+    /// Count messages which couldn't be parsed.
+    pub fn with_parse_error_counter(mut self, parse_errors: ParseErrorCounter) -> Self {
+        self.parse_errors = Some(parse_errors);
+        self
+    }
+
+    /// The PLMN, as "MCC-MNC", of the cell whose SIB1 was analyzed last
+    pub fn serving_plmn(&self) -> Option<&str> {
+        self.harness.serving_plmn()
+    }
+
     // Runs the analysis harness on the given container, serializing the results
-    // to the analysis file, returning the whether any warnings were detected
+    // to the analysis file, returning the most severe event detected
     pub async fn analyze(
         &mut self,
         container: MessagesContainer,
-    ) -> Result<EventType, std::io::Error> {
-        let mut max_type = EventType::Informational;
+    ) -> Result<MaxEvent, std::io::Error> {
+        let mut max_event = MaxEvent {
+            event_type: EventType::Informational,
+            message_timestamp: None,
+            event: None,
+        };
+        let mut events = Vec::new();
 
-        for row in self.harness.analyze_qmdl_messages(container) {
+        if let Some(live_tail) = &self.live_tail {
+            self.harness.set_summarize_messages(live_tail.is_watched());
+        }
+        let start = Instant::now();
+        let parse_errors_before = self.harness.parse_errors();
+        let rows = self.harness.analyze_qmdl_messages(container);
+        self.analysis_time += start.elapsed();
+        if let Some(parse_errors) = &self.parse_errors {
+            parse_errors.add(self.harness.parse_errors() - parse_errors_before);
+        }
+        for row in rows {
+            self.packet_num += 1;
+            self.summary.add_row(&row, &self.analyzer_names);
             if !row.is_empty() {
                 self.write(&row).await?;
             }
-            max_type = cmp::max(max_type, row.get_max_event_type());
+            if let Some((_, recording_name)) = &self.event_store {
+                events.extend(StoredEvent::from_row(
+                    &row,
+                    recording_name,
+                    self.packet_num,
+                    &self.analyzer_names,
+                ));
+            }
+            if let Some(live_tail) = &self.live_tail
+                && let Some(entry) =
+                    LiveTailEntry::from_row(&row, self.packet_num, &self.analyzer_names)
+            {
+                live_tail.push(entry);
+            }
+            let row_max_type = row.get_max_event_type();
+            if row_max_type > max_event.event_type {
+                max_event = MaxEvent {
+                    event_type: row_max_type,
+                    message_timestamp: row.packet_timestamp,
+                    event: row
+                        .events
+                        .iter()
+                        .flatten()
+                        .find(|event| event.event_type == row_max_type)
+                        .cloned(),
+                };
+            }
+        }
+
+        if let Some((event_store_lock, _)) = &self.event_store
+            && !events.is_empty()
+            && let Err(e) = event_store_lock.write().await.insert(events).await
+        {
+            warn!("failed to record events to event store: {e}");
         }
-        Ok(max_type)
+        Ok(max_event)
+    }
+
+    /// Writes a row marking the gap left in the recording by `pause`. It
+    /// isn't a message, so it doesn't count towards the packet numbers or
+    /// the summary.
+    pub async fn write_pause_marker(
+        &mut self,
+        pause: &PauseInterval,
+    ) -> Result<(), std::io::Error> {
+        self.write_marker(pause.start.fixed_offset(), pause.describe())
+            .await
+    }
+
+    /// Writes a row marking where the system clock jumped, like
+    /// [Self::write_pause_marker]
+    pub async fn write_clock_jump_marker(
+        &mut self,
+        jump: &ClockJump,
+    ) -> Result<(), std::io::Error> {
+        self.write_marker(jump.after.fixed_offset(), jump.describe())
+            .await
+    }
+
+    async fn write_marker(
+        &mut self,
+        timestamp: DateTime<FixedOffset>,
+        description: String,
+    ) -> Result<(), std::io::Error> {
+        let row = AnalysisRow {
+            packet_timestamp: Some(timestamp),
+            skipped_message_reason: Some(description),
+            events: Vec::new(),
+            message_summary: None,
+        };
+        self.write(&row).await
     }
 
     async fn write<T: Serialize>(&mut self, value: &T) -> Result<(), std::io::Error> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
         let mut value_str = serde_json::to_string(value).unwrap();
         value_str.push('\n');
-        self.writer.write_all(value_str.as_bytes()).await?;
-        self.writer.flush().await?;
+        writer.write_all(value_str.as_bytes()).await?;
+        writer.flush().await?;
         Ok(())
     }
 
     // Flushes any pending I/O to disk before dropping the writer
     pub async fn close(mut self) -> Result<(), std::io::Error> {
-        self.writer.flush().await?;
+        if let Some(writer) = &mut self.writer {
+            writer.flush().await?;
+        }
+        if let Some(summary_path) = &self.summary_path
+            && has_room_for_auxiliary_write(summary_path, "analysis summary")
+        {
+            self.summary.analysis_duration_ms = self.analysis_time.as_millis() as u64;
+            let summary = serde_json::to_vec(&self.summary).unwrap();
+            tokio::fs::write(summary_path, summary).await?;
+        }
         Ok(())
     }
 }
@@ -83,8 +347,8 @@ impl AnalysisWriter {
 pub struct AnalysisStatus {
     /// The vector array of queued files
     queued: Vec<String>,
-    /// The file currently being analyzed
-    running: Option<String>,
+    /// The files currently being analyzed
+    running: Vec<String>,
     /// The vector array of finished files
     finished: Vec<String>,
 }
@@ -99,10 +363,15 @@ impl AnalysisStatus {
             .collect();
         AnalysisStatus {
             queued: Vec::new(),
-            running: None,
+            running: Vec::new(),
             finished: existing_recordings,
         }
     }
+
+    /// Whether `name` is waiting to be analyzed or being analyzed
+    pub fn is_pending(&self, name: &str) -> bool {
+        self.queued.iter().chain(&self.running).any(|n| n == name)
+    }
 }
 
 pub enum AnalysisCtrlMessage {
@@ -111,35 +380,38 @@ pub enum AnalysisCtrlMessage {
     Exit,
 }
 
-async fn queued_len(analysis_status_lock: Arc<RwLock<AnalysisStatus>>) -> usize {
-    analysis_status_lock.read().await.queued.len()
-}
-
-async fn dequeue_to_running(analysis_status_lock: Arc<RwLock<AnalysisStatus>>) -> String {
+async fn dequeue_to_running(analysis_status_lock: &RwLock<AnalysisStatus>) -> Option<String> {
     let mut analysis_status = analysis_status_lock.write().await;
+    if analysis_status.queued.is_empty() {
+        return None;
+    }
     let name = analysis_status.queued.remove(0);
-    assert!(analysis_status.running.is_none());
-    analysis_status.running = Some(name.clone());
-    name
+    analysis_status.running.push(name.clone());
+    Some(name)
 }
 
-async fn finish_running_analysis(analysis_status_lock: Arc<RwLock<AnalysisStatus>>) {
+async fn finish_running_analysis(analysis_status_lock: &RwLock<AnalysisStatus>, name: String) {
     let mut analysis_status = analysis_status_lock.write().await;
-    let finished = analysis_status.running.take().unwrap();
-    analysis_status.finished.push(finished);
+    analysis_status.running.retain(|n| *n != name);
+    analysis_status.finished.push(name);
 }
 
 async fn perform_analysis(
     name: &str,
     qmdl_store_lock: Arc<RwLock<RecordingStore>>,
+    event_store_lock: Arc<RwLock<EventStore>>,
     analyzer_config: &AnalyzerConfig,
 ) -> Result<(), String> {
     info!("Opening QMDL and analysis file for {name}...");
-    let (analysis_file, qmdl_file) = {
+    let (analysis_file, qmdl_file, summary_path, start_location, pauses, clock_jumps) = {
         let mut qmdl_store = qmdl_store_lock.write().await;
-        let (entry_index, _) = qmdl_store
+        let (entry_index, entry) = qmdl_store
             .entry_for_name(name)
             .ok_or(format!("failed to find QMDL store entry for {name}"))?;
+        let summary_path = entry.get_summary_filepath(&qmdl_store.path);
+        let start_location = entry.start_location;
+        let pauses = entry.pauses.clone();
+        let clock_jumps = entry.clock_jumps.clone();
         let analysis_file = qmdl_store
             .clear_and_open_entry_analysis(entry_index)
             .await
@@ -149,35 +421,69 @@ async fn perform_analysis(
             .await
             .map_err(|e| format!("{e:?}"))?;
 
-        (analysis_file, qmdl_file)
+        (
+            analysis_file,
+            qmdl_file,
+            summary_path,
+            start_location,
+            pauses,
+            clock_jumps,
+        )
     };
 
-    let mut analysis_writer = AnalysisWriter::new(analysis_file, analyzer_config)
+    let mut analysis_writer = AnalysisWriter::new(analysis_file, analyzer_config, start_location)
         .await
-        .map_err(|e| format!("{e:?}"))?;
+        .map_err(|e| format!("{e:?}"))?
+        .with_event_store(event_store_lock, name)
+        .with_summary(summary_path);
     let file_size = qmdl_file
         .metadata()
         .await
         .expect("failed to get QMDL file metadata")
         .len();
     let mut qmdl_reader = QmdlReader::new(qmdl_file, Some(file_size as usize));
-    let mut qmdl_stream = pin::pin!(
-        qmdl_reader
-            .as_stream()
-            .try_filter(|container| future::ready(container.data_type == DataType::UserSpace))
-    );
+    let mut pauses = pauses.iter().peekable();
+    let mut clock_jumps = clock_jumps.iter().peekable();
 
     info!("Starting analysis for {name}...");
-    while let Some(container) = qmdl_stream
-        .try_next()
-        .await
-        .expect("failed getting QMDL container")
-    {
+    loop {
+        let offset = qmdl_reader.bytes_read();
+        let Some(container) = qmdl_reader
+            .get_next_messages_container()
+            .await
+            .expect("failed getting QMDL container")
+        else {
+            break;
+        };
+        // mark each pause just before the first message recorded after it
+        while let Some(pause) = pauses.next_if(|pause| pause.qmdl_offset <= offset) {
+            analysis_writer
+                .write_pause_marker(pause)
+                .await
+                .map_err(|e| format!("{e:?}"))?;
+        }
+        // and likewise each clock jump
+        while let Some(jump) = clock_jumps.next_if(|jump| jump.qmdl_offset <= offset) {
+            analysis_writer
+                .write_clock_jump_marker(jump)
+                .await
+                .map_err(|e| format!("{e:?}"))?;
+        }
+        if container.data_type != DataType::UserSpace {
+            continue;
+        }
         let _ = analysis_writer
             .analyze(container)
             .await
             .map_err(|e| format!("{e:?}"))?;
     }
+    // and pauses which nothing was recorded after
+    for pause in pauses {
+        analysis_writer
+            .write_pause_marker(pause)
+            .await
+            .map_err(|e| format!("{e:?}"))?;
+    }
 
     analysis_writer
         .close()
@@ -188,36 +494,157 @@ async fn perform_analysis(
     Ok(())
 }
 
+/// Runs [perform_analysis] on a blocking thread. The daemon's runtime only has
+/// one thread, so analyses spawned as tasks on it would take turns instead of
+/// running in parallel, and hold up everything else while they decode.
+async fn perform_analysis_blocking(
+    name: String,
+    qmdl_store_lock: Arc<RwLock<RecordingStore>>,
+    event_store_lock: Arc<RwLock<EventStore>>,
+    analyzer_config: AnalyzerConfig,
+) -> Result<(), String> {
+    let runtime = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || {
+        runtime.block_on(perform_analysis(
+            &name,
+            qmdl_store_lock,
+            event_store_lock,
+            &analyzer_config,
+        ))
+    })
+    .await
+    .map_err(|e| format!("analysis thread failed: {e}"))?
+}
+
+/// Analyzes `name` straight away instead of queueing it, for requests which
+/// need its report before they can respond. It's listed as running in the
+/// analysis status meanwhile, and if it's already queued or running, this
+/// fails with 409 Conflict.
+pub async fn analyze_now(state: &ServerState, name: &str) -> Result<(), (StatusCode, String)> {
+    {
+        let mut analysis_status = state.analysis_status_lock.write().await;
+        if analysis_status.is_pending(name) {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("{name} is waiting to be analyzed, try again once it's done"),
+            ));
+        }
+        analysis_status.running.push(name.to_string());
+    }
+    // the analysis runs as its own task, so it's still taken off the running
+    // list if the request which wanted it goes away first
+    let analysis_status_lock = state.analysis_status_lock.clone();
+    let name = name.to_string();
+    let analysis = perform_analysis_blocking(
+        name.clone(),
+        state.qmdl_store_lock.clone(),
+        state.event_store_lock.clone(),
+        state.config.analyzers.clone(),
+    );
+    tokio::spawn(async move {
+        let result = analysis.await;
+        finish_running_analysis(&analysis_status_lock, name).await;
+        result
+    })
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("analysis task failed: {e}"),
+        )
+    })?
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
 pub fn run_analysis_thread(
     task_tracker: &TaskTracker,
-    mut analysis_rx: Receiver<AnalysisCtrlMessage>,
+    analysis_rx: Receiver<AnalysisCtrlMessage>,
     qmdl_store_lock: Arc<RwLock<RecordingStore>>,
     analysis_status_lock: Arc<RwLock<AnalysisStatus>>,
+    event_store_lock: Arc<RwLock<EventStore>>,
     analyzer_config: AnalyzerConfig,
+    max_workers: usize,
 ) {
-    task_tracker.spawn(async move {
-        loop {
-            match analysis_rx.recv().await {
-                Some(AnalysisCtrlMessage::NewFilesQueued) => {
-                    let count = queued_len(analysis_status_lock.clone()).await;
-                    for _ in 0..count {
-                        let name = dequeue_to_running(analysis_status_lock.clone()).await;
-                        if let Err(err) =
-                            perform_analysis(&name, qmdl_store_lock.clone(), &analyzer_config).await
-                        {
-                            error!("failed to analyze {name}: {err}");
-                        }
-                        finish_running_analysis(analysis_status_lock.clone()).await;
+    let analyze = move |name: String| {
+        let qmdl_store_lock = qmdl_store_lock.clone();
+        let event_store_lock = event_store_lock.clone();
+        let analyzer_config = analyzer_config.clone();
+        async move {
+            if let Err(err) = perform_analysis_blocking(
+                name.clone(),
+                qmdl_store_lock,
+                event_store_lock,
+                analyzer_config,
+            )
+            .await
+            {
+                error!("failed to analyze {name}: {err}");
+            }
+        }
+    };
+    task_tracker.spawn(run_analysis_workers(
+        analysis_rx,
+        analysis_status_lock,
+        max_workers,
+        analyze,
+    ));
+}
+
+// Analyzes queued recordings, running up to `max_workers` analyses at once.
+// Each analysis gets its own task, so one that panics on a corrupt QMDL
+// doesn't take the rest of the queue down with it.
+async fn run_analysis_workers<F, Fut>(
+    mut analysis_rx: Receiver<AnalysisCtrlMessage>,
+    analysis_status_lock: Arc<RwLock<AnalysisStatus>>,
+    max_workers: usize,
+    analyze: F,
+) where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let max_workers = max_workers.max(1);
+    let mut workers = JoinSet::new();
+    let mut worker_names = HashMap::new();
+    loop {
+        while workers.len() < max_workers
+            && let Some(name) = dequeue_to_running(&analysis_status_lock).await
+        {
+            let handle = workers.spawn(analyze(name.clone()));
+            worker_names.insert(handle.id(), name);
+        }
+
+        tokio::select! {
+            Some(result) = workers.join_next_with_id(), if !workers.is_empty() => {
+                let id = match result {
+                    Ok((id, ())) => id,
+                    Err(err) => {
+                        error!("analysis worker failed: {err}");
+                        err.id()
                     }
+                };
+                if let Some(name) = worker_names.remove(&id) {
+                    finish_running_analysis(&analysis_status_lock, name).await;
                 }
+            }
+            msg = analysis_rx.recv() => match msg {
+                // the next loop iteration hands the new files to free workers
+                Some(AnalysisCtrlMessage::NewFilesQueued) => {}
                 Some(AnalysisCtrlMessage::RecordingFinished(name)) => {
                     let mut status = analysis_status_lock.write().await;
                     status.finished.push(name);
                 }
-                Some(AnalysisCtrlMessage::Exit) | None => return,
+                Some(AnalysisCtrlMessage::Exit) | None => {
+                    while let Some(result) = workers.join_next_with_id().await {
+                        let id = result.map_or_else(|err| err.id(), |(id, ())| id);
+                        if let Some(name) = worker_names.remove(&id) {
+                            finish_running_analysis(&analysis_status_lock, name).await;
+                        }
+                    }
+                    return;
+                }
             }
         }
-    });
+    }
 }
 
 #[cfg_attr(feature = "apidocs", utoipa::path(
@@ -237,49 +664,101 @@ pub async fn get_analysis_status(
 }
 
 fn queue_qmdl(name: &str, analysis_status: &mut RwLockWriteGuard<AnalysisStatus>) -> bool {
-    if analysis_status.queued.iter().any(|n| n == name)
-        || analysis_status.running.iter().any(|n| n == name)
-    {
+    if analysis_status.is_pending(name) {
         return false;
     }
     analysis_status.queued.push(name.to_string());
     true
 }
 
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    get,
+    path = "/api/analysis-summary/{name}",
+    tag = "Recordings",
+    responses(
+        (status = StatusCode::OK, description = "Success", body = AnalysisSummary),
+        (status = StatusCode::NOT_FOUND, description = "Could not find file {name}, or it hasn't been analyzed yet")
+    ),
+    params(
+        ("name" = String, Path, description = "QMDL file to get the analysis summary of")
+    ),
+    summary = "Analysis summary",
+    description = "Show how many events of each severity each analyzer found in QMDL file {name}. The summary is written when analysis completes, so it's unavailable for the current recording."
+))]
+pub async fn get_analysis_summary(
+    State(state): State<Arc<ServerState>>,
+    Path(qmdl_name): Path<String>,
+) -> Result<Json<AnalysisSummary>, (StatusCode, String)> {
+    let summary_path = {
+        let qmdl_store = state.qmdl_store_lock.read().await;
+        let (_, entry) = qmdl_store.entry_for_name(&qmdl_name).ok_or((
+            StatusCode::NOT_FOUND,
+            format!("couldn't find manifest entry with name {qmdl_name}"),
+        ))?;
+        entry.get_summary_filepath(&qmdl_store.path)
+    };
+    read_analysis_summary(&summary_path).await.map(Json).ok_or((
+        StatusCode::NOT_FOUND,
+        format!("no analysis summary for {qmdl_name}"),
+    ))
+}
+
+/// Why a recording was refused for analysis
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct RecordingTooSmall {
+    /// Always "too_small"
+    #[cfg_attr(feature = "apidocs", schema(value_type = String))]
+    pub error: &'static str,
+    pub size_bytes: u64,
+    /// min_recording_size_to_analyze_bytes from the config
+    pub minimum: u64,
+}
+
 #[cfg_attr(feature = "apidocs", utoipa::path(
     post,
     path = "/api/analysis/{name}",
     tag = "Recordings",
     responses(
         (status = StatusCode::ACCEPTED, description = "Success"),
+        (status = StatusCode::BAD_REQUEST, description = "The recording is smaller than min_recording_size_to_analyze_bytes", body = RecordingTooSmall),
         (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Unable to queue analysis file")
     ),
     params(
         ("name" = String, Path, description = "QMDL file to analyze")
     ),
     summary = "Start analysis",
-    description = "Begin analysis of QMDL file {name}."
+    description = "Begin analysis of QMDL file {name}. If {name} is empty, every recording except the current one is queued, skipping those smaller than min_recording_size_to_analyze_bytes."
 ))]
 pub async fn start_analysis(
     State(state): State<Arc<ServerState>>,
     Path(qmdl_name): Path<String>,
-) -> Result<(StatusCode, Json<AnalysisStatus>), (StatusCode, String)> {
+) -> Result<(StatusCode, Json<AnalysisStatus>), Response> {
+    let min_size_bytes = state.config.min_recording_size_to_analyze_bytes;
     let mut analysis_status = state.analysis_status_lock.write().await;
     let store = state.qmdl_store_lock.read().await;
     let queued = if qmdl_name.is_empty() {
-        let mut entry_names: Vec<&str> = store
-            .manifest
-            .entries
-            .iter()
-            .map(|e| e.name.as_str())
-            .collect();
+        let mut entries: Vec<&ManifestEntry> = store.manifest.entries.iter().collect();
         if let Some(current_entry) = store.current_entry {
-            entry_names.remove(current_entry);
+            entries.remove(current_entry);
         }
-        entry_names
+        entries
             .iter()
-            .any(|name| queue_qmdl(name, &mut analysis_status))
+            .filter(|entry| entry.qmdl_size_bytes as u64 >= min_size_bytes)
+            .any(|entry| queue_qmdl(&entry.name, &mut analysis_status))
     } else {
+        // a name which isn't in the manifest is queued anyway, and fails
+        // once the analysis runs
+        if let Some((_, entry)) = store.entry_for_name(&qmdl_name)
+            && (entry.qmdl_size_bytes as u64) < min_size_bytes
+        {
+            let too_small = RecordingTooSmall {
+                error: "too_small",
+                size_bytes: entry.qmdl_size_bytes as u64,
+                minimum: min_size_bytes,
+            };
+            return Err((StatusCode::BAD_REQUEST, Json(too_small)).into_response());
+        }
         queue_qmdl(&qmdl_name, &mut analysis_status)
     };
     if queued {
@@ -292,7 +771,176 @@ pub async fn start_analysis(
                     StatusCode::INTERNAL_SERVER_ERROR,
                     format!("failed to queue new analysis files: {e:?}"),
                 )
+                    .into_response()
             })?;
     }
     Ok((StatusCode::ACCEPTED, Json(analysis_status.clone())))
 }
+
+/// The largest QMDL file /api/import-qmdl accepts. It's held in memory while
+/// it's stored, so this is well above a long recording but well below the
+/// device's RAM.
+pub const MAX_IMPORT_QMDL_BYTES: usize = 64 * 1024 * 1024;
+
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    post,
+    path = "/api/import-qmdl",
+    tag = "Recordings",
+    request_body(content = Vec<u8>, content_type = "application/octet-stream", description = "Raw QMDL file contents"),
+    responses(
+        (status = StatusCode::CREATED, description = "Recording imported and queued for analysis, returns its name"),
+        (status = StatusCode::BAD_REQUEST, description = "Empty QMDL file"),
+        (status = StatusCode::FORBIDDEN, description = "System is in debug mode"),
+        (status = StatusCode::PAYLOAD_TOO_LARGE, description = "QMDL file is larger than 64 MiB"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Unable to store or queue the QMDL file")
+    ),
+    summary = "Import a QMDL file",
+    description = "Store an uploaded QMDL file as a new recording and queue it for analysis. The recording's name is returned."
+))]
+pub async fn import_qmdl(
+    State(state): State<Arc<ServerState>>,
+    qmdl_data: Bytes,
+) -> Result<(StatusCode, String), (StatusCode, String)> {
+    if state.config.debug_mode {
+        return Err((StatusCode::FORBIDDEN, "server is in debug mode".to_string()));
+    }
+    if qmdl_data.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "empty QMDL file".to_string()));
+    }
+
+    let name = state
+        .qmdl_store_lock
+        .write()
+        .await
+        .import_entry(&qmdl_data)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to store imported QMDL file: {e}"),
+            )
+        })?;
+    info!("imported {} bytes of QMDL as {name}", qmdl_data.len());
+
+    queue_qmdl(&name, &mut state.analysis_status_lock.write().await);
+    state
+        .analysis_sender
+        .send(AnalysisCtrlMessage::NewFilesQueued)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to queue imported QMDL file: {e:?}"),
+            )
+        })?;
+    Ok((StatusCode::CREATED, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    // Queues three recordings and runs them through a fake analysis which
+    // takes a second, recording how many analyses were running at once.
+    async fn run_queued(max_workers: usize) -> (AnalysisStatus, usize) {
+        let analysis_status_lock = Arc::new(RwLock::new(AnalysisStatus {
+            queued: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            running: Vec::new(),
+            finished: Vec::new(),
+        }));
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+        let analyze = {
+            let active = active.clone();
+            let max_active = max_active.clone();
+            move |_name: String| {
+                let active = active.clone();
+                let max_active = max_active.clone();
+                async move {
+                    let now_active = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_active.fetch_max(now_active, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    active.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+        };
+
+        let (analysis_tx, analysis_rx) = mpsc::channel(5);
+        let workers = tokio::spawn(run_analysis_workers(
+            analysis_rx,
+            analysis_status_lock.clone(),
+            max_workers,
+            analyze,
+        ));
+        analysis_tx
+            .send(AnalysisCtrlMessage::NewFilesQueued)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        analysis_tx.send(AnalysisCtrlMessage::Exit).await.unwrap();
+        workers.await.unwrap();
+
+        let status = analysis_status_lock.read().await.clone();
+        (status, max_active.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn test_summary_counts_events() {
+        let names = vec!["Null Cipher".to_string(), "IMSI Requested".to_string()];
+        let event = |event_type| {
+            Some(Event {
+                event_type,
+                analyzer: String::new(),
+                message: String::new(),
+                details: None,
+            })
+        };
+        let row = |events| AnalysisRow {
+            packet_timestamp: None,
+            skipped_message_reason: None,
+            events,
+            message_summary: None,
+        };
+        let mut summary = AnalysisSummary::new(&names);
+        summary.add_row(&row(vec![event(EventType::High), None]), &names);
+        summary.add_row(&row(vec![None, event(EventType::Informational)]), &names);
+        summary.add_row(
+            &row(vec![
+                event(EventType::Medium),
+                event(EventType::Informational),
+            ]),
+            &names,
+        );
+        summary.add_row(&row(vec![None, None]), &names);
+
+        assert_eq!(summary.total_events, 4);
+        assert_eq!(
+            summary.by_severity,
+            SeverityCounts {
+                high: 1,
+                medium: 1,
+                low: 0,
+                informational: 2,
+            }
+        );
+        assert_eq!(summary.by_analyzer["Null Cipher"].high, 1);
+        assert_eq!(summary.by_analyzer["Null Cipher"].medium, 1);
+        assert_eq!(summary.by_analyzer["IMSI Requested"].informational, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queued_recordings_all_finish() {
+        for max_workers in [1, 2] {
+            let (status, max_active) = run_queued(max_workers).await;
+            assert!(status.queued.is_empty());
+            assert!(status.running.is_empty());
+            let mut finished = status.finished;
+            finished.sort();
+            assert_eq!(finished, vec!["a", "b", "c"]);
+            assert_eq!(max_active, max_workers);
+        }
+    }
+}
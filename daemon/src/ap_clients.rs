@@ -0,0 +1,420 @@
+//! Watches for devices joining Rayhunter's own wifi access point. Nobody but
+//! the owner should ever connect to it, so a client we've never seen before
+//! is worth a notification.
+//!
+//! Every client ever seen is remembered in a small JSON file next to the
+//! recordings, so a client only counts as new once, even across reboots.
+//! That list is the record of who joined and when. AP clients have nothing to
+//! do with a recording, so they're kept out of the event store.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use chrono::{DateTime, FixedOffset};
+use log::{info, warn};
+use rayhunter::analysis::analyzer::EventType;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tokio::select;
+use tokio::sync::RwLock;
+use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::config::Config;
+use crate::disk_space::has_room_for_auxiliary_write;
+use crate::notifications::{Notification, NotificationType};
+use crate::server::ServerState;
+
+pub const AP_CLIENTS_FILENAME: &str = "ap_clients.json";
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// The bridge AP clients sit on, on every device with an AP
+const AP_BRIDGE: &str = "bridge0";
+const ARP_TABLE_PATH: &str = "/proc/net/arp";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct ApClient {
+    /// The client's MAC address, in lowercase
+    pub mac: String,
+    #[cfg_attr(feature = "apidocs", schema(value_type = String))]
+    pub first_seen: DateTime<FixedOffset>,
+    #[cfg_attr(feature = "apidocs", schema(value_type = String))]
+    pub last_seen: DateTime<FixedOffset>,
+    /// Whether the client was connected when the AP was last checked
+    #[serde(default)]
+    pub connected: bool,
+}
+
+fn is_mac(s: &str) -> bool {
+    let octets: Vec<&str> = s.split(':').collect();
+    octets.len() == 6
+        && octets
+            .iter()
+            .all(|octet| octet.len() == 2 && octet.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Extracts the MACs of the stations in `hostapd_cli all_sta` output, where
+/// each station is a line with its MAC followed by key=value lines.
+pub fn parse_station_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| is_mac(line))
+        .map(str::to_ascii_lowercase)
+        .collect()
+}
+
+/// Extracts the MACs of the clients on `iface` from /proc/net/arp, skipping
+/// incomplete entries.
+pub fn parse_arp_table(contents: &str, iface: &str) -> Vec<String> {
+    contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            let [_ip, _hw_type, flags, mac, _mask, device] = columns[..] else {
+                return None;
+            };
+            (device == iface && flags != "0x0" && is_mac(mac) && mac != "00:00:00:00:00:00")
+                .then(|| mac.to_ascii_lowercase())
+        })
+        .collect()
+}
+
+/// Hides the device-specific half of a MAC, keeping the manufacturer's half.
+pub fn redact_mac(mac: &str) -> String {
+    let mut octets: Vec<&str> = mac.split(':').collect();
+    for octet in octets.iter_mut().skip(3) {
+        *octet = "xx";
+    }
+    octets.join(":")
+}
+
+/// The MACs of the clients currently connected to the AP. hostapd's station
+/// list is the most accurate, but not every device has hostapd_cli, so the
+/// clients in the ARP table for the AP's bridge are included too.
+async fn read_connected_macs() -> HashSet<String> {
+    let mut macs = HashSet::new();
+    match Command::new("hostapd_cli").arg("all_sta").output().await {
+        Ok(output) if output.status.success() => {
+            macs.extend(parse_station_list(&String::from_utf8_lossy(&output.stdout)));
+        }
+        _ => {}
+    }
+    match tokio::fs::read_to_string(ARP_TABLE_PATH).await {
+        Ok(contents) => macs.extend(parse_arp_table(&contents, AP_BRIDGE)),
+        Err(e) => warn!("couldn't read {ARP_TABLE_PATH}: {e}"),
+    }
+    macs
+}
+
+/// Every client which has ever connected to the AP, oldest first
+pub struct ApClientRegistry {
+    path: PathBuf,
+    clients: Vec<ApClient>,
+}
+
+impl ApClientRegistry {
+    /// Loads the registry at `path`, starting an empty one if it doesn't
+    /// exist or can't be parsed.
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut clients: Vec<ApClient> = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("couldn't parse {path:?}, starting over: {e}");
+                Vec::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        // nothing is known to be connected until the AP is checked
+        for client in &mut clients {
+            client.connected = false;
+        }
+        Ok(ApClientRegistry { path, clients })
+    }
+
+    pub fn clients(&self) -> &[ApClient] {
+        &self.clients
+    }
+
+    /// Records which clients are connected now, returning each one that's
+    /// never been seen before.
+    pub fn observe(
+        &mut self,
+        connected: &HashSet<String>,
+        now: DateTime<FixedOffset>,
+    ) -> Vec<ApClient> {
+        for client in &mut self.clients {
+            client.connected = connected.contains(&client.mac);
+            if client.connected {
+                client.last_seen = now;
+            }
+        }
+        let mut new_macs: Vec<&String> = connected
+            .iter()
+            .filter(|mac| !self.clients.iter().any(|client| &client.mac == *mac))
+            .collect();
+        new_macs.sort();
+
+        let mut new_clients = Vec::new();
+        for mac in new_macs {
+            let client = ApClient {
+                mac: mac.clone(),
+                first_seen: now,
+                last_seen: now,
+                connected: true,
+            };
+            new_clients.push(client.clone());
+            self.clients.push(client);
+        }
+        new_clients
+    }
+
+    /// Moves over to the registry at `path`, e.g. once the QMDL store has
+    /// moved, merging the clients seen here into whatever's already there,
+    /// and saves it.
+    pub async fn move_to<P: AsRef<Path>>(&mut self, path: P) -> Result<(), std::io::Error> {
+        let mut moved = ApClientRegistry::open(path).await?;
+        for client in &self.clients {
+            match moved
+                .clients
+                .iter_mut()
+                .find(|known| known.mac == client.mac)
+            {
+                Some(known) => {
+                    known.first_seen = known.first_seen.min(client.first_seen);
+                    known.last_seen = known.last_seen.max(client.last_seen);
+                    known.connected = client.connected;
+                }
+                None => moved.clients.push(client.clone()),
+            }
+        }
+        moved.clients.sort_by_key(|client| client.first_seen);
+        *self = moved;
+        self.save().await
+    }
+
+    pub async fn save(&self) -> Result<(), std::io::Error> {
+        if !has_room_for_auxiliary_write(&self.path, "AP clients") {
+            return Ok(());
+        }
+        let contents = serde_json::to_string(&self.clients).unwrap();
+        let tmp_path = self.path.with_extension("json.tmp");
+        tokio::fs::write(&tmp_path, contents).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await
+    }
+}
+
+fn new_client_message(client: &ApClient, redact: bool) -> String {
+    let mac = if redact {
+        redact_mac(&client.mac)
+    } else {
+        client.mac.clone()
+    };
+    format!("Unknown client {mac} joined the Rayhunter access point")
+}
+
+pub fn run_ap_client_monitor(
+    task_tracker: &TaskTracker,
+    config: &Config,
+    registry_lock: Arc<RwLock<ApClientRegistry>>,
+    notification_channel: Sender<Notification>,
+    shutdown_token: CancellationToken,
+) {
+    if !config.ap_client_monitor_enabled {
+        info!("AP client monitor disabled");
+        return;
+    }
+    if config.developer_mode {
+        info!("developer mode, not monitoring AP clients");
+        return;
+    }
+    let severity = config.ap_client_event_severity;
+    let redact = config.ap_client_redact_macs;
+
+    task_tracker.spawn(async move {
+        let mut last_connected = None;
+        loop {
+            let connected = read_connected_macs().await;
+            let now = rayhunter::clock::get_adjusted_now().fixed_offset();
+            let new_clients = {
+                let mut registry = registry_lock.write().await;
+                let new_clients = registry.observe(&connected, now);
+                // only save when someone comes or goes, rather than writing
+                // to flash every poll just to bump last_seen
+                if last_connected.as_ref() != Some(&connected)
+                    && let Err(e) = registry.save().await
+                {
+                    warn!("couldn't save AP client list: {e}");
+                }
+                new_clients
+            };
+            last_connected = Some(connected);
+
+            for client in new_clients {
+                let message = new_client_message(&client, redact);
+                info!("{message}");
+                if severity > EventType::Informational {
+                    notification_channel
+                        .send(Notification::new(
+                            NotificationType::Warning,
+                            format!(
+                                "Rayhunter has detected a {severity:?} severity event: {message}"
+                            ),
+                            None,
+                        ))
+                        .await
+                        .expect("Failed to send to notification channel");
+                }
+            }
+
+            select! {
+                _ = shutdown_token.cancelled() => break,
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+    });
+}
+
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    get,
+    path = "/api/ap-clients",
+    tag = "System",
+    responses(
+        (status = StatusCode::OK, description = "Success", body = Vec<ApClient>)
+    ),
+    summary = "List AP clients",
+    description = "List every client which has connected to Rayhunter's wifi access point, oldest first, and whether each is connected now."
+))]
+pub async fn get_ap_clients(
+    State(state): State<Arc<ServerState>>,
+) -> Result<Json<Vec<ApClient>>, (StatusCode, String)> {
+    Ok(Json(state.ap_clients_lock.read().await.clients().to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const ALL_STA: &str = "\
+AA:BB:CC:00:11:22
+flags=[AUTH][ASSOC][AUTHORIZED]
+aid=1
+rx_packets=120
+connected_time=54
+aa:bb:cc:33:44:55
+flags=[AUTH][ASSOC][AUTHORIZED]
+aid=2
+";
+
+    const ARP_TABLE: &str = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.23     0x1         0x2         aa:bb:cc:00:11:22     *        bridge0
+192.168.1.40     0x1         0x0         00:00:00:00:00:00     *        bridge0
+10.0.0.1         0x1         0x2         de:ad:be:ef:00:01     *        rmnet_data0
+192.168.1.41     0x1         0x2         AA:BB:CC:66:77:88     *        bridge0
+";
+
+    fn time(seconds: i64) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339("2025-01-01T00:00:00Z").unwrap()
+            + chrono::Duration::seconds(seconds)
+    }
+
+    fn macs(macs: &[&str]) -> HashSet<String> {
+        macs.iter().map(|mac| mac.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_station_list() {
+        assert_eq!(
+            parse_station_list(ALL_STA),
+            vec!["aa:bb:cc:00:11:22", "aa:bb:cc:33:44:55"]
+        );
+        assert!(parse_station_list("Failed to connect to hostapd - wpa_ctrl_open").is_empty());
+    }
+
+    #[test]
+    fn test_parse_arp_table() {
+        assert_eq!(
+            parse_arp_table(ARP_TABLE, "bridge0"),
+            vec!["aa:bb:cc:00:11:22", "aa:bb:cc:66:77:88"]
+        );
+    }
+
+    #[test]
+    fn test_redact_mac() {
+        assert_eq!(redact_mac("aa:bb:cc:00:11:22"), "aa:bb:cc:xx:xx:xx");
+    }
+
+    #[tokio::test]
+    async fn test_new_clients_are_only_reported_once() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(AP_CLIENTS_FILENAME);
+        let mut registry = ApClientRegistry::open(&path).await.unwrap();
+
+        let first = registry.observe(&macs(&["aa:bb:cc:00:11:22"]), time(0));
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].mac, "aa:bb:cc:00:11:22");
+        assert_eq!(first[0].first_seen, time(0));
+
+        let second = registry.observe(&macs(&["aa:bb:cc:00:11:22", "aa:bb:cc:33:44:55"]), time(30));
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].mac, "aa:bb:cc:33:44:55");
+
+        assert!(registry.observe(&macs(&[]), time(60)).is_empty());
+        let known = registry.clients();
+        assert_eq!(known[0].first_seen, time(0));
+        assert_eq!(known[0].last_seen, time(30));
+        assert!(!known[0].connected);
+    }
+
+    #[tokio::test]
+    async fn test_registry_survives_restart() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(AP_CLIENTS_FILENAME);
+        let mut registry = ApClientRegistry::open(&path).await.unwrap();
+        registry.observe(&macs(&["aa:bb:cc:00:11:22"]), time(0));
+        registry.save().await.unwrap();
+
+        let mut reopened = ApClientRegistry::open(&path).await.unwrap();
+        assert_eq!(reopened.clients().len(), 1);
+        assert!(!reopened.clients()[0].connected);
+        assert!(
+            reopened
+                .observe(&macs(&["aa:bb:cc:00:11:22"]), time(30))
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_move_to_merges_clients() {
+        let dir = TempDir::new().unwrap();
+        let new_path = dir.path().join(AP_CLIENTS_FILENAME);
+        let mut existing = ApClientRegistry::open(&new_path).await.unwrap();
+        existing.observe(&macs(&["aa:bb:cc:00:11:22"]), time(0));
+        existing.save().await.unwrap();
+
+        let mut registry = ApClientRegistry::open(dir.path().join("old.json"))
+            .await
+            .unwrap();
+        registry.observe(&macs(&["aa:bb:cc:00:11:22", "aa:bb:cc:33:44:55"]), time(30));
+        registry.move_to(&new_path).await.unwrap();
+
+        let reopened = ApClientRegistry::open(&new_path).await.unwrap();
+        let clients = reopened.clients();
+        assert_eq!(clients.len(), 2);
+        assert_eq!(clients[0].mac, "aa:bb:cc:00:11:22");
+        assert_eq!(clients[0].first_seen, time(0));
+        assert_eq!(clients[0].last_seen, time(30));
+        assert_eq!(clients[1].mac, "aa:bb:cc:33:44:55");
+        assert!(registry.clients()[1].connected);
+    }
+}
@@ -1,9 +1,14 @@
-use std::{path::Path, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use log::{info, warn};
+use log::{debug, info, warn};
 use rayhunter::Device;
 use serde::Serialize;
 use tokio::select;
+use tokio::sync::Mutex;
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
 use crate::{
@@ -18,14 +23,94 @@ pub mod wingtech;
 
 const LOW_BATTERY_LEVEL: u8 = 10;
 
+/// Where sysfs is mounted on the device. Tests pass a fake tree to
+/// [probe_battery_source] or [BatteryProbe::new] instead.
+pub const SYSFS_ROOT: &str = "/";
+const POWER_SUPPLY_DIR: &str = "sys/class/power_supply";
+
 /// Device battery information
-#[derive(Clone, Copy, PartialEq, Debug, Serialize)]
+#[derive(Clone, PartialEq, Debug, Serialize)]
 #[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
 pub struct BatteryState {
     /// The current level in percentage of the device battery
     level: u8,
     /// A boolean indicating whether the battery is currently being charged
     is_plugged_in: bool,
+    /// Where the battery level was read from, for debugging
+    source: String,
+}
+
+/// How a battery level file is formatted
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LevelFormat {
+    /// A percentage from 0 to 100, like power_supply's `capacity`
+    Percentage,
+    /// The Orbic's 1 to 5 level, one step per bar on its display
+    OrbicSteps,
+}
+
+/// How a file indicating whether the device is charging is formatted
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChargingFormat {
+    /// 1 if plugged in, 0 if not, like power_supply's `online`
+    Online,
+    /// power_supply's `status`, e.g. "Charging" or "Discharging"
+    Status,
+}
+
+/// Somewhere the daemon can read the battery state from
+#[derive(Clone, PartialEq, Debug)]
+pub enum BatterySource {
+    Sysfs {
+        level_path: PathBuf,
+        level_format: LevelFormat,
+        charging_path: PathBuf,
+        charging_format: ChargingFormat,
+    },
+    /// The TP-Link's battery manager, through `uci`
+    Uci,
+}
+
+impl BatterySource {
+    pub async fn read(&self) -> Result<BatteryState, RayhunterError> {
+        match self {
+            BatterySource::Sysfs {
+                level_path,
+                level_format,
+                charging_path,
+                charging_format,
+            } => Ok(BatteryState {
+                level: match level_format {
+                    LevelFormat::Percentage => get_level_from_percentage_file(level_path).await?,
+                    LevelFormat::OrbicSteps => get_level_from_steps_file(level_path).await?,
+                },
+                is_plugged_in: match charging_format {
+                    ChargingFormat::Online => is_plugged_in_from_file(charging_path).await?,
+                    ChargingFormat::Status => is_plugged_in_from_status_file(charging_path).await?,
+                },
+                source: level_path.display().to_string(),
+            }),
+            BatterySource::Uci => tplink::get_battery_state().await,
+        }
+    }
+}
+
+impl std::fmt::Display for BatterySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BatterySource::Sysfs {
+                level_path,
+                charging_path,
+                ..
+            } => write!(
+                f,
+                "{} and {}",
+                level_path.display(),
+                charging_path.display()
+            ),
+            BatterySource::Uci => write!(f, "{}", tplink::UCI_SOURCE),
+        }
+    }
 }
 
 async fn is_plugged_in_from_file(path: &Path) -> Result<bool, RayhunterError> {
@@ -41,81 +126,454 @@ async fn is_plugged_in_from_file(path: &Path) -> Result<bool, RayhunterError> {
     }
 }
 
+/// Whether a power_supply `status` means the device is plugged in. Some
+/// batteries only ever report "Unknown", which gives None.
+fn parse_charging_status(status: &str) -> Option<bool> {
+    match status.trim_end() {
+        "Charging" | "Full" => Some(true),
+        "Discharging" | "Not charging" => Some(false),
+        _ => None,
+    }
+}
+
+async fn is_plugged_in_from_status_file(path: &Path) -> Result<bool, RayhunterError> {
+    let status = tokio::fs::read_to_string(path)
+        .await
+        .map_err(RayhunterError::TokioError)?;
+    parse_charging_status(&status).ok_or(RayhunterError::BatteryPluggedInStatusParseError)
+}
+
 async fn get_level_from_percentage_file(path: &Path) -> Result<u8, RayhunterError> {
-    tokio::fs::read_to_string(path)
+    match tokio::fs::read_to_string(path)
         .await
         .map_err(RayhunterError::TokioError)?
         .trim_end()
         .parse()
-        .or(Err(RayhunterError::BatteryLevelParseError))
+    {
+        Ok(level) if level <= 100 => Ok(level),
+        _ => Err(RayhunterError::BatteryLevelParseError),
+    }
+}
+
+async fn get_level_from_steps_file(path: &Path) -> Result<u8, RayhunterError> {
+    match tokio::fs::read_to_string(path)
+        .await
+        .map_err(RayhunterError::TokioError)?
+        .chars()
+        .next()
+    {
+        Some('1') => Ok(10),
+        Some('2') => Ok(25),
+        Some('3') => Ok(50),
+        Some('4') => Ok(75),
+        Some('5') => Ok(100),
+        _ => Err(RayhunterError::BatteryLevelParseError),
+    }
+}
+
+/// power_supply types which power the device from outside, and so say
+/// whether it's plugged in with their `online` file
+const EXTERNAL_SUPPLY_TYPES: &[&str] = &["Mains", "USB", "USB_DCP", "USB_CDP", "USB_C", "USB_PD"];
+
+/// Every power_supply of type "Battery" under the sysfs root, in name order.
+/// Each battery's own `status` is tried first, and then the `online` file of
+/// each external supply, for batteries whose status is always "Unknown".
+async fn power_supply_sources(sysfs_root: &Path) -> Vec<BatterySource> {
+    let Ok(mut entries) = tokio::fs::read_dir(sysfs_root.join(POWER_SUPPLY_DIR)).await else {
+        return Vec::new();
+    };
+    let mut supply_dirs = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        supply_dirs.push(entry.path());
+    }
+    supply_dirs.sort();
+
+    let mut batteries = Vec::new();
+    let mut external_supplies = Vec::new();
+    for dir in supply_dirs {
+        let Ok(supply_type) = tokio::fs::read_to_string(dir.join("type")).await else {
+            continue;
+        };
+        match supply_type.trim_end() {
+            "Battery" => batteries.push(dir),
+            supply_type if EXTERNAL_SUPPLY_TYPES.contains(&supply_type) => {
+                external_supplies.push(dir)
+            }
+            _ => {}
+        }
+    }
+
+    let mut sources = Vec::new();
+    for battery in batteries {
+        sources.push(BatterySource::Sysfs {
+            level_path: battery.join("capacity"),
+            level_format: LevelFormat::Percentage,
+            charging_path: battery.join("status"),
+            charging_format: ChargingFormat::Status,
+        });
+        for supply in &external_supplies {
+            sources.push(BatterySource::Sysfs {
+                level_path: battery.join("capacity"),
+                level_format: LevelFormat::Percentage,
+                charging_path: supply.join("online"),
+                charging_format: ChargingFormat::Online,
+            });
+        }
+    }
+    sources
+}
+
+/// The places each device might expose its battery state, most specific first.
+async fn candidate_sources(device: &Device, sysfs_root: &Path) -> Vec<BatterySource> {
+    let mut sources = match device {
+        Device::Orbic => vec![orbic::battery_source(sysfs_root)],
+        Device::Wingtech => vec![wingtech::battery_source(sysfs_root)],
+        Device::Tmobile => vec![tmobile::battery_source(sysfs_root)],
+        Device::Tplink => vec![BatterySource::Uci],
+        Device::Pinephone | Device::Uz801 | Device::Moxee => Vec::new(),
+    };
+    sources.extend(power_supply_sources(sysfs_root).await);
+    sources
+}
+
+/// Finds the first of the device's candidate battery sources which can
+/// actually be read, or None if the device doesn't expose its battery state.
+pub async fn probe_battery_source(device: &Device, sysfs_root: &Path) -> Option<BatterySource> {
+    for source in candidate_sources(device, sysfs_root).await {
+        match source.read().await {
+            Ok(_) => {
+                info!("Reading battery status from {source}");
+                return Some(source);
+            }
+            Err(e) => debug!("Battery source {source} is unusable: {e}"),
+        }
+    }
+    None
+}
+
+/// How long to wait before probing again while no battery source can be
+/// read, e.g. because its driver was still loading when the daemon started
+const PROBE_RETRY_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The device's battery source. Once one is found it's kept, since sources
+/// don't come and go while running, but until then it's probed for again
+/// whenever it's asked for, at most every [PROBE_RETRY_INTERVAL].
+#[derive(Clone)]
+pub struct BatteryProbe {
+    device: Device,
+    sysfs_root: PathBuf,
+    state: Arc<Mutex<ProbeState>>,
 }
 
-pub async fn get_battery_status(device: &Device) -> Result<BatteryState, RayhunterError> {
-    Ok(match device {
-        Device::Orbic => orbic::get_battery_state().await?,
-        Device::Wingtech => wingtech::get_battery_state().await?,
-        Device::Tmobile => tmobile::get_battery_state().await?,
-        Device::Tplink => tplink::get_battery_state().await?,
-        _ => return Err(RayhunterError::FunctionNotSupportedForDeviceError),
-    })
+struct ProbeState {
+    source: Option<BatterySource>,
+    last_probe: Option<Instant>,
+}
+
+impl BatteryProbe {
+    pub fn new(device: Device, sysfs_root: PathBuf) -> Self {
+        BatteryProbe {
+            device,
+            sysfs_root,
+            state: Arc::new(Mutex::new(ProbeState {
+                source: None,
+                last_probe: None,
+            })),
+        }
+    }
+
+    /// The battery source, or None if none could be read (yet)
+    pub async fn source(&self) -> Option<BatterySource> {
+        let mut state = self.state.lock().await;
+        if state.source.is_none()
+            && state
+                .last_probe
+                .is_none_or(|last_probe| last_probe.elapsed() >= PROBE_RETRY_INTERVAL)
+        {
+            if state.last_probe.is_none() {
+                info!("Looking for the device's battery status");
+            }
+            state.last_probe = Some(Instant::now());
+            state.source = probe_battery_source(&self.device, &self.sysfs_root).await;
+        }
+        state.source.clone()
+    }
+}
+
+pub async fn get_battery_status(source: &BatterySource) -> Result<BatteryState, RayhunterError> {
+    source.read().await
+}
+
+// Whether `status` calls for a low battery notification. `triggered` is
+// whether one has been sent since the device was last charged, and None
+// until the battery is first read, so that a device which starts out low
+// doesn't get one.
+fn should_notify_low_battery(status: &BatteryState, triggered: &mut Option<bool>) -> bool {
+    let Some(was_triggered) = *triggered else {
+        *triggered = Some(status.level <= LOW_BATTERY_LEVEL);
+        return false;
+    };
+    // To avoid flapping, if the notification has already been triggered
+    // wait until the device has been plugged in and the battery level
+    // is high enough to re-enable notifications.
+    if was_triggered && status.is_plugged_in && status.level > LOW_BATTERY_LEVEL {
+        *triggered = Some(false);
+    } else if !was_triggered && !status.is_plugged_in && status.level <= LOW_BATTERY_LEVEL {
+        *triggered = Some(true);
+        return true;
+    }
+    false
 }
 
 pub fn run_battery_notification_worker(
     task_tracker: &TaskTracker,
-    device: Device,
+    battery: BatteryProbe,
     notification_channel: tokio::sync::mpsc::Sender<Notification>,
     shutdown_token: CancellationToken,
 ) {
     task_tracker.spawn(async move {
-        // Don't send a notification initially if the device starts at a low battery level.
-        let mut triggered = match get_battery_status(&device).await {
-            Err(RayhunterError::FunctionNotSupportedForDeviceError) => {
-                info!("Battery status not supported for this device, disabling battery notifications");
-                return;
-            }
-            Err(e) => {
-                warn!("Failed to get battery status: {e}");
-                true
+        let mut triggered = None;
+        loop {
+            if let Some(source) = battery.source().await {
+                match get_battery_status(&source).await {
+                    Err(e) => warn!("Failed to get battery status: {e}"),
+                    Ok(status) => {
+                        if should_notify_low_battery(&status, &mut triggered) {
+                            notification_channel
+                                .send(Notification::new(
+                                    NotificationType::LowBattery,
+                                    "Rayhunter's battery is low".to_string(),
+                                    None,
+                                ))
+                                .await
+                                .expect("Failed to send to notification channel");
+                        }
+                    }
+                }
             }
-            Ok(status) => status.level <= LOW_BATTERY_LEVEL,
-        };
 
-        loop {
             select! {
                 _ = shutdown_token.cancelled() => break,
                 _ = tokio::time::sleep(Duration::from_secs(15)) => {}
             }
-
-            let status = match get_battery_status(&device).await {
-                Err(RayhunterError::FunctionNotSupportedForDeviceError) => {
-                    info!("Battery status not supported for this device, disabling battery notifications");
-                    break;
-                }
-                Err(e) => {
-                    warn!("Failed to get battery status: {e}");
-                    continue;
-                }
-                Ok(status) => status,
-            };
-
-            // To avoid flapping, if the notification has already been triggered
-            // wait until the device has been plugged in and the battery level
-            // is high enough to re-enable notifications.
-            if triggered && status.is_plugged_in && status.level > LOW_BATTERY_LEVEL {
-                triggered = false;
-                continue;
-            }
-            if !triggered && !status.is_plugged_in && status.level <= LOW_BATTERY_LEVEL {
-                notification_channel
-                    .send(Notification::new(
-                        NotificationType::LowBattery,
-                        "Rayhunter's battery is low".to_string(),
-                        None,
-                    ))
-                    .await
-                    .expect("Failed to send to notification channel");
-                triggered = true;
-            }
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_file(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn write_power_supply(
+        root: &Path,
+        name: &str,
+        supply_type: &str,
+        capacity: &str,
+        status: &str,
+    ) {
+        let dir = format!("{POWER_SUPPLY_DIR}/{name}");
+        write_file(root, &format!("{dir}/type"), supply_type);
+        write_file(root, &format!("{dir}/capacity"), capacity);
+        write_file(root, &format!("{dir}/status"), status);
+    }
+
+    #[tokio::test]
+    async fn test_power_supply_battery() {
+        let root = TempDir::new().unwrap();
+        write_power_supply(root.path(), "usb", "USB\n", "0\n", "Unknown\n");
+        write_power_supply(root.path(), "battery", "Battery\n", "87\n", "Charging\n");
+
+        let source = probe_battery_source(&Device::Uz801, root.path())
+            .await
+            .unwrap();
+        let state = get_battery_status(&source).await.unwrap();
+        assert_eq!(state.level, 87);
+        assert!(state.is_plugged_in);
+        assert_eq!(
+            state.source,
+            root.path()
+                .join("sys/class/power_supply/battery/capacity")
+                .display()
+                .to_string()
+        );
+
+        write_file(
+            root.path(),
+            "sys/class/power_supply/battery/status",
+            "Discharging\n",
+        );
+        assert!(!get_battery_status(&source).await.unwrap().is_plugged_in);
+        write_file(
+            root.path(),
+            "sys/class/power_supply/battery/status",
+            "Full\n",
+        );
+        assert!(get_battery_status(&source).await.unwrap().is_plugged_in);
+    }
+
+    #[test]
+    fn test_parse_charging_status() {
+        assert_eq!(parse_charging_status("Charging\n"), Some(true));
+        assert_eq!(parse_charging_status("Full\n"), Some(true));
+        assert_eq!(parse_charging_status("Discharging\n"), Some(false));
+        assert_eq!(parse_charging_status("Not charging\n"), Some(false));
+        assert_eq!(parse_charging_status("Unknown\n"), None);
+        assert_eq!(parse_charging_status(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_status_falls_back_to_external_supply() {
+        let root = TempDir::new().unwrap();
+        write_power_supply(root.path(), "battery", "Battery\n", "64\n", "Unknown\n");
+        write_file(
+            root.path(),
+            &format!("{POWER_SUPPLY_DIR}/usb/type"),
+            "USB\n",
+        );
+        write_file(
+            root.path(),
+            &format!("{POWER_SUPPLY_DIR}/usb/online"),
+            "1\n",
+        );
+
+        let source = probe_battery_source(&Device::Moxee, root.path())
+            .await
+            .unwrap();
+        assert_eq!(
+            source,
+            BatterySource::Sysfs {
+                level_path: root.path().join(POWER_SUPPLY_DIR).join("battery/capacity"),
+                level_format: LevelFormat::Percentage,
+                charging_path: root.path().join(POWER_SUPPLY_DIR).join("usb/online"),
+                charging_format: ChargingFormat::Online,
+            }
+        );
+        let state = get_battery_status(&source).await.unwrap();
+        assert_eq!(state.level, 64);
+        assert!(state.is_plugged_in);
+    }
+
+    #[tokio::test]
+    async fn test_device_specific_source_is_preferred() {
+        let root = TempDir::new().unwrap();
+        write_file(root.path(), "sys/kernel/chg_info/level", "3\n");
+        write_file(root.path(), "sys/kernel/chg_info/chg_en", "0\n");
+        write_power_supply(root.path(), "battery", "Battery\n", "87\n", "Charging\n");
+
+        let source = probe_battery_source(&Device::Orbic, root.path())
+            .await
+            .unwrap();
+        assert_eq!(source, orbic::battery_source(root.path()));
+        let state = get_battery_status(&source).await.unwrap();
+        assert_eq!(state.level, 50);
+        assert!(!state.is_plugged_in);
+    }
+
+    #[tokio::test]
+    async fn test_no_battery_source() {
+        let root = TempDir::new().unwrap();
+        assert_eq!(
+            probe_battery_source(&Device::Orbic, root.path()).await,
+            None
+        );
+
+        write_power_supply(root.path(), "usb", "USB\n", "0\n", "Unknown\n");
+        assert_eq!(
+            probe_battery_source(&Device::Pinephone, root.path()).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probe_retries_until_found() {
+        let root = TempDir::new().unwrap();
+        let battery = BatteryProbe::new(Device::Uz801, root.path().to_path_buf());
+        assert_eq!(battery.source().await, None);
+
+        // the battery driver finishes loading after the first probe, which
+        // is only retried once PROBE_RETRY_INTERVAL has passed
+        write_power_supply(root.path(), "battery", "Battery\n", "87\n", "Charging\n");
+        assert_eq!(battery.source().await, None);
+        battery.state.lock().await.last_probe = None;
+        let source = battery.source().await.unwrap();
+
+        // and once found, the source is kept
+        std::fs::remove_dir_all(root.path().join(POWER_SUPPLY_DIR)).unwrap();
+        battery.state.lock().await.last_probe = None;
+        assert_eq!(battery.source().await, Some(source));
+    }
+
+    #[test]
+    fn test_low_battery_notification() {
+        let status = |level, is_plugged_in| BatteryState {
+            level,
+            is_plugged_in,
+            source: String::new(),
+        };
+        // no notification if the device starts out low
+        let mut triggered = None;
+        assert!(!should_notify_low_battery(
+            &status(5, false),
+            &mut triggered
+        ));
+        assert!(!should_notify_low_battery(
+            &status(5, false),
+            &mut triggered
+        ));
+
+        // until it's been charged
+        assert!(!should_notify_low_battery(
+            &status(50, true),
+            &mut triggered
+        ));
+        assert!(!should_notify_low_battery(
+            &status(50, false),
+            &mut triggered
+        ));
+        assert!(should_notify_low_battery(
+            &status(10, false),
+            &mut triggered
+        ));
+        assert!(!should_notify_low_battery(
+            &status(9, false),
+            &mut triggered
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_values_are_skipped() {
+        let root = TempDir::new().unwrap();
+        // wingtech's own files are present, but unreadable
+        write_file(
+            root.path(),
+            "sys/devices/78b7000.i2c/i2c-3/3-0063/power_supply/cw2017-bat/capacity",
+            "lots\n",
+        );
+        write_file(
+            root.path(),
+            "sys/devices/8a00000.ssusb/power_supply/usb/online",
+            "1\n",
+        );
+        write_power_supply(root.path(), "bat0", "Battery\n", "250\n", "Charging\n");
+        write_power_supply(root.path(), "bat1", "Battery\n", "40\n", "Sideways\n");
+        assert_eq!(
+            probe_battery_source(&Device::Wingtech, root.path()).await,
+            None
+        );
+
+        write_power_supply(root.path(), "bat2", "Battery\n", "40\n", "Not charging\n");
+        let source = probe_battery_source(&Device::Wingtech, root.path())
+            .await
+            .unwrap();
+        let state = get_battery_status(&source).await.unwrap();
+        assert_eq!(state.level, 40);
+        assert!(!state.is_plugged_in);
+    }
+}
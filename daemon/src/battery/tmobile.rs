@@ -1,16 +1,15 @@
 use std::path::Path;
 
-use crate::{
-    battery::{BatteryState, get_level_from_percentage_file, is_plugged_in_from_file},
-    error::RayhunterError,
-};
+use crate::battery::{BatterySource, ChargingFormat, LevelFormat};
 
-const BATTERY_LEVEL_FILE: &str = "/sys/class/power_supply/bms/capacity";
-const PLUGGED_IN_STATE_FILE: &str = "/sys/devices/78d9000.usb/power_supply/usb/online";
+const BATTERY_LEVEL_FILE: &str = "sys/class/power_supply/bms/capacity";
+const PLUGGED_IN_STATE_FILE: &str = "sys/devices/78d9000.usb/power_supply/usb/online";
 
-pub async fn get_battery_state() -> Result<BatteryState, RayhunterError> {
-    Ok(BatteryState {
-        level: get_level_from_percentage_file(Path::new(BATTERY_LEVEL_FILE)).await?,
-        is_plugged_in: is_plugged_in_from_file(Path::new(PLUGGED_IN_STATE_FILE)).await?,
-    })
+pub fn battery_source(sysfs_root: &Path) -> BatterySource {
+    BatterySource::Sysfs {
+        level_path: sysfs_root.join(BATTERY_LEVEL_FILE),
+        level_format: LevelFormat::Percentage,
+        charging_path: sysfs_root.join(PLUGGED_IN_STATE_FILE),
+        charging_format: ChargingFormat::Online,
+    }
 }
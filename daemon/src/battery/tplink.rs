@@ -1,5 +1,7 @@
 use crate::{battery::BatteryState, error::RayhunterError};
 
+pub const UCI_SOURCE: &str = "uci battery.battery_mgr";
+
 pub async fn get_battery_state() -> Result<BatteryState, RayhunterError> {
     let uci_battery = tokio::process::Command::new("uci")
         .arg("get")
@@ -35,5 +37,6 @@ pub async fn get_battery_state() -> Result<BatteryState, RayhunterError> {
     Ok(BatteryState {
         level: uci_battery,
         is_plugged_in: uci_plugged_in,
+        source: UCI_SOURCE.to_string(),
     })
 }
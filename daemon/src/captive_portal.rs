@@ -0,0 +1,234 @@
+//! Detects when the wifi client has joined a network behind a captive portal,
+//! like hotel or conference wifi. DHCP succeeds on these, but nothing gets
+//! through until someone signs in on the portal's web page.
+//!
+//! Once the wifi client connects, we fetch a URL which always answers 204 No
+//! Content. Anything else means something between us and the internet is
+//! answering in its place. While a portal is in the way, outbound HTTP is
+//! opened in the firewall so the sign-in page can load, and closed again once
+//! the check passes.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::Json;
+use axum::extract::State;
+use log::{info, warn};
+use serde::Serialize;
+use tokio::select;
+use tokio::sync::RwLock;
+use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use wifi_station::WifiStatus;
+
+use crate::config::Config;
+use crate::firewall::{FirewallState, set_captive_portal_http};
+use crate::notifications::{Notification, NotificationType};
+use crate::routing::global_ipv6_addrs;
+use crate::server::ServerState;
+use crate::wifi_signal::read_signal_dbm;
+
+/// Answers every request with an empty 204, unless something intercepts it
+pub const CONNECTIVITY_CHECK_URL: &str = "http://connectivitycheck.gstatic.com/generate_204";
+
+/// How often to look at the wifi client's state
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// How often to check again while waiting for someone to sign in
+const RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+const CAPTIVE_PORTAL_MESSAGE: &str = "Rayhunter's wifi network is behind a captive portal. \
+    To sign in, open any http:// page on a device connected to Rayhunter's hotspot.";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connectivity {
+    Online,
+    CaptivePortal,
+    /// The check couldn't be made at all, e.g. because DNS failed
+    Unknown,
+}
+
+/// Interprets the status code of a response from [CONNECTIVITY_CHECK_URL].
+/// A portal typically redirects to its sign-in page or serves it directly,
+/// but any answer other than 204 means we aren't talking to the real server.
+pub fn connectivity_from_status(status: u16) -> Connectivity {
+    if status == 204 {
+        Connectivity::Online
+    } else {
+        Connectivity::CaptivePortal
+    }
+}
+
+async fn check_connectivity(http_client: &reqwest::Client) -> Connectivity {
+    match http_client.get(CONNECTIVITY_CHECK_URL).send().await {
+        Ok(response) => connectivity_from_status(response.status().as_u16()),
+        Err(e) => {
+            info!("captive portal check failed: {e}");
+            Connectivity::Unknown
+        }
+    }
+}
+
+/// wifi_station doesn't expose its connection state other than for
+/// serialization, so read it the way the web UI does.
+fn wifi_connected(status: &WifiStatus) -> bool {
+    serde_json::to_value(status)
+        .is_ok_and(|status| status["state"] == serde_json::json!("connected"))
+}
+
+#[derive(Clone, Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct WifiStatusResponse {
+    #[serde(flatten)]
+    pub status: WifiStatus,
+    /// Whether the network the wifi client joined is behind a captive
+    /// portal, which someone has to sign in to before it's usable
+    pub captive_portal: bool,
+    /// The signal strength of the network the wifi client is connected to,
+    /// in dBm. Missing while it isn't connected.
+    pub signal_dbm: Option<i32>,
+    /// The wifi client's global IPv6 addresses, from SLAAC or DHCPv6. On an
+    /// IPv6-only network these are its only addresses.
+    pub ipv6_addrs: Vec<String>,
+}
+
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    get,
+    path = "/api/wifi-status",
+    tag = "Configuration",
+    responses(
+        (status = StatusCode::OK, description = "Success", body = WifiStatusResponse)
+    ),
+    summary = "Get wifi status",
+    description = "Show the status of the wifi client, its signal strength and IPv6 addresses, and whether its network is behind a captive portal."
+))]
+pub async fn get_wifi_status(State(state): State<Arc<ServerState>>) -> Json<WifiStatusResponse> {
+    let status = state.wifi_status.read().await.clone();
+    let (signal_dbm, ipv6_addrs) = if wifi_connected(&status) && !state.config.developer_mode {
+        let wifi_config = state.config.wifi_config();
+        (
+            read_signal_dbm(wifi_config.iw_bin.as_deref(), wifi_station::STA_IFACE).await,
+            global_ipv6_addrs(wifi_station::STA_IFACE).await,
+        )
+    } else {
+        (None, Vec::new())
+    };
+    Json(WifiStatusResponse {
+        status,
+        captive_portal: *state.captive_portal.read().await,
+        signal_dbm,
+        ipv6_addrs,
+    })
+}
+
+/// Records whether we're behind a captive portal, opening or closing HTTP
+/// to match.
+async fn set_captive_portal(
+    captive_portal: &RwLock<bool>,
+    firewall_state: &RwLock<FirewallState>,
+    detected: bool,
+) {
+    *captive_portal.write().await = detected;
+    if let Err(e) = set_captive_portal_http(firewall_state, detected).await {
+        warn!("couldn't update firewall for captive portal: {e}");
+    }
+}
+
+pub fn run_captive_portal_detector(
+    task_tracker: &TaskTracker,
+    config: &Config,
+    wifi_status: Arc<RwLock<WifiStatus>>,
+    captive_portal: Arc<RwLock<bool>>,
+    firewall_state: Arc<RwLock<FirewallState>>,
+    notification_channel: Sender<Notification>,
+    shutdown_token: CancellationToken,
+) {
+    if !config.wifi_enabled || !config.wifi_detect_captive_portal || config.developer_mode {
+        return;
+    }
+
+    task_tracker.spawn(async move {
+        let http_client = match reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .timeout(CHECK_TIMEOUT)
+            .build()
+        {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("captive portal detection disabled, couldn't build HTTP client: {e}");
+                return;
+            }
+        };
+
+        // whether we've had an answer since the wifi client last connected
+        let mut checked = false;
+        let mut since_check = RECHECK_INTERVAL;
+        loop {
+            let connected = wifi_connected(&*wifi_status.read().await);
+            let detected = *captive_portal.read().await;
+            if !connected {
+                // check as soon as it connects again
+                checked = false;
+                since_check = RECHECK_INTERVAL;
+                if detected {
+                    set_captive_portal(&captive_portal, &firewall_state, false).await;
+                }
+            } else if (!checked || detected) && since_check >= RECHECK_INTERVAL {
+                // the check itself is plain HTTP, which the firewall would
+                // otherwise block
+                if let Err(e) = set_captive_portal_http(&firewall_state, true).await {
+                    warn!("couldn't open HTTP for captive portal check: {e}");
+                }
+                let connectivity = check_connectivity(&http_client).await;
+                since_check = Duration::ZERO;
+                checked = connectivity != Connectivity::Unknown;
+
+                let now_detected = match connectivity {
+                    Connectivity::CaptivePortal => true,
+                    Connectivity::Online => false,
+                    Connectivity::Unknown => detected,
+                };
+                set_captive_portal(&captive_portal, &firewall_state, now_detected).await;
+                if now_detected && !detected {
+                    info!("wifi client is behind a captive portal");
+                    notification_channel
+                        .send(Notification::new(
+                            NotificationType::Warning,
+                            CAPTIVE_PORTAL_MESSAGE.to_string(),
+                            None,
+                        ))
+                        .await
+                        .expect("Failed to send to notification channel");
+                } else if detected && !now_detected {
+                    info!("signed in to captive portal, wifi client is online");
+                }
+            }
+
+            select! {
+                _ = shutdown_token.cancelled() => break,
+                _ = tokio::time::sleep(POLL_INTERVAL) => since_check += POLL_INTERVAL,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connectivity_from_status() {
+        assert_eq!(connectivity_from_status(204), Connectivity::Online);
+        // a redirect to the sign-in page
+        assert_eq!(connectivity_from_status(302), Connectivity::CaptivePortal);
+        // the sign-in page served in place of the real response
+        assert_eq!(connectivity_from_status(200), Connectivity::CaptivePortal);
+        assert_eq!(connectivity_from_status(511), Connectivity::CaptivePortal);
+    }
+
+    #[test]
+    fn test_disconnected_by_default() {
+        assert!(!wifi_connected(&WifiStatus::default()));
+    }
+}
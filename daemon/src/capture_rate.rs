@@ -0,0 +1,186 @@
+//! How fast the current recording is growing. A healthy diag link writes a
+//! steady trickle of messages, so a rate which drops to zero is the quickest
+//! sign that capture has stalled. The same meter measures how fast the diag
+//! device is read, for /api/system-stats.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// How far back the rate looks
+const CAPTURE_RATE_WINDOW: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct CaptureRate {
+    /// QMDL bytes written per second over the last few seconds
+    pub bytes_per_sec: f64,
+    /// Diag messages written per second over the last few seconds
+    pub messages_per_sec: f64,
+    /// Diag messages written since the recording started
+    pub messages_total: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: Instant,
+    bytes: u64,
+    messages: u64,
+}
+
+/// Works out the capture rate from how many bytes have been written (or
+/// read) over time
+#[derive(Debug)]
+pub struct CaptureRateMeter {
+    // never empty, and only holds one sample from before the window, which
+    // is where the window's counts start from
+    samples: VecDeque<Sample>,
+    messages_total: u64,
+}
+
+impl CaptureRateMeter {
+    /// Starts measuring a recording which began at `now`, with nothing
+    /// written yet
+    pub fn new(now: Instant) -> Self {
+        CaptureRateMeter {
+            samples: VecDeque::from([Sample {
+                at: now,
+                bytes: 0,
+                messages: 0,
+            }]),
+            messages_total: 0,
+        }
+    }
+
+    /// Records that the recording had grown to `total_bytes` at `now`, with
+    /// `messages` more messages than last time
+    pub fn record(&mut self, total_bytes: u64, messages: usize, now: Instant) {
+        self.messages_total += messages as u64;
+        self.samples.push_back(Sample {
+            at: now,
+            bytes: total_bytes,
+            messages: self.messages_total,
+        });
+        if let Some(window_start) = now.checked_sub(CAPTURE_RATE_WINDOW) {
+            while self.samples.len() > 1 && self.samples[1].at <= window_start {
+                self.samples.pop_front();
+            }
+        }
+    }
+
+    pub fn rate_at(&self, now: Instant) -> CaptureRate {
+        let latest = self.samples[self.samples.len() - 1];
+        // the counts at the start of the window, or when recording began
+        let baseline = match now.checked_sub(CAPTURE_RATE_WINDOW) {
+            Some(window_start) => self
+                .samples
+                .iter()
+                .rev()
+                .find(|sample| sample.at <= window_start)
+                .unwrap_or(&self.samples[0]),
+            None => &self.samples[0],
+        };
+        let elapsed = now.saturating_duration_since(baseline.at).as_secs_f64();
+        let per_sec = |count: f64| if elapsed > 0.0 { count / elapsed } else { 0.0 };
+        CaptureRate {
+            bytes_per_sec: per_sec(latest.bytes.saturating_sub(baseline.bytes) as f64),
+            messages_per_sec: per_sec((latest.messages - baseline.messages) as f64),
+            messages_total: self.messages_total,
+        }
+    }
+}
+
+/// Shares the current recording's [CaptureRateMeter] between the recording
+/// task, which feeds it, and the HTTP server, which reports it
+#[derive(Clone, Default)]
+pub struct CaptureRateTracker(Arc<Mutex<Option<CaptureRateMeter>>>);
+
+impl CaptureRateTracker {
+    pub fn start(&self, now: Instant) {
+        *self.0.lock().unwrap() = Some(CaptureRateMeter::new(now));
+    }
+
+    pub fn stop(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+
+    pub fn record(&self, total_bytes: u64, messages: usize, now: Instant) {
+        if let Some(meter) = self.0.lock().unwrap().as_mut() {
+            meter.record(total_bytes, messages, now);
+        }
+    }
+
+    /// The current recording's capture rate, or None if nothing is recording
+    pub fn rate(&self) -> Option<CaptureRate> {
+        self.0
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|meter| meter.rate_at(Instant::now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(start: Instant, secs: f64) -> Instant {
+        start + Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn test_capture_rate() {
+        let start = Instant::now();
+        let mut meter = CaptureRateMeter::new(start);
+        assert_eq!(meter.rate_at(start).bytes_per_sec, 0.0);
+
+        // 2000 bytes in 4 messages a second for the first second
+        for (size, at) in [(500, 0.25), (1000, 0.5), (1500, 0.75), (2000, 1.0)] {
+            meter.record(size, 1, secs(start, at));
+        }
+        let rate = meter.rate_at(secs(start, 1.0));
+        assert_eq!(rate.bytes_per_sec, 2000.0);
+        assert_eq!(rate.messages_per_sec, 4.0);
+        assert_eq!(rate.messages_total, 4);
+
+        // then 1000 bytes in 2 messages a second for 10 seconds, which is
+        // all the window sees
+        for i in 1..=20 {
+            meter.record(2000 + 500 * i, 1, secs(start, 1.0 + 0.5 * i as f64));
+        }
+        let rate = meter.rate_at(secs(start, 11.0));
+        assert_eq!(rate.bytes_per_sec, 1000.0);
+        assert_eq!(rate.messages_per_sec, 2.0);
+        assert_eq!(rate.messages_total, 24);
+    }
+
+    #[test]
+    fn test_capture_rate_falls_when_stalled() {
+        let start = Instant::now();
+        let mut meter = CaptureRateMeter::new(start);
+        for i in 1..=10 {
+            meter.record(100 * i, 1, secs(start, i as f64));
+        }
+        // two seconds after the last write, the window only holds three
+        // seconds' worth of data
+        assert_eq!(meter.rate_at(secs(start, 12.0)).bytes_per_sec, 60.0);
+
+        let stalled = meter.rate_at(secs(start, 20.0));
+        assert_eq!(stalled.bytes_per_sec, 0.0);
+        assert_eq!(stalled.messages_per_sec, 0.0);
+        assert_eq!(stalled.messages_total, 10);
+    }
+
+    #[test]
+    fn test_tracker_only_reports_while_recording() {
+        let tracker = CaptureRateTracker::default();
+        assert_eq!(tracker.rate(), None);
+        tracker.start(Instant::now());
+        tracker.record(100, 1, Instant::now());
+        assert_eq!(tracker.rate().unwrap().messages_total, 1);
+        tracker.stop();
+        assert_eq!(tracker.rate(), None);
+    }
+}
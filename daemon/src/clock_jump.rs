@@ -0,0 +1,199 @@
+//! Detects the system clock jumping while recording. These devices set their
+//! clock whenever they get the chance, so it can move by hours mid-recording,
+//! e.g. when the time offset is set or the network's time arrives.
+//! Timestamps on either side of a jump can't be compared, so each one is
+//! noted in the recording's manifest entry and analysis report.
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, SecondsFormat, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+/// How far the clock has to move, beyond the time which actually passed,
+/// to count as a jump
+pub const DEFAULT_CLOCK_JUMP_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// A jump in the system clock during a recording
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct ClockJump {
+    /// What the system time would have been without the jump
+    #[cfg_attr(feature = "apidocs", schema(value_type = String))]
+    pub before: DateTime<Local>,
+    /// The system time it jumped to
+    #[cfg_attr(feature = "apidocs", schema(value_type = String))]
+    pub after: DateTime<Local>,
+    /// The size of the QMDL file when the jump was noticed, which is where
+    /// the first message after it starts
+    pub qmdl_offset: usize,
+}
+
+impl ClockJump {
+    /// How far the clock jumped, negative if it went backwards
+    pub fn offset(&self) -> TimeDelta {
+        self.after - self.before
+    }
+
+    /// Describes the jump, for reports
+    pub fn describe(&self) -> String {
+        let offset = self.offset();
+        let direction = if offset < TimeDelta::zero() {
+            "back"
+        } else {
+            "forward"
+        };
+        format!(
+            "system clock jumped {direction} {}s, from {} to {}; timestamps before and after this aren't comparable",
+            offset.num_seconds().abs(),
+            self.before.to_rfc3339_opts(SecondsFormat::Secs, true),
+            self.after.to_rfc3339_opts(SecondsFormat::Secs, true),
+        )
+    }
+}
+
+/// Compares how far the system clock moves between checks with how much
+/// time actually passed, going by the monotonic clock
+pub struct ClockJumpDetector {
+    threshold: TimeDelta,
+    last_check: Option<(Instant, DateTime<Local>)>,
+}
+
+impl ClockJumpDetector {
+    pub fn new(threshold: Duration) -> Self {
+        ClockJumpDetector {
+            threshold: TimeDelta::from_std(threshold).unwrap_or(TimeDelta::MAX),
+            last_check: None,
+        }
+    }
+
+    /// Forgets the last check, e.g. when a new recording starts
+    pub fn reset(&mut self) {
+        self.last_check = None;
+    }
+
+    /// Checks the system time `now` against the monotonic time `instant`,
+    /// returning the jump if the clock moved too far since the last check.
+    /// The first check after [Self::new] or [Self::reset] never finds one.
+    pub fn check(
+        &mut self,
+        instant: Instant,
+        now: DateTime<Local>,
+        qmdl_offset: usize,
+    ) -> Option<ClockJump> {
+        let last_check = self.last_check.replace((instant, now));
+        let (last_instant, last_now) = last_check?;
+        let elapsed = TimeDelta::from_std(instant.saturating_duration_since(last_instant)).ok()?;
+        let expected = last_now + elapsed;
+        if (now - expected).abs() <= self.threshold {
+            return None;
+        }
+        Some(ClockJump {
+            before: expected,
+            after: now,
+            qmdl_offset,
+        })
+    }
+}
+
+impl Default for ClockJumpDetector {
+    fn default() -> Self {
+        Self::new(DEFAULT_CLOCK_JUMP_THRESHOLD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds the detector checks which are `step` apart by the monotonic
+    // clock, with the system clock moved by `adjustments` (in seconds) on
+    // top, returning the index and size of each jump found
+    fn run(step: Duration, adjustments: &[i64]) -> Vec<(usize, i64)> {
+        let mut detector = ClockJumpDetector::default();
+        let start = Instant::now();
+        let start_time = DateTime::parse_from_rfc3339("2024-01-01T12:00:00+00:00")
+            .unwrap()
+            .with_timezone(&Local);
+        let mut adjustment = 0;
+        let mut jumps = Vec::new();
+        for (i, adjust) in adjustments.iter().enumerate() {
+            adjustment += adjust;
+            let elapsed = step * i as u32;
+            let now = start_time + elapsed + TimeDelta::seconds(adjustment);
+            if let Some(jump) = detector.check(start + elapsed, now, i) {
+                assert_eq!(jump.qmdl_offset, i);
+                jumps.push((i, jump.offset().num_seconds()));
+            }
+        }
+        jumps
+    }
+
+    #[test]
+    fn test_steady_clock() {
+        assert!(run(Duration::from_secs(1), &[0; 100]).is_empty());
+        // checks far apart don't matter, as long as the clock keeps pace
+        assert!(run(Duration::from_secs(3600), &[0; 10]).is_empty());
+    }
+
+    #[test]
+    fn test_small_adjustments_are_ignored() {
+        // NTP-sized corrections, even if they add up over time
+        assert!(run(Duration::from_secs(1), &[0, 2, -1, 30, 5, -30, 20, 20]).is_empty());
+    }
+
+    #[test]
+    fn test_forward_jump() {
+        assert_eq!(
+            run(Duration::from_secs(1), &[0, 0, 7200, 0, 0]),
+            [(2, 7200)]
+        );
+    }
+
+    #[test]
+    fn test_backward_jump() {
+        assert_eq!(
+            run(Duration::from_secs(5), &[0, 0, 0, -3600, 0, -31, 0]),
+            [(3, -3600), (5, -31)]
+        );
+    }
+
+    #[test]
+    fn test_clock_stopped() {
+        // a clock which stops is as far off as one which jumps back
+        let mut detector = ClockJumpDetector::default();
+        let start = Instant::now();
+        let now = Local::now();
+        assert!(detector.check(start, now, 0).is_none());
+        let jump = detector
+            .check(start + Duration::from_secs(60), now, 10)
+            .unwrap();
+        assert_eq!(jump.offset(), TimeDelta::seconds(-60));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut detector = ClockJumpDetector::default();
+        let start = Instant::now();
+        let now = Local::now();
+        assert!(detector.check(start, now, 0).is_none());
+        detector.reset();
+        // the next recording's first check has nothing to compare against
+        assert!(
+            detector
+                .check(start, now + TimeDelta::hours(1), 0)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_describe() {
+        let before = DateTime::parse_from_rfc3339("2024-01-01T12:00:00Z").unwrap();
+        let jump = ClockJump {
+            before: before.with_timezone(&Local),
+            after: (before - TimeDelta::minutes(90)).with_timezone(&Local),
+            qmdl_offset: 0,
+        };
+        let description = jump.describe();
+        assert!(description.starts_with("system clock jumped back 5400s"));
+    }
+}
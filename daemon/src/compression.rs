@@ -0,0 +1,135 @@
+//! gzip content-encoding for the text endpoints. Analysis reports and logs
+//! compress very well, and the AP link is slow enough for that to matter on
+//! long captures.
+
+use std::io::Write;
+
+use axum::body::Body;
+use axum::http::HeaderMap;
+use axum::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, VARY};
+use axum::response::{IntoResponse, Response};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use futures::{Stream, StreamExt, stream};
+
+/// Whether the client listed gzip in its Accept-Encoding header (and didn't
+/// give it a q-value of zero).
+pub fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let rejected = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            name.eq_ignore_ascii_case("gzip") && !rejected
+        })
+}
+
+/// Compresses a stream of chunks into a gzip stream, chunk by chunk, so the
+/// whole body never has to be held in memory.
+pub fn gzip_stream<S, B>(inner: S) -> impl Stream<Item = Result<Vec<u8>, std::io::Error>>
+where
+    S: Stream<Item = Result<B, std::io::Error>> + Send + 'static,
+    B: AsRef<[u8]>,
+{
+    let encoder = GzEncoder::new(Vec::new(), Compression::default());
+    stream::unfold(
+        (Box::pin(inner), Some(encoder)),
+        |(mut inner, mut encoder)| async move {
+            loop {
+                let mut enc = encoder.take()?;
+                let output = match inner.next().await {
+                    Some(Ok(chunk)) => match enc.write_all(chunk.as_ref()) {
+                        Ok(()) => {
+                            let compressed = std::mem::take(enc.get_mut());
+                            encoder = Some(enc);
+                            if compressed.is_empty() {
+                                // the encoder is still buffering, keep feeding it
+                                continue;
+                            }
+                            Ok(compressed)
+                        }
+                        Err(err) => Err(err),
+                    },
+                    Some(Err(err)) => Err(err),
+                    None => enc.finish(),
+                };
+                return Some((output, (inner, encoder)));
+            }
+        },
+    )
+}
+
+/// Builds a streaming response with the given content type, gzip-compressing
+/// the body if the request's headers allow it.
+pub fn maybe_gzip_response<S, B>(
+    request_headers: &HeaderMap,
+    content_type: &'static str,
+    body: S,
+) -> Response
+where
+    S: Stream<Item = Result<B, std::io::Error>> + Send + 'static,
+    B: AsRef<[u8]> + Into<axum::body::Bytes> + 'static,
+{
+    if accepts_gzip(request_headers) {
+        let headers = [
+            (CONTENT_TYPE, content_type),
+            (CONTENT_ENCODING, "gzip"),
+            (VARY, "accept-encoding"),
+        ];
+        (headers, Body::from_stream(gzip_stream(body))).into_response()
+    } else {
+        let headers = [(CONTENT_TYPE, content_type), (VARY, "accept-encoding")];
+        (headers, Body::from_stream(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use std::io::Read;
+
+    fn headers_with(accept_encoding: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(accept_encoding));
+        headers
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        assert!(!accepts_gzip(&HeaderMap::new()));
+        assert!(accepts_gzip(&headers_with("gzip")));
+        assert!(accepts_gzip(&headers_with("deflate, GZIP;q=0.5, br")));
+        assert!(!accepts_gzip(&headers_with("gzip;q=0")));
+        assert!(!accepts_gzip(&headers_with("deflate, br")));
+    }
+
+    #[tokio::test]
+    async fn test_gzip_stream_roundtrip() {
+        let chunks: Vec<Result<String, std::io::Error>> = (0..1000)
+            .map(|i| Ok(format!("{{\"row\": {i}}}\n")))
+            .collect();
+        let expected: String = chunks.iter().map(|c| c.as_ref().unwrap().clone()).collect();
+
+        let compressed: Vec<u8> = gzip_stream(stream::iter(chunks))
+            .map(|chunk| chunk.unwrap())
+            .concat()
+            .await;
+        assert!(compressed.len() < expected.len());
+
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, expected);
+    }
+}
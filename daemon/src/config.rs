@@ -2,11 +2,25 @@ use log::warn;
 use serde::{Deserialize, Serialize};
 
 use rayhunter::Device;
-use rayhunter::analysis::analyzer::AnalyzerConfig;
+use rayhunter::analysis::analyzer::{AnalyzerConfig, EventType};
 
 use crate::error::RayhunterError;
+use crate::gsmtap_stream::parse_destination;
 use crate::notifications::NotificationType;
 
+/// What happens to the diag stream while recording
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub enum RecordingMode {
+    /// Write a QMDL file and its analysis for each recording
+    #[default]
+    Full,
+    /// Only run the analyzers on live traffic. Events, notifications and the
+    /// display work as usual, but nothing is written to the QMDL store.
+    MonitorOnly,
+}
+
 /// The structure of a valid rayhunter configuration
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
@@ -14,28 +28,70 @@ use crate::notifications::NotificationType;
 pub struct Config {
     /// Path to store QMDL files
     pub qmdl_store_path: String,
+    /// Where the filesystem holding qmdl_store_path should be mounted, e.g.
+    /// an SD card. Recording is refused while nothing is mounted there.
+    pub qmdl_store_expect_mount: Option<String>,
     /// Listening port
     pub port: u16,
     /// Debug mode
     pub debug_mode: bool,
+    /// Run on a development machine rather than a device: no diag device,
+    /// display, key input, firewall or wifi client
+    pub developer_mode: bool,
+    /// Log level: off, error, warn, info, debug or trace
+    pub log_level: String,
     /// Internal device name
     pub device: Device,
     /// UI level
     pub ui_level: u8,
     /// Colorblind mode
     pub colorblind_mode: bool,
+    /// Color of the status line while nothing has been detected, as a
+    /// `#rrggbb` hex string. Ignored in colorblind mode.
+    pub display_accent_color: String,
+    /// Maximum number of display redraws per second (0 = unlimited)
+    pub display_max_redraws_per_sec: u32,
+    /// Turn the display off this many seconds after its status last changed
+    /// or the power button was last pressed (0 = never)
+    pub display_timeout_secs: u64,
+    /// Slowly shift and dim the display while it isn't changing
+    pub display_burn_in_mitigation: bool,
+    /// Keep the display off while recording, until a warning is detected or
+    /// the power button is pressed
+    pub display_off_while_recording: bool,
     /// Key input mode
     pub key_input_mode: u8,
     /// ntfy.sh URL
     pub ntfy_url: Option<String>,
     /// Vector containing the types of enabled notifications
     pub enabled_notifications: Vec<NotificationType>,
+    /// Window (in seconds) over which repeated warning notifications are
+    /// rolled up into a summary (0 = send every warning)
+    pub notification_storm_window_secs: u64,
+    /// Template for notifications' titles, e.g. "[{severity}] Rayhunter
+    /// Alert". Notifications have no title if unset.
+    pub notification_title_template: Option<String>,
+    /// Template for notifications' bodies, e.g. "{analyzer}: {message} at
+    /// {timestamp} on {device}". The message is sent as it is if unset.
+    pub notification_body_template: Option<String>,
     /// Vector containing the list of enabled analyzers
     pub analyzers: AnalyzerConfig,
+    /// Number of recordings to analyze concurrently
+    pub analysis_workers: usize,
+    /// Whether recordings are kept on disk, or only analyzed live
+    pub recording_mode: RecordingMode,
     /// Minimum disk space required to start a recording
     pub min_space_to_start_recording_mb: u64,
     /// Minimum disk space required to continue a recording
     pub min_space_to_continue_recording_mb: u64,
+    /// How far the system clock has to jump while recording, in seconds,
+    /// for the jump to be noted in the recording's manifest entry and report
+    pub clock_jump_threshold_secs: u64,
+    /// Recordings smaller than this are refused for analysis
+    pub min_recording_size_to_analyze_bytes: u64,
+    /// Redact IMSIs, IMEIs and phone numbers from exported PCAPs, ZIPs and
+    /// analysis reports, unless the request says otherwise
+    pub redact_identifiers: bool,
     /// Wifi client SSID
     pub wifi_ssid: Option<String>,
     /// Wifi client password
@@ -44,41 +100,106 @@ pub struct Config {
     pub wifi_security: Option<wifi_station::SecurityType>,
     /// Wifi client mode
     pub wifi_enabled: bool,
+    /// Check whether the wifi client's network is behind a captive portal
+    pub wifi_detect_captive_portal: bool,
+    /// Check new wifi client credentials by connecting with them before
+    /// saving them, keeping the previous ones if that fails
+    pub wifi_verify_credentials: bool,
+    /// How long after a wifi scan further scan requests get its results
+    /// instead of scanning again
+    pub wifi_scan_cooldown_secs: u64,
     /// Vector containing wifi client DNS servers
     pub dns_servers: Option<Vec<String>>,
     /// Wifi client firewall mode
     pub firewall_restrict_outbound: bool,
     /// Vector containing additional wifi client firewall ports to open
     pub firewall_allowed_ports: Option<Vec<u16>>,
+    /// Stream live GSMTAP frames over UDP
+    pub gsmtap_stream_enabled: bool,
+    /// Where to stream GSMTAP frames to, as host or host:port
+    pub gsmtap_stream_destination: String,
+    /// How many recently decoded messages to keep for /api/live-tail
+    pub live_tail_size: usize,
+    /// Watch for unknown clients joining the device's own wifi AP
+    pub ap_client_monitor_enabled: bool,
+    /// The severity of the warning sent when an unknown AP client joins
+    pub ap_client_event_severity: EventType,
+    /// Hide the second half of AP clients' MACs in notifications
+    pub ap_client_redact_macs: bool,
+    /// Tag recordings with the device's GPS position
+    pub location_tagging: bool,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             qmdl_store_path: "/data/rayhunter/qmdl".to_string(),
+            qmdl_store_expect_mount: None,
             port: 8080,
             debug_mode: false,
+            developer_mode: false,
+            log_level: "info".to_string(),
             device: Device::Orbic,
             ui_level: 1,
             colorblind_mode: false,
+            display_accent_color: "#00ff00".to_string(),
+            display_max_redraws_per_sec: 4,
+            display_timeout_secs: 0,
+            display_burn_in_mitigation: false,
+            display_off_while_recording: false,
             key_input_mode: 0,
             analyzers: AnalyzerConfig::default(),
+            analysis_workers: 1,
             ntfy_url: None,
             enabled_notifications: vec![NotificationType::Warning, NotificationType::LowBattery],
+            notification_storm_window_secs: 120,
+            notification_title_template: None,
+            notification_body_template: None,
+            recording_mode: RecordingMode::Full,
             min_space_to_start_recording_mb: 1,
             min_space_to_continue_recording_mb: 1,
+            clock_jump_threshold_secs: 30,
+            min_recording_size_to_analyze_bytes: 1024,
+            redact_identifiers: false,
             wifi_ssid: None,
             wifi_password: None,
             wifi_security: None,
             wifi_enabled: false,
+            wifi_detect_captive_portal: true,
+            wifi_verify_credentials: false,
+            wifi_scan_cooldown_secs: 30,
             dns_servers: None,
             firewall_restrict_outbound: true,
             firewall_allowed_ports: None,
+            gsmtap_stream_enabled: false,
+            gsmtap_stream_destination: "127.0.0.1".to_string(),
+            live_tail_size: 500,
+            ap_client_monitor_enabled: false,
+            ap_client_event_severity: EventType::Low,
+            ap_client_redact_macs: true,
+            location_tagging: false,
         }
     }
 }
 
 impl Config {
+    /// Whether the display may turn itself off, and so needs the power
+    /// button to wake it
+    pub fn display_can_sleep(&self) -> bool {
+        self.display_timeout_secs > 0 || self.display_off_while_recording
+    }
+
+    /// The UDP port GSMTAP frames are streamed to, if streaming is enabled
+    /// and the destination is valid
+    pub fn gsmtap_stream_port(&self) -> Option<u16> {
+        if !self.gsmtap_stream_enabled {
+            return None;
+        }
+        parse_destination(&self.gsmtap_stream_destination)
+            .ok()
+            .map(|(_, port)| port)
+    }
+
     pub fn wifi_config(&self) -> wifi_station::WifiConfig {
         let (wpa_bin, hostapd_conf, ctrl_interface) = match self.device {
             Device::Tmobile | Device::Wingtech => (
@@ -150,10 +271,24 @@ pub struct Args {
     pub config_path: String,
 }
 
+/// What `--version` prints: the version recorded in new manifest entries,
+/// and the commit it was built from if known.
+pub fn version_string() -> String {
+    let metadata = rayhunter::util::RuntimeMetadata::new();
+    match metadata.build_git_hash {
+        Some(hash) => format!("rayhunter-daemon {} ({hash})", metadata.rayhunter_version),
+        None => format!("rayhunter-daemon {}", metadata.rayhunter_version),
+    }
+}
+
 pub fn parse_args() -> Args {
     let args: Vec<String> = std::env::args().collect();
+    if args.get(1).is_some_and(|arg| arg == "--version") {
+        println!("{}", version_string());
+        std::process::exit(0);
+    }
     if args.len() != 2 {
-        println!("Usage: {} /path/to/config/file", args[0]);
+        println!("Usage: {} /path/to/config/file | --version", args[0]);
         std::process::exit(1);
     }
     Args {
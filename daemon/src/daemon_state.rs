@@ -0,0 +1,216 @@
+//! Bookkeeping about daemon starts which is persisted across runs, so we can
+//! report how long the daemon has been up, how many times it has started, and
+//! why it (re)started the last time.
+
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::disk_space::has_room_for_auxiliary_write;
+
+const DAEMON_STATE_FILENAME: &str = "daemon_state.toml";
+
+/// Why the daemon started most recently
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub enum StartReason {
+    /// The previous run shut down cleanly (or this is the first run ever)
+    PowerOn,
+    /// A restart was requested through the API, e.g. after a config change
+    ConfigRestart,
+    /// The previous run never recorded a clean shutdown
+    CrashRecovered,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+struct PersistedDaemonState {
+    boot_count: u64,
+    // set when a run starts, and cleared again once it has closed the
+    // manifest cleanly. finding it set on startup means we crashed.
+    running: bool,
+}
+
+/// Daemon lifecycle statistics
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct DaemonStats {
+    /// Seconds since the daemon last started (including config restarts)
+    pub daemon_uptime_secs: u64,
+    /// Number of times the daemon has started
+    pub boot_count: u64,
+    /// The reason for the most recent start
+    pub last_start_reason: StartReason,
+    /// The PID recorded in the instance lock, or null if the lock couldn't
+    /// be taken
+    pub lock_holder_pid: Option<u32>,
+}
+
+pub struct DaemonStateTracker {
+    path: PathBuf,
+    started_at: Instant,
+    boot_count: u64,
+    last_start_reason: StartReason,
+    lock_holder_pid: Option<u32>,
+}
+
+/// Returns the path of the daemon state file, which lives next to the config file.
+pub fn daemon_state_path<P: AsRef<Path>>(config_path: P) -> PathBuf {
+    config_path.as_ref().with_file_name(DAEMON_STATE_FILENAME)
+}
+
+pub fn derive_start_reason(restart_requested: bool, previous_run_unclean: bool) -> StartReason {
+    if restart_requested {
+        StartReason::ConfigRestart
+    } else if previous_run_unclean {
+        StartReason::CrashRecovered
+    } else {
+        StartReason::PowerOn
+    }
+}
+
+async fn read_state(path: &Path) -> PersistedDaemonState {
+    match tokio::fs::read_to_string(path).await {
+        Ok(contents) => toml::from_str(&contents).unwrap_or_else(|e| {
+            warn!("failed to parse daemon state file {path:?}: {e}");
+            PersistedDaemonState::default()
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => PersistedDaemonState::default(),
+        Err(e) => {
+            warn!("failed to read daemon state file {path:?}: {e}");
+            PersistedDaemonState::default()
+        }
+    }
+}
+
+async fn write_state(path: &Path, state: &PersistedDaemonState) {
+    if !has_room_for_auxiliary_write(path, "daemon state file") {
+        return;
+    }
+    let contents = toml::to_string(state).expect("failed to serialize daemon state");
+    if let Err(e) = tokio::fs::write(path, contents).await {
+        warn!("failed to write daemon state file {path:?}: {e}");
+    }
+}
+
+impl DaemonStateTracker {
+    /// Records a new daemon start in the state file at `path`, deriving the
+    /// start reason from the previous run's state.
+    pub async fn record_start<P: AsRef<Path>>(path: P, restart_requested: bool) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let previous = read_state(&path).await;
+        let last_start_reason = derive_start_reason(restart_requested, previous.running);
+        let boot_count = previous.boot_count + 1;
+        write_state(
+            &path,
+            &PersistedDaemonState {
+                boot_count,
+                running: true,
+            },
+        )
+        .await;
+        DaemonStateTracker {
+            path,
+            started_at: Instant::now(),
+            boot_count,
+            last_start_reason,
+            lock_holder_pid: None,
+        }
+    }
+
+    /// Records which process holds the instance lock, for the system stats.
+    pub fn with_lock_holder_pid(mut self, pid: Option<u32>) -> Self {
+        self.lock_holder_pid = pid;
+        self
+    }
+
+    /// Marks the current run as having shut down cleanly.
+    pub async fn record_clean_shutdown(&self) {
+        write_state(
+            &self.path,
+            &PersistedDaemonState {
+                boot_count: self.boot_count,
+                running: false,
+            },
+        )
+        .await;
+    }
+
+    pub fn last_start_reason(&self) -> StartReason {
+        self.last_start_reason
+    }
+
+    pub fn stats(&self) -> DaemonStats {
+        DaemonStats {
+            daemon_uptime_secs: self.started_at.elapsed().as_secs(),
+            boot_count: self.boot_count,
+            last_start_reason: self.last_start_reason,
+            lock_holder_pid: self.lock_holder_pid,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_derive_start_reason() {
+        assert_eq!(derive_start_reason(false, false), StartReason::PowerOn);
+        assert_eq!(
+            derive_start_reason(false, true),
+            StartReason::CrashRecovered
+        );
+        assert_eq!(derive_start_reason(true, false), StartReason::ConfigRestart);
+        // a requested restart always shuts down through the clean path first,
+        // so it takes precedence over a stale running flag
+        assert_eq!(derive_start_reason(true, true), StartReason::ConfigRestart);
+    }
+
+    #[tokio::test]
+    async fn test_first_start_is_power_on() {
+        let dir = TempDir::new().unwrap();
+        let tracker = DaemonStateTracker::record_start(dir.path().join("state.toml"), false).await;
+        assert_eq!(tracker.last_start_reason(), StartReason::PowerOn);
+        assert_eq!(tracker.stats().boot_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_clean_shutdown_then_power_on() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("state.toml");
+        let tracker = DaemonStateTracker::record_start(&path, false).await;
+        tracker.record_clean_shutdown().await;
+
+        let tracker = DaemonStateTracker::record_start(&path, false).await;
+        assert_eq!(tracker.last_start_reason(), StartReason::PowerOn);
+        assert_eq!(tracker.stats().boot_count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_unclean_shutdown_is_crash_recovered() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("state.toml");
+        let _tracker = DaemonStateTracker::record_start(&path, false).await;
+
+        let tracker = DaemonStateTracker::record_start(&path, false).await;
+        assert_eq!(tracker.last_start_reason(), StartReason::CrashRecovered);
+        assert_eq!(tracker.stats().boot_count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_config_restart_keeps_boot_count() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("state.toml");
+        let tracker = DaemonStateTracker::record_start(&path, false).await;
+        tracker.record_clean_shutdown().await;
+
+        let tracker = DaemonStateTracker::record_start(&path, true).await;
+        assert_eq!(tracker.last_start_reason(), StartReason::ConfigRestart);
+        assert_eq!(tracker.stats().boot_count, 2);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::compression::maybe_gzip_response;
+use crate::server::ServerState;
+
+use anyhow::Error;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use log::error;
+use rayhunter::analysis::dump::InformationElementDumper;
+use rayhunter::diag::DataType;
+use rayhunter::qmdl::QmdlReader;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufWriter, duplex};
+use tokio_util::io::ReaderStream;
+
+// Like get_pcap, this decodes the QMDL data written so far in a spawned task
+// which streams NDJSON lines to a pipe read by the client.
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    get,
+    path = "/api/decoded/{name}",
+    tag = "Recordings",
+    responses(
+        (status = StatusCode::OK, description = "Decoded messages", content_type = "application/x-ndjson"),
+        (status = StatusCode::NOT_FOUND, description = "Could not find file {name}"),
+        (status = StatusCode::SERVICE_UNAVAILABLE, description = "QMDL file is empty")
+    ),
+    params(
+        ("name" = String, Path, description = "QMDL file to decode")
+    ),
+    summary = "Decoded messages",
+    description = "Stream every message in QMDL file {name} that Rayhunter can decode, one JSON object per line, with its packet number, timestamp, protocol, channel and message type. Packet numbers match the ones in the analysis report. The response is gzip-compressed if the request's Accept-Encoding allows it."
+))]
+pub async fn get_decoded_messages(
+    State(state): State<Arc<ServerState>>,
+    Path(qmdl_name): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let qmdl_store = state.qmdl_store_lock.read().await;
+    let (entry_index, entry) = qmdl_store.entry_for_name(&qmdl_name).ok_or((
+        StatusCode::NOT_FOUND,
+        format!("couldn't find manifest entry with name {qmdl_name}"),
+    ))?;
+    if entry.qmdl_size_bytes == 0 {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "QMDL file is empty, try again in a bit!".to_string(),
+        ));
+    }
+    let qmdl_size_bytes = entry.qmdl_size_bytes;
+    let qmdl_file = qmdl_store
+        .open_entry_qmdl(entry_index)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:?}")))?;
+    let (reader, writer) = duplex(1024);
+
+    tokio::spawn(async move {
+        if let Err(e) = generate_decoded_messages(writer, qmdl_file, qmdl_size_bytes).await {
+            error!("failed to decode QMDL: {e:?}");
+        }
+    });
+
+    Ok(maybe_gzip_response(
+        &headers,
+        "application/x-ndjson",
+        ReaderStream::new(reader),
+    ))
+}
+
+pub async fn generate_decoded_messages<R, W>(
+    writer: W,
+    qmdl_file: R,
+    qmdl_size_bytes: usize,
+) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+    R: AsyncRead + Unpin,
+{
+    let mut writer = BufWriter::new(writer);
+    let mut dumper = InformationElementDumper::new();
+    let mut reader = QmdlReader::new(qmdl_file, Some(qmdl_size_bytes));
+    while let Some(container) = reader.get_next_messages_container().await? {
+        if container.data_type != DataType::UserSpace {
+            continue;
+        }
+        for decoded in dumper.dump_qmdl_messages(container) {
+            let mut line = serde_json::to_vec(&decoded)?;
+            line.push(b'\n');
+            writer.write_all(&line).await?;
+        }
+    }
+    writer.flush().await?;
+    Ok(())
+}
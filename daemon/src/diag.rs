@@ -1,43 +1,81 @@
 use std::ops::DerefMut;
-use std::pin::pin;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use axum::body::Body;
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use axum::extract::{Path, Query, State};
 use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::{IntoResponse, Response};
-use futures::{StreamExt, TryStreamExt, future};
+use futures::{TryStreamExt, future};
 use log::{debug, error, info, warn};
+use serde::Deserialize;
+use thiserror::Error;
 use tokio::fs::File;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::sync::{RwLock, oneshot};
+use tokio::sync::{RwLock, broadcast, oneshot, watch};
 use tokio_stream::wrappers::LinesStream;
 use tokio_util::task::TaskTracker;
 
 #[cfg(feature = "apidocs")]
 use rayhunter::analysis::analyzer::ReportMetadata;
-use rayhunter::analysis::analyzer::{AnalysisLineNormalizer, AnalyzerConfig, EventType};
+use rayhunter::analysis::analyzer::{
+    AnalysisLineNormalizer, AnalyzerConfig, EventType, ReportFilter,
+};
+use rayhunter::analysis::event_message::{EventFormatter, MessageLength};
 use rayhunter::diag::{DataType, MessagesContainer};
-use rayhunter::diag_device::DiagDevice;
 use rayhunter::qmdl::QmdlWriter;
+use rayhunter::redact::redact_report_line;
 
-use crate::analysis::{AnalysisCtrlMessage, AnalysisWriter};
+use crate::analysis::{AnalysisCtrlMessage, AnalysisWriter, MaxEvent};
+use crate::capture_rate::CaptureRateTracker;
+use crate::clock_jump::ClockJumpDetector;
+use crate::compression::maybe_gzip_response;
+use crate::config::RecordingMode;
+use crate::diag_multiplexer::{DiagSubscriber, ParseErrorCounter};
+use crate::disk_space;
 use crate::display;
-use crate::notifications::{Notification, NotificationType};
+use crate::event_store::EventStore;
+use crate::live_tail::LiveTail;
+use crate::location::LocationTracker;
+use crate::notifications::{Notification, NotificationDetails, NotificationType};
 use crate::qmdl_store::{RecordingStore, RecordingStoreError};
 use crate::server::ServerState;
-use crate::stats::DiskStats;
 
 const DISK_CHECK_BYTES_INTERVAL: usize = 256 * 1024;
 
+/// The stop reason recorded on a recording stopped because the filesystem it
+/// was on went away, e.g. because the SD card was removed
+pub const STORAGE_LOST_STOP_REASON: &str = "storage_lost";
+
+/// How many recording changes a live QMDL client may fall behind on
+pub const LIVE_QMDL_CHANNEL_CAPACITY: usize = 64;
+
+/// Changes to the current recording, broadcast to clients of the live QMDL
+/// stream. The data itself comes straight from the diag multiplexer.
+#[derive(Clone, Debug)]
+pub enum LiveQmdlMessage {
+    /// The current recording was paused, so nothing is streamed until it's
+    /// resumed
+    RecordingPaused,
+    RecordingResumed,
+    /// The current recording ended, so the stream should end too
+    RecordingStopped,
+}
+
 pub enum DiagDeviceCtrlMessage {
     StopRecording,
     StartRecording {
         response_tx: Option<oneshot::Sender<Result<(), String>>>,
     },
+    PauseRecording {
+        response_tx: oneshot::Sender<Result<(), PauseError>>,
+    },
+    ResumeRecording {
+        response_tx: oneshot::Sender<Result<(), PauseError>>,
+    },
     DeleteEntry {
         name: String,
         response_tx: oneshot::Sender<Result<(), RecordingStoreError>>,
@@ -48,17 +86,55 @@ pub enum DiagDeviceCtrlMessage {
     Exit,
 }
 
+/// The channels, shared state and settings the diag task runs with
+pub struct DiagTaskConfig {
+    pub ui_update_sender: Sender<display::DisplayState>,
+    pub analysis_sender: Sender<AnalysisCtrlMessage>,
+    pub analyzer_config: AnalyzerConfig,
+    pub notification_channel: tokio::sync::mpsc::Sender<Notification>,
+    pub live_qmdl_sender: broadcast::Sender<LiveQmdlMessage>,
+    pub event_store_lock: Arc<RwLock<EventStore>>,
+    /// Told the store's new path when it moves onto its filesystem once
+    /// that's mounted
+    pub store_path_sender: watch::Sender<PathBuf>,
+    pub live_tail: LiveTail,
+    pub capture_rate: CaptureRateTracker,
+    pub location: LocationTracker,
+    /// Set while monitor-only analysis is running
+    pub monitoring: Arc<RwLock<bool>>,
+    pub min_space_to_start_mb: u64,
+    pub min_space_to_continue_mb: u64,
+    pub recording_mode: RecordingMode,
+    pub clock_jump_threshold: Duration,
+}
+
 pub struct DiagTask {
     ui_update_sender: Sender<display::DisplayState>,
     analysis_sender: Sender<AnalysisCtrlMessage>,
     analyzer_config: AnalyzerConfig,
     notification_channel: tokio::sync::mpsc::Sender<Notification>,
+    live_qmdl_sender: broadcast::Sender<LiveQmdlMessage>,
+    event_store_lock: Arc<RwLock<EventStore>>,
+    store_path_sender: watch::Sender<PathBuf>,
+    live_tail: LiveTail,
+    parse_errors: ParseErrorCounter,
+    capture_rate: CaptureRateTracker,
+    location: LocationTracker,
+    monitoring: Arc<RwLock<bool>>,
     min_space_to_start_mb: u64,
     min_space_to_continue_mb: u64,
+    recording_mode: RecordingMode,
     state: DiagState,
     max_type_seen: EventType,
     bytes_since_space_check: usize,
     low_space_warned: bool,
+    event_formatter: EventFormatter,
+    clock_jumps: ClockJumpDetector,
+    /// The PLMN of the cell whose SIB1 was seen last. It's kept across
+    /// recordings, since the modem only logs SIB1 when it (re)reads it.
+    serving_plmn: Option<String>,
+    /// When the current recording began, by the monotonic clock
+    recording_started: Instant,
 }
 
 enum DiagState {
@@ -66,9 +142,37 @@ enum DiagState {
         qmdl_writer: QmdlWriter<File>,
         analysis_writer: Box<AnalysisWriter>,
     },
+    /// The recording's files stay open, but diag messages are discarded
+    /// until it's resumed
+    Paused {
+        qmdl_writer: QmdlWriter<File>,
+        analysis_writer: Box<AnalysisWriter>,
+    },
+    /// Monitor-only mode: the analyzers run, but nothing is written to the
+    /// QMDL store. `session` stands in for the recording name in events and
+    /// notifications.
+    Monitoring {
+        analysis_writer: Box<AnalysisWriter>,
+        session: String,
+    },
     Stopped,
 }
 
+/// Why a recording couldn't be paused or resumed
+#[derive(Debug, Error)]
+pub enum PauseError {
+    #[error("not recording")]
+    NotRecording,
+    #[error("recording is already paused")]
+    AlreadyPaused,
+    #[error("recording isn't paused")]
+    NotPaused,
+    #[error("monitor-only mode has no recording to pause")]
+    MonitorOnly,
+    #[error("couldn't update manifest: {0}")]
+    Manifest(RecordingStoreError),
+}
+
 enum DiskSpaceCheck {
     Ok(u64),
     Warning(u64),
@@ -77,9 +181,9 @@ enum DiskSpaceCheck {
 }
 
 fn check_disk_space(path: &std::path::Path, warning_mb: u64, critical_mb: u64) -> DiskSpaceCheck {
-    match DiskStats::new(path.to_str().unwrap()) {
-        Ok(stats) => {
-            let available_mb = stats.available_bytes.unwrap_or(0) / 1024 / 1024;
+    match disk_space::available_bytes(path) {
+        Ok(available_bytes) => {
+            let available_mb = available_bytes / 1024 / 1024;
             if available_mb < critical_mb {
                 DiskSpaceCheck::Critical(available_mb)
             } else if available_mb < warning_mb {
@@ -96,25 +200,48 @@ fn check_disk_space(path: &std::path::Path, warning_mb: u64, critical_mb: u64) -
 }
 
 impl DiagTask {
-    fn new(
-        ui_update_sender: Sender<display::DisplayState>,
-        analysis_sender: Sender<AnalysisCtrlMessage>,
-        analyzer_config: AnalyzerConfig,
-        notification_channel: tokio::sync::mpsc::Sender<Notification>,
-        min_space_to_start_mb: u64,
-        min_space_to_continue_mb: u64,
-    ) -> Self {
+    fn new(config: DiagTaskConfig, parse_errors: ParseErrorCounter) -> Self {
+        let DiagTaskConfig {
+            ui_update_sender,
+            analysis_sender,
+            analyzer_config,
+            notification_channel,
+            live_qmdl_sender,
+            event_store_lock,
+            store_path_sender,
+            live_tail,
+            capture_rate,
+            location,
+            monitoring,
+            min_space_to_start_mb,
+            min_space_to_continue_mb,
+            recording_mode,
+            clock_jump_threshold,
+        } = config;
         Self {
             ui_update_sender,
             analysis_sender,
             analyzer_config,
             notification_channel,
+            live_qmdl_sender,
+            event_store_lock,
+            store_path_sender,
+            live_tail,
+            parse_errors,
+            capture_rate,
+            location,
+            monitoring,
             min_space_to_start_mb,
             min_space_to_continue_mb,
+            recording_mode,
             state: DiagState::Stopped,
             max_type_seen: EventType::Informational,
             bytes_since_space_check: 0,
             low_space_warned: false,
+            event_formatter: EventFormatter::default(),
+            clock_jumps: ClockJumpDetector::new(clock_jump_threshold),
+            serving_plmn: None,
+            recording_started: Instant::now(),
         }
     }
 
@@ -124,6 +251,24 @@ impl DiagTask {
         self.bytes_since_space_check = 0;
         self.low_space_warned = false;
 
+        if self.recording_mode == RecordingMode::MonitorOnly {
+            self.start_monitoring().await;
+            return Ok(());
+        }
+
+        match qmdl_store.reopen_if_mounted().await {
+            Ok(true) => {
+                self.store_path_sender.send_replace(qmdl_store.path.clone());
+            }
+            Ok(false) => {}
+            Err(e) => warn!("couldn't open the QMDL store now that it's mounted: {e}"),
+        }
+        if let Err(e) = qmdl_store.check_mount() {
+            let msg = format!("QMDL store unavailable: {e}");
+            error!("{msg}");
+            return Err(msg);
+        }
+
         match check_disk_space(
             &qmdl_store.path,
             self.min_space_to_start_mb,
@@ -143,6 +288,10 @@ impl DiagTask {
             DiskSpaceCheck::Failed => {}
         }
 
+        if !matches!(self.state, DiagState::Stopped) {
+            self.end_pause(qmdl_store).await;
+            self.tag_end_location(qmdl_store).await;
+        }
         let (qmdl_file, analysis_file) = match qmdl_store.new_entry().await {
             Ok(files) => files,
             Err(e) => {
@@ -152,20 +301,47 @@ impl DiagTask {
             }
         };
         self.stop_current_recording().await;
-        let qmdl_writer = QmdlWriter::new(qmdl_file);
-        let analysis_writer = match AnalysisWriter::new(analysis_file, &self.analyzer_config).await
-        {
-            Ok(writer) => Box::new(writer),
-            Err(e) => {
-                let msg = format!("failed to create analysis writer: {e}");
+        self.live_tail.clear();
+        let (recording_name, summary_path) = match qmdl_store.get_current_entry() {
+            Some((_, entry)) => (
+                entry.name.clone(),
+                entry.get_summary_filepath(&qmdl_store.path),
+            ),
+            None => {
+                let msg = "new QMDL entry isn't the current entry".to_string();
                 error!("{msg}");
                 return Err(msg);
             }
         };
+        let start_location = self.location.current();
+        if let Some(location) = start_location
+            && let Err(e) = qmdl_store.set_current_start_location(location).await
+        {
+            warn!("couldn't set start location: {e}");
+        }
+        let qmdl_writer = QmdlWriter::new(qmdl_file);
+        let analysis_writer =
+            match AnalysisWriter::new(analysis_file, &self.analyzer_config, start_location).await {
+                Ok(writer) => Box::new(
+                    writer
+                        .with_event_store(self.event_store_lock.clone(), &recording_name)
+                        .with_summary(summary_path)
+                        .with_live_tail(self.live_tail.clone())
+                        .with_parse_error_counter(self.parse_errors.clone()),
+                ),
+                Err(e) => {
+                    let msg = format!("failed to create analysis writer: {e}");
+                    error!("{msg}");
+                    return Err(msg);
+                }
+            };
         self.state = DiagState::Recording {
             qmdl_writer,
             analysis_writer,
         };
+        self.recording_started = Instant::now();
+        self.clock_jumps.reset();
+        self.capture_rate.start(self.recording_started);
         if let Err(e) = self
             .ui_update_sender
             .send(display::DisplayState::Recording)
@@ -176,8 +352,146 @@ impl DiagTask {
         Ok(())
     }
 
+    // Starts analyzing live traffic without a recording, which needs neither
+    // the QMDL store nor any disk space
+    async fn start_monitoring(&mut self) {
+        self.stop_current_recording().await;
+        self.live_tail.clear();
+        let now = rayhunter::clock::get_adjusted_now();
+        let session = format!("monitor-{}", now.timestamp());
+        let analysis_writer = Box::new(
+            AnalysisWriter::monitor_only(&self.analyzer_config)
+                .with_event_store(self.event_store_lock.clone(), &session)
+                .with_live_tail(self.live_tail.clone())
+                .with_parse_error_counter(self.parse_errors.clone()),
+        );
+        info!("monitor-only mode, analyzing without writing a QMDL file");
+        self.state = DiagState::Monitoring {
+            analysis_writer,
+            session,
+        };
+        *self.monitoring.write().await = true;
+        if let Err(e) = self
+            .ui_update_sender
+            .send(display::DisplayState::Recording)
+            .await
+        {
+            warn!("couldn't send ui update message: {e}");
+        }
+    }
+
+    async fn tag_end_location(&self, qmdl_store: &mut RecordingStore) {
+        if let Some(location) = self.location.current()
+            && let Err(e) = qmdl_store.set_current_end_location(location).await
+        {
+            warn!("couldn't set end location: {e}");
+        }
+    }
+
+    /// Pause the current recording, keeping its files open.
+    async fn pause(&mut self, qmdl_store: &mut RecordingStore) -> Result<(), PauseError> {
+        let qmdl_offset = match &self.state {
+            DiagState::Recording { qmdl_writer, .. } => qmdl_writer.total_written,
+            DiagState::Paused { .. } => return Err(PauseError::AlreadyPaused),
+            DiagState::Monitoring { .. } => return Err(PauseError::MonitorOnly),
+            DiagState::Stopped => return Err(PauseError::NotRecording),
+        };
+        qmdl_store
+            .begin_current_pause(qmdl_offset)
+            .await
+            .map_err(PauseError::Manifest)?;
+        if let DiagState::Recording {
+            qmdl_writer,
+            analysis_writer,
+        } = std::mem::replace(&mut self.state, DiagState::Stopped)
+        {
+            self.state = DiagState::Paused {
+                qmdl_writer,
+                analysis_writer,
+            };
+        }
+        info!("recording paused");
+        // an error here just means nobody is listening
+        let _ = self.live_qmdl_sender.send(LiveQmdlMessage::RecordingPaused);
+        if let Err(e) = self
+            .ui_update_sender
+            .send(display::DisplayState::Paused)
+            .await
+        {
+            warn!("couldn't send ui update message: {e}");
+        }
+        Ok(())
+    }
+
+    /// Resume the paused recording, appending to the same files.
+    async fn resume(&mut self, qmdl_store: &mut RecordingStore) -> Result<(), PauseError> {
+        let DiagState::Paused { .. } = self.state else {
+            return Err(PauseError::NotPaused);
+        };
+        let pause = qmdl_store
+            .end_current_pause()
+            .await
+            .map_err(PauseError::Manifest)?;
+        if let DiagState::Paused {
+            qmdl_writer,
+            mut analysis_writer,
+        } = std::mem::replace(&mut self.state, DiagState::Stopped)
+        {
+            if let Err(e) = analysis_writer.write_pause_marker(&pause).await {
+                warn!("couldn't mark pause in analysis: {e}");
+            }
+            self.state = DiagState::Recording {
+                qmdl_writer,
+                analysis_writer,
+            };
+        }
+        info!("recording resumed");
+        // an error here just means nobody is listening
+        let _ = self
+            .live_qmdl_sender
+            .send(LiveQmdlMessage::RecordingResumed);
+        let display_state = if self.max_type_seen > EventType::Informational {
+            display::DisplayState::WarningDetected {
+                event_type: self.max_type_seen,
+            }
+        } else {
+            display::DisplayState::Recording
+        };
+        if let Err(e) = self.ui_update_sender.send(display_state).await {
+            warn!("couldn't send ui update message: {e}");
+        }
+        Ok(())
+    }
+
+    // Ends the current pause before the recording stops, so it's marked in
+    // the analysis like any other
+    async fn end_pause(&mut self, qmdl_store: &mut RecordingStore) {
+        let DiagState::Paused {
+            analysis_writer, ..
+        } = &mut self.state
+        else {
+            return;
+        };
+        match qmdl_store.end_current_pause().await {
+            Ok(pause) => {
+                if let Err(e) = analysis_writer.write_pause_marker(&pause).await {
+                    warn!("couldn't mark pause in analysis: {e}");
+                }
+            }
+            Err(e) => warn!("couldn't end pause: {e}"),
+        }
+    }
+
     /// Stop recording, optionally annotating the entry with a reason.
     async fn stop(&mut self, qmdl_store: &mut RecordingStore, reason: Option<String>) {
+        if let DiagState::Monitoring { .. } = self.state {
+            // nothing was written to the store, so there's no entry to close
+            self.stop_current_recording().await;
+            self.show_stopped().await;
+            return;
+        }
+        self.end_pause(qmdl_store).await;
+        self.tag_end_location(qmdl_store).await;
         self.stop_current_recording().await;
         if let Some(reason) = reason
             && let Err(e) = qmdl_store.set_current_stop_reason(reason).await
@@ -197,6 +511,10 @@ impl DiagTask {
         if let Err(e) = qmdl_store.close_current_entry().await {
             error!("couldn't close current entry: {e}");
         }
+        self.show_stopped().await;
+    }
+
+    async fn show_stopped(&self) {
         if let Err(e) = self
             .ui_update_sender
             .send(display::DisplayState::Paused)
@@ -233,18 +551,55 @@ impl DiagTask {
         res
     }
 
+    /// Stops the recording because the filesystem it's on has gone away,
+    /// as reported by [RecordingStore::check_mount].
+    async fn stop_for_lost_storage(&mut self, qmdl_store: &mut RecordingStore, e: String) {
+        error!("QMDL store lost while recording, stopping: {e}");
+        self.notification_channel
+            .send(Notification::new(
+                NotificationType::Warning,
+                format!("Recording stopped, storage lost: {e}"),
+                None,
+            ))
+            .await
+            .ok();
+        // this most likely fails to reach the manifest, but it's worth a try
+        // in case the storage is back by now
+        self.stop(qmdl_store, Some(STORAGE_LOST_STOP_REASON.to_string()))
+            .await;
+    }
+
     async fn stop_current_recording(&mut self) {
         let mut state = DiagState::Stopped;
         std::mem::swap(&mut self.state, &mut state);
-        if let DiagState::Recording {
-            analysis_writer, ..
-        } = state
-        {
-            analysis_writer
-                .close()
-                .await
-                .expect("failed to close analysis writer");
+        match state {
+            DiagState::Recording {
+                analysis_writer, ..
+            }
+            | DiagState::Paused {
+                analysis_writer, ..
+            } => {
+                // this fails if the storage has gone away, which is no reason
+                // to stop the daemon
+                if let Err(e) = analysis_writer.close().await {
+                    error!("failed to close analysis writer: {e}");
+                }
+                // an error here just means nobody is listening
+                let _ = self
+                    .live_qmdl_sender
+                    .send(LiveQmdlMessage::RecordingStopped);
+            }
+            DiagState::Monitoring {
+                analysis_writer, ..
+            } => {
+                if let Err(e) = analysis_writer.close().await {
+                    error!("failed to close analysis writer: {e}");
+                }
+                *self.monitoring.write().await = false;
+            }
+            DiagState::Stopped => {}
         }
+        self.capture_rate.stop();
     }
 
     async fn process_container(
@@ -265,6 +620,10 @@ impl DiagTask {
         {
             if self.bytes_since_space_check >= DISK_CHECK_BYTES_INTERVAL {
                 self.bytes_since_space_check = 0;
+                if let Err(mount_error) = qmdl_store.check_mount() {
+                    self.stop_for_lost_storage(qmdl_store, mount_error).await;
+                    return;
+                }
                 match check_disk_space(
                     &qmdl_store.path,
                     self.min_space_to_start_mb,
@@ -305,7 +664,28 @@ impl DiagTask {
                 }
             }
 
+            let index = qmdl_store
+                .current_entry
+                .expect("DiagDevice had qmdl_writer, but QmdlStore didn't have current entry???");
+            if let Some(jump) = self.clock_jumps.check(
+                Instant::now(),
+                rayhunter::clock::get_adjusted_now(),
+                qmdl_writer.total_written,
+            ) {
+                warn!("{}", jump.describe());
+                if let Err(e) = analysis_writer.write_clock_jump_marker(&jump).await {
+                    warn!("couldn't mark clock jump in analysis: {e}");
+                }
+                if let Err(e) = qmdl_store.add_current_clock_jump(jump).await {
+                    warn!("couldn't record clock jump: {e}");
+                }
+            }
+
             if let Err(e) = qmdl_writer.write_container(&container).await {
+                if let Err(mount_error) = qmdl_store.check_mount() {
+                    self.stop_for_lost_storage(qmdl_store, mount_error).await;
+                    return;
+                }
                 let reason = format!("failed to write to QMDL (disk full?): {e}");
                 error!("{reason}");
                 self.stop(qmdl_store, Some(reason)).await;
@@ -315,75 +695,124 @@ impl DiagTask {
                 "total QMDL bytes written: {}, updating manifest...",
                 qmdl_writer.total_written
             );
-            let index = qmdl_store
-                .current_entry
-                .expect("DiagDevice had qmdl_writer, but QmdlStore didn't have current entry???");
             if let Err(e) = qmdl_store
-                .update_entry_qmdl_size(index, qmdl_writer.total_written)
+                .update_entry_qmdl_size(
+                    index,
+                    qmdl_writer.total_written,
+                    self.recording_started.elapsed(),
+                )
                 .await
             {
+                if let Err(mount_error) = qmdl_store.check_mount() {
+                    self.stop_for_lost_storage(qmdl_store, mount_error).await;
+                    return;
+                }
                 let reason = format!("failed to update manifest (disk full?): {e}");
                 error!("{reason}");
                 self.stop(qmdl_store, Some(reason)).await;
                 return;
             }
             debug!("done!");
+            self.capture_rate.record(
+                qmdl_writer.total_written as u64,
+                container.messages.len(),
+                Instant::now(),
+            );
             let container_bytes: usize = container.messages.iter().map(|m| m.data.len()).sum();
             self.bytes_since_space_check += container_bytes;
-            let max_type = match analysis_writer.analyze(container).await {
-                Ok(t) => t,
-                Err(e) => {
-                    warn!("failed to analyze container: {e}");
-                    EventType::Informational
-                }
+            let recording = qmdl_store.manifest.entries[index].name.clone();
+            let max_event = analyze(analysis_writer, container).await;
+            if let Some(plmn) = analysis_writer.serving_plmn() {
+                self.serving_plmn = Some(plmn.to_string());
+            }
+            self.report_max_event(max_event, recording).await;
+        } else if let DiagState::Monitoring {
+            analysis_writer,
+            session,
+        } = &mut self.state
+        {
+            let session = session.clone();
+            let max_event = analyze(analysis_writer, container).await;
+            if let Some(plmn) = analysis_writer.serving_plmn() {
+                self.serving_plmn = Some(plmn.to_string());
+            }
+            self.report_max_event(max_event, session).await;
+        } else if let DiagState::Paused { .. } = self.state {
+            debug!("recording is paused, discarding diag messages...");
+        } else {
+            debug!("no qmdl_writer set, continuing...");
+        }
+    }
+
+    // Notifies about and displays the most severe event found in a
+    // container, if it's a warning
+    async fn report_max_event(&mut self, max_event: MaxEvent, recording: String) {
+        let max_type = max_event.event_type;
+
+        if max_type > EventType::Informational {
+            info!("a heuristic triggered on this run!");
+            let mut message = format!("Rayhunter has detected a {:?} severity event", max_type);
+            let mut details = NotificationDetails {
+                location: self.location.current(),
+                mcc_mnc: self.serving_plmn.clone(),
+                ..Default::default()
             };
+            if let Some(event) = &max_event.event {
+                let event_message = self.event_formatter.render(event, MessageLength::Long);
+                message.push_str(": ");
+                message.push_str(&event_message);
+                details.analyzer = Some(event.analyzer.clone()).filter(|name| !name.is_empty());
+                details.event_message = Some(event_message);
+            }
+            self.notification_channel
+                .send(
+                    Notification::event(max_type, recording, message, max_event.message_timestamp)
+                        .with_details(details),
+                )
+                .await
+                .expect("Failed to send to notification channel");
+        }
 
-            if max_type > EventType::Informational {
-                info!("a heuristic triggered on this run!");
-                self.notification_channel
-                    .send(Notification::new(
-                        NotificationType::Warning,
-                        format!("Rayhunter has detected a {:?} severity event", max_type),
-                        Some(Duration::from_secs(60 * 5)),
-                    ))
+        if max_type > self.max_type_seen {
+            self.max_type_seen = max_type;
+            if self.max_type_seen > EventType::Informational {
+                self.ui_update_sender
+                    .send(display::DisplayState::WarningDetected {
+                        event_type: self.max_type_seen,
+                    })
                     .await
-                    .expect("Failed to send to notification channel");
+                    .expect("couldn't send ui update message: {}");
             }
+        }
+    }
+}
 
-            if max_type > self.max_type_seen {
-                self.max_type_seen = max_type;
-                if self.max_type_seen > EventType::Informational {
-                    self.ui_update_sender
-                        .send(display::DisplayState::WarningDetected {
-                            event_type: self.max_type_seen,
-                        })
-                        .await
-                        .expect("couldn't send ui update message: {}");
-                }
+// Runs the analyzers on a container. A failure is logged, and counts as
+// finding nothing.
+async fn analyze(analysis_writer: &mut AnalysisWriter, container: MessagesContainer) -> MaxEvent {
+    match analysis_writer.analyze(container).await {
+        Ok(max_event) => max_event,
+        Err(e) => {
+            warn!("failed to analyze container: {e}");
+            MaxEvent {
+                event_type: EventType::Informational,
+                message_timestamp: None,
+                event: None,
             }
-        } else {
-            debug!("no qmdl_writer set, continuing...");
         }
     }
 }
 
-#[allow(clippy::too_many_arguments)]
 pub fn run_diag_read_thread(
     task_tracker: &TaskTracker,
-    mut dev: DiagDevice,
+    mut diag_subscriber: DiagSubscriber,
     mut qmdl_file_rx: Receiver<DiagDeviceCtrlMessage>,
     qmdl_file_tx: Sender<DiagDeviceCtrlMessage>,
-    ui_update_sender: Sender<display::DisplayState>,
     qmdl_store_lock: Arc<RwLock<RecordingStore>>,
-    analysis_sender: Sender<AnalysisCtrlMessage>,
-    analyzer_config: AnalyzerConfig,
-    notification_channel: tokio::sync::mpsc::Sender<Notification>,
-    min_space_to_start_mb: u64,
-    min_space_to_continue_mb: u64,
+    config: DiagTaskConfig,
 ) {
     task_tracker.spawn(async move {
-        let mut diag_stream = pin!(dev.as_stream().into_stream());
-        let mut diag_task = DiagTask::new(ui_update_sender, analysis_sender, analyzer_config, notification_channel, min_space_to_start_mb, min_space_to_continue_mb);
+        let mut diag_task = DiagTask::new(config, diag_subscriber.parse_error_counter());
         qmdl_file_tx
             .send(DiagDeviceCtrlMessage::StartRecording { response_tx: None })
             .await
@@ -403,12 +832,22 @@ pub fn run_diag_read_thread(
                             let mut qmdl_store = qmdl_store_lock.write().await;
                             diag_task.stop(qmdl_store.deref_mut(), None).await;
                         },
+                        Some(DiagDeviceCtrlMessage::PauseRecording { response_tx }) => {
+                            let mut qmdl_store = qmdl_store_lock.write().await;
+                            let result = diag_task.pause(qmdl_store.deref_mut()).await;
+                            response_tx.send(result).ok();
+                        },
+                        Some(DiagDeviceCtrlMessage::ResumeRecording { response_tx }) => {
+                            let mut qmdl_store = qmdl_store_lock.write().await;
+                            let result = diag_task.resume(qmdl_store.deref_mut()).await;
+                            response_tx.send(result).ok();
+                        },
                         // None means all the Senders have been dropped, so it's
                         // time to go
                         Some(DiagDeviceCtrlMessage::Exit) | None => {
                             info!("Diag reader thread exiting...");
                             diag_task.stop_current_recording().await;
-                            return Ok(())
+                            return;
                         },
                         Some(DiagDeviceCtrlMessage::DeleteEntry { name, response_tx }) => {
                             let mut qmdl_store = qmdl_store_lock.write().await;
@@ -426,15 +865,16 @@ pub fn run_diag_read_thread(
                         },
                     }
                 }
-                maybe_container = diag_stream.next() => {
-                    match maybe_container.unwrap() {
-                        Ok(container) => {
+                maybe_container = diag_subscriber.recv() => {
+                    match maybe_container {
+                        Some(container) => {
                             let mut qmdl_store = qmdl_store_lock.write().await;
                             diag_task.process_container(qmdl_store.deref_mut(), container).await
                         },
-                        Err(err) => {
-                            error!("error reading diag device: {err}");
-                            return Err(err);
+                        None => {
+                            info!("Diag multiplexer is gone, diag reader thread exiting...");
+                            diag_task.stop_current_recording().await;
+                            return;
                         }
                     }
                 }
@@ -519,6 +959,81 @@ pub async fn stop_recording(
     Ok((StatusCode::ACCEPTED, "ok".to_string()))
 }
 
+async fn send_pause_message(
+    state: &ServerState,
+    message: impl FnOnce(oneshot::Sender<Result<(), PauseError>>) -> DiagDeviceCtrlMessage,
+) -> Result<(StatusCode, String), (StatusCode, String)> {
+    if state.config.debug_mode {
+        return Err((StatusCode::FORBIDDEN, "server is in debug mode".to_string()));
+    }
+    let (response_tx, response_rx) = oneshot::channel();
+    state
+        .diag_device_ctrl_sender
+        .send(message(response_tx))
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("couldn't send recording message: {e}"),
+            )
+        })?;
+    match response_rx.await {
+        Ok(Ok(())) => Ok((StatusCode::ACCEPTED, "ok".to_string())),
+        Ok(Err(e @ PauseError::Manifest(_))) => {
+            Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        }
+        Ok(Err(e)) => Err((StatusCode::CONFLICT, e.to_string())),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("failed to receive recording response: {e}"),
+        )),
+    }
+}
+
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    post,
+    path = "/api/pause-recording",
+    tag = "Recordings",
+    responses(
+        (status = StatusCode::ACCEPTED, description = "Success"),
+        (status = StatusCode::FORBIDDEN, description = "System is in debug mode"),
+        (status = StatusCode::CONFLICT, description = "Not recording, the recording is already paused, or in monitor-only mode"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Recording action unsuccessful")
+    ),
+    summary = "Pause recording",
+    description = "Pause the current data capture without finishing it. Diag messages are discarded until it's resumed, and the pause is listed in the recording's manifest entry."
+))]
+pub async fn pause_recording(
+    State(state): State<Arc<ServerState>>,
+) -> Result<(StatusCode, String), (StatusCode, String)> {
+    send_pause_message(&state, |response_tx| {
+        DiagDeviceCtrlMessage::PauseRecording { response_tx }
+    })
+    .await
+}
+
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    post,
+    path = "/api/resume-recording",
+    tag = "Recordings",
+    responses(
+        (status = StatusCode::ACCEPTED, description = "Success"),
+        (status = StatusCode::FORBIDDEN, description = "System is in debug mode"),
+        (status = StatusCode::CONFLICT, description = "The recording isn't paused"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Recording action unsuccessful")
+    ),
+    summary = "Resume recording",
+    description = "Resume the paused data capture, appending to the same recording. Its analysis report and PCAP note the gap."
+))]
+pub async fn resume_recording(
+    State(state): State<Arc<ServerState>>,
+) -> Result<(StatusCode, String), (StatusCode, String)> {
+    send_pause_message(&state, |response_tx| {
+        DiagDeviceCtrlMessage::ResumeRecording { response_tx }
+    })
+    .await
+}
+
 #[cfg_attr(feature = "apidocs", utoipa::path(
     post,
     path = "/api/delete-recording/{name}",
@@ -617,25 +1132,148 @@ pub async fn delete_all_recordings(
     }
 }
 
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    get,
+    path = "/api/live-qmdl",
+    tag = "Recordings",
+    responses(
+        (status = StatusCode::OK, description = "Live QMDL stream", content_type = "application/octet-stream"),
+        (status = StatusCode::SERVICE_UNAVAILABLE, description = "Not currently recording")
+    ),
+    summary = "Stream live QMDL",
+    description = "Stream raw HDLC-framed diag messages as they're read while recording. Nothing is streamed while the recording is paused, and the response ends once it's stopped."
+))]
+pub async fn get_live_qmdl(
+    State(state): State<Arc<ServerState>>,
+) -> Result<Response, (StatusCode, String)> {
+    // subscribe while holding the store lock, so we can't miss a change to
+    // the recording sent between checking on it and subscribing
+    let qmdl_store = state.qmdl_store_lock.read().await;
+    let control_rx = state.live_qmdl_sender.subscribe();
+    let diag_subscriber = state.diag_multiplexer.subscribe();
+    let Some((_, entry)) = qmdl_store.get_current_entry() else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "No QMDL data's being recorded, try starting a new recording!".to_string(),
+        ));
+    };
+    let paused = entry.pauses.last().is_some_and(|pause| pause.end.is_none());
+    drop(qmdl_store);
+
+    let live_stream = futures::stream::unfold(
+        (control_rx, diag_subscriber, paused),
+        |(mut control_rx, mut diag_subscriber, mut paused)| async move {
+            loop {
+                tokio::select! {
+                    biased;
+                    msg = control_rx.recv() => match msg {
+                        Ok(LiveQmdlMessage::RecordingPaused) => paused = true,
+                        Ok(LiveQmdlMessage::RecordingResumed) => paused = false,
+                        Ok(LiveQmdlMessage::RecordingStopped)
+                        | Err(broadcast::error::RecvError::Closed) => return None,
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            warn!("live QMDL client missed {n} recording changes");
+                        }
+                    },
+                    maybe_container = diag_subscriber.recv() => {
+                        let container = maybe_container?;
+                        if paused {
+                            continue;
+                        }
+                        let data: Vec<u8> = container
+                            .messages
+                            .iter()
+                            .flat_map(|m| m.data.iter().copied())
+                            .collect();
+                        return Some((
+                            Ok::<_, std::io::Error>(data),
+                            (control_rx, diag_subscriber, paused),
+                        ));
+                    }
+                }
+            }
+        },
+    );
+
+    let headers = [(CONTENT_TYPE, "application/octet-stream")];
+    let body = Body::from_stream(live_stream);
+    Ok((headers, body).into_response())
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ReportQuery {
+    /// Whether to redact IMSIs, IMEIs and phone numbers, overriding the
+    /// redact_identifiers setting
+    pub redact: Option<bool>,
+    /// Only include rows with an event at least this severe
+    pub min_severity: Option<String>,
+    /// Only include rows with an event from this analyzer, by its setting
+    /// name, e.g. imsi_requested
+    pub analyzer: Option<String>,
+    /// Only include rows with events
+    pub events_only: bool,
+}
+
+impl ReportQuery {
+    fn report_filter(&self) -> Result<ReportFilter, String> {
+        let min_severity = match &self.min_severity {
+            Some(severity) => Some(severity.parse::<EventType>().map_err(|e| e.to_string())?),
+            None => None,
+        };
+        let analyzer_name = match &self.analyzer {
+            Some(id) => {
+                let names = AnalyzerConfig::analyzer_names();
+                let Some((_, name)) = names.iter().find(|(known, _)| known == id) else {
+                    let ids: Vec<&str> = names.iter().map(|(id, _)| *id).collect();
+                    return Err(format!(
+                        "unknown analyzer \"{id}\", expected one of {}",
+                        ids.join(", ")
+                    ));
+                };
+                Some(name.clone())
+            }
+            None => None,
+        };
+        Ok(ReportFilter {
+            min_severity,
+            analyzer_name,
+            events_only: self.events_only,
+        })
+    }
+}
+
 #[cfg_attr(feature = "apidocs", utoipa::path(
     get,
     path = "/api/analysis-report/{name}",
     tag = "Recordings",
     responses(
         (status = StatusCode::OK, description = "Success", body = ReportMetadata, content_type = "application/x-ndjson"),
+        (status = StatusCode::BAD_REQUEST, description = "Unknown severity or analyzer"),
         (status = StatusCode::SERVICE_UNAVAILABLE, description = "No QMDL files available; start a new recording."),
         (status = StatusCode::NOT_FOUND, description = "File {name} not found")
     ),
     params(
-        ("name" = String, Path, description = "QMDL file to analyze")
+        ("name" = String, Path, description = "QMDL file to analyze"),
+        ("redact" = Option<bool>, Query, description = "Redact IMSIs, IMEIs and phone numbers from event messages. Defaults to the redact_identifiers setting."),
+        ("min_severity" = Option<String>, Query, description = "Only include rows with an event at least this severe: informational, low, medium or high"),
+        ("analyzer" = Option<String>, Query, description = "Only include rows with an event from this analyzer, by its setting name, e.g. imsi_requested"),
+        ("events_only" = Option<bool>, Query, description = "Only include rows with events")
     ),
     summary = "Analysis report",
-    description = "Download processed analysis report for QMDL file {name}, as well as the types (and versions) of analyzers used."
+    description = "Download processed analysis report for QMDL file {name}, as well as the types (and versions) of analyzers used. The first line, listing the analyzers, is always included, and the rows after it can be filtered by severity and analyzer. The report is gzip-compressed if the request's Accept-Encoding allows it."
 ))]
 pub async fn get_analysis_report(
     State(state): State<Arc<ServerState>>,
     Path(qmdl_name): Path<String>,
+    Query(query): Query<ReportQuery>,
+    headers: HeaderMap,
 ) -> Result<Response, (StatusCode, String)> {
+    let redact = query.redact.unwrap_or(state.config.redact_identifiers);
+    let mut line_filter = query
+        .report_filter()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?
+        .into_line_filter();
     let qmdl_store = state.qmdl_store_lock.read().await;
     let (entry_index, _) = if qmdl_name == "live" {
         qmdl_store.get_current_entry().ok_or((
@@ -660,9 +1298,219 @@ pub async fn get_analysis_report(
     let mut normalizer = AnalysisLineNormalizer::new();
     let normalized_stream = lines_stream
         .try_filter(|line| future::ready(!line.is_empty()))
-        .map_ok(move |line| normalizer.normalize_line(line));
+        .try_filter(move |line| future::ready(line_filter.keep_line(line)))
+        .map_ok(move |line| normalizer.normalize_line(line))
+        .map_ok(move |line| {
+            if redact {
+                redact_report_line(line)
+            } else {
+                line
+            }
+        });
 
-    let headers = [(CONTENT_TYPE, "application/x-ndjson")];
-    let body = Body::from_stream(normalized_stream);
-    Ok((headers, body).into_response())
+    Ok(maybe_gzip_response(
+        &headers,
+        "application/x-ndjson",
+        normalized_stream,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock_jump::DEFAULT_CLOCK_JUMP_THRESHOLD;
+    use chrono::TimeDelta;
+    use rayhunter::diag::HdlcEncapsulatedMessage;
+    use tempfile::{Builder, TempDir};
+    use tokio::sync::mpsc;
+
+    fn container() -> MessagesContainer {
+        let data = vec![0x10, 0x00, 0x00, 0x00, 0x7e];
+        MessagesContainer {
+            data_type: DataType::UserSpace,
+            num_messages: 1,
+            messages: vec![HdlcEncapsulatedMessage {
+                len: data.len() as u32,
+                data,
+            }],
+        }
+    }
+
+    async fn create_test_task(
+        dir: &TempDir,
+    ) -> (
+        DiagTask,
+        RecordingStore,
+        mpsc::Receiver<display::DisplayState>,
+    ) {
+        let (ui_tx, ui_rx) = mpsc::channel(16);
+        let (analysis_tx, _) = mpsc::channel(16);
+        let (notification_tx, _) = mpsc::channel(16);
+        let event_store = EventStore::open(
+            dir.path().join(crate::event_store::EVENT_STORE_FILENAME),
+            crate::event_store::EVENT_STORE_MAX_BYTES,
+        )
+        .await
+        .unwrap();
+        let config = DiagTaskConfig {
+            ui_update_sender: ui_tx,
+            analysis_sender: analysis_tx,
+            analyzer_config: AnalyzerConfig::default(),
+            notification_channel: notification_tx,
+            live_qmdl_sender: broadcast::channel(4).0,
+            event_store_lock: Arc::new(RwLock::new(event_store)),
+            store_path_sender: watch::channel(dir.path().to_path_buf()).0,
+            live_tail: LiveTail::new(4),
+            capture_rate: CaptureRateTracker::default(),
+            location: LocationTracker::default(),
+            monitoring: Arc::new(RwLock::new(false)),
+            min_space_to_start_mb: 0,
+            min_space_to_continue_mb: 0,
+            recording_mode: RecordingMode::Full,
+            clock_jump_threshold: DEFAULT_CLOCK_JUMP_THRESHOLD,
+        };
+        let task = DiagTask::new(config, ParseErrorCounter::default());
+        let store = RecordingStore::create(dir.path()).await.unwrap();
+        (task, store, ui_rx)
+    }
+
+    fn current_size(store: &RecordingStore) -> usize {
+        store.get_current_entry().unwrap().1.qmdl_size_bytes
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_resume() {
+        let dir = Builder::new().prefix("diag_test").tempdir().unwrap();
+        let (mut task, mut store, mut ui_rx) = create_test_task(&dir).await;
+        assert!(matches!(
+            task.pause(&mut store).await,
+            Err(PauseError::NotRecording)
+        ));
+        assert!(matches!(
+            task.resume(&mut store).await,
+            Err(PauseError::NotPaused)
+        ));
+
+        task.start(&mut store).await.unwrap();
+        let (entry_index, _) = store.get_current_entry().unwrap();
+        task.process_container(&mut store, container()).await;
+        assert_eq!(current_size(&store), 5);
+        assert!(matches!(
+            task.resume(&mut store).await,
+            Err(PauseError::NotPaused)
+        ));
+
+        task.pause(&mut store).await.unwrap();
+        assert!(matches!(
+            task.pause(&mut store).await,
+            Err(PauseError::AlreadyPaused)
+        ));
+        // messages which arrive while paused are discarded
+        task.process_container(&mut store, container()).await;
+        assert_eq!(current_size(&store), 5);
+
+        task.resume(&mut store).await.unwrap();
+        task.process_container(&mut store, container()).await;
+        assert_eq!(store.current_entry, Some(entry_index));
+        assert_eq!(current_size(&store), 10);
+        let entry = &store.manifest.entries[entry_index];
+        assert_eq!(entry.pauses.len(), 1);
+        assert_eq!(entry.pauses[0].qmdl_offset, 5);
+        assert!(entry.pauses[0].end.is_some());
+        let qmdl_path = entry.get_qmdl_filepath(&store.path);
+        assert_eq!(tokio::fs::read(qmdl_path).await.unwrap().len(), 10);
+
+        for expected in [
+            display::DisplayState::Recording,
+            display::DisplayState::Paused,
+            display::DisplayState::Recording,
+        ] {
+            assert!(ui_rx.try_recv().unwrap() == expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_clock_jump() {
+        let dir = Builder::new().prefix("diag_test").tempdir().unwrap();
+        let (mut task, mut store, _ui_rx) = create_test_task(&dir).await;
+        task.start(&mut store).await.unwrap();
+        task.process_container(&mut store, container()).await;
+
+        // pretend the clock read an hour earlier last time it was checked
+        let now = rayhunter::clock::get_adjusted_now();
+        task.clock_jumps
+            .check(Instant::now(), now - TimeDelta::hours(1), 0);
+        task.process_container(&mut store, container()).await;
+
+        let (_, entry) = store.get_current_entry().unwrap();
+        assert_eq!(entry.clock_jumps.len(), 1);
+        assert_eq!(entry.clock_jumps[0].qmdl_offset, 5);
+        assert!(entry.clock_jumps[0].offset() >= TimeDelta::minutes(59));
+        assert_eq!(entry.duration_secs, Some(0));
+        let analysis_path = entry.get_analysis_filepath(&store.path);
+        let description = entry.clock_jumps[0].describe();
+        task.stop(&mut store, None).await;
+        let analysis = tokio::fs::read_to_string(analysis_path).await.unwrap();
+        assert!(analysis.contains(&description));
+    }
+
+    #[tokio::test]
+    async fn test_stop_while_paused() {
+        let dir = Builder::new().prefix("diag_test").tempdir().unwrap();
+        let (mut task, mut store, _ui_rx) = create_test_task(&dir).await;
+        task.start(&mut store).await.unwrap();
+        let (entry_index, _) = store.get_current_entry().unwrap();
+        task.process_container(&mut store, container()).await;
+        task.pause(&mut store).await.unwrap();
+
+        task.stop(&mut store, None).await;
+        assert_eq!(store.current_entry, None);
+        assert!(matches!(
+            task.resume(&mut store).await,
+            Err(PauseError::NotPaused)
+        ));
+        let entry = &store.manifest.entries[entry_index];
+        assert_eq!(entry.qmdl_size_bytes, 5);
+        assert!(entry.pauses[0].end.is_some());
+        let analysis = tokio::fs::read_to_string(entry.get_analysis_filepath(&store.path))
+            .await
+            .unwrap();
+        assert!(analysis.contains(&entry.pauses[0].describe()));
+    }
+
+    #[tokio::test]
+    async fn test_monitor_only() {
+        let dir = Builder::new().prefix("diag_test").tempdir().unwrap();
+        let (mut task, mut store, mut ui_rx) = create_test_task(&dir).await;
+        task.recording_mode = RecordingMode::MonitorOnly;
+        task.start(&mut store).await.unwrap();
+        assert!(matches!(task.state, DiagState::Monitoring { .. }));
+        assert!(*task.monitoring.read().await);
+        assert!(matches!(
+            task.pause(&mut store).await,
+            Err(PauseError::MonitorOnly)
+        ));
+
+        task.process_container(&mut store, container()).await;
+        assert_eq!(store.current_entry, None);
+        assert!(store.manifest.entries.is_empty());
+
+        task.stop(&mut store, None).await;
+        assert!(matches!(task.state, DiagState::Stopped));
+        assert!(!*task.monitoring.read().await);
+        assert!(store.manifest.entries.is_empty());
+        // no recording files were written, only the (empty) manifest
+        let files: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, vec!["manifest.toml"]);
+
+        for expected in [
+            display::DisplayState::Recording,
+            display::DisplayState::Paused,
+        ] {
+            assert!(ui_rx.try_recv().unwrap() == expected);
+        }
+    }
 }
@@ -0,0 +1,404 @@
+//! /dev/diag can only be read from one place, so the daemon reads it once
+//! and hands every container to whoever subscribes. A subscriber which falls
+//! too far behind misses containers rather than holding up everyone else, and
+//! how many it missed is counted for /api/system-stats, along with how much
+//! was read. The recorder is the exception: a gap in the QMDL file is a gap
+//! in the evidence, so it subscribes losslessly, and reading waits for it
+//! instead.
+
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use futures::TryStreamExt;
+use log::{error, info, warn};
+use rayhunter::diag::MessagesContainer;
+use rayhunter::diag_device::DiagDevice;
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use crate::capture_rate::CaptureRateMeter;
+
+/// How many containers a subscriber may fall behind before it starts missing
+/// them, or for lossless subscribers, before reading waits for them.
+pub const DIAG_CHANNEL_CAPACITY: usize = 256;
+
+/// Diag device throughput, for /api/system-stats
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct DiagStats {
+    /// Containers read from the diag device
+    pub frames_read_total: u64,
+    /// Bytes of messages read from the diag device
+    pub bytes_read_total: u64,
+    /// Containers missed by consumers which fell behind. If this keeps
+    /// growing, the device can't keep up with analysis, and enabling fewer
+    /// analyzers may help.
+    pub frames_dropped_total: u64,
+    /// Messages which couldn't be parsed while recording
+    pub parse_errors_total: u64,
+    /// Bytes read per second, averaged over the last few seconds
+    pub read_throughput_bps: f64,
+}
+
+/// Counts diag messages which couldn't be parsed. Parsing happens in the
+/// subscribers, so they report back through this.
+#[derive(Clone, Default)]
+pub struct ParseErrorCounter(Arc<AtomicU64>);
+
+impl ParseErrorCounter {
+    pub fn add(&self, errors: u64) {
+        self.0.fetch_add(errors, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// The senders for every subscriber, which are dropped once reading ends so
+// that subscribers see the end of the stream
+struct Channels {
+    lossy: broadcast::Sender<MessagesContainer>,
+    lossless: Vec<mpsc::Sender<MessagesContainer>>,
+}
+
+#[derive(Clone)]
+pub struct DiagMultiplexer {
+    capacity: usize,
+    channels: Arc<Mutex<Option<Channels>>>,
+    frames_read: Arc<AtomicU64>,
+    bytes_read: Arc<AtomicU64>,
+    frames_dropped: Arc<AtomicU64>,
+    parse_errors: ParseErrorCounter,
+    throughput: Arc<Mutex<CaptureRateMeter>>,
+}
+
+impl DiagMultiplexer {
+    pub fn new(capacity: usize) -> Self {
+        DiagMultiplexer {
+            capacity,
+            channels: Arc::new(Mutex::new(Some(Channels {
+                lossy: broadcast::channel(capacity).0,
+                lossless: Vec::new(),
+            }))),
+            frames_read: Arc::new(AtomicU64::new(0)),
+            bytes_read: Arc::new(AtomicU64::new(0)),
+            frames_dropped: Arc::new(AtomicU64::new(0)),
+            parse_errors: ParseErrorCounter::default(),
+            throughput: Arc::new(Mutex::new(CaptureRateMeter::new(Instant::now()))),
+        }
+    }
+
+    /// Subscribes to every container read from now on, skipping any it
+    /// falls too far behind on.
+    pub fn subscribe(&self) -> DiagSubscriber {
+        let receiver = match self.channels.lock().unwrap().as_ref() {
+            Some(channels) => channels.lossy.subscribe(),
+            // reading has ended, so this subscriber ends right away
+            None => broadcast::channel(1).1,
+        };
+        self.subscriber(SubscriberReceiver::Lossy(receiver))
+    }
+
+    /// Subscribes to every container read from now on, without ever
+    /// missing one: once this subscriber is `capacity` containers behind,
+    /// reading waits for it to catch up.
+    pub fn subscribe_lossless(&self) -> DiagSubscriber {
+        let (sender, receiver) = mpsc::channel(self.capacity);
+        if let Some(channels) = self.channels.lock().unwrap().as_mut() {
+            channels.lossless.push(sender);
+        }
+        self.subscriber(SubscriberReceiver::Lossless(receiver))
+    }
+
+    fn subscriber(&self, receiver: SubscriberReceiver) -> DiagSubscriber {
+        DiagSubscriber {
+            receiver,
+            frames_dropped: self.frames_dropped.clone(),
+            parse_errors: self.parse_errors.clone(),
+        }
+    }
+
+    /// Sends a container to every current subscriber, waiting for room in
+    /// any lossless subscriber which is behind, and returns how many
+    /// subscribers there were.
+    pub async fn publish(&self, container: MessagesContainer) -> usize {
+        let bytes: usize = container.messages.iter().map(|m| m.data.len()).sum();
+        self.frames_read.fetch_add(1, Ordering::Relaxed);
+        let bytes_read = self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed) + bytes as u64;
+        self.throughput.lock().unwrap().record(
+            bytes_read,
+            container.messages.len(),
+            Instant::now(),
+        );
+
+        let Some((lossy, lossless)) = self
+            .channels
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|channels| (channels.lossy.clone(), channels.lossless.clone()))
+        else {
+            return 0;
+        };
+        let mut delivered = 0;
+        let mut any_gone = false;
+        for sender in lossless {
+            // an error here just means the subscriber went away
+            match sender.send(container.clone()).await {
+                Ok(()) => delivered += 1,
+                Err(_) => any_gone = true,
+            }
+        }
+        if any_gone && let Some(channels) = self.channels.lock().unwrap().as_mut() {
+            channels.lossless.retain(|sender| !sender.is_closed());
+        }
+        // an error here just means nobody is subscribed
+        delivered + lossy.send(container).unwrap_or(0)
+    }
+
+    /// Ends every subscription, once the subscribers have received what was
+    /// already published. This happens when reading ends, even if clones of
+    /// the multiplexer are still around.
+    pub fn close(&self) {
+        self.channels.lock().unwrap().take();
+    }
+
+    /// The counters so far, and the current read throughput
+    pub fn stats(&self) -> DiagStats {
+        DiagStats {
+            frames_read_total: self.frames_read.load(Ordering::Relaxed),
+            bytes_read_total: self.bytes_read.load(Ordering::Relaxed),
+            frames_dropped_total: self.frames_dropped(),
+            parse_errors_total: self.parse_errors.get(),
+            read_throughput_bps: self
+                .throughput
+                .lock()
+                .unwrap()
+                .rate_at(Instant::now())
+                .bytes_per_sec,
+        }
+    }
+
+    /// The total number of containers missed by slow subscribers
+    pub fn frames_dropped(&self) -> u64 {
+        self.frames_dropped.load(Ordering::Relaxed)
+    }
+
+    /// Reads containers from the diag device and publishes them until
+    /// shutdown, or until reading fails.
+    pub fn run(
+        &self,
+        task_tracker: &TaskTracker,
+        mut dev: DiagDevice,
+        shutdown_token: CancellationToken,
+    ) {
+        let multiplexer = self.clone();
+        task_tracker.spawn(async move {
+            let mut diag_stream = pin!(dev.as_stream().into_stream());
+            let result = loop {
+                tokio::select! {
+                    _ = shutdown_token.cancelled() => {
+                        info!("Diag multiplexer exiting...");
+                        break Ok(());
+                    }
+                    maybe_container = diag_stream.try_next() => match maybe_container {
+                        Ok(Some(container)) => {
+                            multiplexer.publish(container).await;
+                        }
+                        Ok(None) => break Ok(()),
+                        Err(err) => {
+                            error!("error reading diag device: {err}");
+                            break Err(err);
+                        }
+                    }
+                }
+            };
+            multiplexer.close();
+            result
+        });
+    }
+}
+
+enum SubscriberReceiver {
+    Lossy(broadcast::Receiver<MessagesContainer>),
+    Lossless(mpsc::Receiver<MessagesContainer>),
+}
+
+pub struct DiagSubscriber {
+    receiver: SubscriberReceiver,
+    frames_dropped: Arc<AtomicU64>,
+    parse_errors: ParseErrorCounter,
+}
+
+impl DiagSubscriber {
+    /// Where to count messages from this subscriber which couldn't be parsed
+    pub fn parse_error_counter(&self) -> ParseErrorCounter {
+        self.parse_errors.clone()
+    }
+
+    /// Waits for the next container, skipping past (and counting) any which
+    /// were missed by falling behind. Returns None once reading has ended.
+    pub async fn recv(&mut self) -> Option<MessagesContainer> {
+        let receiver = match &mut self.receiver {
+            SubscriberReceiver::Lossy(receiver) => receiver,
+            SubscriberReceiver::Lossless(receiver) => return receiver.recv().await,
+        };
+        loop {
+            match receiver.recv().await {
+                Ok(container) => return Some(container),
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("diag subscriber fell behind, dropped {n} containers");
+                    self.frames_dropped.fetch_add(n, Ordering::Relaxed);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayhunter::diag::DataType;
+    use std::time::Duration;
+
+    fn container(n: u32) -> MessagesContainer {
+        MessagesContainer {
+            data_type: DataType::UserSpace,
+            num_messages: n,
+            messages: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_every_subscriber_gets_every_container() {
+        let multiplexer = DiagMultiplexer::new(4);
+        let mut recording = multiplexer.subscribe();
+        let mut live = multiplexer.subscribe();
+
+        assert_eq!(multiplexer.publish(container(1)).await, 2);
+        assert_eq!(multiplexer.publish(container(2)).await, 2);
+        for subscriber in [&mut recording, &mut live] {
+            assert_eq!(subscriber.recv().await, Some(container(1)));
+            assert_eq!(subscriber.recv().await, Some(container(2)));
+        }
+        assert_eq!(multiplexer.frames_dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_slow_subscriber_drops_frames() {
+        let multiplexer = DiagMultiplexer::new(2);
+        let mut fast = multiplexer.subscribe();
+        let mut slow = multiplexer.subscribe();
+
+        for n in 0..5 {
+            multiplexer.publish(container(n)).await;
+            assert_eq!(fast.recv().await, Some(container(n)));
+        }
+
+        // the slow subscriber missed the first 3, but still gets the rest
+        assert_eq!(slow.recv().await, Some(container(3)));
+        assert_eq!(slow.recv().await, Some(container(4)));
+        assert_eq!(multiplexer.frames_dropped(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_stats_count_reads() {
+        use rayhunter::diag::HdlcEncapsulatedMessage;
+
+        let multiplexer = DiagMultiplexer::new(4);
+        let subscriber = multiplexer.subscribe();
+        let mut with_data = container(1);
+        with_data.messages.push(HdlcEncapsulatedMessage {
+            len: 10,
+            data: vec![0; 10],
+        });
+        multiplexer.publish(with_data).await;
+        multiplexer.publish(container(0)).await;
+        subscriber.parse_error_counter().add(2);
+
+        let stats = multiplexer.stats();
+        assert_eq!(stats.frames_read_total, 2);
+        assert_eq!(stats.bytes_read_total, 10);
+        assert_eq!(stats.frames_dropped_total, 0);
+        assert_eq!(stats.parse_errors_total, 2);
+        assert!(stats.read_throughput_bps > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_ends_with_multiplexer() {
+        let multiplexer = DiagMultiplexer::new(2);
+        let mut subscriber = multiplexer.subscribe();
+        assert_eq!(multiplexer.publish(container(1)).await, 1);
+        drop(multiplexer);
+
+        assert_eq!(subscriber.recv().await, Some(container(1)));
+        assert_eq!(subscriber.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_lossless_subscriber_holds_up_publishing() {
+        let multiplexer = DiagMultiplexer::new(2);
+        let mut recorder = multiplexer.subscribe_lossless();
+        let mut live = multiplexer.subscribe();
+
+        assert_eq!(multiplexer.publish(container(0)).await, 2);
+        assert_eq!(multiplexer.publish(container(1)).await, 2);
+        // the recorder is full, so the next container waits for it
+        let blocked =
+            tokio::time::timeout(Duration::from_millis(50), multiplexer.publish(container(2)))
+                .await;
+        assert!(blocked.is_err());
+
+        let publisher = {
+            let multiplexer = multiplexer.clone();
+            tokio::spawn(async move {
+                for n in 2..10 {
+                    multiplexer.publish(container(n)).await;
+                }
+            })
+        };
+        for n in 0..10 {
+            assert_eq!(recorder.recv().await, Some(container(n)));
+        }
+        publisher.await.unwrap();
+
+        // only the lossy subscriber missed anything
+        assert_eq!(multiplexer.frames_dropped(), 0);
+        assert_eq!(live.recv().await, Some(container(8)));
+        assert!(multiplexer.frames_dropped() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_close_ends_subscribers() {
+        let multiplexer = DiagMultiplexer::new(2);
+        // e.g. the server's copy
+        let _clone = multiplexer.clone();
+        let mut recorder = multiplexer.subscribe_lossless();
+        let mut live = multiplexer.subscribe();
+        multiplexer.publish(container(1)).await;
+        multiplexer.close();
+
+        for subscriber in [&mut recorder, &mut live] {
+            assert_eq!(subscriber.recv().await, Some(container(1)));
+            assert_eq!(subscriber.recv().await, None);
+        }
+        assert_eq!(multiplexer.subscribe().recv().await, None);
+        assert_eq!(multiplexer.subscribe_lossless().recv().await, None);
+        assert_eq!(multiplexer.publish(container(2)).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_gone_lossless_subscriber_is_dropped() {
+        let multiplexer = DiagMultiplexer::new(1);
+        let recorder = multiplexer.subscribe_lossless();
+        drop(recorder);
+        // this would wait forever if the gone subscriber still counted
+        assert_eq!(multiplexer.publish(container(1)).await, 0);
+        assert_eq!(multiplexer.publish(container(2)).await, 0);
+    }
+}
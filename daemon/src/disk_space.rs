@@ -0,0 +1,65 @@
+//! Free space checks shared by the recorder and everything else which writes
+//! to /data. Keeping the recording going matters more than any of the
+//! daemon's bookkeeping files, so those are skipped well before the disk is
+//! full enough for the recorder to stop.
+//!
+//! The floor for auxiliary writes is far above the recorder's own limits and
+//! each of those writes is tiny in comparison, so an auxiliary write landing
+//! between the recorder's space check and its next write can't use up the
+//! space that check relied on.
+
+use std::path::Path;
+
+use log::warn;
+
+use crate::stats::DiskStats;
+
+/// Below this much free space, auxiliary files aren't written at all
+pub const AUXILIARY_WRITE_FLOOR_BYTES: u64 = 20 * 1024 * 1024;
+
+/// Returns how many bytes are available to the daemon on the partition
+/// holding `path`.
+pub fn available_bytes(path: &Path) -> Result<u64, String> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| format!("path {path:?} isn't valid UTF-8"))?;
+    Ok(DiskStats::new(path)?.available_bytes)
+}
+
+/// Whether an auxiliary write should go ahead given the free space, if it's
+/// known. When it isn't, the write goes ahead, since that's what would have
+/// happened without the check.
+pub fn allow_auxiliary_write(available_bytes: Option<u64>, floor_bytes: u64) -> bool {
+    available_bytes.is_none_or(|available| available >= floor_bytes)
+}
+
+/// Checks whether there's room to write `description` to the file at
+/// `path`, logging a warning if not.
+pub fn has_room_for_auxiliary_write(path: &Path, description: &str) -> bool {
+    let available = available_bytes(path.parent().unwrap_or(path))
+        .inspect_err(|e| warn!("failed to check free space before writing {description}: {e}"))
+        .ok();
+    let allowed = allow_auxiliary_write(available, AUXILIARY_WRITE_FLOOR_BYTES);
+    if !allowed {
+        warn!(
+            "only {}MB free, not writing {description}",
+            available.unwrap_or(0) / 1024 / 1024
+        );
+    }
+    allowed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allow_auxiliary_write() {
+        let floor = AUXILIARY_WRITE_FLOOR_BYTES;
+        assert!(allow_auxiliary_write(Some(floor * 10), floor));
+        assert!(allow_auxiliary_write(Some(floor), floor));
+        assert!(!allow_auxiliary_write(Some(floor - 1), floor));
+        assert!(!allow_auxiliary_write(Some(0), floor));
+        assert!(allow_auxiliary_write(None, floor));
+    }
+}
@@ -0,0 +1,170 @@
+//! Blink patterns for devices which can only show the state through a single
+//! color LED: solid while recording, a slow blink for a low or medium
+//! severity warning, a fast blink for a high severity one, and off while
+//! paused.
+
+use std::time::Duration;
+
+use rayhunter::analysis::analyzer::EventType;
+use tokio::time::Instant;
+
+use crate::display::DisplayState;
+
+/// How long a slowly blinking LED stays on, and then off
+const SLOW_BLINK_HALF_PERIOD: Duration = Duration::from_millis(1000);
+/// How long a fast blinking LED stays on, and then off
+const FAST_BLINK_HALF_PERIOD: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlinkPattern {
+    Off,
+    Solid,
+    SlowBlink,
+    FastBlink,
+}
+
+impl BlinkPattern {
+    pub fn for_state(state: DisplayState) -> Self {
+        match state {
+            DisplayState::Paused => BlinkPattern::Off,
+            DisplayState::Recording => BlinkPattern::Solid,
+            DisplayState::WarningDetected { event_type } => match event_type {
+                EventType::Informational => BlinkPattern::Solid,
+                EventType::Low | EventType::Medium => BlinkPattern::SlowBlink,
+                EventType::High => BlinkPattern::FastBlink,
+            },
+            // these don't replace the current state, so are never shown
+            DisplayState::BootStatus { .. } | DisplayState::ButtonPressed => BlinkPattern::Solid,
+        }
+    }
+
+    fn half_period(self) -> Option<Duration> {
+        match self {
+            BlinkPattern::Off | BlinkPattern::Solid => None,
+            BlinkPattern::SlowBlink => Some(SLOW_BLINK_HALF_PERIOD),
+            BlinkPattern::FastBlink => Some(FAST_BLINK_HALF_PERIOD),
+        }
+    }
+}
+
+/// Works out when an LED following a [BlinkPattern] should be on. A blinking
+/// pattern starts with the LED on.
+pub struct Blinker {
+    pattern: BlinkPattern,
+    since: Instant,
+}
+
+impl Blinker {
+    pub fn new(now: Instant) -> Self {
+        Blinker {
+            pattern: BlinkPattern::Off,
+            since: now,
+        }
+    }
+
+    /// Switches to `pattern`. Setting the pattern it already follows keeps
+    /// its rhythm rather than starting over.
+    pub fn set_pattern(&mut self, pattern: BlinkPattern, now: Instant) {
+        if pattern != self.pattern {
+            self.pattern = pattern;
+            self.since = now;
+        }
+    }
+
+    pub fn is_on(&self, now: Instant) -> bool {
+        match self.pattern.half_period() {
+            None => self.pattern == BlinkPattern::Solid,
+            Some(half_period) => {
+                let elapsed = now.saturating_duration_since(self.since);
+                (elapsed.as_millis() / half_period.as_millis()).is_multiple_of(2)
+            }
+        }
+    }
+
+    /// When the LED next turns on or off, or None if it stays as it is.
+    pub fn next_toggle(&self, now: Instant) -> Option<Instant> {
+        let half_period = self.pattern.half_period()?;
+        let elapsed = now.saturating_duration_since(self.since);
+        let toggles = elapsed.as_millis() / half_period.as_millis() + 1;
+        Some(self.since + half_period * toggles as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warning(event_type: EventType) -> DisplayState {
+        DisplayState::WarningDetected { event_type }
+    }
+
+    #[test]
+    fn test_pattern_for_state() {
+        assert_eq!(
+            BlinkPattern::for_state(DisplayState::Paused),
+            BlinkPattern::Off
+        );
+        assert_eq!(
+            BlinkPattern::for_state(DisplayState::Recording),
+            BlinkPattern::Solid
+        );
+        assert_eq!(
+            BlinkPattern::for_state(warning(EventType::Low)),
+            BlinkPattern::SlowBlink
+        );
+        assert_eq!(
+            BlinkPattern::for_state(warning(EventType::Medium)),
+            BlinkPattern::SlowBlink
+        );
+        assert_eq!(
+            BlinkPattern::for_state(warning(EventType::High)),
+            BlinkPattern::FastBlink
+        );
+    }
+
+    #[test]
+    fn test_steady_patterns_never_toggle() {
+        let start = Instant::now();
+        let mut blinker = Blinker::new(start);
+        let later = start + Duration::from_secs(60);
+        assert!(!blinker.is_on(later));
+        assert_eq!(blinker.next_toggle(later), None);
+
+        blinker.set_pattern(BlinkPattern::Solid, start);
+        assert!(blinker.is_on(later));
+        assert_eq!(blinker.next_toggle(later), None);
+    }
+
+    #[test]
+    fn test_blink_timing() {
+        let start = Instant::now();
+        let mut blinker = Blinker::new(start);
+        blinker.set_pattern(BlinkPattern::FastBlink, start);
+
+        let ms = |ms| start + Duration::from_millis(ms);
+        assert!(blinker.is_on(start));
+        assert_eq!(blinker.next_toggle(start), Some(ms(250)));
+        assert!(blinker.is_on(ms(249)));
+        assert!(!blinker.is_on(ms(250)));
+        assert_eq!(blinker.next_toggle(ms(250)), Some(ms(500)));
+        assert!(!blinker.is_on(ms(499)));
+        assert!(blinker.is_on(ms(500)));
+    }
+
+    #[test]
+    fn test_changing_pattern_restarts_blinking() {
+        let start = Instant::now();
+        let mut blinker = Blinker::new(start);
+        blinker.set_pattern(BlinkPattern::SlowBlink, start);
+        let off = start + SLOW_BLINK_HALF_PERIOD;
+        assert!(!blinker.is_on(off));
+
+        // same pattern, same rhythm
+        blinker.set_pattern(BlinkPattern::SlowBlink, off);
+        assert!(!blinker.is_on(off));
+
+        blinker.set_pattern(BlinkPattern::FastBlink, off);
+        assert!(blinker.is_on(off));
+        assert_eq!(blinker.next_toggle(off), Some(off + FAST_BLINK_HALF_PERIOD));
+    }
+}
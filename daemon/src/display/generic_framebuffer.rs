@@ -5,15 +5,18 @@ use std::time::Duration;
 
 use crate::config;
 use crate::display::DisplayState;
+use crate::display::screen_saver::{ScreenPolicy, ScreenSaver};
 use rayhunter::analysis::analyzer::EventType;
 
-use log::{error, info};
+use log::{error, info, warn};
 use tokio::sync::mpsc::Receiver;
+use tokio::time::Instant;
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
 
 use include_dir::{Dir, include_dir};
 
 const REFRESH_RATE: u64 = 1000; //how often in milliseconds to refresh the display
+const BOOT_STATUS_DURATION: Duration = Duration::from_secs(3);
 
 #[derive(Copy, Clone)]
 pub struct Dimensions {
@@ -40,6 +43,7 @@ pub enum Color {
     Yellow,
     Pink,
     Orange,
+    Rgb(u8, u8, u8),
 }
 
 impl Color {
@@ -54,32 +58,51 @@ impl Color {
             Color::Yellow => (0xff, 0xff, 0),
             Color::Pink => (0xfe, 0x24, 0xff),
             Color::Orange => (0xff, 0xa5, 0),
+            Color::Rgb(r, g, b) => (r, g, b),
         }
     }
 }
 
-fn display_style_from_state(state: DisplayState, colorblind_mode: bool) -> (Color, LinePattern) {
+/// Parses a `#rrggbb` (or `rrggbb`) hex color
+fn parse_hex_color(hex: &str) -> Option<Color> {
+    let hex = hex.trim();
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// The color shown while everything is fine: blue in colorblind mode,
+/// otherwise the configured accent, or green if that isn't a valid color
+fn accent_color(config: &config::Config) -> Color {
+    if config.colorblind_mode {
+        return Color::Blue;
+    }
+    parse_hex_color(&config.display_accent_color).unwrap_or_else(|| {
+        warn!(
+            "invalid display_accent_color {:?}, using green",
+            config.display_accent_color
+        );
+        Color::Green
+    })
+}
+
+fn state_color(state: DisplayState, accent: Color) -> (Color, LinePattern) {
     match state {
         DisplayState::Paused => (Color::White, LinePattern::Solid),
-        DisplayState::Recording => {
-            if colorblind_mode {
-                (Color::Blue, LinePattern::Solid)
-            } else {
-                (Color::Green, LinePattern::Solid)
-            }
-        }
+        DisplayState::Recording => (accent, LinePattern::Solid),
         DisplayState::WarningDetected { event_type } => match event_type {
-            EventType::Informational => {
-                if colorblind_mode {
-                    (Color::Blue, LinePattern::Solid)
-                } else {
-                    (Color::Green, LinePattern::Solid)
-                }
-            }
+            EventType::Informational => (accent, LinePattern::Solid),
             EventType::Low => (Color::Yellow, LinePattern::Dotted),
             EventType::Medium => (Color::Orange, LinePattern::Dashed),
             EventType::High => (Color::Red, LinePattern::Solid),
         },
+        DisplayState::BootStatus { passed: true } => (accent, LinePattern::Solid),
+        DisplayState::BootStatus { passed: false } => (Color::Red, LinePattern::Dashed),
+        // never drawn, since it doesn't replace the current state
+        DisplayState::ButtonPressed => (Color::Black, LinePattern::Solid),
     }
 }
 
@@ -170,7 +193,7 @@ pub trait GenericFramebuffer: Send + 'static {
 pub fn update_ui(
     task_tracker: &TaskTracker,
     config: &config::Config,
-    mut fb: impl GenericFramebuffer,
+    fb: impl GenericFramebuffer,
     shutdown_token: CancellationToken,
     mut ui_update_rx: Receiver<DisplayState>,
 ) {
@@ -181,10 +204,15 @@ pub fn update_ui(
         return;
     }
 
-    let colorblind_mode = config.colorblind_mode;
-    let mut display_style = display_style_from_state(DisplayState::Recording, colorblind_mode);
+    let accent = accent_color(config);
+    let mut current_state = DisplayState::Recording;
+    let policy = ScreenPolicy::from_config(config);
 
     task_tracker.spawn(async move {
+        let mut fb = ScreenSaver::new(fb, policy.burn_in_mitigation);
+        // the last state change or button press
+        let mut last_activity = Instant::now();
+        let mut asleep = false;
         // this feels wrong, is there a more rusty way to do this?
         let mut img: Option<&[u8]> = None;
         if display_level == 2 {
@@ -202,18 +230,65 @@ pub fn update_ui(
                     .contents(),
             );
         }
+        let mut rx_open = true;
         loop {
-            if shutdown_token.is_cancelled() {
-                info!("received UI shutdown");
-                break;
+            let mut updates = Vec::new();
+            // redraw whenever the state changes, and periodically otherwise in
+            // case something else drew over us
+            tokio::select! {
+                _ = shutdown_token.cancelled() => {
+                    info!("received UI shutdown");
+                    break;
+                }
+                msg = ui_update_rx.recv(), if rx_open => match msg {
+                    Some(state) => updates.push(state),
+                    None => {
+                        error!("framebuffer update channel closed");
+                        rx_open = false;
+                    }
+                },
+                _ = tokio::time::sleep(Duration::from_millis(REFRESH_RATE)) => {}
+            }
+            // coalesce a burst of updates into a single redraw
+            while let Ok(state) = ui_update_rx.try_recv() {
+                updates.push(state);
+            }
+
+            for state in updates {
+                if state == DisplayState::ButtonPressed {
+                    last_activity = Instant::now();
+                    continue;
+                }
+                if let DisplayState::BootStatus { .. } = state {
+                    // flash the self-test verdict across the whole screen, then
+                    // carry on with whatever we were showing before
+                    let (color, pattern) = state_color(state, accent);
+                    let height = fb.dimensions().height;
+                    fb.draw_patterned_line(color, height, pattern).await;
+                    tokio::time::sleep(BOOT_STATUS_DURATION).await;
+                    continue;
+                }
+                if state != current_state && policy.wakes_on(state) {
+                    last_activity = Instant::now();
+                }
+                current_state = state;
             }
-            match ui_update_rx.try_recv() {
-                Ok(state) => {
-                    display_style = display_style_from_state(state, colorblind_mode);
+
+            let since_activity = last_activity.elapsed();
+            if !policy.is_awake(current_state, since_activity) {
+                if !asleep {
+                    fb.blank().await;
+                    asleep = true;
                 }
-                Err(tokio::sync::mpsc::error::TryRecvError::Empty) => {}
-                Err(e) => error!("error receiving framebuffer update message: {e}"),
+                continue;
             }
+            asleep = false;
+
+            // the status bar is what matters, so only the picture behind it
+            // is dimmed
+            let burn_in_offset = policy.burn_in_offset(since_activity);
+            fb.set_offset(burn_in_offset.unwrap_or_default());
+            fb.set_dimmed(burn_in_offset.is_some());
 
             let mut status_bar_height = 2;
             match display_level {
@@ -233,10 +308,63 @@ pub fn update_ui(
                 // unknown value is used
                 _ => {}
             };
-            let (color, pattern) = display_style;
+            fb.set_dimmed(false);
+            let (color, pattern) = state_color(current_state, accent);
             fb.draw_patterned_line(color, status_bar_height, pattern)
                 .await;
-            tokio::time::sleep(Duration::from_millis(REFRESH_RATE)).await;
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(color: Option<Color>) -> Option<(u8, u8, u8)> {
+        color.map(Color::rgb)
+    }
+
+    #[test]
+    fn test_parse_hex_color() {
+        assert_eq!(rgb(parse_hex_color("#ff0000")), Some((0xff, 0, 0)));
+        assert_eq!(rgb(parse_hex_color("00Ff7f")), Some((0, 0xff, 0x7f)));
+        assert_eq!(rgb(parse_hex_color(" #123abc ")), Some((0x12, 0x3a, 0xbc)));
+        for invalid in [
+            "", "#", "#fff", "#ff00000", "#gg0000", "red", "#+f+f+f", "#ff00é",
+        ] {
+            assert_eq!(rgb(parse_hex_color(invalid)), None, "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn test_accent_color() {
+        let mut config = config::Config::default();
+        assert_eq!(accent_color(&config).rgb(), Color::Green.rgb());
+        config.display_accent_color = "#ff0000".to_string();
+        assert_eq!(accent_color(&config).rgb(), Color::Red.rgb());
+        config.display_accent_color = "not a color".to_string();
+        assert_eq!(accent_color(&config).rgb(), Color::Green.rgb());
+        // colorblind mode always wins
+        config.display_accent_color = "#ff0000".to_string();
+        config.colorblind_mode = true;
+        assert_eq!(accent_color(&config).rgb(), Color::Blue.rgb());
+    }
+
+    #[test]
+    fn test_state_color_uses_accent() {
+        let accent = Color::Rgb(1, 2, 3);
+        for state in [
+            DisplayState::Recording,
+            DisplayState::BootStatus { passed: true },
+            DisplayState::WarningDetected {
+                event_type: EventType::Informational,
+            },
+        ] {
+            assert_eq!(state_color(state, accent).0.rgb(), (1, 2, 3));
+        }
+        let warning = DisplayState::WarningDetected {
+            event_type: EventType::High,
+        };
+        assert_eq!(state_color(warning, accent).0.rgb(), Color::Red.rgb());
+    }
+}
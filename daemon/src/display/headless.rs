@@ -4,7 +4,7 @@ use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 
 use crate::config;
-use crate::display::DisplayState;
+use crate::display::{DisplayBackend, DisplayState};
 
 pub fn update_ui(
     _task_tracker: &TaskTracker,
@@ -14,3 +14,21 @@ pub fn update_ui(
 ) {
     info!("Headless mode, not spawning UI.");
 }
+
+pub struct HeadlessDisplay;
+
+impl DisplayBackend for HeadlessDisplay {
+    fn name(&self) -> &'static str {
+        "headless"
+    }
+
+    fn update_ui(
+        &self,
+        task_tracker: &TaskTracker,
+        config: &config::Config,
+        shutdown_token: CancellationToken,
+        ui_update_rx: Receiver<DisplayState>,
+    ) {
+        update_ui(task_tracker, config, shutdown_token, ui_update_rx)
+    }
+}
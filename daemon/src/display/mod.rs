@@ -1,7 +1,19 @@
+use std::time::Duration;
+
+use log::debug;
+use rayhunter::Device;
 use rayhunter::analysis::analyzer::EventType;
 use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::config;
 
+mod blink;
 mod generic_framebuffer;
+mod screen_saver;
 
 pub mod headless;
 pub mod orbic;
@@ -25,4 +37,322 @@ pub enum DisplayState {
     /// Note that EventType::Informational is never sent through this. If it is, it's the same as
     /// Recording
     WarningDetected { event_type: EventType },
+    /// Result of the startup self-test. This is shown briefly and doesn't
+    /// replace the current state.
+    BootStatus { passed: bool },
+    /// The power button was pressed. This wakes the display if it's off, and
+    /// doesn't replace the current state.
+    ButtonPressed,
+}
+
+/// A way of showing the daemon's state on a device, be it a screen or LEDs
+pub trait DisplayBackend {
+    /// The backend's name, for logging
+    fn name(&self) -> &'static str;
+
+    /// The file the backend draws to, if it has one. Backends which only
+    /// signal through LEDs return None.
+    fn device_path(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Spawns the task which shows each DisplayState received on
+    /// `ui_update_rx`.
+    fn update_ui(
+        &self,
+        task_tracker: &TaskTracker,
+        config: &config::Config,
+        shutdown_token: CancellationToken,
+        ui_update_rx: Receiver<DisplayState>,
+    );
+}
+
+/// The display backend for `device`. Devices without a display or LEDs we
+/// know how to drive get the headless backend.
+pub fn backend_for(device: &Device) -> Box<dyn DisplayBackend> {
+    match device {
+        Device::Orbic | Device::Moxee => Box::new(orbic::OrbicDisplay),
+        Device::Tplink => Box::new(tplink::TplinkDisplay),
+        Device::Tmobile => Box::new(tmobile::TmobileDisplay),
+        Device::Wingtech => Box::new(wingtech::WingtechDisplay),
+        Device::Uz801 => Box::new(uz801::Uz801Display),
+        Device::Pinephone => Box::new(headless::HeadlessDisplay),
+    }
+}
+
+/// The display backend to use with `config`, which is always headless in
+/// developer mode.
+pub fn backend_for_config(config: &config::Config) -> Box<dyn DisplayBackend> {
+    if config.developer_mode {
+        Box::new(headless::HeadlessDisplay)
+    } else {
+        backend_for(&config.device)
+    }
+}
+
+/// How often the measured redraw rate is logged
+const REDRAW_RATE_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Limits how often a display backend redraws, so bursts of state updates
+/// don't keep the CPU busy redrawing. Urgent updates (e.g. a new warning) are
+/// let through right away.
+struct RedrawThrottle {
+    min_interval: Duration,
+    last_redraw: Option<Instant>,
+    redraws_since_log: u32,
+    last_log: Instant,
+}
+
+impl RedrawThrottle {
+    /// A `max_redraws_per_sec` of 0 disables throttling.
+    fn new(max_redraws_per_sec: u32) -> Self {
+        let min_interval = if max_redraws_per_sec == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(1) / max_redraws_per_sec
+        };
+        RedrawThrottle {
+            min_interval,
+            last_redraw: None,
+            redraws_since_log: 0,
+            last_log: Instant::now(),
+        }
+    }
+
+    /// Waits until the next redraw is allowed, and records that it happened.
+    async fn wait(&mut self, urgent: bool) {
+        if !urgent && let Some(last_redraw) = self.last_redraw {
+            tokio::time::sleep_until(last_redraw + self.min_interval).await;
+        }
+        let now = Instant::now();
+        self.last_redraw = Some(now);
+        self.redraws_since_log += 1;
+
+        let elapsed = now - self.last_log;
+        if elapsed >= REDRAW_RATE_LOG_INTERVAL {
+            debug!(
+                "display redraw rate: {:.2}/s",
+                self.redraws_since_log as f64 / elapsed.as_secs_f64()
+            );
+            self.redraws_since_log = 0;
+            self.last_log = now;
+        }
+    }
+}
+
+/// Whether moving from `old` to `new` should be shown without waiting for
+/// the redraw throttle: a new warning, a button press waking the display, or
+/// the self-test verdict, which is only shown briefly.
+fn is_urgent_transition(old: DisplayState, new: DisplayState) -> bool {
+    match new {
+        DisplayState::ButtonPressed | DisplayState::BootStatus { .. } => true,
+        DisplayState::WarningDetected { .. } => old != new,
+        DisplayState::Recording | DisplayState::Paused => false,
+    }
+}
+
+/// Spawns the task which passes DisplayStates from `ui_update_rx` on to a
+/// display backend, at most `max_redraws_per_sec` times a second. A burst
+/// of updates is coalesced into the latest state, and repeats of the state
+/// being shown are dropped. Urgent updates go through right away. Returns
+/// the receiver the backend should read from.
+pub fn throttle_updates(
+    task_tracker: &TaskTracker,
+    max_redraws_per_sec: u32,
+    shutdown_token: CancellationToken,
+    mut ui_update_rx: Receiver<DisplayState>,
+) -> Receiver<DisplayState> {
+    let (tx, rx) = mpsc::channel(1);
+    let mut throttle = RedrawThrottle::new(max_redraws_per_sec);
+    task_tracker.spawn(async move {
+        // the last Recording, Paused or WarningDetected state passed on
+        let mut shown = None;
+        let mut pending = None;
+        loop {
+            let state = tokio::select! {
+                _ = shutdown_token.cancelled() => break,
+                _ = throttle.wait(false), if pending.is_some() => {
+                    let Some(state) = pending.take() else { continue };
+                    state
+                }
+                msg = ui_update_rx.recv() => match msg {
+                    Some(state)
+                        if is_urgent_transition(shown.unwrap_or(DisplayState::Recording), state) =>
+                    {
+                        throttle.wait(true).await;
+                        state
+                    }
+                    Some(state) => {
+                        pending = (shown != Some(state)).then_some(state);
+                        continue;
+                    }
+                    None => {
+                        // let the backend show the last update before it stops
+                        if let Some(state) = pending.take() {
+                            let _ = tx.send(state).await;
+                        }
+                        break;
+                    }
+                },
+            };
+            if !matches!(
+                state,
+                DisplayState::ButtonPressed | DisplayState::BootStatus { .. }
+            ) {
+                shown = Some(state);
+                pending = None;
+            }
+            if tx.send(state).await.is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttle_limits_redraw_rate() {
+        let mut throttle = RedrawThrottle::new(4);
+        let start = Instant::now();
+        throttle.wait(false).await;
+        assert_eq!(Instant::now() - start, Duration::ZERO);
+
+        for _ in 0..4 {
+            throttle.wait(false).await;
+        }
+        assert_eq!(Instant::now() - start, Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttle_lets_urgent_redraws_through() {
+        let mut throttle = RedrawThrottle::new(4);
+        let start = Instant::now();
+        throttle.wait(false).await;
+        throttle.wait(true).await;
+        assert_eq!(Instant::now() - start, Duration::ZERO);
+
+        // the urgent redraw still counts towards the next deadline
+        throttle.wait(false).await;
+        assert_eq!(Instant::now() - start, Duration::from_millis(250));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttle_doesnt_wait_after_idle() {
+        let mut throttle = RedrawThrottle::new(4);
+        throttle.wait(false).await;
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        let start = Instant::now();
+        throttle.wait(false).await;
+        assert_eq!(Instant::now() - start, Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unlimited_throttle() {
+        let mut throttle = RedrawThrottle::new(0);
+        let start = Instant::now();
+        for _ in 0..100 {
+            throttle.wait(false).await;
+        }
+        assert_eq!(Instant::now() - start, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_every_device_has_a_backend() {
+        let devices = [
+            Device::Orbic,
+            Device::Tplink,
+            Device::Tmobile,
+            Device::Wingtech,
+            Device::Pinephone,
+            Device::Uz801,
+            Device::Moxee,
+        ];
+        for device in &devices {
+            // adding a Device without listing it above breaks this match
+            match device {
+                Device::Orbic
+                | Device::Tplink
+                | Device::Tmobile
+                | Device::Wingtech
+                | Device::Pinephone
+                | Device::Uz801
+                | Device::Moxee => {}
+            }
+            assert!(!backend_for(device).name().is_empty());
+        }
+        assert_eq!(backend_for(&Device::Moxee).name(), "orbic");
+        assert_eq!(backend_for(&Device::Pinephone).name(), "headless");
+        assert_eq!(backend_for(&Device::Uz801).device_path(), None);
+        assert_eq!(
+            backend_for(&Device::Wingtech).device_path(),
+            Some(wingtech::FB_PATH)
+        );
+
+        let mut config = config::Config::default();
+        assert_eq!(backend_for_config(&config).name(), "orbic");
+        config.developer_mode = true;
+        assert_eq!(backend_for_config(&config).name(), "headless");
+    }
+
+    #[test]
+    fn test_urgent_transitions() {
+        let warning = DisplayState::WarningDetected {
+            event_type: EventType::High,
+        };
+        assert!(is_urgent_transition(DisplayState::Recording, warning));
+        assert!(!is_urgent_transition(warning, warning));
+        assert!(!is_urgent_transition(warning, DisplayState::Paused));
+        assert!(is_urgent_transition(warning, DisplayState::ButtonPressed));
+        assert!(is_urgent_transition(
+            DisplayState::Recording,
+            DisplayState::BootStatus { passed: true }
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttle_updates_coalesces_bursts() {
+        let task_tracker = TaskTracker::new();
+        let (tx, rx) = mpsc::channel(8);
+        let mut rx = throttle_updates(&task_tracker, 4, CancellationToken::new(), rx);
+        let start = Instant::now();
+        tx.send(DisplayState::Paused).await.unwrap();
+        assert!(rx.recv().await == Some(DisplayState::Paused));
+        assert_eq!(Instant::now() - start, Duration::ZERO);
+
+        // only the latest of a burst is shown, once the throttle allows it
+        for state in [
+            DisplayState::Recording,
+            DisplayState::Paused,
+            DisplayState::Recording,
+        ] {
+            tx.send(state).await.unwrap();
+        }
+        assert!(rx.recv().await == Some(DisplayState::Recording));
+        assert_eq!(Instant::now() - start, Duration::from_millis(250));
+
+        // and repeats of what's shown are dropped
+        tx.send(DisplayState::Recording).await.unwrap();
+        drop(tx);
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttle_updates_lets_urgent_updates_through() {
+        let task_tracker = TaskTracker::new();
+        let (tx, rx) = mpsc::channel(8);
+        let mut rx = throttle_updates(&task_tracker, 4, CancellationToken::new(), rx);
+        let start = Instant::now();
+        let warning = DisplayState::WarningDetected {
+            event_type: EventType::High,
+        };
+        for state in [DisplayState::Paused, DisplayState::ButtonPressed, warning] {
+            tx.send(state).await.unwrap();
+            assert!(rx.recv().await == Some(state));
+        }
+        assert_eq!(Instant::now() - start, Duration::ZERO);
+    }
 }
@@ -1,13 +1,13 @@
 use crate::config;
-use crate::display::DisplayState;
 use crate::display::generic_framebuffer::{self, Dimensions, GenericFramebuffer};
+use crate::display::{DisplayBackend, DisplayState};
 use async_trait::async_trait;
 
 use tokio::sync::mpsc::Receiver;
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 
-const FB_PATH: &str = "/dev/fb0";
+pub const FB_PATH: &str = "/dev/fb0";
 
 #[derive(Copy, Clone, Default)]
 struct Framebuffer;
@@ -49,3 +49,25 @@ pub fn update_ui(
         ui_update_rx,
     )
 }
+
+pub struct OrbicDisplay;
+
+impl DisplayBackend for OrbicDisplay {
+    fn name(&self) -> &'static str {
+        "orbic"
+    }
+
+    fn device_path(&self) -> Option<&'static str> {
+        Some(FB_PATH)
+    }
+
+    fn update_ui(
+        &self,
+        task_tracker: &TaskTracker,
+        config: &config::Config,
+        shutdown_token: CancellationToken,
+        ui_update_rx: Receiver<DisplayState>,
+    ) {
+        update_ui(task_tracker, config, shutdown_token, ui_update_rx)
+    }
+}
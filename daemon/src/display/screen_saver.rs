@@ -0,0 +1,278 @@
+//! Turning a framebuffer display off when nobody needs to look at it, and
+//! slowly moving and dimming a picture which hasn't changed for a while so it
+//! doesn't burn into the screen.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::config;
+use crate::display::DisplayState;
+use crate::display::generic_framebuffer::{Dimensions, GenericFramebuffer};
+
+/// How long the display must stay the same before burn-in mitigation starts
+const BURN_IN_DELAY: Duration = Duration::from_secs(60);
+/// How long the picture stays at each offset once it's being shifted
+const SHIFT_STEP: Duration = Duration::from_secs(30);
+/// How far the picture may be shifted in each direction, in pixels
+const MAX_SHIFT: u32 = 2;
+/// The offsets the picture is moved through, going round in a square
+const SHIFT_CYCLE: [(i32, i32); 8] = [
+    (2, 0),
+    (2, 2),
+    (0, 2),
+    (-2, 2),
+    (-2, 0),
+    (-2, -2),
+    (0, -2),
+    (2, -2),
+];
+/// How long a button press wakes the display for while recording, if
+/// display_off_while_recording is set but there's no timeout
+const WAKE_DURATION: Duration = Duration::from_secs(30);
+
+/// When the display should be on, and whether to mitigate burn-in
+#[derive(Clone, Copy)]
+pub struct ScreenPolicy {
+    /// Turn the display off this long after the last activity
+    timeout: Option<Duration>,
+    off_while_recording: bool,
+    pub burn_in_mitigation: bool,
+}
+
+impl ScreenPolicy {
+    pub fn from_config(config: &config::Config) -> Self {
+        ScreenPolicy {
+            timeout: (config.display_timeout_secs > 0)
+                .then(|| Duration::from_secs(config.display_timeout_secs)),
+            off_while_recording: config.display_off_while_recording,
+            burn_in_mitigation: config.display_burn_in_mitigation,
+        }
+    }
+
+    /// Whether changing to `state` counts as activity which wakes the
+    /// display. A button press always does.
+    pub fn wakes_on(&self, state: DisplayState) -> bool {
+        !(self.off_while_recording && state == DisplayState::Recording)
+    }
+
+    /// Whether the display should be on while showing `state`, given how
+    /// long it's been since the last activity.
+    pub fn is_awake(&self, state: DisplayState, since_activity: Duration) -> bool {
+        if self.off_while_recording && state == DisplayState::Recording {
+            return since_activity < self.timeout.unwrap_or(WAKE_DURATION);
+        }
+        self.timeout.is_none_or(|timeout| since_activity < timeout)
+    }
+
+    /// The offset to shift the picture by, if burn-in mitigation is enabled
+    /// and the picture hasn't changed for long enough to need it.
+    pub fn burn_in_offset(&self, since_activity: Duration) -> Option<(i32, i32)> {
+        if !self.burn_in_mitigation || since_activity <= BURN_IN_DELAY {
+            return None;
+        }
+        Some(shift_offset(since_activity - BURN_IN_DELAY))
+    }
+}
+
+/// The offset to shift the picture by once it's been shifting for `elapsed`.
+/// Every offset is within [MAX_SHIFT] of the centre, and each is one step
+/// away from the one before, so the picture creeps around rather than jumps.
+fn shift_offset(elapsed: Duration) -> (i32, i32) {
+    let step = (elapsed.as_secs() / SHIFT_STEP.as_secs()) as usize;
+    SHIFT_CYCLE[step % SHIFT_CYCLE.len()]
+}
+
+fn dim((r, g, b): (u8, u8, u8)) -> (u8, u8, u8) {
+    (r / 2, g / 2, b / 2)
+}
+
+/// The area left to draw on, once a margin has been kept for shifting
+fn canvas_dimensions(screen: Dimensions) -> Dimensions {
+    Dimensions {
+        height: screen.height.saturating_sub(2 * MAX_SHIFT).max(1),
+        width: screen.width.saturating_sub(2 * MAX_SHIFT).max(1),
+    }
+}
+
+/// Places the canvas in the middle of an otherwise black screen, moved by
+/// `offset`.
+fn compose(canvas: &[(u8, u8, u8)], screen: Dimensions, (dx, dy): (i32, i32)) -> Vec<(u8, u8, u8)> {
+    let canvas_width = canvas_dimensions(screen).width as usize;
+    let screen_width = screen.width as usize;
+    let shift = MAX_SHIFT as i32;
+    let left = (shift + dx.clamp(-shift, shift)) as usize;
+    let top = (shift + dy.clamp(-shift, shift)) as usize;
+
+    let mut frame = vec![(0, 0, 0); screen_width * screen.height as usize];
+    for (y, row) in canvas.chunks(canvas_width).enumerate() {
+        let start = (top + y) * screen_width + left;
+        frame[start..start + row.len()].copy_from_slice(row);
+    }
+    frame
+}
+
+/// Wraps a framebuffer to dim and shift what's drawn on it. With burn-in
+/// mitigation enabled, everything is drawn onto a canvas [MAX_SHIFT] pixels
+/// smaller than the screen on every side, so shifting it never moves
+/// anything off screen.
+pub struct ScreenSaver<F> {
+    inner: F,
+    canvas: Option<Vec<(u8, u8, u8)>>,
+    offset: (i32, i32),
+    dimmed: bool,
+}
+
+impl<F: GenericFramebuffer> ScreenSaver<F> {
+    pub fn new(inner: F, burn_in_mitigation: bool) -> Self {
+        let canvas = burn_in_mitigation.then(|| {
+            let dimensions = canvas_dimensions(inner.dimensions());
+            vec![(0, 0, 0); (dimensions.width * dimensions.height) as usize]
+        });
+        ScreenSaver {
+            inner,
+            canvas,
+            offset: (0, 0),
+            dimmed: false,
+        }
+    }
+
+    /// Sets the offset used from the next write. Does nothing without burn-in
+    /// mitigation.
+    pub fn set_offset(&mut self, offset: (i32, i32)) {
+        self.offset = offset;
+    }
+
+    /// Whether to dim whatever is written next
+    pub fn set_dimmed(&mut self, dimmed: bool) {
+        self.dimmed = dimmed;
+    }
+
+    /// Turns every pixel off
+    pub async fn blank(&mut self) {
+        if let Some(canvas) = &mut self.canvas {
+            canvas.fill((0, 0, 0));
+        }
+        let screen = self.inner.dimensions();
+        self.inner
+            .write_buffer(vec![(0, 0, 0); (screen.width * screen.height) as usize])
+            .await
+    }
+}
+
+#[async_trait]
+impl<F: GenericFramebuffer> GenericFramebuffer for ScreenSaver<F> {
+    fn dimensions(&self) -> Dimensions {
+        let screen = self.inner.dimensions();
+        if self.canvas.is_some() {
+            canvas_dimensions(screen)
+        } else {
+            screen
+        }
+    }
+
+    async fn write_buffer(&mut self, mut buffer: Vec<(u8, u8, u8)>) {
+        if self.dimmed {
+            buffer.iter_mut().for_each(|pixel| *pixel = dim(*pixel));
+        }
+        let Some(canvas) = &mut self.canvas else {
+            return self.inner.write_buffer(buffer).await;
+        };
+        // like a real framebuffer, a short buffer only overwrites the top of
+        // the canvas
+        let len = buffer.len().min(canvas.len());
+        canvas[..len].copy_from_slice(&buffer[..len]);
+        let frame = compose(canvas, self.inner.dimensions(), self.offset);
+        self.inner.write_buffer(frame).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayhunter::analysis::analyzer::EventType;
+
+    fn policy(timeout_secs: u64, off_while_recording: bool) -> ScreenPolicy {
+        ScreenPolicy {
+            timeout: (timeout_secs > 0).then(|| Duration::from_secs(timeout_secs)),
+            off_while_recording,
+            burn_in_mitigation: true,
+        }
+    }
+
+    #[test]
+    fn test_shift_offset_cycle() {
+        let offsets: Vec<(i32, i32)> = (0..=SHIFT_CYCLE.len() as u64)
+            .map(|step| shift_offset(SHIFT_STEP * step as u32))
+            .collect();
+        // it wraps back round to the start
+        assert_eq!(offsets.first(), offsets.last());
+        for pair in offsets.windows(2) {
+            let ((x1, y1), (x2, y2)) = (pair[0], pair[1]);
+            assert!(x1.abs() <= MAX_SHIFT as i32 && y1.abs() <= MAX_SHIFT as i32);
+            // one step at a time, in one direction
+            assert_eq!((x1 - x2).abs() + (y1 - y2).abs(), MAX_SHIFT as i32);
+        }
+        // the offset only moves once per step
+        assert_eq!(
+            shift_offset(SHIFT_STEP - Duration::from_secs(1)),
+            offsets[0]
+        );
+    }
+
+    #[test]
+    fn test_burn_in_offset_waits_for_delay() {
+        let policy = policy(0, false);
+        assert_eq!(policy.burn_in_offset(Duration::ZERO), None);
+        assert_eq!(policy.burn_in_offset(BURN_IN_DELAY), None);
+        assert_eq!(
+            policy.burn_in_offset(BURN_IN_DELAY + Duration::from_secs(1)),
+            Some(SHIFT_CYCLE[0])
+        );
+        let disabled = ScreenPolicy {
+            burn_in_mitigation: false,
+            ..policy
+        };
+        assert_eq!(disabled.burn_in_offset(BURN_IN_DELAY * 10), None);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let never = policy(0, false);
+        assert!(never.is_awake(DisplayState::Recording, Duration::from_secs(86400)));
+
+        let timeout = policy(30, false);
+        assert!(timeout.is_awake(DisplayState::Paused, Duration::from_secs(29)));
+        assert!(!timeout.is_awake(DisplayState::Paused, Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_off_while_recording() {
+        let policy = policy(0, true);
+        let warning = DisplayState::WarningDetected {
+            event_type: EventType::High,
+        };
+        assert!(!policy.wakes_on(DisplayState::Recording));
+        assert!(policy.wakes_on(warning));
+        assert!(policy.is_awake(DisplayState::Recording, Duration::from_secs(1)));
+        assert!(!policy.is_awake(DisplayState::Recording, WAKE_DURATION));
+        assert!(policy.is_awake(warning, Duration::from_secs(86400)));
+        assert!(policy.is_awake(DisplayState::Paused, Duration::from_secs(86400)));
+    }
+
+    #[test]
+    fn test_compose_never_clips() {
+        let screen = Dimensions {
+            height: 8,
+            width: 8,
+        };
+        let canvas_size = canvas_dimensions(screen);
+        let canvas = vec![(0xff, 0xff, 0xff); (canvas_size.width * canvas_size.height) as usize];
+        for offset in SHIFT_CYCLE {
+            let frame = compose(&canvas, screen, offset);
+            assert_eq!(frame.len(), 64);
+            let lit = frame.iter().filter(|pixel| **pixel != (0, 0, 0)).count();
+            assert_eq!(lit, canvas.len());
+        }
+    }
+}
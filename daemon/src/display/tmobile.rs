@@ -1,27 +1,47 @@
-/// Display module for Tmobile TMOHS1, blink LEDs on the front of the device.
-/// DisplayState::Recording => Signal LED slowly blinks blue.
+/// Display module for Tmobile TMOHS1, light LEDs on the front of the device.
+/// DisplayState::Recording => Signal LED is solid blue.
 /// DisplayState::Paused => WiFi LED blinks white.
-/// DisplayState::WarningDetected { .. } => Signal LED slowly blinks red.
+/// DisplayState::WarningDetected { .. } => Signal LED blinks red, slowly for a
+/// low or medium severity warning and fast for a high severity one.
 use log::{error, info};
 use tokio::sync::mpsc;
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 
-use std::time::Duration;
+use rayhunter::analysis::analyzer::EventType;
 
 use crate::config;
-use crate::display::DisplayState;
+use crate::display::blink::{BlinkPattern, Blinker};
+use crate::display::{DisplayBackend, DisplayState};
 
 macro_rules! led {
-    ($l:expr) => {{ format!("/sys/class/leds/led:{}/blink", $l) }};
+    ($l:expr, $attr:expr) => {{ format!("/sys/class/leds/led:{}/{}", $l, $attr) }};
 }
 
-async fn start_blinking(path: String) {
-    tokio::fs::write(&path, "1").await.ok();
+async fn start_blinking(led: &str) {
+    tokio::fs::write(led!(led, "blink"), "1").await.ok();
 }
 
-async fn stop_blinking(path: String) {
-    tokio::fs::write(&path, "0").await.ok();
+async fn stop_blinking(led: &str) {
+    tokio::fs::write(led!(led, "blink"), "0").await.ok();
+}
+
+async fn set_lit(led: &str, lit: bool) {
+    let brightness = if lit { "1" } else { "0" };
+    tokio::fs::write(led!(led, "brightness"), brightness)
+        .await
+        .ok();
+}
+
+/// The LED which follows the state's [BlinkPattern]
+fn signal_led(state: DisplayState) -> &'static str {
+    match state {
+        DisplayState::WarningDetected { event_type } if event_type != EventType::Informational => {
+            "signal_red"
+        }
+        _ => "signal_blue",
+    }
 }
 
 pub fn update_ui(
@@ -37,41 +57,73 @@ pub fn update_ui(
     }
     task_tracker.spawn(async move {
         let mut state = DisplayState::Recording;
-        let mut last_state = DisplayState::Paused;
+        let mut last_state = None;
+        let mut blinker = Blinker::new(Instant::now());
+        let mut lit = false;
+        let mut rx_open = true;
 
         loop {
-            if shutdown_token.is_cancelled() {
-                info!("received UI shutdown");
-                break;
-            }
-            match ui_update_rx.try_recv() {
-                Ok(new_state) => state = new_state,
-                Err(mpsc::error::TryRecvError::Empty) => {}
-                Err(e) => error!("error receiving ui update message: {e}"),
-            };
-            if invisible || state == last_state {
-                tokio::time::sleep(Duration::from_secs(1)).await;
-                continue;
-            }
-            match state {
-                DisplayState::Paused => {
-                    stop_blinking(led!("signal_blue")).await;
-                    stop_blinking(led!("signal_red")).await;
-                    start_blinking(led!("wlan_white")).await;
+            if !invisible {
+                let now = Instant::now();
+                if last_state != Some(state) {
+                    // the signal LEDs are blinked by us rather than by the
+                    // kernel, so they can blink at different rates
+                    for led in ["signal_blue", "signal_red"] {
+                        stop_blinking(led).await;
+                        set_lit(led, false).await;
+                    }
+                    if state == DisplayState::Paused {
+                        start_blinking("wlan_white").await;
+                    } else {
+                        stop_blinking("wlan_white").await;
+                    }
+                    blinker.set_pattern(BlinkPattern::for_state(state), now);
+                    lit = false;
+                    last_state = Some(state);
                 }
-                DisplayState::Recording => {
-                    stop_blinking(led!("wlan_white")).await;
-                    stop_blinking(led!("signal_red")).await;
-                    start_blinking(led!("signal_blue")).await;
+                if blinker.is_on(now) != lit {
+                    lit = !lit;
+                    set_lit(signal_led(state), lit).await;
                 }
-                DisplayState::WarningDetected { .. } => {
-                    stop_blinking(led!("wlan_white")).await;
-                    stop_blinking(led!("signal_blue")).await;
-                    start_blinking(led!("signal_red")).await;
+            }
+            // while invisible, the pattern is never set and so never toggles
+            let next_toggle = blinker.next_toggle(Instant::now());
+
+            tokio::select! {
+                _ = shutdown_token.cancelled() => {
+                    info!("received UI shutdown");
+                    break;
                 }
+                msg = ui_update_rx.recv(), if rx_open => match msg {
+                    // there's no screen to show the self-test result on, or to wake
+                    Some(DisplayState::BootStatus { .. } | DisplayState::ButtonPressed) => {}
+                    Some(new_state) => state = new_state,
+                    None => {
+                        error!("ui update channel closed");
+                        rx_open = false;
+                    }
+                },
+                _ = tokio::time::sleep_until(next_toggle.unwrap_or_else(Instant::now)),
+                    if next_toggle.is_some() => {}
             }
-            last_state = state;
-            tokio::time::sleep(Duration::from_secs(1)).await;
         }
     });
 }
+
+pub struct TmobileDisplay;
+
+impl DisplayBackend for TmobileDisplay {
+    fn name(&self) -> &'static str {
+        "tmobile"
+    }
+
+    fn update_ui(
+        &self,
+        task_tracker: &TaskTracker,
+        config: &config::Config,
+        shutdown_token: CancellationToken,
+        ui_update_rx: mpsc::Receiver<DisplayState>,
+    ) {
+        update_ui(task_tracker, config, shutdown_token, ui_update_rx)
+    }
+}
@@ -4,7 +4,7 @@ use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 
 use crate::config;
-use crate::display::{DisplayState, tplink_framebuffer, tplink_onebit};
+use crate::display::{DisplayBackend, DisplayState, tplink_framebuffer, tplink_onebit};
 
 use std::fs;
 
@@ -29,3 +29,30 @@ pub fn update_ui(
         tplink_framebuffer::update_ui(task_tracker, config, shutdown_token, ui_update_rx)
     }
 }
+
+pub struct TplinkDisplay;
+
+impl DisplayBackend for TplinkDisplay {
+    fn name(&self) -> &'static str {
+        "tplink"
+    }
+
+    fn device_path(&self) -> Option<&'static str> {
+        // Since this is a one-time check at startup, using sync is acceptable
+        if fs::exists(tplink_onebit::OLED_PATH).unwrap_or_default() {
+            Some(tplink_onebit::OLED_PATH)
+        } else {
+            Some(tplink_framebuffer::FB_PATH)
+        }
+    }
+
+    fn update_ui(
+        &self,
+        task_tracker: &TaskTracker,
+        config: &config::Config,
+        shutdown_token: CancellationToken,
+        ui_update_rx: Receiver<DisplayState>,
+    ) {
+        update_ui(task_tracker, config, shutdown_token, ui_update_rx)
+    }
+}
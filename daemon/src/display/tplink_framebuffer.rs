@@ -11,7 +11,7 @@ use crate::display::generic_framebuffer::{self, Dimensions, GenericFramebuffer};
 use tokio::sync::mpsc::Receiver;
 use tokio_util::task::TaskTracker;
 
-const FB_PATH: &str = "/dev/fb0";
+pub const FB_PATH: &str = "/dev/fb0";
 
 struct Framebuffer;
 
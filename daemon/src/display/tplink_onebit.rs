@@ -132,6 +132,7 @@ pub fn update_ui(
                 Ok(DisplayState::Paused) => pixels = STATUS_PAUSED,
                 Ok(DisplayState::Recording) => pixels = STATUS_SMILING,
                 Ok(DisplayState::WarningDetected { .. }) => pixels = STATUS_WARNING,
+                Ok(DisplayState::BootStatus { .. } | DisplayState::ButtonPressed) => {}
                 Err(tokio::sync::mpsc::error::TryRecvError::Empty) => {}
                 Err(e) => {
                     error!("error receiving framebuffer update message: {e}");
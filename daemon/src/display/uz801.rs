@@ -10,7 +10,7 @@ use tokio_util::task::TaskTracker;
 use std::time::Duration;
 
 use crate::config;
-use crate::display::DisplayState;
+use crate::display::{DisplayBackend, DisplayState};
 
 macro_rules! led {
     ($l:expr) => {{ format!("/sys/class/leds/{}/brightness", $l) }};
@@ -46,6 +46,8 @@ pub fn update_ui(
                 break;
             }
             match ui_update_rx.try_recv() {
+                // there's no screen to show the self-test result on, or to wake
+                Ok(DisplayState::BootStatus { .. } | DisplayState::ButtonPressed) => {}
                 Ok(new_state) => state = new_state,
                 Err(mpsc::error::TryRecvError::Empty) => {}
                 Err(e) => error!("error receiving ui update message: {e}"),
@@ -74,6 +76,7 @@ pub fn update_ui(
                         led_off(led!("wifi")).await;
                         led_on(led!("red")).await;
                     }
+                    DisplayState::BootStatus { .. } | DisplayState::ButtonPressed => {}
                 }
                 last_state = state;
                 last_update = now;
@@ -83,3 +86,21 @@ pub fn update_ui(
         }
     });
 }
+
+pub struct Uz801Display;
+
+impl DisplayBackend for Uz801Display {
+    fn name(&self) -> &'static str {
+        "uz801"
+    }
+
+    fn update_ui(
+        &self,
+        task_tracker: &TaskTracker,
+        config: &config::Config,
+        shutdown_token: CancellationToken,
+        ui_update_rx: mpsc::Receiver<DisplayState>,
+    ) {
+        update_ui(task_tracker, config, shutdown_token, ui_update_rx)
+    }
+}
@@ -1,6 +1,6 @@
 use crate::config;
-use crate::display::DisplayState;
 use crate::display::generic_framebuffer::{self, Dimensions, GenericFramebuffer};
+use crate::display::{DisplayBackend, DisplayState};
 /// Display support for the Wingtech CT2MHS01 hotspot.
 ///
 /// Tested on (from `/etc/wt_version`):
@@ -13,7 +13,7 @@ use tokio::sync::mpsc::Receiver;
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 
-const FB_PATH: &str = "/dev/fb0";
+pub const FB_PATH: &str = "/dev/fb0";
 
 #[derive(Copy, Clone, Default)]
 struct Framebuffer;
@@ -54,3 +54,25 @@ pub fn update_ui(
         ui_update_rx,
     )
 }
+
+pub struct WingtechDisplay;
+
+impl DisplayBackend for WingtechDisplay {
+    fn name(&self) -> &'static str {
+        "wingtech"
+    }
+
+    fn device_path(&self) -> Option<&'static str> {
+        Some(FB_PATH)
+    }
+
+    fn update_ui(
+        &self,
+        task_tracker: &TaskTracker,
+        config: &config::Config,
+        shutdown_token: CancellationToken,
+        ui_update_rx: Receiver<DisplayState>,
+    ) {
+        update_ui(task_tracker, config, shutdown_token, ui_update_rx)
+    }
+}
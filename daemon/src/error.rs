@@ -19,6 +19,4 @@ pub enum RayhunterError {
     BatteryLevelParseError,
     #[error("Error parsing file to determine whether device is plugged in")]
     BatteryPluggedInStatusParseError,
-    #[error("The requested functionality is not supported for this device")]
-    FunctionNotSupportedForDeviceError,
 }
@@ -0,0 +1,453 @@
+//! A long-lived log of every analyzer event this device has seen, across all
+//! recordings. Per-recording NDJSON reports are great for looking at a single
+//! capture, but answering "how many Medium+ events have we ever seen" would
+//! otherwise mean downloading and grepping all of them.
+//!
+//! This is deliberately not a real database: events are rare enough that we
+//! keep them all in memory, and persist them as an append-only NDJSON file
+//! which gets rewritten whenever it's pruned back under its size cap.
+
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use chrono::{DateTime, FixedOffset};
+use log::warn;
+use rayhunter::analysis::analyzer::{AnalysisRow, EventType};
+use serde::{Deserialize, Serialize};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+use crate::disk_space::has_room_for_auxiliary_write;
+use crate::server::ServerState;
+
+pub const EVENT_STORE_FILENAME: &str = "events.ndjson";
+/// The on-disk size the event store is pruned back under
+pub const EVENT_STORE_MAX_BYTES: u64 = 4 * 1024 * 1024;
+const DEFAULT_QUERY_LIMIT: usize = 100;
+const MAX_QUERY_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct StoredEvent {
+    /// The timestamp of the packet which triggered the event, if known
+    #[cfg_attr(feature = "apidocs", schema(value_type = Option<String>))]
+    pub timestamp: Option<DateTime<FixedOffset>>,
+    /// The name of the recording the event was seen in, or empty for events
+    /// which don't come from a recording, like the AP client monitor's
+    pub recording: String,
+    /// The index of the triggering packet within its recording, or of the
+    /// client in the AP client list
+    pub packet_num: usize,
+    /// The name of the analyzer which emitted the event
+    pub analyzer: String,
+    pub event_type: EventType,
+    pub message: String,
+}
+
+impl StoredEvent {
+    /// Converts the events in an analysis row into stored events. The i-th
+    /// event in a row always belongs to the i-th analyzer.
+    pub fn from_row(
+        row: &AnalysisRow,
+        recording: &str,
+        packet_num: usize,
+        analyzer_names: &[String],
+    ) -> Vec<StoredEvent> {
+        row.events
+            .iter()
+            .zip(analyzer_names)
+            .filter_map(|(event, analyzer)| {
+                let event = event.as_ref()?;
+                Some(StoredEvent {
+                    timestamp: row.packet_timestamp,
+                    recording: recording.to_string(),
+                    packet_num,
+                    analyzer: analyzer.clone(),
+                    event_type: event.event_type,
+                    message: event.message.clone(),
+                })
+            })
+            .collect()
+    }
+
+    fn key(&self) -> (String, usize, String) {
+        (
+            self.recording.clone(),
+            self.packet_num,
+            self.analyzer.clone(),
+        )
+    }
+
+    fn to_line(&self) -> String {
+        let mut line = serde_json::to_string(self).unwrap();
+        line.push('\n');
+        line
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct EventQuery {
+    /// Only return events at or after this time
+    pub since: Option<DateTime<FixedOffset>>,
+    /// Only return events at least this severe
+    pub severity: Option<EventType>,
+    /// Only return events from the analyzer with this name
+    pub analyzer: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+impl EventQuery {
+    fn matches(&self, event: &StoredEvent) -> bool {
+        if let Some(since) = self.since
+            && !event.timestamp.is_some_and(|timestamp| timestamp >= since)
+        {
+            return false;
+        }
+        if let Some(severity) = self.severity
+            && event.event_type < severity
+        {
+            return false;
+        }
+        if let Some(analyzer) = &self.analyzer
+            && &event.analyzer != analyzer
+        {
+            return false;
+        }
+        true
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct EventPage {
+    /// The matching events, newest first
+    pub events: Vec<StoredEvent>,
+    /// How many events matched in total, ignoring limit and offset
+    pub total: usize,
+}
+
+pub struct EventStore {
+    path: PathBuf,
+    max_bytes: u64,
+    // oldest first
+    events: VecDeque<StoredEvent>,
+    keys: HashSet<(String, usize, String)>,
+    size_bytes: u64,
+}
+
+impl EventStore {
+    /// Loads the event store at `path`, creating it if it doesn't exist yet.
+    /// Lines which fail to parse are skipped.
+    pub async fn open<P: AsRef<Path>>(path: P, max_bytes: u64) -> Result<Self, std::io::Error> {
+        let path = path.as_ref().to_path_buf();
+        let contents = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut store = EventStore {
+            path,
+            max_bytes,
+            events: VecDeque::new(),
+            keys: HashSet::new(),
+            size_bytes: 0,
+        };
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            match serde_json::from_str::<StoredEvent>(line) {
+                Ok(event) => {
+                    if store.keys.insert(event.key()) {
+                        store.size_bytes += line.len() as u64 + 1;
+                        store.events.push_back(event);
+                    }
+                }
+                Err(e) => warn!("skipping unparseable event store line: {e}"),
+            }
+        }
+        Ok(store)
+    }
+
+    pub fn size_bytes(&self) -> u64 {
+        self.size_bytes
+    }
+
+    /// Moves over to the event store at `path`, e.g. once the QMDL store has
+    /// moved, adding the events recorded here to whatever's already there.
+    pub async fn move_to<P: AsRef<Path>>(&mut self, path: P) -> Result<(), std::io::Error> {
+        let mut moved = EventStore::open(path, self.max_bytes).await?;
+        moved.insert(self.events.iter().cloned().collect()).await?;
+        *self = moved;
+        Ok(())
+    }
+
+    /// Adds events to the store, skipping any that are already recorded (as
+    /// identified by their recording, packet number and analyzer). Returns
+    /// how many were new.
+    pub async fn insert(&mut self, events: Vec<StoredEvent>) -> Result<usize, std::io::Error> {
+        let mut lines = String::new();
+        let mut added = 0;
+        for event in events {
+            if !self.keys.insert(event.key()) {
+                continue;
+            }
+            lines.push_str(&event.to_line());
+            self.events.push_back(event);
+            added += 1;
+        }
+        if added == 0 {
+            return Ok(0);
+        }
+        // the events are still kept in memory, and saved the next time the
+        // file is rewritten
+        if !has_room_for_auxiliary_write(&self.path, "events") {
+            return Ok(added);
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(lines.as_bytes()).await?;
+        file.flush().await?;
+        self.size_bytes += lines.len() as u64;
+
+        if self.size_bytes > self.max_bytes {
+            self.prune().await?;
+        }
+        Ok(added)
+    }
+
+    /// Drops the oldest events until the store fits in three quarters of its
+    /// cap, so we aren't rewriting the whole file on every insert.
+    async fn prune(&mut self) -> Result<(), std::io::Error> {
+        let target = self.max_bytes / 4 * 3;
+        while self.size_bytes > target {
+            let Some(event) = self.events.pop_front() else {
+                break;
+            };
+            self.keys.remove(&event.key());
+            self.size_bytes = self.size_bytes.saturating_sub(event.to_line().len() as u64);
+        }
+
+        let contents: String = self.events.iter().map(StoredEvent::to_line).collect();
+        let tmp_path = self.path.with_extension("ndjson.tmp");
+        tokio::fs::write(&tmp_path, contents.as_bytes()).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+        self.size_bytes = contents.len() as u64;
+        Ok(())
+    }
+
+    pub fn query(&self, query: &EventQuery) -> EventPage {
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_QUERY_LIMIT)
+            .min(MAX_QUERY_LIMIT);
+        let offset = query.offset.unwrap_or(0);
+        let mut total = 0;
+        let mut events = Vec::new();
+        for event in self.events.iter().rev().filter(|e| query.matches(e)) {
+            if total >= offset && events.len() < limit {
+                events.push(event.clone());
+            }
+            total += 1;
+        }
+        EventPage { events, total }
+    }
+}
+
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    get,
+    path = "/api/events",
+    tag = "Recordings",
+    responses(
+        (status = StatusCode::OK, description = "Success", body = EventPage),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid query parameters")
+    ),
+    params(
+        ("since" = Option<String>, Query, description = "Only return events at or after this RFC 3339 timestamp"),
+        ("severity" = Option<String>, Query, description = "Only return events at least this severe (Informational, Low, Medium or High)"),
+        ("analyzer" = Option<String>, Query, description = "Only return events from the analyzer with this name"),
+        ("limit" = Option<usize>, Query, description = "Maximum number of events to return (default 100, at most 1000)"),
+        ("offset" = Option<usize>, Query, description = "Number of matching events to skip, for paging")
+    ),
+    summary = "Query events",
+    description = "Query every analyzer event this device has recorded across all recordings, newest first."
+))]
+pub async fn get_events(
+    State(state): State<Arc<ServerState>>,
+    Query(query): Query<EventQuery>,
+) -> Result<Json<EventPage>, (StatusCode, String)> {
+    Ok(Json(state.event_store_lock.read().await.query(&query)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn event(
+        recording: &str,
+        packet_num: usize,
+        analyzer: &str,
+        event_type: EventType,
+    ) -> StoredEvent {
+        StoredEvent {
+            timestamp: Some(
+                DateTime::parse_from_rfc3339("2025-01-01T00:00:00Z").unwrap()
+                    + chrono::Duration::seconds(packet_num as i64),
+            ),
+            recording: recording.to_string(),
+            packet_num,
+            analyzer: analyzer.to_string(),
+            event_type,
+            message: format!("{analyzer} at {packet_num}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_insert_is_idempotent() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(EVENT_STORE_FILENAME);
+        let mut store = EventStore::open(&path, EVENT_STORE_MAX_BYTES)
+            .await
+            .unwrap();
+
+        let events = vec![
+            event("1", 10, "Null Cipher", EventType::High),
+            event("1", 10, "IMSI Requested", EventType::Medium),
+        ];
+        assert_eq!(store.insert(events.clone()).await.unwrap(), 2);
+        assert_eq!(store.insert(events).await.unwrap(), 0);
+        assert_eq!(
+            store
+                .insert(vec![event("2", 10, "Null Cipher", EventType::High)])
+                .await
+                .unwrap(),
+            1
+        );
+
+        // reopening from disk sees the same events
+        let reopened = EventStore::open(&path, EVENT_STORE_MAX_BYTES)
+            .await
+            .unwrap();
+        assert_eq!(reopened.query(&EventQuery::default()).total, 3);
+        assert_eq!(reopened.size_bytes(), store.size_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_query_filters() {
+        let dir = TempDir::new().unwrap();
+        let mut store =
+            EventStore::open(dir.path().join(EVENT_STORE_FILENAME), EVENT_STORE_MAX_BYTES)
+                .await
+                .unwrap();
+        store
+            .insert(vec![
+                event("1", 1, "IMSI Requested", EventType::Low),
+                event("1", 2, "Null Cipher", EventType::High),
+                event("1", 3, "IMSI Requested", EventType::Medium),
+                event("2", 4, "Diagnostic", EventType::Informational),
+            ])
+            .await
+            .unwrap();
+
+        let page = store.query(&EventQuery::default());
+        assert_eq!(page.total, 4);
+        // newest first
+        assert_eq!(page.events[0].packet_num, 4);
+
+        let page = store.query(&EventQuery {
+            severity: Some(EventType::Medium),
+            ..Default::default()
+        });
+        assert_eq!(page.total, 2);
+
+        let page = store.query(&EventQuery {
+            analyzer: Some("IMSI Requested".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(page.total, 2);
+
+        let page = store.query(&EventQuery {
+            since: Some(DateTime::parse_from_rfc3339("2025-01-01T00:00:03Z").unwrap()),
+            ..Default::default()
+        });
+        assert_eq!(page.total, 2);
+
+        let page = store.query(&EventQuery {
+            limit: Some(1),
+            offset: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(page.total, 4);
+        assert_eq!(page.events.len(), 1);
+        assert_eq!(page.events[0].packet_num, 3);
+    }
+
+    #[tokio::test]
+    async fn test_prunes_oldest_events() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(EVENT_STORE_FILENAME);
+        let line_len = event("1", 0, "Null Cipher", EventType::High)
+            .to_line()
+            .len() as u64;
+        let mut store = EventStore::open(&path, line_len * 10).await.unwrap();
+        for i in 0..11 {
+            store
+                .insert(vec![event("1", i, "Null Cipher", EventType::High)])
+                .await
+                .unwrap();
+        }
+
+        assert!(store.size_bytes() <= line_len * 10);
+        let page = store.query(&EventQuery::default());
+        assert_eq!(page.events.last().unwrap().packet_num, 11 - page.total);
+        assert_eq!(
+            tokio::fs::metadata(&path).await.unwrap().len(),
+            store.size_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_move_to_merges_events() {
+        let dir = TempDir::new().unwrap();
+        let old_path = dir.path().join("old.ndjson");
+        let new_path = dir.path().join(EVENT_STORE_FILENAME);
+        let mut existing = EventStore::open(&new_path, EVENT_STORE_MAX_BYTES)
+            .await
+            .unwrap();
+        existing
+            .insert(vec![event("1", 1, "Null Cipher", EventType::High)])
+            .await
+            .unwrap();
+
+        let mut store = EventStore::open(&old_path, EVENT_STORE_MAX_BYTES)
+            .await
+            .unwrap();
+        store
+            .insert(vec![
+                event("1", 1, "Null Cipher", EventType::High),
+                event("2", 5, "IMSI Requested", EventType::Medium),
+            ])
+            .await
+            .unwrap();
+        store.move_to(&new_path).await.unwrap();
+        assert_eq!(store.query(&EventQuery::default()).total, 2);
+
+        // new events go to the new file
+        store
+            .insert(vec![event("3", 1, "Null Cipher", EventType::High)])
+            .await
+            .unwrap();
+        let reopened = EventStore::open(&new_path, EVENT_STORE_MAX_BYTES)
+            .await
+            .unwrap();
+        assert_eq!(reopened.query(&EventQuery::default()).total, 3);
+        assert_eq!(reopened.size_bytes(), store.size_bytes());
+    }
+}
@@ -1,10 +1,117 @@
+use std::sync::Arc;
+
 use anyhow::{Result, bail};
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
 use log::{info, warn};
+use serde::Serialize;
 use tokio::process::Command;
+use tokio::sync::RwLock;
 
 use wifi_station::detect_bridge_iface;
 
-use crate::config::Config;
+use crate::config::{Config, parse_config};
+use crate::server::ServerState;
+
+/// Why a port is open in the outbound whitelist
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub enum PortOrigin {
+    /// DHCP, DNS and HTTPS, which are always allowed
+    Builtin,
+    /// The port of the configured ntfy_url
+    NtfyAuto,
+    /// The port live GSMTAP frames are streamed to
+    GsmtapAuto,
+    /// Listed in firewall_allowed_ports
+    UserConfig,
+    /// HTTP, opened while the wifi client is behind a captive portal
+    CaptivePortal,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct AllowedPort {
+    pub protocol: &'static str,
+    /// A port, or a range of ports like "67:68"
+    pub port: String,
+    pub origin: PortOrigin,
+}
+
+impl AllowedPort {
+    fn new(protocol: &'static str, port: impl ToString, origin: PortOrigin) -> Self {
+        AllowedPort {
+            protocol,
+            port: port.to_string(),
+            origin,
+        }
+    }
+}
+
+/// The outcome of the last [apply]
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct FirewallState {
+    /// Whether firewall_restrict_outbound was set
+    pub restrict_outbound: bool,
+    /// Whether the outbound whitelist is in place. This is false if it
+    /// failed to set up, in which case outbound traffic is unrestricted.
+    pub active: bool,
+    /// Why setting up the whitelist failed
+    pub error: Option<String>,
+    pub allowed_ports: Vec<AllowedPort>,
+}
+
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct FirewallStatus {
+    #[serde(flatten)]
+    pub state: FirewallState,
+    /// The output of `iptables -S OUTPUT`, if it could be run
+    pub iptables_rules: Option<String>,
+}
+
+/// The ports the outbound whitelist opens, and why. A port is only listed
+/// once, under the first origin which opens it.
+pub fn allowed_ports(
+    extra_ports: &Option<Vec<u16>>,
+    ntfy_url: &Option<String>,
+    gsmtap_port: Option<u16>,
+) -> Vec<AllowedPort> {
+    let mut ports = vec![
+        AllowedPort::new("udp", "67:68", PortOrigin::Builtin),
+        AllowedPort::new("udp", 53, PortOrigin::Builtin),
+        AllowedPort::new("tcp", 53, PortOrigin::Builtin),
+        AllowedPort::new("tcp", 443, PortOrigin::Builtin),
+    ];
+    let mut add_tcp_port = |port: u16, origin| {
+        let port = port.to_string();
+        if !ports.iter().any(|p| p.protocol == "tcp" && p.port == port) {
+            ports.push(AllowedPort::new("tcp", port, origin));
+        }
+    };
+
+    // only the port is kept, the URL contains the (secret) ntfy topic
+    if let Some(url) = ntfy_url
+        && let Ok(parsed) = url::Url::parse(url)
+        && let Some(port) = parsed.port_or_known_default()
+    {
+        add_tcp_port(port, PortOrigin::NtfyAuto);
+    }
+    for port in extra_ports.iter().flatten() {
+        add_tcp_port(*port, PortOrigin::UserConfig);
+    }
+    if let Some(port) = gsmtap_port
+        && !ports
+            .iter()
+            .any(|p| p.protocol == "udp" && p.port == port.to_string())
+    {
+        ports.push(AllowedPort::new("udp", port, PortOrigin::GsmtapAuto));
+    }
+    ports
+}
 
 async fn run_iptables(args: &[&str]) -> Result<()> {
     let out = Command::new("iptables").args(args).output().await?;
@@ -18,75 +125,427 @@ async fn run_iptables(args: &[&str]) -> Result<()> {
     Ok(())
 }
 
-pub async fn apply(config: &Config) {
+pub async fn apply(config: &Config) -> FirewallState {
+    if config.developer_mode {
+        info!("developer mode, not applying the firewall");
+        return FirewallState::default();
+    }
+
     let _ = Command::new("iptables")
         .args(["-F", "OUTPUT"])
         .output()
         .await;
 
+    let mut state = FirewallState {
+        restrict_outbound: config.firewall_restrict_outbound,
+        ..Default::default()
+    };
     if config.firewall_restrict_outbound {
+        state.allowed_ports = allowed_ports(
+            &config.firewall_allowed_ports,
+            &config.ntfy_url,
+            config.gsmtap_stream_port(),
+        );
         // Fail open on partial setup error: reachability beats restriction when recovery means physical access.
-        match setup_outbound_whitelist(&config.firewall_allowed_ports, &config.ntfy_url).await {
-            Ok(()) => info!("outbound firewall active: allowing DHCP, DNS, HTTPS only"),
-            Err(e) => warn!("firewall setup failed: {e} (fail-open, outbound unrestricted)"),
+        match setup_outbound_whitelist(&state.allowed_ports).await {
+            Ok(()) => {
+                info!("outbound firewall active: allowing DHCP, DNS, HTTPS only");
+                state.active = true;
+            }
+            Err(e) => {
+                warn!("firewall setup failed: {e} (fail-open, outbound unrestricted)");
+                state.error = Some(e.to_string());
+            }
         }
     }
+    state
 }
 
-async fn setup_outbound_whitelist(
-    extra_ports: &Option<Vec<u16>>,
-    ntfy_url: &Option<String>,
-) -> Result<()> {
-    run_iptables(&["-A", "OUTPUT", "-o", "lo", "-j", "ACCEPT"]).await?;
-    run_iptables(&["-A", "OUTPUT", "-o", detect_bridge_iface(), "-j", "ACCEPT"]).await?;
-    run_iptables(&[
-        "-A",
+/// The iptables arguments which build the outbound whitelist, in order:
+/// loopback, the wifi bridge and existing connections, then each allowed
+/// port, and finally a DROP for everything else.
+fn whitelist_rules(allowed_ports: &[AllowedPort], bridge_iface: &str) -> Vec<Vec<String>> {
+    let mut rules: Vec<Vec<&str>> = vec![
+        vec!["-A", "OUTPUT", "-o", "lo", "-j", "ACCEPT"],
+        vec!["-A", "OUTPUT", "-o", bridge_iface, "-j", "ACCEPT"],
+        vec![
+            "-A",
+            "OUTPUT",
+            "-m",
+            "state",
+            "--state",
+            "ESTABLISHED,RELATED",
+            "-j",
+            "ACCEPT",
+        ],
+    ];
+    for allowed in allowed_ports {
+        rules.push(vec![
+            "-A",
+            "OUTPUT",
+            "-p",
+            allowed.protocol,
+            "--dport",
+            &allowed.port,
+            "-j",
+            "ACCEPT",
+        ]);
+    }
+    rules.push(vec!["-A", "OUTPUT", "-j", "DROP"]);
+    rules
+        .into_iter()
+        .map(|rule| rule.into_iter().map(String::from).collect())
+        .collect()
+}
+
+async fn setup_outbound_whitelist(allowed_ports: &[AllowedPort]) -> Result<()> {
+    for rule in whitelist_rules(allowed_ports, detect_bridge_iface()) {
+        let args: Vec<&str> = rule.iter().map(String::as_str).collect();
+        run_iptables(&args).await?;
+    }
+    for allowed in allowed_ports {
+        match allowed.origin {
+            PortOrigin::NtfyAuto => info!("firewall: auto-allowed port {} for ntfy", allowed.port),
+            PortOrigin::GsmtapAuto => {
+                info!("firewall: auto-allowed port {} for GSMTAP", allowed.port)
+            }
+            _ => {}
+        }
+    }
+
+    let _ = tokio::fs::write("/proc/sys/net/bridge/bridge-nf-call-iptables", "0").await;
+
+    Ok(())
+}
+
+const CAPTIVE_PORTAL_PORT: u16 = 80;
+
+/// The iptables arguments which add (`-I`) or remove (`-D`) the captive
+/// portal's HTTP rule. It only matches the wifi client's interface, since
+/// that's where the portal is, and as an OUTPUT rule it doesn't touch
+/// traffic forwarded for the hotspot's clients.
+fn captive_portal_rule<'a>(action: &'a str, port: &'a str) -> [&'a str; 10] {
+    [
+        action,
         "OUTPUT",
-        "-m",
-        "state",
-        "--state",
-        "ESTABLISHED,RELATED",
+        "-o",
+        wifi_station::STA_IFACE,
+        "-p",
+        "tcp",
+        "--dport",
+        port,
         "-j",
         "ACCEPT",
-    ])
-    .await?;
-    run_iptables(&[
-        "-A", "OUTPUT", "-p", "udp", "--dport", "67:68", "-j", "ACCEPT",
-    ])
-    .await?;
-    run_iptables(&["-A", "OUTPUT", "-p", "udp", "--dport", "53", "-j", "ACCEPT"]).await?;
-    run_iptables(&["-A", "OUTPUT", "-p", "tcp", "--dport", "53", "-j", "ACCEPT"]).await?;
-    run_iptables(&[
-        "-A", "OUTPUT", "-p", "tcp", "--dport", "443", "-j", "ACCEPT",
-    ])
-    .await?;
+    ]
+}
 
-    if let Some(url) = ntfy_url
-        && let Ok(parsed) = url::Url::parse(url)
-        && let Some(port) = parsed.port_or_known_default()
-        && port != 443
+/// Opens or closes outbound HTTP on the wifi client's interface, which
+/// captive portals need for their sign-in page. This does nothing if the
+/// whitelist isn't active, or if HTTP is already open for some other reason.
+pub async fn set_captive_portal_http(
+    firewall_state: &RwLock<FirewallState>,
+    allow: bool,
+) -> Result<()> {
+    let mut state = firewall_state.write().await;
+    if !state.active {
+        return Ok(());
+    }
+    let port = CAPTIVE_PORTAL_PORT.to_string();
+    let existing = state
+        .allowed_ports
+        .iter()
+        .position(|p| p.protocol == "tcp" && p.port == port);
+    match existing {
+        None if allow => {
+            // insert rather than append, so it comes before the final DROP
+            run_iptables(&captive_portal_rule("-I", &port)).await?;
+            state.allowed_ports.push(AllowedPort::new(
+                "tcp",
+                CAPTIVE_PORTAL_PORT,
+                PortOrigin::CaptivePortal,
+            ));
+            info!("firewall: allowed port {port} for captive portal");
+        }
+        Some(index) if !allow && state.allowed_ports[index].origin == PortOrigin::CaptivePortal => {
+            run_iptables(&captive_portal_rule("-D", &port)).await?;
+            state.allowed_ports.remove(index);
+            info!("firewall: closed port {port} again, no captive portal");
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn iptables_rules() -> Option<String> {
+    let out = Command::new("iptables")
+        .args(["-S", "OUTPUT"])
+        .output()
+        .await
+        .ok()?;
+    out.status
+        .success()
+        .then(|| String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    get,
+    path = "/api/firewall",
+    tag = "Configuration",
+    responses(
+        (status = StatusCode::OK, description = "Success", body = FirewallStatus)
+    ),
+    summary = "Firewall status",
+    description = "Show whether the outbound firewall is active, which ports it allows and why, and the iptables OUTPUT rules actually in place."
+))]
+pub async fn get_firewall(State(state): State<Arc<ServerState>>) -> Json<FirewallStatus> {
+    Json(FirewallStatus {
+        state: state.firewall_state.read().await.clone(),
+        iptables_rules: iptables_rules().await,
+    })
+}
+
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    post,
+    path = "/api/firewall/reapply",
+    tag = "Configuration",
+    responses(
+        (status = StatusCode::OK, description = "Firewall reapplied", body = FirewallState),
+        (status = StatusCode::FORBIDDEN, description = "System is in debug mode"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to read the config file")
+    ),
+    summary = "Reapply firewall",
+    description = "Re-read the firewall settings from the config file and rebuild the outbound firewall, without restarting Rayhunter."
+))]
+pub async fn reapply_firewall(
+    State(state): State<Arc<ServerState>>,
+) -> Result<Json<FirewallState>, (StatusCode, String)> {
+    if state.config.debug_mode {
+        return Err((StatusCode::FORBIDDEN, "server is in debug mode".to_string()));
+    }
+    let config = parse_config(&state.config_path).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("failed to read config file: {e}"),
+        )
+    })?;
+    *state.firewall_state.write().await = apply(&config).await;
+    // rebuilding the whitelist closed HTTP again, but the captive portal may
+    // still need it
+    if *state.captive_portal.read().await
+        && let Err(e) = set_captive_portal_http(&state.firewall_state, true).await
     {
-        let port_str = port.to_string();
-        run_iptables(&[
-            "-A", "OUTPUT", "-p", "tcp", "--dport", &port_str, "-j", "ACCEPT",
-        ])
-        .await?;
-        info!("firewall: auto-allowed port {port} for ntfy");
-    }
-
-    if let Some(ports) = extra_ports {
-        for port in ports {
-            let port_str = port.to_string();
-            run_iptables(&[
-                "-A", "OUTPUT", "-p", "tcp", "--dport", &port_str, "-j", "ACCEPT",
-            ])
-            .await?;
+        warn!("couldn't reopen HTTP for captive portal: {e}");
+    }
+    Ok(Json(state.firewall_state.read().await.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origins(ports: &[AllowedPort]) -> Vec<(&'static str, &str, PortOrigin)> {
+        ports
+            .iter()
+            .map(|p| (p.protocol, p.port.as_str(), p.origin))
+            .collect()
+    }
+
+    #[test]
+    fn test_builtin_ports() {
+        let ports = allowed_ports(&None, &None, None);
+        assert!(ports.iter().all(|p| p.origin == PortOrigin::Builtin));
+        assert_eq!(
+            origins(&ports),
+            vec![
+                ("udp", "67:68", PortOrigin::Builtin),
+                ("udp", "53", PortOrigin::Builtin),
+                ("tcp", "53", PortOrigin::Builtin),
+                ("tcp", "443", PortOrigin::Builtin),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_port_origins() {
+        let ports = allowed_ports(
+            &Some(vec![22, 8080, 443]),
+            &Some("http://ntfy.example.com:8080/secret_topic".to_string()),
+            None,
+        );
+        assert_eq!(
+            origins(&ports[4..]),
+            vec![
+                ("tcp", "8080", PortOrigin::NtfyAuto),
+                ("tcp", "22", PortOrigin::UserConfig),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ntfy_on_https_is_builtin() {
+        let ports = allowed_ports(
+            &None,
+            &Some("https://ntfy.sh/secret_topic".to_string()),
+            None,
+        );
+        assert_eq!(ports.len(), 4);
+    }
+
+    #[test]
+    fn test_gsmtap_port_is_allowed_when_streaming() {
+        let mut config = Config {
+            gsmtap_stream_destination: "192.168.1.10:5000".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.gsmtap_stream_port(), None);
+
+        config.gsmtap_stream_enabled = true;
+        let ports = allowed_ports(&None, &None, config.gsmtap_stream_port());
+        assert_eq!(
+            origins(&ports[4..]),
+            vec![("udp", "5000", PortOrigin::GsmtapAuto)]
+        );
+
+        // DNS is already allowed, so a destination on port 53 adds nothing
+        assert_eq!(allowed_ports(&None, &None, Some(53)).len(), 4);
+    }
+
+    /// The port each ACCEPT rule opens, like "tcp/443"
+    fn accepted_ports(rules: &[Vec<String>]) -> Vec<String> {
+        rules
+            .iter()
+            .filter(|rule| rule.ends_with(&["-j".to_string(), "ACCEPT".to_string()]))
+            .filter_map(|rule| {
+                let protocol = &rule[rule.iter().position(|arg| arg == "-p")? + 1];
+                let port = &rule[rule.iter().position(|arg| arg == "--dport")? + 1];
+                Some(format!("{protocol}/{port}"))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_whitelist_rules() {
+        let rules = whitelist_rules(&allowed_ports(&None, &None, None), "bridge0");
+        let accepted = accepted_ports(&rules);
+        for port in ["udp/53", "tcp/53", "tcp/443"] {
+            assert!(accepted.contains(&port.to_string()), "{port} not accepted");
         }
+        // HTTP is only opened for captive portals, never by the whitelist
+        assert!(!accepted.iter().any(|port| port.ends_with("/80")));
+        assert_eq!(rules.last().unwrap(), &["-A", "OUTPUT", "-j", "DROP"]);
+        assert_eq!(
+            rules
+                .iter()
+                .filter(|rule| rule.contains(&"DROP".to_string()))
+                .count(),
+            1
+        );
     }
 
-    run_iptables(&["-A", "OUTPUT", "-j", "DROP"]).await?;
+    #[test]
+    fn test_whitelist_rules_end_in_drop() {
+        let ports = allowed_ports(
+            &Some(vec![22]),
+            &Some("http://ntfy.example.com:8080/topic".to_string()),
+            Some(5000),
+        );
+        let rules = whitelist_rules(&ports, "bridge0");
+        assert_eq!(
+            accepted_ports(&rules),
+            vec![
+                "udp/67:68",
+                "udp/53",
+                "tcp/53",
+                "tcp/443",
+                "tcp/8080",
+                "tcp/22",
+                "udp/5000"
+            ]
+        );
+        assert_eq!(rules.last().unwrap(), &["-A", "OUTPUT", "-j", "DROP"]);
+    }
 
-    let _ = tokio::fs::write("/proc/sys/net/bridge/bridge-nf-call-iptables", "0").await;
+    #[test]
+    fn test_captive_portal_rule_is_wifi_client_only() {
+        let insert = captive_portal_rule("-I", "80");
+        assert_eq!(
+            insert,
+            [
+                "-I",
+                "OUTPUT",
+                "-o",
+                wifi_station::STA_IFACE,
+                "-p",
+                "tcp",
+                "--dport",
+                "80",
+                "-j",
+                "ACCEPT"
+            ]
+        );
+        // the rule has to be deleted exactly as it was inserted
+        assert_eq!(captive_portal_rule("-D", "80")[1..], insert[1..]);
+    }
 
-    Ok(())
+    #[tokio::test]
+    async fn test_captive_portal_http_needs_active_whitelist() {
+        // without the whitelist in place there's nothing to open, and no
+        // iptables to run
+        let firewall_state = RwLock::new(FirewallState {
+            restrict_outbound: true,
+            active: false,
+            error: Some("iptables failed".to_string()),
+            allowed_ports: allowed_ports(&None, &None, None),
+        });
+        set_captive_portal_http(&firewall_state, true)
+            .await
+            .unwrap();
+        assert_eq!(firewall_state.read().await.allowed_ports.len(), 4);
+    }
+
+    #[test]
+    fn test_status_shape_omits_ntfy_url() {
+        let config = Config {
+            ntfy_url: Some("http://ntfy.example.com:8080/secret_topic".to_string()),
+            ..Default::default()
+        };
+        let status = FirewallStatus {
+            state: FirewallState {
+                restrict_outbound: true,
+                active: true,
+                error: None,
+                allowed_ports: allowed_ports(
+                    &config.firewall_allowed_ports,
+                    &config.ntfy_url,
+                    config.gsmtap_stream_port(),
+                ),
+            },
+            iptables_rules: None,
+        };
+        let json = serde_json::to_value(&status).unwrap();
+        let mut keys: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![
+                "active",
+                "allowed_ports",
+                "error",
+                "iptables_rules",
+                "restrict_outbound"
+            ]
+        );
+        assert_eq!(
+            json["allowed_ports"][4],
+            serde_json::json!({"protocol": "tcp", "port": "8080", "origin": "ntfy_auto"})
+        );
+        let serialized = json.to_string();
+        assert!(!serialized.contains("secret_topic"));
+        assert!(!serialized.contains("ntfy.example.com"));
+    }
 }
@@ -0,0 +1,230 @@
+//! Streams GSMTAP frames over UDP as they're read from the diag device, so
+//! Wireshark can follow along live. By default they go to the device itself,
+//! but they can also be sent to e.g. a laptop connected to the hotspot.
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use log::{debug, error, info, warn};
+use rayhunter::diag::{DataType, MessagesContainer};
+use rayhunter::gsmtap_parser;
+use tokio::net::UdpSocket;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use crate::diag_multiplexer::DiagSubscriber;
+
+/// The port registered for GSMTAP, and the one Wireshark listens on
+pub const GSMTAP_PORT: u16 = 4729;
+
+/// Splits a destination like "192.168.1.10:4729", "[fe80::1]:4729" or
+/// "laptop.local" into its host and port. The port defaults to
+/// [GSMTAP_PORT].
+pub fn parse_destination(destination: &str) -> Result<(String, u16), String> {
+    let destination = destination.trim();
+    let (host, port) = if let Some(rest) = destination.strip_prefix('[') {
+        let (host, rest) = rest
+            .split_once(']')
+            .ok_or_else(|| format!("missing ']' in {destination:?}"))?;
+        match rest {
+            "" => (host, None),
+            _ => match rest.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None => return Err(format!("unexpected {rest:?} after ']'")),
+            },
+        }
+    } else if destination.parse::<Ipv6Addr>().is_ok() {
+        (destination, None)
+    } else {
+        match destination.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (destination, None),
+        }
+    };
+
+    if host.is_empty() || host.contains(|c: char| c.is_whitespace() || c == '/') {
+        return Err(format!("invalid host {host:?}"));
+    }
+    let port = match port {
+        Some(port) => match port.parse::<u16>() {
+            Ok(port) if port != 0 => port,
+            _ => return Err(format!("invalid port {port:?}")),
+        },
+        None => GSMTAP_PORT,
+    };
+    Ok((host.to_string(), port))
+}
+
+/// Sends GSMTAP frames to a single destination
+pub struct GsmtapSender {
+    socket: UdpSocket,
+    destination: SocketAddr,
+    // so an unreachable destination is only logged once, rather than once
+    // per frame
+    send_failed: bool,
+}
+
+impl GsmtapSender {
+    /// Resolves `destination` and opens a socket to send to it.
+    pub async fn connect(destination: &str) -> Result<Self, String> {
+        let (host, port) = parse_destination(destination)?;
+        let destination = tokio::net::lookup_host((host.as_str(), port))
+            .await
+            .map_err(|e| format!("couldn't resolve {host}: {e}"))?
+            .next()
+            .ok_or_else(|| format!("{host} has no addresses"))?;
+        let bind_addr: SocketAddr = match destination {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(bind_addr)
+            .await
+            .map_err(|e| format!("couldn't open a UDP socket: {e}"))?;
+        Ok(GsmtapSender {
+            socket,
+            destination,
+            send_failed: false,
+        })
+    }
+
+    pub fn destination(&self) -> SocketAddr {
+        self.destination
+    }
+
+    /// Sends one frame. Failures are logged rather than returned, since a
+    /// listener which isn't there yet shouldn't stop the stream.
+    pub async fn send(&mut self, frame: &[u8]) {
+        match self.socket.send_to(frame, self.destination).await {
+            Ok(_) => self.send_failed = false,
+            Err(e) if !self.send_failed => {
+                warn!("failed to send GSMTAP to {}: {e}", self.destination);
+                self.send_failed = true;
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Sends every message in the container which has a GSMTAP
+    /// representation.
+    pub async fn send_container(&mut self, container: MessagesContainer) {
+        if container.data_type != DataType::UserSpace {
+            return;
+        }
+        for maybe_msg in container.into_messages() {
+            let gsmtap_msg = match maybe_msg.map(gsmtap_parser::parse) {
+                Ok(Ok(Some((_, gsmtap_msg)))) => gsmtap_msg,
+                Ok(Ok(None)) => continue,
+                Ok(Err(e)) => {
+                    debug!("error converting message to GSMTAP: {e:?}");
+                    continue;
+                }
+                Err(e) => {
+                    debug!("error parsing message: {e:?}");
+                    continue;
+                }
+            };
+            match gsmtap_msg.to_udp_payload() {
+                Ok(frame) => self.send(&frame).await,
+                Err(e) => debug!("error serializing GSMTAP message: {e}"),
+            }
+        }
+    }
+}
+
+/// Streams every container from `subscriber` to `destination` until
+/// shutdown. If the destination can't be resolved, this logs why and gives
+/// up without affecting anything else.
+pub fn run_gsmtap_stream(
+    task_tracker: &TaskTracker,
+    mut subscriber: DiagSubscriber,
+    destination: String,
+    shutdown_token: CancellationToken,
+) {
+    task_tracker.spawn(async move {
+        let mut sender = match GsmtapSender::connect(&destination).await {
+            Ok(sender) => sender,
+            Err(e) => {
+                error!("not streaming GSMTAP to {destination:?}: {e}");
+                return;
+            }
+        };
+        info!("streaming GSMTAP to {}", sender.destination());
+        loop {
+            tokio::select! {
+                _ = shutdown_token.cancelled() => break,
+                maybe_container = subscriber.recv() => match maybe_container {
+                    Some(container) => sender.send_container(container).await,
+                    None => break,
+                },
+            }
+        }
+        info!("GSMTAP stream exiting...");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayhunter::gsmtap::{GsmtapHeader, GsmtapMessage, GsmtapType, LteRrcSubtype};
+
+    #[test]
+    fn test_parse_destination() {
+        assert_eq!(
+            parse_destination("127.0.0.1"),
+            Ok(("127.0.0.1".to_string(), 4729))
+        );
+        assert_eq!(
+            parse_destination(" 192.168.1.10:5000 "),
+            Ok(("192.168.1.10".to_string(), 5000))
+        );
+        assert_eq!(
+            parse_destination("laptop.local"),
+            Ok(("laptop.local".to_string(), 4729))
+        );
+        assert_eq!(
+            parse_destination("[fe80::1]:5000"),
+            Ok(("fe80::1".to_string(), 5000))
+        );
+        assert_eq!(
+            parse_destination("[fe80::1]"),
+            Ok(("fe80::1".to_string(), 4729))
+        );
+        assert_eq!(
+            parse_destination("fe80::1"),
+            Ok(("fe80::1".to_string(), 4729))
+        );
+
+        for invalid in [
+            "",
+            ":4729",
+            "host:",
+            "host:0",
+            "host:70000",
+            "[fe80::1",
+            "a b",
+        ] {
+            assert!(
+                parse_destination(invalid).is_err(),
+                "{invalid:?} should be invalid"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_frames_arrive_at_destination() {
+        let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let destination = listener.local_addr().unwrap().to_string();
+        let mut sender = GsmtapSender::connect(&destination).await.unwrap();
+        assert_eq!(sender.destination(), listener.local_addr().unwrap());
+
+        let frame = GsmtapMessage {
+            header: GsmtapHeader::new(GsmtapType::LteRrc(LteRrcSubtype::DlDcch)),
+            payload: vec![0x28, 0x02],
+        }
+        .to_udp_payload()
+        .unwrap();
+        sender.send(&frame).await;
+
+        let mut buf = [0; 1024];
+        let len = listener.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], frame.as_slice());
+    }
+}
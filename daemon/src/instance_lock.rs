@@ -0,0 +1,217 @@
+//! Makes sure only one copy of the daemon runs at a time. Two daemons would
+//! fight over the diag device and the QMDL store, so a second copy refuses to
+//! start while the first one still holds the lock.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use thiserror::Error;
+
+const INSTANCE_LOCK_FILENAME: &str = "daemon.lock";
+
+/// The exit code used when another daemon already holds the lock, so init
+/// scripts can tell it apart from a crash
+pub const ALREADY_RUNNING_EXIT_CODE: i32 = 3;
+
+#[derive(Error, Debug)]
+pub enum InstanceLockError {
+    #[error("another rayhunter daemon is already running with PID {0}")]
+    AlreadyRunning(u32),
+    #[error("couldn't lock {0:?}: {1}")]
+    Io(PathBuf, std::io::Error),
+}
+
+/// Who the PID recorded in a lock file we couldn't lock belongs to
+#[derive(Debug, PartialEq)]
+pub enum LockHolder {
+    /// A process which is still running
+    Alive(u32),
+    /// A process which has exited, or a file without a usable PID. The lock
+    /// can only still be held by a process which inherited the file
+    /// descriptor, so it's safe to take over.
+    Stale(Option<u32>),
+}
+
+/// Holds the instance lock for as long as it's alive. The kernel releases the
+/// lock when the daemon exits, however it exits.
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+    pid: u32,
+}
+
+/// Returns the path of the instance lock file, which lives next to the config file.
+pub fn instance_lock_path<P: AsRef<Path>>(config_path: P) -> PathBuf {
+    config_path.as_ref().with_file_name(INSTANCE_LOCK_FILENAME)
+}
+
+pub fn classify_holder(contents: &str, is_alive: impl Fn(u32) -> bool) -> LockHolder {
+    match contents.trim().parse::<u32>() {
+        Ok(pid) if pid > 0 && is_alive(pid) => LockHolder::Alive(pid),
+        Ok(pid) if pid > 0 => LockHolder::Stale(Some(pid)),
+        _ => LockHolder::Stale(None),
+    }
+}
+
+fn process_is_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // signal 0 only checks whether the process exists. EPERM means it does,
+    // but belongs to someone else.
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+fn try_flock(file: &File) -> std::io::Result<bool> {
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    if err.kind() == std::io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(err)
+    }
+}
+
+fn open_lock_file(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+impl InstanceLock {
+    /// Takes the lock at `path` and records our PID in it. If the lock is
+    /// held by a process which no longer exists, the stale file is replaced.
+    pub fn acquire<P: AsRef<Path>>(path: P) -> Result<Self, InstanceLockError> {
+        Self::acquire_with(path.as_ref(), process_is_alive)
+    }
+
+    fn acquire_with(
+        path: &Path,
+        is_alive: impl Fn(u32) -> bool,
+    ) -> Result<Self, InstanceLockError> {
+        let io_err = |e| InstanceLockError::Io(path.to_path_buf(), e);
+        let mut file = open_lock_file(path).map_err(io_err)?;
+        let mut previous = String::new();
+        // the file may not be valid UTF-8 if something else wrote to it,
+        // which just makes its contents unusable
+        let _ = file.read_to_string(&mut previous);
+
+        if !try_flock(&file).map_err(io_err)? {
+            match classify_holder(&previous, &is_alive) {
+                LockHolder::Alive(pid) => return Err(InstanceLockError::AlreadyRunning(pid)),
+                LockHolder::Stale(pid) => {
+                    warn!("removing stale instance lock {path:?} held for PID {pid:?}");
+                    // the lock belongs to the old file, so a new file at the
+                    // same path can be locked even if it's still held
+                    std::fs::remove_file(path).map_err(io_err)?;
+                    file = open_lock_file(path).map_err(io_err)?;
+                    if !try_flock(&file).map_err(io_err)? {
+                        // someone else took over the stale lock first
+                        let mut contents = String::new();
+                        let _ = file.read_to_string(&mut contents);
+                        let pid = contents.trim().parse().unwrap_or(0);
+                        return Err(InstanceLockError::AlreadyRunning(pid));
+                    }
+                }
+            }
+        } else if let LockHolder::Stale(Some(pid)) = classify_holder(&previous, &is_alive) {
+            warn!("previous daemon with PID {pid} exited without releasing {path:?}");
+        }
+
+        let pid = std::process::id();
+        file.set_len(0).map_err(io_err)?;
+        file.rewind().map_err(io_err)?;
+        writeln!(file, "{pid}").map_err(io_err)?;
+        Ok(InstanceLock { _file: file, pid })
+    }
+
+    /// The PID recorded in the lock, which is always our own
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn lock_path(dir: &TempDir) -> PathBuf {
+        instance_lock_path(dir.path().join("config.toml"))
+    }
+
+    #[test]
+    fn test_classify_holder() {
+        assert_eq!(classify_holder("1234\n", |_| true), LockHolder::Alive(1234));
+        assert_eq!(
+            classify_holder("1234\n", |_| false),
+            LockHolder::Stale(Some(1234))
+        );
+        assert_eq!(classify_holder("", |_| true), LockHolder::Stale(None));
+        assert_eq!(
+            classify_holder("garbage", |_| true),
+            LockHolder::Stale(None)
+        );
+        assert_eq!(classify_holder("0", |_| true), LockHolder::Stale(None));
+    }
+
+    #[test]
+    fn test_acquire_records_pid() {
+        let dir = TempDir::new().unwrap();
+        let lock = InstanceLock::acquire(lock_path(&dir)).unwrap();
+        assert_eq!(lock.pid(), std::process::id());
+        let contents = std::fs::read_to_string(lock_path(&dir)).unwrap();
+        assert_eq!(contents.trim(), std::process::id().to_string());
+    }
+
+    #[test]
+    fn test_second_instance_is_refused() {
+        let dir = TempDir::new().unwrap();
+        let _lock = InstanceLock::acquire(lock_path(&dir)).unwrap();
+        match InstanceLock::acquire(lock_path(&dir)) {
+            Err(InstanceLockError::AlreadyRunning(pid)) => assert_eq!(pid, std::process::id()),
+            other => panic!("expected AlreadyRunning, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_lock_is_released_on_drop() {
+        let dir = TempDir::new().unwrap();
+        drop(InstanceLock::acquire(lock_path(&dir)).unwrap());
+        InstanceLock::acquire(lock_path(&dir)).unwrap();
+    }
+
+    #[test]
+    fn test_unlocked_file_from_dead_daemon_is_reused() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(lock_path(&dir), "4000000\n").unwrap();
+        let lock = InstanceLock::acquire_with(&lock_path(&dir), |_| false).unwrap();
+        let contents = std::fs::read_to_string(lock_path(&dir)).unwrap();
+        assert_eq!(contents.trim(), lock.pid().to_string());
+    }
+
+    #[test]
+    fn test_held_lock_with_dead_pid_is_taken_over() {
+        let dir = TempDir::new().unwrap();
+        // a lock still held through an inherited file descriptor, after the
+        // daemon which wrote its PID has exited
+        let mut held = open_lock_file(&lock_path(&dir)).unwrap();
+        assert!(try_flock(&held).unwrap());
+        writeln!(held, "4000000").unwrap();
+
+        let lock = InstanceLock::acquire_with(&lock_path(&dir), |_| false).unwrap();
+        let contents = std::fs::read_to_string(lock_path(&dir)).unwrap();
+        assert_eq!(contents.trim(), lock.pid().to_string());
+    }
+}
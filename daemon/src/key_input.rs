@@ -8,6 +8,7 @@ use tokio_util::task::TaskTracker;
 
 use crate::config;
 use crate::diag::DiagDeviceCtrlMessage;
+use crate::display::DisplayState;
 
 #[derive(Debug)]
 enum Event {
@@ -21,9 +22,12 @@ pub fn run_key_input_thread(
     task_tracker: &TaskTracker,
     config: &config::Config,
     diag_tx: Sender<DiagDeviceCtrlMessage>,
+    ui_update_tx: Sender<DisplayState>,
     cancellation_token: CancellationToken,
 ) {
-    if config.key_input_mode == 0 {
+    let restart_gesture = config.key_input_mode != 0;
+    let wake_display = config.display_can_sleep();
+    if !restart_gesture && !wake_display {
         return;
     }
 
@@ -71,6 +75,15 @@ pub fn run_key_input_thread(
 
             match event {
                 Event::KeyUp => {
+                    if wake_display
+                        && let Err(e) = ui_update_tx.send(DisplayState::ButtonPressed).await
+                    {
+                        error!("Failed to wake display: {e}");
+                    }
+                    if !restart_gesture {
+                        continue;
+                    }
+
                     if let Some(last_keyup_instant) = last_keyup {
                         let elapsed = now.duration_since(last_keyup_instant);
 
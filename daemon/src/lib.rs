@@ -1,17 +1,42 @@
+pub mod aggregate;
 pub mod analysis;
+pub mod ap_clients;
 pub mod battery;
+pub mod captive_portal;
+pub mod capture_rate;
+pub mod clock_jump;
+pub mod compression;
 pub mod config;
 pub mod crypto_provider;
+pub mod daemon_state;
+pub mod decoded;
 pub mod diag;
+pub mod diag_multiplexer;
+pub mod disk_space;
 pub mod display;
 pub mod error;
+pub mod event_store;
 pub mod firewall;
+pub mod gsmtap_stream;
+pub mod instance_lock;
 pub mod key_input;
+pub mod live_tail;
+pub mod location;
+pub mod logging;
+pub mod mounts;
 pub mod notifications;
 pub mod pcap;
+pub mod power;
 pub mod qmdl_store;
+pub mod routing;
+pub mod self_test;
 pub mod server;
+pub mod shutdown;
 pub mod stats;
+pub mod wifi_scan;
+pub mod wifi_signal;
+pub mod wifi_verify;
+pub mod wireshark;
 
 #[cfg(feature = "apidocs")]
 use utoipa::OpenApi;
@@ -30,20 +55,38 @@ use utoipa::OpenApi;
     paths(
         pcap::get_pcap,
         server::get_qmdl,
+        diag::get_live_qmdl,
+        live_tail::get_live_tail,
         server::get_zip,
         stats::get_system_stats,
         stats::get_qmdl_manifest,
         stats::get_log,
         diag::start_recording,
         diag::stop_recording,
+        diag::pause_recording,
+        diag::resume_recording,
         diag::delete_recording,
+        server::set_recording_notes,
         diag::delete_all_recordings,
         diag::get_analysis_report,
+        wireshark::get_wireshark_hints,
         analysis::get_analysis_status,
         analysis::start_analysis,
+        analysis::get_analysis_summary,
+        aggregate::aggregate_analysis,
+        analysis::import_qmdl,
+        decoded::get_decoded_messages,
+        event_store::get_events,
+        ap_clients::get_ap_clients,
         server::get_config,
+        server::get_analyzers,
         server::set_config,
         server::test_notification,
+        firewall::get_firewall,
+        firewall::reapply_firewall,
+        power::restart_daemon,
+        power::shutdown_daemon,
+        power::reboot_device,
         server::get_time,
         server::set_time_offset,
         server::debug_set_display_state
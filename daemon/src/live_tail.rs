@@ -0,0 +1,299 @@
+//! A short, in-memory history of the messages the current recording has
+//! decoded, so the web UI can show what's being captured right now without
+//! downloading a PCAP. Only a summary of each message is kept, and never
+//! more than a fixed number of them, so memory use stays bounded however
+//! long the daemon runs. Summarizing every message isn't free, so it only
+//! happens while someone is watching.
+
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::Json;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::header::ACCEPT;
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, FixedOffset};
+use futures::StreamExt;
+use log::warn;
+use rayhunter::analysis::analyzer::{AnalysisRow, EventType};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::server::ServerState;
+
+/// The most entries kept, whatever the config asks for
+pub const MAX_LIVE_TAIL_SIZE: usize = 5000;
+
+/// How many new entries an SSE client may fall behind before it starts
+/// missing them.
+const LIVE_TAIL_CHANNEL_CAPACITY: usize = 64;
+
+/// How long after the list was last fetched to keep filling it, so a client
+/// polling it keeps seeing new messages
+const LIVE_TAIL_WATCH_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct LiveTailEvent {
+    /// The name of the analyzer which emitted the event
+    pub analyzer: String,
+    pub event_type: EventType,
+    pub message: String,
+}
+
+/// A decoded message, without its contents
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct LiveTailEntry {
+    /// The index of the message within the current recording
+    pub packet_num: usize,
+    #[cfg_attr(feature = "apidocs", schema(value_type = Option<String>))]
+    pub packet_timestamp: Option<DateTime<FixedOffset>>,
+    /// e.g. "LTE RRC", "LTE NAS" or "UMTS RRC"
+    #[cfg_attr(feature = "apidocs", schema(value_type = String))]
+    pub protocol: &'static str,
+    /// The logical channel the message was sent on, for RRC messages
+    #[cfg_attr(feature = "apidocs", schema(value_type = Option<String>))]
+    pub channel: Option<&'static str>,
+    /// e.g. "RRCConnectionRelease"
+    pub message_type: Option<String>,
+    /// The events analyzers emitted for this message
+    pub events: Vec<LiveTailEvent>,
+}
+
+impl LiveTailEntry {
+    /// Converts an analysis row into an entry, if the harness summarized its
+    /// message. The i-th event in a row always belongs to the i-th analyzer.
+    pub fn from_row(
+        row: &AnalysisRow,
+        packet_num: usize,
+        analyzer_names: &[String],
+    ) -> Option<LiveTailEntry> {
+        let summary = row.message_summary.as_ref()?;
+        let events = row
+            .events
+            .iter()
+            .zip(analyzer_names)
+            .filter_map(|(event, analyzer)| {
+                let event = event.as_ref()?;
+                Some(LiveTailEvent {
+                    analyzer: analyzer.clone(),
+                    event_type: event.event_type,
+                    message: event.message.clone(),
+                })
+            })
+            .collect();
+        Some(LiveTailEntry {
+            packet_num,
+            packet_timestamp: row.packet_timestamp,
+            protocol: summary.protocol,
+            channel: summary.channel,
+            message_type: summary.message_type.clone(),
+            events,
+        })
+    }
+}
+
+/// A ring buffer of the most recent [LiveTailEntry]s, which also passes each
+/// new entry on to any SSE clients. Cloning it gives another handle to the
+/// same buffer.
+#[derive(Clone)]
+pub struct LiveTail {
+    capacity: usize,
+    entries: Arc<Mutex<VecDeque<LiveTailEntry>>>,
+    sender: broadcast::Sender<LiveTailEntry>,
+    watched_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl LiveTail {
+    /// Creates a buffer holding at most `capacity` entries, up to
+    /// [MAX_LIVE_TAIL_SIZE]. A capacity of 0 disables the live tail.
+    pub fn new(capacity: usize) -> Self {
+        LiveTail {
+            capacity: capacity.min(MAX_LIVE_TAIL_SIZE),
+            entries: Arc::new(Mutex::new(VecDeque::new())),
+            sender: broadcast::channel(LIVE_TAIL_CHANNEL_CAPACITY).0,
+            watched_until: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Whether anyone is following the live tail, i.e. an SSE client is
+    /// connected or the list was fetched recently. Nothing needs to be
+    /// summarized otherwise.
+    pub fn is_watched(&self) -> bool {
+        self.is_watched_at(Instant::now())
+    }
+
+    fn is_watched_at(&self, now: Instant) -> bool {
+        self.is_enabled()
+            && (self.sender.receiver_count() > 0
+                || self
+                    .watched_until
+                    .lock()
+                    .unwrap()
+                    .is_some_and(|until| now < until))
+    }
+
+    fn mark_watched(&self, now: Instant) {
+        *self.watched_until.lock().unwrap() = Some(now + LIVE_TAIL_WATCH_WINDOW);
+    }
+
+    /// Forgets every entry, e.g. when a new recording starts.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Adds an entry, evicting the oldest one if the buffer is full.
+    pub fn push(&self, entry: LiveTailEntry) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry.clone());
+        // an error here just means no SSE client is listening
+        let _ = self.sender.send(entry);
+    }
+
+    /// The buffered entries, newest first
+    pub fn newest_first(&self) -> Vec<LiveTailEntry> {
+        self.entries.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    /// The buffered entries, oldest first, along with a receiver for every
+    /// entry pushed after them.
+    fn subscribe(&self) -> (Vec<LiveTailEntry>, broadcast::Receiver<LiveTailEntry>) {
+        // subscribe while holding the lock, so no entry is missed or sent
+        // twice between the snapshot and the receiver
+        let entries = self.entries.lock().unwrap();
+        let rx = self.sender.subscribe();
+        (entries.iter().cloned().collect(), rx)
+    }
+}
+
+fn sse_event(entry: &LiveTailEntry) -> Result<SseEvent, Infallible> {
+    // serializing an entry can't fail, but don't bring the stream down if
+    // it somehow does
+    Ok(SseEvent::default()
+        .json_data(entry)
+        .unwrap_or_else(|e| SseEvent::default().comment(format!("{e}"))))
+}
+
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    get,
+    path = "/api/live-tail",
+    tag = "Recordings",
+    responses(
+        (status = StatusCode::OK, description = "Recently decoded messages", content(
+            (Vec<LiveTailEntry> = "application/json"),
+            (String = "text/event-stream")
+        ))
+    ),
+    summary = "Live tail",
+    description = "List a summary of the most recent messages decoded by the current recording, and the events they triggered. If the request accepts text/event-stream, the summaries are sent as Server-Sent Events instead, oldest first, and new ones follow as they're decoded. Messages are only summarized while an SSE client is connected or for a minute after the list was last fetched. The number of messages kept is set by live_tail_size in the config."
+))]
+pub async fn get_live_tail(State(state): State<Arc<ServerState>>, headers: HeaderMap) -> Response {
+    let wants_sse = headers
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/event-stream"));
+    if !wants_sse {
+        state.live_tail.mark_watched(Instant::now());
+        return Json(state.live_tail.newest_first()).into_response();
+    }
+
+    let (buffered, rx) = state.live_tail.subscribe();
+    let buffered = futures::stream::iter(buffered).map(|entry| sse_event(&entry));
+    let live = futures::stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(entry) => return Some((sse_event(&entry), rx)),
+                Err(broadcast::error::RecvError::Closed) => return None,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("live tail client fell behind, skipped {n} messages");
+                }
+            }
+        }
+    });
+    Sse::new(buffered.chain(live))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(packet_num: usize) -> LiveTailEntry {
+        LiveTailEntry {
+            packet_num,
+            packet_timestamp: None,
+            protocol: "LTE RRC",
+            channel: Some("BCCH-DL-SCH"),
+            message_type: Some("SystemInformationBlockType1".to_string()),
+            events: Vec::new(),
+        }
+    }
+
+    fn packet_nums(entries: &[LiveTailEntry]) -> Vec<usize> {
+        entries.iter().map(|entry| entry.packet_num).collect()
+    }
+
+    #[test]
+    fn test_evicts_oldest_past_capacity() {
+        let live_tail = LiveTail::new(3);
+        for packet_num in 1..=5 {
+            live_tail.push(entry(packet_num));
+        }
+        assert_eq!(packet_nums(&live_tail.newest_first()), vec![5, 4, 3]);
+
+        let (buffered, mut rx) = live_tail.subscribe();
+        assert_eq!(packet_nums(&buffered), vec![3, 4, 5]);
+        live_tail.push(entry(6));
+        assert_eq!(rx.try_recv().unwrap().packet_num, 6);
+        assert_eq!(packet_nums(&live_tail.newest_first()), vec![6, 5, 4]);
+    }
+
+    #[test]
+    fn test_disabled_keeps_nothing() {
+        let live_tail = LiveTail::new(0);
+        live_tail.push(entry(1));
+        assert!(live_tail.newest_first().is_empty());
+        live_tail.mark_watched(Instant::now());
+        assert!(!live_tail.is_watched());
+    }
+
+    #[test]
+    fn test_watched() {
+        let live_tail = LiveTail::new(3);
+        assert!(!live_tail.is_watched());
+
+        let (_, rx) = live_tail.subscribe();
+        assert!(live_tail.is_watched());
+        drop(rx);
+        assert!(!live_tail.is_watched());
+
+        let now = Instant::now();
+        live_tail.mark_watched(now);
+        assert!(live_tail.is_watched_at(now + Duration::from_secs(59)));
+        assert!(!live_tail.is_watched_at(now + LIVE_TAIL_WATCH_WINDOW));
+    }
+
+    #[test]
+    fn test_clear() {
+        let live_tail = LiveTail::new(3);
+        live_tail.push(entry(1));
+        live_tail.clear();
+        assert!(live_tail.newest_first().is_empty());
+    }
+}
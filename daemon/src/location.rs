@@ -0,0 +1,209 @@
+//! Tags recordings with where they were made. When location tagging is
+//! enabled, NMEA sentences are read from the device's GPS, and the latest
+//! fix is kept for the recording task and the system stats.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use rayhunter::location::GeoPoint;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::config::Config;
+
+/// Where NMEA sentences are read from
+pub const GPS_DEVICE_PATH: &str = "/dev/gps";
+/// How long a fix is used for after the GPS last reported one
+const MAX_FIX_AGE: Duration = Duration::from_secs(120);
+/// How long to wait before reopening the GPS after it failed or went quiet
+const REOPEN_INTERVAL: Duration = Duration::from_secs(30);
+/// The rough error of a consumer GPS receiver at an HDOP of 1, in meters
+const HDOP_TO_METERS: f32 = 5.0;
+
+/// Shares the latest GPS fix between the task reading the GPS and everything
+/// which tags things with it
+#[derive(Clone, Default)]
+pub struct LocationTracker(Arc<Mutex<Option<(GeoPoint, Instant)>>>);
+
+impl LocationTracker {
+    pub fn update(&self, point: GeoPoint, now: Instant) {
+        *self.0.lock().unwrap() = Some((point, now));
+    }
+
+    pub fn current_at(&self, now: Instant) -> Option<GeoPoint> {
+        let fix = *self.0.lock().unwrap();
+        fix.filter(|(_, at)| now.saturating_duration_since(*at) <= MAX_FIX_AGE)
+            .map(|(point, _)| point)
+    }
+
+    /// The latest fix, unless the GPS hasn't had one for a while or location
+    /// tagging is disabled
+    pub fn current(&self) -> Option<GeoPoint> {
+        self.current_at(Instant::now())
+    }
+}
+
+// Converts an NMEA ddmm.mmmm (or dddmm.mmmm) angle and its hemisphere to
+// signed degrees
+fn parse_nmea_angle(value: &str, hemisphere: &str, max_degrees: f64) -> Option<f64> {
+    let (whole, _) = value.split_once('.').unwrap_or((value, ""));
+    if whole.len() < 3 {
+        return None;
+    }
+    let degree_digits = whole.len() - 2;
+    let degrees: f64 = value[..degree_digits].parse().ok()?;
+    let minutes: f64 = value[degree_digits..].parse().ok()?;
+    if minutes >= 60.0 {
+        return None;
+    }
+    let angle = degrees + minutes / 60.0;
+    if angle > max_degrees {
+        return None;
+    }
+    match hemisphere {
+        "N" | "E" => Some(angle),
+        "S" | "W" => Some(-angle),
+        _ => None,
+    }
+}
+
+fn checksum_matches(sentence: &str) -> bool {
+    let Some((body, checksum)) = sentence.split_once('*') else {
+        // the checksum is optional
+        return true;
+    };
+    let expected = body.bytes().fold(0u8, |sum, byte| sum ^ byte);
+    u8::from_str_radix(checksum.trim(), 16) == Ok(expected)
+}
+
+/// Parses an NMEA GGA sentence (e.g. `$GPGGA,...` or `$GNGGA,...`), returning
+/// None for other sentences and for GGA sentences without a fix
+pub fn parse_gga(line: &str) -> Option<GeoPoint> {
+    let sentence = line.trim().strip_prefix('$')?;
+    if !checksum_matches(sentence) {
+        return None;
+    }
+    let sentence = sentence.split('*').next()?;
+    let fields: Vec<&str> = sentence.split(',').collect();
+    // talker ID, then the GGA sentence's time, latitude, N/S, longitude,
+    // E/W, fix quality, satellites in use and HDOP
+    if fields.len() < 9 || fields[0].len() != 5 || !fields[0].ends_with("GGA") {
+        return None;
+    }
+    if fields[6].is_empty() || fields[6] == "0" {
+        return None;
+    }
+    let lat = parse_nmea_angle(fields[2], fields[3], 90.0)?;
+    let lon = parse_nmea_angle(fields[4], fields[5], 180.0)?;
+    let hdop: f32 = fields[8].parse().ok()?;
+    Some(GeoPoint {
+        lat,
+        lon,
+        accuracy_m: hdop * HDOP_TO_METERS,
+    })
+}
+
+async fn read_fixes(path: &str, location: &LocationTracker) -> std::io::Result<()> {
+    let mut lines = BufReader::new(File::open(path).await?).lines();
+    while let Some(line) = lines.next_line().await? {
+        if let Some(point) = parse_gga(&line) {
+            location.update(point, Instant::now());
+        }
+    }
+    Ok(())
+}
+
+/// Keeps `location` up to date from the GPS, if location tagging is enabled.
+pub fn run_location_reader(
+    task_tracker: &TaskTracker,
+    config: &Config,
+    location: LocationTracker,
+    shutdown_token: CancellationToken,
+) {
+    if !config.location_tagging {
+        return;
+    }
+
+    task_tracker.spawn(async move {
+        info!("reading GPS fixes from {GPS_DEVICE_PATH}");
+        let mut warned = false;
+        loop {
+            tokio::select! {
+                result = read_fixes(GPS_DEVICE_PATH, &location) => {
+                    match result {
+                        Err(e) if !warned => {
+                            warn!("couldn't read GPS fixes from {GPS_DEVICE_PATH}, recordings won't be tagged with a location until it works: {e}");
+                            warned = true;
+                        }
+                        Err(_) => {}
+                        Ok(()) => warned = false,
+                    }
+                }
+                _ = shutdown_token.cancelled() => break,
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(REOPEN_INTERVAL) => {}
+                _ = shutdown_token.cancelled() => break,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_gga() {
+        let point =
+            parse_gga("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47").unwrap();
+        assert!((point.lat - 48.1173).abs() < 1e-9);
+        assert!((point.lon - 11.516_666_666).abs() < 1e-6);
+        assert_eq!(point.accuracy_m, 4.5);
+
+        // the southern and western hemispheres are negative, and the
+        // checksum is optional
+        let point = parse_gga("$GNGGA,000000,3351.000,S,15112.600,W,2,05,2.0,,,,,,").unwrap();
+        assert!((point.lat + 33.85).abs() < 1e-9);
+        assert!((point.lon + 151.21).abs() < 1e-9);
+        assert_eq!(point.accuracy_m, 10.0);
+    }
+
+    #[test]
+    fn test_parse_gga_rejects() {
+        // no fix
+        assert_eq!(parse_gga("$GPGGA,123519,,,,,0,00,,,M,,M,,*6B"), None);
+        // a bad checksum
+        assert_eq!(
+            parse_gga("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*48"),
+            None
+        );
+        // other sentences
+        assert_eq!(
+            parse_gga("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A"),
+            None
+        );
+        assert_eq!(parse_gga("garbage"), None);
+    }
+
+    #[test]
+    fn test_stale_fix_is_dropped() {
+        let location = LocationTracker::default();
+        let start = Instant::now();
+        assert_eq!(location.current_at(start), None);
+        let point = GeoPoint {
+            lat: 1.0,
+            lon: 2.0,
+            accuracy_m: 5.0,
+        };
+        location.update(point, start);
+        assert_eq!(location.current_at(start + MAX_FIX_AGE), Some(point));
+        assert_eq!(
+            location.current_at(start + MAX_FIX_AGE + Duration::from_secs(1)),
+            None
+        );
+    }
+}
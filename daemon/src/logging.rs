@@ -0,0 +1,197 @@
+//! The daemon writes its own log file rather than relying on the init
+//! script's output redirection, so that it can rotate the file once it gets
+//! too big. Otherwise a device left running for weeks slowly fills up its
+//! flash with logs.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use log::{LevelFilter, warn};
+
+use crate::disk_space::{AUXILIARY_WRITE_FLOOR_BYTES, allow_auxiliary_write, available_bytes};
+
+pub const LOG_PATH: &str = "/data/rayhunter/rayhunter.log";
+/// The size at which the log is rotated
+pub const LOG_MAX_BYTES: u64 = 1024 * 1024;
+/// How many rotated logs (rayhunter.log.1, rayhunter.log.2, ...) to keep
+pub const LOG_KEEP_FILES: usize = 2;
+/// How often to check there's still room for the log. Checking on every line
+/// would be too slow, and the log can't grow by much in between.
+const SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Parses a log level as written in the config, e.g. "info" or "DEBUG".
+pub fn parse_log_level(level: &str) -> Option<LevelFilter> {
+    level.trim().parse().ok()
+}
+
+/// Applies the config's log level, keeping the current one if it's invalid.
+pub fn apply_log_level(level: &str) {
+    match parse_log_level(level) {
+        Some(level) => rayhunter::set_log_level(level),
+        None => warn!(
+            "invalid log_level {level:?}, expected one of off, error, warn, info, debug or trace"
+        ),
+    }
+}
+
+/// Writes the log, rotating it once it reaches `max_bytes`. While free space
+/// is below the floor for auxiliary writes, lines are dropped instead, so the
+/// log can't take space the recorder needs.
+pub struct RotatingLogWriter {
+    path: PathBuf,
+    max_bytes: u64,
+    keep_files: usize,
+    file: File,
+    size: u64,
+    space_floor_bytes: u64,
+    space_checked_at: Option<Instant>,
+    has_room: bool,
+}
+
+impl RotatingLogWriter {
+    pub fn open<P: AsRef<Path>>(path: P, max_bytes: u64, keep_files: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingLogWriter {
+            path,
+            max_bytes,
+            keep_files,
+            file,
+            size,
+            space_floor_bytes: AUXILIARY_WRITE_FLOOR_BYTES,
+            space_checked_at: None,
+            has_room: true,
+        })
+    }
+
+    // This can't log a warning about the lack of space like the other
+    // auxiliary writers do, since it would end up right back here.
+    fn has_room(&mut self) -> bool {
+        let now = Instant::now();
+        let due = self
+            .space_checked_at
+            .is_none_or(|checked_at| now.duration_since(checked_at) >= SPACE_CHECK_INTERVAL);
+        if due {
+            let dir = self.path.parent().unwrap_or(&self.path);
+            self.has_room =
+                allow_auxiliary_write(available_bytes(dir).ok(), self.space_floor_bytes);
+            self.space_checked_at = Some(now);
+        }
+        self.has_room
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep_files > 0 {
+            for n in (1..self.keep_files).rev() {
+                match std::fs::rename(self.rotated_path(n), self.rotated_path(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingLogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.has_room() {
+            return Ok(buf.len());
+        }
+        // a single line longer than max_bytes still gets written, just into
+        // a file of its own
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_log_level() {
+        assert_eq!(parse_log_level("info"), Some(LevelFilter::Info));
+        assert_eq!(parse_log_level(" DEBUG "), Some(LevelFilter::Debug));
+        assert_eq!(parse_log_level("off"), Some(LevelFilter::Off));
+        assert_eq!(parse_log_level("verbose"), None);
+        assert_eq!(parse_log_level(""), None);
+    }
+
+    #[test]
+    fn test_rotation_trigger() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("rayhunter.log");
+        let line = |i: usize| format!("log line {i:04}\n");
+        // each line is 14 bytes, so 3 lines fit in 50 bytes
+        let mut writer = RotatingLogWriter::open(&path, 50, 2).unwrap();
+        for i in 0..3 {
+            writer.write_all(line(i).as_bytes()).unwrap();
+        }
+        assert!(!dir.path().join("rayhunter.log.1").exists());
+
+        for i in 3..10 {
+            writer.write_all(line(i).as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("rayhunter.log"), line(9));
+        assert_eq!(read("rayhunter.log.1"), line(6) + &line(7) + &line(8));
+        assert_eq!(read("rayhunter.log.2"), line(3) + &line(4) + &line(5));
+        assert!(!dir.path().join("rayhunter.log.3").exists());
+    }
+
+    #[test]
+    fn test_drops_lines_without_room() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("rayhunter.log");
+        let mut writer = RotatingLogWriter::open(&path, 50, 1).unwrap();
+        writer.write_all(b"kept\n").unwrap();
+
+        writer.space_floor_bytes = u64::MAX;
+        writer.space_checked_at = None;
+        writer.write_all(b"dropped\n").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "kept\n");
+    }
+
+    #[test]
+    fn test_reopen_keeps_size() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("rayhunter.log");
+        std::fs::write(&path, "x".repeat(45)).unwrap();
+        let mut writer = RotatingLogWriter::open(&path, 50, 1).unwrap();
+        writer.write_all(b"0123456789").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "0123456789");
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("rayhunter.log.1")).unwrap(),
+            "x".repeat(45)
+        );
+    }
+}
@@ -1,53 +1,109 @@
+mod aggregate;
 mod analysis;
+mod ap_clients;
 mod battery;
+mod captive_portal;
+mod capture_rate;
+mod clock_jump;
+mod compression;
 mod config;
 mod crypto_provider;
+mod daemon_state;
+mod decoded;
 mod diag;
+mod diag_multiplexer;
+mod disk_space;
 mod display;
 mod error;
+mod event_store;
 mod firewall;
+mod gsmtap_stream;
+mod instance_lock;
 mod key_input;
+mod live_tail;
+mod location;
+mod logging;
+mod mounts;
 mod notifications;
 mod pcap;
+mod power;
 mod qmdl_store;
+mod routing;
+mod self_test;
 mod server;
+mod shutdown;
 mod stats;
+mod wifi_scan;
+mod wifi_signal;
+mod wifi_verify;
+mod wireshark;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::battery::run_battery_notification_worker;
+use crate::aggregate::aggregate_analysis;
+use crate::ap_clients::{
+    AP_CLIENTS_FILENAME, ApClientRegistry, get_ap_clients, run_ap_client_monitor,
+};
+use crate::battery::{BatteryProbe, SYSFS_ROOT, run_battery_notification_worker};
+use crate::captive_portal::{get_wifi_status, run_captive_portal_detector};
+use crate::capture_rate::CaptureRateTracker;
 use crate::config::{parse_args, parse_config};
-use crate::diag::run_diag_read_thread;
+use crate::daemon_state::{DaemonStateTracker, daemon_state_path};
+use crate::decoded::get_decoded_messages;
+use crate::diag::{DiagTaskConfig, run_diag_read_thread};
+use crate::diag_multiplexer::{DIAG_CHANNEL_CAPACITY, DiagMultiplexer};
 use crate::error::RayhunterError;
-use crate::notifications::{NotificationService, run_notification_worker};
-use crate::pcap::get_pcap;
+use crate::event_store::{EVENT_STORE_FILENAME, EVENT_STORE_MAX_BYTES, EventStore, get_events};
+use crate::firewall::{get_firewall, reapply_firewall};
+use crate::gsmtap_stream::run_gsmtap_stream;
+use crate::instance_lock::{
+    ALREADY_RUNNING_EXIT_CODE, InstanceLock, InstanceLockError, instance_lock_path,
+};
+use crate::live_tail::{LiveTail, get_live_tail};
+use crate::location::{LocationTracker, run_location_reader};
+use crate::logging::{LOG_KEEP_FILES, LOG_MAX_BYTES, LOG_PATH, RotatingLogWriter, apply_log_level};
+use crate::notifications::{NotificationService, NotificationTemplates, run_notification_worker};
+use crate::pcap::{MAX_CONCURRENT_CONVERSIONS, get_pcap};
+use crate::power::{reboot_device, restart_daemon, shutdown_daemon};
 use crate::qmdl_store::RecordingStore;
+use crate::routing::RoutingSnapshot;
+use crate::self_test::{SelfTestReport, run_self_test};
 use crate::server::{
-    ServerState, debug_set_display_state, get_config, get_qmdl, get_time, get_wifi_status, get_zip,
-    scan_wifi, serve_static, set_config, set_time_offset, test_notification,
+    ServerState, debug_set_display_state, get_analyzers, get_config, get_qmdl, get_time, get_zip,
+    scan_wifi, serve_static, set_config, set_recording_notes, set_time_offset, test_notification,
 };
-use crate::stats::{get_qmdl_manifest, get_system_stats};
+use crate::shutdown::{TASK_SHUTDOWN_TIMEOUT, wait_for_shutdown};
+use crate::stats::{StatsCache, get_qmdl_manifest, get_system_stats, run_stats_collector};
+use crate::wifi_scan::WifiScanLimiter;
+use crate::wireshark::get_wireshark_hints;
 use wifi_station::WifiStatus;
 
 use analysis::{
-    AnalysisCtrlMessage, AnalysisStatus, get_analysis_status, run_analysis_thread, start_analysis,
+    AnalysisCtrlMessage, AnalysisStatus, MAX_IMPORT_QMDL_BYTES, get_analysis_status,
+    get_analysis_summary, import_qmdl, run_analysis_thread, start_analysis,
 };
 use axum::Router;
+use axum::extract::DefaultBodyLimit;
 use axum::response::Redirect;
 use axum::routing::{get, post};
 use diag::{
-    DiagDeviceCtrlMessage, delete_all_recordings, delete_recording, get_analysis_report,
+    DiagDeviceCtrlMessage, LIVE_QMDL_CHANNEL_CAPACITY, LiveQmdlMessage, delete_all_recordings,
+    delete_recording, get_analysis_report, get_live_qmdl, pause_recording, resume_recording,
     start_recording, stop_recording,
 };
-use log::{error, info};
+use log::{error, info, warn};
 use qmdl_store::RecordingStoreError;
-use rayhunter::Device;
 use rayhunter::diag_device::DiagDevice;
 use stats::get_log;
 use tokio::net::TcpListener;
 use tokio::select;
+use tokio::signal::unix::{SignalKind, signal};
 use tokio::sync::RwLock;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::watch;
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
@@ -58,20 +114,46 @@ fn get_router() -> AppRouter {
     Router::new()
         .route("/api/pcap/{name}", get(get_pcap))
         .route("/api/qmdl/{name}", get(get_qmdl))
+        .route("/api/live-qmdl", get(get_live_qmdl))
+        .route("/api/live-tail", get(get_live_tail))
         .route("/api/zip/{name}", get(get_zip))
         .route("/api/system-stats", get(get_system_stats))
         .route("/api/qmdl-manifest", get(get_qmdl_manifest))
         .route("/api/log", get(get_log))
         .route("/api/start-recording", post(start_recording))
         .route("/api/stop-recording", post(stop_recording))
+        .route("/api/pause-recording", post(pause_recording))
+        .route("/api/resume-recording", post(resume_recording))
         .route("/api/delete-recording/{name}", post(delete_recording))
+        .route("/api/recording/{name}/notes", post(set_recording_notes))
         .route("/api/delete-all-recordings", post(delete_all_recordings))
         .route("/api/analysis-report/{name}", get(get_analysis_report))
+        .route(
+            "/api/analysis-report/{name}/wireshark",
+            get(get_wireshark_hints),
+        )
+        .route("/api/decoded/{name}", get(get_decoded_messages))
         .route("/api/analysis", get(get_analysis_status))
         .route("/api/analysis/{name}", post(start_analysis))
+        .route("/api/analysis-summary/{name}", get(get_analysis_summary))
+        .route("/api/analysis-aggregate", post(aggregate_analysis))
+        // axum's default 2 MB body limit is too small for recordings
+        .route(
+            "/api/import-qmdl",
+            post(import_qmdl).layer(DefaultBodyLimit::max(MAX_IMPORT_QMDL_BYTES)),
+        )
+        .route("/api/events", get(get_events))
+        .route("/api/ap-clients", get(get_ap_clients))
         .route("/api/config", get(get_config))
+        .route("/api/analyzers", get(get_analyzers))
         .route("/api/config", post(set_config))
         .route("/api/test-notification", post(test_notification))
+        .route("/api/firewall", get(get_firewall))
+        .route("/api/firewall/reapply", post(reapply_firewall))
+        .route("/api/restart", post(restart_daemon))
+        .route("/api/restart-daemon", post(restart_daemon))
+        .route("/api/shutdown", post(shutdown_daemon))
+        .route("/api/reboot-device", post(reboot_device))
         .route("/api/wifi-status", get(get_wifi_status))
         .route("/api/wifi-scan", post(scan_wifi))
         .route("/api/time", get(get_time))
@@ -103,32 +185,58 @@ async fn run_server(
     })
 }
 
+/// Where the store goes while the filesystem qmdl_store_path should be on
+/// isn't mounted. This is in memory, and just keeps the rest of the daemon
+/// working; recording is refused until the mount is back, at which point
+/// the store moves over to qmdl_store_path.
+const UNMOUNTED_STORE_PATH: &str = "/tmp/rayhunter-qmdl-unmounted";
+
 // Loads a RecordingStore if one exists, and if not, only create one if we're
 // not in debug mode. If we fail to parse the manifest AND we're not in debug
 // mode, try to recover the manifest from the existing QMDL files
 async fn init_qmdl_store(config: &config::Config) -> Result<RecordingStore, RayhunterError> {
-    let store_exists = RecordingStore::exists(&config.qmdl_store_path).await?;
-    if config.debug_mode {
+    let expected_mount = config.qmdl_store_expect_mount.as_ref().map(PathBuf::from);
+    // don't create or load anything in the mount point itself, that's on the
+    // root filesystem
+    let (store_path, awaiting_mount) = match mounts::check_mount(
+        Path::new(mounts::PROC_MOUNTS),
+        Path::new(&config.qmdl_store_path),
+        expected_mount.as_deref(),
+    ) {
+        Ok(()) => (config.qmdl_store_path.clone(), None),
+        Err(e) => {
+            error!("QMDL store unavailable, recording is disabled until it's back: {e}");
+            (
+                UNMOUNTED_STORE_PATH.to_string(),
+                Some(PathBuf::from(&config.qmdl_store_path)),
+            )
+        }
+    };
+    let store_exists = RecordingStore::exists(&store_path).await?;
+    let store = if config.debug_mode {
         if store_exists {
-            Ok(RecordingStore::load(&config.qmdl_store_path).await?)
+            RecordingStore::load(&store_path).await?
         } else {
-            Err(RayhunterError::NoStoreDebugMode(
-                config.qmdl_store_path.clone(),
-            ))
+            return Err(RayhunterError::NoStoreDebugMode(store_path));
         }
     } else if store_exists {
-        match RecordingStore::load(&config.qmdl_store_path).await {
-            Ok(store) => Ok(store),
+        match RecordingStore::load(&store_path).await {
+            Ok(store) => store,
             Err(RecordingStoreError::ParseManifestError(err)) => {
                 error!("failed to parse QMDL manifest: {err}");
                 info!("recovering manifest from existing QMDL files...");
-                Ok(RecordingStore::recover(&config.qmdl_store_path).await?)
+                RecordingStore::recover(&store_path).await?
             }
-            Err(err) => Err(err.into()),
+            Err(err) => return Err(err.into()),
         }
     } else {
-        Ok(RecordingStore::create(&config.qmdl_store_path).await?)
-    }
+        RecordingStore::create(&store_path).await?
+    };
+    let store = store.with_expected_mount(expected_mount);
+    Ok(match awaiting_mount {
+        Some(path) => store.awaiting_mount(path),
+        None => store,
+    })
 }
 
 // Start a thread that'll track when user hits ctrl+c. When that happens,
@@ -140,16 +248,23 @@ fn run_shutdown_thread(
     shutdown_token: CancellationToken,
     qmdl_store_lock: Arc<RwLock<RecordingStore>>,
     analysis_tx: Sender<AnalysisCtrlMessage>,
+    daemon_state: Arc<DaemonStateTracker>,
 ) -> JoinHandle<Result<(), RayhunterError>> {
     info!("create shutdown thread");
 
     task_tracker.spawn(async move {
+        // init scripts stop us with SIGTERM, which should count as a clean
+        // shutdown just like ctrl+c does
+        let mut sigterm = signal(SignalKind::terminate())?;
         select! {
             res = tokio::signal::ctrl_c() => {
                 if let Err(err) = res {
                     error!("Unable to listen for shutdown signal: {err}");
                 }
             }
+            _ = sigterm.recv() => {
+                info!("received SIGTERM");
+            }
             _ = shutdown_token.cancelled() => {}
         }
 
@@ -159,6 +274,7 @@ fn run_shutdown_thread(
             qmdl_store.close_current_entry().await?;
             info!("Done!");
         }
+        daemon_state.record_clean_shutdown().await;
 
         shutdown_token.cancel();
         diag_device_sender
@@ -173,38 +289,133 @@ fn run_shutdown_thread(
     })
 }
 
+// Start a thread that moves the event store and AP client registry along
+// with the QMDL store, when it moves onto its filesystem once that's mounted,
+// so they aren't left behind in the stand-in directory
+fn run_store_move_thread(
+    task_tracker: &TaskTracker,
+    mut store_path: watch::Receiver<PathBuf>,
+    event_store_lock: Arc<RwLock<EventStore>>,
+    ap_clients_lock: Arc<RwLock<ApClientRegistry>>,
+    shutdown_token: CancellationToken,
+) {
+    task_tracker.spawn(async move {
+        loop {
+            select! {
+                _ = shutdown_token.cancelled() => break,
+                changed = store_path.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                }
+            }
+            let path = store_path.borrow_and_update().clone();
+            info!(
+                "moving the event store and AP clients to {}",
+                path.display()
+            );
+            if let Err(e) = event_store_lock
+                .write()
+                .await
+                .move_to(path.join(EVENT_STORE_FILENAME))
+                .await
+            {
+                warn!("couldn't move the event store to {}: {e}", path.display());
+            }
+            if let Err(e) = ap_clients_lock
+                .write()
+                .await
+                .move_to(path.join(AP_CLIENTS_FILENAME))
+                .await
+            {
+                warn!("couldn't move the AP clients to {}: {e}", path.display());
+            }
+        }
+    });
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), RayhunterError> {
-    rayhunter::init_logging(log::LevelFilter::Info);
+    match RotatingLogWriter::open(LOG_PATH, LOG_MAX_BYTES, LOG_KEEP_FILES) {
+        Ok(writer) => rayhunter::init_logging_to_writer(log::LevelFilter::Info, Box::new(writer)),
+        Err(e) => {
+            rayhunter::init_logging(log::LevelFilter::Info);
+            warn!("couldn't open {LOG_PATH}, logging to stderr instead: {e}");
+        }
+    }
 
     crate::crypto_provider::install_default();
 
     let args = parse_args();
 
+    // held until the process exits, across config restarts
+    let instance_lock = match InstanceLock::acquire(instance_lock_path(&args.config_path)) {
+        Ok(lock) => Some(lock),
+        Err(InstanceLockError::AlreadyRunning(pid)) => {
+            error!("another rayhunter daemon is already running with PID {pid}, exiting");
+            std::process::exit(ALREADY_RUNNING_EXIT_CODE);
+        }
+        Err(e) => {
+            warn!("{e}, not checking for other running daemons");
+            None
+        }
+    };
+    let lock_holder_pid = instance_lock.as_ref().map(InstanceLock::pid);
+
+    let mut restart_requested = false;
     loop {
         let config = parse_config(&args.config_path).await?;
-        if !run_with_config(&args, config).await? {
+        apply_log_level(&config.log_level);
+        if !run_with_config(&args, config, restart_requested, lock_holder_pid).await? {
             return Ok(());
         }
+        restart_requested = true;
     }
 }
 
 async fn run_with_config(
     args: &config::Args,
     config: config::Config,
+    restart_requested: bool,
+    lock_holder_pid: Option<u32>,
 ) -> Result<bool, RayhunterError> {
     // TaskTrackers give us an interface to spawn tokio threads, and then
     // eventually await all of them ending
     let task_tracker = TaskTracker::new();
     println!("R A Y H U N T E R 🐳");
 
+    let daemon_state = Arc::new(
+        DaemonStateTracker::record_start(daemon_state_path(&args.config_path), restart_requested)
+            .await
+            .with_lock_holder_pid(lock_holder_pid),
+    );
+    info!(
+        "daemon start reason: {:?}",
+        daemon_state.last_start_reason()
+    );
+
     let store = init_qmdl_store(&config).await?;
     let analysis_status = AnalysisStatus::new(&store);
+    let event_store =
+        EventStore::open(store.path.join(EVENT_STORE_FILENAME), EVENT_STORE_MAX_BYTES).await?;
+    let event_store_lock = Arc::new(RwLock::new(event_store));
+    let ap_clients_lock = Arc::new(RwLock::new(
+        ApClientRegistry::open(store.path.join(AP_CLIENTS_FILENAME)).await?,
+    ));
+    // follows the store when it moves, see RecordingStore::reopen_if_mounted
+    let (store_path_tx, store_path_rx) = watch::channel(store.path.clone());
     let qmdl_store_lock = Arc::new(RwLock::new(store));
     let (diag_tx, diag_rx) = mpsc::channel::<DiagDeviceCtrlMessage>(1);
     let (ui_update_tx, ui_update_rx) = mpsc::channel::<display::DisplayState>(1);
     let (analysis_tx, analysis_rx) = mpsc::channel::<AnalysisCtrlMessage>(5);
+    let (live_qmdl_tx, _) = broadcast::channel::<LiveQmdlMessage>(LIVE_QMDL_CHANNEL_CAPACITY);
+    let live_tail = LiveTail::new(config.live_tail_size);
+    let capture_rate = CaptureRateTracker::default();
+    let location = LocationTracker::default();
+    let monitoring = Arc::new(RwLock::new(false));
+    let diag_multiplexer = DiagMultiplexer::new(DIAG_CHANNEL_CAPACITY);
     let restart_token = CancellationToken::new();
+    let reboot_token = CancellationToken::new();
     let shutdown_token = restart_token.child_token();
     // Ensure shutdown_token is cancelled when this function exits for any
     // reason (e.g. diag device init failure), so all spawned tasks get
@@ -213,48 +424,112 @@ async fn run_with_config(
 
     let notification_service = NotificationService::new(config.ntfy_url.clone());
 
+    let mut self_test = SelfTestReport::default();
+    if config.developer_mode {
+        info!("developer mode: not touching the diag device, display, key input, firewall or wifi");
+    }
     if !config.debug_mode {
         info!("Using configuration for device: {0:?}", config.device);
-        let mut dev = DiagDevice::new(&config.device)
-            .await
-            .map_err(RayhunterError::DiagInitError)?;
-        dev.config_logs()
-            .await
-            .map_err(RayhunterError::DiagInitError)?;
+        // in developer mode, recordings can be started and stopped, they
+        // just stay empty
+        let dev = if config.developer_mode {
+            self_test.skip("diag device", "developer mode");
+            None
+        } else {
+            let mut dev = match DiagDevice::new(&config.device).await {
+                Ok(dev) => dev,
+                Err(e) => {
+                    self_test.record("diag device", Err(e.to_string()));
+                    self_test.log();
+                    return Err(RayhunterError::DiagInitError(e));
+                }
+            };
+            dev.config_logs()
+                .await
+                .map_err(RayhunterError::DiagInitError)?;
+            self_test.record("diag device", Ok(()));
+            Some(dev)
+        };
 
         info!("Starting Diag Thread");
+        // subscribe before the multiplexer starts, so nothing is missed
         run_diag_read_thread(
             &task_tracker,
-            dev,
+            diag_multiplexer.subscribe_lossless(),
             diag_rx,
             diag_tx.clone(),
-            ui_update_tx.clone(),
             qmdl_store_lock.clone(),
-            analysis_tx.clone(),
-            config.analyzers.clone(),
-            notification_service.new_handler(),
-            config.min_space_to_start_recording_mb,
-            config.min_space_to_continue_recording_mb,
+            DiagTaskConfig {
+                ui_update_sender: ui_update_tx.clone(),
+                analysis_sender: analysis_tx.clone(),
+                analyzer_config: config.analyzers.clone(),
+                notification_channel: notification_service.new_handler(),
+                live_qmdl_sender: live_qmdl_tx.clone(),
+                event_store_lock: event_store_lock.clone(),
+                store_path_sender: store_path_tx,
+                live_tail: live_tail.clone(),
+                capture_rate: capture_rate.clone(),
+                location: location.clone(),
+                monitoring: monitoring.clone(),
+                min_space_to_start_mb: config.min_space_to_start_recording_mb,
+                min_space_to_continue_mb: config.min_space_to_continue_recording_mb,
+                recording_mode: config.recording_mode,
+                clock_jump_threshold: Duration::from_secs(config.clock_jump_threshold_secs),
+            },
         );
+        if config.gsmtap_stream_enabled {
+            run_gsmtap_stream(
+                &task_tracker,
+                diag_multiplexer.subscribe(),
+                config.gsmtap_stream_destination.clone(),
+                shutdown_token.clone(),
+            );
+        }
+        if let Some(dev) = dev {
+            diag_multiplexer.run(&task_tracker, dev, shutdown_token.clone());
+        }
         info!("Starting UI");
 
-        let update_ui = match &config.device {
-            Device::Orbic | Device::Moxee => display::orbic::update_ui,
-            Device::Tplink => display::tplink::update_ui,
-            Device::Tmobile => display::tmobile::update_ui,
-            Device::Wingtech => display::wingtech::update_ui,
-            Device::Pinephone => display::headless::update_ui,
-            Device::Uz801 => display::uz801::update_ui,
-        };
-        update_ui(&task_tracker, &config, shutdown_token.clone(), ui_update_rx);
-
-        info!("Starting Key Input service");
-        key_input::run_key_input_thread(
+        let display = display::backend_for_config(&config);
+        info!("using {} display backend", display.name());
+        let ui_update_rx = display::throttle_updates(
             &task_tracker,
-            &config,
-            diag_tx.clone(),
+            config.display_max_redraws_per_sec,
             shutdown_token.clone(),
+            ui_update_rx,
         );
+        display.update_ui(&task_tracker, &config, shutdown_token.clone(), ui_update_rx);
+
+        if config.developer_mode {
+            info!("developer mode, not reading key input");
+        } else {
+            info!("Starting Key Input service");
+            key_input::run_key_input_thread(
+                &task_tracker,
+                &config,
+                diag_tx.clone(),
+                ui_update_tx.clone(),
+                shutdown_token.clone(),
+            );
+        }
+    }
+
+    if config.debug_mode {
+        self_test.skip("diag device", "debug mode");
+    }
+    run_self_test(&config, &mut self_test).await;
+    self_test.log();
+    if !config.debug_mode && display::backend_for_config(&config).device_path().is_some() {
+        let ui_update_tx = ui_update_tx.clone();
+        let passed = self_test.passed();
+        task_tracker.spawn(async move {
+            if let Err(e) = ui_update_tx
+                .send(display::DisplayState::BootStatus { passed })
+                .await
+            {
+                warn!("couldn't show self-test result on display: {e}");
+            }
+        });
     }
 
     let analysis_status_lock = Arc::new(RwLock::new(analysis_status));
@@ -263,7 +538,9 @@ async fn run_with_config(
         analysis_rx,
         qmdl_store_lock.clone(),
         analysis_status_lock.clone(),
+        event_store_lock.clone(),
         config.analyzers.clone(),
+        config.analysis_workers,
     );
 
     run_shutdown_thread(
@@ -272,46 +549,148 @@ async fn run_with_config(
         shutdown_token.clone(),
         qmdl_store_lock.clone(),
         analysis_tx.clone(),
+        daemon_state.clone(),
     );
 
+    let battery = BatteryProbe::new(config.device.clone(), PathBuf::from(SYSFS_ROOT));
     run_battery_notification_worker(
         &task_tracker,
+        battery.clone(),
+        notification_service.new_handler(),
+        shutdown_token.clone(),
+    );
+    let stats_cache = StatsCache::default();
+    run_stats_collector(
+        &task_tracker,
+        store_path_rx.clone(),
         config.device.clone(),
+        battery.clone(),
+        stats_cache.clone(),
+        shutdown_token.clone(),
+    );
+
+    run_store_move_thread(
+        &task_tracker,
+        store_path_rx.clone(),
+        event_store_lock.clone(),
+        ap_clients_lock.clone(),
+        shutdown_token.clone(),
+    );
+
+    run_ap_client_monitor(
+        &task_tracker,
+        &config,
+        ap_clients_lock.clone(),
         notification_service.new_handler(),
         shutdown_token.clone(),
     );
 
+    let captive_portal_notifications = notification_service.new_handler();
     run_notification_worker(
         &task_tracker,
         notification_service,
         config.enabled_notifications.clone(),
+        Duration::from_secs(config.notification_storm_window_secs),
+        NotificationTemplates {
+            title: config.notification_title_template.clone(),
+            body: config.notification_body_template.clone(),
+            device: format!("{:?}", config.device).to_lowercase(),
+        },
+        Some(store_path_rx.clone()),
     );
 
+    let routing_snapshot = if config.wifi_enabled && !config.developer_mode {
+        match RoutingSnapshot::capture(wifi_station::STA_IFACE).await {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                warn!("couldn't snapshot routing state before starting wifi client: {e}");
+                None
+            }
+        }
+    } else {
+        None
+    };
     let wifi_status = Arc::new(RwLock::new(WifiStatus::default()));
-    wifi_station::run_wifi_client(
+    if config.developer_mode {
+        info!("developer mode, not starting the wifi client");
+    } else {
+        wifi_station::run_wifi_client(
+            &task_tracker,
+            &config.wifi_config(),
+            shutdown_token.clone(),
+            wifi_status.clone(),
+        );
+    }
+    let firewall_state = Arc::new(RwLock::new(firewall::apply(&config).await));
+    let captive_portal = Arc::new(RwLock::new(false));
+    run_captive_portal_detector(
         &task_tracker,
-        &config.wifi_config(),
-        shutdown_token.clone(),
+        &config,
         wifi_status.clone(),
+        captive_portal.clone(),
+        firewall_state.clone(),
+        captive_portal_notifications,
+        shutdown_token.clone(),
+    );
+    run_location_reader(
+        &task_tracker,
+        &config,
+        location.clone(),
+        shutdown_token.clone(),
     );
-    firewall::apply(&config).await;
 
+    let wifi_scan = WifiScanLimiter::new(Duration::from_secs(config.wifi_scan_cooldown_secs));
     let state = Arc::new(ServerState {
         config_path: args.config_path.clone(),
         config,
         qmdl_store_lock: qmdl_store_lock.clone(),
         diag_device_ctrl_sender: diag_tx,
+        live_qmdl_sender: live_qmdl_tx,
+        live_tail,
+        capture_rate,
+        location,
+        monitoring,
         analysis_status_lock,
         analysis_sender: analysis_tx,
+        diag_multiplexer,
         daemon_restart_token: restart_token.clone(),
+        daemon_shutdown_token: shutdown_token.clone(),
+        device_reboot_token: reboot_token.clone(),
         ui_update_sender: Some(ui_update_tx),
         wifi_status,
-        wifi_scan_lock: tokio::sync::Mutex::new(()),
+        wifi_scan,
+        daemon_state,
+        event_store_lock,
+        ap_clients_lock,
+        firewall_state,
+        captive_portal,
+        pcap_conversion_semaphore: Arc::new(tokio::sync::Semaphore::new(
+            MAX_CONCURRENT_CONVERSIONS,
+        )),
+        battery,
+        stats_cache,
     });
     run_server(&task_tracker, state, shutdown_token.clone()).await;
 
-    task_tracker.close();
-    task_tracker.wait().await;
+    // the wifi client has stopped by now (or is stuck), make sure it didn't
+    // leave the AP without a route back to us
+    wait_for_shutdown(
+        &task_tracker,
+        &shutdown_token,
+        TASK_SHUTDOWN_TIMEOUT,
+        async move {
+            if let Some(snapshot) = routing_snapshot {
+                snapshot.restore().await;
+            }
+        },
+    )
+    .await;
+
+    if reboot_token.is_cancelled() {
+        power::reboot().await;
+        // the device is going down, so don't start the daemon up again
+        return Ok(false);
+    }
 
     info!("see you space cowboy...");
     Ok(restart_token.is_cancelled())
@@ -326,4 +705,60 @@ mod test {
         // assert that creating the router does not panic from invalid route patterns.
         let _ = get_router();
     }
+
+    // Boots the whole daemon in developer mode against a temp directory,
+    // fetches its config over HTTP, then shuts it down through the API.
+    #[tokio::test]
+    async fn test_developer_mode_smoke() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = config::Config {
+            developer_mode: true,
+            port,
+            qmdl_store_path: dir.path().join("qmdl").to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let args = config::Args {
+            config_path: dir
+                .path()
+                .join("config.toml")
+                .to_string_lossy()
+                .into_owned(),
+        };
+
+        let client = async {
+            let http = reqwest::Client::new();
+            let base = format!("http://127.0.0.1:{port}");
+            let mut response = None;
+            for _ in 0..100 {
+                match http.get(format!("{base}/api/config")).send().await {
+                    Ok(r) => {
+                        response = Some(r);
+                        break;
+                    }
+                    Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+                }
+            }
+            let response = response.expect("daemon never started listening");
+            assert_eq!(response.status(), reqwest::StatusCode::OK);
+            let served: serde_json::Value =
+                serde_json::from_str(&response.text().await.unwrap()).unwrap();
+            assert_eq!(served["developer_mode"], true);
+            assert_eq!(served["port"], port);
+
+            let response = http
+                .post(format!("{base}/api/shutdown"))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::ACCEPTED);
+        };
+        let (restart, ()) = tokio::join!(run_with_config(&args, config, false, None), client);
+        assert!(!restart.unwrap(), "the daemon should stop, not restart");
+        assert!(dir.path().join("qmdl").join("manifest.toml").exists());
+    }
 }
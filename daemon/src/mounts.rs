@@ -0,0 +1,242 @@
+//! Checks which filesystem the recording store is on. Some devices keep
+//! recordings on an SD card mounted over an empty directory. If the card is
+//! missing or fails to mount, that directory is just part of the (tiny) root
+//! filesystem, and recordings written there would quietly fill it up.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+pub const PROC_MOUNTS: &str = "/proc/mounts";
+
+/// A line of /proc/mounts
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Mount {
+    /// The device or pseudo-filesystem mounted, e.g. "/dev/mmcblk0p1"
+    pub device: String,
+    pub mount_point: PathBuf,
+    /// e.g. "vfat" or "ubifs"
+    pub fs_type: String,
+}
+
+/// Undoes the octal escapes the kernel uses for spaces, tabs, newlines and
+/// backslashes in /proc/mounts fields.
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(index) = rest.find('\\') {
+        unescaped.push_str(&rest[..index]);
+        let escape = rest.get(index + 1..index + 4);
+        match escape.and_then(|octal| u8::from_str_radix(octal, 8).ok()) {
+            Some(byte) => {
+                unescaped.push(byte as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+pub fn parse_mounts(contents: &str) -> Vec<Mount> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some(Mount {
+                device: unescape(fields.next()?),
+                mount_point: PathBuf::from(unescape(fields.next()?)),
+                fs_type: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+pub fn read_mounts(mounts_path: &Path) -> Result<Vec<Mount>, String> {
+    std::fs::read_to_string(mounts_path)
+        .map(|contents| parse_mounts(&contents))
+        .map_err(|e| format!("couldn't read {}: {e}", mounts_path.display()))
+}
+
+/// The mount `path` is on: the one with the longest mount point containing
+/// it. If something is mounted over the same point twice, the later mount
+/// hides the earlier one.
+pub fn mount_containing<'a>(mounts: &'a [Mount], path: &Path) -> Option<&'a Mount> {
+    mounts
+        .iter()
+        .filter(|mount| path.starts_with(&mount.mount_point))
+        .max_by_key(|mount| mount.mount_point.components().count())
+}
+
+/// Checks that `path` is on the filesystem mounted at `expected_mount`.
+pub fn verify_mount(mounts: &[Mount], path: &Path, expected_mount: &Path) -> Result<(), String> {
+    if !mounts.iter().any(|m| m.mount_point == expected_mount) {
+        return Err(format!(
+            "nothing is mounted at {} (is the SD card inserted?)",
+            expected_mount.display()
+        ));
+    }
+    if !path.starts_with(expected_mount) {
+        return Err(format!(
+            "{} isn't inside {}, where it's expected to be mounted",
+            path.display(),
+            expected_mount.display()
+        ));
+    }
+    match mount_containing(mounts, path) {
+        Some(mount) if mount.mount_point == expected_mount => Ok(()),
+        Some(mount) => Err(format!(
+            "{} is on {} mounted at {}, not on {}",
+            path.display(),
+            mount.device,
+            mount.mount_point.display(),
+            expected_mount.display()
+        )),
+        None => Err(format!("{} isn't on any mount", path.display())),
+    }
+}
+
+/// Checks that `path` is on the filesystem mounted at `expected_mount`, if
+/// there is one, using the mount table at `mounts_path`.
+pub fn check_mount(
+    mounts_path: &Path,
+    path: &Path,
+    expected_mount: Option<&Path>,
+) -> Result<(), String> {
+    let Some(expected_mount) = expected_mount else {
+        return Ok(());
+    };
+    // resolve symlinks if we can, but the path may well not exist when
+    // whatever it's on isn't mounted
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    verify_mount(&read_mounts(mounts_path)?, &path, expected_mount)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTS: &str = "\
+rootfs / rootfs rw 0 0
+ubi0:rootfs / ubifs rw,relatime 0 0
+tmpfs /tmp tmpfs rw,relatime 0 0
+ubi1:usrfs /data ubifs rw,relatime 0 0
+/dev/mmcblk0p1 /media/card vfat rw,relatime,fmask=0022 0 0
+/dev/mmcblk0p2 /media/my\\040card vfat rw,relatime 0 0
+";
+
+    fn without_card() -> String {
+        MOUNTS
+            .lines()
+            .filter(|line| !line.contains("mmcblk0p1"))
+            .map(|line| format!("{line}\n"))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_mounts() {
+        let mounts = parse_mounts(MOUNTS);
+        assert_eq!(mounts.len(), 6);
+        assert_eq!(
+            mounts[4],
+            Mount {
+                device: "/dev/mmcblk0p1".to_string(),
+                mount_point: PathBuf::from("/media/card"),
+                fs_type: "vfat".to_string(),
+            }
+        );
+        assert_eq!(mounts[5].mount_point, PathBuf::from("/media/my card"));
+        assert_eq!(unescape("a\\134b\\"), "a\\b\\");
+    }
+
+    #[test]
+    fn test_mount_containing() {
+        let mounts = parse_mounts(MOUNTS);
+        let mount_point = |path: &str| {
+            mount_containing(&mounts, Path::new(path))
+                .map(|m| (m.device.as_str(), m.mount_point.to_str().unwrap()))
+        };
+        assert_eq!(
+            mount_point("/media/card/qmdl"),
+            Some(("/dev/mmcblk0p1", "/media/card"))
+        );
+        assert_eq!(
+            mount_point("/data/rayhunter/qmdl"),
+            Some(("ubi1:usrfs", "/data"))
+        );
+        // the later root mount hides rootfs
+        assert_eq!(mount_point("/media"), Some(("ubi0:rootfs", "/")));
+        // a mount point only contains whole path components
+        assert_eq!(mount_point("/media/cards"), Some(("ubi0:rootfs", "/")));
+    }
+
+    #[test]
+    fn test_verify_mount() {
+        let card = Path::new("/media/card");
+        let mounts = parse_mounts(MOUNTS);
+        assert_eq!(
+            verify_mount(&mounts, Path::new("/media/card/qmdl"), card),
+            Ok(())
+        );
+        assert_eq!(
+            verify_mount(
+                &mounts,
+                Path::new("/media/my card/qmdl"),
+                Path::new("/media/my card")
+            ),
+            Ok(())
+        );
+
+        let err = verify_mount(&mounts, Path::new("/data/rayhunter/qmdl"), card).unwrap_err();
+        assert!(err.contains("isn't inside /media/card"), "{err}");
+
+        let mounts = parse_mounts(&without_card());
+        let err = verify_mount(&mounts, Path::new("/media/card/qmdl"), card).unwrap_err();
+        assert!(err.contains("nothing is mounted at /media/card"), "{err}");
+    }
+
+    #[test]
+    fn test_verify_mount_shadowed() {
+        // something else mounted over part of the card
+        let mut mounts = parse_mounts(MOUNTS);
+        mounts.push(Mount {
+            device: "tmpfs".to_string(),
+            mount_point: PathBuf::from("/media/card/qmdl"),
+            fs_type: "tmpfs".to_string(),
+        });
+        let err = verify_mount(
+            &mounts,
+            Path::new("/media/card/qmdl"),
+            Path::new("/media/card"),
+        )
+        .unwrap_err();
+        assert!(
+            err.contains("is on tmpfs mounted at /media/card/qmdl"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_check_mount_with_fake_proc_mounts() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mounts_path = dir.path().join("mounts");
+        let store = Path::new("/media/card/qmdl");
+        let card = Some(Path::new("/media/card"));
+
+        std::fs::write(&mounts_path, MOUNTS).unwrap();
+        assert_eq!(check_mount(&mounts_path, store, card), Ok(()));
+        assert_eq!(check_mount(&mounts_path, store, None), Ok(()));
+
+        // the card goes away
+        std::fs::write(&mounts_path, without_card()).unwrap();
+        assert!(check_mount(&mounts_path, store, card).is_err());
+        assert_eq!(check_mount(&mounts_path, store, None), Ok(()));
+
+        let missing = dir.path().join("missing");
+        assert!(check_mount(&missing, store, card).is_err());
+    }
+}
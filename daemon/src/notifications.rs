@@ -1,17 +1,39 @@
 use std::{
     cmp::min,
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::{Path, PathBuf},
     time::{Duration, Instant},
 };
 
-use log::error;
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use log::{error, warn};
+use rayhunter::analysis::analyzer::EventType;
+use rayhunter::location::GeoPoint;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::sync::mpsc::{self, error::TryRecvError};
+use tokio::sync::watch;
 use tokio_util::task::TaskTracker;
 
+use crate::disk_space::has_room_for_auxiliary_write;
+
 pub const DEFAULT_NOTIFICATION_TIMEOUT: u64 = 10; //seconds
 
+/// How many undelivered notifications each sink holds on to
+pub const NOTIFICATION_QUEUE_CAPACITY: usize = 50;
+
+/// The file undelivered notifications are kept in, under the QMDL store, so
+/// they survive a restart
+pub const NOTIFICATION_QUEUE_FILENAME: &str = "notification_queue.json";
+
+/// How many notifications the worker holds on to while it's busy delivering
+/// earlier ones, before it starts dropping the least severe
+const RECEIVED_CAPACITY: usize = 50;
+
+/// How long the worker waits between delivery attempts
+const FLUSH_INTERVAL: Duration = Duration::from_secs(2);
+
 #[derive(Error, Debug)]
 pub enum NotificationError {
     #[error("HTTP request failed: {0}")]
@@ -31,10 +53,21 @@ pub enum NotificationType {
 pub struct Notification {
     notification_type: NotificationType,
     message: String,
+    title: Option<String>,
     debounce: Option<Duration>,
+    event: Option<NotificationEvent>,
+    details: NotificationDetails,
 }
 
 impl Notification {
+    pub fn notification_type(&self) -> &NotificationType {
+        &self.notification_type
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
     pub fn new(
         notification_type: NotificationType,
         message: String,
@@ -43,51 +76,503 @@ impl Notification {
         Notification {
             notification_type,
             message,
+            title: None,
             debounce,
+            event: None,
+            details: NotificationDetails::default(),
+        }
+    }
+
+    /// A warning about an analyzer event. Rather than being debounced, these
+    /// go through [EventStormControl]. `time` is when the triggering message
+    /// was captured, if known.
+    pub fn event(
+        event_type: EventType,
+        recording: String,
+        message: String,
+        time: Option<DateTime<FixedOffset>>,
+    ) -> Self {
+        Notification {
+            notification_type: NotificationType::Warning,
+            message,
+            title: None,
+            debounce: None,
+            event: Some(NotificationEvent {
+                event_type,
+                recording,
+                time: time.unwrap_or_else(|| rayhunter::clock::get_adjusted_now().fixed_offset()),
+            }),
+            details: NotificationDetails::default(),
         }
     }
+
+    /// Adds what the notification is about, for the title and body templates
+    pub fn with_details(mut self, details: NotificationDetails) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+/// What a notification is about, beyond its message, for filling in the
+/// title and body templates
+#[derive(Debug, Clone, Default)]
+pub struct NotificationDetails {
+    /// The name of the analyzer which raised the event
+    pub analyzer: Option<String>,
+    /// The event's own message, without the "Rayhunter has detected" preamble
+    pub event_message: Option<String>,
+    /// The serving cell's network, from its SIB1, e.g. "310-260"
+    pub mcc_mnc: Option<String>,
+    /// Where the device was
+    pub location: Option<GeoPoint>,
+}
+
+/// Replaces each `{name}` in `template` with its value, or "unknown" if it
+/// doesn't have one. Anything else in braces is left as it is.
+pub fn fill_template(template: &str, values: &[(&str, Option<String>)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |text, (name, value)| {
+            text.replace(
+                &format!("{{{name}}}"),
+                value.as_deref().unwrap_or("unknown"),
+            )
+        })
+}
+
+/// Formats notifications from the `notification_title_template` and
+/// `notification_body_template` settings. Without a body template the
+/// message is sent as it is, and without a title template there's no title.
+#[derive(Debug, Clone, Default)]
+pub struct NotificationTemplates {
+    pub title: Option<String>,
+    pub body: Option<String>,
+    /// What `{device}` is filled in with
+    pub device: String,
+}
+
+impl NotificationTemplates {
+    /// The title and body to send `message` with. `notification` is what
+    /// it's about, unless it's a summary of several events.
+    fn format(
+        &self,
+        notification: Option<&Notification>,
+        message: &str,
+    ) -> (Option<String>, String) {
+        if self.title.is_none() && self.body.is_none() {
+            return (None, message.to_string());
+        }
+        let event = notification.and_then(|notification| notification.event.as_ref());
+        let details = notification.map(|notification| &notification.details);
+        let time = event.map_or_else(
+            || rayhunter::clock::get_adjusted_now().fixed_offset(),
+            |event| event.time,
+        );
+        let values = [
+            (
+                "device",
+                Some(self.device.clone()).filter(|device| !device.is_empty()),
+            ),
+            (
+                "severity",
+                event.map(|event| format!("{:?}", event.event_type)),
+            ),
+            (
+                "analyzer",
+                details.and_then(|details| details.analyzer.clone()),
+            ),
+            (
+                "timestamp",
+                Some(time.format("%Y-%m-%d %H:%M:%S").to_string()),
+            ),
+            ("recording_name", event.map(|event| event.recording.clone())),
+            (
+                "mcc_mnc",
+                details.and_then(|details| details.mcc_mnc.clone()),
+            ),
+            (
+                "location",
+                details
+                    .and_then(|details| details.location)
+                    .map(|point| format!("{:.5},{:.5}", point.lat, point.lon)),
+            ),
+            // last, so that braces in the message aren't filled in
+            (
+                "message",
+                Some(
+                    details
+                        .and_then(|details| details.event_message.clone())
+                        .unwrap_or_else(|| message.to_string()),
+                ),
+            ),
+        ];
+        let title = self
+            .title
+            .as_ref()
+            .map(|template| fill_template(template, &values));
+        let body = match &self.body {
+            Some(template) => fill_template(template, &values),
+            None => message.to_string(),
+        };
+        (title, body)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NotificationEvent {
+    pub event_type: EventType,
+    pub recording: String,
+    pub time: DateTime<FixedOffset>,
+}
+
+/// Events which were held back during a storm window
+struct SuppressedEvents {
+    count: u32,
+    highest: EventType,
+    latest: String,
+    first_time: DateTime<FixedOffset>,
+    last_time: DateTime<FixedOffset>,
+}
+
+/// A message storm control lets through
+#[derive(Debug, PartialEq)]
+pub struct StormMessage {
+    pub message: String,
+    /// The event's severity, or for a summary, the highest severity of the
+    /// events it rolls up
+    pub severity: EventType,
+    /// Whether this is a summary of events which were held back
+    pub is_summary: bool,
+}
+
+struct StormWindow {
+    opened_at: Instant,
+    recording: String,
+    sent: Vec<EventType>,
+    suppressed: Option<SuppressedEvents>,
+}
+
+impl StormWindow {
+    fn summary(&mut self) -> Option<StormMessage> {
+        let suppressed = self.suppressed.take()?;
+        let message = format!(
+            "+{} more event{} in recording {} between {} and {}, highest severity {:?}, latest: {}",
+            suppressed.count,
+            if suppressed.count == 1 { "" } else { "s" },
+            self.recording,
+            suppressed.first_time.format("%Y-%m-%d %H:%M:%S"),
+            suppressed.last_time.format("%H:%M:%S"),
+            suppressed.highest,
+            suppressed.latest,
+        );
+        Some(StormMessage {
+            message,
+            severity: suppressed.highest,
+            is_summary: true,
+        })
+    }
 }
 
-struct NotificationStatus {
+/// Keeps a burst of analyzer events from turning into a burst of push
+/// notifications. Within each window, the first event of each severity is
+/// sent right away, and the rest are rolled up into a single summary which
+/// is sent when the window closes, or early if a more severe event arrives.
+pub struct EventStormControl {
+    window: Duration,
+    current: Option<StormWindow>,
+}
+
+impl EventStormControl {
+    /// A zero `window` disables storm control, sending every event.
+    pub fn new(window: Duration) -> Self {
+        EventStormControl {
+            window,
+            current: None,
+        }
+    }
+
+    /// Returns the messages which should be sent for this event right now.
+    pub fn on_event(
+        &mut self,
+        event: &NotificationEvent,
+        message: &str,
+        now: Instant,
+    ) -> Vec<StormMessage> {
+        let mut messages: Vec<StormMessage> = self.on_tick(now).into_iter().collect();
+        let own_message = StormMessage {
+            message: message.to_string(),
+            severity: event.event_type,
+            is_summary: false,
+        };
+        if self.window.is_zero() {
+            messages.push(own_message);
+            return messages;
+        }
+
+        // a new recording gets a fresh window
+        if let Some(window) = self.current.as_mut()
+            && window.recording != event.recording
+        {
+            messages.extend(window.summary());
+            self.current = None;
+        }
+        let window = self.current.get_or_insert_with(|| StormWindow {
+            opened_at: now,
+            recording: event.recording.clone(),
+            sent: Vec::new(),
+            suppressed: None,
+        });
+
+        if window.sent.contains(&event.event_type) {
+            let suppressed = window.suppressed.get_or_insert_with(|| SuppressedEvents {
+                count: 0,
+                highest: event.event_type,
+                latest: String::new(),
+                first_time: event.time,
+                last_time: event.time,
+            });
+            suppressed.count += 1;
+            suppressed.highest = suppressed.highest.max(event.event_type);
+            suppressed.latest = message.to_string();
+            suppressed.last_time = event.time;
+            return messages;
+        }
+
+        if window.sent.iter().all(|sent| event.event_type > *sent) {
+            // escalation, so don't sit on what we've held back so far
+            messages.extend(window.summary());
+        }
+        window.sent.push(event.event_type);
+        messages.push(own_message);
+        messages
+    }
+
+    /// Closes the current window if it has run its course, returning the
+    /// summary of any events held back during it.
+    pub fn on_tick(&mut self, now: Instant) -> Option<StormMessage> {
+        let window = self.current.as_ref()?;
+        if now.duration_since(window.opened_at) < self.window {
+            return None;
+        }
+        self.current.take()?.summary()
+    }
+}
+
+/// A notification waiting to be delivered to a sink
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct QueuedNotification {
+    notification_type: NotificationType,
     message: String,
-    needs_sending: bool,
-    last_sent: Option<Instant>,
-    last_attempt: Option<Instant>,
-    failed_since_last_success: u32,
+    #[serde(default)]
+    title: Option<String>,
+    /// The severity of the event this is about, if any. Notifications
+    /// without one are the first to go when the queue is full.
+    severity: Option<EventType>,
+    /// When the notification was raised, which is missing from queues
+    /// saved by older versions
+    #[serde(default)]
+    raised_at: Option<DateTime<FixedOffset>>,
+    /// Whether this was left undelivered by a previous run, in which case
+    /// it says when it was raised
+    #[serde(skip)]
+    replayed: bool,
 }
 
-pub struct NotificationService {
-    url: Option<String>,
+impl QueuedNotification {
+    /// The message to deliver, noting when it was raised if that was before
+    /// a restart
+    fn message(&self) -> String {
+        match self.raised_at {
+            Some(raised_at) if self.replayed => format!(
+                "{}\n\n(delayed, raised at {})",
+                self.message,
+                raised_at.format("%Y-%m-%d %H:%M:%S")
+            ),
+            _ => self.message.clone(),
+        }
+    }
+}
+
+/// What's waiting to be sent to one sink. It holds at most `capacity`
+/// notifications, evicting the least severe (and of those, the oldest) when
+/// it overflows, so an outage never makes it grow without bound.
+#[derive(Debug)]
+struct NotificationQueue {
+    capacity: usize,
+    entries: VecDeque<QueuedNotification>,
+}
+
+impl NotificationQueue {
+    fn new(capacity: usize) -> Self {
+        NotificationQueue {
+            capacity,
+            entries: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, notification: QueuedNotification) {
+        self.entries.push_back(notification);
+        while self.entries.len() > self.capacity {
+            // min_by_key returns the first of equally low entries, which is
+            // the oldest
+            let Some((index, evicted)) = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| entry.severity)
+            else {
+                break;
+            };
+            warn!("notification queue is full, dropping: {}", evicted.message);
+            self.entries.remove(index);
+        }
+    }
+
+    /// Replaces a queued notification of the same type which isn't about an
+    /// event, e.g. an older low battery warning, rather than adding another.
+    fn replace(&mut self, notification: QueuedNotification) {
+        self.entries.retain(|entry| {
+            entry.severity.is_some() || entry.notification_type != notification.notification_type
+        });
+        self.push(notification);
+    }
+}
+
+/// A channel notifications can be delivered over, like ntfy. The
+/// notification worker takes care of filtering by the enabled notification
+/// types, storm control, debouncing and retries, so a sink only has to
+/// deliver a message once.
+#[async_trait]
+pub trait NotificationSink: Send + Sync {
+    /// Whether this sink delivers notifications of this type at all
+    fn supports(&self, _notification_type: &NotificationType) -> bool {
+        true
+    }
+
+    /// Identifies the sink's queue of undelivered notifications across
+    /// restarts
+    fn name(&self) -> &str;
+
+    async fn send(&self, notification: &Notification) -> Result<(), NotificationError>;
+}
+
+/// Delivers notifications by POSTing them to an ntfy topic URL
+pub struct NtfySink {
+    url: String,
     timeout: u64,
+    http_client: reqwest::Client,
+}
+
+impl NtfySink {
+    pub fn new(url: String) -> Self {
+        NtfySink {
+            url,
+            timeout: DEFAULT_NOTIFICATION_TIMEOUT,
+            http_client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl NotificationSink for NtfySink {
+    fn name(&self) -> &str {
+        "ntfy"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<(), NotificationError> {
+        send_notification(
+            &self.http_client,
+            &self.url,
+            notification.title.as_deref(),
+            notification.message.clone(),
+            self.timeout,
+        )
+        .await
+    }
+}
+
+pub struct NotificationService {
+    sinks: Vec<Box<dyn NotificationSink>>,
     tx: mpsc::Sender<Notification>,
     rx: mpsc::Receiver<Notification>,
 }
 
+/// The sinks to deliver notifications over: ntfy, if `ntfy_url` is set.
+pub fn notification_sinks(ntfy_url: Option<String>) -> Vec<Box<dyn NotificationSink>> {
+    let mut sinks: Vec<Box<dyn NotificationSink>> = Vec::new();
+    if let Some(url) = ntfy_url.filter(|url| !url.is_empty()) {
+        sinks.push(Box::new(NtfySink::new(url)));
+    }
+    sinks
+}
+
+/// How delivering a test notification over one sink went
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct SinkTestResult {
+    /// The sink's name, e.g. `ntfy`
+    pub sink: String,
+    pub success: bool,
+    /// Why delivery failed, if it did
+    pub error: Option<String>,
+}
+
+/// Sends `notification` over each sink which delivers its type, right away
+/// and only once, rather than through the notification worker's filtering,
+/// queueing and retries.
+pub async fn send_test_notification(
+    sinks: &[Box<dyn NotificationSink>],
+    notification: &Notification,
+) -> Vec<SinkTestResult> {
+    let mut results = Vec::new();
+    for sink in sinks {
+        if !sink.supports(notification.notification_type()) {
+            continue;
+        }
+        let result = sink.send(notification).await;
+        results.push(SinkTestResult {
+            sink: sink.name().to_string(),
+            success: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+        });
+    }
+    results
+}
+
 impl NotificationService {
+    /// Creates a service which delivers to `url` over ntfy, if it's set.
     pub fn new(url: Option<String>) -> Self {
         let (tx, rx) = mpsc::channel(10);
         Self {
-            url,
-            timeout: DEFAULT_NOTIFICATION_TIMEOUT,
+            sinks: notification_sinks(url),
             tx,
             rx,
         }
     }
 
+    pub fn add_sink(&mut self, sink: Box<dyn NotificationSink>) {
+        self.sinks.push(sink);
+    }
+
     pub fn new_handler(&self) -> mpsc::Sender<Notification> {
         self.tx.clone()
     }
 }
 
-/// Sends a notification message to the specified URL.
+/// Sends a notification message to the specified URL, with a title if given.
 pub async fn send_notification(
     http_client: &reqwest::Client,
     url: &str,
+    title: Option<&str>,
     message: String,
     timeout: u64,
 ) -> Result<(), NotificationError> {
-    let response = http_client
-        .post(url)
+    let mut request = http_client.post(url);
+    if let Some(title) = title {
+        // rather than the Title header, which can't hold emoji
+        request = request.query(&[("title", title)]);
+    }
+    let response = request
         .body(message)
         .timeout(Duration::from_secs(timeout))
         .send()
@@ -100,100 +585,386 @@ pub async fn send_notification(
     }
 }
 
-pub fn run_notification_worker(
-    task_tracker: &TaskTracker,
-    mut notification_service: NotificationService,
+// A sink, and what's waiting to be sent to it. Each sink retries on its own,
+// so one which is down doesn't hold up or duplicate deliveries to the rest.
+struct SinkQueue {
+    sink: Box<dyn NotificationSink>,
+    queue: NotificationQueue,
+    last_sent: HashMap<NotificationType, Instant>,
+    last_attempt: Option<Instant>,
+    failed_since_last_success: u32,
+}
+
+/// Fans notifications out to every sink which supports them, after the
+/// filtering and rate limiting which apply to all of them.
+pub struct NotificationDispatcher {
+    sinks: Vec<SinkQueue>,
     enabled_notifications: Vec<NotificationType>,
-) {
-    task_tracker.spawn(async move {
-        if let Some(url) = notification_service.url
-            && !url.is_empty()
-        {
-            let mut notification_statuses = HashMap::new();
-            let http_client = reqwest::Client::new();
+    storm_control: EventStormControl,
+    templates: NotificationTemplates,
+    queue_path: Option<PathBuf>,
+    queue_changed: bool,
+}
 
-            loop {
-                // Get any notifications since the last time we checked
-                loop {
-                    match notification_service.rx.try_recv() {
-                        Ok(notification) => {
-                            if !enabled_notifications.contains(&notification.notification_type) {
-                                continue;
-                            }
+impl NotificationDispatcher {
+    pub fn new(
+        sinks: Vec<Box<dyn NotificationSink>>,
+        enabled_notifications: Vec<NotificationType>,
+        storm_window: Duration,
+    ) -> Self {
+        NotificationDispatcher {
+            sinks: sinks
+                .into_iter()
+                .map(|sink| SinkQueue {
+                    sink,
+                    queue: NotificationQueue::new(NOTIFICATION_QUEUE_CAPACITY),
+                    last_sent: HashMap::new(),
+                    last_attempt: None,
+                    failed_since_last_success: 0,
+                })
+                .collect(),
+            enabled_notifications,
+            storm_control: EventStormControl::new(storm_window),
+            templates: NotificationTemplates::default(),
+            queue_path: None,
+            queue_changed: false,
+        }
+    }
 
-                            let status = notification_statuses
-                                .entry(notification.notification_type)
-                                .or_insert_with(|| NotificationStatus {
-                                    message: "".to_string(),
-                                    needs_sending: true,
-                                    last_sent: None,
-                                    last_attempt: None,
-                                    failed_since_last_success: 0,
+    /// Formats notifications with the given title and body templates.
+    pub fn with_templates(mut self, templates: NotificationTemplates) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    /// Keeps undelivered notifications in the given file, picking up any
+    /// left there by a previous run.
+    pub fn with_queue_file(mut self, path: PathBuf) -> Self {
+        self.load_queue_file(&path);
+        self.queue_path = Some(path);
+        self
+    }
+
+    /// Keeps undelivered notifications in the given file from now on, e.g.
+    /// once the QMDL store has moved, picking up any left there by a previous
+    /// run and removing the old file.
+    pub async fn move_queue_file(&mut self, path: PathBuf) {
+        self.load_queue_file(&path);
+        if let Some(old_path) = self.queue_path.replace(path)
+            && let Err(e) = remove_queue_file(&old_path).await
+        {
+            warn!("failed to remove old notification queue {old_path:?}: {e}");
+        }
+        self.queue_changed = true;
+        self.save_queue().await;
+    }
+
+    fn load_queue_file(&mut self, path: &Path) {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                match serde_json::from_str::<BTreeMap<String, Vec<QueuedNotification>>>(&contents) {
+                    Ok(mut saved) => {
+                        for queue in &mut self.sinks {
+                            for entry in saved.remove(queue.sink.name()).unwrap_or_default() {
+                                queue.queue.push(QueuedNotification {
+                                    replayed: true,
+                                    ..entry
                                 });
-                            // Ignore if we're in the debounce period
-                            if let Some(debounce) = notification.debounce
-                                && let Some(last_sent) = status.last_sent
-                                && last_sent.elapsed() < debounce
-                            {
-                                continue;
                             }
-                            status.message = notification.message;
-                            status.needs_sending = true;
-                        }
-                        Err(TryRecvError::Empty) => {
-                            break;
-                        }
-                        Err(TryRecvError::Disconnected) => {
-                            return;
                         }
                     }
+                    Err(e) => warn!("ignoring unreadable notification queue {path:?}: {e}"),
                 }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("failed to read notification queue {path:?}: {e}"),
+        }
+    }
 
-                // Attempt to send pending notifications
-                for notification in notification_statuses.values_mut() {
-                    if !notification.needs_sending {
-                        continue;
-                    }
+    /// Queues a notification for every sink which supports it.
+    pub fn receive(&mut self, notification: Notification, now: Instant) {
+        if !self
+            .enabled_notifications
+            .contains(&notification.notification_type)
+        {
+            return;
+        }
 
-                    // Backoff retries, up to a maximum of 256 seconds.
-                    if let Some(last_attempt) = notification.last_attempt {
-                        let min_wait_time = Duration::from_secs(
-                            2u64.pow(min(notification.failed_since_last_success, 8)),
-                        );
-                        if last_attempt.elapsed() < min_wait_time {
-                            continue;
-                        }
-                    }
+        if let Some(event) = &notification.event {
+            for sent in self
+                .storm_control
+                .on_event(event, &notification.message, now)
+            {
+                let (title, message) = if sent.is_summary {
+                    self.templates.format(None, &sent.message)
+                } else {
+                    self.templates.format(Some(&notification), &sent.message)
+                };
+                self.queue(
+                    &notification.notification_type,
+                    title,
+                    message,
+                    Some(sent.severity),
+                    event.time,
+                );
+            }
+            return;
+        }
 
-                    match send_notification(
-                        &http_client,
-                        &url,
-                        notification.message.clone(),
-                        notification_service.timeout,
-                    )
-                    .await
-                    {
-                        Ok(()) => {
-                            notification.last_sent = Some(Instant::now());
-                            notification.failed_since_last_success = 0;
-                            notification.needs_sending = false;
-                        }
-                        Err(e) => {
-                            error!("Failed to send notification: {e}");
-                            notification.failed_since_last_success += 1;
-                            notification.last_attempt = Some(Instant::now());
-                        }
+        let (title, message) = self
+            .templates
+            .format(Some(&notification), &notification.message);
+        for queue in &mut self.sinks {
+            if !queue.sink.supports(&notification.notification_type) {
+                continue;
+            }
+            // Ignore if we're in the debounce period
+            if let Some(debounce) = notification.debounce
+                && let Some(last_sent) = queue.last_sent.get(&notification.notification_type)
+                && now.duration_since(*last_sent) < debounce
+            {
+                continue;
+            }
+            queue.queue.replace(QueuedNotification {
+                notification_type: notification.notification_type.clone(),
+                message: message.clone(),
+                title: title.clone(),
+                severity: None,
+                raised_at: Some(rayhunter::clock::get_adjusted_now().fixed_offset()),
+                replayed: false,
+            });
+            self.queue_changed = true;
+        }
+    }
+
+    fn queue(
+        &mut self,
+        notification_type: &NotificationType,
+        title: Option<String>,
+        message: String,
+        severity: Option<EventType>,
+        raised_at: DateTime<FixedOffset>,
+    ) {
+        for queue in &mut self.sinks {
+            if queue.sink.supports(notification_type) {
+                queue.queue.push(QueuedNotification {
+                    notification_type: notification_type.clone(),
+                    message: message.clone(),
+                    title: title.clone(),
+                    severity,
+                    raised_at: Some(raised_at),
+                    replayed: false,
+                });
+                self.queue_changed = true;
+            }
+        }
+    }
+
+    /// Queues the summary of a storm window which has closed, if any.
+    pub fn tick(&mut self, now: Instant) {
+        if let Some(summary) = self.storm_control.on_tick(now) {
+            let (title, message) = self.templates.format(None, &summary.message);
+            let raised_at = rayhunter::clock::get_adjusted_now().fixed_offset();
+            self.queue(
+                &NotificationType::Warning,
+                title,
+                message,
+                Some(summary.severity),
+                raised_at,
+            );
+        }
+    }
+
+    /// Attempts to send everything which is pending, in the order it was
+    /// queued, backing off from sinks which have been failing.
+    pub async fn flush(&mut self, now: Instant) {
+        for queue in &mut self.sinks {
+            // Backoff retries, up to a maximum of 256 seconds.
+            if let Some(last_attempt) = queue.last_attempt {
+                let min_wait_time =
+                    Duration::from_secs(2u64.pow(min(queue.failed_since_last_success, 8)));
+                if now.duration_since(last_attempt) < min_wait_time {
+                    continue;
+                }
+            }
+
+            while let Some(entry) = queue.queue.entries.front() {
+                let mut notification =
+                    Notification::new(entry.notification_type.clone(), entry.message(), None);
+                notification.title = entry.title.clone();
+                match queue.sink.send(&notification).await {
+                    Ok(()) => {
+                        queue.last_sent.insert(entry.notification_type.clone(), now);
+                        queue.last_attempt = None;
+                        queue.failed_since_last_success = 0;
+                        queue.queue.entries.pop_front();
+                        self.queue_changed = true;
+                    }
+                    Err(e) => {
+                        error!("Failed to send notification via {}: {e}", queue.sink.name());
+                        queue.failed_since_last_success += 1;
+                        queue.last_attempt = Some(now);
+                        break;
                     }
                 }
+            }
+        }
+        self.save_queue().await;
+    }
 
-                tokio::time::sleep(Duration::from_secs(2)).await;
+    /// How many notifications are waiting to be delivered, across all sinks
+    pub fn queued(&self) -> usize {
+        self.sinks
+            .iter()
+            .map(|queue| queue.queue.entries.len())
+            .sum()
+    }
+
+    async fn save_queue(&mut self) {
+        if !self.queue_changed {
+            return;
+        }
+        let Some(path) = &self.queue_path else {
+            return;
+        };
+        let saved: BTreeMap<&str, &VecDeque<QueuedNotification>> = self
+            .sinks
+            .iter()
+            .filter(|queue| !queue.queue.entries.is_empty())
+            .map(|queue| (queue.sink.name(), &queue.queue.entries))
+            .collect();
+        let result = if saved.is_empty() {
+            remove_queue_file(path).await
+        } else if !has_room_for_auxiliary_write(path, "notification queue") {
+            // try again the next time the queue changes
+            return;
+        } else {
+            match serde_json::to_vec(&saved) {
+                Ok(json) => write_queue_file(path, &json).await,
+                Err(e) => Err(std::io::Error::other(e)),
             }
+        };
+        match result {
+            Ok(()) => self.queue_changed = false,
+            Err(e) => warn!("failed to save notification queue to {path:?}: {e}"),
+        }
+    }
+}
+
+// Writes the queue to a temporary file first, so a crash or power loss
+// partway through never leaves a truncated queue behind
+async fn write_queue_file(path: &Path, json: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp_path, json).await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
+
+async fn remove_queue_file(path: &Path) -> std::io::Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Holds on to a notification which arrived while the worker was busy, up to
+/// [RECEIVED_CAPACITY] of them, dropping the least severe (and of those, the
+/// oldest) beyond that.
+fn hold(received: &mut Vec<Notification>, notification: Notification) {
+    received.push(notification);
+    if received.len() > RECEIVED_CAPACITY {
+        let severity =
+            |notification: &Notification| notification.event.as_ref().map(|event| event.event_type);
+        if let Some((index, _)) = received
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, notification)| severity(notification))
+        {
+            let dropped = received.remove(index);
+            warn!(
+                "too many notifications at once, dropping: {}",
+                dropped.message
+            );
         }
-        // If there's no url to send to we'll just discard the notifications
-        else {
+    }
+}
+
+/// Delivers notifications in the background. Undelivered ones are kept in
+/// the QMDL store at `store_path`, if given, following it when it moves, and
+/// retried until they go through.
+pub fn run_notification_worker(
+    task_tracker: &TaskTracker,
+    mut notification_service: NotificationService,
+    enabled_notifications: Vec<NotificationType>,
+    storm_window: Duration,
+    templates: NotificationTemplates,
+    mut store_path: Option<watch::Receiver<PathBuf>>,
+) {
+    task_tracker.spawn(async move {
+        let sinks = std::mem::take(&mut notification_service.sinks);
+        // If there's nowhere to send to we'll just discard the notifications
+        if sinks.is_empty() {
+            while notification_service.rx.recv().await.is_some() {}
+            return;
+        }
+
+        let mut dispatcher =
+            NotificationDispatcher::new(sinks, enabled_notifications, storm_window)
+                .with_templates(templates);
+        if let Some(store_path) = &mut store_path {
+            let queue_path = store_path
+                .borrow_and_update()
+                .join(NOTIFICATION_QUEUE_FILENAME);
+            dispatcher = dispatcher.with_queue_file(queue_path);
+        }
+        let mut received = Vec::new();
+        loop {
+            if let Some(store_path) = &mut store_path
+                && store_path.has_changed().unwrap_or(false)
+            {
+                let queue_path = store_path
+                    .borrow_and_update()
+                    .join(NOTIFICATION_QUEUE_FILENAME);
+                dispatcher.move_queue_file(queue_path).await;
+            }
+
+            // Get any notifications since the last time we checked
+            loop {
+                match notification_service.rx.try_recv() {
+                    Ok(notification) => hold(&mut received, notification),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+            for notification in received.drain(..) {
+                dispatcher.receive(notification, Instant::now());
+            }
+            dispatcher.tick(Instant::now());
+
+            // Delivering can take a while if the network is down, so keep
+            // taking notifications meanwhile, rather than leaving senders
+            // (like the diag task) waiting for room in the channel
+            let flush = dispatcher.flush(Instant::now());
+            tokio::pin!(flush);
             loop {
-                if notification_service.rx.recv().await.is_none() {
-                    break;
+                tokio::select! {
+                    _ = &mut flush => break,
+                    notification = notification_service.rx.recv() => match notification {
+                        Some(notification) => hold(&mut received, notification),
+                        None => return,
+                    },
+                }
+            }
+
+            // and while waiting for the next attempt
+            let sleep = tokio::time::sleep(FLUSH_INTERVAL);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    notification = notification_service.rx.recv() => match notification {
+                        Some(notification) => hold(&mut received, notification),
+                        None => return,
+                    },
                 }
             }
         }
@@ -205,6 +976,7 @@ mod tests {
     use super::*;
     use axum::{Router, body::Bytes, extract::State, routing::post};
     use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
     use tokio::net::TcpListener;
     use tokio::sync::Mutex;
 
@@ -280,6 +1052,7 @@ mod tests {
         let result = send_notification(
             &http_client,
             &url,
+            None,
             "test warning message".to_string(),
             timeout,
         )
@@ -306,6 +1079,9 @@ mod tests {
             &task_tracker,
             notification_service,
             vec![NotificationType::Warning],
+            Duration::ZERO,
+            NotificationTemplates::default(),
+            None,
         );
 
         notification_sender
@@ -339,6 +1115,9 @@ mod tests {
             &task_tracker,
             notification_service,
             vec![NotificationType::Warning],
+            Duration::ZERO,
+            NotificationTemplates::default(),
+            None,
         );
 
         notification_sender
@@ -381,6 +1160,9 @@ mod tests {
             &task_tracker,
             notification_service,
             vec![NotificationType::Warning, NotificationType::LowBattery],
+            Duration::ZERO,
+            NotificationTemplates::default(),
+            None,
         );
 
         notification_sender
@@ -423,6 +1205,9 @@ mod tests {
             &task_tracker,
             notification_service,
             vec![NotificationType::Warning],
+            Duration::ZERO,
+            NotificationTemplates::default(),
+            None,
         );
 
         notification_sender
@@ -438,4 +1223,528 @@ mod tests {
 
         cleanup_worker(notification_sender, task_tracker).await;
     }
+
+    type Deliveries = Arc<std::sync::Mutex<Vec<(NotificationType, String)>>>;
+
+    // records what it's sent, and fails every delivery while told to
+    struct MockSink {
+        supported: Vec<NotificationType>,
+        fail: Arc<AtomicBool>,
+        delivered: Deliveries,
+    }
+
+    impl MockSink {
+        fn new(supported: &[NotificationType], fail: bool) -> (Box<Self>, Deliveries) {
+            let (sink, delivered, failing) = Self::failing(supported);
+            failing.store(fail, Ordering::Relaxed);
+            (sink, delivered)
+        }
+
+        // a sink which fails until the returned flag is cleared
+        fn failing(supported: &[NotificationType]) -> (Box<Self>, Deliveries, Arc<AtomicBool>) {
+            let delivered = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let fail = Arc::new(AtomicBool::new(true));
+            let sink = MockSink {
+                supported: supported.to_vec(),
+                fail: fail.clone(),
+                delivered: delivered.clone(),
+            };
+            (Box::new(sink), delivered, fail)
+        }
+    }
+
+    #[async_trait]
+    impl NotificationSink for MockSink {
+        fn supports(&self, notification_type: &NotificationType) -> bool {
+            self.supported.contains(notification_type)
+        }
+
+        fn name(&self) -> &str {
+            "mock"
+        }
+
+        async fn send(&self, notification: &Notification) -> Result<(), NotificationError> {
+            if self.fail.load(Ordering::Relaxed) {
+                return Err(NotificationError::HttpError(
+                    reqwest::StatusCode::SERVICE_UNAVAILABLE,
+                ));
+            }
+            self.delivered.lock().unwrap().push((
+                notification.notification_type().clone(),
+                notification.message().to_string(),
+            ));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_test_notification_reports_each_sink() {
+        let (working, delivered) = MockSink::new(&[NotificationType::Warning], false);
+        let (broken, _) = MockSink::new(&[NotificationType::Warning], true);
+        let (battery_only, battery_delivered) =
+            MockSink::new(&[NotificationType::LowBattery], false);
+        let sinks: Vec<Box<dyn NotificationSink>> = vec![working, broken, battery_only];
+        let notification = Notification::new(NotificationType::Warning, "test".to_string(), None);
+
+        assert_eq!(
+            send_test_notification(&sinks, &notification).await,
+            vec![
+                SinkTestResult {
+                    sink: "mock".to_string(),
+                    success: true,
+                    error: None,
+                },
+                SinkTestResult {
+                    sink: "mock".to_string(),
+                    success: false,
+                    error: Some(
+                        "Server returned error status: 503 Service Unavailable".to_string()
+                    ),
+                },
+            ]
+        );
+        assert_eq!(delivered.lock().unwrap().len(), 1);
+        assert!(battery_delivered.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_notification_sinks() {
+        assert!(notification_sinks(None).is_empty());
+        assert!(notification_sinks(Some(String::new())).is_empty());
+        let sinks = notification_sinks(Some("https://ntfy.sh/topic".to_string()));
+        let names: Vec<_> = sinks.iter().map(|sink| sink.name()).collect();
+        assert_eq!(names, ["ntfy"]);
+    }
+
+    #[tokio::test]
+    async fn test_dispatcher_fans_out_to_sinks() {
+        let (all, all_delivered) = MockSink::new(
+            &[NotificationType::Warning, NotificationType::LowBattery],
+            false,
+        );
+        let (warnings, warnings_delivered) = MockSink::new(&[NotificationType::Warning], false);
+        let (broken, _) = MockSink::new(&[NotificationType::Warning], true);
+        let mut dispatcher = NotificationDispatcher::new(
+            vec![all, warnings, broken],
+            vec![NotificationType::Warning, NotificationType::LowBattery],
+            Duration::ZERO,
+        );
+
+        let now = Instant::now();
+        dispatcher.receive(
+            Notification::new(NotificationType::Warning, "warning".to_string(), None),
+            now,
+        );
+        dispatcher.receive(
+            Notification::new(NotificationType::LowBattery, "battery".to_string(), None),
+            now,
+        );
+        dispatcher.flush(now).await;
+        // the broken sink is backing off, and the others have nothing new
+        dispatcher.flush(now).await;
+
+        let mut delivered = all_delivered.lock().unwrap().clone();
+        delivered.sort_by_key(|(_, message)| message.clone());
+        assert_eq!(
+            delivered,
+            [
+                (NotificationType::LowBattery, "battery".to_string()),
+                (NotificationType::Warning, "warning".to_string()),
+            ]
+        );
+        assert_eq!(
+            *warnings_delivered.lock().unwrap(),
+            [(NotificationType::Warning, "warning".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dispatcher_filters_disabled_types() {
+        let (sink, delivered) = MockSink::new(
+            &[NotificationType::Warning, NotificationType::LowBattery],
+            false,
+        );
+        let mut dispatcher = NotificationDispatcher::new(
+            vec![sink],
+            vec![NotificationType::Warning],
+            Duration::ZERO,
+        );
+
+        let now = Instant::now();
+        dispatcher.receive(
+            Notification::new(NotificationType::LowBattery, "battery".to_string(), None),
+            now,
+        );
+        dispatcher.receive(
+            Notification::event(EventType::High, "1".to_string(), "event".to_string(), None),
+            now,
+        );
+        dispatcher.flush(now).await;
+
+        assert_eq!(
+            *delivered.lock().unwrap(),
+            [(NotificationType::Warning, "event".to_string())]
+        );
+    }
+
+    fn warning(event_type: EventType, message: &str) -> Notification {
+        Notification::event(event_type, "1".to_string(), message.to_string(), None)
+    }
+
+    #[test]
+    fn test_dispatcher_queues_summary_with_its_severity() {
+        let (sink, _) = MockSink::new(&[NotificationType::Warning], false);
+        let mut dispatcher = NotificationDispatcher::new(
+            vec![sink],
+            vec![NotificationType::Warning],
+            Duration::from_secs(120),
+        );
+
+        let now = Instant::now();
+        for message in ["high", "high again", "and again"] {
+            dispatcher.receive(warning(EventType::High, message), now);
+        }
+        // a new recording closes the window, but its event's severity
+        // doesn't carry over to the summary
+        dispatcher.receive(
+            Notification::event(EventType::Low, "2".to_string(), "low".to_string(), None),
+            now,
+        );
+        let queued: Vec<(&str, Option<EventType>)> = dispatcher.sinks[0]
+            .queue
+            .entries
+            .iter()
+            .map(|entry| (entry.message.as_str(), entry.severity))
+            .collect();
+        assert_eq!(queued.len(), 3);
+        assert_eq!(queued[0], ("high", Some(EventType::High)));
+        assert!(queued[1].0.starts_with("+2 more events in recording 1"));
+        assert_eq!(queued[1].1, Some(EventType::High));
+        assert_eq!(queued[2], ("low", Some(EventType::Low)));
+    }
+
+    #[tokio::test]
+    async fn test_dispatcher_keeps_undelivered_notifications() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let queue_path = dir.path().join(NOTIFICATION_QUEUE_FILENAME);
+        let (sink, _, _) = MockSink::failing(&[NotificationType::Warning]);
+        let mut dispatcher = NotificationDispatcher::new(
+            vec![sink],
+            vec![NotificationType::Warning],
+            Duration::ZERO,
+        )
+        .with_queue_file(queue_path.clone());
+
+        let now = Instant::now();
+        let raised_at = DateTime::parse_from_rfc3339("2024-01-02T03:04:05+00:00").unwrap();
+        let high = Notification::event(
+            EventType::High,
+            "1".to_string(),
+            "high".to_string(),
+            Some(raised_at),
+        );
+        dispatcher.receive(high, now);
+        dispatcher.flush(now).await;
+        assert_eq!(dispatcher.queued(), 1);
+        assert!(queue_path.exists());
+        assert!(!queue_path.with_extension("json.tmp").exists());
+
+        // a restarted daemon picks up where this one left off, and says how
+        // late the notification is
+        let (sink, delivered) = MockSink::new(&[NotificationType::Warning], false);
+        let mut dispatcher = NotificationDispatcher::new(
+            vec![sink],
+            vec![NotificationType::Warning],
+            Duration::ZERO,
+        )
+        .with_queue_file(queue_path.clone());
+        assert_eq!(dispatcher.queued(), 1);
+        dispatcher.flush(now).await;
+        assert_eq!(
+            *delivered.lock().unwrap(),
+            [(
+                NotificationType::Warning,
+                "high\n\n(delayed, raised at 2024-01-02 03:04:05)".to_string()
+            )]
+        );
+        assert!(!queue_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_dispatcher_moves_queue_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let old_path = dir.path().join("old.json");
+        let queue_path = dir.path().join(NOTIFICATION_QUEUE_FILENAME);
+        let (sink, _, _) = MockSink::failing(&[NotificationType::Warning]);
+        let mut dispatcher = NotificationDispatcher::new(
+            vec![sink],
+            vec![NotificationType::Warning],
+            Duration::ZERO,
+        )
+        .with_queue_file(old_path.clone());
+
+        let now = Instant::now();
+        let warning = Notification::new(NotificationType::Warning, "warning".to_string(), None);
+        dispatcher.receive(warning, now);
+        dispatcher.flush(now).await;
+        assert!(old_path.exists());
+
+        dispatcher.move_queue_file(queue_path.clone()).await;
+        assert!(!old_path.exists());
+        assert!(queue_path.exists());
+
+        let (sink, _) = MockSink::new(&[NotificationType::Warning], false);
+        let dispatcher = NotificationDispatcher::new(
+            vec![sink],
+            vec![NotificationType::Warning],
+            Duration::ZERO,
+        )
+        .with_queue_file(queue_path);
+        assert_eq!(dispatcher.queued(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dispatcher_retries_once_sink_recovers() {
+        let (sink, delivered, failing) = MockSink::failing(&[NotificationType::Warning]);
+        let mut dispatcher = NotificationDispatcher::new(
+            vec![sink],
+            vec![NotificationType::Warning],
+            Duration::ZERO,
+        );
+
+        let start = Instant::now();
+        dispatcher.receive(warning(EventType::High, "first"), start);
+        dispatcher.receive(warning(EventType::Medium, "second"), start);
+        dispatcher.flush(start).await;
+        assert!(delivered.lock().unwrap().is_empty());
+
+        failing.store(false, Ordering::Relaxed);
+        // still backing off from the failure
+        dispatcher.flush(start + Duration::from_secs(1)).await;
+        assert!(delivered.lock().unwrap().is_empty());
+
+        dispatcher.flush(start + Duration::from_secs(2)).await;
+        assert_eq!(
+            *delivered.lock().unwrap(),
+            [
+                (NotificationType::Warning, "first".to_string()),
+                (NotificationType::Warning, "second".to_string()),
+            ]
+        );
+        assert_eq!(dispatcher.queued(), 0);
+    }
+
+    #[test]
+    fn test_fill_template() {
+        let values = [
+            ("device", Some("orbic".to_string())),
+            ("severity", None),
+            ("message", Some("{device}".to_string())),
+        ];
+        assert_eq!(
+            fill_template("[{severity}] {message} on {device} at {location}", &values),
+            "[unknown] {device} on orbic at {location}"
+        );
+    }
+
+    #[test]
+    fn test_templates() {
+        let templates = NotificationTemplates {
+            title: Some("[{severity}] Rayhunter Alert".to_string()),
+            body: Some(
+                "{analyzer}: {message} at {timestamp} on {device} in {recording_name}, \
+                 {mcc_mnc} {location}"
+                    .to_string(),
+            ),
+            device: "orbic".to_string(),
+        };
+        let time = DateTime::parse_from_rfc3339("2024-01-02T03:04:05+00:00").unwrap();
+        let notification = Notification::event(
+            EventType::High,
+            "1700000000".to_string(),
+            "Rayhunter has detected a High severity event: IMSI requested".to_string(),
+            Some(time),
+        )
+        .with_details(NotificationDetails {
+            analyzer: Some("IMSI Requested".to_string()),
+            event_message: Some("IMSI requested".to_string()),
+            mcc_mnc: Some("310-260".to_string()),
+            location: Some(GeoPoint {
+                lat: 48.1173,
+                lon: 11.516666,
+                accuracy_m: 5.0,
+            }),
+        });
+        let (title, body) = templates.format(Some(&notification), &notification.message);
+        assert_eq!(title.as_deref(), Some("[High] Rayhunter Alert"));
+        assert_eq!(
+            body,
+            "IMSI Requested: IMSI requested at 2024-01-02 03:04:05 on orbic in 1700000000, \
+             310-260 48.11730,11.51667"
+        );
+
+        // anything a notification isn't about is unknown
+        let notification = Notification::new(
+            NotificationType::LowBattery,
+            "Battery low".to_string(),
+            None,
+        );
+        let (title, body) = templates.format(Some(&notification), &notification.message);
+        assert_eq!(title.as_deref(), Some("[unknown] Rayhunter Alert"));
+        assert!(body.starts_with("unknown: Battery low at "));
+        assert!(body.ends_with(" on orbic in unknown, unknown unknown"));
+
+        // without templates, the message goes out as it is
+        let (title, body) =
+            NotificationTemplates::default().format(Some(&notification), &notification.message);
+        assert_eq!(title, None);
+        assert_eq!(body, "Battery low");
+    }
+
+    #[test]
+    fn test_received_notifications_are_capped() {
+        let mut received = Vec::new();
+        hold(&mut received, warning(EventType::High, "high"));
+        for i in 0..RECEIVED_CAPACITY {
+            hold(
+                &mut received,
+                Notification::new(NotificationType::LowBattery, format!("battery {i}"), None),
+            );
+        }
+        assert_eq!(received.len(), RECEIVED_CAPACITY);
+        assert_eq!(received[0].message(), "high");
+        assert_eq!(received[1].message(), "battery 1");
+    }
+
+    #[test]
+    fn test_queue_evicts_least_severe_first() {
+        let queued = |severity: Option<EventType>, message: &str| QueuedNotification {
+            notification_type: NotificationType::Warning,
+            message: message.to_string(),
+            title: None,
+            severity,
+            raised_at: None,
+            replayed: false,
+        };
+        let mut queue = NotificationQueue::new(3);
+        queue.push(queued(Some(EventType::High), "high"));
+        queue.push(queued(Some(EventType::Low), "old low"));
+        queue.push(queued(Some(EventType::Low), "new low"));
+        queue.push(queued(Some(EventType::Medium), "medium"));
+        queue.push(queued(None, "summary"));
+        let messages: Vec<&str> = queue.entries.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, ["high", "new low", "medium"]);
+
+        queue.push(queued(Some(EventType::High), "high again"));
+        let messages: Vec<&str> = queue.entries.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, ["high", "medium", "high again"]);
+    }
+
+    fn event(event_type: EventType, recording: &str, seconds: i64) -> NotificationEvent {
+        NotificationEvent {
+            event_type,
+            recording: recording.to_string(),
+            time: DateTime::from_timestamp(1_700_000_000 + seconds, 0)
+                .unwrap()
+                .into(),
+        }
+    }
+
+    fn messages(sent: &[StormMessage]) -> Vec<&str> {
+        sent.iter().map(|sent| sent.message.as_str()).collect()
+    }
+
+    #[test]
+    fn test_storm_control_rolls_up_bursts() {
+        let start = Instant::now();
+        let mut storm_control = EventStormControl::new(Duration::from_secs(120));
+
+        let sent = storm_control.on_event(&event(EventType::Medium, "1", 0), "first", start);
+        assert_eq!(messages(&sent), ["first"]);
+        for i in 1..=17 {
+            let now = start + Duration::from_secs(i);
+            let message = format!("event {i}");
+            let sent =
+                storm_control.on_event(&event(EventType::Medium, "1", i as i64), &message, now);
+            assert!(sent.is_empty());
+        }
+
+        assert!(
+            storm_control
+                .on_tick(start + Duration::from_secs(119))
+                .is_none()
+        );
+        let summary = storm_control
+            .on_tick(start + Duration::from_secs(120))
+            .unwrap();
+        assert!(summary.is_summary);
+        assert_eq!(summary.severity, EventType::Medium);
+        assert!(
+            summary
+                .message
+                .starts_with("+17 more events in recording 1 between ")
+        );
+        assert!(
+            summary
+                .message
+                .ends_with("highest severity Medium, latest: event 17")
+        );
+        assert!(
+            storm_control
+                .on_tick(start + Duration::from_secs(240))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_storm_control_sends_escalation_immediately() {
+        let start = Instant::now();
+        let mut storm_control = EventStormControl::new(Duration::from_secs(120));
+
+        storm_control.on_event(&event(EventType::Low, "1", 0), "low", start);
+        storm_control.on_event(&event(EventType::Low, "1", 1), "low again", start);
+        let sent = storm_control.on_event(&event(EventType::High, "1", 2), "high", start);
+        assert_eq!(sent.len(), 2);
+        assert!(sent[0].message.starts_with("+1 more event in recording 1"));
+        // the summary is only as severe as what it held back
+        assert_eq!(sent[0].severity, EventType::Low);
+        assert_eq!(sent[1].message, "high");
+        assert_eq!(sent[1].severity, EventType::High);
+
+        // the first event of a lower severity than what's been sent still
+        // goes out, but repeats of it don't
+        let sent = storm_control.on_event(&event(EventType::Medium, "1", 3), "medium", start);
+        assert_eq!(messages(&sent), ["medium"]);
+        let sent = storm_control.on_event(&event(EventType::Medium, "1", 4), "medium", start);
+        assert!(sent.is_empty());
+    }
+
+    #[test]
+    fn test_storm_control_quiet_periods() {
+        let start = Instant::now();
+        let mut storm_control = EventStormControl::new(Duration::from_secs(120));
+
+        // single events spread out more than a window apart all go out, and
+        // there's never anything to summarize
+        for i in 0..3 {
+            let now = start + Duration::from_secs(i * 300);
+            let sent = storm_control.on_event(&event(EventType::High, "1", 0), "high", now);
+            assert_eq!(messages(&sent), ["high"]);
+            assert!(
+                storm_control
+                    .on_tick(now + Duration::from_secs(150))
+                    .is_none()
+            );
+        }
+    }
+
+    #[test]
+    fn test_storm_control_disabled() {
+        let start = Instant::now();
+        let mut storm_control = EventStormControl::new(Duration::ZERO);
+        for _ in 0..5 {
+            let sent = storm_control.on_event(&event(EventType::High, "1", 0), "high", start);
+            assert_eq!(messages(&sent), ["high"]);
+        }
+    }
 }
@@ -1,46 +1,94 @@
+use crate::compression::maybe_gzip_response;
+use crate::disk_space::has_room_for_auxiliary_write;
+use crate::qmdl_store::PauseInterval;
 use crate::server::ServerState;
 
 use anyhow::Error;
-use axum::body::Body;
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
-use axum::http::header::CONTENT_TYPE;
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::header::{HeaderName, RETRY_AFTER};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
-use log::error;
+use futures::{Stream, StreamExt, stream};
+use log::{error, info, warn};
 use rayhunter::diag::DataType;
 use rayhunter::gsmtap_parser;
-use rayhunter::pcap::GsmtapPcapWriter;
+use rayhunter::pcap::{GsmtapPcapWriter, MessageFilter, PcapFormat};
 use rayhunter::qmdl::QmdlReader;
+use serde::Deserialize;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::io::{AsyncRead, AsyncWrite, duplex};
+use std::time::{Instant, SystemTime};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, DuplexStream, duplex};
+use tokio::sync::oneshot;
 use tokio_util::io::ReaderStream;
 
+/// How many conversions may run at once. Each one keeps a CPU core busy, and
+/// the devices we run on don't have many to spare.
+pub const MAX_CONCURRENT_CONVERSIONS: usize = 1;
+/// The size of the buffers between the converter and the client, which bounds
+/// how much memory a conversion uses regardless of the size of the QMDL file
+const PCAP_BUFFER_SIZE: usize = 64 * 1024;
+/// How long a client which hit the conversion limit is told to wait
+pub(crate) const RETRY_AFTER_SECS: &str = "5";
+const CONVERSION_CACHED_HEADER: HeaderName = HeaderName::from_static("x-conversion-cached");
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PcapQuery {
+    /// Container format, "pcapng" (the default) or "pcap"
+    #[serde(default)]
+    pub format: PcapFormat,
+    /// Whether to redact IMSIs, IMEIs and phone numbers, overriding the
+    /// redact_identifiers setting
+    pub redact: Option<bool>,
+    /// Comma-separated kinds of message to include, e.g. "lte_nas,lte_rrc".
+    /// Every message is included if unset.
+    pub filter: Option<String>,
+}
+
 // Streams a pcap file chunk-by-chunk to the client by reading the QMDL data
 // written so far. This is done by spawning a thread which streams chunks of
 // pcap data to a channel that's piped to the client.
+//
+// Finished recordings never change, so their converted pcap is also saved
+// next to the QMDL file and served from there on later downloads.
 #[cfg_attr(feature = "apidocs", utoipa::path(
     get,
     path = "/api/pcap/{name}",
     tag = "Recordings",
     responses(
         (status = StatusCode::OK, description = "PCAP conversion successful", content_type = "application/vnd.tcpdump.pcap"),
+        (status = StatusCode::BAD_REQUEST, description = "Unknown message type in filter"),
         (status = StatusCode::NOT_FOUND, description = "Could not find file {name}"),
+        (status = StatusCode::TOO_MANY_REQUESTS, description = "Another conversion is already running, retry after the number of seconds in the Retry-After header"),
         (status = StatusCode::SERVICE_UNAVAILABLE, description = "QMDL file is empty")
     ),
     params(
-        ("name" = String, Path, description = "QMDL filename to convert and download")
+        ("name" = String, Path, description = "QMDL filename to convert and download"),
+        ("format" = Option<String>, Query, description = "Container format: pcapng (the default) or classic pcap"),
+        ("redact" = Option<bool>, Query, description = "Redact IMSIs, IMEIs and phone numbers. Defaults to the redact_identifiers setting."),
+        ("filter" = Option<String>, Query, description = "Only include these kinds of message, comma-separated: lte_nas, lte_rrc, umts_nas, umts_rrc and gsm. Defaults to every message.")
     ),
     summary = "Download a PCAP file",
-    description = "Stream a PCAP file to a client in chunks by converting the QMDL data for file {name} written so far."
+    description = "Stream a PCAP file to a client in chunks by converting the QMDL data for file {name} written so far. The converted file is cached for recordings which have finished, unless it's filtered, and the X-Conversion-Cached header says whether the response came from the cache. Only one conversion runs at a time. The response is gzip-compressed if the request's Accept-Encoding allows it."
 ))]
 pub async fn get_pcap(
     State(state): State<Arc<ServerState>>,
-    Path(mut qmdl_name): Path<String>,
+    Path(qmdl_name): Path<String>,
+    Query(query): Query<PcapQuery>,
+    headers: HeaderMap,
 ) -> Result<Response, (StatusCode, String)> {
+    let message_filter = query
+        .filter
+        .as_deref()
+        .unwrap_or_default()
+        .parse::<MessageFilter>()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
     let qmdl_store = state.qmdl_store_lock.read().await;
-    if qmdl_name.ends_with("pcapng") {
-        qmdl_name = qmdl_name.trim_end_matches(".pcapng").to_string();
-    }
+    let qmdl_name = qmdl_name
+        .trim_end_matches(".pcapng")
+        .trim_end_matches(".pcap");
     let (entry_index, entry) = qmdl_store.entry_for_name(&qmdl_name).ok_or((
         StatusCode::NOT_FOUND,
         format!("couldn't find manifest entry with name {qmdl_name}"),
@@ -52,39 +100,240 @@ pub async fn get_pcap(
         ));
     }
     let qmdl_size_bytes = entry.qmdl_size_bytes;
+    let pauses = entry.pauses.clone();
+    let format = query.format;
+    let redact = query.redact.unwrap_or(state.config.redact_identifiers);
+    // the current recording is still growing, so caching it would be a waste.
+    // filtered conversions are usually one-offs, so they aren't cached either.
+    let cache_path = if qmdl_store.current_entry == Some(entry_index)
+        || message_filter != MessageFilter::default()
+    {
+        None
+    } else {
+        Some(entry.get_pcap_cache_filepath(&qmdl_store.path, format, redact))
+    };
+
+    if let Some(cache_path) = &cache_path
+        && let Some(cache_file) =
+            open_fresh_cache(cache_path, &entry.get_qmdl_filepath(&qmdl_store.path)).await
+    {
+        let body = ReaderStream::with_capacity(cache_file, PCAP_BUFFER_SIZE);
+        return Ok(pcap_response(&headers, true, body));
+    }
+
+    let Ok(permit) = state.pcap_conversion_semaphore.clone().try_acquire_owned() else {
+        return Ok((
+            StatusCode::TOO_MANY_REQUESTS,
+            [(RETRY_AFTER, RETRY_AFTER_SECS)],
+            "another PCAP conversion is already running, try again in a bit!",
+        )
+            .into_response());
+    };
+
     let qmdl_file = qmdl_store
         .open_entry_qmdl(entry_index)
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:?}")))?;
     // the QMDL reader should stop at the last successfully written data chunk
     // (entry.size_bytes)
-    let (reader, writer) = duplex(1024);
+    let (reader, writer) = duplex(PCAP_BUFFER_SIZE);
+    let (done_tx, done_rx) = oneshot::channel();
 
+    let qmdl_name = qmdl_name.to_string();
     tokio::spawn(async move {
-        if let Err(e) = generate_pcap_data(writer, qmdl_file, qmdl_size_bytes).await {
-            error!("failed to generate PCAP: {e:?}");
+        let _permit = permit;
+        let start = Instant::now();
+        match generate_pcap_data(
+            writer,
+            qmdl_file,
+            qmdl_size_bytes,
+            format,
+            redact,
+            message_filter,
+            &pauses,
+        )
+        .await
+        {
+            Ok(()) => {
+                let secs = start.elapsed().as_secs_f64();
+                info!(
+                    "converted {qmdl_name} ({qmdl_size_bytes} bytes) to {} in {secs:.2}s ({:.0} KiB/s)",
+                    format.file_extension(),
+                    qmdl_size_bytes as f64 / 1024.0 / secs.max(0.001)
+                );
+                let _ = done_tx.send(true);
+            }
+            Err(e) => {
+                error!("failed to generate PCAP: {e:?}");
+                let _ = done_tx.send(false);
+            }
         }
     });
 
-    let headers = [(CONTENT_TYPE, "application/vnd.tcpdump.pcap")];
-    let body = Body::from_stream(ReaderStream::new(reader));
-    Ok((headers, body).into_response())
+    let cache = match cache_path {
+        Some(cache_path) => PcapCacheWriter::create(cache_path).await,
+        None => None,
+    };
+    let body = stream_and_cache(reader, cache, done_rx);
+    Ok(pcap_response(&headers, false, body))
+}
+
+// The cache always holds the uncompressed pcap, so compression happens here
+// whichever way the body was produced.
+fn pcap_response<S>(request_headers: &HeaderMap, cached: bool, body: S) -> Response
+where
+    S: Stream<Item = std::io::Result<Bytes>> + Send + 'static,
+{
+    let mut response = maybe_gzip_response(request_headers, "application/vnd.tcpdump.pcap", body);
+    response.headers_mut().insert(
+        CONVERSION_CACHED_HEADER,
+        HeaderValue::from_static(if cached { "true" } else { "false" }),
+    );
+    response
+}
+
+// Opens the cached pcap, unless it's missing or older than the QMDL file it
+// was converted from.
+async fn open_fresh_cache(
+    cache_path: &std::path::Path,
+    qmdl_path: &std::path::Path,
+) -> Option<File> {
+    let modified = |path| async move { tokio::fs::metadata(path).await?.modified() };
+    let cache_modified: SystemTime = modified(cache_path).await.ok()?;
+    let qmdl_modified: SystemTime = modified(qmdl_path).await.ok()?;
+    if cache_modified < qmdl_modified {
+        return None;
+    }
+    File::open(cache_path).await.ok()
+}
+
+// Writes a copy of the converted pcap to a temporary file, which is only moved
+// into place once the whole conversion succeeded, so a failed or abandoned
+// download never leaves a truncated cache behind. If the client disconnects
+// partway, the stream is dropped along with the writer, which removes the
+// temporary file.
+struct PcapCacheWriter {
+    file: File,
+    tmp_path: PathBuf,
+    path: PathBuf,
+    committed: bool,
+}
+
+impl PcapCacheWriter {
+    async fn create(path: PathBuf) -> Option<Self> {
+        if !has_room_for_auxiliary_write(&path, "PCAP cache") {
+            return None;
+        }
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        match File::create(&tmp_path).await {
+            Ok(file) => Some(PcapCacheWriter {
+                file,
+                tmp_path,
+                path,
+                committed: false,
+            }),
+            Err(e) => {
+                warn!("couldn't create PCAP cache {tmp_path:?}: {e}");
+                None
+            }
+        }
+    }
+
+    async fn finish(mut self) {
+        let result = match self.file.flush().await {
+            Ok(()) => tokio::fs::rename(&self.tmp_path, &self.path).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => self.committed = true,
+            Err(e) => warn!("couldn't save PCAP cache {:?}: {e}", self.path),
+        }
+    }
+}
+
+impl Drop for PcapCacheWriter {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+// Streams the converter's output to the client, teeing it into the cache.
+fn stream_and_cache(
+    reader: DuplexStream,
+    cache: Option<PcapCacheWriter>,
+    done_rx: oneshot::Receiver<bool>,
+) -> impl Stream<Item = std::io::Result<Bytes>> {
+    let chunks = ReaderStream::with_capacity(reader, PCAP_BUFFER_SIZE);
+    stream::unfold(
+        (chunks, cache, done_rx),
+        |(mut chunks, mut cache, done_rx)| async move {
+            match chunks.next().await {
+                Some(Ok(chunk)) => {
+                    if let Some(writer) = &mut cache
+                        && let Err(e) = writer.file.write_all(&chunk).await
+                    {
+                        warn!("couldn't write PCAP cache {:?}: {e}", writer.tmp_path);
+                        cache = None;
+                    }
+                    Some((Ok(chunk), (chunks, cache, done_rx)))
+                }
+                Some(Err(e)) => Some((Err(e), (chunks, None, done_rx))),
+                None => {
+                    if let Some(writer) = cache {
+                        // the converter is done writing, but may still have
+                        // hit an error, in which case dropping the writer
+                        // removes what it wrote
+                        if done_rx.await.unwrap_or(false) {
+                            writer.finish().await;
+                        }
+                    }
+                    None
+                }
+            }
+        },
+    )
 }
 
 pub async fn generate_pcap_data<R, W>(
     writer: W,
     qmdl_file: R,
     qmdl_size_bytes: usize,
+    format: PcapFormat,
+    redact: bool,
+    message_filter: MessageFilter,
+    pauses: &[PauseInterval],
 ) -> Result<(), Error>
 where
     W: AsyncWrite + Unpin + Send,
     R: AsyncRead + Unpin,
 {
-    let mut pcap_writer = GsmtapPcapWriter::new(writer).await?;
+    let mut pcap_writer = if redact {
+        GsmtapPcapWriter::new_redacted(writer, format).await?
+    } else {
+        GsmtapPcapWriter::new_with_format(writer, format).await?
+    };
+    pcap_writer.set_message_filter(message_filter);
     pcap_writer.write_iface_header().await?;
 
     let mut reader = QmdlReader::new(qmdl_file, Some(qmdl_size_bytes));
-    while let Some(container) = reader.get_next_messages_container().await? {
+    let mut pauses = pauses.iter().peekable();
+    loop {
+        let offset = reader.bytes_read();
+        let Some(container) = reader.get_next_messages_container().await? else {
+            break;
+        };
+        // note each pause on the first packet recorded after it
+        let gaps: Vec<String> =
+            std::iter::from_fn(|| pauses.next_if(|pause| pause.qmdl_offset <= offset))
+                .map(PauseInterval::describe)
+                .collect();
+        if !gaps.is_empty() {
+            pcap_writer.comment_next_packet(gaps.join("\n"));
+        }
         if container.data_type != DataType::UserSpace {
             continue;
         }
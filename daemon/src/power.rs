@@ -0,0 +1,162 @@
+//! Restarting or stopping the daemon, or rebooting the device, over the API,
+//! for when something has wedged and there's no shell to fix it from. All of
+//! them go through the same clean shutdown as a SIGTERM, so the current
+//! recording is closed and the routing state restored before anything else
+//! happens.
+
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use log::{error, info, warn};
+use serde::Serialize;
+use tokio::process::Command;
+
+use crate::server::ServerState;
+
+/// The stop reason recorded on the current recording when it's stopped by a
+/// restart, shutdown or reboot
+pub const SHUTDOWN_STOP_REASON: &str = "shutdown";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub enum PowerAction {
+    RestartDaemon,
+    ShutdownDaemon,
+    RebootDevice,
+}
+
+impl PowerAction {
+    /// A rough guess at how long the web UI will be unreachable
+    fn estimated_downtime_secs(self) -> Option<u64> {
+        match self {
+            PowerAction::RestartDaemon => Some(10),
+            // it's down until someone starts it again
+            PowerAction::ShutdownDaemon => None,
+            PowerAction::RebootDevice => Some(90),
+        }
+    }
+}
+
+/// Response for POST /api/restart, /api/shutdown and /api/reboot-device
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct PowerActionResponse {
+    pub action: PowerAction,
+    /// A rough guess at how long the web UI will be unreachable, in seconds,
+    /// or None if Rayhunter won't come back by itself
+    pub estimated_downtime_secs: Option<u64>,
+}
+
+async fn begin_shutdown(
+    state: &ServerState,
+    action: PowerAction,
+) -> Result<(StatusCode, Json<PowerActionResponse>), (StatusCode, String)> {
+    if state.config.debug_mode {
+        return Err((StatusCode::FORBIDDEN, "server is in debug mode".to_string()));
+    }
+    if matches!(action, PowerAction::RebootDevice) && state.config.developer_mode {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "not rebooting the machine in developer mode".to_string(),
+        ));
+    }
+
+    // the shutdown thread closes the current recording once the token is
+    // cancelled, so its stop reason has to be in place before then
+    {
+        let mut qmdl_store = state.qmdl_store_lock.write().await;
+        if let Err(e) = qmdl_store
+            .set_current_stop_reason(SHUTDOWN_STOP_REASON.to_string())
+            .await
+        {
+            warn!("couldn't set stop reason: {e}");
+        }
+    }
+
+    info!("{action:?} requested");
+    match action {
+        PowerAction::RestartDaemon => state.daemon_restart_token.cancel(),
+        // stops everything without the restart which follows cancelling
+        // daemon_restart_token
+        PowerAction::ShutdownDaemon => state.daemon_shutdown_token.cancel(),
+        PowerAction::RebootDevice => {
+            state.device_reboot_token.cancel();
+            state.daemon_restart_token.cancel();
+        }
+    }
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(PowerActionResponse {
+            action,
+            estimated_downtime_secs: action.estimated_downtime_secs(),
+        }),
+    ))
+}
+
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    post,
+    path = "/api/restart",
+    tag = "System",
+    responses(
+        (status = StatusCode::ACCEPTED, description = "Restart started", body = PowerActionResponse),
+        (status = StatusCode::FORBIDDEN, description = "System is in debug mode")
+    ),
+    summary = "Restart Rayhunter",
+    description = "Stop the current recording, shut Rayhunter down cleanly and start it again with the config file as it is on disk. Also available as /api/restart-daemon."
+))]
+pub async fn restart_daemon(
+    State(state): State<Arc<ServerState>>,
+) -> Result<(StatusCode, Json<PowerActionResponse>), (StatusCode, String)> {
+    begin_shutdown(&state, PowerAction::RestartDaemon).await
+}
+
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    post,
+    path = "/api/shutdown",
+    tag = "System",
+    responses(
+        (status = StatusCode::ACCEPTED, description = "Shutdown started", body = PowerActionResponse),
+        (status = StatusCode::FORBIDDEN, description = "System is in debug mode")
+    ),
+    summary = "Shut Rayhunter down",
+    description = "Stop the current recording and shut Rayhunter down cleanly without restarting it, e.g. before unplugging the device."
+))]
+pub async fn shutdown_daemon(
+    State(state): State<Arc<ServerState>>,
+) -> Result<(StatusCode, Json<PowerActionResponse>), (StatusCode, String)> {
+    begin_shutdown(&state, PowerAction::ShutdownDaemon).await
+}
+
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    post,
+    path = "/api/reboot-device",
+    tag = "System",
+    responses(
+        (status = StatusCode::ACCEPTED, description = "Reboot started", body = PowerActionResponse),
+        (status = StatusCode::FORBIDDEN, description = "System is in debug or developer mode")
+    ),
+    summary = "Reboot device",
+    description = "Stop the current recording, shut Rayhunter down cleanly, then reboot the device."
+))]
+pub async fn reboot_device(
+    State(state): State<Arc<ServerState>>,
+) -> Result<(StatusCode, Json<PowerActionResponse>), (StatusCode, String)> {
+    begin_shutdown(&state, PowerAction::RebootDevice).await
+}
+
+/// Flushes everything to disk and reboots the device. Only called once the
+/// daemon has shut down cleanly.
+pub async fn reboot() {
+    info!("rebooting device");
+    // SAFETY: sync() takes no arguments and can't fail
+    unsafe { libc::sync() };
+    match Command::new("reboot").status().await {
+        Ok(status) if status.success() => {}
+        Ok(status) => error!("reboot exited with {status}"),
+        Err(e) => error!("couldn't run reboot: {e}"),
+    }
+}
@@ -1,9 +1,12 @@
 use std::io::{self, ErrorKind};
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, SecondsFormat};
 use log::{info, warn};
+use rayhunter::location::GeoPoint;
+use rayhunter::pcap::PcapFormat;
 use rayhunter::util::RuntimeMetadata;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -12,10 +15,15 @@ use tokio::{
     io::AsyncWriteExt,
 };
 
+use crate::clock_jump::ClockJump;
+use crate::mounts;
+
 #[derive(Debug, Error)]
 pub enum RecordingStoreError {
     #[error("Can't close an entry when there's no current entry")]
     NoCurrentEntry,
+    #[error("The current entry isn't paused")]
+    NotPaused,
     #[error("An entry with that name doesn't exist")]
     NoSuchEntryError,
     #[error("Couldn't create file: {0}")]
@@ -38,6 +46,12 @@ pub struct RecordingStore {
     pub path: PathBuf,
     pub manifest: Manifest,
     pub current_entry: Option<usize>, // index into manifest
+    /// Where the filesystem holding the store should be mounted, if it's on
+    /// removable storage
+    pub expected_mount: Option<PathBuf>,
+    /// Where the store belongs, while it's been put somewhere temporary
+    /// because that filesystem wasn't mounted
+    pub awaiting_mount: Option<PathBuf>,
 }
 
 #[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
@@ -49,7 +63,8 @@ pub struct Manifest {
 #[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
 #[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
 pub struct ManifestEntry {
-    /// The name of the entry
+    /// The name of the entry. This is the unix timestamp at which recording
+    /// began, followed by "-<n>" if other entries began in the same second
     pub name: String,
     /// The system time when recording began
     #[cfg_attr(feature = "apidocs", schema(value_type = String))]
@@ -67,14 +82,80 @@ pub struct ManifestEntry {
     pub arch: Option<String>,
     #[serde(default)]
     pub stop_reason: Option<String>,
+    /// Where the device was when recording began, if location tagging is
+    /// enabled and the GPS had a fix
+    #[serde(default)]
+    pub start_location: Option<GeoPoint>,
+    /// Where the device was when recording stopped
+    #[serde(default)]
+    pub end_location: Option<GeoPoint>,
+    /// When recording was paused, in order
+    #[serde(default)]
+    pub pauses: Vec<PauseInterval>,
+    /// Free-form notes about the recording, e.g. where it was made
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// Short labels for sorting recordings, e.g. "suspected"
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Jumps in the system clock while recording, in order. Timestamps on
+    /// either side of one can't be compared.
+    #[serde(default)]
+    pub clock_jumps: Vec<ClockJump>,
+    /// How long it recorded for, up to its last message, going by the
+    /// monotonic clock. Unlike the difference between start_time and
+    /// last_message_time, clock jumps don't throw this off. Missing for
+    /// recordings made before it was added.
+    #[serde(default)]
+    pub duration_secs: Option<u64>,
+}
+
+/// A stretch of time during which a recording was paused, so nothing was
+/// written to it
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct PauseInterval {
+    /// The system time when the recording was paused
+    #[cfg_attr(feature = "apidocs", schema(value_type = String))]
+    pub start: DateTime<Local>,
+    /// The system time when the recording was resumed, unless it's still
+    /// paused
+    #[cfg_attr(feature = "apidocs", schema(value_type = Option<String>))]
+    pub end: Option<DateTime<Local>>,
+    /// The size of the QMDL file when the recording was paused, which is
+    /// where the first message after the pause starts
+    pub qmdl_offset: usize,
+}
+
+impl PauseInterval {
+    /// Describes the gap in the recording, for reports and PCAP comments
+    pub fn describe(&self) -> String {
+        let start = self.start.to_rfc3339_opts(SecondsFormat::Secs, true);
+        match self.end {
+            Some(end) => format!(
+                "recording paused from {start} to {}",
+                end.to_rfc3339_opts(SecondsFormat::Secs, true)
+            ),
+            None => format!("recording paused from {start}"),
+        }
+    }
 }
 
 impl ManifestEntry {
-    fn new() -> Self {
+    // Creates an entry beginning now, named after the time it began, with a
+    // "-n" suffix if `is_taken` says that name is already in use
+    fn new(is_taken: impl Fn(&str) -> bool) -> Self {
         let now = rayhunter::clock::get_adjusted_now();
         let metadata = RuntimeMetadata::new();
+        let timestamp = now.timestamp();
+        let mut name = timestamp.to_string();
+        let mut suffix = 0;
+        while is_taken(&name) {
+            suffix += 1;
+            name = format!("{timestamp}-{suffix}");
+        }
         ManifestEntry {
-            name: format!("{}", now.timestamp()),
+            name,
             start_time: now,
             last_message_time: None,
             qmdl_size_bytes: 0,
@@ -82,6 +163,13 @@ impl ManifestEntry {
             system_os: Some(metadata.system_os),
             arch: Some(metadata.arch),
             stop_reason: None,
+            start_location: None,
+            end_location: None,
+            pauses: Vec::new(),
+            notes: None,
+            tags: Vec::new(),
+            clock_jumps: Vec::new(),
+            duration_secs: None,
         }
     }
 
@@ -96,6 +184,45 @@ impl ManifestEntry {
         filepath.set_extension("ndjson");
         filepath
     }
+
+    pub fn get_summary_filepath<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let mut filepath = path.as_ref().join(&self.name);
+        filepath.set_extension("summary.json");
+        filepath
+    }
+
+    // Where a converted copy of the QMDL file is cached
+    pub fn get_pcap_cache_filepath<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: PcapFormat,
+        redacted: bool,
+    ) -> PathBuf {
+        let mut filepath = if redacted {
+            path.as_ref().join(format!("{}-redacted", self.name))
+        } else {
+            path.as_ref().join(&self.name)
+        };
+        filepath.set_extension(format.file_extension());
+        filepath
+    }
+
+    // Every file derived from the QMDL file which may exist on disk, besides
+    // the analysis
+    fn get_cache_filepaths<P: AsRef<Path>>(&self, path: P) -> Vec<PathBuf> {
+        let mut filepaths: Vec<PathBuf> = [PcapFormat::PcapNg, PcapFormat::Pcap]
+            .into_iter()
+            .flat_map(|format| [(format, false), (format, true)])
+            .flat_map(|(format, redacted)| {
+                let filepath = self.get_pcap_cache_filepath(&path, format, redacted);
+                let mut tmp_filepath = filepath.clone().into_os_string();
+                tmp_filepath.push(".tmp");
+                [filepath, tmp_filepath.into()]
+            })
+            .collect();
+        filepaths.push(self.get_summary_filepath(&path));
+        filepaths
+    }
 }
 
 impl RecordingStore {
@@ -127,6 +254,8 @@ impl RecordingStore {
             path,
             manifest,
             current_entry: None,
+            expected_mount: None,
+            awaiting_mount: None,
         })
     }
 
@@ -146,6 +275,8 @@ impl RecordingStore {
                 entries: Vec::new(),
             },
             current_entry: None,
+            expected_mount: None,
+            awaiting_mount: None,
         };
 
         store.write_manifest().await?;
@@ -153,8 +284,9 @@ impl RecordingStore {
     }
 
     // Does a best-effort attempt to recover the manifest from a directory of
-    // QMDL files. We expect these files to be named like "<timestamp>.qmdl",
-    // and skip any files which don't match that pattern.
+    // QMDL files. We expect these files to be named like "<timestamp>.qmdl"
+    // or "<timestamp>-<n>.qmdl", and skip any files which don't match that
+    // pattern.
     pub async fn recover<P>(path: P) -> Result<Self, RecordingStoreError>
     where
         P: AsRef<Path>,
@@ -179,7 +311,10 @@ impl RecordingStore {
             }
 
             let stem = filename.trim_end_matches(".qmdl");
-            let Ok(start_timestamp) = stem.parse::<i64>() else {
+            let timestamp = stem
+                .split_once('-')
+                .map_or(stem, |(timestamp, _)| timestamp);
+            let Ok(start_timestamp) = timestamp.parse::<i64>() else {
                 warn!("QMDL file has invalid name {os_filename:?}, skipping");
                 continue;
             };
@@ -212,6 +347,13 @@ impl RecordingStore {
                 system_os: None,
                 arch: None,
                 stop_reason: None,
+                start_location: None,
+                end_location: None,
+                pauses: Vec::new(),
+                notes: None,
+                tags: Vec::new(),
+                clock_jumps: Vec::new(),
+                duration_secs: None,
             });
         }
 
@@ -224,12 +366,73 @@ impl RecordingStore {
                 entries: manifest_entries,
             },
             current_entry: None,
+            expected_mount: None,
+            awaiting_mount: None,
         };
         store.write_manifest().await?;
 
         Ok(store)
     }
 
+    /// Sets where the store's filesystem should be mounted, for
+    /// [Self::check_mount].
+    pub fn with_expected_mount(mut self, expected_mount: Option<PathBuf>) -> Self {
+        self.expected_mount = expected_mount;
+        self
+    }
+
+    /// Marks this as a stand-in for the store at `path`, whose filesystem
+    /// isn't mounted yet, for [Self::reopen_if_mounted].
+    pub fn awaiting_mount(mut self, path: PathBuf) -> Self {
+        self.awaiting_mount = Some(path);
+        self
+    }
+
+    /// If this is a stand-in for a store whose filesystem wasn't mounted,
+    /// and it now is, moves over to the real store, loading or creating it
+    /// there. Returns whether it moved.
+    pub async fn reopen_if_mounted(&mut self) -> Result<bool, RecordingStoreError> {
+        let Some(path) = self.awaiting_mount.clone() else {
+            return Ok(false);
+        };
+        if mounts::check_mount(
+            Path::new(mounts::PROC_MOUNTS),
+            &path,
+            self.expected_mount.as_deref(),
+        )
+        .is_err()
+        {
+            return Ok(false);
+        }
+        let store = if RecordingStore::exists(&path).await? {
+            match RecordingStore::load(&path).await {
+                Err(RecordingStoreError::ParseManifestError(err)) => {
+                    warn!("failed to parse QMDL manifest: {err}, recovering it");
+                    RecordingStore::recover(&path).await?
+                }
+                result => result?,
+            }
+        } else {
+            RecordingStore::create(&path).await?
+        };
+        info!("QMDL store is mounted again, moving to {}", path.display());
+        self.path = store.path;
+        self.manifest = store.manifest;
+        self.current_entry = None;
+        self.awaiting_mount = None;
+        Ok(true)
+    }
+
+    /// Checks that the store is on the filesystem it's expected to be on,
+    /// rather than on whatever's underneath when that isn't mounted.
+    pub fn check_mount(&self) -> Result<(), String> {
+        mounts::check_mount(
+            Path::new(mounts::PROC_MOUNTS),
+            &self.path,
+            self.expected_mount.as_deref(),
+        )
+    }
+
     async fn read_manifest<P>(path: P) -> Result<Manifest, RecordingStoreError>
     where
         P: AsRef<Path>,
@@ -241,6 +444,34 @@ impl RecordingStore {
         toml::from_str(&file_contents).map_err(RecordingStoreError::ParseManifestError)
     }
 
+    // Creates an entry beginning now, with a name which isn't used by any
+    // other entry, nor by files left over from a recording which never made
+    // it into the manifest (e.g. after a crash before it was written)
+    async fn new_manifest_entry(&self) -> ManifestEntry {
+        let mut leftovers: Vec<String> = Vec::new();
+        loop {
+            let entry = ManifestEntry::new(|name| {
+                leftovers.iter().any(|leftover| leftover == name)
+                    || self.manifest.entries.iter().any(|entry| entry.name == name)
+            });
+            let mut has_leftovers = false;
+            for path in [
+                entry.get_qmdl_filepath(&self.path),
+                entry.get_analysis_filepath(&self.path),
+            ] {
+                has_leftovers |= try_exists(path).await.unwrap_or(false);
+            }
+            if !has_leftovers {
+                return entry;
+            }
+            warn!(
+                "not naming a recording {}, since files from an earlier one with that name are in the way",
+                entry.name
+            );
+            leftovers.push(entry.name);
+        }
+    }
+
     // Closes the current entry (if needed), creates a new entry based on the
     // current time, and updates the manifest. Returns a tuple of the entry's
     // newly created QMDL file and analysis file.
@@ -249,19 +480,35 @@ impl RecordingStore {
         if self.current_entry.is_some() {
             self.close_current_entry().await?;
         }
-        let new_entry = ManifestEntry::new();
-        let qmdl_filepath = new_entry.get_qmdl_filepath(&self.path);
-        let qmdl_file = File::create(&qmdl_filepath)
+        let new_entry = self.new_manifest_entry().await;
+        let qmdl_file = create_new_file(&new_entry.get_qmdl_filepath(&self.path)).await?;
+        let analysis_file = create_new_file(&new_entry.get_analysis_filepath(&self.path)).await?;
+        self.manifest.entries.push(new_entry);
+        self.current_entry = Some(self.manifest.entries.len() - 1);
+        self.write_manifest().await?;
+        Ok((qmdl_file, analysis_file))
+    }
+
+    // Adds a new, already finished entry holding the given QMDL data, leaving
+    // the current entry (if any) alone. Returns the new entry's name.
+    pub async fn import_entry(&mut self, qmdl_data: &[u8]) -> Result<String, RecordingStoreError> {
+        let mut new_entry = self.new_manifest_entry().await;
+        new_entry.qmdl_size_bytes = qmdl_data.len();
+        new_entry.last_message_time = Some(new_entry.start_time);
+        let mut qmdl_file = create_new_file(&new_entry.get_qmdl_filepath(&self.path)).await?;
+        qmdl_file
+            .write_all(qmdl_data)
             .await
             .map_err(RecordingStoreError::CreateFileError)?;
-        let analysis_filepath = new_entry.get_analysis_filepath(&self.path);
-        let analysis_file = File::create(&analysis_filepath)
+        qmdl_file
+            .flush()
             .await
             .map_err(RecordingStoreError::CreateFileError)?;
+        create_new_file(&new_entry.get_analysis_filepath(&self.path)).await?;
+        let name = new_entry.name.clone();
         self.manifest.entries.push(new_entry);
-        self.current_entry = Some(self.manifest.entries.len() - 1);
         self.write_manifest().await?;
-        Ok((qmdl_file, analysis_file))
+        Ok(name)
     }
 
     // Returns the corresponding QMDL file for a given entry
@@ -288,6 +535,10 @@ impl RecordingStore {
         entry_index: usize,
     ) -> Result<File, RecordingStoreError> {
         let entry = &self.manifest.entries[entry_index];
+        // the summary is rewritten once the new analysis completes
+        remove_file_if_exists(&entry.get_summary_filepath(&self.path))
+            .await
+            .map_err(RecordingStoreError::DeleteFileError)?;
         let file = OpenOptions::new()
             .write(true)
             .truncate(true)
@@ -308,15 +559,18 @@ impl RecordingStore {
         }
     }
 
-    // Sets the given entry's size and updates the last_message_time to now, updating the manifest
+    // Sets the given entry's size and how long it's been recording for, and
+    // updates the last_message_time to now, updating the manifest
     pub async fn update_entry_qmdl_size(
         &mut self,
         entry_index: usize,
         size_bytes: usize,
+        duration: Duration,
     ) -> Result<(), RecordingStoreError> {
-        self.manifest.entries[entry_index].qmdl_size_bytes = size_bytes;
-        self.manifest.entries[entry_index].last_message_time =
-            Some(rayhunter::clock::get_adjusted_now());
+        let entry = &mut self.manifest.entries[entry_index];
+        entry.qmdl_size_bytes = size_bytes;
+        entry.last_message_time = Some(rayhunter::clock::get_adjusted_now());
+        entry.duration_secs = Some(duration.as_secs());
         self.write_manifest().await
     }
 
@@ -368,6 +622,91 @@ impl RecordingStore {
         Ok(())
     }
 
+    pub async fn set_current_start_location(
+        &mut self,
+        location: GeoPoint,
+    ) -> Result<(), RecordingStoreError> {
+        if let Some(idx) = self.current_entry {
+            self.manifest.entries[idx].start_location = Some(location);
+            self.write_manifest().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn set_current_end_location(
+        &mut self,
+        location: GeoPoint,
+    ) -> Result<(), RecordingStoreError> {
+        if let Some(idx) = self.current_entry {
+            self.manifest.entries[idx].end_location = Some(location);
+            self.write_manifest().await?;
+        }
+        Ok(())
+    }
+
+    /// Records that the current entry was paused now, with `qmdl_offset`
+    /// bytes written to it
+    pub async fn begin_current_pause(
+        &mut self,
+        qmdl_offset: usize,
+    ) -> Result<(), RecordingStoreError> {
+        let idx = self
+            .current_entry
+            .ok_or(RecordingStoreError::NoCurrentEntry)?;
+        self.manifest.entries[idx].pauses.push(PauseInterval {
+            start: rayhunter::clock::get_adjusted_now(),
+            end: None,
+            qmdl_offset,
+        });
+        self.write_manifest().await
+    }
+
+    /// Records that the current entry's pause ended now, returning the
+    /// finished pause
+    pub async fn end_current_pause(&mut self) -> Result<PauseInterval, RecordingStoreError> {
+        let idx = self
+            .current_entry
+            .ok_or(RecordingStoreError::NoCurrentEntry)?;
+        let pause = match self.manifest.entries[idx].pauses.last_mut() {
+            Some(pause) if pause.end.is_none() => pause,
+            _ => return Err(RecordingStoreError::NotPaused),
+        };
+        pause.end = Some(rayhunter::clock::get_adjusted_now());
+        let pause = pause.clone();
+        self.write_manifest().await?;
+        Ok(pause)
+    }
+
+    /// Records a jump in the system clock during the current entry
+    pub async fn add_current_clock_jump(
+        &mut self,
+        jump: ClockJump,
+    ) -> Result<(), RecordingStoreError> {
+        let idx = self
+            .current_entry
+            .ok_or(RecordingStoreError::NoCurrentEntry)?;
+        self.manifest.entries[idx].clock_jumps.push(jump);
+        self.write_manifest().await
+    }
+
+    /// Replaces the notes and tags of the entry with the given name
+    pub async fn set_entry_notes(
+        &mut self,
+        name: &str,
+        notes: Option<String>,
+        tags: Vec<String>,
+    ) -> Result<(), RecordingStoreError> {
+        let entry = self
+            .manifest
+            .entries
+            .iter_mut()
+            .find(|entry| entry.name == name)
+            .ok_or(RecordingStoreError::NoSuchEntryError)?;
+        entry.notes = notes;
+        entry.tags = tags;
+        self.write_manifest().await
+    }
+
     pub fn is_current_entry(&self, name: &str) -> bool {
         match self.current_entry {
             Some(idx) => match self.manifest.entries.get(idx) {
@@ -404,6 +743,11 @@ impl RecordingStore {
         remove_file_if_exists(&analysis_filepath)
             .await
             .map_err(RecordingStoreError::DeleteFileError)?;
+        for cache_filepath in entry_to_delete.get_cache_filepaths(&self.path) {
+            remove_file_if_exists(&cache_filepath)
+                .await
+                .map_err(RecordingStoreError::DeleteFileError)?;
+        }
         Ok(())
     }
 
@@ -430,6 +774,12 @@ impl RecordingStore {
                 continue;
             }
 
+            for cache_filepath in entry.get_cache_filepaths(&self.path) {
+                if let Err(e) = remove_file_if_exists(&cache_filepath).await {
+                    log::warn!("failed to remove {cache_filepath:?}: {e:?}");
+                }
+            }
+
             keep.push(false);
         }
 
@@ -440,6 +790,17 @@ impl RecordingStore {
     }
 }
 
+// Creates a file, failing rather than truncating it if it already exists, so a
+// stray file left over from an old recording is never silently overwritten
+async fn create_new_file(path: &Path) -> Result<File, RecordingStoreError> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await
+        .map_err(RecordingStoreError::CreateFileError)
+}
+
 async fn remove_file_if_exists(path: &Path) -> Result<(), io::Error> {
     match tokio::fs::remove_file(path).await {
         Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
@@ -466,6 +827,39 @@ mod tests {
         assert_eq!(loaded_store.manifest.entries.len(), 0);
     }
 
+    #[tokio::test]
+    async fn test_reopen_once_mounted() {
+        let placeholder = make_temp_dir();
+        let dir = make_temp_dir();
+        let mut existing = RecordingStore::create(dir.path()).await.unwrap();
+        existing.new_entry().await.unwrap();
+
+        let mut store = RecordingStore::create(placeholder.path()).await.unwrap();
+        assert!(!store.reopen_if_mounted().await.unwrap());
+
+        // with no expected mount, the real path counts as mounted right away
+        let mut store = store.awaiting_mount(dir.path().to_path_buf());
+        assert!(store.reopen_if_mounted().await.unwrap());
+        assert_eq!(store.path, dir.path());
+        assert_eq!(store.manifest.entries.len(), 1);
+        assert!(store.check_mount().is_ok());
+        assert!(!store.reopen_if_mounted().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_entry_version_matches_binary() {
+        let dir = make_temp_dir();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+        let _ = store.new_entry().await.unwrap();
+        let entry = &store.manifest.entries[store.current_entry.unwrap()];
+        let version = entry.rayhunter_version.as_deref().unwrap();
+        assert_eq!(version, RuntimeMetadata::new().rayhunter_version);
+        // what --version prints is what gets recorded
+        assert!(
+            crate::config::version_string().starts_with(&format!("rayhunter-daemon {version}")),
+        );
+    }
+
     #[tokio::test]
     async fn test_creating_updating_and_closing_entries() {
         let dir = make_temp_dir();
@@ -483,7 +877,7 @@ mod tests {
         );
 
         store
-            .update_entry_qmdl_size(entry_index, 1000)
+            .update_entry_qmdl_size(entry_index, 1000, Duration::from_secs(5))
             .await
             .unwrap();
         let (entry_index, entry) = store
@@ -491,6 +885,7 @@ mod tests {
             .unwrap();
         assert!(entry.last_message_time.is_some());
         assert_eq!(store.manifest.entries[entry_index].qmdl_size_bytes, 1000);
+        assert_eq!(store.manifest.entries[entry_index].duration_secs, Some(5));
         assert_eq!(
             RecordingStore::read_manifest(dir.path()).await.unwrap(),
             store.manifest
@@ -510,7 +905,7 @@ mod tests {
         let _ = store.new_entry().await.unwrap();
         let entry_index = store.current_entry.unwrap();
         store
-            .update_entry_qmdl_size(entry_index, 1000)
+            .update_entry_qmdl_size(entry_index, 1000, Duration::from_secs(5))
             .await
             .unwrap();
         let store = RecordingStore::create(dir.path()).await.unwrap();
@@ -529,6 +924,206 @@ mod tests {
         assert_eq!(store.manifest.entries.len(), 2);
     }
 
+    #[tokio::test]
+    async fn test_rapid_entries_have_distinct_names() {
+        let dir = make_temp_dir();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+        let _ = store.new_entry().await.unwrap();
+        let _ = store.new_entry().await.unwrap();
+        store.import_entry(&[0x7E]).await.unwrap();
+
+        let mut names: Vec<String> = store
+            .manifest
+            .entries
+            .iter()
+            .map(|entry| entry.name.clone())
+            .collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), 3);
+
+        // the suffixed names survive manifest recovery
+        let recovered = RecordingStore::recover(dir.path()).await.unwrap();
+        let mut recovered_names: Vec<String> = recovered
+            .manifest
+            .entries
+            .iter()
+            .map(|entry| entry.name.clone())
+            .collect();
+        recovered_names.sort();
+        assert_eq!(recovered_names, names);
+    }
+
+    #[tokio::test]
+    async fn test_new_entry_skips_leftover_files() {
+        let dir = make_temp_dir();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+        // files from recordings which never made it into the manifest, for
+        // this second and the next in case the clock ticks over meanwhile
+        let now = rayhunter::clock::get_adjusted_now().timestamp();
+        let leftovers = [now.to_string(), (now + 1).to_string()];
+        for name in &leftovers {
+            fs::write(dir.path().join(format!("{name}.qmdl")), b"old")
+                .await
+                .unwrap();
+        }
+
+        let _ = store.new_entry().await.unwrap();
+        store.import_entry(&[0x7E]).await.unwrap();
+        for entry in &store.manifest.entries {
+            assert!(!leftovers.contains(&entry.name), "{}", entry.name);
+        }
+        for name in &leftovers {
+            let old = fs::read(dir.path().join(format!("{name}.qmdl")))
+                .await
+                .unwrap();
+            assert_eq!(old, b"old");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stop_reason() {
+        let dir = make_temp_dir();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+
+        // without a current entry there's nothing to annotate
+        store
+            .set_current_stop_reason("disk full".to_string())
+            .await
+            .unwrap();
+
+        let _ = store.new_entry().await.unwrap();
+        let entry_index = store.current_entry.unwrap();
+        assert!(store.manifest.entries[entry_index].stop_reason.is_none());
+
+        store
+            .set_current_stop_reason("disk full".to_string())
+            .await
+            .unwrap();
+        store.close_current_entry().await.unwrap();
+        let manifest = RecordingStore::read_manifest(dir.path()).await.unwrap();
+        assert_eq!(
+            manifest.entries[entry_index].stop_reason.as_deref(),
+            Some("disk full")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_locations() {
+        let dir = make_temp_dir();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+        let start = GeoPoint {
+            lat: 48.1173,
+            lon: 11.5167,
+            accuracy_m: 4.5,
+        };
+        let end = GeoPoint {
+            lat: -33.85,
+            lon: 151.21,
+            accuracy_m: 10.0,
+        };
+
+        let _ = store.new_entry().await.unwrap();
+        let entry_index = store.current_entry.unwrap();
+        store.set_current_start_location(start).await.unwrap();
+        store.set_current_end_location(end).await.unwrap();
+        store.close_current_entry().await.unwrap();
+
+        let manifest = RecordingStore::read_manifest(dir.path()).await.unwrap();
+        assert_eq!(manifest.entries[entry_index].start_location, Some(start));
+        assert_eq!(manifest.entries[entry_index].end_location, Some(end));
+    }
+
+    #[tokio::test]
+    async fn test_pauses() {
+        let dir = make_temp_dir();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+        assert!(matches!(
+            store.begin_current_pause(0).await,
+            Err(RecordingStoreError::NoCurrentEntry)
+        ));
+
+        let _ = store.new_entry().await.unwrap();
+        let entry_index = store.current_entry.unwrap();
+        assert!(matches!(
+            store.end_current_pause().await,
+            Err(RecordingStoreError::NotPaused)
+        ));
+        store.begin_current_pause(100).await.unwrap();
+        let pause = store.end_current_pause().await.unwrap();
+        assert_eq!(pause.qmdl_offset, 100);
+        assert!(pause.end.is_some());
+        assert!(matches!(
+            store.end_current_pause().await,
+            Err(RecordingStoreError::NotPaused)
+        ));
+        store.begin_current_pause(200).await.unwrap();
+        store.close_current_entry().await.unwrap();
+
+        let manifest = RecordingStore::read_manifest(dir.path()).await.unwrap();
+        let pauses = &manifest.entries[entry_index].pauses;
+        assert_eq!(pauses.len(), 2);
+        assert_eq!(pauses[0], pause);
+        assert_eq!(pauses[1].qmdl_offset, 200);
+        assert_eq!(pauses[1].end, None);
+    }
+
+    #[tokio::test]
+    async fn test_notes() {
+        let dir = make_temp_dir();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+        assert!(matches!(
+            store.set_entry_notes("nope", None, Vec::new()).await,
+            Err(RecordingStoreError::NoSuchEntryError)
+        ));
+
+        let _ = store.new_entry().await.unwrap();
+        let (_, entry) = store.get_current_entry().unwrap();
+        let name = entry.name.clone();
+        assert_eq!(entry.notes, None);
+        assert!(entry.tags.is_empty());
+        store
+            .set_entry_notes(
+                &name,
+                Some("outside the courthouse".to_string()),
+                vec!["suspected".to_string()],
+            )
+            .await
+            .unwrap();
+        store.close_current_entry().await.unwrap();
+
+        // the notes survive reloading the store, e.g. after a restart
+        let store = RecordingStore::load(dir.path()).await.unwrap();
+        let (_, entry) = store.entry_for_name(&name).unwrap();
+        assert_eq!(entry.notes.as_deref(), Some("outside the courthouse"));
+        assert_eq!(entry.tags, vec!["suspected".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_import_entry() {
+        let dir = make_temp_dir();
+        let mut store = RecordingStore::create(dir.path()).await.unwrap();
+        let _ = store.new_entry().await.unwrap();
+        let current_entry = store.current_entry;
+
+        let qmdl_data = vec![0x7E, 0x00, 0x00, 0x00, 0x10, 0x00, 0x7E];
+        let name = store.import_entry(&qmdl_data).await.unwrap();
+        assert_eq!(store.current_entry, current_entry);
+        assert_eq!(store.manifest.entries.len(), 2);
+
+        let (entry_index, entry) = store.entry_for_name(&name).unwrap();
+        assert_eq!(entry.qmdl_size_bytes, qmdl_data.len());
+        assert_eq!(
+            fs::read(entry.get_qmdl_filepath(dir.path())).await.unwrap(),
+            qmdl_data
+        );
+        assert!(store.open_entry_analysis(entry_index).await.is_ok());
+        assert_eq!(
+            RecordingStore::read_manifest(dir.path()).await.unwrap(),
+            store.manifest
+        );
+    }
+
     #[tokio::test]
     async fn test_delete_all_entries() {
         let dir = make_temp_dir();
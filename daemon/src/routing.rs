@@ -0,0 +1,454 @@
+//! The wifi client replaces the device's default route (normally via the
+//! bridge the AP clients sit on) with one via the station interface, adds ip
+//! rules, and rewrites resolv.conf. If any of that is left behind when the
+//! client stops, the web UI becomes unreachable from the AP. We snapshot the
+//! routing state before the client starts and put it back verbatim on
+//! shutdown, regardless of how cleanly the client tore itself down.
+
+use anyhow::{Result, bail};
+use log::{debug, info, warn};
+use tokio::process::Command;
+
+const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+/// A rule from `ip rule show`, e.g. `32766: from all lookup main`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IpRule {
+    pub priority: u32,
+    pub selector: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RoutingSnapshot {
+    /// Default routes as `ip route add` arguments, including their metrics
+    pub default_routes: Vec<String>,
+    pub ip_rules: Vec<IpRule>,
+    /// The same for IPv6, where the wifi client installs the default route
+    /// it learns from router advertisements
+    pub default_routes_v6: Vec<String>,
+    pub ip_rules_v6: Vec<IpRule>,
+    pub resolv_conf: Option<String>,
+}
+
+/// Extracts the default routes from `ip route show` output, dropping the
+/// state flags (and the remaining lifetime of routes learned from router
+/// advertisements) which `ip route add` won't accept back.
+pub fn parse_default_routes(ip_route_output: &str) -> Vec<String> {
+    ip_route_output
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("default"))
+        .map(|line| {
+            let mut words = Vec::new();
+            let mut iter = line.split_whitespace();
+            while let Some(word) = iter.next() {
+                match word {
+                    "linkdown" | "dead" | "offload" => {}
+                    "expires" => {
+                        iter.next();
+                    }
+                    _ => words.push(word),
+                }
+            }
+            words.join(" ")
+        })
+        .collect()
+}
+
+/// Extracts the global-scope addresses from `ip -6 addr show` output,
+/// leaving out link-local ones and addresses still doing duplicate address
+/// detection.
+pub fn parse_global_ipv6_addrs(ip_addr_output: &str) -> Vec<String> {
+    ip_addr_output
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            if words.next()? != "inet6" {
+                return None;
+            }
+            let (addr, _prefix_len) = words.next()?.split_once('/')?;
+            let flags: Vec<_> = words.collect();
+            let global = flags.windows(2).any(|pair| pair == ["scope", "global"]);
+            if !global || flags.contains(&"tentative") {
+                return None;
+            }
+            Some(addr.to_string())
+        })
+        .collect()
+}
+
+/// The global-scope IPv6 addresses on `iface`, e.g. from SLAAC or DHCPv6.
+/// Empty if it has none, or IPv6 isn't available.
+pub async fn global_ipv6_addrs(iface: &str) -> Vec<String> {
+    let mut cmd = args("-6 addr show dev");
+    cmd.push(iface.to_string());
+    match run_ip(&cmd).await {
+        Ok(output) => parse_global_ipv6_addrs(&output),
+        Err(e) => {
+            debug!("{e}");
+            Vec::new()
+        }
+    }
+}
+
+pub fn parse_ip_rules(ip_rule_output: &str) -> Vec<IpRule> {
+    ip_rule_output
+        .lines()
+        .filter_map(|line| {
+            let (priority, selector) = line.split_once(':')?;
+            Some(IpRule {
+                priority: priority.trim().parse().ok()?,
+                selector: selector.split_whitespace().collect::<Vec<_>>().join(" "),
+            })
+        })
+        .collect()
+}
+
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(str::to_string).collect()
+}
+
+/// Default routes via `exclude_iface` are left out
+fn parse_family(
+    ip_route_output: &str,
+    ip_rule_output: &str,
+    exclude_iface: Option<&str>,
+) -> (Vec<String>, Vec<IpRule>) {
+    let default_routes = parse_default_routes(ip_route_output)
+        .into_iter()
+        .filter(|route| {
+            let words: Vec<_> = route.split_whitespace().collect();
+            !exclude_iface.is_some_and(|iface| words.windows(2).any(|pair| pair == ["dev", iface]))
+        })
+        .collect();
+    (default_routes, parse_ip_rules(ip_rule_output))
+}
+
+/// The `ip` invocations which turn one address family's `current` routes
+/// and rules back into `original`. `family` is prepended to each, e.g.
+/// `-6`.
+fn family_restore_commands(
+    family: &[&str],
+    (original_routes, original_rules): (&[String], &[IpRule]),
+    (current_routes, current_rules): (&[String], &[IpRule]),
+) -> Vec<Vec<String>> {
+    let command = |line: &str| {
+        let mut cmd: Vec<String> = family.iter().map(|arg| arg.to_string()).collect();
+        cmd.extend(args(line));
+        cmd
+    };
+    let mut commands = Vec::new();
+    for rule in current_rules.iter().rev() {
+        if !original_rules.contains(rule) {
+            let mut cmd = command("rule del priority");
+            cmd.push(rule.priority.to_string());
+            cmd.extend(args(&rule.selector));
+            commands.push(cmd);
+        }
+    }
+    for route in current_routes.iter().rev() {
+        if !original_routes.contains(route) {
+            let mut cmd = command("route del");
+            cmd.extend(args(route));
+            commands.push(cmd);
+        }
+    }
+    for route in original_routes {
+        if !current_routes.contains(route) {
+            let mut cmd = command("route add");
+            cmd.extend(args(route));
+            commands.push(cmd);
+        }
+    }
+    commands
+}
+
+impl RoutingSnapshot {
+    /// Parses a snapshot from the output of `ip route show` and `ip rule
+    /// show`. Default routes via `exclude_iface` are left out.
+    pub fn parse(
+        ip_route_output: &str,
+        ip_rule_output: &str,
+        resolv_conf: Option<String>,
+        exclude_iface: Option<&str>,
+    ) -> Self {
+        let (default_routes, ip_rules) =
+            parse_family(ip_route_output, ip_rule_output, exclude_iface);
+        RoutingSnapshot {
+            default_routes,
+            ip_rules,
+            resolv_conf,
+            ..Default::default()
+        }
+    }
+
+    /// Adds the IPv6 state from the output of `ip -6 route show` and `ip -6
+    /// rule show`. Default routes via `exclude_iface` are left out.
+    pub fn with_v6(
+        self,
+        ip_route_output: &str,
+        ip_rule_output: &str,
+        exclude_iface: Option<&str>,
+    ) -> Self {
+        let (default_routes_v6, ip_rules_v6) =
+            parse_family(ip_route_output, ip_rule_output, exclude_iface);
+        RoutingSnapshot {
+            default_routes_v6,
+            ip_rules_v6,
+            ..self
+        }
+    }
+
+    /// The `ip` invocations which turn `current` back into this snapshot,
+    /// undoing the wifi client's changes in the reverse of the order it
+    /// makes them: first its ip rules, then its default routes.
+    pub fn restore_commands(&self, current: &RoutingSnapshot) -> Vec<Vec<String>> {
+        let mut commands = family_restore_commands(
+            &[],
+            (&self.default_routes, &self.ip_rules),
+            (&current.default_routes, &current.ip_rules),
+        );
+        commands.extend(family_restore_commands(
+            &["-6"],
+            (&self.default_routes_v6, &self.ip_rules_v6),
+            (&current.default_routes_v6, &current.ip_rules_v6),
+        ));
+        commands
+    }
+
+    async fn read(exclude_iface: Option<&str>) -> Result<Self> {
+        let routes = run_ip(&args("route show")).await?;
+        let rules = run_ip(&args("rule show")).await?;
+        let resolv_conf = tokio::fs::read_to_string(RESOLV_CONF_PATH).await.ok();
+        let snapshot = Self::parse(&routes, &rules, resolv_conf, exclude_iface);
+
+        // not every kernel this runs on has IPv6, and then there's nothing to
+        // snapshot
+        let v6 = async {
+            let routes = run_ip(&args("-6 route show")).await?;
+            let rules = run_ip(&args("-6 rule show")).await?;
+            anyhow::Ok((routes, rules))
+        };
+        match v6.await {
+            Ok((routes, rules)) => Ok(snapshot.with_v6(&routes, &rules, exclude_iface)),
+            Err(e) => {
+                warn!("couldn't read IPv6 routing state, leaving it alone: {e}");
+                Ok(snapshot)
+            }
+        }
+    }
+
+    /// Snapshots the current routing state. Default routes via `sta_iface`
+    /// belong to the wifi client, and can only be present now if a previous
+    /// run didn't clean up after itself, so they're left out.
+    pub async fn capture(sta_iface: &str) -> Result<Self> {
+        Self::read(Some(sta_iface)).await
+    }
+
+    /// Puts the routing state back the way it was when the snapshot was
+    /// captured. Only what differs is touched, so calling this again (or
+    /// after the wifi client already cleaned up properly) is harmless.
+    pub async fn restore(&self) {
+        let current = match Self::read(None).await {
+            Ok(current) => current,
+            Err(e) => {
+                warn!("couldn't read routing state, not restoring it: {e}");
+                return;
+            }
+        };
+
+        let commands = self.restore_commands(&current);
+        if !commands.is_empty() {
+            info!("restoring routing state from before wifi client start");
+        }
+        for cmd in commands {
+            if let Err(e) = run_ip(&cmd).await {
+                warn!("{e}");
+            }
+        }
+        if let Some(resolv_conf) = &self.resolv_conf
+            && current.resolv_conf.as_ref() != Some(resolv_conf)
+            && let Err(e) = tokio::fs::write(RESOLV_CONF_PATH, resolv_conf).await
+        {
+            warn!("couldn't restore {RESOLV_CONF_PATH}: {e}");
+        }
+    }
+}
+
+async fn run_ip(args: &[String]) -> Result<String> {
+    let out = Command::new("ip").args(args).output().await?;
+    if !out.status.success() {
+        bail!(
+            "ip {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL_ROUTES: &str = "\
+default via 192.168.1.1 dev bridge0 metric 10
+default via 10.0.0.1 dev rmnet_data0 proto static metric 200 linkdown
+192.168.1.0/24 dev bridge0 proto kernel scope link src 192.168.1.1
+";
+
+    const ORIGINAL_RULES: &str = "\
+0:	from all lookup local
+32766:	from all lookup main
+32767:	from all lookup default
+";
+
+    fn snapshot(routes: &str, rules: &str) -> RoutingSnapshot {
+        RoutingSnapshot::parse(routes, rules, None, Some("wlan1"))
+    }
+
+    fn command_lines(commands: Vec<Vec<String>>) -> Vec<String> {
+        commands.into_iter().map(|cmd| cmd.join(" ")).collect()
+    }
+
+    #[test]
+    fn test_parse_multiple_default_routes_with_metrics() {
+        let snapshot = snapshot(ORIGINAL_ROUTES, ORIGINAL_RULES);
+        assert_eq!(
+            snapshot.default_routes,
+            vec![
+                "default via 192.168.1.1 dev bridge0 metric 10",
+                "default via 10.0.0.1 dev rmnet_data0 proto static metric 200",
+            ]
+        );
+        assert_eq!(
+            snapshot.ip_rules[1],
+            IpRule {
+                priority: 32766,
+                selector: "from all lookup main".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_snapshot_ignores_leftover_station_routes() {
+        let routes = "default via 172.16.0.1 dev wlan1 metric 5\n".to_string() + ORIGINAL_ROUTES;
+        assert_eq!(snapshot(&routes, "").default_routes.len(), 2);
+    }
+
+    #[test]
+    fn test_restore_commands() {
+        let original = snapshot(ORIGINAL_ROUTES, ORIGINAL_RULES);
+        // the wifi client deleted the bridge route, added its own default and
+        // an ip rule for the station's table
+        let current = RoutingSnapshot::parse(
+            "\
+default via 172.16.0.1 dev wlan1 metric 5
+default via 10.0.0.1 dev rmnet_data0 proto static metric 200
+",
+            "\
+0:	from all lookup local
+100:	from 172.16.0.23 lookup 100
+32766:	from all lookup main
+32767:	from all lookup default
+",
+            None,
+            None,
+        );
+        assert_eq!(
+            command_lines(original.restore_commands(&current)),
+            vec![
+                "rule del priority 100 from 172.16.0.23 lookup 100",
+                "route del default via 172.16.0.1 dev wlan1 metric 5",
+                "route add default via 192.168.1.1 dev bridge0 metric 10",
+            ]
+        );
+    }
+
+    const ORIGINAL_ROUTES_V6: &str = "\
+fe80::/64 dev bridge0 proto kernel metric 256 pref medium
+default via fe80::1 dev rmnet_data0 proto static metric 1024 pref medium
+";
+
+    const ORIGINAL_RULES_V6: &str = "\
+0:	from all lookup local
+32766:	from all lookup main
+";
+
+    #[test]
+    fn test_parse_v6_default_routes() {
+        let routes = "\
+default via fe80::aa:bbff:fecc:ddee dev wlan1 proto ra metric 1024 expires 1789sec hoplimit 64 pref medium
+"
+        .to_string()
+            + ORIGINAL_ROUTES_V6;
+        let snapshot = snapshot("", "").with_v6(&routes, ORIGINAL_RULES_V6, Some("wlan1"));
+        assert_eq!(
+            snapshot.default_routes_v6,
+            vec!["default via fe80::1 dev rmnet_data0 proto static metric 1024 pref medium"]
+        );
+        assert_eq!(snapshot.ip_rules_v6.len(), 2);
+
+        // the lifetime left on a router advertisement's route can't be added
+        // back
+        assert_eq!(
+            parse_default_routes(&routes)[0],
+            "default via fe80::aa:bbff:fecc:ddee dev wlan1 proto ra metric 1024 hoplimit 64 pref medium"
+        );
+    }
+
+    #[test]
+    fn test_restore_commands_v6() {
+        let original = snapshot(ORIGINAL_ROUTES, ORIGINAL_RULES).with_v6(
+            ORIGINAL_ROUTES_V6,
+            ORIGINAL_RULES_V6,
+            Some("wlan1"),
+        );
+        let current = RoutingSnapshot::parse(ORIGINAL_ROUTES, ORIGINAL_RULES, None, None).with_v6(
+            "\
+default via fe80::aa:bbff:fecc:ddee dev wlan1 proto ra metric 5 pref medium
+",
+            "\
+0:	from all lookup local
+100:	from 2001:db8::23 lookup 100
+32766:	from all lookup main
+",
+            None,
+        );
+        assert_eq!(
+            command_lines(original.restore_commands(&current)),
+            vec![
+                "-6 rule del priority 100 from 2001:db8::23 lookup 100",
+                "-6 route del default via fe80::aa:bbff:fecc:ddee dev wlan1 proto ra metric 5 pref medium",
+                "-6 route add default via fe80::1 dev rmnet_data0 proto static metric 1024 pref medium",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_global_ipv6_addrs() {
+        let output = "\
+5: wlan1: <BROADCAST,MULTICAST,UP,LOWER_UP> mtu 1500 state UP qlen 1000
+    inet6 2001:db8:1:2:aabb:ccff:fedd:eeff/64 scope global dynamic mngtmpaddr noprefixroute
+       valid_lft 86390sec preferred_lft 14390sec
+    inet6 2001:db8:1:2::99/128 scope global tentative dynamic noprefixroute
+       valid_lft 3599sec preferred_lft 3599sec
+    inet6 fd00::23/64 scope global
+       valid_lft forever preferred_lft forever
+    inet6 fe80::aabb:ccff:fedd:eeff/64 scope link
+       valid_lft forever preferred_lft forever
+";
+        assert_eq!(
+            parse_global_ipv6_addrs(output),
+            vec!["2001:db8:1:2:aabb:ccff:fedd:eeff", "fd00::23"]
+        );
+        assert!(parse_global_ipv6_addrs("").is_empty());
+    }
+
+    #[test]
+    fn test_restore_is_idempotent() {
+        let original = snapshot(ORIGINAL_ROUTES, ORIGINAL_RULES);
+        let restored = RoutingSnapshot::parse(ORIGINAL_ROUTES, ORIGINAL_RULES, None, None);
+        assert!(original.restore_commands(&restored).is_empty());
+    }
+}
@@ -0,0 +1,215 @@
+//! A one-shot check, run at startup, that everything rayhunter depends on is
+//! wired up. The result is logged and flashed on the display so a broken setup
+//! is obvious right away rather than hours into a deployment.
+
+use std::path::Path;
+
+use log::{error, info, warn};
+
+use crate::config::Config;
+use crate::display;
+
+const STORE_PROBE_FILENAME: &str = ".self_test_probe";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckOutcome {
+    Pass,
+    Fail(String),
+    Skipped(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub outcome: CheckOutcome,
+}
+
+#[derive(Debug, Default)]
+pub struct SelfTestReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl SelfTestReport {
+    pub fn record(&mut self, name: &'static str, result: Result<(), String>) {
+        let outcome = match result {
+            Ok(()) => CheckOutcome::Pass,
+            Err(reason) => CheckOutcome::Fail(reason),
+        };
+        self.checks.push(CheckResult { name, outcome });
+    }
+
+    pub fn skip(&mut self, name: &'static str, reason: &'static str) {
+        self.checks.push(CheckResult {
+            name,
+            outcome: CheckOutcome::Skipped(reason),
+        });
+    }
+
+    /// True if no check failed. Skipped checks don't count against the verdict.
+    pub fn passed(&self) -> bool {
+        !self
+            .checks
+            .iter()
+            .any(|check| matches!(check.outcome, CheckOutcome::Fail(_)))
+    }
+
+    pub fn log(&self) {
+        for check in &self.checks {
+            match &check.outcome {
+                CheckOutcome::Pass => info!("self-test: {} ok", check.name),
+                CheckOutcome::Fail(reason) => error!("self-test: {} FAILED: {reason}", check.name),
+                CheckOutcome::Skipped(reason) => {
+                    info!("self-test: {} skipped ({reason})", check.name)
+                }
+            }
+        }
+        let failed = self
+            .checks
+            .iter()
+            .filter(|check| matches!(check.outcome, CheckOutcome::Fail(_)))
+            .count();
+        if failed == 0 {
+            info!("self-test passed ({} checks)", self.checks.len());
+        } else {
+            warn!(
+                "self-test failed: {failed} of {} checks failed",
+                self.checks.len()
+            );
+        }
+    }
+}
+
+/// Creates and removes a probe file in the QMDL store directory.
+async fn check_store_writable(store_path: &str) -> Result<(), String> {
+    let probe_path = Path::new(store_path).join(STORE_PROBE_FILENAME);
+    tokio::fs::write(&probe_path, b"rayhunter")
+        .await
+        .map_err(|e| format!("couldn't write to {store_path}: {e}"))?;
+    tokio::fs::remove_file(&probe_path)
+        .await
+        .map_err(|e| format!("couldn't clean up {probe_path:?}: {e}"))
+}
+
+/// Opens the display device for writing, without actually writing anything
+/// to it.
+async fn check_display_writable(path: &str) -> Result<(), String> {
+    tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("couldn't open {path} for writing: {e}"))
+}
+
+fn check_wifi_prerequisites(config: &Config) -> Result<(), String> {
+    let wifi_config = config.wifi_config();
+    let mut missing = Vec::new();
+    if wifi_config.wpa_supplicant_bin.is_none() {
+        missing.push("wpa_supplicant");
+    }
+    if wifi_config.iw_bin.is_none() {
+        missing.push("iw");
+    }
+    if wifi_config.udhcpc_bin.is_none() {
+        missing.push("udhcpc");
+    }
+    if !missing.is_empty() {
+        return Err(format!("missing binaries: {}", missing.join(", ")));
+    }
+    if config.wifi_ssid.is_none() {
+        return Err("no network configured".to_string());
+    }
+    Ok(())
+}
+
+fn check_ntfy_url(url: &str) -> Result<(), String> {
+    let parsed = url::Url::parse(url).map_err(|e| format!("invalid ntfy_url {url:?}: {e}"))?;
+    match parsed.scheme() {
+        "http" | "https" => Ok(()),
+        scheme => Err(format!("unsupported ntfy_url scheme {scheme:?}")),
+    }
+}
+
+/// Runs the checks which don't depend on the diag device, adding them to
+/// `report`. The diag device is checked by whoever opens it, since a failure
+/// there aborts startup anyway.
+pub async fn run_self_test(config: &Config, report: &mut SelfTestReport) {
+    report.record(
+        "qmdl store",
+        check_store_writable(&config.qmdl_store_path).await,
+    );
+
+    if config.debug_mode {
+        report.skip("display", "debug mode");
+    } else if config.developer_mode {
+        report.skip("display", "developer mode");
+    } else if config.ui_level == 0 {
+        report.skip("display", "invisible mode");
+    } else if let Some(path) = display::backend_for_config(config).device_path() {
+        report.record("display", check_display_writable(path).await);
+    } else {
+        report.skip("display", "no framebuffer on this device");
+    }
+
+    if config.developer_mode {
+        report.skip("wifi client", "developer mode");
+    } else if config.wifi_enabled {
+        report.record("wifi client", check_wifi_prerequisites(config));
+    } else {
+        report.skip("wifi client", "disabled");
+    }
+
+    match config.ntfy_url.as_deref() {
+        Some(url) if !url.is_empty() => report.record("ntfy", check_ntfy_url(url)),
+        _ => report.skip("ntfy", "no ntfy_url configured"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_report_passes() {
+        assert!(SelfTestReport::default().passed());
+    }
+
+    #[test]
+    fn test_verdict_aggregates_checks() {
+        let mut report = SelfTestReport::default();
+        report.record("diag device", Ok(()));
+        report.skip("wifi client", "disabled");
+        assert!(report.passed());
+
+        report.record("display", Err("couldn't open /dev/fb0".to_string()));
+        report.record("qmdl store", Ok(()));
+        assert!(!report.passed());
+        assert_eq!(report.checks.len(), 4);
+        assert_eq!(
+            report.checks[2].outcome,
+            CheckOutcome::Fail("couldn't open /dev/fb0".to_string())
+        );
+    }
+
+    #[test]
+    fn test_check_ntfy_url() {
+        assert!(check_ntfy_url("https://ntfy.sh/my-topic").is_ok());
+        assert!(check_ntfy_url("ftp://ntfy.sh/my-topic").is_err());
+        assert!(check_ntfy_url("not a url").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_store_writable_check() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        assert!(check_store_writable(path).await.is_ok());
+        assert!(!dir.path().join(STORE_PROBE_FILENAME).exists());
+
+        let missing = dir.path().join("does-not-exist");
+        assert!(
+            check_store_writable(missing.to_str().unwrap())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -5,41 +5,87 @@ use async_zip::tokio::write::ZipFileWriter;
 use axum::Json;
 use axum::body::Body;
 use axum::extract::Path;
+use axum::extract::Query;
 use axum::extract::State;
 use axum::http::header::{self, CONTENT_LENGTH, CONTENT_TYPE};
 use axum::http::{HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
 use chrono::{DateTime, Local};
 use log::{error, warn};
+use rayhunter::analysis::analyzer::AnalyzerInfo;
+use rayhunter::pcap::{MessageFilter, PcapFormat};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::fs::write;
 use tokio::io::{AsyncReadExt, copy, duplex};
-use tokio::sync::RwLock;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::{RwLock, broadcast};
 use tokio_util::compat::FuturesAsyncWriteCompatExt;
 use tokio_util::io::ReaderStream;
 use tokio_util::sync::CancellationToken;
 
 use crate::analysis::{AnalysisCtrlMessage, AnalysisStatus};
+use crate::ap_clients::ApClientRegistry;
+use crate::battery::BatteryProbe;
+use crate::capture_rate::CaptureRateTracker;
 use crate::config::Config;
-use crate::diag::DiagDeviceCtrlMessage;
+use crate::daemon_state::DaemonStateTracker;
+use crate::diag::{DiagDeviceCtrlMessage, LiveQmdlMessage};
+use crate::diag_multiplexer::DiagMultiplexer;
 use crate::display::DisplayState;
-use crate::notifications::DEFAULT_NOTIFICATION_TIMEOUT;
+use crate::event_store::EventStore;
+use crate::firewall::FirewallState;
+use crate::gsmtap_stream::parse_destination;
+use crate::live_tail::LiveTail;
+use crate::location::LocationTracker;
+use crate::notifications::{
+    Notification, NotificationType, SinkTestResult, notification_sinks, send_test_notification,
+};
 use crate::pcap::generate_pcap_data;
-use crate::qmdl_store::RecordingStore;
+use crate::qmdl_store::{RecordingStore, RecordingStoreError};
+use crate::stats::StatsCache;
+use crate::wifi_scan::WifiScanLimiter;
+use crate::wifi_verify::{VERIFY_TIMEOUT, commit_if_verified, test_associate, verification_needed};
 
 pub struct ServerState {
     pub config_path: String,
     pub config: Config,
     pub qmdl_store_lock: Arc<RwLock<RecordingStore>>,
     pub diag_device_ctrl_sender: Sender<DiagDeviceCtrlMessage>,
+    pub live_qmdl_sender: broadcast::Sender<LiveQmdlMessage>,
+    pub live_tail: LiveTail,
+    /// How fast the current recording is growing
+    pub capture_rate: CaptureRateTracker,
+    /// The latest GPS fix, if location tagging is enabled
+    pub location: LocationTracker,
+    /// Whether monitor-only analysis is running
+    pub monitoring: Arc<RwLock<bool>>,
+    pub diag_multiplexer: DiagMultiplexer,
     pub analysis_status_lock: Arc<RwLock<AnalysisStatus>>,
     pub analysis_sender: Sender<AnalysisCtrlMessage>,
     pub daemon_restart_token: CancellationToken,
+    /// Cancelled to stop the daemon without restarting it. A child of
+    /// daemon_restart_token, so it's also cancelled by a restart.
+    pub daemon_shutdown_token: CancellationToken,
+    /// Cancelled, along with daemon_restart_token, to reboot the device once
+    /// the daemon has shut down
+    pub device_reboot_token: CancellationToken,
     pub ui_update_sender: Option<Sender<DisplayState>>,
     pub wifi_status: Arc<RwLock<wifi_station::WifiStatus>>,
-    pub wifi_scan_lock: tokio::sync::Mutex<()>,
+    pub wifi_scan: WifiScanLimiter<Vec<wifi_station::WifiNetwork>>,
+    pub daemon_state: Arc<DaemonStateTracker>,
+    pub event_store_lock: Arc<RwLock<EventStore>>,
+    /// Every client which has connected to the device's own AP
+    pub ap_clients_lock: Arc<RwLock<ApClientRegistry>>,
+    pub firewall_state: Arc<RwLock<FirewallState>>,
+    /// Whether the wifi client is behind a captive portal
+    pub captive_portal: Arc<RwLock<bool>>,
+    /// Limits how many QMDL to PCAP conversions run at once
+    pub pcap_conversion_semaphore: Arc<tokio::sync::Semaphore>,
+    /// Where the battery state is read from
+    pub battery: BatteryProbe,
+    /// The latest disk, memory and battery sample for /api/system-stats
+    pub stats_cache: StatsCache,
 }
 
 #[cfg_attr(feature = "apidocs", utoipa::path(
@@ -142,6 +188,20 @@ pub async fn get_config(
     Ok(Json(config))
 }
 
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    get,
+    path = "/api/analyzers",
+    tag = "Configuration",
+    responses(
+        (status = StatusCode::OK, description = "Success", body = Vec<AnalyzerInfo>)
+    ),
+    summary = "List analyzers",
+    description = "Describe every analyzer Rayhunter has, enabled or not: the setting which enables it, its name, description and version, the severities of the events it can emit, and its other settings with their current values."
+))]
+pub async fn get_analyzers(State(state): State<Arc<ServerState>>) -> Json<Vec<AnalyzerInfo>> {
+    Json(state.config.analyzers.analyzer_info())
+}
+
 #[cfg_attr(feature = "apidocs", utoipa::path(
     post,
     path = "/api/config",
@@ -152,6 +212,7 @@ pub async fn get_config(
     ),
     responses(
         (status = StatusCode::ACCEPTED, description = "Success"),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid GSMTAP stream destination, or the new wifi credentials failed to connect"),
         (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to parse or write config file"),
         (status = 422, description = "Failed to deserialize JSON body")
     ),
@@ -162,6 +223,15 @@ pub async fn set_config(
     State(state): State<Arc<ServerState>>,
     Json(config): Json<Config>,
 ) -> Result<(StatusCode, String), (StatusCode, String)> {
+    if config.gsmtap_stream_enabled
+        && let Err(e) = parse_destination(&config.gsmtap_stream_destination)
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("invalid gsmtap_stream_destination: {e}"),
+        ));
+    }
+
     let mut config_to_write = config.clone();
     config_to_write.wifi_ssid = None;
     config_to_write.wifi_password = None;
@@ -174,14 +244,32 @@ pub async fn set_config(
         )
     })?;
 
-    write(&state.config_path, config_str).await.map_err(|err| {
+    // Nothing is written until new wifi credentials have connected, so a
+    // failed check leaves the previous, working ones in place.
+    let commit = async {
+        write(&state.config_path, config_str).await.map_err(|err| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to write config file: {err}"),
+            )
+        })?;
+        if !state.config.developer_mode {
+            wifi_station::update_wpa_conf(&config.wifi_config()).await;
+        }
+        Ok::<_, (StatusCode, String)>(())
+    };
+    commit_if_verified(
+        verification_needed(&state.config, &config),
+        test_associate(&config, VERIFY_TIMEOUT),
+        commit,
+    )
+    .await
+    .map_err(|e| {
         (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("failed to write config file: {err}"),
+            StatusCode::BAD_REQUEST,
+            format!("couldn't connect with the new wifi settings, keeping the previous ones: {e}"),
         )
-    })?;
-
-    wifi_station::update_wpa_conf(&config.wifi_config()).await;
+    })??;
 
     // Trigger daemon restart after writing config
     state.daemon_restart_token.cancel();
@@ -191,55 +279,46 @@ pub async fn set_config(
     ))
 }
 
+/// Response for POST /api/test-notification
+#[derive(Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct TestNotificationResponse {
+    /// How delivery went over each configured notification channel
+    pub sinks: Vec<SinkTestResult>,
+}
+
+// Allowed in debug mode: this doesn't change anything on the device, and
+// checking that notifications get through is part of setting it up.
 #[cfg_attr(feature = "apidocs", utoipa::path(
     post,
     path = "/api/test-notification",
     tag = "Configuration",
     responses(
-        (status = StatusCode::OK, description = "Success"),
-        (status = StatusCode::BAD_REQUEST, description = "No notification URL set"),
-        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Failed to send HTTP request. Ensure your device can reach the internet.")
+        (status = StatusCode::OK, description = "Sent over each channel, successfully or not", body = TestNotificationResponse),
+        (status = StatusCode::BAD_REQUEST, description = "No notification channels configured")
     ),
-    summary = "Test ntfy notification",
-    description = "Send a test notification to the ntfy_url in the running configuration for Rayhunter."
+    summary = "Test notifications",
+    description = "Send a test notification over each notification channel in the running configuration for Rayhunter, like ntfy, and report whether each delivery succeeded. Delivery is attempted once, without retries."
 ))]
 pub async fn test_notification(
     State(state): State<Arc<ServerState>>,
-) -> Result<(StatusCode, String), (StatusCode, String)> {
-    let url = state.config.ntfy_url.as_ref().ok_or((
-        StatusCode::BAD_REQUEST,
-        "No notification URL configured".to_string(),
-    ))?;
-
-    if url.is_empty() {
+) -> Result<Json<TestNotificationResponse>, (StatusCode, String)> {
+    let sinks = notification_sinks(state.config.ntfy_url.clone());
+    if sinks.is_empty() {
         return Err((
             StatusCode::BAD_REQUEST,
-            "Notification URL is empty".to_string(),
+            "No notification channels configured".to_string(),
         ));
     }
 
-    let http_client = reqwest::Client::new();
-    let message = "Test notification from Rayhunter".to_string();
-
-    crate::notifications::send_notification(
-        &http_client,
-        url,
-        message,
-        DEFAULT_NOTIFICATION_TIMEOUT,
-    )
-    .await
-    .map(|()| {
-        (
-            StatusCode::OK,
-            "Test notification sent successfully".to_string(),
-        )
-    })
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to send test notification: {e}"),
-        )
-    })
+    let notification = Notification::new(
+        NotificationType::Warning,
+        "Test notification from Rayhunter".to_string(),
+        None,
+    );
+    Ok(Json(TestNotificationResponse {
+        sinks: send_test_notification(&sinks, &notification).await,
+    }))
 }
 
 /// Response for GET /api/time
@@ -305,6 +384,132 @@ pub async fn set_time_offset(Json(req): Json<SetTimeOffsetRequest>) -> StatusCod
     StatusCode::OK
 }
 
+/// The longest notes a recording can have, in characters. They're kept in
+/// the manifest, which is rewritten every time a recording grows.
+pub const MAX_NOTES_CHARS: usize = 4096;
+/// The most tags a recording can have
+pub const MAX_TAGS: usize = 20;
+/// The longest a tag can be, in characters
+pub const MAX_TAG_CHARS: usize = 64;
+
+/// Request for POST /api/recording/{name}/notes
+#[derive(Deserialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct RecordingNotesRequest {
+    /// Free-form notes about the recording, or null to clear them
+    pub notes: Option<String>,
+    /// Short labels for the recording, replacing any it already has
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    post,
+    path = "/api/recording/{name}/notes",
+    tag = "Recordings",
+    request_body(
+        content = RecordingNotesRequest
+    ),
+    responses(
+        (status = StatusCode::ACCEPTED, description = "Notes saved"),
+        (status = StatusCode::BAD_REQUEST, description = "Notes longer than 4096 characters, more than 20 tags, or a tag longer than 64 characters"),
+        (status = StatusCode::NOT_FOUND, description = "Could not find recording {name}"),
+        (status = StatusCode::FORBIDDEN, description = "System is in debug mode"),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Unable to update the manifest")
+    ),
+    params(
+        ("name" = String, Path, description = "The name of the recording to annotate")
+    ),
+    summary = "Set recording notes",
+    description = "Replace the notes and tags of the recording {name}. They're kept in the manifest, and included in the recording's ZIP download. Blank notes and tags are dropped."
+))]
+pub async fn set_recording_notes(
+    State(state): State<Arc<ServerState>>,
+    Path(name): Path<String>,
+    Json(req): Json<RecordingNotesRequest>,
+) -> Result<(StatusCode, String), (StatusCode, String)> {
+    if state.config.debug_mode {
+        return Err((StatusCode::FORBIDDEN, "server is in debug mode".to_string()));
+    }
+    let notes = req.notes.filter(|notes| !notes.trim().is_empty());
+    let tags: Vec<String> = req
+        .tags
+        .iter()
+        .map(|tag| tag.trim())
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .collect();
+    if notes
+        .as_ref()
+        .is_some_and(|notes| notes.chars().count() > MAX_NOTES_CHARS)
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("notes can't be longer than {MAX_NOTES_CHARS} characters"),
+        ));
+    }
+    if tags.len() > MAX_TAGS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("a recording can't have more than {MAX_TAGS} tags"),
+        ));
+    }
+    if let Some(tag) = tags.iter().find(|tag| tag.chars().count() > MAX_TAG_CHARS) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("tag \"{tag}\" is longer than {MAX_TAG_CHARS} characters"),
+        ));
+    }
+    match state
+        .qmdl_store_lock
+        .write()
+        .await
+        .set_entry_notes(&name, notes, tags)
+        .await
+    {
+        Ok(()) => Ok((StatusCode::ACCEPTED, "ok".to_string())),
+        Err(RecordingStoreError::NoSuchEntryError) => Err((
+            StatusCode::NOT_FOUND,
+            format!("no recording with name {name}"),
+        )),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("couldn't save notes: {e}"),
+        )),
+    }
+}
+
+// The contents of the notes file in a recording's ZIP, if it has any notes or
+// tags
+fn notes_file_contents(notes: Option<&str>, tags: &[String]) -> Option<String> {
+    if notes.is_none() && tags.is_empty() {
+        return None;
+    }
+    let mut contents = String::new();
+    if !tags.is_empty() {
+        contents.push_str(&format!("Tags: {}\n", tags.join(", ")));
+    }
+    if let Some(notes) = notes {
+        if !contents.is_empty() {
+            contents.push('\n');
+        }
+        contents.push_str(notes);
+        contents.push('\n');
+    }
+    Some(contents)
+}
+
+/// The comment on ZIPs from /api/zip with identifiers redacted
+pub const REDACTED_ZIP_COMMENT: &str = "IMSIs, IMEIs and phone numbers have been redacted from \
+    this recording's PCAP. The original QMDL file is left out, since it can't be redacted.";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RedactQuery {
+    /// Whether to redact IMSIs, IMEIs and phone numbers, overriding the
+    /// redact_identifiers setting
+    pub redact: Option<bool>,
+}
+
 #[cfg_attr(feature = "apidocs", utoipa::path(
     get,
     path = "/api/zip/{name}",
@@ -315,17 +520,20 @@ pub async fn set_time_offset(Json(req): Json<SetTimeOffsetRequest>) -> StatusCod
         (status = StatusCode::SERVICE_UNAVAILABLE, description = "QMDL file is empty, or error opening file")
     ),
     params(
-        ("name" = String, Path, description = "QMDL filename to convert and download")
+        ("name" = String, Path, description = "QMDL filename to convert and download"),
+        ("redact" = Option<bool>, Query, description = "Redact IMSIs, IMEIs and phone numbers. Defaults to the redact_identifiers setting.")
     ),
     summary = "Download a ZIP file",
-    description = "Stream a ZIP file to the client which contains the QMDL file {name} and a PCAP generated from the same file."
+    description = "Stream a ZIP file to the client which contains the QMDL file {name} and a PCAP generated from the same file, along with a text file of its notes and tags if it has any. When redacting, the QMDL file is left out and the ZIP says so in its comment."
 ))]
 pub async fn get_zip(
     State(state): State<Arc<ServerState>>,
     Path(entry_name): Path<String>,
+    Query(query): Query<RedactQuery>,
 ) -> Result<Response, (StatusCode, String)> {
     let qmdl_idx = entry_name.trim_end_matches(".zip").to_owned();
-    let (entry_index, qmdl_size_bytes) = {
+    let redact = query.redact.unwrap_or(state.config.redact_identifiers);
+    let (entry_index, qmdl_size_bytes, pauses, notes) = {
         let qmdl_store = state.qmdl_store_lock.read().await;
         let (entry_index, entry) = qmdl_store.entry_for_name(&qmdl_idx).ok_or((
             StatusCode::NOT_FOUND,
@@ -339,7 +547,12 @@ pub async fn get_zip(
             ));
         }
 
-        (entry_index, entry.qmdl_size_bytes)
+        (
+            entry_index,
+            entry.qmdl_size_bytes,
+            entry.pauses.clone(),
+            notes_file_contents(entry.notes.as_deref(), &entry.tags),
+        )
     };
 
     let qmdl_store_lock = state.qmdl_store_lock.clone();
@@ -349,9 +562,12 @@ pub async fn get_zip(
     tokio::spawn(async move {
         let result: Result<(), Error> = async {
             let mut zip = ZipFileWriter::with_tokio(writer);
+            if redact {
+                zip.comment(REDACTED_ZIP_COMMENT.to_string());
+            }
 
-            // Add QMDL file
-            {
+            // Add QMDL file, which can't be redacted
+            if !redact {
                 let entry =
                     ZipEntryBuilder::new(format!("{qmdl_idx}.qmdl").into(), Compression::Stored);
                 // FuturesAsyncWriteCompatExt::compat_write because async-zip's entrystream does
@@ -373,8 +589,12 @@ pub async fn get_zip(
 
             // Add PCAP file
             {
-                let entry =
-                    ZipEntryBuilder::new(format!("{qmdl_idx}.pcapng").into(), Compression::Stored);
+                let pcap_name = if redact {
+                    format!("{qmdl_idx}-redacted.pcapng")
+                } else {
+                    format!("{qmdl_idx}.pcapng")
+                };
+                let entry = ZipEntryBuilder::new(pcap_name.into(), Compression::Stored);
                 let mut entry_writer = zip.write_entry_stream(entry).await?.compat_write();
 
                 let qmdl_file_for_pcap = {
@@ -385,8 +605,16 @@ pub async fn get_zip(
                         .take(qmdl_size_bytes as u64)
                 };
 
-                if let Err(e) =
-                    generate_pcap_data(&mut entry_writer, qmdl_file_for_pcap, qmdl_size_bytes).await
+                if let Err(e) = generate_pcap_data(
+                    &mut entry_writer,
+                    qmdl_file_for_pcap,
+                    qmdl_size_bytes,
+                    PcapFormat::PcapNg,
+                    redact,
+                    MessageFilter::default(),
+                    &pauses,
+                )
+                .await
                 {
                     // if we fail to generate the PCAP file, we should still continue and give the
                     // user the QMDL.
@@ -396,6 +624,16 @@ pub async fn get_zip(
                 entry_writer.into_inner().close().await?;
             }
 
+            // Add the notes, which the user wrote themselves so are left as
+            // they are when redacting
+            if let Some(notes) = notes {
+                let entry = ZipEntryBuilder::new(
+                    format!("{qmdl_idx}-notes.txt").into(),
+                    Compression::Stored,
+                );
+                zip.write_entry_whole(entry, notes.as_bytes()).await?;
+            }
+
             zip.close().await?;
             Ok(())
         }
@@ -411,45 +649,31 @@ pub async fn get_zip(
     Ok((headers, body).into_response())
 }
 
-#[cfg_attr(feature = "apidocs", utoipa::path(
-    get,
-    path = "/api/wifi-status",
-    tag = "Configuration",
-    responses(
-        (status = StatusCode::OK, description = "Success", body = wifi_station::WifiStatus)
-    ),
-    summary = "Get wifi status",
-    description = "Show the status of the wifi client."
-))]
-pub async fn get_wifi_status(
-    State(state): State<Arc<ServerState>>,
-) -> Json<wifi_station::WifiStatus> {
-    let status = state.wifi_status.read().await;
-    Json(status.clone())
-}
-
 #[cfg_attr(feature = "apidocs", utoipa::path(
     post,
     path = "/api/wifi-scan",
     tag = "Configuration",
     responses(
         (status = StatusCode::OK, description = "Scan success", body = inline(Vec<wifi_station::WifiNetwork>), content_type = "application/json"),
-        (status = StatusCode::TOO_MANY_REQUESTS, description = "Scan already in progress"),
+        (status = StatusCode::TOO_MANY_REQUESTS, description = "The last scan was too recent. Its results are returned, and the Retry-After header says how many seconds until another scan is allowed.", body = inline(Vec<wifi_station::WifiNetwork>), content_type = "application/json"),
         (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Scan failed"),
+        (status = StatusCode::SERVICE_UNAVAILABLE, description = "There's no wifi client in developer mode"),
     ),
     summary = "Wifi SSID scan",
-    description = "Poll for a list of available wifi networks. Returns an array of WifiNetwork objects."
+    description = "Poll for a list of available wifi networks. Returns an array of WifiNetwork objects. Requests made during a scan wait for it and get its results, and requests made within wifi_scan_cooldown_secs of the last scan get its results without scanning again."
 ))]
 pub async fn scan_wifi(
     State(state): State<Arc<ServerState>>,
-) -> Result<Json<Vec<wifi_station::WifiNetwork>>, (StatusCode, String)> {
-    let _guard = state.wifi_scan_lock.try_lock().map_err(|_| {
-        (
-            StatusCode::TOO_MANY_REQUESTS,
-            "WiFi scan already in progress".to_string(),
-        )
-    })?;
-    let networks = wifi_station::scan_wifi_networks(wifi_station::STA_IFACE)
+) -> Result<Response, (StatusCode, String)> {
+    if state.config.developer_mode {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "there's no wifi client in developer mode".to_string(),
+        ));
+    }
+    let (networks, retry_after) = state
+        .wifi_scan
+        .scan(|| wifi_station::scan_wifi_networks(wifi_station::STA_IFACE))
         .await
         .map_err(|e| {
             (
@@ -457,7 +681,17 @@ pub async fn scan_wifi(
                 format!("WiFi scan failed: {e}"),
             )
         })?;
-    Ok(Json(networks))
+    let Some(retry_after) = retry_after else {
+        return Ok(Json(networks).into_response());
+    };
+    // round up, so a client which waits that long is let through
+    let retry_after_secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    Ok((
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after_secs.to_string())],
+        Json(networks),
+    )
+        .into_response())
 }
 
 #[cfg_attr(feature = "apidocs", utoipa::path(
@@ -533,7 +767,7 @@ mod tests {
             let entry_name = entry.name.clone();
 
             store
-                .update_entry_qmdl_size(current_entry, test_data.len())
+                .update_entry_qmdl_size(current_entry, test_data.len(), std::time::Duration::ZERO)
                 .await
                 .unwrap();
             entry_name
@@ -544,29 +778,81 @@ mod tests {
         entry_name
     }
 
-    fn create_test_server_state(
+    async fn create_test_server_state(
         store_lock: Arc<RwLock<crate::qmdl_store::RecordingStore>>,
     ) -> Arc<ServerState> {
+        create_test_server_state_with_analysis(store_lock).await.0
+    }
+
+    // Like create_test_server_state, but also returns the receiving end of
+    // the analysis queue, for tests which run queued analyses
+    async fn create_test_server_state_with_analysis(
+        store_lock: Arc<RwLock<crate::qmdl_store::RecordingStore>>,
+    ) -> (
+        Arc<ServerState>,
+        tokio::sync::mpsc::Receiver<AnalysisCtrlMessage>,
+    ) {
         let (tx, _rx) = tokio::sync::mpsc::channel(1);
-        let (analysis_tx, _analysis_rx) = tokio::sync::mpsc::channel(1);
+        let (analysis_tx, analysis_rx) = tokio::sync::mpsc::channel(1);
 
-        let analysis_status = {
+        // everything the state writes goes in the store's temp dir, so tests
+        // running in parallel don't share files
+        let (analysis_status, event_store_path, ap_clients_path, config_path, store_path) = {
             let store = store_lock.try_read().unwrap();
-            crate::analysis::AnalysisStatus::new(&store)
+            (
+                crate::analysis::AnalysisStatus::new(&store),
+                store.path.join(crate::event_store::EVENT_STORE_FILENAME),
+                store.path.join(crate::ap_clients::AP_CLIENTS_FILENAME),
+                store.path.join("config.toml"),
+                store.path.clone(),
+            )
         };
 
-        Arc::new(ServerState {
-            config_path: "/tmp/test_config.toml".to_string(),
+        let daemon_restart_token = CancellationToken::new();
+        let state = Arc::new(ServerState {
+            config_path: config_path.to_string_lossy().into_owned(),
             config: Config::default(),
             qmdl_store_lock: store_lock,
             diag_device_ctrl_sender: tx,
+            live_qmdl_sender: broadcast::channel(4).0,
+            live_tail: LiveTail::new(4),
+            capture_rate: CaptureRateTracker::default(),
+            location: LocationTracker::default(),
+            diag_multiplexer: DiagMultiplexer::new(4),
             analysis_status_lock: Arc::new(RwLock::new(analysis_status)),
             analysis_sender: analysis_tx,
-            daemon_restart_token: CancellationToken::new(),
+            daemon_shutdown_token: daemon_restart_token.child_token(),
+            daemon_restart_token,
+            device_reboot_token: CancellationToken::new(),
             ui_update_sender: None,
             wifi_status: Arc::new(RwLock::new(wifi_station::WifiStatus::default())),
-            wifi_scan_lock: tokio::sync::Mutex::new(()),
-        })
+            wifi_scan: WifiScanLimiter::new(std::time::Duration::from_secs(30)),
+            daemon_state: Arc::new(
+                DaemonStateTracker::record_start(
+                    crate::daemon_state::daemon_state_path(&config_path),
+                    false,
+                )
+                .await,
+            ),
+            event_store_lock: Arc::new(RwLock::new(
+                EventStore::open(event_store_path, crate::event_store::EVENT_STORE_MAX_BYTES)
+                    .await
+                    .unwrap(),
+            )),
+            ap_clients_lock: Arc::new(RwLock::new(
+                ApClientRegistry::open(ap_clients_path).await.unwrap(),
+            )),
+            firewall_state: Arc::new(RwLock::new(FirewallState::default())),
+            captive_portal: Arc::new(RwLock::new(false)),
+            monitoring: Arc::new(RwLock::new(false)),
+            pcap_conversion_semaphore: Arc::new(tokio::sync::Semaphore::new(
+                crate::pcap::MAX_CONCURRENT_CONVERSIONS,
+            )),
+            // finds no battery in the store's empty temp dir
+            battery: BatteryProbe::new(Config::default().device, store_path),
+            stats_cache: StatsCache::default(),
+        });
+        (state, analysis_rx)
     }
 
     #[tokio::test]
@@ -574,9 +860,14 @@ mod tests {
         let (_temp_dir, store_lock) = create_test_qmdl_store().await;
         let test_qmdl_data = vec![0x7E, 0x00, 0x00, 0x00, 0x10, 0x00, 0x7E];
         let entry_name = create_test_entry_with_data(&store_lock, &test_qmdl_data).await;
-        let state = create_test_server_state(store_lock);
+        let state = create_test_server_state(store_lock).await;
 
-        let result = get_zip(State(state), Path(entry_name.clone())).await;
+        let result = get_zip(
+            State(state),
+            Path(entry_name.clone()),
+            Query(RedactQuery::default()),
+        )
+        .await;
 
         assert!(result.is_ok());
         let response = result.unwrap();
@@ -601,4 +892,858 @@ mod tests {
             vec![format!("{entry_name}.qmdl"), format!("{entry_name}.pcapng"),]
         );
     }
+
+    #[tokio::test]
+    async fn test_get_zip_redacted() {
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let test_qmdl_data = vec![0x7E, 0x00, 0x00, 0x00, 0x10, 0x00, 0x7E];
+        let entry_name = create_test_entry_with_data(&store_lock, &test_qmdl_data).await;
+        let state = create_test_server_state(store_lock).await;
+
+        let response = get_zip(
+            State(state),
+            Path(entry_name.clone()),
+            Query(RedactQuery { redact: Some(true) }),
+        )
+        .await
+        .unwrap();
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let zip_reader = ZipFileReader::new(body_bytes.to_vec()).await.unwrap();
+
+        // the unredacted QMDL is left out
+        let filenames = zip_reader
+            .file()
+            .entries()
+            .iter()
+            .map(|entry| entry.filename().as_str().unwrap().to_owned())
+            .collect::<Vec<String>>();
+        assert_eq!(filenames, vec![format!("{entry_name}-redacted.pcapng")]);
+        assert_eq!(
+            zip_reader.file().comment().as_str().unwrap(),
+            REDACTED_ZIP_COMMENT
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recording_notes() {
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let test_qmdl_data = vec![0x7E, 0x00, 0x00, 0x00, 0x10, 0x00, 0x7E];
+        let entry_name = create_test_entry_with_data(&store_lock, &test_qmdl_data).await;
+        let state = create_test_server_state(store_lock.clone()).await;
+
+        let result = set_recording_notes(
+            State(state.clone()),
+            Path("nope".to_string()),
+            Json(RecordingNotesRequest {
+                notes: None,
+                tags: Vec::new(),
+            }),
+        )
+        .await;
+        assert_eq!(result.unwrap_err().0, StatusCode::NOT_FOUND);
+
+        let result = set_recording_notes(
+            State(state.clone()),
+            Path(entry_name.clone()),
+            Json(RecordingNotesRequest {
+                notes: Some("x".repeat(MAX_NOTES_CHARS + 1)),
+                tags: Vec::new(),
+            }),
+        )
+        .await;
+        assert_eq!(result.unwrap_err().0, StatusCode::BAD_REQUEST);
+        let result = set_recording_notes(
+            State(state.clone()),
+            Path(entry_name.clone()),
+            Json(RecordingNotesRequest {
+                notes: None,
+                tags: vec!["x".repeat(MAX_TAG_CHARS + 1)],
+            }),
+        )
+        .await;
+        assert_eq!(result.unwrap_err().0, StatusCode::BAD_REQUEST);
+
+        set_recording_notes(
+            State(state.clone()),
+            Path(entry_name.clone()),
+            Json(RecordingNotesRequest {
+                notes: Some("outside courthouse, 2pm".to_string()),
+                tags: vec![" suspected ".to_string(), "".to_string()],
+            }),
+        )
+        .await
+        .unwrap();
+
+        let manifest = crate::stats::get_qmdl_manifest(State(state.clone()))
+            .await
+            .unwrap();
+        let json = serde_json::to_value(&manifest.0).unwrap();
+        assert_eq!(json["entries"][0]["notes"], "outside courthouse, 2pm");
+        assert_eq!(json["entries"][0]["tags"], serde_json::json!(["suspected"]));
+
+        let response = get_zip(
+            State(state),
+            Path(entry_name.clone()),
+            Query(RedactQuery::default()),
+        )
+        .await
+        .unwrap();
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let zip_reader = ZipFileReader::new(body_bytes.to_vec()).await.unwrap();
+        let index = zip_reader
+            .file()
+            .entries()
+            .iter()
+            .position(|entry| {
+                entry.filename().as_str().unwrap() == format!("{entry_name}-notes.txt")
+            })
+            .unwrap();
+        let mut notes = String::new();
+        zip_reader
+            .reader_with_entry(index)
+            .await
+            .unwrap()
+            .read_to_string_checked(&mut notes)
+            .await
+            .unwrap();
+        assert_eq!(notes, "Tags: suspected\n\noutside courthouse, 2pm\n");
+    }
+
+    #[tokio::test]
+    async fn test_live_qmdl_unavailable_when_not_recording() {
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let state = create_test_server_state(store_lock).await;
+
+        let result = crate::diag::get_live_qmdl(State(state)).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    fn container_with(data: &[u8]) -> rayhunter::diag::MessagesContainer {
+        rayhunter::diag::MessagesContainer {
+            data_type: rayhunter::diag::DataType::UserSpace,
+            num_messages: 1,
+            messages: vec![rayhunter::diag::HdlcEncapsulatedMessage {
+                len: data.len() as u32,
+                data: data.to_vec(),
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_live_qmdl_streams_until_recording_stops() {
+        use futures::StreamExt;
+
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        store_lock.write().await.new_entry().await.unwrap();
+        let state = create_test_server_state(store_lock).await;
+
+        let response = crate::diag::get_live_qmdl(State(state.clone()))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "application/octet-stream"
+        );
+        let mut body = response.into_body().into_data_stream();
+
+        state
+            .diag_multiplexer
+            .publish(container_with(&[1, 2, 3, 0x7e]))
+            .await;
+        assert_eq!(
+            body.next().await.unwrap().unwrap().as_ref(),
+            &[1, 2, 3, 0x7e]
+        );
+
+        // nothing is streamed while paused
+        state
+            .live_qmdl_sender
+            .send(LiveQmdlMessage::RecordingPaused)
+            .unwrap();
+        state
+            .diag_multiplexer
+            .publish(container_with(&[9, 0x7e]))
+            .await;
+        let while_paused =
+            tokio::time::timeout(std::time::Duration::from_millis(50), body.next()).await;
+        assert!(while_paused.is_err());
+
+        state
+            .live_qmdl_sender
+            .send(LiveQmdlMessage::RecordingResumed)
+            .unwrap();
+        state
+            .diag_multiplexer
+            .publish(container_with(&[4, 5, 0x7e]))
+            .await;
+        assert_eq!(body.next().await.unwrap().unwrap().as_ref(), &[4, 5, 0x7e]);
+
+        state
+            .live_qmdl_sender
+            .send(LiveQmdlMessage::RecordingStopped)
+            .unwrap();
+        assert!(body.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_live_qmdl_ends_with_diag_reading() {
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        store_lock.write().await.new_entry().await.unwrap();
+        let state = create_test_server_state(store_lock).await;
+
+        let response = crate::diag::get_live_qmdl(State(state.clone()))
+            .await
+            .unwrap();
+        state
+            .diag_multiplexer
+            .publish(container_with(&[1, 0x7e]))
+            .await;
+        state.diag_multiplexer.close();
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body_bytes.as_ref(), &[1, 0x7e]);
+    }
+
+    #[tokio::test]
+    async fn test_live_tail_returns_newest_first() {
+        use crate::live_tail::LiveTailEntry;
+
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let state = create_test_server_state(store_lock).await;
+        // the test state keeps 4 entries
+        for packet_num in 1..=6 {
+            state.live_tail.push(LiveTailEntry {
+                packet_num,
+                packet_timestamp: None,
+                protocol: "LTE NAS",
+                channel: None,
+                message_type: Some("EMMIdentityRequest".to_string()),
+                events: Vec::new(),
+            });
+        }
+
+        let response =
+            crate::live_tail::get_live_tail(State(state), axum::http::HeaderMap::new()).await;
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let entries: Vec<serde_json::Value> = serde_json::from_slice(&body_bytes).unwrap();
+        let packet_nums: Vec<u64> = entries
+            .iter()
+            .map(|entry| entry["packet_num"].as_u64().unwrap())
+            .collect();
+        assert_eq!(packet_nums, vec![6, 5, 4, 3]);
+        assert_eq!(entries[0]["message_type"], "EMMIdentityRequest");
+    }
+
+    #[tokio::test]
+    async fn test_gzip_analysis_report_matches_plain() {
+        use std::io::Read;
+        use tokio::io::AsyncWriteExt;
+
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let entry_name = {
+            let mut store = store_lock.write().await;
+            let (_qmdl_file, mut analysis_file) = store.new_entry().await.unwrap();
+            for i in 0..200 {
+                let line = format!("{{\"skipped_message_reason\":\"row {i}\",\"events\":[]}}\n");
+                analysis_file.write_all(line.as_bytes()).await.unwrap();
+            }
+            analysis_file.flush().await.unwrap();
+            let name = store.manifest.entries[store.current_entry.unwrap()]
+                .name
+                .clone();
+            store.close_current_entry().await.unwrap();
+            name
+        };
+        let state = create_test_server_state(store_lock).await;
+
+        let plain = crate::diag::get_analysis_report(
+            State(state.clone()),
+            Path(entry_name.clone()),
+            Query(crate::diag::ReportQuery::default()),
+            axum::http::HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert!(plain.headers().get(header::CONTENT_ENCODING).is_none());
+        let plain_bytes = axum::body::to_bytes(plain.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        let mut gzip_headers = axum::http::HeaderMap::new();
+        gzip_headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+        let gzipped = crate::diag::get_analysis_report(
+            State(state),
+            Path(entry_name),
+            Query(crate::diag::ReportQuery::default()),
+            gzip_headers,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            gzipped.headers().get(header::CONTENT_ENCODING).unwrap(),
+            "gzip"
+        );
+        assert_eq!(
+            gzipped.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/x-ndjson"
+        );
+        let gzipped_bytes = axum::body::to_bytes(gzipped.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(gzipped_bytes.len() < plain_bytes.len());
+
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(gzipped_bytes.as_ref())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, plain_bytes.as_ref());
+    }
+
+    #[tokio::test]
+    async fn test_pcap_download_is_cached() {
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let test_qmdl_data = vec![0x7E, 0x00, 0x00, 0x00, 0x10, 0x00, 0x7E];
+        let entry_name = create_test_entry_with_data(&store_lock, &test_qmdl_data).await;
+        let state = create_test_server_state(store_lock).await;
+
+        let download = |state: Arc<ServerState>| {
+            let entry_name = entry_name.clone();
+            async move {
+                let response = crate::pcap::get_pcap(
+                    State(state),
+                    Path(entry_name),
+                    axum::extract::Query(crate::pcap::PcapQuery::default()),
+                    axum::http::HeaderMap::new(),
+                )
+                .await
+                .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let cached = response.headers()["x-conversion-cached"].clone();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (cached, body)
+            }
+        };
+
+        let (cached, converted) = download(state.clone()).await;
+        assert_eq!(cached, "false");
+        assert!(!converted.is_empty());
+
+        // a cached download doesn't need a conversion slot
+        let _permit = state
+            .pcap_conversion_semaphore
+            .clone()
+            .try_acquire_owned()
+            .unwrap();
+        let (cached, from_cache) = download(state.clone()).await;
+        assert_eq!(cached, "true");
+        assert_eq!(from_cache, converted);
+    }
+
+    #[tokio::test]
+    async fn test_pcap_conversions_are_limited() {
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let test_qmdl_data = vec![0x7E, 0x00, 0x00, 0x00, 0x10, 0x00, 0x7E];
+        let entry_name = create_test_entry_with_data(&store_lock, &test_qmdl_data).await;
+        let state = create_test_server_state(store_lock).await;
+
+        // stands in for a conversion which is already running
+        let permit = state
+            .pcap_conversion_semaphore
+            .clone()
+            .try_acquire_owned()
+            .unwrap();
+        let response = crate::pcap::get_pcap(
+            State(state.clone()),
+            Path(entry_name.clone()),
+            axum::extract::Query(crate::pcap::PcapQuery::default()),
+            axum::http::HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+
+        drop(permit);
+        let response = crate::pcap::get_pcap(
+            State(state),
+            Path(entry_name),
+            axum::extract::Query(crate::pcap::PcapQuery::default()),
+            axum::http::HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_now_conflicts_with_pending_analysis() {
+        use crate::analysis::analyze_now;
+
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let entry_name = create_test_entry_with_data(&store_lock, &[]).await;
+        let state = create_test_server_state(store_lock).await;
+
+        let (first, second) = tokio::join!(
+            analyze_now(&state, &entry_name),
+            analyze_now(&state, &entry_name)
+        );
+        assert!(first.is_ok(), "{first:?}");
+        assert_eq!(second.unwrap_err().0, StatusCode::CONFLICT);
+        assert!(
+            !state
+                .analysis_status_lock
+                .read()
+                .await
+                .is_pending(&entry_name)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_analyze_now_finishes_without_its_request() {
+        use crate::analysis::analyze_now;
+
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let entry_name = create_test_entry_with_data(&store_lock, &[]).await;
+        let state = create_test_server_state(store_lock).await;
+
+        // the request goes away as soon as the analysis has started
+        let abandoned =
+            tokio::time::timeout(std::time::Duration::ZERO, analyze_now(&state, &entry_name)).await;
+        assert!(abandoned.is_err());
+        assert!(
+            state
+                .analysis_status_lock
+                .read()
+                .await
+                .is_pending(&entry_name)
+        );
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while state
+                .analysis_status_lock
+                .read()
+                .await
+                .is_pending(&entry_name)
+            {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the analysis never finished");
+    }
+
+    #[tokio::test]
+    async fn test_imported_recording_is_analyzed() {
+        use rayhunter::qmdl_fixture::{FixtureMessage, QmdlFixtureBuilder};
+
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let (state, analysis_rx) = create_test_server_state_with_analysis(store_lock).await;
+        // asking for the IMSI once the device has authenticated is what an
+        // IMSI catcher does
+        let recording = QmdlFixtureBuilder::new()
+            .messages([
+                FixtureMessage::AuthenticationResponse,
+                FixtureMessage::IdentityRequest,
+            ])
+            .build();
+        let (status, name) = crate::analysis::import_qmdl(State(state.clone()), recording.into())
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+
+        let task_tracker = tokio_util::task::TaskTracker::new();
+        crate::analysis::run_analysis_thread(
+            &task_tracker,
+            analysis_rx,
+            state.qmdl_store_lock.clone(),
+            state.analysis_status_lock.clone(),
+            state.event_store_lock.clone(),
+            state.config.analyzers.clone(),
+            1,
+        );
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while state.analysis_status_lock.read().await.is_pending(&name) {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the analysis never finished");
+        state
+            .analysis_sender
+            .send(AnalysisCtrlMessage::Exit)
+            .await
+            .unwrap();
+        task_tracker.close();
+        task_tracker.wait().await;
+
+        let analysis_path = {
+            let store = state.qmdl_store_lock.read().await;
+            let (_, entry) = store.entry_for_name(&name).unwrap();
+            entry.get_analysis_filepath(&store.path)
+        };
+        let report = tokio::fs::read_to_string(analysis_path).await.unwrap();
+        let found: Vec<(String, String)> = report
+            .lines()
+            .skip(1)
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .flat_map(|row| row["events"].as_array().cloned().unwrap_or_default())
+            .filter(|event| event["details"]["analyzer"] == "imsi_requested")
+            .map(|event| {
+                (
+                    event["details"]["code"].as_str().unwrap().to_string(),
+                    event["event_type"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [("identity_after_auth".to_string(), "High".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wireshark_hints_share_conversion_limit() {
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let test_qmdl_data = vec![0x7E, 0x00, 0x00, 0x00, 0x10, 0x00, 0x7E];
+        let entry_name = create_test_entry_with_data(&store_lock, &test_qmdl_data).await;
+        let state = create_test_server_state(store_lock).await;
+
+        let permit = state
+            .pcap_conversion_semaphore
+            .clone()
+            .try_acquire_owned()
+            .unwrap();
+        let hints = |state| {
+            crate::wireshark::get_wireshark_hints(
+                State(state),
+                Path(entry_name.clone()),
+                axum::extract::Query(crate::wireshark::WiresharkQuery::default()),
+            )
+        };
+        let response = hints(state.clone()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        drop(permit);
+        let response = hints(state).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_gzip_pcap_matches_plain() {
+        use std::io::Read;
+
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let test_qmdl_data = vec![0x7E, 0x00, 0x00, 0x00, 0x10, 0x00, 0x7E];
+        let entry_name = create_test_entry_with_data(&store_lock, &test_qmdl_data).await;
+        let state = create_test_server_state(store_lock).await;
+
+        let mut gzip_headers = axum::http::HeaderMap::new();
+        gzip_headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+        let mut bodies = Vec::new();
+        // converted, then served from the cache
+        for _ in 0..2 {
+            let response = crate::pcap::get_pcap(
+                State(state.clone()),
+                Path(entry_name.clone()),
+                axum::extract::Query(crate::pcap::PcapQuery::default()),
+                gzip_headers.clone(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                response.headers().get(header::CONTENT_ENCODING).unwrap(),
+                "gzip"
+            );
+            assert!(response.headers().get(CONTENT_LENGTH).is_none());
+            let gzipped = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let mut decompressed = Vec::new();
+            flate2::read::GzDecoder::new(gzipped.as_ref())
+                .read_to_end(&mut decompressed)
+                .unwrap();
+            bodies.push(decompressed);
+        }
+
+        // the pcapng section header block's type
+        assert_eq!(bodies[0][..4], [0x0a, 0x0d, 0x0d, 0x0a]);
+        assert_eq!(bodies[0], bodies[1]);
+    }
+
+    #[tokio::test]
+    async fn test_manifest_includes_analysis_summary() {
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let test_qmdl_data = vec![0x7E, 0x00, 0x00, 0x00, 0x10, 0x00, 0x7E];
+        let entry_name = create_test_entry_with_data(&store_lock, &test_qmdl_data).await;
+        let state = create_test_server_state(store_lock.clone()).await;
+
+        let result =
+            crate::analysis::get_analysis_summary(State(state.clone()), Path(entry_name.clone()))
+                .await;
+        assert_eq!(result.unwrap_err().0, StatusCode::NOT_FOUND);
+
+        let summary = crate::analysis::AnalysisSummary {
+            total_events: 1,
+            ..Default::default()
+        };
+        {
+            let store = store_lock.read().await;
+            let (_, entry) = store.entry_for_name(&entry_name).unwrap();
+            write(
+                entry.get_summary_filepath(&store.path),
+                serde_json::to_vec(&summary).unwrap(),
+            )
+            .await
+            .unwrap();
+        }
+
+        let result =
+            crate::analysis::get_analysis_summary(State(state.clone()), Path(entry_name.clone()))
+                .await
+                .unwrap();
+        assert_eq!(result.0, summary);
+
+        let manifest = crate::stats::get_qmdl_manifest(State(state)).await.unwrap();
+        let json = serde_json::to_value(&manifest.0).unwrap();
+        assert_eq!(json["entries"][0]["name"], entry_name);
+        assert_eq!(json["entries"][0]["analysis_summary"]["total_events"], 1);
+        assert_eq!(json["mode"], "full");
+        assert_eq!(json["monitoring"], false);
+    }
+
+    #[tokio::test]
+    async fn test_power_actions_forbidden_in_debug_mode() {
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        store_lock.write().await.new_entry().await.unwrap();
+        let mut state = create_test_server_state(store_lock.clone()).await;
+        Arc::get_mut(&mut state).unwrap().config.debug_mode = true;
+
+        let result = crate::power::restart_daemon(State(state.clone())).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::FORBIDDEN);
+        let result = crate::power::shutdown_daemon(State(state.clone())).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::FORBIDDEN);
+        let result = crate::power::reboot_device(State(state.clone())).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::FORBIDDEN);
+
+        assert!(!state.daemon_restart_token.is_cancelled());
+        assert!(!state.daemon_shutdown_token.is_cancelled());
+        assert!(!state.device_reboot_token.is_cancelled());
+        let store = store_lock.read().await;
+        let (_, entry) = store.get_current_entry().unwrap();
+        assert_eq!(entry.stop_reason, None);
+    }
+
+    #[tokio::test]
+    async fn test_developer_mode_refuses_reboot_and_wifi_scan() {
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let mut state = create_test_server_state(store_lock).await;
+        Arc::get_mut(&mut state).unwrap().config.developer_mode = true;
+
+        let result = crate::power::reboot_device(State(state.clone())).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::FORBIDDEN);
+        assert!(!state.device_reboot_token.is_cancelled());
+        let result = scan_wifi(State(state.clone())).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_stop_reason_recorded_before_shutdown() {
+        for action in [
+            crate::power::PowerAction::RestartDaemon,
+            crate::power::PowerAction::ShutdownDaemon,
+            crate::power::PowerAction::RebootDevice,
+        ] {
+            let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+            store_lock.write().await.new_entry().await.unwrap();
+            let state = create_test_server_state(store_lock.clone()).await;
+
+            // stands in for the shutdown thread, which closes the current
+            // recording as soon as the token is cancelled
+            let shutdown_thread = {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    state.daemon_shutdown_token.cancelled().await;
+                    let store = state
+                        .qmdl_store_lock
+                        .try_read()
+                        .expect("store still locked after shutdown was requested");
+                    let (_, entry) = store.get_current_entry().unwrap();
+                    entry.stop_reason.clone()
+                })
+            };
+
+            let (status, response) = match action {
+                crate::power::PowerAction::RestartDaemon => {
+                    crate::power::restart_daemon(State(state.clone())).await
+                }
+                crate::power::PowerAction::ShutdownDaemon => {
+                    crate::power::shutdown_daemon(State(state.clone())).await
+                }
+                crate::power::PowerAction::RebootDevice => {
+                    crate::power::reboot_device(State(state.clone())).await
+                }
+            }
+            .unwrap();
+            assert_eq!(status, StatusCode::ACCEPTED);
+            assert_eq!(response.action, action);
+            assert_eq!(
+                response.estimated_downtime_secs.is_none(),
+                action == crate::power::PowerAction::ShutdownDaemon
+            );
+
+            assert_eq!(
+                shutdown_thread.await.unwrap().as_deref(),
+                Some(crate::power::SHUTDOWN_STOP_REASON)
+            );
+            assert_eq!(
+                state.device_reboot_token.is_cancelled(),
+                action == crate::power::PowerAction::RebootDevice
+            );
+            // only a shutdown leaves the daemon stopped
+            assert_eq!(
+                state.daemon_restart_token.is_cancelled(),
+                action != crate::power::PowerAction::ShutdownDaemon
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_analysis_refuses_tiny_recording() {
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let test_qmdl_data = vec![0x7E, 0x00, 0x00, 0x00, 0x10, 0x00, 0x7E];
+        let entry_name = create_test_entry_with_data(&store_lock, &test_qmdl_data).await;
+        let state = create_test_server_state(store_lock).await;
+
+        let response = crate::analysis::start_analysis(State(state), Path(entry_name))
+            .await
+            .unwrap_err();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"error": "too_small", "size_bytes": 7, "minimum": 1024})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_analysis() {
+        use tokio::io::AsyncWriteExt;
+
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let mut names = Vec::new();
+        for (secs, severity) in [(30, "High"), (10, "Medium")] {
+            let mut store = store_lock.write().await;
+            let (_qmdl_file, mut analysis_file) = store.new_entry().await.unwrap();
+            let report = format!(
+                "{{\"analyzers\":[{{\"name\":\"Test\",\"description\":\"\",\"version\":1}}]}}\n\
+                 {{\"packet_timestamp\":\"2024-01-01T00:00:{secs}Z\",\"events\":[{{\"event_type\":\"{severity}\",\"message\":\"{severity} event\"}}]}}\n"
+            );
+            analysis_file.write_all(report.as_bytes()).await.unwrap();
+            analysis_file.flush().await.unwrap();
+            names.push(
+                store.manifest.entries[store.current_entry.unwrap()]
+                    .name
+                    .clone(),
+            );
+            store.close_current_entry().await.unwrap();
+        }
+        let state = create_test_server_state(store_lock).await;
+
+        let request = |recording_names: Vec<String>, min_severity: &str| {
+            Json(crate::aggregate::AggregateRequest {
+                recording_names,
+                min_severity: Some(min_severity.to_string()),
+                dedup_window_secs: None,
+                redact: None,
+            })
+        };
+        let response = crate::aggregate::aggregate_analysis(
+            State(state.clone()),
+            axum::http::HeaderMap::new(),
+            request(names.clone(), "medium"),
+        )
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let events: Vec<serde_json::Value> = body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        // sorted by timestamp, not by recording
+        assert_eq!(events[0]["source_recording"], names[1].as_str());
+        assert_eq!(events[0]["analyzer"], "Test");
+        assert_eq!(events[1]["source_recording"], names[0].as_str());
+
+        let result = crate::aggregate::aggregate_analysis(
+            State(state.clone()),
+            axum::http::HeaderMap::new(),
+            request(names.clone(), "critical"),
+        )
+        .await;
+        assert_eq!(result.unwrap_err().0, StatusCode::BAD_REQUEST);
+
+        let too_many = (0..=crate::aggregate::MAX_AGGREGATE_RECORDINGS)
+            .map(|i| i.to_string())
+            .collect();
+        let result = crate::aggregate::aggregate_analysis(
+            State(state.clone()),
+            axum::http::HeaderMap::new(),
+            request(too_many, "medium"),
+        )
+        .await;
+        assert_eq!(result.unwrap_err().0, StatusCode::BAD_REQUEST);
+
+        let result = crate::aggregate::aggregate_analysis(
+            State(state),
+            axum::http::HeaderMap::new(),
+            request(vec!["missing".to_string()], "medium"),
+        )
+        .await;
+        assert_eq!(result.unwrap_err().0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_analyzers_cover_config() {
+        let (_temp_dir, store_lock) = create_test_qmdl_store().await;
+        let state = create_test_server_state(store_lock).await;
+        let Json(analyzers) = get_analyzers(State(state.clone())).await;
+        let keys: Vec<&str> = analyzers.iter().map(|a| a.key.as_str()).collect();
+
+        // every toggle in the analyzer settings has an entry, and the
+        // analyzers with settings of their own list them
+        let config = serde_json::to_value(&state.config.analyzers).unwrap();
+        for (key, value) in config.as_object().unwrap() {
+            match value {
+                serde_json::Value::Bool(enabled) => {
+                    let analyzer = analyzers.iter().find(|a| a.key == *key);
+                    assert_eq!(
+                        analyzer.map(|a| a.enabled),
+                        Some(*enabled),
+                        "{key} isn't listed"
+                    );
+                }
+                _ if key == "diagnostic_severity" => {
+                    assert!(keys.contains(&"diagnostic_analyzer"))
+                }
+                _ => assert!(keys.contains(&key.as_str()), "{key} isn't listed"),
+            }
+        }
+    }
 }
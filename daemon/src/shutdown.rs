@@ -0,0 +1,126 @@
+//! Every long-running part of the daemon is a task on one [TaskTracker],
+//! stopped by cancelling one [CancellationToken]. Some of the networking
+//! changes made while running (the wifi client's routes and resolv.conf) have
+//! to be undone after those tasks stop, and must be undone even if one of
+//! them hangs, or the device is left unreachable from its own AP.
+
+use std::future::Future;
+use std::time::Duration;
+
+use log::{info, warn};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+/// How long to wait for tasks to stop after shutdown is requested before
+/// tearing down networking anyway
+pub const TASK_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Waits for shutdown to be requested, then for every task on the tracker to
+/// finish (up to `timeout`), then runs `teardown` to completion. Returns
+/// whether all the tasks finished in time.
+pub async fn wait_for_shutdown<F>(
+    task_tracker: &TaskTracker,
+    shutdown_token: &CancellationToken,
+    timeout: Duration,
+    teardown: F,
+) -> bool
+where
+    F: Future<Output = ()>,
+{
+    shutdown_token.cancelled().await;
+    task_tracker.close();
+
+    let finished = tokio::time::timeout(timeout, task_tracker.wait())
+        .await
+        .is_ok();
+    if finished {
+        info!("all tasks stopped");
+    } else {
+        warn!(
+            "{} task(s) didn't stop within {}s, tearing down networking anyway",
+            task_tracker.len(),
+            timeout.as_secs()
+        );
+    }
+
+    teardown.await;
+    finished
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    type Steps = Arc<Mutex<Vec<&'static str>>>;
+
+    fn record(steps: &Steps, step: &'static str) {
+        steps.lock().unwrap().push(step);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_teardown_runs_after_tasks_stop() {
+        let task_tracker = TaskTracker::new();
+        let shutdown_token = CancellationToken::new();
+        let steps = Steps::default();
+
+        for (delay, step) in [(3, "slow task stopped"), (1, "fast task stopped")] {
+            let token = shutdown_token.clone();
+            let steps = steps.clone();
+            task_tracker.spawn(async move {
+                token.cancelled().await;
+                tokio::time::sleep(Duration::from_secs(delay)).await;
+                record(&steps, step);
+            });
+        }
+
+        let token = shutdown_token.clone();
+        let signal_steps = steps.clone();
+        tokio::spawn(async move {
+            record(&signal_steps, "shutdown requested");
+            token.cancel();
+        });
+
+        let teardown_steps = steps.clone();
+        let finished = wait_for_shutdown(
+            &task_tracker,
+            &shutdown_token,
+            TASK_SHUTDOWN_TIMEOUT,
+            async move { record(&teardown_steps, "routing restored") },
+        )
+        .await;
+
+        assert!(finished);
+        assert_eq!(
+            *steps.lock().unwrap(),
+            [
+                "shutdown requested",
+                "fast task stopped",
+                "slow task stopped",
+                "routing restored"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_teardown_runs_when_a_task_hangs() {
+        let task_tracker = TaskTracker::new();
+        let shutdown_token = CancellationToken::new();
+        let steps = Steps::default();
+
+        task_tracker.spawn(std::future::pending::<()>());
+        shutdown_token.cancel();
+
+        let teardown_steps = steps.clone();
+        let finished = wait_for_shutdown(
+            &task_tracker,
+            &shutdown_token,
+            Duration::from_secs(5),
+            async move { record(&teardown_steps, "routing restored") },
+        )
+        .await;
+
+        assert!(!finished);
+        assert_eq!(task_tracker.len(), 1);
+        assert_eq!(*steps.lock().unwrap(), ["routing restored"]);
+    }
+}
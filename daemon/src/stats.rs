@@ -1,18 +1,37 @@
 use std::ffi::CString;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::battery::get_battery_status;
-use crate::error::RayhunterError;
+use crate::analysis::{AnalysisSummary, read_analysis_summary};
+use crate::battery::{BatteryProbe, get_battery_status};
+use crate::capture_rate::CaptureRate;
+use crate::compression::maybe_gzip_response;
+use crate::config::RecordingMode;
+use crate::daemon_state::DaemonStats;
+use crate::diag_multiplexer::DiagStats;
+use crate::logging::LOG_PATH;
+use crate::mounts::{PROC_MOUNTS, mount_containing, read_mounts};
 use crate::server::ServerState;
 use crate::{battery::BatteryState, qmdl_store::ManifestEntry};
 
 use axum::Json;
 use axum::extract::State;
-use axum::http::StatusCode;
-use log::error;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use log::{error, warn};
+use rayhunter::location::GeoPoint;
 use rayhunter::{Device, util::RuntimeMetadata};
 use serde::Serialize;
 use tokio::process::Command;
+use tokio::select;
+use tokio::sync::{RwLock, watch};
+use tokio_util::io::ReaderStream;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// How often [run_stats_collector] samples the disk, memory and battery
+const STATS_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
 
 /// Structure of device system statistics
 #[derive(Debug, Serialize)]
@@ -21,33 +40,121 @@ pub struct SystemStats {
     pub disk_stats: DiskStats,
     pub memory_stats: MemoryStats,
     pub runtime_metadata: RuntimeMetadata,
+    pub daemon_stats: DaemonStats,
+    /// Size of the long-term event store on disk, in bytes
+    pub event_store_size_bytes: u64,
+    /// How many diag containers were skipped by consumers which fell behind.
+    /// Also reported in diag_stats, and kept here for older clients.
+    pub diag_frames_dropped: u64,
+    /// How much has been read from the diag device, how fast, and how much
+    /// of it was dropped or couldn't be parsed
+    pub diag_stats: DiagStats,
+    /// How fast the current recording is growing, if there is one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture_rate: Option<CaptureRate>,
+    /// The device's GPS position, if location tagging is enabled and the GPS
+    /// has a fix
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_location: Option<GeoPoint>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub battery_status: Option<BatteryState>,
 }
 
 impl SystemStats {
-    pub async fn new(qmdl_path: &str, device: &Device) -> Result<Self, String> {
+    pub fn new(
+        sample: SampledStats,
+        daemon_stats: DaemonStats,
+        event_store_size_bytes: u64,
+        diag_stats: DiagStats,
+        capture_rate: Option<CaptureRate>,
+        current_location: Option<GeoPoint>,
+    ) -> Self {
+        Self {
+            disk_stats: sample.disk_stats,
+            memory_stats: sample.memory_stats,
+            runtime_metadata: RuntimeMetadata::new(),
+            daemon_stats,
+            event_store_size_bytes,
+            diag_frames_dropped: diag_stats.frames_dropped_total,
+            diag_stats,
+            capture_rate,
+            current_location,
+            battery_status: sample.battery_status,
+        }
+    }
+}
+
+/// The parts of [SystemStats] which are slow to collect, so they're sampled
+/// periodically by [run_stats_collector] rather than on every request
+#[derive(Debug, Clone)]
+pub struct SampledStats {
+    pub disk_stats: DiskStats,
+    pub memory_stats: MemoryStats,
+    pub battery_status: Option<BatteryState>,
+}
+
+impl SampledStats {
+    pub async fn collect(
+        qmdl_path: &str,
+        device: &Device,
+        battery: &BatteryProbe,
+    ) -> Result<Self, String> {
         Ok(Self {
             disk_stats: DiskStats::new(qmdl_path)?,
             memory_stats: MemoryStats::new(device).await?,
-            runtime_metadata: RuntimeMetadata::new(),
-            battery_status: match get_battery_status(device).await {
-                Ok(status) => Some(status),
-                Err(RayhunterError::FunctionNotSupportedForDeviceError) => None,
-                Err(err) => {
-                    log::error!("Failed to get battery status: {err}");
-                    None
-                }
+            battery_status: match battery.source().await {
+                Some(source) => match get_battery_status(&source).await {
+                    Ok(status) => Some(status),
+                    Err(err) => {
+                        log::error!("Failed to get battery status: {err}");
+                        None
+                    }
+                },
+                None => None,
             },
         })
     }
 }
 
+/// The latest sample from [run_stats_collector], or the error collecting it
+/// failed with. Empty until the first sample has been taken.
+pub type StatsCache = Arc<RwLock<Option<Result<SampledStats, String>>>>;
+
+/// Samples the disk, memory and battery every few seconds into `cache`, so
+/// requests for system stats don't each run `free` and read sysfs. The disk
+/// is whichever one the QMDL store is on at the time, as `store_path` tracks
+/// it moving.
+pub fn run_stats_collector(
+    task_tracker: &TaskTracker,
+    store_path: watch::Receiver<PathBuf>,
+    device: Device,
+    battery: BatteryProbe,
+    cache: StatsCache,
+    shutdown_token: CancellationToken,
+) {
+    task_tracker.spawn(async move {
+        loop {
+            let qmdl_path = store_path.borrow().to_string_lossy().into_owned();
+            let sample = SampledStats::collect(&qmdl_path, &device, &battery).await;
+            if let Err(err) = &sample {
+                warn!("error sampling system stats: {err}");
+            }
+            *cache.write().await = Some(sample);
+
+            select! {
+                _ = shutdown_token.cancelled() => break,
+                _ = tokio::time::sleep(STATS_SAMPLE_INTERVAL) => {}
+            }
+        }
+    });
+}
+
 /// Device storage information
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
 pub struct DiskStats {
-    /// The partition to which the daemon is installed
+    /// The device the recording store is on, e.g. "/dev/mmcblk0p1" for an
+    /// SD card
     partition: String,
     /// The total disk size of the partition
     total_size: String,
@@ -59,8 +166,15 @@ pub struct DiskStats {
     used_percent: String,
     /// The root folder to which the partition is mounted
     mounted_on: String,
+    /// The partition's filesystem type, e.g. "vfat"
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub available_bytes: Option<u64>,
+    filesystem: Option<String>,
+    /// The total size of the partition, in bytes
+    pub total_bytes: u64,
+    /// Used space on the partition, in bytes
+    pub used_bytes: u64,
+    /// Space on the partition available to the daemon, in bytes
+    pub available_bytes: u64,
 }
 
 impl DiskStats {
@@ -77,10 +191,10 @@ impl DiskStats {
         }
 
         let block_size = stat.f_frsize as u64;
-        let total_kb = (stat.f_blocks as u64 * block_size / 1024) as usize;
-        let free_kb = (stat.f_bfree as u64 * block_size / 1024) as usize;
-        let available_kb = (stat.f_bavail as u64 * block_size / 1024) as usize;
-        let used_kb = total_kb.saturating_sub(free_kb);
+        let total_bytes = stat.f_blocks as u64 * block_size;
+        let free_bytes = stat.f_bfree as u64 * block_size;
+        let available_bytes = stat.f_bavail as u64 * block_size;
+        let used_bytes = total_bytes.saturating_sub(free_bytes);
         let used_percent = format!(
             "{}%",
             ((stat.f_blocks - stat.f_bfree) * 100)
@@ -88,20 +202,33 @@ impl DiskStats {
                 .unwrap_or(0)
         );
 
+        let path = std::fs::canonicalize(qmdl_path).unwrap_or_else(|_| PathBuf::from(qmdl_path));
+        let mount = read_mounts(Path::new(PROC_MOUNTS))
+            .ok()
+            .and_then(|mounts| mount_containing(&mounts, &path).cloned());
+
         Ok(Self {
-            partition: qmdl_path.to_string(),
-            total_size: humanize_kb(total_kb),
-            used_size: humanize_kb(used_kb),
-            available_size: humanize_kb(available_kb),
+            partition: mount
+                .as_ref()
+                .map_or_else(|| qmdl_path.to_string(), |m| m.device.clone()),
+            total_size: humanize_kb((total_bytes / 1024) as usize),
+            used_size: humanize_kb((used_bytes / 1024) as usize),
+            available_size: humanize_kb((available_bytes / 1024) as usize),
             used_percent,
-            mounted_on: qmdl_path.to_string(),
-            available_bytes: Some(stat.f_bavail as u64 * block_size),
+            mounted_on: mount.as_ref().map_or_else(
+                || qmdl_path.to_string(),
+                |m| m.mount_point.display().to_string(),
+            ),
+            filesystem: mount.map(|m| m.fs_type),
+            total_bytes,
+            used_bytes,
+            available_bytes,
         })
     }
 }
 
 /// Device memory information
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
 pub struct MemoryStats {
     /// The total memory available on the device
@@ -110,6 +237,77 @@ pub struct MemoryStats {
     used: String,
     /// Remaining free memory
     free: String,
+    /// The total memory, in bytes
+    pub total_bytes: u64,
+    /// The used memory, in bytes
+    pub used_bytes: u64,
+    /// The free memory, in bytes
+    pub free_bytes: u64,
+    /// Memory available to start new processes without swapping, including
+    /// caches the kernel can reclaim (MemAvailable in /proc/meminfo)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available_bytes: Option<u64>,
+    /// Memory used by the page cache (Cached in /proc/meminfo)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cached_bytes: Option<u64>,
+    /// Memory used by block device buffers (Buffers in /proc/meminfo)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffers_bytes: Option<u64>,
+}
+
+const MEMINFO_PATH: &str = "/proc/meminfo";
+
+/// The fields of /proc/meminfo which [MemoryStats] reports, in bytes
+#[derive(Debug, Default, PartialEq)]
+struct MemInfo {
+    available_bytes: Option<u64>,
+    cached_bytes: Option<u64>,
+    buffers_bytes: Option<u64>,
+}
+
+// parses lines like "MemAvailable:     123456 kB". Fields which are missing,
+// like MemAvailable on kernels older than 3.14, are left as None.
+fn parse_meminfo(contents: &str) -> MemInfo {
+    let mut meminfo = MemInfo::default();
+    for line in contents.lines() {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let field = match name {
+            "MemAvailable" => &mut meminfo.available_bytes,
+            "Cached" => &mut meminfo.cached_bytes,
+            "Buffers" => &mut meminfo.buffers_bytes,
+            _ => continue,
+        };
+        *field = value
+            .trim()
+            .strip_suffix("kB")
+            .and_then(|kb| kb.trim().parse::<u64>().ok())
+            .map(|kb| kb * 1024);
+    }
+    meminfo
+}
+
+/// The memory line of `free -k`'s output, in kilobytes
+#[derive(Debug, PartialEq)]
+struct FreeOutput {
+    total_kb: usize,
+    used_kb: usize,
+    free_kb: usize,
+}
+
+// parses the "Mem:" line of `free -k`, whose first three columns are the
+// same in every busybox and procps version we've seen
+fn parse_free_output(stdout: &str) -> Option<FreeOutput> {
+    let line = stdout
+        .lines()
+        .find_map(|line| line.trim_start().strip_prefix("Mem:"))?;
+    let mut numbers = line.split_whitespace().map(|part| part.parse::<usize>());
+    Some(FreeOutput {
+        total_kb: numbers.next()?.ok()?,
+        used_kb: numbers.next()?.ok()?,
+        free_kb: numbers.next()?.ok()?,
+    })
 }
 
 // runs the given command and returns its stdout as a string
@@ -142,13 +340,24 @@ impl MemoryStats {
         }
         free_cmd.arg("-k");
         let stdout = get_cmd_output(free_cmd).await?;
-        let mut numbers = stdout
-            .split_whitespace()
-            .flat_map(|part| part.parse::<usize>());
+        let free = parse_free_output(&stdout).ok_or("error parsing free output")?;
+        let meminfo = match tokio::fs::read_to_string(MEMINFO_PATH).await {
+            Ok(contents) => parse_meminfo(&contents),
+            Err(e) => {
+                warn!("failed to read {MEMINFO_PATH}: {e}");
+                MemInfo::default()
+            }
+        };
         Ok(Self {
-            total: humanize_kb(numbers.next().ok_or("error parsing free output")?),
-            used: humanize_kb(numbers.next().ok_or("error parsing free output")?),
-            free: humanize_kb(numbers.next().ok_or("error parsing free output")?),
+            total: humanize_kb(free.total_kb),
+            used: humanize_kb(free.used_kb),
+            free: humanize_kb(free.free_kb),
+            total_bytes: free.total_kb as u64 * 1024,
+            used_bytes: free.used_kb as u64 * 1024,
+            free_bytes: free.free_kb as u64 * 1024,
+            available_bytes: meminfo.available_bytes,
+            cached_bytes: meminfo.cached_bytes,
+            buffers_bytes: meminfo.buffers_bytes,
         })
     }
 }
@@ -170,14 +379,34 @@ fn humanize_kb(kb: usize) -> String {
         (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Error collecting statistics")
     ),
     summary = "Get system info",
-    description = "Display system/device statistics."
+    description = "Display system/device statistics. Disk, memory and battery figures are sampled every few seconds, so they may be that much out of date."
 ))]
 pub async fn get_system_stats(
     State(state): State<Arc<ServerState>>,
 ) -> Result<Json<SystemStats>, (StatusCode, String)> {
-    let qmdl_store = state.qmdl_store_lock.read().await;
-    match SystemStats::new(qmdl_store.path.to_str().unwrap(), &state.config.device).await {
-        Ok(stats) => Ok(Json(stats)),
+    let cached = state.stats_cache.read().await.clone();
+    let sample = match cached {
+        Some(sample) => sample,
+        // the collector hasn't taken its first sample yet
+        None => {
+            let qmdl_store = state.qmdl_store_lock.read().await;
+            SampledStats::collect(
+                qmdl_store.path.to_str().unwrap(),
+                &state.config.device,
+                &state.battery,
+            )
+            .await
+        }
+    };
+    match sample {
+        Ok(sample) => Ok(Json(SystemStats::new(
+            sample,
+            state.daemon_state.stats(),
+            state.event_store_lock.read().await.size_bytes(),
+            state.diag_multiplexer.stats(),
+            state.capture_rate.rate(),
+            state.location.current(),
+        ))),
         Err(err) => {
             error!("error getting system stats: {err}");
             Err((
@@ -188,14 +417,42 @@ pub async fn get_system_stats(
     }
 }
 
+/// A QMDL manifest entry, with the summary of its analysis if it has one
+#[derive(Serialize)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct ManifestEntryStats {
+    #[serde(flatten)]
+    pub entry: ManifestEntry,
+    pub analysis_summary: Option<AnalysisSummary>,
+    /// How fast the entry is growing, for the entry being recorded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture_rate: Option<CaptureRate>,
+}
+
+impl ManifestEntryStats {
+    async fn new(entry: ManifestEntry, store_path: &std::path::Path) -> Self {
+        let analysis_summary = read_analysis_summary(&entry.get_summary_filepath(store_path)).await;
+        ManifestEntryStats {
+            entry,
+            analysis_summary,
+            capture_rate: None,
+        }
+    }
+}
+
 /// QMDL manifest information
 #[derive(Serialize)]
 #[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
 pub struct ManifestStats {
     /// A vector containing the names of the QMDL files
-    pub entries: Vec<ManifestEntry>,
+    pub entries: Vec<ManifestEntryStats>,
     /// The currently open QMDL file
-    pub current_entry: Option<ManifestEntry>,
+    pub current_entry: Option<ManifestEntryStats>,
+    /// The configured recording mode. In monitor_only mode there's never a
+    /// current entry, since nothing is written to disk.
+    pub mode: RecordingMode,
+    /// Whether monitor-only analysis is running
+    pub monitoring: bool,
 }
 
 #[cfg_attr(feature = "apidocs", utoipa::path(
@@ -206,17 +463,30 @@ pub struct ManifestStats {
         (status = StatusCode::OK, description = "Success", body = ManifestStats)
     ),
     summary = "QMDL Manifest",
-    description = "List QMDL files available on the device and some of their basic statistics."
+    description = "List QMDL files available on the device and some of their basic statistics, including any jumps in the system clock while they were recorded."
 ))]
 pub async fn get_qmdl_manifest(
     State(state): State<Arc<ServerState>>,
 ) -> Result<Json<ManifestStats>, (StatusCode, String)> {
     let qmdl_store = state.qmdl_store_lock.read().await;
     let mut entries = qmdl_store.manifest.entries.clone();
-    let current_entry = qmdl_store.current_entry.map(|index| entries.remove(index));
+    let current_entry = match qmdl_store.current_entry {
+        Some(index) => {
+            let mut stats = ManifestEntryStats::new(entries.remove(index), &qmdl_store.path).await;
+            stats.capture_rate = state.capture_rate.rate();
+            Some(stats)
+        }
+        None => None,
+    };
+    let mut entry_stats = Vec::with_capacity(entries.len());
+    for entry in entries {
+        entry_stats.push(ManifestEntryStats::new(entry, &qmdl_store.path).await);
+    }
     Ok(Json(ManifestStats {
-        entries,
+        entries: entry_stats,
         current_entry,
+        mode: state.config.recording_mode,
+        monitoring: *state.monitoring.read().await,
     }))
 }
 
@@ -229,10 +499,82 @@ pub async fn get_qmdl_manifest(
         (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Could not read /data/rayhunter/rayhunter.log file")
     ),
     summary = "Display log",
-    description = "Download the current device log in UTF-8 plaintext."
+    description = "Download the current device log in UTF-8 plaintext. The log is gzip-compressed if the request's Accept-Encoding allows it."
 ))]
-pub async fn get_log() -> Result<String, (StatusCode, String)> {
-    tokio::fs::read_to_string("/data/rayhunter/rayhunter.log")
+pub async fn get_log(headers: HeaderMap) -> Result<Response, (StatusCode, String)> {
+    let log_file = tokio::fs::File::open(LOG_PATH)
         .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(maybe_gzip_response(
+        &headers,
+        "text/plain; charset=utf-8",
+        ReaderStream::new(log_file),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_free_output() {
+        // busybox 1.2x, as on the Orbic and TP-Link
+        let old_busybox =
+            "             total       used       free     shared    buffers     cached
+Mem:        233740     222872      10868          0       1024      80364
+-/+ buffers/cache:     141484      92256
+Swap:            0          0          0
+";
+        assert_eq!(
+            parse_free_output(old_busybox),
+            Some(FreeOutput {
+                total_kb: 233740,
+                used_kb: 222872,
+                free_kb: 10868,
+            })
+        );
+
+        // busybox 1.30 and later, and procps
+        let new_busybox =
+            "              total        used        free      shared  buff/cache   available
+Mem:         233740      141484       10868           0       81388       96512
+Swap:             0           0           0
+";
+        assert_eq!(
+            parse_free_output(new_busybox),
+            Some(FreeOutput {
+                total_kb: 233740,
+                used_kb: 141484,
+                free_kb: 10868,
+            })
+        );
+
+        assert_eq!(parse_free_output(""), None);
+        assert_eq!(parse_free_output("Mem: 233740 lots\n"), None);
+    }
+
+    #[test]
+    fn test_parse_meminfo() {
+        let contents = "MemTotal:         233740 kB
+MemFree:           10868 kB
+MemAvailable:      96512 kB
+Buffers:            1024 kB
+Cached:            80364 kB
+SwapCached:            0 kB
+";
+        assert_eq!(
+            parse_meminfo(contents),
+            MemInfo {
+                available_bytes: Some(96512 * 1024),
+                cached_bytes: Some(80364 * 1024),
+                buffers_bytes: Some(1024 * 1024),
+            }
+        );
+
+        // MemAvailable only exists since Linux 3.14
+        let old_kernel = "MemTotal:  233740 kB\nMemFree:  10868 kB\nCached:  80364 kB\n";
+        let meminfo = parse_meminfo(old_kernel);
+        assert_eq!(meminfo.available_bytes, None);
+        assert_eq!(meminfo.cached_bytes, Some(80364 * 1024));
+    }
 }
@@ -0,0 +1,183 @@
+//! Keeps wifi scans from coming too often. Scanning takes the radio away
+//! from the device's own AP for a while, so a client which keeps asking for
+//! scans can noticeably slow down everything connected to it.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+
+/// What to do with a scan request
+#[derive(Debug, PartialEq)]
+enum ScanDecision<T> {
+    /// A scan finished after the request was made, whether the request ran
+    /// it or waited for it, so use its results
+    Share(T),
+    /// The last scan was too recent. Try again after `retry_after`, and use
+    /// its results meanwhile.
+    CoolingDown { retry_after: Duration, cached: T },
+    /// Go ahead and scan
+    Scan,
+}
+
+/// The results of the last successful scan, and when it finished
+#[derive(Debug)]
+struct ScanCache<T> {
+    last_scan: Option<(Instant, T)>,
+}
+
+impl<T> Default for ScanCache<T> {
+    fn default() -> Self {
+        ScanCache { last_scan: None }
+    }
+}
+
+impl<T: Clone> ScanCache<T> {
+    /// Decides what to do with a request made at `requested_at`, now that no
+    /// other scan is running
+    fn decide(&self, requested_at: Instant, now: Instant, cooldown: Duration) -> ScanDecision<T> {
+        let Some((finished_at, results)) = &self.last_scan else {
+            return ScanDecision::Scan;
+        };
+        if *finished_at >= requested_at {
+            return ScanDecision::Share(results.clone());
+        }
+        let since = now.saturating_duration_since(*finished_at);
+        if since < cooldown {
+            return ScanDecision::CoolingDown {
+                retry_after: cooldown - since,
+                cached: results.clone(),
+            };
+        }
+        ScanDecision::Scan
+    }
+
+    fn record(&mut self, results: T, now: Instant) {
+        self.last_scan = Some((now, results));
+    }
+}
+
+/// Runs one scan at a time. Requests made during a scan wait for it and
+/// share its results, and requests made within `cooldown` of the last scan
+/// get its results with how long to wait before asking again.
+pub struct WifiScanLimiter<T> {
+    cooldown: Duration,
+    cache: Mutex<ScanCache<T>>,
+}
+
+impl<T: Clone> WifiScanLimiter<T> {
+    pub fn new(cooldown: Duration) -> Self {
+        WifiScanLimiter {
+            cooldown,
+            cache: Mutex::new(ScanCache::default()),
+        }
+    }
+
+    /// Runs `scan`, unless its results would come from a scan which is
+    /// running or finished recently. Returns the results, and how long to
+    /// wait before asking again if they're from a scan within the cooldown.
+    /// Failed scans don't start a cooldown.
+    pub async fn scan<F, Fut, E>(&self, scan: F) -> Result<(T, Option<Duration>), E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let requested_at = Instant::now();
+        // held for the whole scan, which is what makes other requests wait
+        let mut cache = self.cache.lock().await;
+        match cache.decide(requested_at, Instant::now(), self.cooldown) {
+            ScanDecision::Share(results) => Ok((results, None)),
+            ScanDecision::CoolingDown {
+                retry_after,
+                cached,
+            } => Ok((cached, Some(retry_after))),
+            ScanDecision::Scan => {
+                let results = scan().await?;
+                cache.record(results.clone(), Instant::now());
+                Ok((results, None))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const COOLDOWN: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn test_cooldown() {
+        let start = Instant::now();
+        let mut cache = ScanCache::default();
+        assert_eq!(cache.decide(start, start, COOLDOWN), ScanDecision::Scan);
+
+        cache.record("networks", start + Duration::from_secs(5));
+        let requested = start + Duration::from_secs(15);
+        assert_eq!(
+            cache.decide(requested, requested, COOLDOWN),
+            ScanDecision::CoolingDown {
+                retry_after: Duration::from_secs(20),
+                cached: "networks",
+            }
+        );
+        let requested = start + Duration::from_secs(35);
+        assert_eq!(
+            cache.decide(requested, requested, COOLDOWN),
+            ScanDecision::Scan
+        );
+    }
+
+    #[test]
+    fn test_waiting_requests_share_the_scan() {
+        let start = Instant::now();
+        let mut cache = ScanCache::default();
+        // requested while a scan was running, which then finished
+        cache.record("networks", start + Duration::from_secs(5));
+        assert_eq!(
+            cache.decide(start, start + Duration::from_secs(5), COOLDOWN),
+            ScanDecision::Share("networks")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_scan_once() {
+        let limiter = Arc::new(WifiScanLimiter::new(COOLDOWN));
+        let scans = Arc::new(AtomicUsize::new(0));
+        let scan = |scans: Arc<AtomicUsize>| async move {
+            scans.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok::<_, ()>(vec!["network"])
+        };
+
+        let first = tokio::spawn({
+            let limiter = limiter.clone();
+            let scans = scans.clone();
+            async move { limiter.scan(|| scan(scans)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let second = limiter.scan(|| scan(scans.clone())).await;
+
+        assert_eq!(first.await.unwrap(), Ok((vec!["network"], None)));
+        assert_eq!(second, Ok((vec!["network"], None)));
+        assert_eq!(scans.load(Ordering::Relaxed), 1);
+
+        // and a later one is told to wait
+        let (cached, retry_after) = limiter.scan(|| scan(scans.clone())).await.unwrap();
+        assert_eq!(cached, vec!["network"]);
+        assert!(retry_after.unwrap() > COOLDOWN - Duration::from_secs(1));
+        assert_eq!(scans.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_scan_does_not_cool_down() {
+        let limiter = WifiScanLimiter::<Vec<&str>>::new(COOLDOWN);
+        assert_eq!(limiter.scan(|| async { Err("busy") }).await, Err("busy"));
+        let result = limiter
+            .scan(|| async { Ok::<_, ()>(vec!["network"]) })
+            .await;
+        assert_eq!(result, Ok((vec!["network"], None)));
+    }
+}
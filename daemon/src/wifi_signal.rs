@@ -0,0 +1,65 @@
+//! Reads the signal strength of the network the wifi client is connected
+//! to, the first thing to check when its link is flaky.
+
+use log::debug;
+use tokio::process::Command;
+
+/// The signal strength in dBm from `iw dev <iface> link`, or None if it
+/// isn't connected.
+pub fn parse_iw_link_signal(output: &str) -> Option<i32> {
+    output.lines().find_map(|line| {
+        let value = line.trim().strip_prefix("signal:")?;
+        value.split_whitespace().next()?.parse().ok()
+    })
+}
+
+/// The wifi client's signal strength in dBm, or None if it isn't connected
+/// or `iw` couldn't be run.
+pub async fn read_signal_dbm(iw_bin: Option<&str>, iface: &str) -> Option<i32> {
+    let output = match Command::new(iw_bin.unwrap_or("iw"))
+        .args(["dev", iface, "link"])
+        .output()
+        .await
+    {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            debug!(
+                "iw dev {iface} link failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            return None;
+        }
+        Err(e) => {
+            debug!("couldn't run iw: {e}");
+            return None;
+        }
+    };
+    parse_iw_link_signal(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_iw_link_signal() {
+        let connected = "\
+Connected to 11:22:33:44:55:66 (on wlan1)
+\tSSID: home
+\tfreq: 2437
+\tRX: 123456 bytes (789 packets)
+\tTX: 23456 bytes (189 packets)
+\tsignal: -62 dBm
+\trx bitrate: 72.2 MBit/s MCS 7 short GI
+\ttx bitrate: 65.0 MBit/s MCS 7
+
+\tbss flags:\tshort-slot-time
+\tdtim period:\t1
+\tbeacon int:\t100
+";
+        assert_eq!(parse_iw_link_signal(connected), Some(-62));
+        assert_eq!(parse_iw_link_signal("Not connected.\n"), None);
+        assert_eq!(parse_iw_link_signal(""), None);
+        assert_eq!(parse_iw_link_signal("\tsignal: unknown\n"), None);
+    }
+}
@@ -0,0 +1,527 @@
+//! Checks new wifi client credentials by associating with them before
+//! they're saved. Otherwise a mistyped password only shows up as the wifi
+//! client failing after the restart, by which point the previous, working
+//! credentials are gone and the device may not be reachable over wifi.
+
+use std::fmt::Display;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::UnixDatagram;
+use tokio::process::Command;
+
+use crate::config::Config;
+
+/// How long to wait for the test association to succeed
+pub const VERIFY_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Where wpa_supplicant usually keeps its control sockets, for devices whose
+/// config doesn't set a directory
+const DEFAULT_CTRL_DIR: &str = "/var/run/wpa_supplicant";
+
+/// How long wpa_supplicant gets to answer a control interface command
+const CTRL_REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Why new wifi credentials didn't work
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum AssociationFailure {
+    #[error("the password was rejected")]
+    WrongKey,
+    #[error("the network wasn't found")]
+    NetworkNotFound,
+    #[error("the access point rejected the connection ({0})")]
+    Rejected(String),
+    #[error("timed out connecting")]
+    Timeout,
+    #[error("couldn't run wpa_supplicant: {0}")]
+    Unavailable(String),
+}
+
+fn unavailable(e: impl Display) -> AssociationFailure {
+    AssociationFailure::Unavailable(e.to_string())
+}
+
+/// Classifies a line of wpa_supplicant's output, returning the test
+/// association's outcome if the line settles it.
+pub fn classify_wpa_line(line: &str) -> Option<Result<(), AssociationFailure>> {
+    if line.contains("CTRL-EVENT-CONNECTED") {
+        Some(Ok(()))
+    } else if line.contains("reason=WRONG_KEY") || line.contains("pre-shared key may be incorrect")
+    {
+        Some(Err(AssociationFailure::WrongKey))
+    } else if line.contains("CTRL-EVENT-ASSOC-REJECT") || line.contains("CTRL-EVENT-AUTH-REJECT") {
+        let status = line
+            .split_whitespace()
+            .find(|word| word.starts_with("status_code="))
+            .unwrap_or("no status code");
+        Some(Err(AssociationFailure::Rejected(status.to_string())))
+    } else {
+        None
+    }
+}
+
+/// Follows wpa_supplicant's output until it settles the test association
+#[derive(Debug, Default)]
+struct OutcomeWatcher {
+    network_not_found: bool,
+}
+
+impl OutcomeWatcher {
+    /// Returns the outcome once a line settles it. Not finding the network
+    /// doesn't: wpa_supplicant keeps scanning, and an access point can miss
+    /// a scan or two before it shows up.
+    fn feed(&mut self, line: &str) -> Option<Result<(), AssociationFailure>> {
+        debug!("wpa_supplicant: {line}");
+        if line.contains("CTRL-EVENT-NETWORK-NOT-FOUND") {
+            self.network_not_found = true;
+        }
+        classify_wpa_line(line)
+    }
+
+    /// Why the association failed, if nothing settled it in time
+    fn timed_out(&self) -> AssociationFailure {
+        if self.network_not_found {
+            AssociationFailure::NetworkNotFound
+        } else {
+            AssociationFailure::Timeout
+        }
+    }
+}
+
+/// Whether going from the `running` config to `new` sets new wifi client
+/// credentials which should be checked first. The API never returns the
+/// password, so one is only sent when it's being changed.
+pub fn verification_needed(running: &Config, new: &Config) -> bool {
+    new.wifi_verify_credentials
+        && !running.developer_mode
+        && new.wifi_enabled
+        && new.wifi_ssid.is_some()
+        && new.wifi_password.is_some()
+}
+
+/// Runs `commit` if `verify` succeeds, or right away if verification isn't
+/// `needed`. If it fails, nothing is committed, so the previous settings
+/// stay in place.
+pub async fn commit_if_verified<T>(
+    needed: bool,
+    verify: impl Future<Output = Result<(), AssociationFailure>>,
+    commit: impl Future<Output = T>,
+) -> Result<T, AssociationFailure> {
+    if needed {
+        verify.await?;
+    }
+    Ok(commit.await)
+}
+
+/// Associates with the network `config` sets, waiting up to `timeout` for
+/// the outcome. If the wifi client is running, its wpa_supplicant is asked
+/// to try the network alongside its own, and goes back to its own network
+/// afterwards. Otherwise a separate wpa_supplicant is started for the test.
+pub async fn test_associate(config: &Config, timeout: Duration) -> Result<(), AssociationFailure> {
+    // the generated wpa_supplicant config holds the password, so it's kept
+    // in a directory only we can read, which is removed afterwards
+    let dir = tempfile::Builder::new()
+        .prefix("rayhunter-wifi-verify")
+        .tempdir()
+        .map_err(unavailable)?;
+    let conf_path = dir.path().join("wpa.conf");
+    let mut wifi_config = config.wifi_config();
+    let ctrl_dir = wifi_config
+        .ctrl_interface
+        .as_ref()
+        .map_or(Path::new(DEFAULT_CTRL_DIR), Path::new)
+        .to_path_buf();
+    wifi_config.wpa_conf_path = Some(conf_path.to_string_lossy().into_owned().into());
+    wifi_config.ctrl_interface = Some(
+        dir.path()
+            .join("ctrl")
+            .to_string_lossy()
+            .into_owned()
+            .into(),
+    );
+    wifi_station::update_wpa_conf(&wifi_config).await;
+
+    let live_socket = ctrl_dir.join(wifi_station::STA_IFACE);
+    let result = if tokio::fs::try_exists(&live_socket).await.unwrap_or(false) {
+        associate_via_live_client(&live_socket, &conf_path, dir.path(), timeout).await
+    } else {
+        let wpa_bin = wifi_config
+            .wpa_supplicant_bin
+            .as_deref()
+            .unwrap_or("wpa_supplicant");
+        associate(wpa_bin, &conf_path, timeout).await
+    };
+    if let Err(e) = dir.close() {
+        debug!("couldn't remove the test association's config: {e}");
+    }
+    match &result {
+        Ok(()) => info!("new wifi credentials verified"),
+        Err(e) => warn!("new wifi credentials didn't work: {e}"),
+    }
+    result
+}
+
+// The settings in the network block of a wpa_supplicant config. The control
+// interface's SET_NETWORK takes the same values.
+fn network_settings(conf: &str) -> Vec<(&str, &str)> {
+    conf.lines()
+        .map(str::trim)
+        .skip_while(|line| !(line.starts_with("network") && line.ends_with('{')))
+        .skip(1)
+        .take_while(|line| *line != "}")
+        .filter_map(|line| line.split_once('='))
+        .collect()
+}
+
+/// A client of a running wpa_supplicant's control interface
+struct WpaCtrl(UnixDatagram);
+
+impl WpaCtrl {
+    fn open(socket: &Path, local: &Path) -> Result<Self, AssociationFailure> {
+        let client = UnixDatagram::bind(local).map_err(unavailable)?;
+        client.connect(socket).map_err(unavailable)?;
+        Ok(WpaCtrl(client))
+    }
+
+    async fn recv(&self) -> Result<String, AssociationFailure> {
+        let mut buf = vec![0; 4096];
+        let len = self.0.recv(&mut buf).await.map_err(unavailable)?;
+        Ok(String::from_utf8_lossy(&buf[..len]).trim_end().to_string())
+    }
+
+    async fn request(&self, command: &str) -> Result<String, AssociationFailure> {
+        // the command's arguments may include the password, so only its
+        // name and the network ID and setting it applies to are logged
+        let name = command.splitn(4, ' ').take(3).collect::<Vec<_>>().join(" ");
+        self.0
+            .send(command.as_bytes())
+            .await
+            .map_err(|e| unavailable(format!("{name}: {e}")))?;
+        match tokio::time::timeout(CTRL_REPLY_TIMEOUT, self.recv()).await {
+            Ok(reply) => reply,
+            Err(_) => Err(unavailable(format!("no reply to {name}"))),
+        }
+    }
+
+    async fn expect_ok(&self, command: &str) -> Result<(), AssociationFailure> {
+        match self.request(command).await?.as_str() {
+            "OK" => Ok(()),
+            reply => Err(unavailable(format!(
+                "{} failed: {reply}",
+                command.split(' ').next().unwrap_or(command)
+            ))),
+        }
+    }
+}
+
+// Adds the network in `conf_path` to the wifi client's wpa_supplicant and
+// switches to it, then removes it again, leaving the wifi client to go back
+// to its own network
+async fn associate_via_live_client(
+    socket: &Path,
+    conf_path: &Path,
+    local_dir: &Path,
+    timeout: Duration,
+) -> Result<(), AssociationFailure> {
+    let conf = tokio::fs::read_to_string(conf_path)
+        .await
+        .map_err(unavailable)?;
+    let ctrl = WpaCtrl::open(socket, &local_dir.join("ctrl-request"))?;
+    let monitor = WpaCtrl::open(socket, &local_dir.join("ctrl-events"))?;
+    monitor.expect_ok("ATTACH").await?;
+    let id = ctrl.request("ADD_NETWORK").await?;
+    if id.parse::<u32>().is_err() {
+        return Err(unavailable(format!("ADD_NETWORK failed: {id}")));
+    }
+
+    let result = async {
+        for (name, value) in network_settings(&conf) {
+            ctrl.expect_ok(&format!("SET_NETWORK {id} {name} {value}"))
+                .await?;
+        }
+        ctrl.expect_ok(&format!("SELECT_NETWORK {id}")).await?;
+        let mut watcher = OutcomeWatcher::default();
+        tokio::time::timeout(timeout, async {
+            loop {
+                if let Some(outcome) = watcher.feed(&monitor.recv().await?) {
+                    return outcome;
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| Err(watcher.timed_out()))
+    }
+    .await;
+
+    for command in [
+        format!("REMOVE_NETWORK {id}"),
+        "ENABLE_NETWORK all".to_string(),
+        "REASSOCIATE".to_string(),
+    ] {
+        if let Err(e) = ctrl.expect_ok(&command).await {
+            warn!("couldn't restore the wifi client's network: {e}");
+        }
+    }
+    if let Err(e) = monitor.expect_ok("DETACH").await {
+        debug!("couldn't detach from wpa_supplicant: {e}");
+    }
+    result
+}
+
+// Runs a wpa_supplicant of our own with the config in `conf_path`, for when
+// the wifi client isn't running
+async fn associate(
+    wpa_bin: &str,
+    conf_path: &Path,
+    timeout: Duration,
+) -> Result<(), AssociationFailure> {
+    let iface = wifi_station::STA_IFACE;
+    match Command::new("ip")
+        .args(["link", "set", iface, "up"])
+        .status()
+        .await
+    {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("ip link set {iface} up failed: {status}"),
+        Err(e) => warn!("couldn't run ip: {e}"),
+    }
+
+    let mut child = Command::new(wpa_bin)
+        .args(["-i", iface, "-c"])
+        .arg(conf_path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(unavailable)?;
+    let stdout = child.stdout.take().expect("wpa_supplicant stdout is piped");
+    let mut lines = BufReader::new(stdout).lines();
+    let mut watcher = OutcomeWatcher::default();
+    let outcome = tokio::time::timeout(timeout, async {
+        while let Ok(Some(line)) = lines.next_line().await {
+            if let Some(outcome) = watcher.feed(&line) {
+                return outcome;
+            }
+        }
+        Err(AssociationFailure::Unavailable(
+            "wpa_supplicant exited".to_string(),
+        ))
+    })
+    .await
+    .unwrap_or_else(|_| Err(watcher.timed_out()));
+
+    if let Err(e) = child.kill().await {
+        debug!("couldn't stop the test wpa_supplicant: {e}");
+    }
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn test_classify_wpa_line() {
+        let cases = [
+            (
+                "wlan1: CTRL-EVENT-CONNECTED - Connection to 11:22:33:44:55:66 completed [id=0 id_str=]",
+                Some(Ok(())),
+            ),
+            (
+                "wlan1: CTRL-EVENT-SSID-TEMP-DISABLED id=0 ssid=\"home\" auth_failures=1 duration=10 reason=WRONG_KEY",
+                Some(Err(AssociationFailure::WrongKey)),
+            ),
+            (
+                "wlan1: WPA: 4-Way Handshake failed - pre-shared key may be incorrect",
+                Some(Err(AssociationFailure::WrongKey)),
+            ),
+            // wpa_supplicant keeps looking after this
+            ("wlan1: CTRL-EVENT-NETWORK-NOT-FOUND", None),
+            (
+                "wlan1: CTRL-EVENT-ASSOC-REJECT bssid=11:22:33:44:55:66 status_code=17",
+                Some(Err(AssociationFailure::Rejected(
+                    "status_code=17".to_string(),
+                ))),
+            ),
+            (
+                "wlan1: Trying to associate with 11:22:33:44:55:66 (SSID='home' freq=2437 MHz)",
+                None,
+            ),
+            ("Successfully initialized wpa_supplicant", None),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(classify_wpa_line(line), expected, "{line}");
+        }
+    }
+
+    #[test]
+    fn test_network_not_found_is_reported_on_timeout() {
+        let mut watcher = OutcomeWatcher::default();
+        assert_eq!(watcher.timed_out(), AssociationFailure::Timeout);
+        assert_eq!(watcher.feed("wlan1: CTRL-EVENT-NETWORK-NOT-FOUND"), None);
+        assert_eq!(watcher.timed_out(), AssociationFailure::NetworkNotFound);
+
+        // the access point showing up on a later scan still counts
+        assert_eq!(
+            watcher.feed("wlan1: CTRL-EVENT-CONNECTED - Connection to 11:22:33:44:55:66 completed"),
+            Some(Ok(()))
+        );
+    }
+
+    #[test]
+    fn test_network_settings() {
+        let conf = "ctrl_interface=/tmp/ctrl\nupdate_config=0\n\nnetwork={\n\tssid=\"home\"\n\tpsk=\"pass=word\"\n\tkey_mgmt=WPA-PSK\n}\n";
+        assert_eq!(
+            network_settings(conf),
+            [
+                ("ssid", "\"home\""),
+                ("psk", "\"pass=word\""),
+                ("key_mgmt", "WPA-PSK")
+            ]
+        );
+        assert!(network_settings("ctrl_interface=/tmp/ctrl\n").is_empty());
+    }
+
+    // Stands in for the wifi client's wpa_supplicant, answering every
+    // command and reporting a connection once a network is selected
+    async fn fake_wpa_supplicant(socket: UnixDatagram) -> Vec<String> {
+        let mut commands = Vec::new();
+        let mut monitor = None;
+        let mut buf = vec![0; 4096];
+        loop {
+            let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+            let from = from.as_pathname().unwrap().to_path_buf();
+            let command = String::from_utf8_lossy(&buf[..len]).to_string();
+            let reply = match command.as_str() {
+                "ADD_NETWORK" => "1",
+                _ => "OK",
+            };
+            socket.send_to(reply.as_bytes(), &from).await.unwrap();
+            if command == "ATTACH" {
+                monitor = Some(from);
+            } else if command.starts_with("SELECT_NETWORK") {
+                let event = "<3>CTRL-EVENT-CONNECTED - Connection to 11:22:33:44:55:66 completed";
+                let monitor = monitor.as_ref().unwrap();
+                socket.send_to(event.as_bytes(), monitor).await.unwrap();
+            }
+            let done = command == "DETACH";
+            commands.push(command);
+            if done {
+                return commands;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_associate_via_live_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("wlan1");
+        let supplicant = tokio::spawn(fake_wpa_supplicant(
+            UnixDatagram::bind(&socket_path).unwrap(),
+        ));
+        let conf_path = dir.path().join("wpa.conf");
+        tokio::fs::write(
+            &conf_path,
+            "network={\n\tssid=\"home\"\n\tpsk=\"hunter22\"\n}\n",
+        )
+        .await
+        .unwrap();
+
+        let result =
+            associate_via_live_client(&socket_path, &conf_path, dir.path(), VERIFY_TIMEOUT).await;
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            supplicant.await.unwrap(),
+            [
+                "ATTACH",
+                "ADD_NETWORK",
+                "SET_NETWORK 1 ssid \"home\"",
+                "SET_NETWORK 1 psk \"hunter22\"",
+                "SELECT_NETWORK 1",
+                // the wifi client goes back to its own network
+                "REMOVE_NETWORK 1",
+                "ENABLE_NETWORK all",
+                "REASSOCIATE",
+                "DETACH",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verification_needed() {
+        let running = Config::default();
+        let new = Config {
+            wifi_verify_credentials: true,
+            wifi_enabled: true,
+            wifi_ssid: Some("home".to_string()),
+            wifi_password: Some("hunter22".to_string()),
+            ..Config::default()
+        };
+        assert!(verification_needed(&running, &new));
+
+        // the password isn't changing
+        let unchanged = Config {
+            wifi_password: None,
+            ..new.clone()
+        };
+        assert!(!verification_needed(&running, &unchanged));
+
+        let disabled = Config {
+            wifi_verify_credentials: false,
+            ..new.clone()
+        };
+        assert!(!verification_needed(&running, &disabled));
+
+        let wifi_off = Config {
+            wifi_enabled: false,
+            ..new.clone()
+        };
+        assert!(!verification_needed(&running, &wifi_off));
+
+        let developer = Config {
+            developer_mode: true,
+            ..Config::default()
+        };
+        assert!(!verification_needed(&developer, &new));
+    }
+
+    async fn decide(
+        needed: bool,
+        verified: Result<(), AssociationFailure>,
+    ) -> (Result<(), AssociationFailure>, bool) {
+        let committed = AtomicBool::new(false);
+        let result = commit_if_verified(needed, async { verified }, async {
+            committed.store(true, Ordering::Relaxed);
+        })
+        .await;
+        (result, committed.load(Ordering::Relaxed))
+    }
+
+    #[tokio::test]
+    async fn test_commit_on_success() {
+        assert_eq!(decide(true, Ok(())).await, (Ok(()), true));
+    }
+
+    #[tokio::test]
+    async fn test_rollback_on_failure() {
+        assert_eq!(
+            decide(true, Err(AssociationFailure::WrongKey)).await,
+            (Err(AssociationFailure::WrongKey), false)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commit_without_verification() {
+        // a failure which would have been found doesn't matter if the
+        // credentials aren't being checked
+        assert_eq!(
+            decide(false, Err(AssociationFailure::Timeout)).await,
+            (Ok(()), true)
+        );
+    }
+}
@@ -0,0 +1,366 @@
+use crate::pcap::RETRY_AFTER_SECS;
+use crate::server::ServerState;
+
+use anyhow::Error;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::http::header::{CONTENT_TYPE, RETRY_AFTER};
+use axum::response::{IntoResponse, Response};
+use rayhunter::analysis::analyzer::{AnalyzerConfig, EventType, Harness, ReportMetadata};
+use rayhunter::diag::DataType;
+use rayhunter::qmdl::QmdlReader;
+use rayhunter::redact::redact_text;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HintFormat {
+    /// A display filter matching the flagged frames
+    #[default]
+    Filter,
+    /// A Lua post-dissector which labels and colors the flagged frames
+    Lua,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct WiresharkQuery {
+    #[serde(default)]
+    pub format: HintFormat,
+    /// Only flag frames with an event at least this severe. Defaults to
+    /// medium.
+    pub min_severity: Option<String>,
+    /// Whether to redact IMSIs, IMEIs and phone numbers in event messages,
+    /// overriding the redact_identifiers setting
+    pub redact: Option<bool>,
+}
+
+/// An event, and the number of the pcap frame holding the message which
+/// triggered it
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventFrame {
+    pub frame: usize,
+    pub event_type: EventType,
+    pub message: String,
+}
+
+// The analysis report doesn't record which pcap frame each row became, so
+// this runs the report's analyzers over the QMDL file again. The pcap writer
+// writes one frame for every message the GSMTAP parser turns into a packet,
+// which are exactly the rows with a packet timestamp, so the mapping doesn't
+// need the pcap itself. That's the unfiltered pcap: a download with
+// ?filter= leaves frames out, so its frame numbers don't match. Going over
+// the whole recording costs as much as a conversion, so it takes one of the
+// conversion slots.
+#[cfg_attr(feature = "apidocs", utoipa::path(
+    get,
+    path = "/api/analysis-report/{name}/wireshark",
+    tag = "Recordings",
+    responses(
+        (status = StatusCode::OK, description = "Display filter or Lua post-dissector", content_type = "text/plain"),
+        (status = StatusCode::BAD_REQUEST, description = "Unknown severity or format"),
+        (status = StatusCode::NOT_FOUND, description = "Could not find file {name}"),
+        (status = StatusCode::TOO_MANY_REQUESTS, description = "A PCAP conversion is already running"),
+        (status = StatusCode::SERVICE_UNAVAILABLE, description = "QMDL file is empty")
+    ),
+    params(
+        ("name" = String, Path, description = "QMDL file to find the flagged frames of"),
+        ("format" = Option<String>, Query, description = "filter (the default) for a display filter, or lua for a post-dissector which labels and colors the flagged frames"),
+        ("min_severity" = Option<String>, Query, description = "Only flag frames with an event at least this severe: informational, low, medium (the default) or high"),
+        ("redact" = Option<bool>, Query, description = "Redact IMSIs, IMEIs and phone numbers in the Lua post-dissector's messages. Defaults to the redact_identifiers setting.")
+    ),
+    summary = "Wireshark hints",
+    description = "Find the frames of the PCAP download for {name} which hold the messages that triggered events, and return a Wireshark display filter matching them, e.g. frame.number in {102 344 1203}, or a Lua post-dissector which labels and colors them. The frames are worked out by running the analyzers the report was written with, so the PCAP doesn't have to be downloaded first. The frame numbers are those of the unfiltered PCAP, so they don't match a download with ?filter=. Working them out takes one of the PCAP conversion slots."
+))]
+pub async fn get_wireshark_hints(
+    State(state): State<Arc<ServerState>>,
+    Path(qmdl_name): Path<String>,
+    Query(query): Query<WiresharkQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let min_severity = match &query.min_severity {
+        Some(severity) => severity
+            .parse::<EventType>()
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?,
+        None => EventType::Medium,
+    };
+    let redact = query.redact.unwrap_or(state.config.redact_identifiers);
+
+    let (qmdl_file, qmdl_size_bytes, analyzer_config) = {
+        let qmdl_store = state.qmdl_store_lock.read().await;
+        let (entry_index, entry) = qmdl_store.entry_for_name(&qmdl_name).ok_or((
+            StatusCode::NOT_FOUND,
+            format!("couldn't find manifest entry with name {qmdl_name}"),
+        ))?;
+        if entry.qmdl_size_bytes == 0 {
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "QMDL file is empty, try again in a bit!".to_string(),
+            ));
+        }
+        let qmdl_size_bytes = entry.qmdl_size_bytes;
+        // use the analyzers the report was written with, so the frames match
+        // its events. recordings which haven't been analyzed yet fall back to
+        // the current settings.
+        let analyzer_config = match qmdl_store.open_entry_analysis(entry_index).await {
+            Ok(file) => match read_report_metadata(file).await {
+                Some(metadata) => {
+                    let names: Vec<String> = metadata
+                        .analyzers
+                        .into_iter()
+                        .map(|analyzer| analyzer.name)
+                        .collect();
+                    state.config.analyzers.with_only_named(&names)
+                }
+                None => state.config.analyzers.clone(),
+            },
+            Err(_) => state.config.analyzers.clone(),
+        };
+        let qmdl_file = qmdl_store
+            .open_entry_qmdl(entry_index)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:?}")))?;
+        (qmdl_file, qmdl_size_bytes, analyzer_config)
+    };
+
+    let Ok(_permit) = state.pcap_conversion_semaphore.try_acquire() else {
+        return Ok((
+            StatusCode::TOO_MANY_REQUESTS,
+            [(RETRY_AFTER, RETRY_AFTER_SECS)],
+            "a PCAP conversion is already running, try again in a bit!",
+        )
+            .into_response());
+    };
+    let frames = find_event_frames(qmdl_file, qmdl_size_bytes, &analyzer_config, min_severity)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:?}")))?;
+    let body = match query.format {
+        HintFormat::Filter => display_filter(frames.iter().map(|frame| frame.frame)),
+        HintFormat::Lua => lua_post_dissector(&qmdl_name, &frames, redact),
+    };
+    Ok(([(CONTENT_TYPE, "text/plain; charset=utf-8")], body).into_response())
+}
+
+async fn read_report_metadata<R: AsyncRead + Unpin>(file: R) -> Option<ReportMetadata> {
+    let line = BufReader::new(file).lines().next_line().await.ok()??;
+    serde_json::from_str(&line).ok()
+}
+
+pub async fn find_event_frames<R: AsyncRead + Unpin>(
+    qmdl_file: R,
+    qmdl_size_bytes: usize,
+    analyzer_config: &AnalyzerConfig,
+    min_severity: EventType,
+) -> Result<Vec<EventFrame>, Error> {
+    let mut harness = Harness::new_with_config(analyzer_config);
+    let mut reader = QmdlReader::new(qmdl_file, Some(qmdl_size_bytes));
+    let mut frame = 0;
+    let mut frames = Vec::new();
+    while let Some(container) = reader.get_next_messages_container().await? {
+        if container.data_type != DataType::UserSpace {
+            continue;
+        }
+        for row in harness.analyze_qmdl_messages(container) {
+            if row.packet_timestamp.is_none() {
+                continue;
+            }
+            frame += 1;
+            for event in row.events.into_iter().flatten() {
+                if event.event_type >= min_severity {
+                    frames.push(EventFrame {
+                        frame,
+                        event_type: event.event_type,
+                        message: event.message,
+                    });
+                }
+            }
+        }
+    }
+    Ok(frames)
+}
+
+/// Builds a display filter matching the given frame numbers, with runs of
+/// consecutive frames written as ranges to keep long lists short. With no
+/// frames, the filter matches nothing.
+pub fn display_filter(frames: impl IntoIterator<Item = usize>) -> String {
+    let mut frames: Vec<usize> = frames.into_iter().collect();
+    frames.sort_unstable();
+    frames.dedup();
+    if frames.is_empty() {
+        // frame numbers start at 1
+        return "frame.number == 0".to_string();
+    }
+
+    let mut ranges = Vec::new();
+    let mut start = frames[0];
+    let mut end = start;
+    for &frame in &frames[1..] {
+        if frame == end + 1 {
+            end = frame;
+        } else {
+            ranges.push((start, end));
+            start = frame;
+            end = frame;
+        }
+    }
+    ranges.push((start, end));
+
+    let set: Vec<String> = ranges
+        .into_iter()
+        .map(|(start, end)| match end - start {
+            0 => start.to_string(),
+            1 => format!("{start} {end}"),
+            _ => format!("{start}..{end}"),
+        })
+        .collect();
+    format!("frame.number in {{{}}}", set.join(" "))
+}
+
+fn lua_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => {
+                for byte in c.to_string().bytes() {
+                    let _ = write!(escaped, "\\{byte:03}");
+                }
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Builds a Lua post-dissector which adds a "rayhunter" tree to each flagged
+/// frame, with its most severe event and every event's message, and colors
+/// those frames.
+pub fn lua_post_dissector(qmdl_name: &str, frames: &[EventFrame], redact: bool) -> String {
+    let mut by_frame: BTreeMap<usize, (EventType, Vec<String>)> = BTreeMap::new();
+    for event in frames {
+        let message = if redact {
+            redact_text(&event.message).into_owned()
+        } else {
+            event.message.clone()
+        };
+        let entry = by_frame
+            .entry(event.frame)
+            .or_insert((event.event_type, Vec::new()));
+        entry.0 = entry.0.max(event.event_type);
+        entry.1.push(message);
+    }
+
+    let mut lua = String::new();
+    let _ = writeln!(lua, "-- Rayhunter events in {qmdl_name}");
+    let _ = writeln!(
+        lua,
+        "-- Load with: wireshark -X lua_script:rayhunter.lua {qmdl_name}.pcapng"
+    );
+    lua.push_str(concat!(
+        "local rayhunter = Proto(\"rayhunter\", \"Rayhunter events\")\n",
+        "local f_severity = ProtoField.string(\"rayhunter.severity\", \"Severity\")\n",
+        "local f_message = ProtoField.string(\"rayhunter.message\", \"Message\")\n",
+        "rayhunter.fields = { f_severity, f_message }\n",
+        "\n",
+        "local events = {\n",
+    ));
+    for (frame, (event_type, messages)) in &by_frame {
+        let messages: Vec<String> = messages.iter().map(|m| lua_string(m)).collect();
+        let _ = writeln!(
+            lua,
+            "  [{frame}] = {{ {}, {{ {} }} }},",
+            lua_string(&format!("{event_type:?}")),
+            messages.join(", ")
+        );
+    }
+    lua.push_str(concat!(
+        "}\n",
+        "\n",
+        "function rayhunter.dissector(tvb, pinfo, tree)\n",
+        "  local event = events[pinfo.number]\n",
+        "  if event == nil then return end\n",
+        "  local subtree = tree:add(rayhunter, \"Rayhunter: \" .. event[1])\n",
+        "  subtree:add(f_severity, event[1])\n",
+        "  for _, message in ipairs(event[2]) do\n",
+        "    subtree:add(f_message, message)\n",
+        "  end\n",
+        "end\n",
+        "\n",
+        "register_postdissector(rayhunter)\n",
+        "if set_color_filter_slot then\n",
+        "  set_color_filter_slot(1, \"rayhunter\")\n",
+        "end\n",
+    ));
+    lua
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event_frame(frame: usize, event_type: EventType, message: &str) -> EventFrame {
+        EventFrame {
+            frame,
+            event_type,
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_display_filter_empty() {
+        assert_eq!(display_filter([]), "frame.number == 0");
+    }
+
+    #[test]
+    fn test_display_filter() {
+        assert_eq!(
+            display_filter([1203, 102, 344]),
+            "frame.number in {102 344 1203}"
+        );
+        assert_eq!(
+            display_filter([5, 1, 2, 3, 7, 8, 5]),
+            "frame.number in {1..3 5 7 8}"
+        );
+    }
+
+    #[test]
+    fn test_display_filter_many_frames() {
+        // consecutive frames collapse into a single range
+        assert_eq!(display_filter(1..=1500), "frame.number in {1..1500}");
+
+        let scattered: Vec<usize> = (1..=1500).map(|n| n * 2).collect();
+        let filter = display_filter(scattered.clone());
+        let inner = filter
+            .strip_prefix("frame.number in {")
+            .and_then(|rest| rest.strip_suffix('}'))
+            .unwrap();
+        let numbers: Vec<usize> = inner.split(' ').map(|n| n.parse().unwrap()).collect();
+        assert_eq!(numbers, scattered);
+    }
+
+    #[test]
+    fn test_lua_post_dissector() {
+        let frames = [
+            event_frame(12, EventType::Medium, "IMSI requested (packet 14)"),
+            event_frame(12, EventType::High, "say \"hi\"\\"),
+            event_frame(40, EventType::Medium, "IMSI 001010123456789 (packet 44)"),
+        ];
+        let lua = lua_post_dissector("1700000000", &frames, true);
+        assert!(lua.contains(
+            "[12] = { \"High\", { \"IMSI requested (packet 14)\", \"say \\\"hi\\\"\\\\\" } },"
+        ));
+        assert!(!lua.contains("001010123456789"));
+        assert!(lua.contains("register_postdissector(rayhunter)"));
+    }
+
+    #[test]
+    fn test_lua_string() {
+        assert_eq!(lua_string("a\nb\u{7}"), "\"a\\nb\\007\"");
+    }
+}
@@ -56,9 +56,17 @@ export type PacketAnalysis = {
 
 export type EventType = 'Informational' | 'Low' | 'Medium' | 'High';
 
+export type EventDetails = {
+    analyzer: string;
+    code: string;
+    fields?: Record<string, number | string>;
+};
+
 export type Event = {
     event_type: EventType;
+    analyzer?: string;
     message: string;
+    details?: EventDetails;
 } | null;
 
 function get_event(event_json: any): Event {
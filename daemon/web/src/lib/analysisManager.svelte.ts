@@ -12,7 +12,7 @@ export enum AnalysisStatus {
 }
 
 type AnalysisStatusJson = {
-    running: string | null;
+    running: string[];
     queued: string[];
     finished: string[];
 };
@@ -32,8 +32,8 @@ export class AnalysisManager {
 
     public async update() {
         const status: AnalysisStatusJson = JSON.parse(await req('GET', '/api/analysis'));
-        if (status.running) {
-            this.status.set(status.running, AnalysisStatus.Running);
+        for (const entry of status.running) {
+            this.status.set(entry, AnalysisStatus.Running);
         }
 
         for (const entry of status.queued) {
@@ -1,9 +1,34 @@
 import { get_report, type AnalysisReport } from './analysis.svelte';
 import { AnalysisStatus, type AnalysisManager } from './analysisManager.svelte';
+import type { CaptureRate, GeoPoint } from './systemStats';
+
+export type RecordingMode = 'full' | 'monitor_only';
 
 interface JsonManifest {
     entries: JsonManifestEntry[];
     current_entry: JsonManifestEntry | null;
+    mode: RecordingMode;
+    monitoring: boolean;
+}
+
+export interface SeverityCounts {
+    high: number;
+    medium: number;
+    low: number;
+    informational: number;
+}
+
+export interface AnalysisSummary {
+    total_events: number;
+    by_analyzer: Record<string, SeverityCounts>;
+    by_severity: SeverityCounts;
+    analysis_duration_ms: number;
+}
+
+export interface ClockJump {
+    before: string;
+    after: string;
+    qmdl_offset: number;
 }
 
 interface JsonManifestEntry {
@@ -12,13 +37,23 @@ interface JsonManifestEntry {
     last_message_time: string;
     qmdl_size_bytes: number;
     stop_reason: string | null;
+    analysis_summary: AnalysisSummary | null;
+    capture_rate?: CaptureRate;
+    start_location?: GeoPoint;
+    end_location?: GeoPoint;
+    clock_jumps?: ClockJump[];
+    duration_secs?: number | null;
 }
 
 export class Manifest {
     public entries: ManifestEntry[] = [];
     public current_entry: ManifestEntry | undefined;
+    public mode: RecordingMode;
+    public monitoring: boolean;
 
     constructor(json: JsonManifest) {
+        this.mode = json.mode;
+        this.monitoring = json.monitoring;
         for (const entry of json.entries) {
             this.entries.push(new ManifestEntry(entry));
         }
@@ -59,6 +94,12 @@ export class ManifestEntry {
     public analysis_status: AnalysisStatus | undefined = $state(undefined);
     public analysis_report: AnalysisReport | string | undefined = $state(undefined);
     public stop_reason: string | undefined = $state(undefined);
+    public analysis_summary: AnalysisSummary | undefined = $state(undefined);
+    public capture_rate: CaptureRate | undefined = $state(undefined);
+    public start_location: GeoPoint | undefined = $state(undefined);
+    public end_location: GeoPoint | undefined = $state(undefined);
+    public clock_jumps: ClockJump[] = $state([]);
+    public duration_secs: number | undefined = $state(undefined);
 
     constructor(json: JsonManifestEntry) {
         this.name = json.name;
@@ -70,6 +111,14 @@ export class ManifestEntry {
         if (json.stop_reason) {
             this.stop_reason = json.stop_reason;
         }
+        if (json.analysis_summary) {
+            this.analysis_summary = json.analysis_summary;
+        }
+        this.capture_rate = json.capture_rate;
+        this.start_location = json.start_location;
+        this.end_location = json.end_location;
+        this.clock_jumps = json.clock_jumps ?? [];
+        this.duration_secs = json.duration_secs ?? undefined;
     }
 
     get_readable_qmdl_size(): string {
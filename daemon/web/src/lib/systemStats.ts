@@ -3,12 +3,39 @@ export interface SystemStats {
     memory_stats: MemoryStats;
     runtime_metadata: RuntimeMetadata;
     battery_status?: BatteryStatus;
+    daemon_stats: DaemonStats;
+    event_store_size_bytes: number;
+    diag_frames_dropped: number;
+    diag_stats: DiagStats;
+    capture_rate?: CaptureRate;
+    current_location?: GeoPoint;
+}
+
+export interface DiagStats {
+    frames_read_total: number;
+    bytes_read_total: number;
+    frames_dropped_total: number;
+    parse_errors_total: number;
+    read_throughput_bps: number;
+}
+
+export interface CaptureRate {
+    bytes_per_sec: number;
+    messages_per_sec: number;
+    messages_total: number;
+}
+
+export interface GeoPoint {
+    lat: number;
+    lon: number;
+    accuracy_m: number;
 }
 
 export interface RuntimeMetadata {
     rayhunter_version: string;
     system_os: string;
     arch: string;
+    build_git_hash?: string;
 }
 
 export interface DiskStats {
@@ -18,16 +45,35 @@ export interface DiskStats {
     available_size: string;
     used_percent: string;
     mounted_on: string;
-    available_bytes?: number;
+    filesystem?: string;
+    total_bytes: number;
+    used_bytes: number;
+    available_bytes: number;
 }
 
 export interface MemoryStats {
     total: string;
     used: string;
     free: string;
+    total_bytes: number;
+    used_bytes: number;
+    free_bytes: number;
+    available_bytes?: number;
+    cached_bytes?: number;
+    buffers_bytes?: number;
 }
 
 export interface BatteryStatus {
     level: number;
     is_plugged_in: boolean;
+    source: string;
+}
+
+export type StartReason = 'power-on' | 'config-restart' | 'crash-recovered';
+
+export interface DaemonStats {
+    daemon_uptime_secs: number;
+    boot_count: number;
+    last_start_reason: StartReason;
+    lock_holder_pid?: number | null;
 }
@@ -8,9 +8,37 @@ export interface AnalyzerConfig {
     lte_sib6_and_7_downgrade: boolean;
     null_cipher: boolean;
     nas_null_cipher: boolean;
+    nas_integrity_downgrade: boolean;
+    wcdma_null_cipher: boolean;
     incomplete_sib: boolean;
+    sfn_continuity: boolean;
+    otdoa_request: boolean;
+    sib3_reselection_anomaly: boolean;
+    sib_neighbor_list: boolean;
+    service_request_flood: boolean;
     test_analyzer: boolean;
     diagnostic_analyzer: boolean;
+    diagnostic_severity: Record<string, 'Informational' | 'Low' | 'Medium' | 'High'>;
+    fault_injection: FaultInjectionConfig;
+}
+
+export interface FaultInjectionConfig {
+    enabled: boolean;
+    every_messages: number;
+    event_type: 'Informational' | 'Low' | 'Medium' | 'High';
+    message: string;
+}
+
+// Describes an analyzer, as listed by GET /api/analyzers
+export interface AnalyzerInfo {
+    key: string;
+    name: string;
+    description: string;
+    version: number;
+    enabled: boolean;
+    default_enabled: boolean;
+    severities: ('Informational' | 'Low' | 'Medium' | 'High')[];
+    settings: unknown | null;
 }
 
 export enum enabled_notifications {
@@ -20,21 +48,46 @@ export enum enabled_notifications {
 
 export interface Config {
     device: string;
+    developer_mode: boolean;
     ui_level: number;
     colorblind_mode: boolean;
+    display_accent_color: string;
+    display_max_redraws_per_sec: number;
+    display_timeout_secs: number;
+    display_burn_in_mitigation: boolean;
+    display_off_while_recording: boolean;
     key_input_mode: number;
+    log_level: string;
     ntfy_url: string;
     enabled_notifications: enabled_notifications[];
+    notification_storm_window_secs: number;
+    notification_title_template: string | null;
+    notification_body_template: string | null;
     analyzers: AnalyzerConfig;
+    analysis_workers: number;
+    min_recording_size_to_analyze_bytes: number;
+    redact_identifiers: boolean;
+    recording_mode: 'full' | 'monitor_only';
     min_space_to_start_recording_mb: number;
     min_space_to_continue_recording_mb: number;
+    clock_jump_threshold_secs: number;
     wifi_ssid: string | null;
     wifi_password: string | null;
     wifi_security: 'wpa_psk' | 'sae' | null;
     wifi_enabled: boolean;
+    wifi_detect_captive_portal: boolean;
+    wifi_verify_credentials: boolean;
+    wifi_scan_cooldown_secs: number;
     dns_servers: string[] | null;
     firewall_restrict_outbound: boolean;
     firewall_allowed_ports: number[] | null;
+    gsmtap_stream_enabled: boolean;
+    gsmtap_stream_destination: string;
+    live_tail_size: number;
+    ap_client_monitor_enabled: boolean;
+    ap_client_event_severity: 'Informational' | 'Low' | 'Medium' | 'High';
+    ap_client_redact_macs: boolean;
+    location_tagging: boolean;
 }
 
 export interface WifiStatus {
@@ -42,6 +95,17 @@ export interface WifiStatus {
     ssid?: string;
     ip?: string;
     error?: string;
+    captive_portal: boolean;
+    signal_dbm?: number | null;
+    ipv6_addrs?: string[];
+}
+
+// How many of 4 bars to show for a wifi signal strength
+export function signal_bars(signal_dbm: number): number {
+    if (signal_dbm >= -55) return 4;
+    if (signal_dbm >= -65) return 3;
+    if (signal_dbm >= -75) return 2;
+    return 1;
 }
 
 export interface WifiNetwork {
@@ -55,7 +119,13 @@ export async function get_wifi_status(): Promise<WifiStatus> {
 }
 
 export async function scan_wifi_networks(): Promise<WifiNetwork[]> {
-    return JSON.parse(await req('POST', '/api/wifi-scan'));
+    const response = await fetch('/api/wifi-scan', { method: 'POST' });
+    const body = await response.text();
+    // a 429 means the last scan was too recent, and comes with its results
+    if (response.ok || response.status === 429) {
+        return JSON.parse(body);
+    }
+    throw new Error(body);
 }
 
 export async function req(method: string, url: string, json_body?: unknown): Promise<string> {
@@ -107,6 +177,10 @@ export async function get_config(): Promise<Config> {
     return JSON.parse(await req('GET', '/api/config'));
 }
 
+export async function get_analyzers(): Promise<AnalyzerInfo[]> {
+    return JSON.parse(await req('GET', '/api/analyzers'));
+}
+
 export async function set_config(config: Config): Promise<void> {
     const response = await fetch('/api/config', {
         method: 'POST',
@@ -122,7 +196,13 @@ export async function set_config(config: Config): Promise<void> {
     }
 }
 
-export async function test_notification(): Promise<void> {
+export interface SinkTestResult {
+    sink: string;
+    success: boolean;
+    error: string | null;
+}
+
+export async function test_notification(): Promise<SinkTestResult[]> {
     const response = await fetch('/api/test-notification', {
         method: 'POST',
     });
@@ -131,6 +211,7 @@ export async function test_notification(): Promise<void> {
         const error = await response.text();
         throw new Error(error);
     }
+    return (await response.json()).sinks;
 }
 
 export interface TimeResponse {
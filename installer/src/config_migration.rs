@@ -0,0 +1,327 @@
+//! Adding the settings introduced by newer versions of rayhunter to an existing config.toml.
+//!
+//! The daemon fills in a default for any setting missing from the config, so an old config keeps
+//! working, but the user never finds out the new setting exists. This copies each missing setting
+//! over from the bundled config.toml.in, together with the comment explaining it. Everything
+//! already in the file, comments included, is left exactly as it was.
+//!
+//! This works on the text rather than parsing the TOML, since round-tripping through a parser
+//! would lose the user's comments and formatting.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::output::println;
+
+/// A setting which was missing from the config, and the lines added for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddedSetting {
+    /// The table the setting belongs to, or None for the top level
+    pub table: Option<String>,
+    pub key: String,
+    pub lines: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigratedConfig {
+    pub config: String,
+    pub added: Vec<AddedSetting>,
+}
+
+enum Line<'a> {
+    Blank,
+    Comment,
+    /// A commented out setting, e.g. `# ntfy_url = "..."`
+    CommentedKey(&'a str),
+    Key(&'a str),
+    Table(&'a str),
+}
+
+fn classify(line: &str) -> Line<'_> {
+    let line = line.trim();
+    if line.is_empty() {
+        Line::Blank
+    } else if let Some(comment) = line.strip_prefix('#') {
+        match parse_key(comment) {
+            Some(key) => Line::CommentedKey(key),
+            None => Line::Comment,
+        }
+    } else if let Some(table) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+        Line::Table(table.trim())
+    } else {
+        match parse_key(line) {
+            Some(key) => Line::Key(key),
+            None => Line::Comment,
+        }
+    }
+}
+
+fn parse_key(line: &str) -> Option<&str> {
+    let (key, _) = line.split_once('=')?;
+    let key = key.trim();
+    // starting with a letter rules out comments like `# 0 = unlimited`
+    let is_key = key.starts_with(|c: char| c.is_ascii_alphabetic())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    is_key.then_some(key)
+}
+
+/// The comment lines directly above `index`, up to the nearest blank line, setting or commented
+/// out setting
+fn comment_block_start(lines: &[&str], index: usize) -> usize {
+    let mut start = index;
+    while start > 0 && matches!(classify(lines[start - 1]), Line::Comment) {
+        start -= 1;
+    }
+    start
+}
+
+/// Where to add settings to the section which ends at `end`: after its last line, but before
+/// any comment describing the next table and the blank lines around it.
+fn insertion_point(lines: &[&str], end: usize) -> usize {
+    let mut point = if end < lines.len() {
+        comment_block_start(lines, end)
+    } else {
+        end
+    };
+    while point > 0 && matches!(classify(lines[point - 1]), Line::Blank) {
+        point -= 1;
+    }
+    point
+}
+
+struct Section {
+    table: Option<String>,
+    /// The index of the table header, or 0 for the top level
+    start: usize,
+    /// The index of the next table header, or the number of lines
+    end: usize,
+    /// Both settings and commented out settings. A setting the user commented out was removed on
+    /// purpose, so it doesn't count as missing.
+    keys: Vec<String>,
+}
+
+fn sections(lines: &[&str]) -> Vec<Section> {
+    let mut sections = vec![Section {
+        table: None,
+        start: 0,
+        end: lines.len(),
+        keys: Vec::new(),
+    }];
+    for (i, line) in lines.iter().enumerate() {
+        match classify(line) {
+            Line::Table(table) => {
+                sections.last_mut().unwrap().end = i;
+                sections.push(Section {
+                    table: Some(table.to_string()),
+                    start: i,
+                    end: lines.len(),
+                    keys: Vec::new(),
+                });
+            }
+            Line::Key(key) | Line::CommentedKey(key) => {
+                sections.last_mut().unwrap().keys.push(key.to_string());
+            }
+            Line::Blank | Line::Comment => {}
+        }
+    }
+    sections
+}
+
+/// Adds every setting in `template` which is missing from `existing`, each with the comment
+/// above it in the template. Settings which are commented out in the template have no default,
+/// so they're left out.
+pub fn migrate_config(existing: &str, template: &str) -> MigratedConfig {
+    let template_lines: Vec<&str> = template.lines().collect();
+    let existing_lines: Vec<&str> = existing.lines().collect();
+    let existing_sections = sections(&existing_lines);
+
+    // (insertion point, lines) for each existing section, and whole new tables for the end
+    let mut insertions: Vec<(usize, Vec<String>)> = Vec::new();
+    let mut new_tables: Vec<String> = Vec::new();
+    let mut added = Vec::new();
+
+    for section in sections(&template_lines) {
+        let existing_section = existing_sections.iter().find(|s| s.table == section.table);
+        let mut lines = Vec::new();
+        for i in section.start..section.end {
+            let Line::Key(key) = classify(template_lines[i]) else {
+                continue;
+            };
+            if existing_section.is_some_and(|s| s.keys.iter().any(|k| k == key)) {
+                continue;
+            }
+            let setting_lines: Vec<String> = template_lines
+                [comment_block_start(&template_lines, i)..=i]
+                .iter()
+                .map(|line| line.to_string())
+                .collect();
+            if setting_lines.len() > 1 && !lines.is_empty() {
+                lines.push(String::new());
+            }
+            lines.extend(setting_lines.iter().cloned());
+            added.push(AddedSetting {
+                table: section.table.clone(),
+                key: key.to_string(),
+                lines: setting_lines,
+            });
+        }
+        if lines.is_empty() {
+            continue;
+        }
+
+        match existing_section {
+            Some(existing_section) => {
+                let point = insertion_point(&existing_lines, existing_section.end);
+                // keep a setting's comment from running into the line above it
+                let mut block = Vec::new();
+                if lines[0].trim_start().starts_with('#') {
+                    block.push(String::new());
+                }
+                block.extend(lines);
+                insertions.push((point, block));
+            }
+            None => {
+                new_tables.push(String::new());
+                new_tables.extend(
+                    template_lines
+                        [comment_block_start(&template_lines, section.start)..=section.start]
+                        .iter()
+                        .map(|line| line.to_string()),
+                );
+                new_tables.extend(lines);
+            }
+        }
+    }
+
+    let mut config = Vec::new();
+    for i in 0..=existing_lines.len() {
+        for (_, block) in insertions.iter().filter(|(point, _)| *point == i) {
+            config.extend(block.iter().cloned());
+        }
+        if let Some(line) = existing_lines.get(i) {
+            config.push(line.to_string());
+        }
+    }
+    if config.last().is_none_or(|line| line.trim().is_empty()) && !new_tables.is_empty() {
+        new_tables.remove(0);
+    }
+    config.extend(new_tables);
+
+    let mut config = config.join("\n");
+    config.push('\n');
+    MigratedConfig { config, added }
+}
+
+/// Prints the settings which were added, in the style of a diff
+pub fn print_added(added: &[AddedSetting]) {
+    if added.is_empty() {
+        println!("Config is up to date, nothing to add");
+        return;
+    }
+    for setting in added {
+        match &setting.table {
+            Some(table) => println!("[{table}]"),
+            None => println!("(top level)"),
+        }
+        for line in &setting.lines {
+            println!("+{line}");
+        }
+    }
+}
+
+/// Migrates the config file at `path`, or only prints what would be added if `dry_run` is set
+pub fn migrate_config_file(path: &Path, dry_run: bool) -> Result<()> {
+    let existing = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let migrated = migrate_config(&existing, crate::CONFIG_TOML);
+    print_added(&migrated.added);
+    if !dry_run && !migrated.added.is_empty() {
+        std::fs::write(path, migrated.config)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        println!("Updated {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = r#"# How much detail to log
+log_level = "info"
+# If set, attempts to send a notification
+# ntfy_url = "https://ntfy.sh/your-topic"
+# What notification types to enable
+enabled_notifications = ["Warning"]
+
+# Analyzer Configuration
+[analyzers]
+imsi_requested = true
+null_cipher = true
+
+[extra]
+# A brand new table
+enabled = false
+"#;
+
+    #[test]
+    fn test_up_to_date_config_is_unchanged() {
+        let migrated = migrate_config(TEMPLATE, TEMPLATE);
+        assert!(migrated.added.is_empty());
+        assert_eq!(migrated.config, TEMPLATE);
+    }
+
+    #[test]
+    fn test_missing_settings_are_added_with_comments() {
+        let existing = r#"# my own comment
+log_level = "debug"
+
+# Analyzer Configuration
+[analyzers]
+imsi_requested = false
+"#;
+        let migrated = migrate_config(existing, TEMPLATE);
+        let added: Vec<_> = migrated
+            .added
+            .iter()
+            .map(|s| (s.table.as_deref(), s.key.as_str()))
+            .collect();
+        assert_eq!(
+            added,
+            vec![
+                (None, "enabled_notifications"),
+                (Some("analyzers"), "null_cipher"),
+                (Some("extra"), "enabled"),
+            ]
+        );
+        assert_eq!(
+            migrated.config,
+            r#"# my own comment
+log_level = "debug"
+
+# What notification types to enable
+enabled_notifications = ["Warning"]
+
+# Analyzer Configuration
+[analyzers]
+imsi_requested = false
+null_cipher = true
+
+[extra]
+# A brand new table
+enabled = false
+"#
+        );
+    }
+
+    #[test]
+    fn test_commented_out_settings_count_as_present() {
+        let existing = "# log_level = \"info\"\n# enabled_notifications = []\n";
+        let migrated = migrate_config(existing, "log_level = \"info\"\n");
+        assert!(migrated.added.is_empty());
+        assert_eq!(migrated.config, existing);
+    }
+}
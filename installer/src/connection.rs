@@ -38,11 +38,50 @@ pub async fn install_config<C: DeviceConnection>(
         );
         conn.write_file(config_path, config.as_bytes()).await?;
     } else {
-        println!("Config file already exists, skipping (use --reset-config to overwrite)");
+        println!("Config file already exists, keeping it (use --reset-config to overwrite)");
+        migrate_config(conn, config_path).await?;
     }
     Ok(())
 }
 
+/// Add any settings introduced since the existing config was written. A config which can't be
+/// read back is left alone, since the daemon uses defaults for whatever is missing anyway.
+async fn migrate_config<C: DeviceConnection>(conn: &mut C, config_path: &str) -> Result<()> {
+    let Some(existing) = read_file(conn, config_path).await else {
+        println!("Couldn't read the existing config, not checking it for new settings");
+        return Ok(());
+    };
+    let migrated = crate::config_migration::migrate_config(&existing, crate::CONFIG_TOML);
+    if migrated.added.is_empty() {
+        return Ok(());
+    }
+    println!("Adding new settings to the existing config:");
+    crate::config_migration::print_added(&migrated.added);
+    conn.write_file(config_path, migrated.config.as_bytes())
+        .await
+}
+
+/// Read a text file using a DeviceConnection
+async fn read_file<C: DeviceConnection>(conn: &mut C, path: &str) -> Option<String> {
+    // As in readlink, markers separate the file from anything else in the output. Lines are
+    // matched exactly, so a shell echoing back the command doesn't count.
+    let output = conn
+        .run_command(&format!("echo FILE_START; cat '{path}'; echo FILE_END"))
+        .await
+        .ok()?;
+    let mut lines = output.lines().map(|line| line.trim_end_matches('\r'));
+    lines.find(|line| *line == "FILE_START")?;
+    let mut contents = String::new();
+    for line in lines {
+        if line == "FILE_END" {
+            return Some(contents);
+        }
+        contents.push_str(line);
+        contents.push('\n');
+    }
+    None
+}
+
 /// Install wifi tools (wpa_supplicant, wpa_cli, iw) to /data/rayhunter/bin.
 ///
 /// Skips any binary that is already present on the device (e.g. provided by firmware),
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use anyhow::{Context, Error};
 use clap::{Parser, Subcommand};
 use env_logger::Env;
@@ -5,6 +7,7 @@ use env_logger::Env;
 #[cfg(not(target_os = "android"))]
 use anyhow::bail;
 
+mod config_migration;
 mod connection;
 mod files;
 pub(crate) use files::*;
@@ -206,6 +209,12 @@ enum UtilSubCommand {
     /// Before running this utility, you need to make telnet accessible with `installer util
     /// wingtech-start-telnet`.
     WingtechSendFile(WingtechSendFile),
+    /// Add settings introduced by this version of rayhunter to an existing config.toml.
+    ///
+    /// Settings missing from the file are added with their default values and the comments
+    /// explaining them. Existing settings and comments are kept as they are. Installing over an
+    /// existing installation does this automatically, so this is for config files kept elsewhere.
+    MigrateConfig(MigrateConfig),
 }
 
 #[derive(Parser, Debug)]
@@ -224,6 +233,10 @@ struct Uz801Args {
     /// IP address for Uz801 admin interface, if custom.
     #[arg(long, default_value = "192.168.100.1")]
     admin_ip: String,
+
+    /// Install over ADB over TCP at this address (ip:port) instead of USB. Needs adb on your PATH.
+    #[arg(long)]
+    adb_tcp: Option<std::net::SocketAddr>,
 }
 
 #[derive(Parser, Debug)]
@@ -255,6 +268,15 @@ struct WingtechSendFile {
     remote_path: String,
 }
 
+#[derive(Parser, Debug)]
+struct MigrateConfig {
+    /// Path to the config.toml to update.
+    path: PathBuf,
+    /// Only print the settings that would be added, without changing the file.
+    #[arg(long)]
+    dry_run: bool,
+}
+
 #[derive(Parser, Debug)]
 struct WingtechArgs {
     /// IP address for Wingtech admin interface, if custom.
@@ -279,7 +301,7 @@ async fn run(args: Args) -> Result<(), Error> {
     match args.command {
         Command::Tmobile(args) => tmobile::install(args).await.context("Failed to install rayhunter on the Tmobile TMOHS1. Make sure your computer is connected to the hotspot using USB tethering or WiFi.")?,
         #[cfg(not(target_os = "android"))]
-        Command::Uz801(args) => uz801::install(args).await.context("Failed to install rayhunter on the Uz801. Make sure your computer is connected to the hotspot using USB.")?,
+        Command::Uz801(args) => uz801::install(args).await.context("Failed to install rayhunter on the Uz801. Make sure your computer is connected to the hotspot using USB, or pass --adb-tcp to install over the network.")?,
         Command::Tplink(tplink) => tplink::main_tplink(tplink).await.context("Failed to install rayhunter on the TP-Link M7350. Make sure your computer is connected to the hotspot using USB tethering or WiFi.")?,
         #[cfg(not(target_os = "android"))]
         Command::Pinephone(_) => pinephone::install().await
@@ -329,6 +351,7 @@ async fn run(args: Args) -> Result<(), Error> {
             UtilSubCommand::WingtechSendFile(options) => {
                 util::send_file(&options.admin_ip, &options.local_path, &options.remote_path).await?;
             }
+            UtilSubCommand::MigrateConfig(args) => config_migration::migrate_config_file(&args.path, args.dry_run).context("\nFailed to migrate config")?,
             UtilSubCommand::WingtechStartTelnet(args) => wingtech::start_telnet(&args.admin_ip, &args.admin_password).await.context("\nFailed to start telnet on the Wingtech CT2MHS01")?,
             UtilSubCommand::WingtechStartAdb(args) => wingtech::start_adb(&args.admin_ip, &args.admin_password).await.context("\nFailed to start adb on the Wingtech CT2MHS01")?,
             #[cfg(not(target_os = "android"))]
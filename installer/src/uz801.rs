@@ -1,4 +1,3 @@
-use std::path::Path;
 /// Installer for the Uz801 hotspot.
 ///
 /// Installation process:
@@ -6,27 +5,43 @@ use std::path::Path;
 /// 2. Wait for device reboot and ADB availability
 /// 3. Use ADB to install rayhunter files
 /// 4. Modify startup script to launch rayhunter on boot
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::time::Duration;
 
 use adb_client::{ADBDeviceExt, ADBUSBDevice, RustADBError};
-use anyhow::{Result, anyhow};
+use anyhow::{Result, anyhow, bail};
 use md5::compute as md5_compute;
 use tokio::time::sleep;
 
 use crate::Uz801Args as Args;
 use crate::output::{print, println};
 
-pub async fn install(Args { admin_ip }: Args) -> Result<()> {
-    run_install(admin_ip).await
+pub async fn install(Args { admin_ip, adb_tcp }: Args) -> Result<()> {
+    match AdbTarget::from_args(adb_tcp) {
+        AdbTarget::Usb => run_install(&admin_ip, &AdbTarget::Usb, UsbConnector).await,
+        AdbTarget::Tcp(addr) => {
+            run_install(&admin_ip, &AdbTarget::Tcp(addr), TcpConnector { addr }).await
+        }
+    }
 }
 
-async fn run_install(admin_ip: String) -> Result<()> {
+async fn run_install<C: AdbConnector>(
+    admin_ip: &str,
+    target: &AdbTarget,
+    mut connector: C,
+) -> Result<()> {
     print!("Activating USB debugging backdoor... ");
-    activate_usb_debug(&admin_ip).await?;
+    activate_usb_debug(admin_ip).await?;
     println!("ok");
 
-    print!("Waiting for device reboot and ADB connection... ");
-    let mut adb_device = wait_for_adb().await?;
+    print!("Waiting for device reboot and ADB connection over {target}... ");
+    // Wait a bit for the reboot to start
+    sleep(Duration::from_secs(10)).await;
+    let Some(mut adb_device) = connect_with_backoff(&mut connector, &REBOOT_BACKOFF).await? else {
+        return Err(target.unreachable_error());
+    };
     println!("ok");
 
     print!("Installing rayhunter files... ");
@@ -38,7 +53,7 @@ async fn run_install(admin_ip: String) -> Result<()> {
     println!("ok");
 
     print!("Rebooting the device... ");
-    let _ = adb_device.reboot(adb_client::RebootType::System);
+    let _ = adb_device.reboot();
     println!("ok");
 
     println!("Installation complete!");
@@ -94,44 +109,252 @@ pub async fn activate_usb_debug(admin_ip: &str) -> Result<()> {
     Ok(())
 }
 
-async fn wait_for_adb() -> Result<ADBUSBDevice> {
-    const MAX_ATTEMPTS: u32 = 30; // 30 seconds
-    let mut attempts = 0;
+/// Where to reach the device's ADB
+#[derive(Debug, Clone, PartialEq)]
+enum AdbTarget {
+    /// The first Uz801 found on USB
+    Usb,
+    /// ADB over TCP, e.g. for devices whose USB port only supplies power
+    Tcp(SocketAddr),
+}
 
-    // Wait a bit for the reboot to start
-    sleep(Duration::from_secs(10)).await;
+impl AdbTarget {
+    /// An explicitly given TCP target is used over looking for the device on USB.
+    fn from_args(adb_tcp: Option<SocketAddr>) -> Self {
+        match adb_tcp {
+            Some(addr) => AdbTarget::Tcp(addr),
+            None => AdbTarget::Usb,
+        }
+    }
 
-    loop {
-        if attempts >= MAX_ATTEMPTS {
-            anyhow::bail!("Timeout waiting for ADB connection after USB debug activation");
+    fn unreachable_error(&self) -> anyhow::Error {
+        match self {
+            AdbTarget::Usb => {
+                anyhow!("Timeout waiting for ADB connection after USB debug activation")
+            }
+            AdbTarget::Tcp(addr) => anyhow!(
+                "The device didn't accept ADB connections at {addr} after rebooting. Some \
+                 firmwares only bring ADB back over USB once the debug backdoor has rebooted \
+                 them. Check that the device kept the same IP address, try `adb connect {addr}` \
+                 yourself, and run the installer again once that works. If ADB over TCP doesn't \
+                 come back, the USB port is the only way to install."
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for AdbTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdbTarget::Usb => write!(f, "USB"),
+            AdbTarget::Tcp(addr) => write!(f, "TCP ({addr})"),
+        }
+    }
+}
+
+/// The ADB operations the install needs, whichever way the device is connected
+trait AdbTransport {
+    /// Runs a shell command, returning its output
+    fn shell(&mut self, command: &[&str]) -> Result<String>;
+    fn push(&mut self, data: &[u8], path: &str) -> Result<()>;
+    fn pull(&mut self, path: &str) -> Result<Vec<u8>>;
+    fn reboot(&mut self) -> Result<()>;
+}
+
+impl AdbTransport for ADBUSBDevice {
+    fn shell(&mut self, command: &[&str]) -> Result<String> {
+        let mut buf = Vec::<u8>::new();
+        self.shell_command(command, &mut buf)?;
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+
+    fn push(&mut self, mut data: &[u8], path: &str) -> Result<()> {
+        ADBDeviceExt::push(self, &mut data, &path)?;
+        Ok(())
+    }
+
+    fn pull(&mut self, path: &str) -> Result<Vec<u8>> {
+        let mut buf = Vec::<u8>::new();
+        ADBDeviceExt::pull(self, &path, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn reboot(&mut self) -> Result<()> {
+        ADBDeviceExt::reboot(self, adb_client::RebootType::System)?;
+        Ok(())
+    }
+}
+
+/// ADB over TCP, through the system's `adb` after `adb connect`
+struct TcpAdb {
+    serial: String,
+}
+
+impl TcpAdb {
+    fn adb(&self, args: &[&str]) -> Result<String> {
+        let output = Command::new("adb")
+            .arg("-s")
+            .arg(&self.serial)
+            .args(args)
+            .output()?;
+        if !output.status.success() {
+            bail!(
+                "adb {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
         }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    // adb only pushes and pulls files, so data goes through a temporary one
+    fn temp_path(path: &str) -> PathBuf {
+        let name = Path::new(path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("file");
+        std::env::temp_dir().join(format!("rayhunter-{}-{name}", std::process::id()))
+    }
+}
+
+impl AdbTransport for TcpAdb {
+    fn shell(&mut self, command: &[&str]) -> Result<String> {
+        let mut args = vec!["shell"];
+        args.extend_from_slice(command);
+        self.adb(&args)
+    }
+
+    fn push(&mut self, data: &[u8], path: &str) -> Result<()> {
+        let temp_path = Self::temp_path(path);
+        std::fs::write(&temp_path, data)?;
+        let result = self.adb(&["push", &temp_path.to_string_lossy(), path]);
+        let _ = std::fs::remove_file(&temp_path);
+        result.map(|_| ())
+    }
+
+    fn pull(&mut self, path: &str) -> Result<Vec<u8>> {
+        let temp_path = Self::temp_path(path);
+        let result = self
+            .adb(&["pull", path, &temp_path.to_string_lossy()])
+            .and_then(|_| Ok(std::fs::read(&temp_path)?));
+        let _ = std::fs::remove_file(&temp_path);
+        result
+    }
+
+    fn reboot(&mut self) -> Result<()> {
+        self.adb(&["reboot"]).map(|_| ())
+    }
+}
+
+/// Opens ADB connections to the device
+trait AdbConnector {
+    type Transport: AdbTransport;
+
+    /// Returns None if the device can't be reached yet, e.g. because it's
+    /// still rebooting
+    fn try_connect(&mut self) -> Result<Option<Self::Transport>>;
+}
 
+struct UsbConnector;
+
+impl AdbConnector for UsbConnector {
+    type Transport = ADBUSBDevice;
+
+    fn try_connect(&mut self) -> Result<Option<ADBUSBDevice>> {
         // UZ801 USB vendor and product IDs.
         // TODO: Research if other variants use different IDs.
         match ADBUSBDevice::new(0x05c6, 0x90b6) {
-            Ok(mut device) => {
-                // Test ADB connection
-                if test_adb_connection(&mut device).await.is_ok() {
-                    return Ok(device);
-                }
-            }
-            Err(RustADBError::DeviceNotFound(_)) => {
-                // Device not ready yet, continue waiting
-            }
-            Err(e) => {
-                anyhow::bail!("ADB connection error: {}", e);
-            }
+            Ok(device) => Ok(Some(device)),
+            // Device not ready yet, continue waiting
+            Err(RustADBError::DeviceNotFound(_)) => Ok(None),
+            Err(e) => bail!("ADB connection error: {}", e),
+        }
+    }
+}
+
+struct TcpConnector {
+    addr: SocketAddr,
+}
+
+impl AdbConnector for TcpConnector {
+    type Transport = TcpAdb;
+
+    fn try_connect(&mut self) -> Result<Option<TcpAdb>> {
+        let serial = self.addr.to_string();
+        let output = match Command::new("adb").args(["connect", &serial]).output() {
+            Ok(output) => output,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => bail!(
+                "Installing over TCP needs adb from the Android SDK Platform-Tools on your PATH"
+            ),
+            Err(e) => return Err(e.into()),
+        };
+        // adb connect exits successfully even when it couldn't connect
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if stdout.contains("connected to") {
+            Ok(Some(TcpAdb { serial }))
+        } else {
+            Ok(None)
         }
+    }
+}
+
+/// How long to keep trying to reach the device, waiting longer between each
+/// attempt
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    /// The most time to spend waiting in total
+    total: Duration,
+}
+
+/// Waiting for the device to reboot after the debug backdoor is activated.
+/// Over TCP this includes the device rejoining the network.
+const REBOOT_BACKOFF: Backoff = Backoff {
+    initial: Duration::from_secs(1),
+    max: Duration::from_secs(8),
+    total: Duration::from_secs(120),
+};
+
+impl Backoff {
+    /// The delays between attempts
+    fn delays(&self) -> impl Iterator<Item = Duration> {
+        let (max, total) = (self.max, self.total);
+        let mut next = self.initial;
+        let mut waited = Duration::ZERO;
+        std::iter::from_fn(move || {
+            if waited + next > total {
+                return None;
+            }
+            let delay = next;
+            waited += delay;
+            next = (next * 2).min(max);
+            Some(delay)
+        })
+    }
+}
 
-        sleep(Duration::from_secs(1)).await;
-        attempts += 1;
+/// Connects to the device, retrying with `backoff` until it answers. Returns
+/// None if it never did.
+async fn connect_with_backoff<C: AdbConnector>(
+    connector: &mut C,
+    backoff: &Backoff,
+) -> Result<Option<C::Transport>> {
+    let mut delays = backoff.delays();
+    loop {
+        if let Some(mut device) = connector.try_connect()?
+            && test_adb_connection(&mut device).is_ok()
+        {
+            return Ok(Some(device));
+        }
+        match delays.next() {
+            Some(delay) => sleep(delay).await,
+            None => return Ok(None),
+        }
     }
 }
 
-async fn test_adb_connection(adb_device: &mut ADBUSBDevice) -> Result<()> {
-    let mut buf = Vec::<u8>::new();
-    adb_device.shell_command(&["echo", "test"], &mut buf)?;
-    let output = String::from_utf8_lossy(&buf);
+fn test_adb_connection<T: AdbTransport>(adb_device: &mut T) -> Result<()> {
+    let output = adb_device.shell(&["echo", "test"])?;
     if output.contains("test") {
         Ok(())
     } else {
@@ -139,13 +362,12 @@ async fn test_adb_connection(adb_device: &mut ADBUSBDevice) -> Result<()> {
     }
 }
 
-async fn install_rayhunter_files(adb_device: &mut ADBUSBDevice) -> Result<()> {
+async fn install_rayhunter_files<T: AdbTransport>(adb_device: &mut T) -> Result<()> {
     // Create rayhunter directory
-    let mut buf = Vec::<u8>::new();
-    adb_device.shell_command(&["mkdir", "-p", "/data/rayhunter"], &mut buf)?;
+    adb_device.shell(&["mkdir", "-p", "/data/rayhunter"])?;
 
     // Remount system as writable
-    adb_device.shell_command(&["mount", "-o", "remount,rw", "/system"], &mut buf)?;
+    adb_device.shell(&["mount", "-o", "remount,rw", "/system"])?;
 
     // Install rayhunter daemon binary with verification
     let rayhunter_daemon_bin = crate::get_file!("FILE_RAYHUNTER_DAEMON");
@@ -157,15 +379,10 @@ async fn install_rayhunter_files(adb_device: &mut ADBUSBDevice) -> Result<()> {
 
     // Install config file
     let config_content = crate::CONFIG_TOML.replace("#device = \"orbic\"", "device = \"uz801\"");
-    let mut config_data = config_content.as_bytes();
-    adb_device.push(&mut config_data, &"/data/rayhunter/config.toml")?;
+    adb_device.push(config_content.as_bytes(), "/data/rayhunter/config.toml")?;
 
     // Make daemon executable
-    let mut buf = Vec::<u8>::new();
-    adb_device.shell_command(
-        &["chmod", "755", "/data/rayhunter/rayhunter-daemon"],
-        &mut buf,
-    )?;
+    adb_device.shell(&["chmod", "755", "/data/rayhunter/rayhunter-daemon"])?;
 
     Ok(())
 }
@@ -173,7 +390,7 @@ async fn install_rayhunter_files(adb_device: &mut ADBUSBDevice) -> Result<()> {
 /// Transfer a file to the device's filesystem with adb push.
 /// Validates the file sends successfully to /data/local/tmp
 /// before overwriting the destination.
-fn install_file(adb_device: &mut ADBUSBDevice, dest: &str, payload: &[u8]) -> Result<()> {
+fn install_file<T: AdbTransport>(adb_device: &mut T, dest: &str, payload: &[u8]) -> Result<()> {
     const MAX_RETRIES: u32 = 3;
 
     let file_name = Path::new(dest)
@@ -187,47 +404,36 @@ fn install_file(adb_device: &mut ADBUSBDevice, dest: &str, payload: &[u8]) -> Re
 
     for attempt in 1..=MAX_RETRIES {
         // Push the file
-        let mut payload_copy = payload;
-        if let Err(e) = adb_device.push(&mut payload_copy, &push_tmp_path) {
+        if let Err(e) = adb_device.push(payload, &push_tmp_path) {
             if attempt == MAX_RETRIES {
-                return Err(e.into());
+                return Err(e);
             }
             continue;
         }
 
         // Verify with md5sum
-        let mut buf = Vec::<u8>::new();
-        if adb_device
-            .shell_command(&["busybox", "md5sum", &push_tmp_path], &mut buf)
-            .is_ok()
+        if let Ok(output) = adb_device.shell(&["busybox", "md5sum", &push_tmp_path])
+            && output.contains(&format!("{file_hash:x}"))
         {
-            let output = String::from_utf8_lossy(&buf);
-            if output.contains(&format!("{file_hash:x}")) {
-                // Verification successful, move to final destination
-                let mut buf = Vec::<u8>::new();
-                adb_device.shell_command(&["mv", &push_tmp_path, dest], &mut buf)?;
-                println!("ok");
-                return Ok(());
-            }
+            // Verification successful, move to final destination
+            adb_device.shell(&["mv", &push_tmp_path, dest])?;
+            println!("ok");
+            return Ok(());
         }
 
         // Verification failed, clean up and retry
         if attempt < MAX_RETRIES {
             println!("MD5 verification failed on attempt {attempt}, retrying...");
-            let mut buf = Vec::<u8>::new();
-            adb_device
-                .shell_command(&["rm", "-f", &push_tmp_path], &mut buf)
-                .ok();
+            adb_device.shell(&["rm", "-f", &push_tmp_path]).ok();
         }
     }
 
     anyhow::bail!("MD5 verification failed for {dest} after {MAX_RETRIES} attempts")
 }
 
-async fn modify_startup_script(adb_device: &mut ADBUSBDevice) -> Result<()> {
+async fn modify_startup_script<T: AdbTransport>(adb_device: &mut T) -> Result<()> {
     // Pull the existing startup script
-    let mut script_content = Vec::<u8>::new();
-    adb_device.pull(&"/system/bin/initmifiservice.sh", &mut script_content)?;
+    let script_content = adb_device.pull("/system/bin/initmifiservice.sh")?;
 
     // Convert to string and add our line
     let mut script_str = String::from_utf8_lossy(&script_content).into_owned();
@@ -239,15 +445,185 @@ async fn modify_startup_script(adb_device: &mut ADBUSBDevice) -> Result<()> {
     }
 
     // Push the modified script back
-    let mut modified_script = script_str.as_bytes();
-    adb_device.push(&mut modified_script, &"/system/bin/initmifiservice.sh")?;
+    adb_device.push(script_str.as_bytes(), "/system/bin/initmifiservice.sh")?;
 
     // Make sure it's executable
-    let mut buf = Vec::<u8>::new();
-    adb_device.shell_command(
-        &["chmod", "755", "/system/bin/initmifiservice.sh"],
-        &mut buf,
-    )?;
+    adb_device.shell(&["chmod", "755", "/system/bin/initmifiservice.sh"])?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    /// Pretends to be a device which only answers after a number of attempts
+    struct MockConnector {
+        unreachable_attempts: usize,
+        attempts: usize,
+        /// Whether the device answers the connection test once reachable
+        responsive: bool,
+    }
+
+    impl MockConnector {
+        fn new(unreachable_attempts: usize) -> Self {
+            MockConnector {
+                unreachable_attempts,
+                attempts: 0,
+                responsive: true,
+            }
+        }
+    }
+
+    struct MockTransport {
+        responsive: bool,
+    }
+
+    impl AdbTransport for MockTransport {
+        fn shell(&mut self, command: &[&str]) -> Result<String> {
+            if self.responsive {
+                Ok(command[1..].join(" "))
+            } else {
+                Ok(String::new())
+            }
+        }
+
+        fn push(&mut self, _data: &[u8], _path: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn pull(&mut self, _path: &str) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn reboot(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AdbConnector for MockConnector {
+        type Transport = MockTransport;
+
+        fn try_connect(&mut self) -> Result<Option<MockTransport>> {
+            self.attempts += 1;
+            if self.attempts <= self.unreachable_attempts {
+                return Ok(None);
+            }
+            Ok(Some(MockTransport {
+                responsive: self.responsive,
+            }))
+        }
+    }
+
+    struct FailingConnector;
+
+    impl AdbConnector for FailingConnector {
+        type Transport = MockTransport;
+
+        fn try_connect(&mut self) -> Result<Option<MockTransport>> {
+            bail!("no adb")
+        }
+    }
+
+    /// A single attempt
+    const NO_RETRIES: Backoff = Backoff {
+        initial: Duration::from_millis(1),
+        max: Duration::from_millis(1),
+        total: Duration::ZERO,
+    };
+
+    fn parse_uz801_args(args: &[&str]) -> Args {
+        let mut argv = vec!["installer", "uz801"];
+        argv.extend_from_slice(args);
+        match crate::Args::try_parse_from(argv).unwrap().command {
+            crate::Command::Uz801(args) => args,
+            other => panic!("expected the uz801 command, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_adb_tcp_argument() {
+        let args = parse_uz801_args(&["--adb-tcp", "192.168.100.1:5555"]);
+        assert_eq!(args.admin_ip, "192.168.100.1");
+        let addr: SocketAddr = "192.168.100.1:5555".parse().unwrap();
+        assert_eq!(args.adb_tcp, Some(addr));
+        assert_eq!(AdbTarget::from_args(args.adb_tcp), AdbTarget::Tcp(addr));
+
+        let args = parse_uz801_args(&[]);
+        assert_eq!(args.adb_tcp, None);
+        assert_eq!(AdbTarget::from_args(args.adb_tcp), AdbTarget::Usb);
+
+        assert!(crate::Args::try_parse_from(["installer", "uz801", "--adb-tcp", "nope"]).is_err());
+    }
+
+    #[test]
+    fn test_backoff_delays() {
+        let backoff = Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(8),
+            total: Duration::from_secs(40),
+        };
+        let delays: Vec<u64> = backoff.delays().map(|d| d.as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 8, 8, 8]);
+
+        let total: Duration = REBOOT_BACKOFF.delays().sum();
+        assert!(total <= REBOOT_BACKOFF.total);
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_after_reboot() {
+        let backoff = Backoff {
+            total: Duration::from_millis(5),
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(1),
+        };
+        let mut connector = MockConnector::new(3);
+        let device = connect_with_backoff(&mut connector, &backoff)
+            .await
+            .unwrap();
+        assert!(device.is_some());
+        assert_eq!(connector.attempts, 4);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_when_device_never_answers() {
+        let mut connector = MockConnector::new(usize::MAX);
+        let device = connect_with_backoff(&mut connector, &NO_RETRIES)
+            .await
+            .unwrap();
+        assert!(device.is_none());
+        assert_eq!(connector.attempts, 1);
+
+        // a device which connects but never answers is no better
+        let mut connector = MockConnector::new(0);
+        connector.responsive = false;
+        let backoff = Backoff {
+            total: Duration::from_millis(3),
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(1),
+        };
+        let device = connect_with_backoff(&mut connector, &backoff)
+            .await
+            .unwrap();
+        assert!(device.is_none());
+        assert_eq!(connector.attempts, 4);
+    }
+
+    #[tokio::test]
+    async fn test_connection_errors_are_not_retried() {
+        assert!(
+            connect_with_backoff(&mut FailingConnector, &REBOOT_BACKOFF)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_tcp_unreachable_error_explains() {
+        let addr: SocketAddr = "192.168.100.1:5555".parse().unwrap();
+        let message = AdbTarget::Tcp(addr).unreachable_error().to_string();
+        assert!(message.contains("adb connect 192.168.100.1:5555"));
+        assert!(message.contains("USB"));
+    }
+}
@@ -3,33 +3,63 @@ use log::debug;
 use pcap_file_tokio::pcapng::blocks::enhanced_packet::EnhancedPacketBlock;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use thiserror::Error;
 
-use crate::analysis::diagnostic::DiagnosticAnalyzer;
+use crate::analysis::diagnostic::{DiagnosticAnalyzer, DiagnosticCategory};
+use crate::analysis::event_message::{EventDetails, english_message};
+use crate::analysis::fault_injection::{FaultInjectionAnalyzer, FaultInjectionConfig};
 use crate::gsmtap::{GsmtapHeader, GsmtapMessage, GsmtapType};
+use crate::location::GeoPoint;
 use crate::util::RuntimeMetadata;
 use crate::{diag::MessagesContainer, gsmtap_parser};
 
 use super::{
     connection_redirect_downgrade::ConnectionRedirect2GDowngradeAnalyzer,
-    imsi_requested::ImsiRequestedAnalyzer, incomplete_sib::IncompleteSibAnalyzer,
-    information_element::InformationElement, nas_null_cipher::NasNullCipherAnalyzer,
-    null_cipher::NullCipherAnalyzer, priority_2g_downgrade::LteSib6And7DowngradeAnalyzer,
+    imsi_requested::ImsiRequestedAnalyzer,
+    incomplete_sib::IncompleteSibAnalyzer,
+    information_element::{InformationElement, InformationElementError, InformationElementSummary},
+    nas_integrity_downgrade::NasIntegrityDowngradeAnalyzer,
+    nas_null_cipher::NasNullCipherAnalyzer,
+    null_cipher::NullCipherAnalyzer,
+    otdoa_request::OtdoaRequestAnalyzer,
+    priority_2g_downgrade::LteSib6And7DowngradeAnalyzer,
+    service_request_flood::ServiceRequestFloodAnalyzer,
+    sfn_continuity::SfnContinuityAnalyzer,
+    sib_neighbor_list::SibNeighborListAnalyzer,
+    sib3_reselection_anomaly::Sib3ReselectionAnomalyAnalyzer,
     test_analyzer::TestAnalyzer,
+    util::sib1_plmn,
+    wcdma_null_cipher::WcdmaCipheringAnalyzer,
 };
 
 /// A list of booleans which stores information about which analyzers are enabled
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
 #[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+#[non_exhaustive]
 pub struct AnalyzerConfig {
     pub diagnostic_analyzer: bool,
     pub connection_redirect_2g_downgrade: bool,
     pub lte_sib6_and_7_downgrade: bool,
     pub null_cipher: bool,
     pub nas_null_cipher: bool,
+    pub nas_integrity_downgrade: bool,
+    pub wcdma_null_cipher: bool,
     pub incomplete_sib: bool,
     pub test_analyzer: bool,
     pub imsi_requested: bool,
+    pub sfn_continuity: bool,
+    pub otdoa_request: bool,
+    pub sib3_reselection_anomaly: bool,
+    pub sib_neighbor_list: bool,
+    pub service_request_flood: bool,
+    /// Severities to report diagnostic analyzer categories at, instead of
+    /// Informational
+    pub diagnostic_severity: BTreeMap<DiagnosticCategory, EventType>,
+    /// Emits fake events on a fixed schedule, for testing
+    pub fault_injection: FaultInjectionConfig,
 }
 
 impl Default for AnalyzerConfig {
@@ -41,12 +71,168 @@ impl Default for AnalyzerConfig {
             lte_sib6_and_7_downgrade: true,
             null_cipher: true,
             nas_null_cipher: true,
+            nas_integrity_downgrade: true,
+            wcdma_null_cipher: true,
             incomplete_sib: true,
+            sfn_continuity: true,
+            otdoa_request: true,
+            sib3_reselection_anomaly: true,
+            sib_neighbor_list: false,
+            service_request_flood: false,
             test_analyzer: false,
+            diagnostic_severity: BTreeMap::new(),
+            fault_injection: FaultInjectionConfig::default(),
         }
     }
 }
 
+impl AnalyzerConfig {
+    // Every analyzer, keyed by the setting which enables it, set up with this
+    // config's settings, and whether this config enables it. This is the one
+    // list of analyzers everything describing them is built from.
+    fn known_analyzers(&self) -> [(&'static str, bool, Box<dyn Analyzer + Send>); 16] {
+        [
+            (
+                "imsi_requested",
+                self.imsi_requested,
+                Box::new(ImsiRequestedAnalyzer::new()),
+            ),
+            (
+                "connection_redirect_2g_downgrade",
+                self.connection_redirect_2g_downgrade,
+                Box::new(ConnectionRedirect2GDowngradeAnalyzer {}),
+            ),
+            (
+                "lte_sib6_and_7_downgrade",
+                self.lte_sib6_and_7_downgrade,
+                Box::new(LteSib6And7DowngradeAnalyzer::new()),
+            ),
+            (
+                "null_cipher",
+                self.null_cipher,
+                Box::new(NullCipherAnalyzer {}),
+            ),
+            (
+                "nas_null_cipher",
+                self.nas_null_cipher,
+                Box::new(NasNullCipherAnalyzer {}),
+            ),
+            (
+                "nas_integrity_downgrade",
+                self.nas_integrity_downgrade,
+                Box::new(NasIntegrityDowngradeAnalyzer::new()),
+            ),
+            (
+                "wcdma_null_cipher",
+                self.wcdma_null_cipher,
+                Box::new(WcdmaCipheringAnalyzer {}),
+            ),
+            (
+                "incomplete_sib",
+                self.incomplete_sib,
+                Box::new(IncompleteSibAnalyzer {}),
+            ),
+            (
+                "sfn_continuity",
+                self.sfn_continuity,
+                Box::new(SfnContinuityAnalyzer::new()),
+            ),
+            (
+                "otdoa_request",
+                self.otdoa_request,
+                Box::new(OtdoaRequestAnalyzer::new()),
+            ),
+            (
+                "sib3_reselection_anomaly",
+                self.sib3_reselection_anomaly,
+                Box::new(Sib3ReselectionAnomalyAnalyzer::new()),
+            ),
+            (
+                "sib_neighbor_list",
+                self.sib_neighbor_list,
+                Box::new(SibNeighborListAnalyzer::new()),
+            ),
+            (
+                "service_request_flood",
+                self.service_request_flood,
+                Box::new(ServiceRequestFloodAnalyzer::new()),
+            ),
+            (
+                "test_analyzer",
+                self.test_analyzer,
+                Box::new(TestAnalyzer {}),
+            ),
+            (
+                "fault_injection",
+                self.fault_injection.enabled,
+                Box::new(FaultInjectionAnalyzer::new(self.fault_injection.clone())),
+            ),
+            (
+                "diagnostic_analyzer",
+                self.diagnostic_analyzer,
+                Box::new(DiagnosticAnalyzer::with_severity_overrides(
+                    self.diagnostic_severity.clone(),
+                )),
+            ),
+        ]
+    }
+
+    /// The name each analyzer reports from [Analyzer::get_name], keyed by
+    /// the setting which enables it, e.g. `imsi_requested`.
+    pub fn analyzer_names() -> Vec<(&'static str, String)> {
+        AnalyzerConfig::default()
+            .known_analyzers()
+            .into_iter()
+            .map(|(id, _, analyzer)| (id, analyzer.get_name().into_owned()))
+            .collect()
+    }
+
+    /// Describes every analyzer, whether this config enables it or not, with
+    /// its settings as this config has them.
+    pub fn analyzer_info(&self) -> Vec<AnalyzerInfo> {
+        let defaults = AnalyzerConfig::default().known_analyzers();
+        self.known_analyzers()
+            .into_iter()
+            .zip(defaults)
+            .map(|((key, enabled, analyzer), (_, default_enabled, _))| {
+                let settings = match key {
+                    "diagnostic_analyzer" => serde_json::to_value(&self.diagnostic_severity).ok(),
+                    "fault_injection" => serde_json::to_value(&self.fault_injection).ok(),
+                    _ => None,
+                };
+                AnalyzerInfo {
+                    key: key.to_string(),
+                    name: analyzer.get_name().into_owned(),
+                    description: analyzer.get_description().into_owned(),
+                    version: analyzer.get_version(),
+                    enabled,
+                    default_enabled,
+                    severities: analyzer.get_severities(),
+                    settings,
+                }
+            })
+            .collect()
+    }
+
+    /// A copy of this config with only the analyzers with the given names
+    /// (as reported by [Analyzer::get_name]) enabled, e.g. to run the same
+    /// analyzers a report was written with. Their other settings are kept.
+    pub fn with_only_named<S: AsRef<str>>(&self, names: &[S]) -> AnalyzerConfig {
+        // each analyzer's key is the name of its setting, so the settings
+        // can be switched by key
+        let mut settings = serde_json::to_value(self).expect("AnalyzerConfig serializes");
+        for (key, name) in Self::analyzer_names() {
+            let on = names.iter().any(|n| n.as_ref() == name);
+            let enabled = match key {
+                "fault_injection" => &mut settings[key]["enabled"],
+                _ => &mut settings[key],
+            };
+            *enabled = on.into();
+        }
+        serde_json::from_value(settings).expect("AnalyzerConfig deserializes")
+    }
+}
+
 pub const REPORT_VERSION: u32 = 2;
 
 /// The severity level of an event.
@@ -61,6 +247,42 @@ pub enum EventType {
     High = 3,
 }
 
+impl EventType {
+    /// Every severity, from least to most severe
+    pub const ALL: [EventType; 4] = [
+        EventType::Informational,
+        EventType::Low,
+        EventType::Medium,
+        EventType::High,
+    ];
+
+    /// The severity's name, as it's serialized, e.g. `Medium`
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventType::Informational => "Informational",
+            EventType::Low => "Low",
+            EventType::Medium => "Medium",
+            EventType::High => "High",
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+#[error("unknown severity \"{0}\", expected informational, low, medium or high")]
+pub struct UnknownSeverity(pub String);
+
+/// Parses a severity by its name in any case, e.g. `medium`
+impl FromStr for EventType {
+    type Err = UnknownSeverity;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EventType::ALL
+            .into_iter()
+            .find(|event_type| event_type.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| UnknownSeverity(s.to_string()))
+    }
+}
+
 impl<'de> Deserialize<'de> for EventType {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -83,13 +305,10 @@ impl<'de> Deserialize<'de> for EventType {
         }
 
         match EventTypeHelper::deserialize(deserializer)? {
-            EventTypeHelper::New(s) => match s.as_str() {
-                "Informational" => Ok(EventType::Informational),
-                "Low" => Ok(EventType::Low),
-                "Medium" => Ok(EventType::Medium),
-                "High" => Ok(EventType::High),
-                _ => Err(D::Error::custom(format!("unknown EventType: {s}"))),
-            },
+            EventTypeHelper::New(s) => EventType::ALL
+                .into_iter()
+                .find(|event_type| event_type.name() == s)
+                .ok_or_else(|| D::Error::custom(format!("unknown EventType: {s}"))),
             EventTypeHelper::Old(old) => match old {
                 OldEventType::Informational => Ok(EventType::Informational),
                 OldEventType::QualitativeWarning { severity } => match severity.as_str() {
@@ -109,13 +328,50 @@ impl<'de> Deserialize<'de> for EventType {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Event {
     pub event_type: EventType,
+    /// The name of the analyzer which emitted the event, as returned by its
+    /// [Analyzer::get_name]. The [Harness] fills this in, so analyzers can
+    /// leave it empty. It's empty in reports written before it was added.
+    #[serde(default)]
+    pub analyzer: String,
+    /// The event's message in English, for channels which don't format it
+    /// themselves
     pub message: String,
+    /// What the event is about, for rendering its message in other languages
+    /// and lengths with an [EventFormatter](super::event_message::EventFormatter)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<EventDetails>,
+}
+
+impl Event {
+    /// Creates an event, with its message rendered in English from the
+    /// details.
+    pub fn new(event_type: EventType, details: EventDetails) -> Self {
+        Event {
+            event_type,
+            analyzer: String::new(),
+            message: english_message(&details),
+            details: Some(details),
+        }
+    }
+}
+
+/// Information about the packet an [InformationElement] was decoded from,
+/// which isn't part of the message itself.
+#[derive(Debug, Clone)]
+pub struct PacketMetadata {
+    pub timestamp: DateTime<FixedOffset>,
+    /// The ARFCN (or EARFCN, for LTE) from the GSMTAP header. This is 0 when
+    /// unknown, or when the EARFCN doesn't fit in GSMTAP's 14 bits.
+    pub arfcn: u16,
 }
 
 /// An [Analyzer] represents one type of heuristic for detecting an IMSI Catcher
 /// (IC). While maintaining some amount of state is useful, be mindful of how
 /// much memory your [Analyzer] uses at runtime, since rayhunter may run for
 /// many hours at a time with dozens of [Analyzers](Analyzer) working in parallel.
+///
+/// Analyzers outside this crate are supported: any method added to this
+/// trait in future will have a default implementation.
 pub trait Analyzer {
     /// Returns a user-friendly, concise name for your heuristic.
     fn get_name(&self) -> Cow<'_, str>;
@@ -137,10 +393,50 @@ pub trait Analyzer {
         packet_num: usize,
     ) -> Option<Event>;
 
+    /// Called before each [InformationElement] is analyzed, with metadata
+    /// about the packet it was decoded from. Only analyzers which care about
+    /// timing or the radio channel need to implement this.
+    fn set_packet_metadata(&mut self, _metadata: &PacketMetadata) {}
+
     /// Returns a version number for this Analyzer. This should only ever
     /// increase in value, and do so whenever substantial changes are made to
     /// the Analyzer's heuristic.
     fn get_version(&self) -> u32;
+
+    /// Returns the severities of the [Events](Event) this Analyzer can emit,
+    /// least severe first, so users can tell what it might flag before it
+    /// does. Empty if the Analyzer doesn't say.
+    fn get_severities(&self) -> Vec<EventType> {
+        Vec::new()
+    }
+
+    /// Forgets everything learned from the messages analyzed so far, so the
+    /// [Analyzer] can be reused on another recording (or the same one again)
+    /// as if it were new. Stateless analyzers don't need to implement this.
+    fn reset(&mut self) {}
+}
+
+/// Everything there is to show users about an analyzer, from
+/// [AnalyzerConfig::analyzer_info]
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct AnalyzerInfo {
+    /// The setting which enables the analyzer, e.g. `imsi_requested`
+    pub key: String,
+    /// The name the analyzer reports from [Analyzer::get_name]
+    pub name: String,
+    pub description: String,
+    pub version: u32,
+    pub enabled: bool,
+    /// Whether the analyzer is enabled when the config doesn't say
+    pub default_enabled: bool,
+    /// The severities of the events the analyzer can emit, from
+    /// [Analyzer::get_severities]
+    pub severities: Vec<EventType>,
+    /// The analyzer's other settings, with their current values, for those
+    /// which have any
+    #[cfg_attr(feature = "apidocs", schema(value_type = Option<Object>))]
+    pub settings: Option<serde_json::Value>,
 }
 
 /// Specific information on a given analyzer
@@ -172,6 +468,10 @@ pub struct ReportMetadata {
     // clearly differentiate some known false-positive-results from the pre-versioned era from v1
     // heuristics
     pub report_version: u32,
+    /// Where the device was when the recording started, if location tagging
+    /// was enabled and the GPS had a fix
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_location: Option<GeoPoint>,
 }
 
 impl ReportMetadata {
@@ -223,11 +523,95 @@ impl AnalysisLineNormalizer {
     }
 }
 
+/// Which rows of an analysis report to keep. Rows are kept only if they pass
+/// every filter which is set.
+#[derive(Debug, Default, Clone)]
+pub struct ReportFilter {
+    /// Keep rows with an event at least this severe
+    pub min_severity: Option<EventType>,
+    /// Keep rows with an event from the analyzer with this name, as returned
+    /// by its [Analyzer::get_name]
+    pub analyzer_name: Option<String>,
+    /// Keep rows with any event
+    pub events_only: bool,
+}
+
+impl ReportFilter {
+    /// Whether the filter keeps every row
+    pub fn is_empty(&self) -> bool {
+        self.min_severity.is_none() && self.analyzer_name.is_none() && !self.events_only
+    }
+
+    pub fn into_line_filter(self) -> ReportLineFilter {
+        ReportLineFilter {
+            filter: self,
+            analyzer_index: None,
+            is_first: true,
+        }
+    }
+}
+
+/// Applies a [ReportFilter] to an analysis report one line at a time, so a
+/// report never has to be loaded into memory. The first line is the report
+/// metadata, which is always kept.
+pub struct ReportLineFilter {
+    filter: ReportFilter,
+    // where the filtered analyzer's events are in each row, from the order
+    // of the analyzers in the metadata
+    analyzer_index: Option<usize>,
+    is_first: bool,
+}
+
+impl ReportLineFilter {
+    /// Whether to keep `line` of the report
+    pub fn keep_line(&mut self, line: &str) -> bool {
+        if self.is_first {
+            self.is_first = false;
+            if let (Some(name), Ok(metadata)) = (
+                &self.filter.analyzer_name,
+                serde_json::from_str::<ReportMetadata>(line),
+            ) {
+                self.analyzer_index = metadata.analyzers.iter().position(|a| &a.name == name);
+            }
+            return true;
+        }
+        if self.filter.is_empty() {
+            return true;
+        }
+        match serde_json::from_str::<AnalysisRow>(line) {
+            Ok(row) => self.keep_row(&row),
+            Err(_) => false,
+        }
+    }
+
+    // only called for a non-empty filter
+    fn keep_row(&self, row: &AnalysisRow) -> bool {
+        let events: Vec<&Event> = match (&self.filter.analyzer_name, self.analyzer_index) {
+            (None, _) => row.events.iter().flatten().collect(),
+            (Some(_), Some(index)) => row.events.get(index).into_iter().flatten().collect(),
+            // the analyzer didn't run when this report was written
+            (Some(_), None) => Vec::new(),
+        };
+        // every filter needs at least one event, so events_only needs no
+        // check of its own
+        let min_severity = self.filter.min_severity.unwrap_or(EventType::Informational);
+        events.iter().any(|event| event.event_type >= min_severity)
+    }
+}
+
 #[derive(Serialize, Debug)]
 pub struct AnalysisRow {
+    /// When the message was captured, from its diag log header. This doesn't
+    /// depend on when the analysis ran, so a recording analyzed live and
+    /// reanalyzed later gets the same timestamps.
     pub packet_timestamp: Option<DateTime<FixedOffset>>,
     pub skipped_message_reason: Option<String>,
     pub events: Vec<Option<Event>>,
+    /// What the message was, without its decoded contents. Only filled in
+    /// when [Harness::set_summarize_messages] is enabled, and never written
+    /// to the report.
+    #[serde(skip)]
+    pub message_summary: Option<InformationElementSummary>,
 }
 
 impl AnalysisRow {
@@ -292,12 +676,14 @@ impl<'de> Deserialize<'de> for AnalysisRow {
                         packet_timestamp: Some(first_analysis.timestamp),
                         skipped_message_reason: None,
                         events: first_analysis.events.clone(),
+                        message_summary: None,
                     })
                 } else if let Some(first_reason) = v1.skipped_message_reasons.first() {
                     Ok(AnalysisRow {
                         packet_timestamp: Some(v1.timestamp),
                         skipped_message_reason: Some(first_reason.clone()),
                         events: Vec::new(),
+                        message_summary: None,
                     })
                 } else {
                     Err(D::Error::custom(
@@ -309,6 +695,7 @@ impl<'de> Deserialize<'de> for AnalysisRow {
                 packet_timestamp: v2.packet_timestamp,
                 skipped_message_reason: v2.skipped_message_reason,
                 events: v2.events,
+                message_summary: None,
             }),
         }
     }
@@ -317,6 +704,9 @@ impl<'de> Deserialize<'de> for AnalysisRow {
 pub struct Harness {
     analyzers: Vec<Box<dyn Analyzer + Send>>,
     packet_num: usize,
+    parse_errors: u64,
+    serving_plmn: Option<String>,
+    summarize_messages: bool,
 }
 
 impl Default for Harness {
@@ -330,56 +720,85 @@ impl Harness {
         Self {
             analyzers: Vec::new(),
             packet_num: 0,
+            parse_errors: 0,
+            serving_plmn: None,
+            summarize_messages: false,
         }
     }
 
+    /// A harness running every analyzer `analyzer_config` enables, in the
+    /// order [AnalyzerConfig::analyzer_info] lists them.
     pub fn new_with_config(analyzer_config: &AnalyzerConfig) -> Self {
         let mut harness = Harness::new();
-
-        if analyzer_config.imsi_requested {
-            harness.add_analyzer(Box::new(ImsiRequestedAnalyzer::new()));
-        }
-        if analyzer_config.connection_redirect_2g_downgrade {
-            harness.add_analyzer(Box::new(ConnectionRedirect2GDowngradeAnalyzer {}));
-        }
-        if analyzer_config.lte_sib6_and_7_downgrade {
-            harness.add_analyzer(Box::new(LteSib6And7DowngradeAnalyzer::new()));
-        }
-        if analyzer_config.null_cipher {
-            harness.add_analyzer(Box::new(NullCipherAnalyzer {}));
+        for (_, enabled, analyzer) in analyzer_config.known_analyzers() {
+            if enabled {
+                harness.add_analyzer(analyzer);
+            }
         }
+        harness
+    }
 
-        if analyzer_config.nas_null_cipher {
-            harness.add_analyzer(Box::new(NasNullCipherAnalyzer {}))
-        }
+    pub fn add_analyzer(&mut self, analyzer: Box<dyn Analyzer + Send>) {
+        self.analyzers.push(analyzer);
+    }
 
-        if analyzer_config.incomplete_sib {
-            harness.add_analyzer(Box::new(IncompleteSibAnalyzer {}))
+    /// Resets every analyzer, the packet and parse error counts and the
+    /// serving PLMN, so the next recording is analyzed independently of
+    /// whatever came before it.
+    pub fn reset(&mut self) {
+        self.packet_num = 0;
+        self.parse_errors = 0;
+        self.serving_plmn = None;
+        for analyzer in &mut self.analyzers {
+            analyzer.reset();
         }
+    }
 
-        if analyzer_config.test_analyzer {
-            harness.add_analyzer(Box::new(TestAnalyzer {}))
-        }
+    /// Fill in [AnalysisRow::message_summary] for every message that could be
+    /// decoded. This costs a Debug formatting of each message, so it's off by
+    /// default.
+    pub fn set_summarize_messages(&mut self, summarize_messages: bool) {
+        self.summarize_messages = summarize_messages;
+    }
 
-        if analyzer_config.diagnostic_analyzer {
-            harness.add_analyzer(Box::new(DiagnosticAnalyzer {}));
-        }
+    /// How many messages couldn't be parsed. Unlike
+    /// [AnalysisRow::skipped_message_reason], this doesn't count messages
+    /// which were skipped because they're of a type nothing analyzes.
+    pub fn parse_errors(&self) -> u64 {
+        self.parse_errors
+    }
 
-        harness
+    /// The PLMN, as "MCC-MNC", of the cell whose SIB1 was analyzed last, if
+    /// one has been seen since the harness was created or reset.
+    pub fn serving_plmn(&self) -> Option<&str> {
+        self.serving_plmn.as_deref()
     }
 
-    pub fn add_analyzer(&mut self, analyzer: Box<dyn Analyzer + Send>) {
-        self.analyzers.push(analyzer);
+    // Records a message which couldn't be turned into an information
+    // element, unless that's just because it's a type we don't decode
+    fn count_ie_error(&mut self, err: &InformationElementError) {
+        if !matches!(err, InformationElementError::UnsupportedGsmtapType(_)) {
+            self.parse_errors += 1;
+        }
+    }
+
+    fn summarize(&self, element: &InformationElement) -> Option<InformationElementSummary> {
+        self.summarize_messages.then(|| InformationElementSummary {
+            decoded: None,
+            ..element.summary()
+        })
     }
 
     pub fn analyze_pcap_packet(&mut self, packet: EnhancedPacketBlock) -> AnalysisRow {
         self.packet_num += 1;
 
         let epoch = DateTime::parse_from_rfc3339("1980-01-06T00:00:00-00:00").unwrap();
+        let packet_timestamp = epoch + packet.timestamp;
         let mut row = AnalysisRow {
-            packet_timestamp: Some(epoch + packet.timestamp),
+            packet_timestamp: Some(packet_timestamp),
             skipped_message_reason: None,
             events: Vec::new(),
+            message_summary: None,
         };
         let gsmtap_offset = 20 + 8;
         let gsmtap_data = &packet.data[gsmtap_offset..];
@@ -391,6 +810,11 @@ impl Harness {
                 return row;
             }
         };
+        // the ARFCN is the low 14 bits of the big-endian u16 at byte offset 4
+        let metadata = PacketMetadata {
+            timestamp: packet_timestamp,
+            arfcn: u16::from_be_bytes([gsmtap_data[4], gsmtap_data[5]]) & 0x3fff,
+        };
         let packet_offset = gsmtap_offset + 16;
         let packet_data = &packet.data[packet_offset..];
         let gsmtap_message = GsmtapMessage {
@@ -398,8 +822,12 @@ impl Harness {
             payload: packet_data.to_vec(),
         };
         row.events = match InformationElement::try_from(&gsmtap_message) {
-            Ok(element) => self.analyze_information_element(&element),
+            Ok(element) => {
+                row.message_summary = self.summarize(&element);
+                self.analyze_information_element(&element, &metadata)
+            }
             Err(err) => {
+                self.count_ie_error(&err);
                 let msg = format!(
                     "in packet {}, failed to convert gsmtap message to IE: {err:?}",
                     self.packet_num
@@ -421,12 +849,14 @@ impl Harness {
                 packet_timestamp: None,
                 skipped_message_reason: None,
                 events: Vec::new(),
+                message_summary: None,
             });
             // unwrap is safe here since we just pushed a value
             let row = rows.last_mut().unwrap();
             let qmdl_message = match maybe_qmdl_message {
                 Ok(msg) => msg,
                 Err(err) => {
+                    self.parse_errors += 1;
                     row.skipped_message_reason = Some(format!("{err:?}"));
                     continue;
                 }
@@ -435,6 +865,7 @@ impl Harness {
             let gsmtap_message = match gsmtap_parser::parse(qmdl_message) {
                 Ok(msg) => msg,
                 Err(err) => {
+                    self.parse_errors += 1;
                     row.skipped_message_reason = Some(format!("{err:?}"));
                     continue;
                 }
@@ -443,32 +874,47 @@ impl Harness {
             let Some((timestamp, gsmtap_msg)) = gsmtap_message else {
                 continue;
             };
-            row.packet_timestamp = Some(timestamp.to_datetime());
+            let metadata = PacketMetadata {
+                timestamp: timestamp.to_datetime(),
+                arfcn: gsmtap_msg.header.arfcn,
+            };
+            row.packet_timestamp = Some(metadata.timestamp);
 
             let element = match InformationElement::try_from(&gsmtap_msg) {
                 Ok(element) => element,
                 Err(err) => {
+                    self.count_ie_error(&err);
                     row.skipped_message_reason = Some(format!("{err:?}"));
                     continue;
                 }
             };
 
-            row.events = self.analyze_information_element(&element);
+            row.message_summary = self.summarize(&element);
+            row.events = self.analyze_information_element(&element, &metadata);
         }
         rows
     }
 
-    fn analyze_information_element(&mut self, ie: &InformationElement) -> Vec<Option<Event>> {
+    fn analyze_information_element(
+        &mut self,
+        ie: &InformationElement,
+        metadata: &PacketMetadata,
+    ) -> Vec<Option<Event>> {
         // This method is private because incrementing packet_num is currently handled entirely by the other
         // methods that call this one. This could be changed with some careful refactoring, but
         // while this method is only used by other Harness methods, let's keep it private to help
         // ensure we always bump packet_num exactly once for each processed packet.
+        if let Some(plmn) = sib1_plmn(ie) {
+            self.serving_plmn = Some(plmn);
+        }
         let packet_str = format!(" (packet {})", self.packet_num);
         self.analyzers
             .iter_mut()
             .map(|analyzer| {
+                analyzer.set_packet_metadata(metadata);
                 let mut maybe_event = analyzer.analyze_information_element(ie, self.packet_num);
                 if let Some(ref mut event) = maybe_event {
+                    event.analyzer = analyzer.get_name().into_owned();
                     event.message.push_str(&packet_str);
                 }
                 maybe_event
@@ -492,6 +938,7 @@ impl Harness {
             analyzers,
             rayhunter,
             report_version: REPORT_VERSION,
+            start_location: None,
         }
     }
 }
@@ -548,4 +995,346 @@ mod tests {
         );
         assert!(row.events[2].is_none());
     }
+
+    // 2024-08-19T03:33:55.5Z: the upper 48 bits count 1/800s ticks since
+    // 1980-01-06, and the lower 16 bits are a fraction of a tick
+    const KNOWN_DIAG_TIMESTAMP: u64 = (1_126_458_908_400 << 16) + 0x1234;
+
+    fn container_with_timestamp(ts: u64) -> MessagesContainer {
+        use crate::diag::{
+            CRC_CCITT, DataType, HdlcEncapsulatedMessage, LogBody, LteRrcOtaPacket, Message,
+            Timestamp,
+        };
+        use deku::DekuContainerWrite;
+
+        let payload = [0x40, 0x01, 0xee, 0xad, 0xd5, 0x4d, 0xd0];
+        let length = 31 + payload.len() as u16;
+        let message = Message::Log {
+            pending_msgs: 0,
+            outer_length: length,
+            inner_length: length,
+            log_type: 0xb0c0,
+            timestamp: Timestamp { ts },
+            body: LogBody::LteRrcOtaMessage {
+                ext_header_version: 20,
+                packet: LteRrcOtaPacket::V8 {
+                    rrc_rel_maj: 14,
+                    rrc_rel_min: 48,
+                    bearer_id: 0,
+                    phy_cell_id: 160,
+                    earfcn: 2050,
+                    sfn_subfn: 4057,
+                    pdu_num: 5,
+                    sib_mask: 0,
+                    len: payload.len() as u16,
+                    packet: payload.to_vec(),
+                },
+            },
+        };
+        let data = crate::hdlc::hdlc_encapsulate(&message.to_bytes().unwrap(), &CRC_CCITT);
+        MessagesContainer {
+            data_type: DataType::UserSpace,
+            num_messages: 1,
+            messages: vec![HdlcEncapsulatedMessage {
+                len: data.len() as u32,
+                data,
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_packet_timestamp_is_message_timestamp() {
+        use crate::qmdl::{QmdlReader, QmdlWriter};
+
+        let expected = DateTime::parse_from_rfc3339("2024-08-19T03:33:55Z").unwrap();
+        let container = container_with_timestamp(KNOWN_DIAG_TIMESTAMP);
+
+        // analyzed live, straight off the diag device
+        let live_rows = Harness::new().analyze_qmdl_messages(container.clone());
+        let live_timestamp = live_rows[0].packet_timestamp.unwrap();
+        assert_eq!(live_timestamp.timestamp(), expected.timestamp());
+
+        // analyzed later, after a round trip through a QMDL file
+        let mut qmdl = Vec::new();
+        QmdlWriter::new(&mut qmdl)
+            .write_container(&container)
+            .await
+            .unwrap();
+        let mut reader = QmdlReader::new(qmdl.as_slice(), None);
+        let mut harness = Harness::new();
+        let mut queued_rows = Vec::new();
+        while let Some(container) = reader.get_next_messages_container().await.unwrap() {
+            queued_rows.extend(harness.analyze_qmdl_messages(container));
+        }
+        assert_eq!(queued_rows.len(), 1);
+        assert_eq!(queued_rows[0].packet_timestamp, Some(live_timestamp));
+    }
+
+    #[test]
+    fn test_parse_errors_count_corrupt_messages() {
+        let mut harness = Harness::new();
+        harness.analyze_qmdl_messages(container_with_timestamp(KNOWN_DIAG_TIMESTAMP));
+        assert_eq!(harness.parse_errors(), 0);
+
+        // flip a bit of the log command code, so the CRC no longer matches
+        let mut corrupt = container_with_timestamp(KNOWN_DIAG_TIMESTAMP);
+        corrupt.messages[0].data[0] ^= 0x01;
+        let rows = harness.analyze_qmdl_messages(corrupt);
+        assert!(rows[0].skipped_message_reason.is_some());
+        assert_eq!(harness.parse_errors(), 1);
+
+        harness.reset();
+        assert_eq!(harness.parse_errors(), 0);
+    }
+
+    #[test]
+    fn test_serving_plmn_follows_sib1() {
+        use crate::analysis::test_support::{ie_identity_request, ie_sib1};
+
+        let metadata = PacketMetadata {
+            timestamp: DateTime::parse_from_rfc3339("2024-01-01T00:00:00+00:00").unwrap(),
+            arfcn: 0,
+        };
+        let mut harness = Harness::new();
+        assert_eq!(harness.serving_plmn(), None);
+
+        harness.analyze_information_element(&ie_sib1([3, 1, 0], &[2, 6, 0], 1), &metadata);
+        assert_eq!(harness.serving_plmn(), Some("310-260"));
+
+        // other messages don't clear it
+        harness.analyze_information_element(&ie_identity_request(), &metadata);
+        assert_eq!(harness.serving_plmn(), Some("310-260"));
+
+        harness.analyze_information_element(&ie_sib1([2, 6, 2], &[0, 1], 2), &metadata);
+        assert_eq!(harness.serving_plmn(), Some("262-01"));
+
+        harness.reset();
+        assert_eq!(harness.serving_plmn(), None);
+    }
+
+    #[tokio::test]
+    async fn test_events_are_tagged_with_analyzer_name() {
+        use crate::qmdl::QmdlReader;
+        use crate::qmdl_fixture::{FixtureMessage, QmdlFixtureBuilder};
+
+        let qmdl = QmdlFixtureBuilder::new()
+            .message(FixtureMessage::AttachReject { cause: 3 })
+            .build();
+        let mut harness = Harness::new_with_config(&AnalyzerConfig::default());
+        let mut reader = QmdlReader::new(qmdl.as_slice(), Some(qmdl.len()));
+        let mut events = Vec::new();
+        while let Some(container) = reader.get_next_messages_container().await.unwrap() {
+            for row in harness.analyze_qmdl_messages(container) {
+                events.extend(row.events.into_iter().flatten());
+            }
+        }
+
+        let event = events
+            .iter()
+            .find(|event| event.details.as_ref().unwrap().analyzer == "diagnostic_analyzer")
+            .expect("the diagnostic analyzer didn't flag the attach reject");
+        assert!(
+            event.analyzer.starts_with("Diagnostic "),
+            "{}",
+            event.analyzer
+        );
+        let serialized = serde_json::to_value(event).unwrap();
+        assert_eq!(serialized["analyzer"], json!(event.analyzer));
+    }
+
+    fn filtered_report(filter: ReportFilter) -> Vec<String> {
+        let report = [
+            json!({
+                "analyzers": [
+                    { "name": "Null Cipher", "description": "", "version": 1 },
+                    { "name": "Incomplete SIB", "description": "", "version": 1 },
+                ],
+                "report_version": 2,
+            }),
+            json!({ "packet_timestamp": null, "skipped_message_reason": null, "events": [null, null] }),
+            json!({ "packet_timestamp": null, "skipped_message_reason": "bad", "events": [] }),
+            json!({
+                "packet_timestamp": null,
+                "skipped_message_reason": null,
+                "events": [null, { "event_type": "Informational", "message": "sib" }],
+            }),
+            json!({
+                "packet_timestamp": null,
+                "skipped_message_reason": null,
+                "events": [{ "event_type": "High", "message": "null cipher" }, null],
+            }),
+        ]
+        .map(|line| line.to_string());
+        let mut line_filter = filter.into_line_filter();
+        report
+            .into_iter()
+            .filter(|line| line_filter.keep_line(line))
+            .collect()
+    }
+
+    fn event_messages(lines: &[String]) -> Vec<String> {
+        lines
+            .iter()
+            .skip(1)
+            .flat_map(|line| serde_json::from_str::<AnalysisRow>(line).unwrap().events)
+            .flatten()
+            .map(|event| event.message)
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_event_type() {
+        for event_type in EventType::ALL {
+            let name = event_type.name().to_lowercase();
+            assert_eq!(name.parse::<EventType>(), Ok(event_type));
+            let json = serde_json::to_string(&event_type).unwrap();
+            assert_eq!(json, format!("\"{}\"", event_type.name()));
+        }
+        assert_eq!(
+            "severe".parse::<EventType>(),
+            Err(UnknownSeverity("severe".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_report_filter() {
+        let all = filtered_report(ReportFilter::default());
+        assert_eq!(all.len(), 5);
+
+        let events_only = filtered_report(ReportFilter {
+            events_only: true,
+            ..Default::default()
+        });
+        assert_eq!(events_only.len(), 3);
+        assert!(events_only[0].contains("analyzers"));
+        assert_eq!(event_messages(&events_only), ["sib", "null cipher"]);
+
+        let high = filtered_report(ReportFilter {
+            min_severity: Some(EventType::Medium),
+            ..Default::default()
+        });
+        assert_eq!(event_messages(&high), ["null cipher"]);
+
+        let sib = filtered_report(ReportFilter {
+            analyzer_name: Some("Incomplete SIB".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(event_messages(&sib), ["sib"]);
+
+        let sib_high = filtered_report(ReportFilter {
+            min_severity: Some(EventType::High),
+            analyzer_name: Some("Incomplete SIB".to_string()),
+            events_only: false,
+        });
+        assert_eq!(sib_high.len(), 1);
+
+        // an analyzer which didn't run when the report was written
+        let missing = filtered_report(ReportFilter {
+            analyzer_name: Some("SFN Discontinuity".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(missing.len(), 1);
+
+        for filtered in [events_only, high, sib, sib_high, missing] {
+            assert!(filtered.iter().all(|line| all.contains(line)));
+        }
+    }
+
+    #[test]
+    fn test_analyzer_names_cover_config() {
+        let names = AnalyzerConfig::analyzer_names();
+        let config = serde_json::to_value(AnalyzerConfig::default()).unwrap();
+        for (id, _) in &names {
+            assert!(config.get(id).is_some(), "{id} isn't an analyzer setting");
+        }
+        let (_, name) = names
+            .iter()
+            .find(|(id, _)| *id == "imsi_requested")
+            .unwrap();
+        assert_eq!(*name, ImsiRequestedAnalyzer::new().get_name());
+    }
+
+    #[test]
+    fn test_harness_runs_enabled_analyzers() {
+        let config = AnalyzerConfig {
+            null_cipher: false,
+            test_analyzer: true,
+            ..Default::default()
+        };
+        let running: Vec<String> = Harness::new_with_config(&config)
+            .get_metadata()
+            .analyzers
+            .into_iter()
+            .map(|analyzer| analyzer.name)
+            .collect();
+        let enabled: Vec<String> = config
+            .analyzer_info()
+            .into_iter()
+            .filter(|analyzer| analyzer.enabled)
+            .map(|analyzer| analyzer.name)
+            .collect();
+        assert_eq!(running, enabled);
+    }
+
+    #[test]
+    fn test_with_only_named() {
+        let config = AnalyzerConfig::default();
+        let names: Vec<String> = Harness::new_with_config(&config)
+            .get_metadata()
+            .analyzers
+            .into_iter()
+            .map(|analyzer| analyzer.name)
+            .collect();
+        let same = Harness::new_with_config(&config.with_only_named(&names)).get_metadata();
+        assert_eq!(same.analyzers.len(), names.len());
+
+        let imsi_only = config.with_only_named(&[ImsiRequestedAnalyzer::new().get_name()]);
+        assert!(imsi_only.imsi_requested);
+        assert!(!imsi_only.null_cipher);
+        assert!(!imsi_only.diagnostic_analyzer);
+        assert!(!imsi_only.fault_injection.enabled);
+    }
+
+    #[test]
+    fn test_analyzer_info() {
+        let mut config = AnalyzerConfig {
+            null_cipher: false,
+            ..Default::default()
+        };
+        config
+            .diagnostic_severity
+            .insert(DiagnosticCategory::AttachReject, EventType::Medium);
+        let info = config.analyzer_info();
+
+        // every analyzer is described, whether it's enabled or not
+        let keys: Vec<&str> = info.iter().map(|analyzer| analyzer.key.as_str()).collect();
+        let known: Vec<&str> = AnalyzerConfig::analyzer_names()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, known);
+        for analyzer in &info {
+            assert!(!analyzer.description.is_empty(), "{}", analyzer.key);
+            assert!(!analyzer.severities.is_empty(), "{}", analyzer.key);
+        }
+
+        let null_cipher = info.iter().find(|a| a.key == "null_cipher").unwrap();
+        assert!(!null_cipher.enabled);
+        assert!(null_cipher.default_enabled);
+        assert_eq!(null_cipher.name, NullCipherAnalyzer {}.get_name());
+        assert_eq!(null_cipher.settings, None);
+
+        let diagnostic = info
+            .iter()
+            .find(|a| a.key == "diagnostic_analyzer")
+            .unwrap();
+        assert_eq!(
+            diagnostic.severities,
+            [EventType::Informational, EventType::Medium]
+        );
+        assert_eq!(
+            diagnostic.settings,
+            Some(serde_json::json!({"attach_reject": "Medium"}))
+        );
+    }
 }
@@ -1,6 +1,7 @@
 use std::borrow::Cow;
 
 use super::analyzer::{Analyzer, Event, EventType};
+use super::event_message::EventDetails;
 use super::information_element::{InformationElement, LteInformationElement};
 use telcom_parser::lte_rrc::{
     DL_DCCH_MessageType, DL_DCCH_MessageType_c1, RRCConnectionReleaseCriticalExtensions,
@@ -25,6 +26,10 @@ impl Analyzer for ConnectionRedirect2GDowngradeAnalyzer {
         1
     }
 
+    fn get_severities(&self) -> Vec<EventType> {
+        vec![EventType::Informational, EventType::High]
+    }
+
     fn analyze_information_element(
         &mut self,
         ie: &InformationElement,
@@ -39,14 +44,15 @@ impl Analyzer for ConnectionRedirect2GDowngradeAnalyzer {
             && let Some(carrier_info) = &r8_ies.redirected_carrier_info
         {
             match carrier_info {
-                RedirectedCarrierInfo::Geran(_carrier_freqs_geran) => Some(Event {
-                    event_type: EventType::High,
-                    message: "Detected 2G downgrade".to_owned(),
-                }),
-                _ => Some(Event {
-                    event_type: EventType::Informational,
-                    message: format!("RRCConnectionRelease CarrierInfo: {carrier_info:?}"),
-                }),
+                RedirectedCarrierInfo::Geran(_carrier_freqs_geran) => Some(Event::new(
+                    EventType::High,
+                    EventDetails::new("connection_redirect_2g_downgrade", "geran_redirect"),
+                )),
+                _ => Some(Event::new(
+                    EventType::Informational,
+                    EventDetails::new("connection_redirect_2g_downgrade", "redirect")
+                        .with("carrier_info", format!("{carrier_info:?}")),
+                )),
             }
         } else {
             None
@@ -1,4 +1,5 @@
 use crate::analysis::analyzer::{Analyzer, Event, EventType};
+use crate::analysis::event_message::EventDetails;
 use crate::analysis::information_element::{InformationElement, LteInformationElement};
 use pycrate_rs::nas::NASMessage;
 use pycrate_rs::nas::emm::EMMMessage;
@@ -6,22 +7,50 @@ use pycrate_rs::nas::generated::emm::emm_attach_reject::EMMCauseEMMCause as Atta
 use pycrate_rs::nas::generated::emm::emm_detach_request_mt::EPSDetachTypeMTType;
 use pycrate_rs::nas::generated::emm::emm_service_reject::EMMCauseEMMCause as ServiceRejectEMMCause;
 use pycrate_rs::nas::generated::emm::emm_tracking_area_update_reject::EMMCauseEMMCause as TAURejectEMMCause;
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 
-pub struct DiagnosticAnalyzer;
+/// The kinds of message the diagnostic analyzer reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+#[non_exhaustive]
+pub enum DiagnosticCategory {
+    IdentityRequest,
+    TrackingAreaUpdateReject,
+    AttachReject,
+    DetachRequest,
+    ServiceReject,
+}
 
-impl Default for DiagnosticAnalyzer {
-    fn default() -> Self {
-        Self::new()
-    }
+#[derive(Default)]
+pub struct DiagnosticAnalyzer {
+    severity_overrides: BTreeMap<DiagnosticCategory, EventType>,
 }
 
 impl DiagnosticAnalyzer {
     pub fn new() -> Self {
-        DiagnosticAnalyzer
+        Self::default()
+    }
+
+    /// Report the given categories at a severity other than Informational.
+    pub fn with_severity_overrides(
+        severity_overrides: BTreeMap<DiagnosticCategory, EventType>,
+    ) -> Self {
+        DiagnosticAnalyzer { severity_overrides }
     }
 
-    fn is_imsi_exposing_nas(&self, nas_msg: &NASMessage) -> bool {
+    fn severity(&self, category: DiagnosticCategory) -> EventType {
+        self.severity_overrides
+            .get(&category)
+            .copied()
+            .unwrap_or(EventType::Informational)
+    }
+
+    /// Whether the message is one of those the Marlin paper found may lead
+    /// to the device exposing its IMSI
+    pub(crate) fn is_imsi_exposing_nas(nas_msg: &NASMessage) -> bool {
         match nas_msg {
             NASMessage::EMMMessage(emm_msg) => match emm_msg {
                 EMMMessage::EMMIdentityRequest(_) => true, // Alert on all identity requests (IMSI, IMEI, IMEISV)
@@ -87,6 +116,27 @@ impl DiagnosticAnalyzer {
             _ => false,
         }
     }
+
+    fn category(nas_msg: &NASMessage) -> Option<DiagnosticCategory> {
+        match nas_msg {
+            NASMessage::EMMMessage(EMMMessage::EMMIdentityRequest(_)) => {
+                Some(DiagnosticCategory::IdentityRequest)
+            }
+            NASMessage::EMMMessage(EMMMessage::EMMTrackingAreaUpdateReject(_)) => {
+                Some(DiagnosticCategory::TrackingAreaUpdateReject)
+            }
+            NASMessage::EMMMessage(EMMMessage::EMMAttachReject(_)) => {
+                Some(DiagnosticCategory::AttachReject)
+            }
+            NASMessage::EMMMessage(EMMMessage::EMMDetachRequestMT(_)) => {
+                Some(DiagnosticCategory::DetachRequest)
+            }
+            NASMessage::EMMMessage(EMMMessage::EMMServiceReject(_)) => {
+                Some(DiagnosticCategory::ServiceReject)
+            }
+            _ => None,
+        }
+    }
 }
 
 impl Analyzer for DiagnosticAnalyzer {
@@ -108,6 +158,14 @@ impl Analyzer for DiagnosticAnalyzer {
         1
     }
 
+    fn get_severities(&self) -> Vec<EventType> {
+        let mut severities: Vec<EventType> = self.severity_overrides.values().copied().collect();
+        severities.push(EventType::Informational);
+        severities.sort();
+        severities.dedup();
+        severities
+    }
+
     fn analyze_information_element(
         &mut self,
         ie: &InformationElement,
@@ -120,7 +178,9 @@ impl Analyzer for DiagnosticAnalyzer {
 
         match lte_ie.as_ref() {
             LteInformationElement::NAS(nas_msg) => {
-                if self.is_imsi_exposing_nas(nas_msg) {
+                if Self::is_imsi_exposing_nas(nas_msg)
+                    && let Some(category) = Self::category(nas_msg)
+                {
                     let message_type = match nas_msg {
                         NASMessage::EMMMessage(emm_msg) => match emm_msg {
                             EMMMessage::EMMIdentityRequest(request) => {
@@ -152,10 +212,12 @@ impl Analyzer for DiagnosticAnalyzer {
                         _ => "Unknown NAS Message".to_string(),
                     };
 
-                    Some(Event {
-                        event_type: EventType::Informational,
-                        message: format!("Diagnostic: {message_type}."),
-                    })
+                    Some(Event::new(
+                        self.severity(category),
+                        EventDetails::new("diagnostic_analyzer", "diagnostic")
+                            .with("message_type", message_type)
+                            .with("category", format!("{category:?}")),
+                    ))
                 } else {
                     None
                 }
@@ -164,3 +226,56 @@ impl Analyzer for DiagnosticAnalyzer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::test_support::{ie_attach_reject, ie_identity_request};
+
+    fn event_type(analyzer: &mut DiagnosticAnalyzer, ie: &InformationElement) -> EventType {
+        analyzer
+            .analyze_information_element(ie, 1)
+            .expect("expected a diagnostic event")
+            .event_type
+    }
+
+    #[test]
+    fn test_default_severity_is_informational() {
+        let mut analyzer = DiagnosticAnalyzer::new();
+        assert_eq!(
+            event_type(&mut analyzer, &ie_identity_request()),
+            EventType::Informational
+        );
+        // EMM cause #3, illegal UE
+        assert_eq!(
+            event_type(&mut analyzer, &ie_attach_reject(3)),
+            EventType::Informational
+        );
+    }
+
+    #[test]
+    fn test_severity_override() {
+        let mut analyzer = DiagnosticAnalyzer::with_severity_overrides(BTreeMap::from([(
+            DiagnosticCategory::IdentityRequest,
+            EventType::Low,
+        )]));
+        assert_eq!(
+            event_type(&mut analyzer, &ie_identity_request()),
+            EventType::Low
+        );
+        assert_eq!(
+            event_type(&mut analyzer, &ie_attach_reject(3)),
+            EventType::Informational
+        );
+    }
+
+    #[test]
+    fn test_overrides_from_config() {
+        let config: BTreeMap<DiagnosticCategory, EventType> =
+            serde_json::from_str(r#"{"attach_reject": "Medium"}"#).unwrap();
+        assert_eq!(
+            config,
+            BTreeMap::from([(DiagnosticCategory::AttachReject, EventType::Medium)])
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Decodes every message in a recording into [InformationElementSummary]s,
+//! for debugging parsers and analyzers without going through Wireshark.
+
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+
+use super::information_element::{InformationElement, InformationElementSummary};
+use crate::diag::{MessagesContainer, Timestamp};
+use crate::gsmtap::GsmtapMessage;
+use crate::gsmtap_parser;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DecodedMessage {
+    pub packet_num: usize,
+    pub packet_timestamp: DateTime<FixedOffset>,
+    #[serde(flatten)]
+    pub summary: InformationElementSummary,
+}
+
+/// Numbers packets the same way [Harness](super::analyzer::Harness) does, so
+/// the packet numbers in a dump line up with the ones in an analysis report.
+#[derive(Default)]
+pub struct InformationElementDumper {
+    packet_num: usize,
+}
+
+impl InformationElementDumper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes every message in the container. Messages which can't be
+    /// parsed or decoded still use up a packet number, but are left out.
+    pub fn dump_qmdl_messages(&mut self, container: MessagesContainer) -> Vec<DecodedMessage> {
+        let mut decoded = Vec::new();
+        for maybe_qmdl_message in container.into_messages() {
+            self.packet_num += 1;
+            let Ok(qmdl_message) = maybe_qmdl_message else {
+                continue;
+            };
+            let Ok(Some((timestamp, gsmtap_msg))) = gsmtap_parser::parse(qmdl_message) else {
+                continue;
+            };
+            decoded.extend(decode_gsmtap_message(
+                self.packet_num,
+                &timestamp,
+                &gsmtap_msg,
+            ));
+        }
+        decoded
+    }
+}
+
+fn decode_gsmtap_message(
+    packet_num: usize,
+    timestamp: &Timestamp,
+    gsmtap_msg: &GsmtapMessage,
+) -> Option<DecodedMessage> {
+    let element = InformationElement::try_from(gsmtap_msg).ok()?;
+    Some(DecodedMessage {
+        packet_num,
+        packet_timestamp: timestamp.to_datetime(),
+        summary: element.summary(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gsmtap::{GsmtapHeader, GsmtapType, UmtsRrcSubtype};
+    use crate::umts_rrc::tests::r3_security_mode_command;
+
+    fn gsmtap_message(gsmtap_type: GsmtapType, payload: Vec<u8>) -> GsmtapMessage {
+        GsmtapMessage {
+            header: GsmtapHeader::new(gsmtap_type),
+            payload,
+        }
+    }
+
+    #[test]
+    fn test_one_object_per_decoded_message() {
+        let timestamp = Timestamp { ts: 0 };
+        let messages = [
+            gsmtap_message(
+                GsmtapType::UmtsRrc(UmtsRrcSubtype::DlDcch),
+                r3_security_mode_command("1"),
+            ),
+            // not a type we decode
+            gsmtap_message(GsmtapType::Abis, vec![0; 4]),
+        ];
+        let lines: Vec<String> = messages
+            .iter()
+            .enumerate()
+            .filter_map(|(i, msg)| decode_gsmtap_message(i + 1, &timestamp, msg))
+            .map(|decoded| serde_json::to_string(&decoded).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 1);
+
+        let json: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(json["packet_num"], 1);
+        assert_eq!(json["protocol"], "UMTS RRC");
+        assert_eq!(json["channel"], "DL-DCCH");
+        assert_eq!(json["message_type"], "SecurityModeCommand");
+        assert!(json["packet_timestamp"].is_string());
+    }
+}
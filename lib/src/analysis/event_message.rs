@@ -0,0 +1,581 @@
+//! Rendering [Event]s into text. Analyzers describe what they found as
+//! [EventDetails], and an [EventFormatter] turns that into a message as long
+//! as the channel it's sent over allows, in whichever language its
+//! [StringTable] provides. Every event also keeps a rendering in English, as
+//! its `message`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::analyzer::Event;
+
+/// How much room a channel has for an event's message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageLength {
+    /// A few words, e.g. for a device's display
+    Short,
+    /// A sentence which fits in a small radio payload
+    Medium,
+    /// The full message, e.g. for reports and push notifications
+    Long,
+}
+
+impl MessageLength {
+    pub const ALL: [MessageLength; 3] = [
+        MessageLength::Short,
+        MessageLength::Medium,
+        MessageLength::Long,
+    ];
+
+    /// The most bytes a message of this length may take up, if limited
+    pub fn max_bytes(self) -> Option<usize> {
+        match self {
+            MessageLength::Short => Some(32),
+            MessageLength::Medium => Some(180),
+            MessageLength::Long => None,
+        }
+    }
+}
+
+/// A value filled into a message template
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum FieldValue {
+    Number(i64),
+    Text(String),
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldValue::Number(number) => write!(f, "{number}"),
+            FieldValue::Text(text) => f.write_str(text),
+        }
+    }
+}
+
+macro_rules! number_field {
+    ($($ty:ty),*) => {$(
+        impl From<$ty> for FieldValue {
+            fn from(number: $ty) -> Self {
+                FieldValue::Number(number.into())
+            }
+        }
+    )*};
+}
+
+number_field!(u8, u16, u32, i32, i64);
+
+impl From<String> for FieldValue {
+    fn from(text: String) -> Self {
+        FieldValue::Text(text)
+    }
+}
+
+impl From<&str> for FieldValue {
+    fn from(text: &str) -> Self {
+        FieldValue::Text(text.to_string())
+    }
+}
+
+/// What an [Event] is about, independently of how it's worded
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EventDetails {
+    /// The analyzer which emitted the event, named as in
+    /// [AnalyzerConfig](super::analyzer::AnalyzerConfig), e.g.
+    /// "sfn_continuity"
+    pub analyzer: String,
+    /// What the analyzer found, e.g. "backwards". This is unique to the
+    /// analyzer, and picks the message template along with it.
+    pub code: String,
+    /// Values to fill into the message template, e.g. {"frames": 12}
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, FieldValue>,
+}
+
+impl EventDetails {
+    pub fn new(analyzer: &str, code: &str) -> Self {
+        EventDetails {
+            analyzer: analyzer.to_string(),
+            code: code.to_string(),
+            fields: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a field, which replaces `{name}` in the message template.
+    pub fn with(mut self, name: &str, value: impl Into<FieldValue>) -> Self {
+        self.fields.insert(name.to_string(), value.into());
+        self
+    }
+}
+
+/// Message templates for one language. A template is text with a field's
+/// name in braces wherever its value goes, e.g. "SFN jumped {frames} frames".
+/// Translations implement this, and can leave out any template they don't
+/// have yet.
+pub trait StringTable: Send + Sync {
+    /// The template for an event, or None to use the English one.
+    fn template(&self, analyzer: &str, code: &str, length: MessageLength) -> Option<&str>;
+}
+
+/// The English templates for every event the built-in analyzers emit, as
+/// (analyzer, code, [short, medium, long]).
+const ENGLISH: &[(&str, &str, [&str; 3])] = &[
+    (
+        "connection_redirect_2g_downgrade",
+        "geran_redirect",
+        [
+            "2G redirect",
+            "Connection released with a redirect to 2G",
+            "Detected 2G downgrade",
+        ],
+    ),
+    (
+        "connection_redirect_2g_downgrade",
+        "redirect",
+        [
+            "Redirect",
+            "Connection released with a redirect to {carrier_info}",
+            "RRCConnectionRelease CarrierInfo: {carrier_info}",
+        ],
+    ),
+    (
+        "diagnostic_analyzer",
+        "diagnostic",
+        [
+            "{message_type}",
+            "Diagnostic: {message_type}.",
+            "Diagnostic: {message_type}.",
+        ],
+    ),
+    (
+        "fault_injection",
+        "injected",
+        ["{message}", "{message}", "{message}"],
+    ),
+    (
+        "imsi_requested",
+        "identity_after_auth",
+        [
+            "Identity requested",
+            "Identity requested after auth request",
+            "Identity requested after auth request",
+        ],
+    ),
+    (
+        "imsi_requested",
+        "identity_without_attach",
+        [
+            "Identity requested",
+            "Identity requested without Attach Request",
+            "Identity requested without Attach Request",
+        ],
+    ),
+    (
+        "imsi_requested",
+        "disconnect_without_auth_accept",
+        [
+            "Identity requested",
+            "Disconnected after Identity Request without Auth Accept",
+            "Disconnected after Identity Request without Auth Accept",
+        ],
+    ),
+    (
+        "imsi_requested",
+        "identity_without_auth",
+        [
+            "Identity requested",
+            "Identity request without auth request followup",
+            "Identity request happened without auth request followup",
+        ],
+    ),
+    (
+        "incomplete_sib",
+        "malformed_scheduling_info",
+        [
+            "Malformed SIB1",
+            "SIB1 scheduling info list was malformed",
+            "SIB1 scheduling info list was malformed",
+        ],
+    ),
+    (
+        "lte_sib6_and_7_downgrade",
+        "no_lte_neighbors",
+        [
+            "No LTE neighbors",
+            "LTE cell advertised 3G/2G neighbors but no LTE neighbors",
+            "LTE cell advertised a legacy (3G/2G) neighbors but no LTE neighbors",
+        ],
+    ),
+    (
+        "lte_sib6_and_7_downgrade",
+        "legacy_priority",
+        [
+            "2G/3G priority",
+            "LTE cell prefers 3G/2G (priority {legacy_priority}) over LTE (priority {lte_priority})",
+            "LTE cell advertised a legacy (3G/2G) cell for priority {legacy_priority} reselection over LTE neighbors at priority {lte_priority}",
+        ],
+    ),
+    (
+        "nas_integrity_downgrade",
+        "eia0",
+        [
+            "No NAS integrity",
+            "NAS Security Mode Command selected no integrity protection (EIA0)",
+            "NAS Security Mode Command selected no integrity protection (EIA0)",
+        ],
+    ),
+    (
+        "nas_integrity_downgrade",
+        "downgrade",
+        [
+            "NAS downgrade",
+            "NAS security on {plmn} downgraded to {downgrades}",
+            "NAS Security Mode Command on {plmn} downgraded to {downgrades}",
+        ],
+    ),
+    (
+        "nas_null_cipher",
+        "null_cipher",
+        [
+            "NAS null cipher",
+            "NAS Security mode command requested null cipher",
+            "NAS Security mode command requested null cipher",
+        ],
+    ),
+    (
+        "null_cipher",
+        "null_cipher",
+        [
+            "Null cipher",
+            "Cell suggested use of null cipher",
+            "Cell suggested use of null cipher",
+        ],
+    ),
+    (
+        "otdoa_request",
+        "unsolicited",
+        [
+            "Location requested",
+            "Network requested location outside an emergency call",
+            "Network requested the device's location (LPP) outside of an emergency call",
+        ],
+    ),
+    (
+        "service_request_flood",
+        "flood",
+        [
+            "{requests} service requests",
+            "Device sent {requests} service requests within {window_secs} seconds",
+            "Device sent {requests} service requests within {window_secs} seconds, each exposing its TMSI, which may mean something is forcing it to reconnect over and over",
+        ],
+    ),
+    (
+        "sfn_continuity",
+        "backwards",
+        [
+            "SFN jumped back",
+            "MIB SFN {sfn} jumped {frames} frames back from {expected}",
+            "MIB SFN {sfn} jumped {frames} frames backwards from the expected SFN {expected}",
+        ],
+    ),
+    (
+        "sfn_continuity",
+        "deviation",
+        [
+            "SFN off",
+            "MIB SFN {sfn} was {frames} frames off from {expected}",
+            "MIB SFN {sfn} was {frames} frames off from the expected SFN {expected}",
+        ],
+    ),
+    (
+        "sib3_reselection_anomaly",
+        "s_intra_search_zero",
+        [
+            "SIB3 s-IntraSearch 0",
+            "Cell's SIB3 set s-IntraSearch to 0dB",
+            "Cell's SIB3 set s-IntraSearch to 0dB, which keeps the device from looking for better cells on the same frequency",
+        ],
+    ),
+    (
+        "sib3_reselection_anomaly",
+        "thresh_serving_low",
+        [
+            "SIB3 threshold {thresh_serving_low_db}dB",
+            "Cell's SIB3 set threshServingLow to {thresh_serving_low_db}dB",
+            "Cell's SIB3 set threshServingLow to {thresh_serving_low_db}dB, which pushes the device to reselect away from it",
+        ],
+    ),
+    (
+        "sib_neighbor_list",
+        "empty",
+        [
+            "No neighbor cells",
+            "Cell advertised no neighbor cells in {periods} SIB4/SIB5 broadcasts in a row",
+            "Cell advertised no neighbor cells in {periods} SIB4/SIB5 broadcasts in a row, while another cell in this recording advertised {neighbors}",
+        ],
+    ),
+    (
+        "sib_neighbor_list",
+        "empty_with_imsi_exposure",
+        [
+            "No neighbors, IMSI exposed",
+            "Cell advertising no neighbor cells sent a message which may expose the IMSI",
+            "Cell advertised no neighbor cells in {periods} SIB4/SIB5 broadcasts in a row, while another cell in this recording advertised {neighbors}, and sent a message which may expose the IMSI",
+        ],
+    ),
+    (
+        "test_analyzer",
+        "sib1",
+        [
+            "SIB1 {plmn}",
+            "SIB1 CID: {cid}, TAC: {tac}, PLMN: {plmn}",
+            "SIB1 received CID: {cid}, TAC: {tac}, PLMN: {plmn}",
+        ],
+    ),
+    (
+        "wcdma_null_cipher",
+        "uea0",
+        [
+            "UMTS null cipher",
+            "UMTS RRC security mode command requested null cipher (UEA0)",
+            "UMTS RRC security mode command requested null cipher (UEA0)",
+        ],
+    ),
+    (
+        "wcdma_null_cipher",
+        "unprotected",
+        [
+            "UMTS no integrity",
+            "UMTS RRC security mode command was not integrity protected",
+            "UMTS RRC security mode command was not integrity protected",
+        ],
+    ),
+];
+
+/// The templates the built-in analyzers' events are written with
+pub struct English;
+
+impl StringTable for English {
+    fn template(&self, analyzer: &str, code: &str, length: MessageLength) -> Option<&str> {
+        let index = MessageLength::ALL.iter().position(|l| *l == length)?;
+        ENGLISH
+            .iter()
+            .find(|(a, c, _)| *a == analyzer && *c == code)
+            .map(|(_, _, templates)| templates[index])
+    }
+}
+
+/// Replaces each `{name}` in the template with the value of that field.
+/// Placeholders without a field are left as they are.
+fn fill(template: &str, fields: &BTreeMap<String, FieldValue>) -> String {
+    let mut text = template.to_string();
+    for (name, value) in fields {
+        text = text.replace(&format!("{{{name}}}"), &value.to_string());
+    }
+    text
+}
+
+/// Shortens the text to at most `max_bytes`, ending it with an ellipsis if
+/// anything was cut.
+fn truncate(mut text: String, max_bytes: usize) -> String {
+    const ELLIPSIS: char = '…';
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let mut end = max_bytes.saturating_sub(ELLIPSIS.len_utf8());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    text.push(ELLIPSIS);
+    text
+}
+
+/// The English rendering of the details at [MessageLength::Long], which
+/// becomes an event's `message`.
+pub(crate) fn english_message(details: &EventDetails) -> String {
+    match English.template(&details.analyzer, &details.code, MessageLength::Long) {
+        Some(template) => fill(template, &details.fields),
+        None => format!("{}: {}", details.analyzer, details.code),
+    }
+}
+
+/// Renders events' messages from a [StringTable], falling back to English
+/// for any template it doesn't have.
+pub struct EventFormatter {
+    table: Box<dyn StringTable>,
+}
+
+impl Default for EventFormatter {
+    fn default() -> Self {
+        EventFormatter::new(Box::new(English))
+    }
+}
+
+impl EventFormatter {
+    pub fn new(table: Box<dyn StringTable>) -> Self {
+        EventFormatter { table }
+    }
+
+    /// Renders the event's message, no longer than `length` allows. Events
+    /// without details, or whose template no table has, use their `message`.
+    pub fn render(&self, event: &Event, length: MessageLength) -> String {
+        let text = event
+            .details
+            .as_ref()
+            .and_then(|details| {
+                let template = self
+                    .table
+                    .template(&details.analyzer, &details.code, length)
+                    .or_else(|| English.template(&details.analyzer, &details.code, length))?;
+                Some(fill(template, &details.fields))
+            })
+            .unwrap_or_else(|| event.message.clone());
+        match length.max_bytes() {
+            Some(max_bytes) => truncate(text, max_bytes),
+            None => text,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::analyzer::EventType;
+
+    /// Details for every built-in event, with plausible field values
+    fn every_event() -> Vec<EventDetails> {
+        ENGLISH
+            .iter()
+            .map(|(analyzer, code, _)| {
+                let details = EventDetails::new(analyzer, code);
+                match (*analyzer, *code) {
+                    ("connection_redirect_2g_downgrade", "redirect") => {
+                        details.with("carrier_info", "Utra_FDD(1234)")
+                    }
+                    ("diagnostic_analyzer", _) => details
+                        .with("category", "AttachReject")
+                        .with("message_type", "EMM Attach Reject (IllegalUE)"),
+                    ("fault_injection", _) => details.with("message", "Injected fault"),
+                    ("lte_sib6_and_7_downgrade", "legacy_priority") => details
+                        .with("legacy_priority", 7u8)
+                        .with("lte_priority", 2u8),
+                    ("nas_integrity_downgrade", "downgrade") => details
+                        .with("plmn", "310-260")
+                        .with("downgrades", "EIA1 after EIA2"),
+                    ("service_request_flood", _) => {
+                        details.with("requests", 16u32).with("window_secs", 60i64)
+                    }
+                    ("sfn_continuity", _) => details
+                        .with("sfn", 12i64)
+                        .with("frames", 500i64)
+                        .with("expected", 512i64),
+                    ("sib3_reselection_anomaly", "thresh_serving_low") => {
+                        details.with("thresh_serving_low_db", 62u8)
+                    }
+                    ("sib_neighbor_list", _) => {
+                        details.with("periods", 3u32).with("neighbors", 7u32)
+                    }
+                    ("test_analyzer", _) => details
+                        .with("cid", 12345u32)
+                        .with("tac", 678u32)
+                        .with("plmn", "310-260"),
+                    _ => details,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_every_event_renders_at_every_length() {
+        let formatter = EventFormatter::default();
+        for details in every_event() {
+            let event = Event::new(EventType::High, details.clone());
+            for length in MessageLength::ALL {
+                let text = formatter.render(&event, length);
+                assert!(!text.is_empty(), "{details:?} at {length:?}");
+                assert!(!text.contains('{'), "{details:?} at {length:?}: {text}");
+                if let Some(max_bytes) = length.max_bytes() {
+                    assert!(text.len() <= max_bytes, "{details:?} at {length:?}: {text}");
+                }
+            }
+            assert_eq!(formatter.render(&event, MessageLength::Long), event.message);
+        }
+    }
+
+    #[test]
+    fn test_renders_fields() {
+        let event = Event::new(
+            EventType::High,
+            EventDetails::new("sfn_continuity", "backwards")
+                .with("sfn", 12i64)
+                .with("frames", 500i64)
+                .with("expected", 512i64),
+        );
+        assert_eq!(
+            event.message,
+            "MIB SFN 12 jumped 500 frames backwards from the expected SFN 512"
+        );
+        let formatter = EventFormatter::default();
+        assert_eq!(
+            formatter.render(&event, MessageLength::Medium),
+            "MIB SFN 12 jumped 500 frames back from 512"
+        );
+        assert_eq!(
+            formatter.render(&event, MessageLength::Short),
+            "SFN jumped back"
+        );
+    }
+
+    #[test]
+    fn test_medium_never_exceeds_cap() {
+        let formatter = EventFormatter::default();
+        let event = Event::new(
+            EventType::Informational,
+            EventDetails::new("connection_redirect_2g_downgrade", "redirect")
+                .with("carrier_info", "é".repeat(500)),
+        );
+        let medium = formatter.render(&event, MessageLength::Medium);
+        assert!(medium.len() <= MessageLength::Medium.max_bytes().unwrap());
+        assert!(medium.ends_with('…'));
+        assert!(event.message.len() > 500);
+    }
+
+    #[test]
+    fn test_falls_back_to_english_then_message() {
+        struct Pirate;
+        impl StringTable for Pirate {
+            fn template(&self, analyzer: &str, _code: &str, length: MessageLength) -> Option<&str> {
+                (analyzer == "null_cipher" && length == MessageLength::Long)
+                    .then_some("Arr, the cell be wantin' no cipher")
+            }
+        }
+        let formatter = EventFormatter::new(Box::new(Pirate));
+        let event = Event::new(
+            EventType::High,
+            EventDetails::new("null_cipher", "null_cipher"),
+        );
+        assert_eq!(
+            formatter.render(&event, MessageLength::Long),
+            "Arr, the cell be wantin' no cipher"
+        );
+        assert_eq!(
+            formatter.render(&event, MessageLength::Short),
+            "Null cipher"
+        );
+
+        let without_details = Event {
+            event_type: EventType::Low,
+            analyzer: String::new(),
+            message: "something happened".to_string(),
+            details: None,
+        };
+        assert_eq!(
+            formatter.render(&without_details, MessageLength::Medium),
+            "something happened"
+        );
+    }
+}
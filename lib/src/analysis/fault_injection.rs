@@ -0,0 +1,132 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use super::analyzer::{Analyzer, Event, EventType};
+use super::event_message::EventDetails;
+use super::information_element::InformationElement;
+
+/// Settings for [FaultInjectionAnalyzer]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+#[non_exhaustive]
+pub struct FaultInjectionConfig {
+    pub enabled: bool,
+    /// Emit an event on every this many-th message. 0 never emits any.
+    pub every_messages: usize,
+    pub event_type: EventType,
+    pub message: String,
+}
+
+impl Default for FaultInjectionConfig {
+    fn default() -> Self {
+        FaultInjectionConfig {
+            enabled: false,
+            every_messages: 100,
+            event_type: EventType::Low,
+            message: "Injected fault".to_string(),
+        }
+    }
+}
+
+/// Emits the same configured event on every K-th message it sees, whatever
+/// the message is, so the rest of the pipeline can be tested against any
+/// recording without needing one of a real attack.
+pub struct FaultInjectionAnalyzer {
+    config: FaultInjectionConfig,
+    messages_seen: usize,
+}
+
+impl FaultInjectionAnalyzer {
+    pub fn new(config: FaultInjectionConfig) -> Self {
+        FaultInjectionAnalyzer {
+            config,
+            messages_seen: 0,
+        }
+    }
+}
+
+impl Analyzer for FaultInjectionAnalyzer {
+    fn get_name(&self) -> Cow<'_, str> {
+        Cow::from("Fault Injection")
+    }
+
+    fn get_description(&self) -> Cow<'_, str> {
+        Cow::from(format!(
+            "Emits a {:?} severity event every {} messages, regardless of what they contain. This is only meant for testing notifications and the display, and every event it emits is fake.",
+            self.config.event_type, self.config.every_messages
+        ))
+    }
+
+    fn get_version(&self) -> u32 {
+        1
+    }
+
+    fn get_severities(&self) -> Vec<EventType> {
+        vec![self.config.event_type]
+    }
+
+    fn reset(&mut self) {
+        self.messages_seen = 0;
+    }
+
+    fn analyze_information_element(
+        &mut self,
+        _ie: &InformationElement,
+        _packet_num: usize,
+    ) -> Option<Event> {
+        self.messages_seen += 1;
+        let every = self.config.every_messages;
+        if every == 0 || !self.messages_seen.is_multiple_of(every) {
+            return None;
+        }
+        Some(Event::new(
+            self.config.event_type,
+            EventDetails::new("fault_injection", "injected")
+                .with("message", self.config.message.as_str()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fires_every_k_messages() {
+        let mut analyzer = FaultInjectionAnalyzer::new(FaultInjectionConfig {
+            enabled: true,
+            every_messages: 3,
+            event_type: EventType::High,
+            message: "boom".to_string(),
+        });
+        let fired: Vec<usize> = (1..=10)
+            .filter(|packet_num| {
+                analyzer
+                    .analyze_information_element(&InformationElement::GSM, *packet_num)
+                    .inspect(|event| {
+                        assert_eq!(event.event_type, EventType::High);
+                        assert_eq!(event.message, "boom");
+                    })
+                    .is_some()
+            })
+            .collect();
+        assert_eq!(fired, vec![3, 6, 9]);
+    }
+
+    #[test]
+    fn test_zero_period_never_fires() {
+        let mut analyzer = FaultInjectionAnalyzer::new(FaultInjectionConfig {
+            every_messages: 0,
+            ..Default::default()
+        });
+        for packet_num in 1..=10 {
+            assert!(
+                analyzer
+                    .analyze_information_element(&InformationElement::GSM, packet_num)
+                    .is_none()
+            );
+        }
+    }
+}
@@ -4,6 +4,7 @@ use pycrate_rs::nas::NASMessage;
 use pycrate_rs::nas::emm::EMMMessage;
 
 use super::analyzer::{Analyzer, Event, EventType};
+use super::event_message::EventDetails;
 use super::information_element::{InformationElement, LteInformationElement};
 use log::debug;
 
@@ -56,26 +57,26 @@ impl ImsiRequestedAnalyzer {
 
             // IMSI or IMEI requested after auth accept
             (State::AuthAccept, State::IdentityRequest) => {
-                self.flag = Some(Event {
-                    event_type: EventType::High,
-                    message: "Identity requested after auth request".to_string(),
-                });
+                self.flag = Some(Event::new(
+                    EventType::High,
+                    EventDetails::new("imsi_requested", "identity_after_auth"),
+                ));
             }
 
             // Unexpected IMSI without AttachRequest
             (State::Disconnect, State::IdentityRequest) => {
-                self.flag = Some(Event {
-                    event_type: EventType::High,
-                    message: "Identity requested without Attach Request".to_string(),
-                });
+                self.flag = Some(Event::new(
+                    EventType::High,
+                    EventDetails::new("imsi_requested", "identity_without_attach"),
+                ));
             }
 
             // IMSI to Disconnect without AuthAccept
             (State::IdentityRequest, State::Disconnect) => {
-                self.flag = Some(Event {
-                    event_type: EventType::High,
-                    message: "Disconnected after Identity Request without Auth Accept".to_string(),
-                });
+                self.flag = Some(Event::new(
+                    EventType::High,
+                    EventDetails::new("imsi_requested", "disconnect_without_auth_accept"),
+                ));
             }
 
             (_, State::IdentityRequest) => {
@@ -109,6 +110,14 @@ impl Analyzer for ImsiRequestedAnalyzer {
         3
     }
 
+    fn get_severities(&self) -> Vec<EventType> {
+        vec![EventType::Informational, EventType::High]
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
     fn analyze_information_element(
         &mut self,
         ie: &InformationElement,
@@ -167,10 +176,10 @@ impl Analyzer for ImsiRequestedAnalyzer {
                 self.timeout_counter, packet_num
             );
             if self.timeout_counter >= TIMEOUT_THRESHHOLD {
-                self.flag = Some(Event {
-                    event_type: EventType::Informational {},
-                    message: "Identity request happened without auth request followup".to_string(),
-                });
+                self.flag = Some(Event::new(
+                    EventType::Informational,
+                    EventDetails::new("imsi_requested", "identity_without_auth"),
+                ));
                 self.timeout_counter = 0;
             }
         }
@@ -178,3 +187,78 @@ impl Analyzer for ImsiRequestedAnalyzer {
         self.flag.take()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::test_support::{
+        ie_attach_reject, ie_authentication_response, ie_identity_request,
+        ie_rrc_connection_release,
+    };
+
+    fn event_type(event: Option<Event>) -> Option<EventType> {
+        event.map(|event| event.event_type)
+    }
+
+    #[test]
+    fn test_identity_request_after_auth_is_high() {
+        let mut analyzer = ImsiRequestedAnalyzer::new();
+        assert!(
+            analyzer
+                .analyze_information_element(&ie_authentication_response(), 1)
+                .is_none()
+        );
+        assert_eq!(
+            event_type(analyzer.analyze_information_element(&ie_identity_request(), 2)),
+            Some(EventType::High)
+        );
+    }
+
+    #[test]
+    fn test_identity_request_after_reject_is_high() {
+        let mut analyzer = ImsiRequestedAnalyzer::new();
+        // EMM cause #3, illegal UE
+        assert!(
+            analyzer
+                .analyze_information_element(&ie_attach_reject(3), 1)
+                .is_none()
+        );
+        assert_eq!(
+            event_type(analyzer.analyze_information_element(&ie_identity_request(), 2)),
+            Some(EventType::High)
+        );
+    }
+
+    #[test]
+    fn test_release_after_identity_request_is_high() {
+        let mut analyzer = ImsiRequestedAnalyzer::new();
+        assert!(
+            analyzer
+                .analyze_information_element(&ie_identity_request(), 1)
+                .is_none()
+        );
+        assert_eq!(
+            event_type(analyzer.analyze_information_element(&ie_rrc_connection_release(), 2)),
+            Some(EventType::High)
+        );
+    }
+
+    #[test]
+    fn test_unanswered_identity_request_times_out() {
+        let mut analyzer = ImsiRequestedAnalyzer::new();
+        analyzer.analyze_information_element(&ie_identity_request(), 1);
+        for packet_num in 2..TIMEOUT_THRESHHOLD {
+            assert!(
+                analyzer
+                    .analyze_information_element(&InformationElement::GSM, packet_num)
+                    .is_none()
+            );
+        }
+        assert_eq!(
+            event_type(
+                analyzer.analyze_information_element(&InformationElement::GSM, TIMEOUT_THRESHHOLD)
+            ),
+            Some(EventType::Informational)
+        );
+    }
+}
@@ -3,6 +3,7 @@ use std::borrow::Cow;
 use telcom_parser::lte_rrc::{BCCH_DL_SCH_MessageType, BCCH_DL_SCH_MessageType_c1};
 
 use super::analyzer::{Analyzer, Event, EventType};
+use super::event_message::EventDetails;
 use super::information_element::{InformationElement, LteInformationElement};
 
 pub struct IncompleteSibAnalyzer {}
@@ -20,6 +21,10 @@ impl Analyzer for IncompleteSibAnalyzer {
         2
     }
 
+    fn get_severities(&self) -> Vec<EventType> {
+        vec![EventType::Informational]
+    }
+
     fn analyze_information_element(
         &mut self,
         ie: &InformationElement,
@@ -31,10 +36,10 @@ impl Analyzer for IncompleteSibAnalyzer {
             && let BCCH_DL_SCH_MessageType_c1::SystemInformationBlockType1(sib1) = c1
             && sib1.scheduling_info_list.0.len() < 2
         {
-            return Some(Event {
-                event_type: EventType::Informational,
-                message: "SIB1 scheduling info list was malformed".to_string(),
-            });
+            return Some(Event::new(
+                EventType::Informational,
+                EventDetails::new("incomplete_sib", "malformed_scheduling_info"),
+            ));
         }
         None
     }
@@ -3,25 +3,31 @@
 //! the term to refer to a structured, fully parsed message in any telcom
 //! standard.
 
-use crate::gsmtap::{GsmtapMessage, GsmtapType, LteNasSubtype, LteRrcSubtype};
+use crate::gsmtap::{GsmtapMessage, GsmtapType, LteNasSubtype, LteRrcSubtype, UmtsRrcSubtype};
+use crate::umts_rrc;
 use pycrate_rs::nas::NASMessage;
+use serde::Serialize;
 use telcom_parser::{decode, lte_rrc};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum InformationElementError {
     #[error("Failed decoding RRC message")]
     RRCDecodingError(#[from] telcom_parser::ParsingError),
     #[error("Failed decoding NAS message")]
     NASDecodingError(#[from] pycrate_rs::nas::ParseError),
+    #[error("Failed decoding UMTS RRC message")]
+    UmtsRrcDecodingError(#[from] umts_rrc::UmtsRrcError),
     #[error("Unsupported LTE RRC subtype {0:?}")]
     UnsupportedGsmtapType(GsmtapType),
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum InformationElement {
     GSM,
-    UMTS,
+    UMTS(UmtsInformationElement),
     // This element of the enum is substantially larger than the others,
     // so we box it to prevent the size of the enum (any variant) from blowing up.
     LTE(Box<LteInformationElement>),
@@ -29,6 +35,13 @@ pub enum InformationElement {
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
+pub enum UmtsInformationElement {
+    DlDcch(umts_rrc::DlDcchMessage),
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
 pub enum LteInformationElement {
     DlCcch(lte_rrc::DL_CCCH_Message),
     // This element of the enum is substantially larger than the others,
@@ -91,6 +104,12 @@ impl TryFrom<&GsmtapMessage> for InformationElement {
                 };
                 Ok(InformationElement::LTE(Box::new(lte)))
             }
+            GsmtapType::UmtsRrc(UmtsRrcSubtype::DlDcch) => {
+                let msg = umts_rrc::decode_dl_dcch(&gsmtap_msg.payload)?;
+                Ok(InformationElement::UMTS(UmtsInformationElement::DlDcch(
+                    msg,
+                )))
+            }
             GsmtapType::LteNas(LteNasSubtype::Plain) => {
                 let msg = NASMessage::parse(&gsmtap_msg.payload)?;
                 Ok(InformationElement::LTE(Box::new(
@@ -103,3 +122,111 @@ impl TryFrom<&GsmtapMessage> for InformationElement {
         }
     }
 }
+
+/// A flattened, serializable view of an [InformationElement]. The generated
+/// ASN.1 and NAS types aren't serializable, so the decoded message is carried
+/// as its Debug representation rather than as structured JSON.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InformationElementSummary {
+    /// e.g. "LTE RRC", "LTE NAS" or "UMTS RRC"
+    pub protocol: &'static str,
+    /// The logical channel the message was sent on, for RRC messages
+    pub channel: Option<&'static str>,
+    /// The name of the innermost message type, e.g. "RRCConnectionRelease"
+    pub message_type: Option<String>,
+    /// The full decoded message
+    pub decoded: Option<String>,
+}
+
+impl InformationElementSummary {
+    fn new(protocol: &'static str, channel: Option<&'static str>, decoded: String) -> Self {
+        InformationElementSummary {
+            protocol,
+            channel,
+            message_type: Some(innermost_type_name(&decoded).to_string()),
+            decoded: Some(decoded),
+        }
+    }
+
+    fn lte_rrc<T: std::fmt::Debug>(channel: &'static str, message: &T) -> Self {
+        Self::new("LTE RRC", Some(channel), format!("{message:?}"))
+    }
+}
+
+/// Follows the chain of enum variants and newtype wrappers at the start of a
+/// Debug representation, e.g. `C1(RrcConnectionRelease(RRCConnectionRelease
+/// { .. }))`, and returns the innermost name.
+fn innermost_type_name(debug: &str) -> &str {
+    let mut rest = debug;
+    let mut name = "";
+    while rest.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        let end = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        name = &rest[..end];
+        match rest[end..].strip_prefix('(') {
+            Some(inner) => rest = inner,
+            None => break,
+        }
+    }
+    name
+}
+
+impl InformationElement {
+    pub fn summary(&self) -> InformationElementSummary {
+        match self {
+            InformationElement::GSM => InformationElementSummary {
+                protocol: "GSM",
+                channel: None,
+                message_type: None,
+                decoded: None,
+            },
+            InformationElement::FiveG => InformationElementSummary {
+                protocol: "5G",
+                channel: None,
+                message_type: None,
+                decoded: None,
+            },
+            InformationElement::UMTS(UmtsInformationElement::DlDcch(msg)) => {
+                InformationElementSummary::new("UMTS RRC", Some("DL-DCCH"), format!("{msg:?}"))
+            }
+            InformationElement::LTE(lte) => {
+                use InformationElementSummary as S;
+                use LteInformationElement as R;
+                match lte.as_ref() {
+                    R::DlCcch(msg) => S::lte_rrc("DL-CCCH", &msg.message),
+                    R::DlDcch(msg) => S::lte_rrc("DL-DCCH", &msg.message),
+                    R::UlCcch(msg) => S::lte_rrc("UL-CCCH", &msg.message),
+                    R::UlDcch(msg) => S::lte_rrc("UL-DCCH", &msg.message),
+                    R::BcchBch(msg) => S::lte_rrc("BCCH-BCH", &msg.message),
+                    R::BcchDlSch(msg) => S::lte_rrc("BCCH-DL-SCH", &msg.message),
+                    R::PCCH(msg) => S::lte_rrc("PCCH", &msg.message),
+                    R::MCCH(msg) => S::lte_rrc("MCCH", &msg.message),
+                    R::ScMcch(msg) => S::lte_rrc("SC-MCCH", &msg.message),
+                    R::BcchBchMbms(msg) => S::lte_rrc("BCCH-BCH-MBMS", &msg.message),
+                    R::BcchDlSchBr(msg) => S::lte_rrc("BCCH-DL-SCH-BR", &msg.message),
+                    R::BcchDlSchMbms(msg) => S::lte_rrc("BCCH-DL-SCH-MBMS", &msg.message),
+                    R::SbcchSlBch(msg) => S::lte_rrc("SBCCH-SL-BCH", &msg.message),
+                    R::SbcchSlBchV2x(msg) => S::lte_rrc("SBCCH-SL-BCH-V2X", &msg.message),
+                    R::NAS(msg) => S::new("LTE NAS", None, format!("{msg:?}")),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_innermost_type_name() {
+        assert_eq!(
+            innermost_type_name("C1(RrcConnectionRelease(RRCConnectionRelease { x: 1 }))"),
+            "RRCConnectionRelease"
+        );
+        assert_eq!(innermost_type_name("Other(5)"), "Other");
+        assert_eq!(innermost_type_name("Unit"), "Unit");
+        assert_eq!(innermost_type_name(""), "");
+    }
+}
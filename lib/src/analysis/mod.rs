@@ -1,11 +1,24 @@
 pub mod analyzer;
 pub mod connection_redirect_downgrade;
 pub mod diagnostic;
+pub mod dump;
+pub mod event_message;
+pub mod fault_injection;
 pub mod imsi_requested;
 pub mod incomplete_sib;
 pub mod information_element;
+pub mod nas_integrity_downgrade;
 pub mod nas_null_cipher;
 pub mod null_cipher;
+pub mod otdoa_request;
 pub mod priority_2g_downgrade;
+pub mod service_request_flood;
+pub mod sfn_continuity;
+pub mod sib3_reselection_anomaly;
+pub mod sib_neighbor_list;
 pub mod test_analyzer;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+#[doc(hidden)]
 pub mod util;
+pub mod wcdma_null_cipher;
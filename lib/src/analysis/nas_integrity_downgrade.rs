@@ -0,0 +1,237 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use pycrate_rs::nas::NASMessage;
+use pycrate_rs::nas::emm::EMMMessage;
+use pycrate_rs::nas::generated::emm::emm_attach_request::EPSAttachTypeV;
+
+use super::analyzer::{Analyzer, Event, EventType};
+use super::event_message::EventDetails;
+use super::information_element::InformationElement;
+use super::util::{
+    NasSecurityAlgorithms, lte_nas_message, nas_security_mode_command_algorithms, sib1_plmn,
+};
+
+/// The strongest algorithms seen selected on a PLMN so far. Algorithms are
+/// ranked by number, so EIA0 is the weakest, though 2 (AES) and 3 (ZUC) are
+/// equally strong.
+#[derive(Debug, Default, Clone, Copy)]
+struct BestSeen {
+    eea: Option<u8>,
+    eia: Option<u8>,
+}
+
+/// Tracks the algorithms selected by each NAS Security Mode Command in a
+/// recording, and flags one which selects weaker algorithms than the same
+/// PLMN has selected before, or no integrity protection at all. Only a move
+/// to the null algorithm, or from 2 or 3 down to 1 (SNOW 3G), counts as
+/// weaker, since networks legitimately switch between AES and ZUC.
+pub struct NasIntegrityDowngradeAnalyzer {
+    /// None until a SIB1 tells us which PLMN we're on
+    plmn: Option<String>,
+    best_seen: HashMap<Option<String>, BestSeen>,
+    emergency_attach: bool,
+}
+
+impl Default for NasIntegrityDowngradeAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NasIntegrityDowngradeAnalyzer {
+    pub fn new() -> Self {
+        NasIntegrityDowngradeAnalyzer {
+            plmn: None,
+            best_seen: HashMap::new(),
+            emergency_attach: false,
+        }
+    }
+
+    fn on_security_mode_command(&mut self, algorithms: NasSecurityAlgorithms) -> Option<Event> {
+        let best = self.best_seen.entry(self.plmn.clone()).or_default();
+        let previous = *best;
+        best.eea = best.eea.max(algorithms.eea);
+        best.eia = best.eia.max(algorithms.eia);
+
+        // an emergency attach is allowed to go without integrity protection
+        // (TS 33.401 15.2), so only a downgrade is worth flagging then
+        if algorithms.eia == Some(0) && !self.emergency_attach {
+            return Some(Event::new(
+                EventType::High,
+                EventDetails::new("nas_integrity_downgrade", "eia0"),
+            ));
+        }
+
+        let downgrade = |name: &str, selected: Option<u8>, best: Option<u8>| match (selected, best)
+        {
+            (Some(selected), Some(best)) if selected < best && selected <= 1 => {
+                Some(format!("{name}{selected} after {name}{best}"))
+            }
+            _ => None,
+        };
+        let downgrades: Vec<String> = [
+            downgrade("EIA", algorithms.eia, previous.eia),
+            downgrade("EEA", algorithms.eea, previous.eea),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if downgrades.is_empty() {
+            return None;
+        }
+        let plmn = self.plmn.as_deref().unwrap_or("unknown PLMN");
+        Some(Event::new(
+            EventType::Medium,
+            EventDetails::new("nas_integrity_downgrade", "downgrade")
+                .with("plmn", plmn)
+                .with("downgrades", downgrades.join(" and ")),
+        ))
+    }
+}
+
+impl Analyzer for NasIntegrityDowngradeAnalyzer {
+    fn get_name(&self) -> Cow<'_, str> {
+        Cow::from("NAS Security Algorithm Downgrade")
+    }
+
+    fn get_description(&self) -> Cow<'_, str> {
+        Cow::from(
+            "Tests whether a NAS Security Mode Command selects weaker integrity or ciphering algorithms than the same network selected earlier in the recording, i.e. the null algorithm or SNOW 3G after AES or ZUC (medium severity), or selects no integrity protection (EIA0) outside of an emergency attach (high severity).",
+        )
+    }
+
+    fn get_version(&self) -> u32 {
+        1
+    }
+
+    fn get_severities(&self) -> Vec<EventType> {
+        vec![EventType::Medium, EventType::High]
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    fn analyze_information_element(
+        &mut self,
+        ie: &InformationElement,
+        _packet_num: usize,
+    ) -> Option<Event> {
+        if let Some(plmn) = sib1_plmn(ie) {
+            self.plmn = Some(plmn);
+            return None;
+        }
+        if let Some(NASMessage::EMMMessage(EMMMessage::EMMAttachRequest(req))) = lte_nas_message(ie)
+        {
+            self.emergency_attach =
+                req.eps_attach_type.inner.v == EPSAttachTypeV::EPSEmergencyAttach;
+            return None;
+        }
+        let algorithms = nas_security_mode_command_algorithms(ie)?;
+        self.on_security_mode_command(algorithms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::test_support::{
+        EEA0, EEA2, EEA3, EIA0, EIA1, EIA2, EIA3, EPS_ATTACH, EPS_EMERGENCY_ATTACH,
+        ie_attach_request, ie_nas_security_mode_command_with_eia,
+    };
+
+    fn analyze(
+        analyzer: &mut NasIntegrityDowngradeAnalyzer,
+        ie: InformationElement,
+    ) -> Option<EventType> {
+        analyzer
+            .analyze_information_element(&ie, 1)
+            .map(|event| event.event_type)
+    }
+
+    fn smc(eea: u8, eia: u8) -> InformationElement {
+        ie_nas_security_mode_command_with_eia(eea, eia)
+    }
+
+    #[test]
+    fn test_stable_algorithms() {
+        let mut analyzer = NasIntegrityDowngradeAnalyzer::new();
+        for _ in 0..3 {
+            assert_eq!(analyze(&mut analyzer, smc(EEA2, EIA2)), None);
+        }
+    }
+
+    #[test]
+    fn test_improvement_is_not_flagged() {
+        let mut analyzer = NasIntegrityDowngradeAnalyzer::new();
+        assert_eq!(analyze(&mut analyzer, smc(EEA0, EIA1)), None);
+        assert_eq!(analyze(&mut analyzer, smc(EEA2, EIA2)), None);
+    }
+
+    #[test]
+    fn test_downgrade_is_medium() {
+        let mut analyzer = NasIntegrityDowngradeAnalyzer::new();
+        assert_eq!(analyze(&mut analyzer, smc(EEA2, EIA2)), None);
+        assert_eq!(
+            analyze(&mut analyzer, smc(EEA2, EIA1)),
+            Some(EventType::Medium)
+        );
+        // still a downgrade compared to the best seen, not the last seen
+        assert_eq!(
+            analyze(&mut analyzer, smc(EEA2, EIA1)),
+            Some(EventType::Medium)
+        );
+        assert_eq!(
+            analyze(&mut analyzer, smc(EEA0, EIA2)),
+            Some(EventType::Medium)
+        );
+    }
+
+    #[test]
+    fn test_switching_between_aes_and_zuc_is_not_flagged() {
+        let mut analyzer = NasIntegrityDowngradeAnalyzer::new();
+        assert_eq!(analyze(&mut analyzer, smc(EEA3, EIA3)), None);
+        assert_eq!(analyze(&mut analyzer, smc(EEA2, EIA2)), None);
+        assert_eq!(analyze(&mut analyzer, smc(EEA3, EIA2)), None);
+        assert_eq!(
+            analyze(&mut analyzer, smc(EEA3, EIA1)),
+            Some(EventType::Medium)
+        );
+        assert_eq!(
+            analyze(&mut analyzer, smc(EEA0, EIA3)),
+            Some(EventType::Medium)
+        );
+    }
+
+    #[test]
+    fn test_downgrade_is_per_plmn() {
+        let mut analyzer = NasIntegrityDowngradeAnalyzer::new();
+        analyzer.plmn = Some("001-01".to_string());
+        assert_eq!(analyze(&mut analyzer, smc(EEA2, EIA2)), None);
+        analyzer.plmn = Some("001-02".to_string());
+        assert_eq!(analyze(&mut analyzer, smc(EEA2, EIA1)), None);
+        analyzer.plmn = Some("001-01".to_string());
+        assert_eq!(
+            analyze(&mut analyzer, smc(EEA2, EIA1)),
+            Some(EventType::Medium)
+        );
+    }
+
+    #[test]
+    fn test_eia0_is_high_except_for_emergency_attach() {
+        let mut analyzer = NasIntegrityDowngradeAnalyzer::new();
+        assert_eq!(analyze(&mut analyzer, ie_attach_request(EPS_ATTACH)), None);
+        assert_eq!(
+            analyze(&mut analyzer, smc(EEA2, EIA0)),
+            Some(EventType::High)
+        );
+
+        let mut analyzer = NasIntegrityDowngradeAnalyzer::new();
+        assert_eq!(
+            analyze(&mut analyzer, ie_attach_request(EPS_EMERGENCY_ATTACH)),
+            None
+        );
+        assert_eq!(analyze(&mut analyzer, smc(EEA0, EIA0)), None);
+    }
+}
@@ -1,11 +1,9 @@
 use std::borrow::Cow;
 
-use pycrate_rs::nas::NASMessage;
-use pycrate_rs::nas::emm::EMMMessage;
-use pycrate_rs::nas::generated::emm::emm_security_mode_command::NASSecAlgoCiphAlgo::EPSEncryptionAlgorithmEEA0Null;
-
 use super::analyzer::{Analyzer, Event, EventType};
-use super::information_element::{InformationElement, LteInformationElement};
+use super::event_message::EventDetails;
+use super::information_element::InformationElement;
+use super::util::nas_security_mode_command_algorithms;
 
 pub struct NasNullCipherAnalyzer {}
 
@@ -24,27 +22,47 @@ impl Analyzer for NasNullCipherAnalyzer {
         1
     }
 
+    fn get_severities(&self) -> Vec<EventType> {
+        vec![EventType::High]
+    }
+
     fn analyze_information_element(
         &mut self,
         ie: &InformationElement,
         _packet_num: usize,
     ) -> Option<Event> {
-        let payload = match ie {
-            InformationElement::LTE(inner) => match &**inner {
-                LteInformationElement::NAS(payload) => payload,
-                _ => return None,
-            },
-            _ => return None,
-        };
-
-        if let NASMessage::EMMMessage(EMMMessage::EMMSecurityModeCommand(req)) = payload
-            && req.nas_sec_algo.inner.ciph_algo == EPSEncryptionAlgorithmEEA0Null
-        {
-            return Some(Event {
-                event_type: EventType::High,
-                message: "NAS Security mode command requested null cipher".to_string(),
-            });
+        let algorithms = nas_security_mode_command_algorithms(ie)?;
+        if algorithms.eea == Some(0) {
+            return Some(Event::new(
+                EventType::High,
+                EventDetails::new("nas_null_cipher", "null_cipher"),
+            ));
         }
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::test_support::{EEA0, EEA2, ie_nas_security_mode_command};
+
+    #[test]
+    fn test_eea0_is_high() {
+        let mut analyzer = NasNullCipherAnalyzer {};
+        let event = analyzer
+            .analyze_information_element(&ie_nas_security_mode_command(EEA0), 1)
+            .unwrap();
+        assert_eq!(event.event_type, EventType::High);
+    }
+
+    #[test]
+    fn test_eea2_is_not_flagged() {
+        let mut analyzer = NasNullCipherAnalyzer {};
+        assert!(
+            analyzer
+                .analyze_information_element(&ie_nas_security_mode_command(EEA2), 1)
+                .is_none()
+        );
+    }
+}
@@ -9,6 +9,7 @@ use telcom_parser::lte_rrc::{
 };
 
 use super::analyzer::{Analyzer, Event, EventType};
+use super::event_message::EventDetails;
 use super::information_element::{InformationElement, LteInformationElement};
 
 pub struct NullCipherAnalyzer {}
@@ -131,6 +132,10 @@ impl Analyzer for NullCipherAnalyzer {
         1
     }
 
+    fn get_severities(&self) -> Vec<EventType> {
+        vec![EventType::High]
+    }
+
     fn analyze_information_element(
         &mut self,
         ie: &InformationElement,
@@ -156,10 +161,10 @@ impl Analyzer for NullCipherAnalyzer {
             _ => return None,
         };
         if null_cipher_detected {
-            return Some(Event {
-                event_type: EventType::High,
-                message: "Cell suggested use of null cipher".to_string(),
-            });
+            return Some(Event::new(
+                EventType::High,
+                EventDetails::new("null_cipher", "null_cipher"),
+            ));
         }
         None
     }
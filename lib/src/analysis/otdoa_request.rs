@@ -0,0 +1,248 @@
+use std::borrow::Cow;
+
+use pycrate_rs::nas::NASMessage;
+use pycrate_rs::nas::emm::EMMMessage;
+use pycrate_rs::nas::generated::emm::emm_attach_request::EPSAttachTypeV;
+use telcom_parser::lte_rrc::{
+    DL_DCCH_MessageType, DL_DCCH_MessageType_c1, EstablishmentCause,
+    RRCConnectionRequestCriticalExtensions, UL_CCCH_MessageType, UL_CCCH_MessageType_c1,
+};
+
+use super::analyzer::{Analyzer, Event, EventType};
+use super::event_message::EventDetails;
+use super::information_element::{InformationElement, LteInformationElement};
+use crate::lpp::{self, Initiator, LppMessage, LppMessageType, NAS_CONTAINER_TYPE_LPP};
+
+/// Flags the network asking for the device's location (OTDOA, A-GNSS and so
+/// on) over LPP, unless the current connection is for an emergency call or
+/// the device started a location session itself.
+pub struct OtdoaRequestAnalyzer {
+    /// Whether the current connection was set up for an emergency call
+    emergency: bool,
+    /// Whether the device has started an LPP transaction of its own on the
+    /// current connection, e.g. to fetch GPS assistance data
+    device_initiated: bool,
+}
+
+impl Default for OtdoaRequestAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The LPP message carried by an EMM Generic NAS Transport, and whether it
+/// was sent by the device
+fn lpp_message(nas: &NASMessage) -> Option<(LppMessage, bool)> {
+    let (container_type, container, uplink) = match nas {
+        NASMessage::EMMMessage(EMMMessage::EMMDLGenericNASTransport(transport)) => (
+            transport.generic_cont_type.inner,
+            &transport.generic_cont.inner,
+            false,
+        ),
+        NASMessage::EMMMessage(EMMMessage::EMMULGenericNASTransport(transport)) => (
+            transport.generic_cont_type.inner,
+            &transport.generic_cont.inner,
+            true,
+        ),
+        _ => return None,
+    };
+    if container_type != NAS_CONTAINER_TYPE_LPP {
+        return None;
+    }
+    lpp::decode(container).ok().map(|message| (message, uplink))
+}
+
+impl OtdoaRequestAnalyzer {
+    pub fn new() -> Self {
+        OtdoaRequestAnalyzer {
+            emergency: false,
+            device_initiated: false,
+        }
+    }
+
+    fn on_new_connection(&mut self, emergency: bool) {
+        self.emergency = emergency;
+        self.device_initiated = false;
+    }
+
+    fn on_lpp(&mut self, message: LppMessage, uplink: bool) -> Option<Event> {
+        if uplink {
+            if message.initiator == Some(Initiator::TargetDevice) {
+                self.device_initiated = true;
+            }
+            return None;
+        }
+        if message.message_type != Some(LppMessageType::RequestLocationInformation)
+            || self.emergency
+            || self.device_initiated
+        {
+            return None;
+        }
+        Some(Event::new(
+            EventType::Medium,
+            EventDetails::new("otdoa_request", "unsolicited"),
+        ))
+    }
+}
+
+impl Analyzer for OtdoaRequestAnalyzer {
+    fn get_name(&self) -> Cow<'_, str> {
+        Cow::from("Unsolicited Location Request")
+    }
+
+    fn get_description(&self) -> Cow<'_, str> {
+        Cow::from(
+            "Tests whether the network asks for the device's location (e.g. OTDOA measurements) over LPP outside of an emergency call, without the device having asked for help locating itself (medium severity).",
+        )
+    }
+
+    fn get_version(&self) -> u32 {
+        1
+    }
+
+    fn get_severities(&self) -> Vec<EventType> {
+        vec![EventType::Medium]
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    fn analyze_information_element(
+        &mut self,
+        ie: &InformationElement,
+        _packet_num: usize,
+    ) -> Option<Event> {
+        let InformationElement::LTE(lte_ie) = ie else {
+            return None;
+        };
+        match &**lte_ie {
+            LteInformationElement::UlCcch(rrc_payload) => {
+                if let UL_CCCH_MessageType::C1(UL_CCCH_MessageType_c1::RrcConnectionRequest(
+                    request,
+                )) = &rrc_payload.message
+                {
+                    let emergency = match &request.critical_extensions {
+                        RRCConnectionRequestCriticalExtensions::RrcConnectionRequest_r8(r8) => {
+                            r8.establishment_cause.0 == EstablishmentCause::EMERGENCY
+                        }
+                        _ => false,
+                    };
+                    self.on_new_connection(emergency);
+                }
+                None
+            }
+            LteInformationElement::DlDcch(rrc_payload) => {
+                if let DL_DCCH_MessageType::C1(DL_DCCH_MessageType_c1::RrcConnectionRelease(_)) =
+                    &rrc_payload.message
+                {
+                    self.on_new_connection(false);
+                }
+                None
+            }
+            LteInformationElement::NAS(NASMessage::EMMMessage(EMMMessage::EMMAttachRequest(
+                req,
+            ))) => {
+                if req.eps_attach_type.inner.v == EPSAttachTypeV::EPSEmergencyAttach {
+                    self.emergency = true;
+                }
+                None
+            }
+            LteInformationElement::NAS(nas) => {
+                let (message, uplink) = lpp_message(nas)?;
+                self.on_lpp(message, uplink)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::test_support::{
+        EPS_EMERGENCY_ATTACH, ESTABLISHMENT_CAUSE_EMERGENCY, ESTABLISHMENT_CAUSE_MO_DATA,
+        ie_attach_request, ie_dl_lpp, ie_rrc_connection_release, ie_rrc_connection_request,
+        ie_ul_lpp,
+    };
+    use crate::lpp::tests::server_message;
+    use crate::umts_rrc::tests::bits;
+
+    // indexes in the LPP-MessageBody c1 CHOICE
+    const REQUEST_CAPABILITIES: u32 = 0;
+    const REQUEST_LOCATION_INFORMATION: u32 = 4;
+
+    fn analyze(analyzer: &mut OtdoaRequestAnalyzer, ie: InformationElement) -> Option<EventType> {
+        analyzer
+            .analyze_information_element(&ie, 1)
+            .map(|event| event.event_type)
+    }
+
+    fn location_request() -> InformationElement {
+        ie_dl_lpp(&server_message(REQUEST_LOCATION_INFORMATION))
+    }
+
+    #[test]
+    fn test_unsolicited_location_request() {
+        let mut analyzer = OtdoaRequestAnalyzer::new();
+        analyze(
+            &mut analyzer,
+            ie_rrc_connection_request(ESTABLISHMENT_CAUSE_MO_DATA),
+        );
+        assert_eq!(
+            analyze(
+                &mut analyzer,
+                ie_dl_lpp(&server_message(REQUEST_CAPABILITIES))
+            ),
+            None
+        );
+        assert_eq!(
+            analyze(&mut analyzer, location_request()),
+            Some(EventType::Medium)
+        );
+    }
+
+    #[test]
+    fn test_emergency_connection_is_not_flagged() {
+        let mut analyzer = OtdoaRequestAnalyzer::new();
+        analyze(
+            &mut analyzer,
+            ie_rrc_connection_request(ESTABLISHMENT_CAUSE_EMERGENCY),
+        );
+        assert_eq!(analyze(&mut analyzer, location_request()), None);
+
+        // the emergency ends with its connection
+        analyze(&mut analyzer, ie_rrc_connection_release());
+        assert_eq!(
+            analyze(&mut analyzer, location_request()),
+            Some(EventType::Medium)
+        );
+    }
+
+    #[test]
+    fn test_emergency_attach_is_not_flagged() {
+        let mut analyzer = OtdoaRequestAnalyzer::new();
+        analyze(&mut analyzer, ie_attach_request(EPS_EMERGENCY_ATTACH));
+        assert_eq!(analyze(&mut analyzer, location_request()), None);
+    }
+
+    #[test]
+    fn test_device_initiated_session_is_not_flagged() {
+        let mut analyzer = OtdoaRequestAnalyzer::new();
+        // the device asks for assistance data in a transaction it started
+        analyze(
+            &mut analyzer,
+            ie_ul_lpp(&bits("1001 0 0 1 00000001 0 0 0010")),
+        );
+        assert_eq!(analyze(&mut analyzer, location_request()), None);
+
+        analyze(
+            &mut analyzer,
+            ie_rrc_connection_request(ESTABLISHMENT_CAUSE_MO_DATA),
+        );
+        assert_eq!(
+            analyze(&mut analyzer, location_request()),
+            Some(EventType::Medium)
+        );
+    }
+}
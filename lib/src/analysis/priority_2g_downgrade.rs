@@ -1,6 +1,7 @@
 use std::borrow::Cow;
 
 use super::analyzer::{Analyzer, Event, EventType};
+use super::event_message::EventDetails;
 use super::information_element::{InformationElement, LteInformationElement};
 use log::debug;
 use telcom_parser::lte_rrc::{
@@ -62,6 +63,14 @@ impl Analyzer for LteSib6And7DowngradeAnalyzer {
         2
     }
 
+    fn get_severities(&self) -> Vec<EventType> {
+        vec![EventType::Informational, EventType::High]
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
     fn analyze_information_element(
         &mut self,
         ie: &InformationElement,
@@ -75,19 +84,17 @@ impl Analyzer for LteSib6And7DowngradeAnalyzer {
             debug!("encountered sib1 at {_packet_num}");
             let flag = if self.legacy_priority > self.lte_priority {
                 if self.lte_priority.is_none() {
-                    Some(Event {
-                        event_type: EventType::Informational,
-                        message:
-                            "LTE cell advertised a legacy (3G/2G) neighbors but no LTE neighbors"
-                                .to_string(),
-                    })
+                    Some(Event::new(
+                        EventType::Informational,
+                        EventDetails::new("lte_sib6_and_7_downgrade", "no_lte_neighbors"),
+                    ))
                 } else {
-                    Some(Event {
-                        event_type: EventType::High,
-                        message:
-                            format!("LTE cell advertised a legacy (3G/2G) cell for priority {:?} reselection over LTE neighbors at priority {:?}", self.legacy_priority?, self.lte_priority)
-                                .to_string(),
-                    })
+                    Some(Event::new(
+                        EventType::High,
+                        EventDetails::new("lte_sib6_and_7_downgrade", "legacy_priority")
+                            .with("legacy_priority", self.legacy_priority?)
+                            .with("lte_priority", self.lte_priority?),
+                    ))
                 }
             } else {
                 None
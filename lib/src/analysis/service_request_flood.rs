@@ -0,0 +1,248 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+use chrono::{DateTime, FixedOffset, TimeDelta};
+use pycrate_rs::nas::NASMessage;
+use pycrate_rs::nas::emm::EMMMessage;
+use pycrate_rs::nas::esm::ESMMessage;
+use pycrate_rs::nas::generated::emm::emm_ext_service_request::ServiceTypeV;
+
+use super::analyzer::{Analyzer, Event, EventType, PacketMetadata};
+use super::event_message::EventDetails;
+use super::information_element::{InformationElement, LteInformationElement};
+
+/// How far back service requests are counted
+const WINDOW_SECS: i64 = 60;
+
+/// More device-originated service requests than this within the window is
+/// medium severity
+const MEDIUM_REQUESTS: usize = 5;
+
+/// More device-originated service requests than this within the window is
+/// high severity
+const HIGH_REQUESTS: usize = 15;
+
+/// Flags bursts of EMM Service Requests from the device. Each one exposes
+/// its S-TMSI, so a fake base station which keeps dropping the connection
+/// (or keeps paging for CS fallback) can track the device's location by
+/// making it send them over and over.
+pub struct ServiceRequestFloodAnalyzer {
+    metadata: Option<PacketMetadata>,
+    /// When each device-originated service request within the window was
+    /// sent, oldest first
+    requests: VecDeque<DateTime<FixedOffset>>,
+    /// Whether a dedicated bearer, which is what carries a VoLTE call's
+    /// audio, is up. A call legitimately sends a flurry of requests.
+    in_call: bool,
+    /// The most severe event raised for the current burst
+    reported: Option<EventType>,
+}
+
+impl Default for ServiceRequestFloodAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServiceRequestFloodAnalyzer {
+    pub fn new() -> Self {
+        ServiceRequestFloodAnalyzer {
+            metadata: None,
+            requests: VecDeque::new(),
+            in_call: false,
+            reported: None,
+        }
+    }
+
+    fn on_service_request(&mut self, timestamp: DateTime<FixedOffset>) -> Option<Event> {
+        let window_start = timestamp - TimeDelta::seconds(WINDOW_SECS);
+        while self
+            .requests
+            .front()
+            .is_some_and(|sent| *sent <= window_start)
+        {
+            self.requests.pop_front();
+        }
+        self.requests.push_back(timestamp);
+
+        let requests = self.requests.len();
+        let event_type = if requests > HIGH_REQUESTS {
+            EventType::High
+        } else if requests > MEDIUM_REQUESTS {
+            EventType::Medium
+        } else {
+            self.reported = None;
+            return None;
+        };
+        if self.in_call || self.reported >= Some(event_type) {
+            return None;
+        }
+        self.reported = Some(event_type);
+        Some(Event::new(
+            event_type,
+            EventDetails::new("service_request_flood", "flood")
+                .with("requests", requests as u32)
+                .with("window_secs", WINDOW_SECS),
+        ))
+    }
+}
+
+impl Analyzer for ServiceRequestFloodAnalyzer {
+    fn get_name(&self) -> Cow<'_, str> {
+        Cow::from("Service Request Flood")
+    }
+
+    fn get_description(&self) -> Cow<'_, str> {
+        Cow::from(
+            "Tests whether the device sends more than 5 EMM Service Requests within a minute (medium severity), or more than 15 (high severity), each of which exposes its TMSI. Answering a page for an incoming CS fallback call isn't counted, and nothing is flagged during a VoLTE call. This can be triggered by a flaky connection, or an app which keeps waking the device up.",
+        )
+    }
+
+    fn get_version(&self) -> u32 {
+        1
+    }
+
+    fn get_severities(&self) -> Vec<EventType> {
+        vec![EventType::Medium, EventType::High]
+    }
+
+    fn set_packet_metadata(&mut self, metadata: &PacketMetadata) {
+        self.metadata = Some(metadata.clone());
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    fn analyze_information_element(
+        &mut self,
+        ie: &InformationElement,
+        _packet_num: usize,
+    ) -> Option<Event> {
+        let InformationElement::LTE(lte_ie) = ie else {
+            return None;
+        };
+        let LteInformationElement::NAS(nas_msg) = &**lte_ie else {
+            return None;
+        };
+        match nas_msg {
+            NASMessage::EMMMessage(EMMMessage::EMMServiceRequest(_)) => {}
+            NASMessage::EMMMessage(EMMMessage::EMMExtServiceRequest(req)) => {
+                // the network asked for this one by paging the device for
+                // an incoming CS fallback call (TS 24.301 9.9.3.27)
+                if req.service_type.inner.v
+                    == ServiceTypeV::MobileTerminatingCSFallbackOr1xCSFallback
+                {
+                    return None;
+                }
+            }
+            NASMessage::ESMMessage(ESMMessage::ESMActDediEPSBearerCtxtRequest(_)) => {
+                self.in_call = true;
+                return None;
+            }
+            NASMessage::ESMMessage(ESMMessage::ESMDeactEPSBearerCtxtRequest(_)) => {
+                self.in_call = false;
+                return None;
+            }
+            _ => return None,
+        }
+        let timestamp = self.metadata.as_ref()?.timestamp;
+        self.on_service_request(timestamp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::test_support::{
+        SERVICE_TYPE_MO_CSFB, SERVICE_TYPE_MT_CSFB, ie_activate_dedicated_bearer,
+        ie_deactivate_bearer, ie_ext_service_request,
+    };
+
+    fn at(secs: i64) -> PacketMetadata {
+        let start = DateTime::parse_from_rfc3339("2024-01-01T00:00:00+00:00").unwrap();
+        PacketMetadata {
+            timestamp: start + TimeDelta::seconds(secs),
+            arfcn: 5230,
+        }
+    }
+
+    // Sends an Extended Service Request of the given type at `secs`
+    fn request(
+        analyzer: &mut ServiceRequestFloodAnalyzer,
+        service_type: u8,
+        secs: i64,
+    ) -> Option<EventType> {
+        analyzer.set_packet_metadata(&at(secs));
+        analyzer
+            .analyze_information_element(&ie_ext_service_request(service_type), 0)
+            .map(|event| event.event_type)
+    }
+
+    #[test]
+    fn test_flood_escalates() {
+        let mut analyzer = ServiceRequestFloodAnalyzer::new();
+        let mut raised = Vec::new();
+        for i in 0..20 {
+            if let Some(event_type) = request(&mut analyzer, SERVICE_TYPE_MO_CSFB, i as i64 * 2) {
+                raised.push((i + 1, event_type));
+            }
+        }
+        // each severity is only raised once per burst
+        assert_eq!(
+            raised,
+            [
+                (MEDIUM_REQUESTS + 1, EventType::Medium),
+                (HIGH_REQUESTS + 1, EventType::High)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_requests_spread_out_are_fine() {
+        let mut analyzer = ServiceRequestFloodAnalyzer::new();
+        for secs in 0..50 {
+            assert_eq!(
+                request(&mut analyzer, SERVICE_TYPE_MO_CSFB, secs * 13),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn test_new_burst_is_reported_again() {
+        let mut analyzer = ServiceRequestFloodAnalyzer::new();
+        let burst = |analyzer: &mut ServiceRequestFloodAnalyzer, start: i64| {
+            (0..=MEDIUM_REQUESTS as i64)
+                .filter_map(|i| request(analyzer, SERVICE_TYPE_MO_CSFB, start + i))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(burst(&mut analyzer, 0), [EventType::Medium]);
+        assert_eq!(burst(&mut analyzer, 600), [EventType::Medium]);
+    }
+
+    #[test]
+    fn test_mobile_terminated_csfb_is_not_counted() {
+        let mut analyzer = ServiceRequestFloodAnalyzer::new();
+        for secs in 0..30 {
+            assert_eq!(request(&mut analyzer, SERVICE_TYPE_MT_CSFB, secs), None);
+        }
+    }
+
+    #[test]
+    fn test_voice_call_suppresses_alert() {
+        let mut analyzer = ServiceRequestFloodAnalyzer::new();
+        analyzer.analyze_information_element(&ie_activate_dedicated_bearer(), 0);
+        for secs in 0..30 {
+            assert_eq!(request(&mut analyzer, SERVICE_TYPE_MO_CSFB, secs), None);
+        }
+
+        // once the call is over, the flood that's still going is flagged
+        analyzer.analyze_information_element(&ie_deactivate_bearer(), 0);
+        assert!(!analyzer.in_call);
+        assert_eq!(
+            request(&mut analyzer, SERVICE_TYPE_MO_CSFB, 30),
+            Some(EventType::High)
+        );
+    }
+}
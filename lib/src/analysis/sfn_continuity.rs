@@ -0,0 +1,295 @@
+use std::borrow::Cow;
+
+use chrono::{DateTime, FixedOffset};
+use telcom_parser::lte_rrc::{BCCH_DL_SCH_MessageType, BCCH_DL_SCH_MessageType_c1};
+
+use super::analyzer::{Analyzer, Event, EventType, PacketMetadata};
+use super::event_message::EventDetails;
+use super::information_element::{InformationElement, LteInformationElement};
+
+/// The SFN counts 10ms radio frames, wrapping after 1024 of them
+const SFN_MODULUS: i64 = 1024;
+const FRAME_MILLIS: i64 = 10;
+
+/// How far (in frames) an observed SFN may be from the expected one before
+/// it's flagged. The MIB only carries the top 8 bits of the SFN, so a
+/// legitimate cell can already be off by up to 3 frames.
+const MAX_DEVIATION_FRAMES: i64 = 10;
+
+/// How far (in frames) the SFN must jump backwards to count as a replay
+/// rather than jitter.
+const MAX_BACKWARDS_JUMP_FRAMES: i64 = 100;
+
+/// What identifies the cell a MIB came from, as far as the MIB itself can.
+/// After a reselection the new cell's MIB arrives before its SIB1, so this is
+/// how its SFN is kept from being compared with the previous cell's.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct MibCell {
+    arfcn: u16,
+    dl_bandwidth: u8,
+    phich_duration: u8,
+    phich_resource: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ObservedSfn {
+    sfn: i64,
+    timestamp: DateTime<FixedOffset>,
+    cell: MibCell,
+}
+
+/// Tracks the System Frame Number broadcast in each MIB, and flags MIBs
+/// whose SFN doesn't agree with the time elapsed since the previous one.
+pub struct SfnContinuityAnalyzer {
+    metadata: Option<PacketMetadata>,
+    last_observed: Option<ObservedSfn>,
+    cell_identity: Option<u32>,
+}
+
+impl Default for SfnContinuityAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SfnContinuityAnalyzer {
+    pub fn new() -> Self {
+        SfnContinuityAnalyzer {
+            metadata: None,
+            last_observed: None,
+            cell_identity: None,
+        }
+    }
+
+    fn on_cell_identity(&mut self, cell_identity: u32) {
+        if self.cell_identity != Some(cell_identity) {
+            // a different cell has its own, unrelated frame timing
+            self.cell_identity = Some(cell_identity);
+            self.last_observed = None;
+        }
+    }
+
+    fn on_sfn(&mut self, observed: ObservedSfn) -> Option<Event> {
+        let last = self.last_observed.replace(observed.clone())?;
+        if last.cell != observed.cell {
+            // a different cell has its own, unrelated frame timing
+            return None;
+        }
+        let elapsed_millis = (observed.timestamp - last.timestamp).num_milliseconds();
+        if elapsed_millis < 0 {
+            return None;
+        }
+
+        let elapsed_frames = (elapsed_millis + FRAME_MILLIS / 2) / FRAME_MILLIS;
+        let expected = (last.sfn + elapsed_frames).rem_euclid(SFN_MODULUS);
+        // the shortest way around the SFN cycle, in -512..512
+        let deviation =
+            (observed.sfn - expected + SFN_MODULUS / 2).rem_euclid(SFN_MODULUS) - SFN_MODULUS / 2;
+
+        if deviation < -MAX_BACKWARDS_JUMP_FRAMES {
+            Some(Event::new(
+                EventType::High,
+                EventDetails::new("sfn_continuity", "backwards")
+                    .with("sfn", observed.sfn)
+                    .with("frames", -deviation)
+                    .with("expected", expected),
+            ))
+        } else if deviation.abs() > MAX_DEVIATION_FRAMES {
+            Some(Event::new(
+                EventType::Medium,
+                EventDetails::new("sfn_continuity", "deviation")
+                    .with("sfn", observed.sfn)
+                    .with("frames", deviation.abs())
+                    .with("expected", expected),
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+impl Analyzer for SfnContinuityAnalyzer {
+    fn get_name(&self) -> Cow<'_, str> {
+        Cow::from("SFN Discontinuity")
+    }
+
+    fn get_description(&self) -> Cow<'_, str> {
+        Cow::from(
+            "Tests whether the System Frame Number broadcast in a cell's MIB advances in step with time, as it does on a legitimate cell. A jump of more than 10 frames is medium severity, and a jump backwards by more than 100 frames (as when recorded broadcasts are replayed) is high severity. Counting starts over whenever the frequency, the MIB's cell configuration or the SIB1's cell identity changes.",
+        )
+    }
+
+    fn get_version(&self) -> u32 {
+        2
+    }
+
+    fn get_severities(&self) -> Vec<EventType> {
+        vec![EventType::Medium, EventType::High]
+    }
+
+    fn set_packet_metadata(&mut self, metadata: &PacketMetadata) {
+        self.metadata = Some(metadata.clone());
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    fn analyze_information_element(
+        &mut self,
+        ie: &InformationElement,
+        _packet_num: usize,
+    ) -> Option<Event> {
+        let InformationElement::LTE(lte_ie) = ie else {
+            return None;
+        };
+        match &**lte_ie {
+            LteInformationElement::BcchBch(bch_msg) => {
+                let metadata = self.metadata.as_ref()?;
+                // the MIB carries the 8 most significant bits of the 10 bit SFN
+                let sfn_msb = bch_msg
+                    .message
+                    .system_frame_number
+                    .0
+                    .iter()
+                    .fold(0, |acc, bit| (acc << 1) | *bit as i64);
+                let mib = &bch_msg.message;
+                let observed = ObservedSfn {
+                    sfn: sfn_msb << 2,
+                    timestamp: metadata.timestamp,
+                    cell: MibCell {
+                        arfcn: metadata.arfcn,
+                        dl_bandwidth: mib.dl_bandwidth.0,
+                        phich_duration: mib.phich_config.phich_duration.0,
+                        phich_resource: mib.phich_config.phich_resource.0,
+                    },
+                };
+                self.on_sfn(observed)
+            }
+            LteInformationElement::BcchDlSch(sch_msg) => {
+                if let BCCH_DL_SCH_MessageType::C1(c1) = &sch_msg.message
+                    && let BCCH_DL_SCH_MessageType_c1::SystemInformationBlockType1(sib1) = c1
+                {
+                    let cell_identity = sib1
+                        .cell_access_related_info
+                        .cell_identity
+                        .0
+                        .iter()
+                        .fold(0, |acc, bit| (acc << 1) | *bit as u32);
+                    self.on_cell_identity(cell_identity);
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::test_support::{DL_BANDWIDTH_N50, DL_BANDWIDTH_N100, ie_mib};
+    use chrono::TimeDelta;
+
+    fn at(millis: i64) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339("2024-01-01T00:00:00+00:00").unwrap()
+            + TimeDelta::milliseconds(millis)
+    }
+
+    fn observed(sfn: i64, millis: i64, arfcn: u16) -> ObservedSfn {
+        ObservedSfn {
+            sfn,
+            timestamp: at(millis),
+            cell: MibCell {
+                arfcn,
+                dl_bandwidth: DL_BANDWIDTH_N50,
+                phich_duration: 0,
+                phich_resource: 2,
+            },
+        }
+    }
+
+    fn event_type(event: Option<Event>) -> Option<EventType> {
+        event.map(|event| event.event_type)
+    }
+
+    #[test]
+    fn test_sfn_advancing_with_time() {
+        let mut analyzer = SfnContinuityAnalyzer::new();
+        assert!(analyzer.on_sfn(observed(1000, 0, 5230)).is_none());
+        // 400ms later the SFN has wrapped around to (1000 + 40) % 1024
+        assert!(analyzer.on_sfn(observed(16, 400, 5230)).is_none());
+        // within the tolerance for jitter and the MIB's 4 frame granularity
+        assert!(analyzer.on_sfn(observed(28, 500, 5230)).is_none());
+    }
+
+    #[test]
+    fn test_sfn_deviation_is_medium() {
+        let mut analyzer = SfnContinuityAnalyzer::new();
+        analyzer.on_sfn(observed(100, 0, 5230));
+        assert_eq!(
+            event_type(analyzer.on_sfn(observed(140, 200, 5230))),
+            Some(EventType::Medium)
+        );
+        // we compare against the last observed SFN, so the alert isn't repeated
+        assert!(analyzer.on_sfn(observed(160, 400, 5230)).is_none());
+    }
+
+    #[test]
+    fn test_sfn_backwards_jump_is_high() {
+        let mut analyzer = SfnContinuityAnalyzer::new();
+        analyzer.on_sfn(observed(500, 0, 5230));
+        assert_eq!(
+            event_type(analyzer.on_sfn(observed(300, 1000, 5230))),
+            Some(EventType::High)
+        );
+    }
+
+    #[test]
+    fn test_cell_change_resets_expected_sfn() {
+        let mut analyzer = SfnContinuityAnalyzer::new();
+        analyzer.on_sfn(observed(500, 0, 5230));
+        assert!(analyzer.on_sfn(observed(12, 1000, 2050)).is_none());
+
+        analyzer.on_cell_identity(1);
+        analyzer.on_sfn(observed(500, 2000, 2050));
+        analyzer.on_cell_identity(2);
+        assert!(analyzer.on_sfn(observed(12, 3000, 2050)).is_none());
+    }
+
+    // Feeds a MIB received on `arfcn` at `millis` through the analyzer
+    fn mib(
+        analyzer: &mut SfnContinuityAnalyzer,
+        dl_bandwidth: u8,
+        sfn: u16,
+        millis: i64,
+        arfcn: u16,
+    ) -> Option<EventType> {
+        analyzer.set_packet_metadata(&PacketMetadata {
+            timestamp: at(millis),
+            arfcn,
+        });
+        event_type(analyzer.analyze_information_element(&ie_mib(dl_bandwidth, sfn), 0))
+    }
+
+    #[test]
+    fn test_reselection_before_sib1() {
+        let mut analyzer = SfnContinuityAnalyzer::new();
+        assert_eq!(mib(&mut analyzer, DL_BANDWIDTH_N50, 100, 0, 5230), None);
+        assert_eq!(mib(&mut analyzer, DL_BANDWIDTH_N50, 140, 400, 5230), None);
+
+        // the device reselects to a wider cell on the same frequency, whose
+        // frame timing has nothing to do with the last one's, and its MIB
+        // arrives before its SIB1
+        assert_eq!(mib(&mut analyzer, DL_BANDWIDTH_N100, 900, 600, 5230), None);
+        assert_eq!(mib(&mut analyzer, DL_BANDWIDTH_N100, 940, 1000, 5230), None);
+        // and then to a cell on another frequency
+        assert_eq!(mib(&mut analyzer, DL_BANDWIDTH_N100, 12, 1200, 2050), None);
+
+        // the new cell's own timing is still checked
+        assert_eq!(
+            mib(&mut analyzer, DL_BANDWIDTH_N100, 400, 1600, 2050),
+            Some(EventType::Medium)
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use telcom_parser::lte_rrc::{
+    BCCH_DL_SCH_MessageType, BCCH_DL_SCH_MessageType_c1,
+    SystemInformation_r8_IEsSib_TypeAndInfo_Entry, SystemInformationBlockType3,
+    SystemInformationCriticalExtensions,
+};
+
+use super::analyzer::{Analyzer, Event, EventType};
+use super::event_message::EventDetails;
+use super::information_element::{InformationElement, LteInformationElement};
+
+/// threshServingLow is broadcast in steps of 2dB, so anything above this is
+/// more than 30dB
+const MAX_THRESH_SERVING_LOW: u8 = 15;
+
+/// How many times a cell has to broadcast the same SIB3 before its values
+/// are taken as its usual configuration
+const BASELINE_SIGHTINGS: u32 = 3;
+
+/// The reselection thresholds from a SIB3, as broadcast (in steps of 2dB)
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ReselectionParams {
+    s_intra_search: Option<u8>,
+    s_non_intra_search: Option<u8>,
+    thresh_serving_low: u8,
+}
+
+impl From<&SystemInformationBlockType3> for ReselectionParams {
+    fn from(sib3: &SystemInformationBlockType3) -> Self {
+        ReselectionParams {
+            s_intra_search: sib3
+                .intra_freq_cell_reselection_info
+                .s_intra_search
+                .as_ref()
+                .map(|threshold| threshold.0),
+            s_non_intra_search: sib3
+                .cell_reselection_serving_freq_info
+                .s_non_intra_search
+                .as_ref()
+                .map(|threshold| threshold.0),
+            thresh_serving_low: sib3.cell_reselection_serving_freq_info.thresh_serving_low.0,
+        }
+    }
+}
+
+/// Flags SIB3 cell reselection thresholds extreme enough to push the device
+/// off its current cell, unless the cell has broadcast the same values
+/// several times already.
+pub struct Sib3ReselectionAnomalyAnalyzer {
+    /// The cell the last SIB1 came from, which later SIB3s belong to
+    cell_identity: Option<u32>,
+    /// The SIB3 values each cell last broadcast, and how many times in a row
+    /// it's broadcast them
+    baselines: HashMap<u32, (ReselectionParams, u32)>,
+}
+
+impl Default for Sib3ReselectionAnomalyAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sib3ReselectionAnomalyAnalyzer {
+    pub fn new() -> Self {
+        Sib3ReselectionAnomalyAnalyzer {
+            cell_identity: None,
+            baselines: HashMap::new(),
+        }
+    }
+
+    // Whether the current cell has broadcast these values enough times
+    // before to count as its baseline, counting this sighting
+    fn is_baseline(&mut self, params: ReselectionParams) -> bool {
+        let Some(cell_identity) = self.cell_identity else {
+            return false;
+        };
+        let (baseline, sightings) = self.baselines.entry(cell_identity).or_insert((params, 0));
+        if *baseline != params {
+            // the cell changed its configuration, so start over
+            *baseline = params;
+            *sightings = 0;
+        }
+        let calibrated = *sightings >= BASELINE_SIGHTINGS;
+        *sightings += 1;
+        calibrated
+    }
+
+    fn on_sib3(&mut self, params: ReselectionParams) -> Option<Event> {
+        if self.is_baseline(params) {
+            return None;
+        }
+        if params.thresh_serving_low > MAX_THRESH_SERVING_LOW {
+            Some(Event::new(
+                EventType::Medium,
+                EventDetails::new("sib3_reselection_anomaly", "thresh_serving_low")
+                    .with("thresh_serving_low_db", params.thresh_serving_low * 2),
+            ))
+        } else if params.s_intra_search == Some(0) {
+            Some(Event::new(
+                EventType::Low,
+                EventDetails::new("sib3_reselection_anomaly", "s_intra_search_zero"),
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+impl Analyzer for Sib3ReselectionAnomalyAnalyzer {
+    fn get_name(&self) -> Cow<'_, str> {
+        Cow::from("SIB3 Reselection Anomaly")
+    }
+
+    fn get_description(&self) -> Cow<'_, str> {
+        Cow::from(
+            "Tests for LTE cells broadcasting extreme cell reselection thresholds in SIB3: s-IntraSearch of 0 (low severity), or a threshServingLow above 30dB, which pushes the device to reselect away from its current cell (medium severity). Once a cell has broadcast the same values three times, they're taken as its usual configuration and no longer flagged.",
+        )
+    }
+
+    fn get_version(&self) -> u32 {
+        1
+    }
+
+    fn get_severities(&self) -> Vec<EventType> {
+        vec![EventType::Low, EventType::Medium]
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    fn analyze_information_element(
+        &mut self,
+        ie: &InformationElement,
+        _packet_num: usize,
+    ) -> Option<Event> {
+        let InformationElement::LTE(lte_ie) = ie else {
+            return None;
+        };
+        let LteInformationElement::BcchDlSch(sch_msg) = &**lte_ie else {
+            return None;
+        };
+        let BCCH_DL_SCH_MessageType::C1(c1) = &sch_msg.message else {
+            return None;
+        };
+        match c1 {
+            BCCH_DL_SCH_MessageType_c1::SystemInformationBlockType1(sib1) => {
+                self.cell_identity = Some(
+                    sib1.cell_access_related_info
+                        .cell_identity
+                        .0
+                        .iter()
+                        .fold(0, |acc, bit| (acc << 1) | *bit as u32),
+                );
+                None
+            }
+            BCCH_DL_SCH_MessageType_c1::SystemInformation(system_information) => {
+                let SystemInformationCriticalExtensions::SystemInformation_r8(sib) =
+                    &system_information.critical_extensions
+                else {
+                    return None;
+                };
+                sib.sib_type_and_info
+                    .0
+                    .iter()
+                    .find_map(|entry| match entry {
+                        SystemInformation_r8_IEsSib_TypeAndInfo_Entry::Sib3(sib3) => {
+                            self.on_sib3(ReselectionParams::from(sib3))
+                        }
+                        _ => None,
+                    })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NORMAL: ReselectionParams = ReselectionParams {
+        s_intra_search: Some(31),
+        s_non_intra_search: Some(4),
+        thresh_serving_low: 2,
+    };
+
+    fn event_type(event: Option<Event>) -> Option<EventType> {
+        event.map(|event| event.event_type)
+    }
+
+    #[test]
+    fn test_normal_values_are_not_flagged() {
+        let mut analyzer = Sib3ReselectionAnomalyAnalyzer::new();
+        assert_eq!(event_type(analyzer.on_sib3(NORMAL)), None);
+        // s-IntraSearch may be left out, which means measuring always
+        let params = ReselectionParams {
+            s_intra_search: None,
+            ..NORMAL
+        };
+        assert_eq!(event_type(analyzer.on_sib3(params)), None);
+        // 30dB is high, but not over the limit
+        let params = ReselectionParams {
+            thresh_serving_low: 15,
+            ..NORMAL
+        };
+        assert_eq!(event_type(analyzer.on_sib3(params)), None);
+    }
+
+    #[test]
+    fn test_extreme_values_are_flagged() {
+        let mut analyzer = Sib3ReselectionAnomalyAnalyzer::new();
+        let params = ReselectionParams {
+            s_intra_search: Some(0),
+            ..NORMAL
+        };
+        assert_eq!(event_type(analyzer.on_sib3(params)), Some(EventType::Low));
+
+        let params = ReselectionParams {
+            thresh_serving_low: 16,
+            ..NORMAL
+        };
+        let event = analyzer.on_sib3(params).unwrap();
+        assert_eq!(event.event_type, EventType::Medium);
+        assert_eq!(
+            event.message,
+            "Cell's SIB3 set threshServingLow to 32dB, which pushes the device to reselect away from it"
+        );
+
+        // the more severe of the two wins
+        let params = ReselectionParams {
+            s_intra_search: Some(0),
+            thresh_serving_low: 31,
+            ..NORMAL
+        };
+        assert_eq!(
+            event_type(analyzer.on_sib3(params)),
+            Some(EventType::Medium)
+        );
+    }
+
+    #[test]
+    fn test_baseline_suppresses_repeats_from_the_same_cell() {
+        let mut analyzer = Sib3ReselectionAnomalyAnalyzer::new();
+        let extreme = ReselectionParams {
+            thresh_serving_low: 20,
+            ..NORMAL
+        };
+        analyzer.cell_identity = Some(1);
+        for _ in 0..BASELINE_SIGHTINGS {
+            assert!(analyzer.on_sib3(extreme).is_some());
+        }
+        assert!(analyzer.on_sib3(extreme).is_none());
+
+        // another cell has a baseline of its own
+        analyzer.cell_identity = Some(2);
+        assert!(analyzer.on_sib3(extreme).is_some());
+
+        // and changing the values starts the first cell's over
+        analyzer.cell_identity = Some(1);
+        let other = ReselectionParams {
+            s_non_intra_search: Some(8),
+            ..extreme
+        };
+        assert!(analyzer.on_sib3(other).is_some());
+    }
+
+    #[test]
+    fn test_no_baseline_without_a_cell() {
+        let mut analyzer = Sib3ReselectionAnomalyAnalyzer::new();
+        let extreme = ReselectionParams {
+            s_intra_search: Some(0),
+            ..NORMAL
+        };
+        for _ in 0..=BASELINE_SIGHTINGS {
+            assert!(analyzer.on_sib3(extreme).is_some());
+        }
+    }
+}
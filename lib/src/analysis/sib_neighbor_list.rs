@@ -0,0 +1,403 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use telcom_parser::lte_rrc::{
+    BCCH_DL_SCH_MessageType, BCCH_DL_SCH_MessageType_c1,
+    SystemInformation_r8_IEsSib_TypeAndInfo_Entry, SystemInformationCriticalExtensions,
+};
+
+use super::analyzer::{Analyzer, Event, EventType};
+use super::diagnostic::DiagnosticAnalyzer;
+use super::event_message::EventDetails;
+use super::information_element::{InformationElement, LteInformationElement};
+
+/// How many SIB4/SIB5 broadcast cycles in a row a cell has to advertise no
+/// neighbors in before it's flagged
+const EMPTY_PERIODS: u32 = 3;
+
+/// How many neighbors another cell has to advertise for an empty list to
+/// stand out
+const RICH_NEIGHBORS: u32 = 3;
+
+/// What a cell's SIB4 and SIB5 have advertised so far
+#[derive(Debug, Default)]
+struct CellNeighbors {
+    /// Neighbor cells on the serving frequency, from SIB4
+    intra_freq: Option<u32>,
+    /// Other carrier frequencies, and the neighbor cells listed on them,
+    /// from SIB5
+    inter_freq: Option<u32>,
+    /// Whether a SIB4 or SIB5 has been seen in the current broadcast cycle.
+    /// A cell may send them in separate SystemInformation messages, so a
+    /// cycle ends when one of them comes around again.
+    sib4_this_period: bool,
+    sib5_this_period: bool,
+    /// How many broadcast cycles in a row have advertised no neighbors
+    empty_periods: u32,
+    /// Whether the cell sent a message which may expose the IMSI
+    imsi_exposed: bool,
+    /// The most severe event raised for the cell so far
+    reported: Option<EventType>,
+}
+
+impl CellNeighbors {
+    fn total(&self) -> u32 {
+        self.intra_freq.unwrap_or(0) + self.inter_freq.unwrap_or(0)
+    }
+}
+
+/// Flags cells which keep advertising no neighbor cells in SIB4/SIB5 while
+/// other cells in the same recording advertise several, a common tell of a
+/// fake base station which doesn't want the device to leave it.
+pub struct SibNeighborListAnalyzer {
+    /// The cell the last SIB1 came from, which later SIBs belong to
+    cell_identity: Option<u32>,
+    cells: HashMap<u32, CellNeighbors>,
+}
+
+impl Default for SibNeighborListAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SibNeighborListAnalyzer {
+    pub fn new() -> Self {
+        SibNeighborListAnalyzer {
+            cell_identity: None,
+            cells: HashMap::new(),
+        }
+    }
+
+    // Records the neighbor counts from one SystemInformation message, which
+    // may carry SIB4, SIB5 or both
+    fn on_neighbors(&mut self, intra_freq: Option<u32>, inter_freq: Option<u32>) -> Option<Event> {
+        let cell_identity = self.cell_identity?;
+        let cell = self.cells.entry(cell_identity).or_default();
+        let new_period = !(cell.sib4_this_period || cell.sib5_this_period)
+            || (intra_freq.is_some() && cell.sib4_this_period)
+            || (inter_freq.is_some() && cell.sib5_this_period);
+        if new_period {
+            cell.sib4_this_period = false;
+            cell.sib5_this_period = false;
+        }
+        if intra_freq.is_some() {
+            cell.intra_freq = intra_freq;
+            cell.sib4_this_period = true;
+        }
+        if inter_freq.is_some() {
+            cell.inter_freq = inter_freq;
+            cell.sib5_this_period = true;
+        }
+        if cell.total() == 0 {
+            if new_period {
+                cell.empty_periods += 1;
+            }
+        } else {
+            cell.empty_periods = 0;
+            cell.reported = None;
+        }
+        self.check(cell_identity)
+    }
+
+    fn on_imsi_exposure(&mut self) -> Option<Event> {
+        let cell_identity = self.cell_identity?;
+        self.cells.entry(cell_identity).or_default().imsi_exposed = true;
+        self.check(cell_identity)
+    }
+
+    // Raises an event for the cell if it's persistently advertising no
+    // neighbors while another cell advertises plenty, unless one at least
+    // as severe was already raised
+    fn check(&mut self, cell_identity: u32) -> Option<Event> {
+        let busiest_neighbor = self
+            .cells
+            .iter()
+            .filter(|(other, _)| **other != cell_identity)
+            .map(|(_, other)| other.total())
+            .max()
+            .unwrap_or(0);
+        let cell = self.cells.get_mut(&cell_identity)?;
+        if cell.empty_periods < EMPTY_PERIODS || busiest_neighbor < RICH_NEIGHBORS {
+            return None;
+        }
+        let (event_type, code) = if cell.imsi_exposed {
+            (EventType::Medium, "empty_with_imsi_exposure")
+        } else {
+            (EventType::Low, "empty")
+        };
+        if cell.reported >= Some(event_type) {
+            return None;
+        }
+        cell.reported = Some(event_type);
+        Some(Event::new(
+            event_type,
+            EventDetails::new("sib_neighbor_list", code)
+                .with("periods", cell.empty_periods)
+                .with("neighbors", busiest_neighbor),
+        ))
+    }
+}
+
+impl Analyzer for SibNeighborListAnalyzer {
+    fn get_name(&self) -> Cow<'_, str> {
+        Cow::from("Missing Neighbor Cells")
+    }
+
+    fn get_description(&self) -> Cow<'_, str> {
+        Cow::from(
+            "Tests for LTE cells whose SIB4 and SIB5 keep advertising no neighbor cells or frequencies while other cells in the same recording advertise several (low severity), or which also send a message that may expose the IMSI (medium severity).",
+        )
+    }
+
+    fn get_version(&self) -> u32 {
+        1
+    }
+
+    fn get_severities(&self) -> Vec<EventType> {
+        vec![EventType::Low, EventType::Medium]
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    fn analyze_information_element(
+        &mut self,
+        ie: &InformationElement,
+        _packet_num: usize,
+    ) -> Option<Event> {
+        let InformationElement::LTE(lte_ie) = ie else {
+            return None;
+        };
+        match &**lte_ie {
+            LteInformationElement::NAS(nas_msg) => {
+                if DiagnosticAnalyzer::is_imsi_exposing_nas(nas_msg) {
+                    self.on_imsi_exposure()
+                } else {
+                    None
+                }
+            }
+            LteInformationElement::BcchDlSch(sch_msg) => {
+                let BCCH_DL_SCH_MessageType::C1(c1) = &sch_msg.message else {
+                    return None;
+                };
+                match c1 {
+                    BCCH_DL_SCH_MessageType_c1::SystemInformationBlockType1(sib1) => {
+                        self.cell_identity = Some(
+                            sib1.cell_access_related_info
+                                .cell_identity
+                                .0
+                                .iter()
+                                .fold(0, |acc, bit| (acc << 1) | *bit as u32),
+                        );
+                        None
+                    }
+                    BCCH_DL_SCH_MessageType_c1::SystemInformation(system_information) => {
+                        let SystemInformationCriticalExtensions::SystemInformation_r8(sib) =
+                            &system_information.critical_extensions
+                        else {
+                            return None;
+                        };
+                        let mut intra_freq = None;
+                        let mut inter_freq = None;
+                        for entry in &sib.sib_type_and_info.0 {
+                            match entry {
+                                SystemInformation_r8_IEsSib_TypeAndInfo_Entry::Sib4(sib4) => {
+                                    intra_freq = Some(
+                                        sib4.intra_freq_neigh_cell_list
+                                            .as_ref()
+                                            .map_or(0, |list| list.0.len() as u32),
+                                    );
+                                }
+                                SystemInformation_r8_IEsSib_TypeAndInfo_Entry::Sib5(sib5) => {
+                                    let carriers = &sib5.inter_freq_carrier_freq_list.0;
+                                    let cells: usize = carriers
+                                        .iter()
+                                        .filter_map(|carrier| {
+                                            carrier.inter_freq_neigh_cell_list.as_ref()
+                                        })
+                                        .map(|list| list.0.len())
+                                        .sum();
+                                    inter_freq = Some((carriers.len() + cells) as u32);
+                                }
+                                _ => {}
+                            }
+                        }
+                        if intra_freq.is_none() && inter_freq.is_none() {
+                            return None;
+                        }
+                        self.on_neighbors(intra_freq, inter_freq)
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::test_support::{
+        ie_identity_request, ie_sib1, ie_sib4, ie_sib4_and_sib5, ie_sib5,
+    };
+
+    const FAKE_CELL: u32 = 1;
+    const MACRO_CELL: u32 = 2;
+
+    fn analyze(analyzer: &mut SibNeighborListAnalyzer, ie: &InformationElement) -> Option<Event> {
+        analyzer.analyze_information_element(ie, 0)
+    }
+
+    // The SIB1 which the following SIBs belong to
+    fn camp(analyzer: &mut SibNeighborListAnalyzer, cell: u32) {
+        assert!(analyze(analyzer, &ie_sib1([3, 1, 0], &[2, 6, 0], cell)).is_none());
+    }
+
+    fn event_type(
+        analyzer: &mut SibNeighborListAnalyzer,
+        ie: &InformationElement,
+    ) -> Option<EventType> {
+        analyze(analyzer, ie).map(|event| event.event_type)
+    }
+
+    // A cycle from the macro cell, advertising 4 intra-frequency neighbors
+    // and one carrier with 2 more, 7 in all
+    fn macro_cell(analyzer: &mut SibNeighborListAnalyzer) {
+        camp(analyzer, MACRO_CELL);
+        assert_eq!(event_type(analyzer, &ie_sib4_and_sib5(4, &[2])), None);
+    }
+
+    #[test]
+    fn test_persistently_empty_cell_is_flagged() {
+        let mut analyzer = SibNeighborListAnalyzer::new();
+        macro_cell(&mut analyzer);
+        camp(&mut analyzer, FAKE_CELL);
+        for _ in 1..EMPTY_PERIODS {
+            assert_eq!(event_type(&mut analyzer, &ie_sib4(0)), None);
+        }
+        let event = analyze(&mut analyzer, &ie_sib4(0)).unwrap();
+        assert_eq!(event.event_type, EventType::Low);
+        assert_eq!(
+            event.message,
+            "Cell advertised no neighbor cells in 3 SIB4/SIB5 broadcasts in a row, while another cell in this recording advertised 7"
+        );
+        // and it isn't raised again for every broadcast after that
+        assert_eq!(event_type(&mut analyzer, &ie_sib4(0)), None);
+    }
+
+    #[test]
+    fn test_empty_cell_alone_is_not_flagged() {
+        // with nothing to compare to, an empty list may just be a quiet area
+        let mut analyzer = SibNeighborListAnalyzer::new();
+        camp(&mut analyzer, FAKE_CELL);
+        for _ in 0..EMPTY_PERIODS * 2 {
+            assert_eq!(event_type(&mut analyzer, &ie_sib4(0)), None);
+        }
+        // nor is it next to cells with only a neighbor or two
+        camp(&mut analyzer, MACRO_CELL);
+        assert_eq!(event_type(&mut analyzer, &ie_sib4_and_sib5(1, &[0])), None);
+        camp(&mut analyzer, FAKE_CELL);
+        assert_eq!(event_type(&mut analyzer, &ie_sib4(0)), None);
+    }
+
+    #[test]
+    fn test_neighbors_reset_the_count() {
+        let mut analyzer = SibNeighborListAnalyzer::new();
+        camp(&mut analyzer, MACRO_CELL);
+        analyze(&mut analyzer, &ie_sib5(&[4]));
+        camp(&mut analyzer, FAKE_CELL);
+        for _ in 1..EMPTY_PERIODS {
+            assert_eq!(event_type(&mut analyzer, &ie_sib4(0)), None);
+        }
+        assert_eq!(event_type(&mut analyzer, &ie_sib4(2)), None);
+        assert_eq!(event_type(&mut analyzer, &ie_sib4(0)), None);
+        assert_eq!(analyzer.cells[&FAKE_CELL].empty_periods, 1);
+
+        // a SIB5 on its own keeps the SIB4's count
+        assert_eq!(event_type(&mut analyzer, &ie_sib4(1)), None);
+        for _ in 0..EMPTY_PERIODS {
+            assert_eq!(event_type(&mut analyzer, &ie_sib5(&[0])), None);
+        }
+        let cell = &analyzer.cells[&FAKE_CELL];
+        assert_eq!((cell.intra_freq, cell.inter_freq), (Some(1), Some(1)));
+        assert_eq!(cell.empty_periods, 0);
+    }
+
+    #[test]
+    fn test_cycles_span_separate_sib4_and_sib5() {
+        let mut analyzer = SibNeighborListAnalyzer::new();
+        camp(&mut analyzer, FAKE_CELL);
+        analyze(&mut analyzer, &ie_sib4(0));
+        assert_eq!(analyzer.cells[&FAKE_CELL].empty_periods, 1);
+        // a SIB5 in its own message completes the same cycle
+        analyze(&mut analyzer, &ie_sib5(&[0]));
+        let cell = &analyzer.cells[&FAKE_CELL];
+        assert!(cell.sib4_this_period && cell.sib5_this_period);
+        // and the next SIB4 starts another
+        analyze(&mut analyzer, &ie_sib4(0));
+        let cell = &analyzer.cells[&FAKE_CELL];
+        assert!(cell.sib4_this_period && !cell.sib5_this_period);
+
+        // a cell's neighbors add up across the messages of a cycle
+        camp(&mut analyzer, MACRO_CELL);
+        analyze(&mut analyzer, &ie_sib4(4));
+        analyze(&mut analyzer, &ie_sib5(&[2]));
+        assert_eq!(analyzer.cells[&MACRO_CELL].total(), 7);
+    }
+
+    #[test]
+    fn test_imsi_exposure_escalates() {
+        let mut analyzer = SibNeighborListAnalyzer::new();
+        macro_cell(&mut analyzer);
+        camp(&mut analyzer, FAKE_CELL);
+        for _ in 0..EMPTY_PERIODS {
+            analyze(&mut analyzer, &ie_sib4(0));
+        }
+        let event = analyze(&mut analyzer, &ie_identity_request()).unwrap();
+        assert_eq!(event.event_type, EventType::Medium);
+        assert_eq!(event.details.unwrap().code, "empty_with_imsi_exposure");
+        assert_eq!(event_type(&mut analyzer, &ie_sib4(0)), None);
+    }
+
+    #[test]
+    fn test_imsi_exposure_before_empty_lists() {
+        let mut analyzer = SibNeighborListAnalyzer::new();
+        macro_cell(&mut analyzer);
+        camp(&mut analyzer, FAKE_CELL);
+        assert!(analyze(&mut analyzer, &ie_identity_request()).is_none());
+        for _ in 1..EMPTY_PERIODS {
+            assert_eq!(event_type(&mut analyzer, &ie_sib4(0)), None);
+        }
+        assert_eq!(
+            event_type(&mut analyzer, &ie_sib4(0)),
+            Some(EventType::Medium)
+        );
+
+        // the macro cell's own exposure means nothing, it has neighbors
+        camp(&mut analyzer, MACRO_CELL);
+        assert!(analyze(&mut analyzer, &ie_identity_request()).is_none());
+    }
+
+    #[test]
+    fn test_reset_clears_state() {
+        let mut analyzer = SibNeighborListAnalyzer::new();
+        macro_cell(&mut analyzer);
+        camp(&mut analyzer, FAKE_CELL);
+        for _ in 1..EMPTY_PERIODS {
+            analyze(&mut analyzer, &ie_sib4(0));
+        }
+        analyzer.reset();
+        assert_eq!(analyzer.cell_identity, None);
+        assert!(analyzer.cells.is_empty());
+
+        // the empty broadcasts from before the reset don't count anymore
+        macro_cell(&mut analyzer);
+        camp(&mut analyzer, FAKE_CELL);
+        for _ in 1..EMPTY_PERIODS {
+            assert_eq!(event_type(&mut analyzer, &ie_sib4(0)), None);
+        }
+        assert_eq!(event_type(&mut analyzer, &ie_sib4(0)), Some(EventType::Low));
+    }
+}
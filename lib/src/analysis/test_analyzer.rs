@@ -3,6 +3,7 @@ use std::borrow::Cow;
 use telcom_parser::lte_rrc::{BCCH_DL_SCH_MessageType, BCCH_DL_SCH_MessageType_c1};
 
 use super::analyzer::{Analyzer, Event, EventType};
+use super::event_message::EventDetails;
 use super::information_element::{InformationElement, LteInformationElement};
 use deku::bitvec::*;
 
@@ -23,6 +24,10 @@ impl Analyzer for TestAnalyzer {
         1
     }
 
+    fn get_severities(&self) -> Vec<EventType> {
+        vec![EventType::Low]
+    }
+
     fn analyze_information_element(
         &mut self,
         ie: &InformationElement,
@@ -65,13 +70,13 @@ impl Analyzer for TestAnalyzer {
                 mnc_string = format!("{:?}", mnc.0);
             }
 
-            return Some(Event {
-                event_type: EventType::Low,
-                message: format!(
-                    "SIB1 received CID: {}, TAC: {}, PLMN: {}-{}",
-                    cid, tac, mcc_string, mnc_string
-                ),
-            });
+            return Some(Event::new(
+                EventType::Low,
+                EventDetails::new("test_analyzer", "sib1")
+                    .with("cid", cid)
+                    .with("tac", tac)
+                    .with("plmn", format!("{mcc_string}-{mnc_string}")),
+            ));
         }
         None
     }
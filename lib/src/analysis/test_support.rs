@@ -0,0 +1,570 @@
+//! Builders for [InformationElement]s, for unit testing analyzers without
+//! constructing the generated NAS and RRC types by hand.
+//!
+//! Each builder decodes a small, hand-encoded message through the same
+//! GSMTAP path a real recording takes, so analyzers see exactly what they
+//! would in the field. Enable the `test-support` feature to use these outside
+//! of this crate's own tests.
+
+use super::information_element::InformationElement;
+use crate::gsmtap::{GsmtapHeader, GsmtapMessage, GsmtapType, LteNasSubtype, LteRrcSubtype};
+
+/// EPS encryption algorithm identifiers (TS 24.301 9.9.3.23)
+pub const EEA0: u8 = 0;
+pub const EEA2: u8 = 2;
+pub const EEA3: u8 = 3;
+
+/// EPS integrity algorithm identifiers (TS 24.301 9.9.3.23)
+pub const EIA0: u8 = 0;
+pub const EIA1: u8 = 1;
+pub const EIA2: u8 = 2;
+pub const EIA3: u8 = 3;
+
+/// EPS attach types (TS 24.301 9.9.3.11)
+pub const EPS_ATTACH: u8 = 1;
+pub const EPS_EMERGENCY_ATTACH: u8 = 6;
+
+/// The IMSI carried by [ie_paging_with_imsi], from the 001/01 test network
+pub const TEST_IMSI: [u8; 15] = [0, 0, 1, 0, 1, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+
+fn decode(gsmtap_type: GsmtapType, payload: &[u8]) -> InformationElement {
+    let gsmtap_msg = GsmtapMessage {
+        header: GsmtapHeader::new(gsmtap_type),
+        payload: payload.to_vec(),
+    };
+    InformationElement::try_from(&gsmtap_msg)
+        .unwrap_or_else(|e| panic!("failed to decode {gsmtap_type:?} {payload:02x?}: {e}"))
+}
+
+/// Decodes a plain (not security protected) LTE NAS message.
+pub fn ie_from_nas_bytes(payload: &[u8]) -> InformationElement {
+    decode(GsmtapType::LteNas(LteNasSubtype::Plain), payload)
+}
+
+/// Decodes a UPER-encoded LTE RRC message sent on the given channel.
+pub fn ie_from_lte_rrc_bytes(subtype: LteRrcSubtype, payload: &[u8]) -> InformationElement {
+    decode(GsmtapType::LteRrc(subtype), payload)
+}
+
+/// Decodes a UPER-encoded LTE RRC DL-DCCH message.
+pub fn ie_from_rrc_dl_dcch_bytes(payload: &[u8]) -> InformationElement {
+    ie_from_lte_rrc_bytes(LteRrcSubtype::DlDcch, payload)
+}
+
+/// The payload of an EMM Identity Request for the IMSI (TS 24.301 8.2.18).
+pub fn identity_request_payload() -> Vec<u8> {
+    vec![
+        0x07, // plain EMM message
+        0x55, // Identity Request
+        0x01, // identity type 2: IMSI
+    ]
+}
+
+/// An EMM Identity Request for the IMSI (TS 24.301 8.2.18).
+pub fn ie_identity_request() -> InformationElement {
+    ie_from_nas_bytes(&identity_request_payload())
+}
+
+/// The payload of an EMM Attach Reject with the given EMM cause (TS 24.301
+/// 8.2.3, 9.9.3.9).
+pub fn attach_reject_payload(cause: u8) -> Vec<u8> {
+    vec![
+        0x07, // plain EMM message
+        0x44, // Attach Reject
+        cause,
+    ]
+}
+
+/// An EMM Attach Reject with the given EMM cause (TS 24.301 8.2.3, 9.9.3.9).
+pub fn ie_attach_reject(cause: u8) -> InformationElement {
+    ie_from_nas_bytes(&attach_reject_payload(cause))
+}
+
+/// The payload of an EMM Authentication Response (TS 24.301 8.2.8).
+pub fn authentication_response_payload() -> Vec<u8> {
+    vec![
+        0x07, // plain EMM message
+        0x53, // Authentication Response
+        0x08, // RES length
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef,
+    ]
+}
+
+/// An EMM Authentication Response (TS 24.301 8.2.8).
+pub fn ie_authentication_response() -> InformationElement {
+    ie_from_nas_bytes(&authentication_response_payload())
+}
+
+/// An EMM Security Mode Command selecting the given EPS encryption algorithm
+/// and EIA2 for integrity (TS 24.301 8.2.20).
+pub fn ie_nas_security_mode_command(eea: u8) -> InformationElement {
+    ie_nas_security_mode_command_with_eia(eea, EIA2)
+}
+
+/// The payload of an EMM Security Mode Command selecting the given EPS
+/// encryption and integrity algorithms (TS 24.301 8.2.20).
+pub fn security_mode_command_payload(eea: u8, eia: u8) -> Vec<u8> {
+    vec![
+        0x07,             // plain EMM message
+        0x5d,             // Security Mode Command
+        (eea << 4) | eia, // selected NAS security algorithms
+        0x00,             // NAS key set identifier 0
+        0x02,             // replayed UE security capabilities length
+        0xe0,             // EEA0, 128-EEA1, 128-EEA2
+        0x60,             // 128-EIA1, 128-EIA2
+    ]
+}
+
+/// An EMM Security Mode Command selecting the given EPS encryption and
+/// integrity algorithms (TS 24.301 8.2.20).
+pub fn ie_nas_security_mode_command_with_eia(eea: u8, eia: u8) -> InformationElement {
+    ie_from_nas_bytes(&security_mode_command_payload(eea, eia))
+}
+
+/// The payload of an EMM Attach Request of the given EPS attach type,
+/// identifying the UE by [TEST_IMSI] (TS 24.301 8.2.4).
+pub fn attach_request_payload(attach_type: u8) -> Vec<u8> {
+    let mut payload = vec![
+        0x07,               // plain EMM message
+        0x41,               // Attach Request
+        0x70 | attach_type, // no NAS key set identifier, EPS attach type
+    ];
+    // EPS mobile identity: length, then the IMSI's digits in BCD
+    payload.extend([0x08, 0x09, 0x10, 0x10, 0x10, 0x32, 0x54, 0x76, 0x98]);
+    // UE network capability: length, EEA0-2, EIA0-2
+    payload.extend([0x02, 0xe0, 0xe0]);
+    // ESM message container: length, then an emergency PDN Connectivity
+    // Request for IPv4
+    payload.extend([0x00, 0x04, 0x02, 0x01, 0xd0, 0x14]);
+    payload
+}
+
+/// An EMM Attach Request of the given EPS attach type, identifying the UE by
+/// [TEST_IMSI] (TS 24.301 8.2.4).
+pub fn ie_attach_request(attach_type: u8) -> InformationElement {
+    ie_from_nas_bytes(&attach_request_payload(attach_type))
+}
+
+/// An EMM Downlink Generic NAS Transport carrying the given LPP message
+/// (TS 24.301 8.2.31).
+pub fn ie_dl_lpp(lpp: &[u8]) -> InformationElement {
+    ie_from_nas_bytes(&generic_nas_transport(0x68, lpp))
+}
+
+/// An EMM Uplink Generic NAS Transport carrying the given LPP message
+/// (TS 24.301 8.2.32).
+pub fn ie_ul_lpp(lpp: &[u8]) -> InformationElement {
+    ie_from_nas_bytes(&generic_nas_transport(0x69, lpp))
+}
+
+fn generic_nas_transport(message_type: u8, lpp: &[u8]) -> Vec<u8> {
+    let mut payload = vec![
+        0x07,         // plain EMM message
+        message_type, // Downlink or Uplink Generic NAS Transport
+        0x01,         // generic message container type: LPP
+    ];
+    payload.extend((lpp.len() as u16).to_be_bytes());
+    payload.extend(lpp);
+    payload
+}
+
+/// Extended Service Request service types for mobile originating and
+/// mobile terminating CS fallback (TS 24.301 9.9.3.27)
+pub const SERVICE_TYPE_MO_CSFB: u8 = 0;
+pub const SERVICE_TYPE_MT_CSFB: u8 = 1;
+
+/// An EMM Extended Service Request of the given service type, identifying
+/// the UE by its M-TMSI (TS 24.301 8.2.15).
+pub fn ie_ext_service_request(service_type: u8) -> InformationElement {
+    let mut payload = vec![
+        0x07,         // plain EMM message
+        0x4c,         // Extended Service Request
+        service_type, // NAS key set identifier 0, service type
+    ];
+    // M-TMSI: length, TMSI type with filler, then the TMSI itself
+    payload.extend([0x05, 0xf4, 0x12, 0x34, 0x56, 0x78]);
+    ie_from_nas_bytes(&payload)
+}
+
+/// An ESM Activate Dedicated EPS Bearer Context Request for a QCI 1 (voice)
+/// bearer, as set up for a VoLTE call (TS 24.301 8.3.3).
+pub fn ie_activate_dedicated_bearer() -> InformationElement {
+    let mut payload = vec![
+        0x62, // EPS bearer identity 6, ESM message
+        0x00, // no procedure transaction identity
+        0xc5, // Activate Dedicated EPS Bearer Context Request
+        0x05, // linked to EPS bearer identity 5
+    ];
+    // EPS QoS: length, QCI 1
+    payload.extend([0x01, 0x01]);
+    // TFT: length, then a new TFT with one bidirectional packet filter for
+    // local port 8000
+    payload.extend([0x07, 0x21, 0x31, 0x00, 0x03, 0x40, 0x1f, 0x40]);
+    ie_from_nas_bytes(&payload)
+}
+
+/// An ESM Deactivate EPS Bearer Context Request for the bearer
+/// [ie_activate_dedicated_bearer] sets up, as sent when a VoLTE call ends
+/// (TS 24.301 8.3.12).
+pub fn ie_deactivate_bearer() -> InformationElement {
+    ie_from_nas_bytes(&[
+        0x62, // EPS bearer identity 6, ESM message
+        0x00, // no procedure transaction identity
+        0xcd, // Deactivate EPS Bearer Context Request
+        0x24, // ESM cause 36: regular deactivation
+    ])
+}
+
+/// RRC connection establishment causes (TS 36.331 6.2.2)
+pub const ESTABLISHMENT_CAUSE_EMERGENCY: u8 = 0;
+pub const ESTABLISHMENT_CAUSE_MO_DATA: u8 = 4;
+
+/// An RRC Connection Request with a random UE identity and the given
+/// establishment cause (TS 36.331 6.2.2).
+pub fn ie_rrc_connection_request(cause: u8) -> InformationElement {
+    // c1, rrcConnectionRequest, rrcConnectionRequest-r8, randomValue (40
+    // zero bits), establishmentCause, spare
+    ie_from_lte_rrc_bytes(
+        LteRrcSubtype::UlCcch,
+        &[0x50, 0x00, 0x00, 0x00, 0x00, cause << 1],
+    )
+}
+
+/// The DL-DCCH payload of an RRC Connection Release with release cause
+/// "other" (TS 36.331 6.2.2).
+pub fn rrc_connection_release_payload() -> Vec<u8> {
+    // c1, rrcConnectionRelease (5), transaction 0, c1, rrcConnectionRelease-r8,
+    // no optional fields, releaseCause other (1)
+    vec![0x28, 0x02]
+}
+
+/// The DL-DCCH payload of an RRC Connection Release redirecting the UE to
+/// GERAN ARFCN 0 in the DCS 1800 band (TS 36.331 6.2.2).
+pub fn rrc_connection_release_redirect_to_geran_payload() -> Vec<u8> {
+    // as above, but with redirectedCarrierInfo present: geran (1),
+    // startingARFCN 0, dcs1800, an empty explicitListOfARFCNs
+    vec![0x28, 0x22, 0x20, 0x00, 0x00]
+}
+
+/// An RRC Connection Release with release cause "other" (TS 36.331 6.2.2).
+pub fn ie_rrc_connection_release() -> InformationElement {
+    ie_from_rrc_dl_dcch_bytes(&rrc_connection_release_payload())
+}
+
+/// MIB dl-Bandwidth values, in resource blocks (TS 36.331 6.2.2)
+pub const DL_BANDWIDTH_N50: u8 = 3;
+pub const DL_BANDWIDTH_N100: u8 = 5;
+
+/// A MIB with the given downlink bandwidth, broadcast in radio frame `sfn`,
+/// of which it carries the 8 most significant bits (TS 36.331 6.2.2).
+pub fn ie_mib(dl_bandwidth: u8, sfn: u16) -> InformationElement {
+    // dl-Bandwidth (3 bits), phich-Duration normal (1), phich-Resource one
+    // (2), systemFrameNumber (8), then schedulingInfoSIB1-BR-r13,
+    // systemInfoUnchanged-BR-r15, partEARFCN-17 and spare (10), all zero
+    let sfn_msb = ((sfn >> 2) & 0xff) as u32;
+    let bits = ((dl_bandwidth as u32) << 21) | (2 << 18) | (sfn_msb << 10);
+    ie_from_lte_rrc_bytes(LteRrcSubtype::BcchBch, &bits.to_be_bytes()[1..])
+}
+
+// Packs values most significant bit first, the way UPER does
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn push(&mut self, value: u32, width: usize) -> &mut Self {
+        for i in (0..width).rev() {
+            if self.bits % 8 == 0 {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+            }
+            self.bits += 1;
+        }
+        self
+    }
+}
+
+/// A SIB1 for the given cell, broadcasting a single PLMN with the given MCC
+/// and 2 or 3 digit MNC (TS 36.331 6.2.2).
+pub fn ie_sib1(mcc: [u8; 3], mnc: &[u8], cell_identity: u32) -> InformationElement {
+    let mut bits = BitWriter::default();
+    bits.push(0, 1) // c1
+        .push(1, 1) // systemInformationBlockType1
+        .push(0, 3) // no p-Max, tdd-Config or nonCriticalExtension
+        .push(0, 1) // no csg-Identity
+        .push(0, 3) // 1 PLMN
+        .push(1, 1); // with an MCC
+    for digit in mcc {
+        bits.push(digit.into(), 4);
+    }
+    bits.push(mnc.len() as u32 - 2, 1);
+    for &digit in mnc {
+        bits.push(digit.into(), 4);
+    }
+    bits.push(1, 1) // cellReservedForOperatorUse notReserved
+        .push(1, 16) // trackingAreaCode
+        .push(cell_identity, 28)
+        .push(1, 1) // cellBarred notBarred
+        .push(0, 1) // intraFreqReselection allowed
+        .push(0, 1) // csg-Indication false
+        .push(0, 1) // no q-RxLevMinOffset
+        .push(6, 6) // q-RxLevMin -64
+        .push(3, 6) // freqBandIndicator 4
+        .push(0, 5) // 1 SI message
+        .push(1, 3) // si-Periodicity rf16
+        .push(1, 5) // mapping 1 SIB
+        .push(0, 1) // not an extension value
+        .push(0, 4) // sibType3
+        .push(3, 3) // si-WindowLength ms10
+        .push(0, 5); // systemInfoValueTag
+    ie_from_lte_rrc_bytes(LteRrcSubtype::BcchDlSch, &bits.bytes)
+}
+
+/// A SystemInformation message carrying a SIB4 which lists `intra_freq`
+/// neighbor cells, leaving the list out when that's 0 (TS 36.331 6.2.2).
+pub fn ie_sib4(intra_freq: u32) -> InformationElement {
+    system_information(Some(intra_freq), None)
+}
+
+/// A SystemInformation message carrying a SIB5 with a carrier frequency for
+/// each entry of `cells_per_carrier`, listing that many neighbor cells on it
+/// (TS 36.331 6.2.2). A SIB5 lists at least one carrier.
+pub fn ie_sib5(cells_per_carrier: &[u32]) -> InformationElement {
+    system_information(None, Some(cells_per_carrier))
+}
+
+/// A SystemInformation message carrying both a SIB4 and a SIB5, as
+/// [ie_sib4] and [ie_sib5] build them.
+pub fn ie_sib4_and_sib5(intra_freq: u32, cells_per_carrier: &[u32]) -> InformationElement {
+    system_information(Some(intra_freq), Some(cells_per_carrier))
+}
+
+fn system_information(sib4: Option<u32>, sib5: Option<&[u32]>) -> InformationElement {
+    let entries = sib4.is_some() as u32 + sib5.is_some() as u32;
+    let mut bits = BitWriter::default();
+    bits.push(0, 1) // c1
+        .push(0, 1) // systemInformation
+        .push(0, 1) // systemInformation-r8
+        .push(0, 1) // no nonCriticalExtension
+        .push(entries - 1, 5); // sib-TypeAndInfo entries
+    if let Some(neighbors) = sib4 {
+        bits.push(0, 1) // not an extension value
+            .push(2, 4) // sib4
+            .push(0, 1) // no extensions
+            .push((neighbors > 0).into(), 1) // intraFreqNeighCellList
+            .push(0, 2); // no intraFreqExcludedCellList or csg-PhysCellIdRange
+        if neighbors > 0 {
+            bits.push(neighbors - 1, 4);
+            for phys_cell_id in 0..neighbors {
+                bits.push(0, 1) // no extensions
+                    .push(phys_cell_id, 9)
+                    .push(15, 5); // q-OffsetCell dB0
+            }
+        }
+    }
+    if let Some(cells_per_carrier) = sib5 {
+        bits.push(0, 1) // not an extension value
+            .push(3, 4) // sib5
+            .push(0, 1) // no extensions
+            .push(cells_per_carrier.len() as u32 - 1, 3);
+        for (carrier, &cells) in (0..).zip(cells_per_carrier) {
+            bits.push(0, 1) // no extensions
+                .push(((cells > 0) as u32) << 1, 6) // only interFreqNeighCellList
+                .push(2000 + carrier, 16) // dl-CarrierFreq
+                .push(6, 6) // q-RxLevMin -64
+                .push(1, 3) // t-ReselectionEUTRA
+                .push(10, 5) // threshX-High
+                .push(2, 5) // threshX-Low
+                .push(3, 3) // allowedMeasBandwidth mbw50
+                .push(0, 1) // presenceAntennaPort1 false
+                .push(1, 2); // neighCellConfig
+            if cells > 0 {
+                bits.push(cells - 1, 4);
+                for phys_cell_id in 0..cells {
+                    bits.push(phys_cell_id, 9).push(15, 5); // q-OffsetCell dB0
+                }
+            }
+        }
+    }
+    ie_from_lte_rrc_bytes(LteRrcSubtype::BcchDlSch, &bits.bytes)
+}
+
+/// A Paging message with a single paging record addressed to [TEST_IMSI]
+/// (TS 36.331 6.2.2).
+pub fn ie_paging_with_imsi() -> InformationElement {
+    // c1, paging, only pagingRecordList present, 1 record, imsi, 15 digits,
+    // the digits themselves, cn-Domain ps
+    ie_from_lte_rrc_bytes(
+        LteRrcSubtype::PCCH,
+        &[0x40, 0x19, 0x00, 0x10, 0x10, 0x12, 0x34, 0x56, 0x78, 0x90],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::information_element::LteInformationElement;
+    use pycrate_rs::nas::NASMessage;
+    use pycrate_rs::nas::emm::EMMMessage;
+    use pycrate_rs::nas::esm::ESMMessage;
+    use telcom_parser::lte_rrc::{
+        BCCH_DL_SCH_MessageType, BCCH_DL_SCH_MessageType_c1, DL_DCCH_MessageType,
+        DL_DCCH_MessageType_c1, PCCH_MessageType, PCCH_MessageType_c1, PagingUE_Identity,
+        SystemInformation_r8_IEsSib_TypeAndInfo_Entry, SystemInformationCriticalExtensions,
+    };
+
+    fn lte(ie: InformationElement) -> LteInformationElement {
+        match ie {
+            InformationElement::LTE(lte) => *lte,
+            other => panic!("expected an LTE information element, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_nas_builders() {
+        assert!(matches!(
+            lte(ie_identity_request()),
+            LteInformationElement::NAS(NASMessage::EMMMessage(EMMMessage::EMMIdentityRequest(_)))
+        ));
+        assert!(matches!(
+            lte(ie_attach_reject(3)),
+            LteInformationElement::NAS(NASMessage::EMMMessage(EMMMessage::EMMAttachReject(_)))
+        ));
+        assert!(matches!(
+            lte(ie_authentication_response()),
+            LteInformationElement::NAS(NASMessage::EMMMessage(
+                EMMMessage::EMMAuthenticationResponse(_)
+            ))
+        ));
+        assert!(matches!(
+            lte(ie_attach_request(EPS_EMERGENCY_ATTACH)),
+            LteInformationElement::NAS(NASMessage::EMMMessage(EMMMessage::EMMAttachRequest(_)))
+        ));
+        assert!(matches!(
+            lte(ie_nas_security_mode_command(EEA2)),
+            LteInformationElement::NAS(NASMessage::EMMMessage(EMMMessage::EMMSecurityModeCommand(
+                _
+            )))
+        ));
+        assert!(matches!(
+            lte(ie_ext_service_request(SERVICE_TYPE_MO_CSFB)),
+            LteInformationElement::NAS(NASMessage::EMMMessage(EMMMessage::EMMExtServiceRequest(_)))
+        ));
+        assert!(matches!(
+            lte(ie_activate_dedicated_bearer()),
+            LteInformationElement::NAS(NASMessage::ESMMessage(
+                ESMMessage::ESMActDediEPSBearerCtxtRequest(_)
+            ))
+        ));
+        assert!(matches!(
+            lte(ie_deactivate_bearer()),
+            LteInformationElement::NAS(NASMessage::ESMMessage(
+                ESMMessage::ESMDeactEPSBearerCtxtRequest(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_rrc_connection_release() {
+        let LteInformationElement::DlDcch(msg) = lte(ie_rrc_connection_release()) else {
+            panic!("expected a DL-DCCH message");
+        };
+        assert!(matches!(
+            msg.message,
+            DL_DCCH_MessageType::C1(DL_DCCH_MessageType_c1::RrcConnectionRelease(_))
+        ));
+    }
+
+    #[test]
+    fn test_mib() {
+        let LteInformationElement::BcchBch(msg) = lte(ie_mib(DL_BANDWIDTH_N50, 1000)) else {
+            panic!("expected a BCCH-BCH message");
+        };
+        assert_eq!(msg.message.dl_bandwidth.0, DL_BANDWIDTH_N50);
+        assert_eq!(msg.message.phich_config.phich_resource.0, 2);
+        let sfn_msb = msg
+            .message
+            .system_frame_number
+            .0
+            .iter()
+            .fold(0u16, |acc, bit| (acc << 1) | *bit as u16);
+        assert_eq!(sfn_msb, 1000 >> 2);
+    }
+
+    fn sib_entries(ie: InformationElement) -> Vec<SystemInformation_r8_IEsSib_TypeAndInfo_Entry> {
+        let LteInformationElement::BcchDlSch(msg) = lte(ie) else {
+            panic!("expected a BCCH-DL-SCH message");
+        };
+        let BCCH_DL_SCH_MessageType::C1(BCCH_DL_SCH_MessageType_c1::SystemInformation(si)) =
+            msg.message
+        else {
+            panic!("expected a SystemInformation message");
+        };
+        let SystemInformationCriticalExtensions::SystemInformation_r8(si) = si.critical_extensions
+        else {
+            panic!("expected systemInformation-r8");
+        };
+        si.sib_type_and_info.0
+    }
+
+    #[test]
+    fn test_sib4_and_sib5() {
+        let entries = sib_entries(ie_sib4(0));
+        let [SystemInformation_r8_IEsSib_TypeAndInfo_Entry::Sib4(sib4)] = entries.as_slice() else {
+            panic!("expected only a SIB4");
+        };
+        assert!(sib4.intra_freq_neigh_cell_list.is_none());
+
+        let entries = sib_entries(ie_sib5(&[0, 3]));
+        let [SystemInformation_r8_IEsSib_TypeAndInfo_Entry::Sib5(sib5)] = entries.as_slice() else {
+            panic!("expected only a SIB5");
+        };
+        let carriers = &sib5.inter_freq_carrier_freq_list.0;
+        assert_eq!(carriers.len(), 2);
+        assert!(carriers[0].inter_freq_neigh_cell_list.is_none());
+        assert_eq!(carriers[1].dl_carrier_freq.0, 2001);
+        assert_eq!(
+            carriers[1]
+                .inter_freq_neigh_cell_list
+                .as_ref()
+                .unwrap()
+                .0
+                .len(),
+            3
+        );
+
+        let entries = sib_entries(ie_sib4_and_sib5(4, &[2]));
+        let [
+            SystemInformation_r8_IEsSib_TypeAndInfo_Entry::Sib4(sib4),
+            SystemInformation_r8_IEsSib_TypeAndInfo_Entry::Sib5(sib5),
+        ] = entries.as_slice()
+        else {
+            panic!("expected a SIB4 and a SIB5");
+        };
+        let cells = &sib4.intra_freq_neigh_cell_list.as_ref().unwrap().0;
+        assert_eq!(cells.len(), 4);
+        assert_eq!(cells[3].phys_cell_id.0, 3);
+        assert_eq!(sib5.inter_freq_carrier_freq_list.0.len(), 1);
+    }
+
+    #[test]
+    fn test_paging_with_imsi() {
+        let LteInformationElement::PCCH(msg) = lte(ie_paging_with_imsi()) else {
+            panic!("expected a PCCH message");
+        };
+        let PCCH_MessageType::C1(PCCH_MessageType_c1::Paging(paging)) = msg.message else {
+            panic!("expected a Paging message");
+        };
+        let records = paging.paging_record_list.unwrap().0;
+        assert_eq!(records.len(), 1);
+        let PagingUE_Identity::Imsi(imsi) = &records[0].ue_identity else {
+            panic!("expected the record to carry an IMSI");
+        };
+        let digits: Vec<u8> = imsi.0.iter().map(|digit| digit.0).collect();
+        assert_eq!(digits, TEST_IMSI);
+    }
+}
@@ -1 +1,115 @@
+//! Parsing helpers shared between analyzers.
 
+use pycrate_rs::nas::NASMessage;
+use pycrate_rs::nas::emm::EMMMessage;
+use pycrate_rs::nas::generated::emm::emm_security_mode_command::{
+    NASSecAlgoCiphAlgo, NASSecAlgoIntegAlgo,
+};
+use telcom_parser::lte_rrc::{BCCH_DL_SCH_MessageType, BCCH_DL_SCH_MessageType_c1, MCC_MNC_Digit};
+
+use super::information_element::{InformationElement, LteInformationElement};
+
+/// The algorithms selected by a NAS Security Mode Command, by their number in
+/// TS 24.301 9.9.3.23, e.g. 0 for EEA0 and 2 for 128-EIA2. Values which are
+/// reserved for future algorithms are None.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NasSecurityAlgorithms {
+    pub eea: Option<u8>,
+    pub eia: Option<u8>,
+}
+
+fn eea_number(algo: &NASSecAlgoCiphAlgo) -> Option<u8> {
+    match algo {
+        NASSecAlgoCiphAlgo::EPSEncryptionAlgorithmEEA0Null => Some(0),
+        NASSecAlgoCiphAlgo::EPSEncryptionAlgorithm128EEA1 => Some(1),
+        NASSecAlgoCiphAlgo::EPSEncryptionAlgorithm128EEA2 => Some(2),
+        NASSecAlgoCiphAlgo::EPSEncryptionAlgorithm128EEA3 => Some(3),
+        _ => None,
+    }
+}
+
+fn eia_number(algo: &NASSecAlgoIntegAlgo) -> Option<u8> {
+    match algo {
+        NASSecAlgoIntegAlgo::EPSIntegrityAlgorithmEIA0Null => Some(0),
+        NASSecAlgoIntegAlgo::EPSIntegrityAlgorithm128EIA1 => Some(1),
+        NASSecAlgoIntegAlgo::EPSIntegrityAlgorithm128EIA2 => Some(2),
+        NASSecAlgoIntegAlgo::EPSIntegrityAlgorithm128EIA3 => Some(3),
+        _ => None,
+    }
+}
+
+/// Returns the LTE NAS message carried by the information element, if any.
+pub fn lte_nas_message(ie: &InformationElement) -> Option<&NASMessage> {
+    match ie {
+        InformationElement::LTE(inner) => match &**inner {
+            LteInformationElement::NAS(payload) => Some(payload),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the algorithms selected, if the information element is a NAS
+/// Security Mode Command.
+pub fn nas_security_mode_command_algorithms(
+    ie: &InformationElement,
+) -> Option<NasSecurityAlgorithms> {
+    match lte_nas_message(ie)? {
+        NASMessage::EMMMessage(EMMMessage::EMMSecurityModeCommand(req)) => {
+            Some(NasSecurityAlgorithms {
+                eea: eea_number(&req.nas_sec_algo.inner.ciph_algo),
+                eia: eia_number(&req.nas_sec_algo.inner.integ_algo),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Returns the first PLMN a cell broadcasts as "MCC-MNC", if the information
+/// element is a SIB1. The MNC's digits are kept exactly as broadcast, so e.g.
+/// "310-26" and "310-026" are different PLMNs.
+pub fn sib1_plmn(ie: &InformationElement) -> Option<String> {
+    let InformationElement::LTE(lte_ie) = ie else {
+        return None;
+    };
+    let LteInformationElement::BcchDlSch(sch_msg) = &**lte_ie else {
+        return None;
+    };
+    let BCCH_DL_SCH_MessageType::C1(BCCH_DL_SCH_MessageType_c1::SystemInformationBlockType1(sib1)) =
+        &sch_msg.message
+    else {
+        return None;
+    };
+    let plmn = &sib1
+        .cell_access_related_info
+        .plmn_identity_list
+        .0
+        .first()?
+        .plmn_identity;
+    let digits = |digits: &[MCC_MNC_Digit]| -> String {
+        digits
+            .iter()
+            .map(|digit| char::from(b'0' + digit.0))
+            .collect()
+    };
+    // a PLMN without an MCC shares the MCC of the one before it, and there
+    // isn't one before the first
+    let mcc = plmn.mcc.as_ref().map(|mcc| digits(&mcc.0))?;
+    Some(format!("{mcc}-{}", digits(&plmn.mnc.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::test_support::{ie_identity_request, ie_sib1};
+
+    #[test]
+    fn test_sib1_plmn_keeps_mnc_digits() {
+        let plmn = |mcc, mnc: &[u8]| sib1_plmn(&ie_sib1(mcc, mnc, 1));
+        assert_eq!(plmn([3, 1, 0], &[2, 6, 0]).as_deref(), Some("310-260"));
+        assert_eq!(plmn([3, 1, 0], &[0, 2, 6]).as_deref(), Some("310-026"));
+        assert_eq!(plmn([3, 1, 0], &[2, 6]).as_deref(), Some("310-26"));
+        assert_eq!(plmn([2, 6, 2], &[0, 1]).as_deref(), Some("262-01"));
+        assert_eq!(sib1_plmn(&ie_identity_request()), None);
+    }
+}
@@ -0,0 +1,92 @@
+use std::borrow::Cow;
+
+use super::analyzer::{Analyzer, Event, EventType};
+use super::event_message::EventDetails;
+use super::information_element::{InformationElement, UmtsInformationElement};
+use crate::umts_rrc::{CipheringAlgorithm, DlDcchMessage};
+
+pub struct WcdmaCipheringAnalyzer {}
+
+impl Analyzer for WcdmaCipheringAnalyzer {
+    fn get_name(&self) -> Cow<'_, str> {
+        Cow::from("WCDMA Null Cipher Requested")
+    }
+
+    fn get_description(&self) -> Cow<'_, str> {
+        Cow::from(
+            "Tests whether a UMTS RRC security mode command requests the null cipher (UEA0), or arrives without integrity protection",
+        )
+    }
+
+    fn get_version(&self) -> u32 {
+        1
+    }
+
+    fn get_severities(&self) -> Vec<EventType> {
+        vec![EventType::Medium, EventType::High]
+    }
+
+    fn analyze_information_element(
+        &mut self,
+        ie: &InformationElement,
+        _packet_num: usize,
+    ) -> Option<Event> {
+        let InformationElement::UMTS(UmtsInformationElement::DlDcch(
+            DlDcchMessage::SecurityModeCommand(command),
+        )) = ie
+        else {
+            return None;
+        };
+
+        if command.ciphering_algorithm == Some(CipheringAlgorithm::Uea0) {
+            return Some(Event::new(
+                EventType::High,
+                EventDetails::new("wcdma_null_cipher", "uea0"),
+            ));
+        }
+        // there's no UIA0 in UMTS, so an unprotected security mode command is
+        // the only way a cell can get away without integrity protection
+        if !command.integrity_protected {
+            return Some(Event::new(
+                EventType::Medium,
+                EventDetails::new("wcdma_null_cipher", "unprotected"),
+            ));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::umts_rrc::decode_dl_dcch;
+    use crate::umts_rrc::tests::{bits, r3_security_mode_command};
+
+    fn analyze(data: &[u8]) -> Option<Event> {
+        let ie = InformationElement::UMTS(UmtsInformationElement::DlDcch(
+            decode_dl_dcch(data).unwrap(),
+        ));
+        WcdmaCipheringAnalyzer {}.analyze_information_element(&ie, 0)
+    }
+
+    #[test]
+    fn test_uea1_is_not_flagged() {
+        assert!(analyze(&r3_security_mode_command("1")).is_none());
+    }
+
+    #[test]
+    fn test_uea0_is_high() {
+        let event = analyze(&r3_security_mode_command("0")).unwrap();
+        assert_eq!(event.event_type, EventType::High);
+    }
+
+    #[test]
+    fn test_unprotected_command_is_medium() {
+        let data = bits(&format!(
+            "0 10000 0 0 1 1 0 00 {cap} 00 0 1",
+            cap = "1100000000000000".repeat(2),
+        ));
+        let event = analyze(&data).unwrap();
+        assert_eq!(event.event_type, EventType::Medium);
+    }
+}
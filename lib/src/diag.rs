@@ -55,6 +55,7 @@ pub enum DataType {
 }
 
 #[derive(Debug, Clone, PartialEq, Error)]
+#[non_exhaustive]
 pub enum DiagParsingError {
     #[error("Failed to parse Message: {0}, data: {1:?}")]
     MessageParsingError(deku::DekuError, Vec<u8>),
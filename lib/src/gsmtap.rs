@@ -295,3 +295,11 @@ pub struct GsmtapMessage {
     pub header: GsmtapHeader,
     pub payload: Vec<u8>,
 }
+
+impl GsmtapMessage {
+    /// Serializes the message as it's sent in a GSMTAP UDP datagram, i.e.
+    /// without any IP or UDP headers.
+    pub fn to_udp_payload(&self) -> Result<Vec<u8>, DekuError> {
+        self.to_bytes()
+    }
+}
@@ -5,11 +5,14 @@ use log::error;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum GsmtapParserError {
     #[error("Invalid LteRrcOtaMessage ext header version {0}")]
     InvalidLteRrcOtaExtHeaderVersion(u8),
     #[error("Invalid LteRrcOtaMessage header/PDU number combination: {0}/{1}")]
     InvalidLteRrcOtaHeaderPduNum(u8, u8),
+    #[error("Invalid WcdmaSignallingMessage channel type {0}")]
+    InvalidWcdmaChannelType(u8),
 }
 
 pub fn parse(msg: Message) -> Result<Option<(Timestamp, GsmtapMessage)>, GsmtapParserError> {
@@ -152,9 +155,65 @@ fn log_to_gsmtap(value: LogBody) -> Result<Option<GsmtapMessage>, GsmtapParserEr
                 payload: msg,
             }))
         }
+        LogBody::WcdmaSignallingMessage {
+            channel_type, msg, ..
+        } => {
+            // based on https://github.com/fgsect/scat/blob/97442580e628de414c9f7c2a185f4e28d0ee7523/src/scat/parsers/qualcomm/diagwcdmalogparser.py
+            let subtype = match channel_type {
+                0 => UmtsRrcSubtype::UlCcch,
+                1 => UmtsRrcSubtype::UlDcch,
+                2 => UmtsRrcSubtype::DlCcch,
+                3 => UmtsRrcSubtype::DlDcch,
+                4 => UmtsRrcSubtype::BcchBch,
+                5 => UmtsRrcSubtype::BcchFach,
+                6 => UmtsRrcSubtype::Pcch,
+                7 => UmtsRrcSubtype::Mcch,
+                8 => UmtsRrcSubtype::Msch,
+                // scat treats these, which carry extension SIBs and the
+                // BCH's own transport blocks, as BCCH-BCH too
+                254 | 255 => UmtsRrcSubtype::BcchBch,
+                other => return Err(GsmtapParserError::InvalidWcdmaChannelType(other)),
+            };
+            let mut header = GsmtapHeader::new(GsmtapType::UmtsRrc(subtype));
+            header.uplink = matches!(subtype, UmtsRrcSubtype::UlCcch | UmtsRrcSubtype::UlDcch);
+            Ok(Some(GsmtapMessage {
+                header,
+                payload: msg,
+            }))
+        }
         _ => {
             error!("gsmtap_sink: ignoring unhandled log type: {value:?}");
             Ok(None)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wcdma_message(channel_type: u8) -> LogBody {
+        LogBody::WcdmaSignallingMessage {
+            channel_type,
+            radio_bearer: 0,
+            length: 1,
+            msg: vec![0],
+        }
+    }
+
+    #[test]
+    fn test_wcdma_bcch_bch_channel_types() {
+        for channel_type in [4, 254, 255] {
+            let msg = log_to_gsmtap(wcdma_message(channel_type)).unwrap().unwrap();
+            assert_eq!(
+                msg.header.gsmtap_type,
+                GsmtapType::UmtsRrc(UmtsRrcSubtype::BcchBch)
+            );
+            assert!(!msg.header.uplink);
+        }
+        assert!(matches!(
+            log_to_gsmtap(wcdma_message(9)),
+            Err(GsmtapParserError::InvalidWcdmaChannelType(9))
+        ));
+    }
+}
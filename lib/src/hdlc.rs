@@ -8,10 +8,11 @@ use crc::Crc;
 use thiserror::Error;
 
 use crate::diag::{
-    ESCAPED_MESSAGE_ESCAPE_CHAR, ESCAPED_MESSAGE_TERMINATOR, MESSAGE_ESCAPE_CHAR,
+    CRC_CCITT, ESCAPED_MESSAGE_ESCAPE_CHAR, ESCAPED_MESSAGE_TERMINATOR, MESSAGE_ESCAPE_CHAR,
     MESSAGE_TERMINATOR,
 };
 
+/// Why [hdlc_decode] couldn't unframe a frame
 #[derive(Debug, Clone, Error, PartialEq)]
 pub enum HdlcError {
     #[error("Invalid checksum (expected {0}, got {1})")]
@@ -26,6 +27,22 @@ pub enum HdlcError {
     TooShort,
 }
 
+/// Frames a diag payload the way the diag device does: escapes any
+/// terminator and escape bytes, then appends the payload's CRC-CCITT and a
+/// 0x7e terminator. Concatenating frames from this gives a valid QMDL file,
+/// which makes it handy for building test recordings.
+pub fn hdlc_encode(payload: &[u8]) -> Vec<u8> {
+    hdlc_encapsulate(payload, &CRC_CCITT)
+}
+
+/// The inverse of [hdlc_encode]: unescapes a single frame and checks its
+/// CRC, returning the payload.
+pub fn hdlc_decode(frame: &[u8]) -> Result<Vec<u8>, HdlcError> {
+    hdlc_decapsulate(frame, &CRC_CCITT)
+}
+
+// [hdlc_encode] with any CRC; only diag's is part of the stable API
+#[doc(hidden)]
 pub fn hdlc_encapsulate(data: &[u8], crc: &Crc<u16>) -> Vec<u8> {
     let mut result: Vec<u8> = Vec::with_capacity(data.len());
 
@@ -53,6 +70,8 @@ pub fn hdlc_encapsulate(data: &[u8], crc: &Crc<u16>) -> Vec<u8> {
     result
 }
 
+// [hdlc_decode] with any CRC, likewise
+#[doc(hidden)]
 pub fn hdlc_decapsulate(data: &[u8], crc: &Crc<u16>) -> Result<Vec<u8>, HdlcError> {
     if data.len() < 3 {
         return Err(HdlcError::TooShort);
@@ -106,4 +125,54 @@ mod tests {
         assert_eq!(&encapsulated, &expected);
         assert_eq!(hdlc_decapsulate(&encapsulated, &crc), Ok(data));
     }
+
+    /// A small xorshift generator, so the round trip test covers lots of
+    /// payloads while staying reproducible
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// A payload with plenty of the bytes which need escaping
+        fn payload(&mut self) -> Vec<u8> {
+            let len = (self.next() % 300) as usize;
+            (0..len)
+                .map(|_| match self.next() % 4 {
+                    0 => MESSAGE_TERMINATOR,
+                    1 => MESSAGE_ESCAPE_CHAR,
+                    _ => self.next() as u8,
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_hdlc_encode_round_trips() {
+        let mut rng = XorShift(0x5eed_cafe_f00d_d00d);
+        for _ in 0..1000 {
+            let payload = rng.payload();
+            let frame = hdlc_encode(&payload);
+            // the only terminator is the one at the end
+            assert_eq!(
+                frame.iter().position(|&b| b == MESSAGE_TERMINATOR),
+                Some(frame.len() - 1)
+            );
+            assert_eq!(hdlc_decode(&frame), Ok(payload));
+        }
+    }
+
+    #[test]
+    fn test_hdlc_decode_rejects_corruption() {
+        let mut frame = hdlc_encode(&[0x10, 0x20, 0x30]);
+        frame[1] ^= 0xff;
+        assert!(matches!(
+            hdlc_decode(&frame),
+            Err(HdlcError::InvalidChecksum(_, _))
+        ));
+    }
 }
@@ -1,29 +1,99 @@
+//! Decoding and analysis of Qualcomm diag logs for IMSI catcher detection.
+//!
+//! # Stable API
+//!
+//! These items follow semantic versioning, so they only change in a breaking
+//! way alongside a major version bump (or a minor one, while the version is
+//! 0.x), and each change is listed in `CHANGELOG.md`:
+//!
+//! * [qmdl::QmdlReader] and [qmdl::QmdlWriter], for reading and writing
+//!   recordings
+//! * [diag::MessagesContainer], which a [qmdl::QmdlReader] yields
+//! * [analysis::analyzer::Harness], [analysis::analyzer::AnalyzerConfig],
+//!   [analysis::analyzer::AnalysisRow] and [analysis::analyzer::ReportMetadata],
+//!   for running the analyzers over a recording
+//! * [location::GeoPoint], where a recording was made
+//! * [analysis::analyzer::Analyzer], [analysis::analyzer::Event] and
+//!   [analysis::analyzer::EventType], for writing analyzers of your own
+//! * [analysis::information_element::InformationElement], which analyzers
+//!   are given to inspect
+//! * [gsmtap_parser::parse] and [pcap::GsmtapPcapWriter], for converting
+//!   recordings to PCAP
+//! * [hdlc::hdlc_encode], [hdlc::hdlc_decode] and [hdlc::HdlcError], for
+//!   framing diag messages, e.g. to build test recordings
+//! * [Device]
+//!
+//! Enums and structs which are expected to grow are `#[non_exhaustive]`, so
+//! adding a variant or field to them isn't a breaking change. New methods on
+//! [analysis::analyzer::Analyzer] always come with a default implementation,
+//! so existing analyzers keep compiling.
+//!
+//! Everything else, including the modules hidden from these docs, is an
+//! implementation detail shared with the rest of rayhunter, and may change
+//! in any release.
+
 use serde::{Deserialize, Serialize};
 
 /// Initialize logging with the given default level, suppressing noisy warnings
 /// from hampi about undecoded ASN1 extensions. Respects `RUST_LOG` overrides.
 pub fn init_logging(default_level: log::LevelFilter) {
+    init_logging_with_target(default_level, env_logger::Target::Stderr);
+}
+
+/// Like [init_logging], but writes log lines to `writer` instead of stderr.
+pub fn init_logging_to_writer(
+    default_level: log::LevelFilter,
+    writer: Box<dyn std::io::Write + Send>,
+) {
+    init_logging_with_target(default_level, env_logger::Target::Pipe(writer));
+}
+
+fn init_logging_with_target(default_level: log::LevelFilter, target: env_logger::Target) {
+    // the logger itself lets everything through, so that set_log_level can
+    // raise the level later on
     env_logger::Builder::new()
-        .filter_level(default_level)
+        .filter_level(log::LevelFilter::Trace)
         //Filter out a stupid massive amount of uneccessary warnings from hampi about undecoded extensions
         .filter_module("asn1_codecs", log::LevelFilter::Error)
         .parse_default_env()
+        .target(target)
         .init();
+    set_log_level(default_level);
+}
+
+/// Changes the level set by [init_logging], unless it was overridden with
+/// `RUST_LOG`.
+pub fn set_log_level(level: log::LevelFilter) {
+    if std::env::var_os("RUST_LOG").is_none() {
+        log::set_max_level(level);
+    }
 }
 
 pub mod analysis;
+#[doc(hidden)]
 pub mod clock;
 pub mod diag;
 pub mod gsmtap;
 pub mod gsmtap_parser;
 pub mod hdlc;
+pub mod location;
+#[doc(hidden)]
 pub mod log_codes;
+#[doc(hidden)]
+pub mod lpp;
 pub mod pcap;
 pub mod qmdl;
+#[cfg(any(test, feature = "test-support"))]
+pub mod qmdl_fixture;
+pub mod redact;
+#[doc(hidden)]
+pub mod umts_rrc;
+#[doc(hidden)]
 pub mod util;
 
 // bin/check.rs may target windows and does not use this mod
 #[cfg(target_family = "unix")]
+#[doc(hidden)]
 pub mod diag_device;
 
 // re-export telcom_parser, since we use its types in our API
@@ -0,0 +1,15 @@
+//! Where the device was when it recorded something.
+
+use serde::{Deserialize, Serialize};
+
+/// A position from the device's GPS
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "apidocs", derive(utoipa::ToSchema))]
+pub struct GeoPoint {
+    /// Latitude in degrees, north of the equator being positive
+    pub lat: f64,
+    /// Longitude in degrees, east of Greenwich being positive
+    pub lon: f64,
+    /// Roughly how far off the position may be, in meters
+    pub accuracy_m: f32,
+}
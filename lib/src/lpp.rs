@@ -0,0 +1,211 @@
+//! A minimal decoder for LPP (LTE Positioning Protocol) messages, as
+//! specified in 3GPP TS 36.355. LPP travels between the device and the
+//! network's location server inside NAS Generic Transport messages. Like
+//! [crate::umts_rrc], this decodes the unaligned PER encoding by hand, and
+//! only as far as the message's transaction and type.
+
+use thiserror::Error;
+
+/// The generic message container type of LPP in NAS Generic Transport
+/// messages (TS 24.301 9.9.3.42)
+pub const NAS_CONTAINER_TYPE_LPP: u8 = 1;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum LppError {
+    #[error("LPP message ended unexpectedly at bit {0}")]
+    UnexpectedEnd(usize),
+}
+
+/// Which end started an LPP transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Initiator {
+    LocationServer,
+    TargetDevice,
+    /// A value added in a later release
+    Other,
+}
+
+/// The message types in the LPP-MessageBody c1 CHOICE
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LppMessageType {
+    RequestCapabilities,
+    ProvideCapabilities,
+    RequestAssistanceData,
+    ProvideAssistanceData,
+    RequestLocationInformation,
+    ProvideLocationInformation,
+    Abort,
+    Error,
+    /// A spare or extension message type
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LppMessage {
+    /// Who started the transaction this message belongs to, if it says
+    pub initiator: Option<Initiator>,
+    /// None if the message has no body, e.g. a bare acknowledgement
+    pub message_type: Option<LppMessageType>,
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0 }
+    }
+
+    fn read_bit(&mut self) -> Result<bool, LppError> {
+        let byte = self
+            .data
+            .get(self.pos / 8)
+            .ok_or(LppError::UnexpectedEnd(self.pos))?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1 == 1;
+        self.pos += 1;
+        Ok(bit)
+    }
+
+    fn read_bits(&mut self, n: usize) -> Result<u32, LppError> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | self.read_bit()? as u32;
+        }
+        Ok(value)
+    }
+}
+
+pub fn decode(data: &[u8]) -> Result<LppMessage, LppError> {
+    let mut reader = BitReader::new(data);
+    // LPP-Message ::= SEQUENCE { transactionID OPTIONAL, endTransaction,
+    //     sequenceNumber OPTIONAL, acknowledgement OPTIONAL,
+    //     lpp-MessageBody OPTIONAL }
+    let has_transaction_id = reader.read_bit()?;
+    let has_sequence_number = reader.read_bit()?;
+    let has_acknowledgement = reader.read_bit()?;
+    let has_body = reader.read_bit()?;
+
+    let initiator = if has_transaction_id {
+        // LPP-TransactionID ::= SEQUENCE { initiator, transactionNumber, ... }
+        reader.read_bit()?;
+        // Initiator ::= ENUMERATED { locationServer, targetDevice, ... }
+        let initiator = if reader.read_bit()? {
+            // an extension value, as a normally small number
+            reader.read_bits(7)?;
+            Initiator::Other
+        } else if reader.read_bit()? {
+            Initiator::TargetDevice
+        } else {
+            Initiator::LocationServer
+        };
+        // TransactionNumber ::= INTEGER (0..255)
+        reader.read_bits(8)?;
+        Some(initiator)
+    } else {
+        None
+    };
+    // endTransaction BOOLEAN
+    reader.read_bit()?;
+    if has_sequence_number {
+        // SequenceNumber ::= INTEGER (0..255)
+        reader.read_bits(8)?;
+    }
+    if has_acknowledgement {
+        // Acknowledgement ::= SEQUENCE { ackRequested, ackIndicator OPTIONAL }
+        let has_ack_indicator = reader.read_bit()?;
+        reader.read_bit()?;
+        if has_ack_indicator {
+            reader.read_bits(8)?;
+        }
+    }
+
+    let message_type = if !has_body {
+        None
+    } else if reader.read_bit()? {
+        // messageClassExtension
+        Some(LppMessageType::Other)
+    } else {
+        // c1 ::= CHOICE { 8 message types, spare7 .. spare0 }
+        Some(match reader.read_bits(4)? {
+            0 => LppMessageType::RequestCapabilities,
+            1 => LppMessageType::ProvideCapabilities,
+            2 => LppMessageType::RequestAssistanceData,
+            3 => LppMessageType::ProvideAssistanceData,
+            4 => LppMessageType::RequestLocationInformation,
+            5 => LppMessageType::ProvideLocationInformation,
+            6 => LppMessageType::Abort,
+            7 => LppMessageType::Error,
+            _ => LppMessageType::Other,
+        })
+    };
+    Ok(LppMessage {
+        initiator,
+        message_type,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::umts_rrc::tests::bits;
+
+    /// Builds the start of an LPP message of the given type (its index in the
+    /// c1 CHOICE) in a transaction started by the location server. The
+    /// message's body is left out, since we never decode it.
+    pub(crate) fn server_message(message_type: u32) -> Vec<u8> {
+        bits(&format!("1001 0 0 0 00000001 0 0 {message_type:04b}"))
+    }
+
+    #[test]
+    fn test_decode_server_request() {
+        assert_eq!(
+            decode(&server_message(4)),
+            Ok(LppMessage {
+                initiator: Some(Initiator::LocationServer),
+                message_type: Some(LppMessageType::RequestLocationInformation),
+            })
+        );
+        assert_eq!(
+            decode(&server_message(0)).unwrap().message_type,
+            Some(LppMessageType::RequestCapabilities)
+        );
+        assert_eq!(
+            decode(&server_message(12)).unwrap().message_type,
+            Some(LppMessageType::Other)
+        );
+    }
+
+    #[test]
+    fn test_decode_device_message_with_sequence_and_ack() {
+        // transaction 2 started by the device, sequence number 3, ack
+        // requested with indicator 3, provideLocationInformation
+        let data = bits("1111 0 0 1 00000010 0 00000011 1 1 00000011 0 0101");
+        assert_eq!(
+            decode(&data),
+            Ok(LppMessage {
+                initiator: Some(Initiator::TargetDevice),
+                message_type: Some(LppMessageType::ProvideLocationInformation),
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_bare_acknowledgement() {
+        // no transaction or body, just an acknowledgement of sequence number 7
+        let data = bits("0010 0 1 0 00000111");
+        assert_eq!(
+            decode(&data),
+            Ok(LppMessage {
+                initiator: None,
+                message_type: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        assert_eq!(decode(&[0x90]), Err(LppError::UnexpectedEnd(8)));
+    }
+}
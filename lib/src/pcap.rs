@@ -1,20 +1,26 @@
 //! Parse QMDL files and create a pcap file.
 //! Creates a plausible IP header and [GSMtap](https://osmocom.org/projects/baseband/wiki/GSMTAP) header and then puts the rest of the data under that for wireshark to parse.
+//! Output is pcapng by default, or classic pcap for tools which don't support pcapng. The packets are the same either way.
 use crate::diag::Timestamp;
-use crate::gsmtap::GsmtapMessage;
+use crate::gsmtap::{GsmtapMessage, GsmtapType};
+use crate::redact::redact_gsmtap_message;
 
 use chrono::prelude::*;
 use deku::prelude::*;
+use pcap_file_tokio::pcap::{PcapHeader, PcapPacket, PcapWriter};
 use pcap_file_tokio::pcapng::PcapNgWriter;
-use pcap_file_tokio::pcapng::blocks::enhanced_packet::EnhancedPacketBlock;
+use pcap_file_tokio::pcapng::blocks::enhanced_packet::{EnhancedPacketBlock, EnhancedPacketOption};
 use pcap_file_tokio::pcapng::blocks::interface_description::InterfaceDescriptionBlock;
 use pcap_file_tokio::pcapng::blocks::section_header::{SectionHeaderBlock, SectionHeaderOption};
-use pcap_file_tokio::{Endianness, PcapError};
+use pcap_file_tokio::{DataLink, Endianness, PcapError, TsResolution};
+use serde::Deserialize;
 use std::borrow::Cow;
+use std::str::FromStr;
 use thiserror::Error;
 use tokio::io::AsyncWrite;
 
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum GsmtapPcapError {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
@@ -26,14 +32,139 @@ pub enum GsmtapPcapError {
     Deku(#[from] DekuError),
 }
 
+/// The container format to write packets in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PcapFormat {
+    #[default]
+    PcapNg,
+    /// Classic libpcap format
+    Pcap,
+}
+
+impl PcapFormat {
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            PcapFormat::PcapNg => "pcapng",
+            PcapFormat::Pcap => "pcap",
+        }
+    }
+}
+
+/// A kind of message which can be picked out with a [MessageFilter]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    LteNas,
+    LteRrc,
+    /// GSM/UMTS NAS (3GPP TS 24.008), which GSMTAP carries as Abis
+    UmtsNas,
+    UmtsRrc,
+    /// GSM air interface and GPRS messages
+    Gsm,
+}
+
+impl MessageKind {
+    pub const ALL: [MessageKind; 5] = [
+        MessageKind::LteNas,
+        MessageKind::LteRrc,
+        MessageKind::UmtsNas,
+        MessageKind::UmtsRrc,
+        MessageKind::Gsm,
+    ];
+
+    /// The name used in a [MessageFilter] string
+    pub fn name(&self) -> &'static str {
+        match self {
+            MessageKind::LteNas => "lte_nas",
+            MessageKind::LteRrc => "lte_rrc",
+            MessageKind::UmtsNas => "umts_nas",
+            MessageKind::UmtsRrc => "umts_rrc",
+            MessageKind::Gsm => "gsm",
+        }
+    }
+
+    pub fn of(gsmtap_type: &GsmtapType) -> Option<Self> {
+        match gsmtap_type {
+            GsmtapType::LteNas(_) => Some(MessageKind::LteNas),
+            GsmtapType::LteRrc(_) => Some(MessageKind::LteRrc),
+            GsmtapType::Abis => Some(MessageKind::UmtsNas),
+            GsmtapType::UmtsRrc(_) => Some(MessageKind::UmtsRrc),
+            GsmtapType::Um(_)
+            | GsmtapType::UmBurst
+            | GsmtapType::GbLlc
+            | GsmtapType::GbSndcp
+            | GsmtapType::GsmRlp => Some(MessageKind::Gsm),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+#[error("unknown message type \"{0}\", expected lte_nas, lte_rrc, umts_nas, umts_rrc or gsm")]
+pub struct UnknownMessageKind(pub String);
+
+/// Which kinds of message a [GsmtapPcapWriter] writes. The default writes
+/// every message.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MessageFilter {
+    // empty means every message, including kinds we have no name for
+    kinds: Vec<MessageKind>,
+}
+
+impl MessageFilter {
+    pub fn new(kinds: &[MessageKind]) -> Self {
+        MessageFilter {
+            kinds: kinds.to_vec(),
+        }
+    }
+
+    pub fn matches(&self, gsmtap_type: &GsmtapType) -> bool {
+        self.kinds.is_empty()
+            || MessageKind::of(gsmtap_type).is_some_and(|kind| self.kinds.contains(&kind))
+    }
+}
+
+/// Parses a comma-separated list of [MessageKind] names, e.g. `lte_nas,lte_rrc`
+impl FromStr for MessageFilter {
+    type Err = UnknownMessageKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut kinds = Vec::new();
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let kind = MessageKind::ALL
+                .into_iter()
+                .find(|kind| kind.name() == name)
+                .ok_or_else(|| UnknownMessageKind(name.to_string()))?;
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+        Ok(MessageFilter { kinds })
+    }
+}
+
+enum PcapContainerWriter<T>
+where
+    T: AsyncWrite,
+{
+    PcapNg(PcapNgWriter<T>),
+    Pcap(PcapWriter<T>),
+}
+
 pub struct GsmtapPcapWriter<T>
 where
     T: AsyncWrite,
 {
-    writer: PcapNgWriter<T>,
+    writer: PcapContainerWriter<T>,
     ip_id: u16,
+    redact_identifiers: bool,
+    message_filter: MessageFilter,
+    next_packet_comment: Option<String>,
 }
 
+/// The section header comment of pcapng files from [GsmtapPcapWriter::new_redacted]
+pub const REDACTED_COMMENT: &str = "IMSIs, IMEIs and phone numbers have been redacted";
+
 const IP_HEADER_LEN: u16 = 20;
 #[derive(DekuWrite)]
 #[deku(endian = "big")]
@@ -67,52 +198,120 @@ where
     T: AsyncWrite + Unpin + Send,
 {
     pub async fn new(writer: T) -> Result<Self, GsmtapPcapError> {
+        Self::new_with_format(writer, PcapFormat::PcapNg).await
+    }
+
+    pub async fn new_with_format(writer: T, format: PcapFormat) -> Result<Self, GsmtapPcapError> {
+        Self::create(writer, format, false).await
+    }
+
+    /// Creates a writer which redacts subscriber identifiers from every
+    /// message with [redact_gsmtap_message]. pcapng files are marked as
+    /// redacted with a comment in their section header.
+    pub async fn new_redacted(writer: T, format: PcapFormat) -> Result<Self, GsmtapPcapError> {
+        Self::create(writer, format, true).await
+    }
+
+    async fn create(
+        writer: T,
+        format: PcapFormat,
+        redact_identifiers: bool,
+    ) -> Result<Self, GsmtapPcapError> {
+        let writer = match format {
+            PcapFormat::PcapNg => PcapContainerWriter::PcapNg(
+                Self::new_pcapng_writer(writer, redact_identifiers).await?,
+            ),
+            PcapFormat::Pcap => {
+                let header = PcapHeader {
+                    snaplen: 0xffff,
+                    datalink: DataLink::IPV4,
+                    ts_resolution: TsResolution::MicroSecond,
+                    endianness: Endianness::Big,
+                    ..Default::default()
+                };
+                PcapContainerWriter::Pcap(PcapWriter::with_header(writer, header).await?)
+            }
+        };
+        Ok(GsmtapPcapWriter {
+            writer,
+            ip_id: 0,
+            redact_identifiers,
+            message_filter: MessageFilter::default(),
+            next_packet_comment: None,
+        })
+    }
+
+    async fn new_pcapng_writer(
+        writer: T,
+        redacted: bool,
+    ) -> Result<PcapNgWriter<T>, GsmtapPcapError> {
         let metadata = crate::util::RuntimeMetadata::new();
         let package = format!(
             "{} {}",
             env!("CARGO_PKG_NAME").to_owned(),
             metadata.rayhunter_version
         );
+        let mut options = vec![
+            SectionHeaderOption::Hardware(Cow::from(metadata.arch)),
+            SectionHeaderOption::OS(Cow::from(metadata.system_os)),
+            SectionHeaderOption::UserApplication(Cow::from(package)),
+        ];
+        if redacted {
+            options.push(SectionHeaderOption::Comment(Cow::from(REDACTED_COMMENT)));
+        }
         let section = SectionHeaderBlock {
             endianness: Endianness::Big,
             major_version: 1,
             minor_version: 0,
             section_length: -1,
-            options: vec![
-                SectionHeaderOption::Hardware(Cow::from(metadata.arch)),
-                SectionHeaderOption::OS(Cow::from(metadata.system_os)),
-                SectionHeaderOption::UserApplication(Cow::from(package)),
-            ],
+            options,
         };
-        let writer = PcapNgWriter::with_section_header(writer, section).await?;
-        Ok(GsmtapPcapWriter { writer, ip_id: 0 })
+        Ok(PcapNgWriter::with_section_header(writer, section).await?)
     }
 
+    /// Writes the pcapng interface description block. Classic pcap files
+    /// carry the link type in their file header, so this is a no-op for them.
     pub async fn write_iface_header(&mut self) -> Result<(), GsmtapPcapError> {
-        let interface = InterfaceDescriptionBlock {
-            linktype: pcap_file_tokio::DataLink::IPV4,
-            snaplen: 0xffff,
-            options: vec![],
-        };
-        self.writer.write_pcapng_block(interface).await?;
+        if let PcapContainerWriter::PcapNg(writer) = &mut self.writer {
+            let interface = InterfaceDescriptionBlock {
+                linktype: DataLink::IPV4,
+                snaplen: 0xffff,
+                options: vec![],
+            };
+            writer.write_pcapng_block(interface).await?;
+        }
         Ok(())
     }
 
+    /// Only write messages which match `filter` from now on.
+    pub fn set_message_filter(&mut self, filter: MessageFilter) {
+        self.message_filter = filter;
+    }
+
+    /// Attaches a comment to the next packet written, e.g. to mark a gap in
+    /// the recording before it. Classic pcap files can't hold comments, so
+    /// it's dropped for them.
+    pub fn comment_next_packet(&mut self, comment: String) {
+        self.next_packet_comment = Some(comment);
+    }
+
+    /// Writes a message as a packet, unless the message filter leaves it out.
     pub async fn write_gsmtap_message(
         &mut self,
-        msg: GsmtapMessage,
+        mut msg: GsmtapMessage,
         timestamp: Timestamp,
     ) -> Result<(), GsmtapPcapError> {
+        if !self.message_filter.matches(&msg.header.gsmtap_type) {
+            return Ok(());
+        }
+        if self.redact_identifiers {
+            redact_gsmtap_message(&mut msg);
+        }
         let duration = timestamp
             .to_datetime()
             .signed_duration_since(DateTime::UNIX_EPOCH)
             .to_std()?;
 
-        // despite the timestamp above being correct, we have reduce it by
-        // orders of magnitude due to a bug in pcap_file:
-        // https://github.com/courvoif/pcap-file/pull/32
-        let duration = std::time::Duration::from_nanos(duration.as_micros() as u64);
-
         let msg_bytes = msg.to_bytes()?;
         let ip_header = IpHeader {
             version_and_ihl: 0x45,
@@ -137,15 +336,240 @@ where
         data.extend(&ip_header.to_bytes()?);
         data.extend(&udp_header.to_bytes()?);
         data.extend(&msg_bytes);
-        let packet = EnhancedPacketBlock {
-            interface_id: 0,
-            timestamp: duration,
-            original_len: data.len() as u32,
-            data: Cow::Owned(data),
-            options: vec![],
-        };
-        self.writer.write_pcapng_block(packet).await?;
+        match &mut self.writer {
+            PcapContainerWriter::PcapNg(writer) => {
+                // despite the timestamp above being correct, we have reduce it by
+                // orders of magnitude due to a bug in pcap_file:
+                // https://github.com/courvoif/pcap-file/pull/32
+                let duration = std::time::Duration::from_nanos(duration.as_micros() as u64);
+                let options = self
+                    .next_packet_comment
+                    .take()
+                    .map(|comment| EnhancedPacketOption::Comment(Cow::Owned(comment)))
+                    .into_iter()
+                    .collect();
+                let packet = EnhancedPacketBlock {
+                    interface_id: 0,
+                    timestamp: duration,
+                    original_len: data.len() as u32,
+                    data: Cow::Owned(data),
+                    options,
+                };
+                writer.write_pcapng_block(packet).await?;
+            }
+            PcapContainerWriter::Pcap(writer) => {
+                self.next_packet_comment = None;
+                let packet = PcapPacket::new(duration, data.len() as u32, &data);
+                writer.write_packet(&packet).await?;
+            }
+        }
         self.ip_id = self.ip_id.wrapping_add(1);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gsmtap::{GsmtapHeader, GsmtapType, LteNasSubtype};
+    use pcap_file_tokio::pcap::PcapReader;
+    use pcap_file_tokio::pcapng::{Block, PcapNgReader};
+
+    async fn write_messages(format: PcapFormat) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut writer = GsmtapPcapWriter::new_with_format(&mut output, format)
+            .await
+            .unwrap();
+        writer.write_iface_header().await.unwrap();
+        for i in 0..3u8 {
+            let msg = GsmtapMessage {
+                header: GsmtapHeader::new(GsmtapType::Abis),
+                payload: vec![i; 4 + i as usize],
+            };
+            let timestamp = Timestamp {
+                ts: 0x0000_dead_0000 + i as u64,
+            };
+            writer.write_gsmtap_message(msg, timestamp).await.unwrap();
+        }
+        output
+    }
+
+    #[tokio::test]
+    async fn test_pcap_and_pcapng_contain_the_same_packets() {
+        let pcapng = write_messages(PcapFormat::PcapNg).await;
+        let mut pcapng_reader = PcapNgReader::new(pcapng.as_slice()).await.unwrap();
+        let mut pcapng_packets = Vec::new();
+        while let Some(block) = pcapng_reader.next_block().await {
+            if let Block::EnhancedPacket(packet) = block.unwrap() {
+                pcapng_packets.push(packet.data.into_owned());
+            }
+        }
+
+        let pcap = write_messages(PcapFormat::Pcap).await;
+        let mut pcap_reader = PcapReader::new(pcap.as_slice()).await.unwrap();
+        let mut pcap_packets = Vec::new();
+        while let Some(packet) = pcap_reader.next_packet().await {
+            pcap_packets.push(packet.unwrap().data.into_owned());
+        }
+
+        assert_eq!(pcapng_packets.len(), 3);
+        assert_eq!(pcapng_packets, pcap_packets);
+    }
+
+    #[tokio::test]
+    async fn test_comment_next_packet() {
+        let mut output = Vec::new();
+        let mut writer = GsmtapPcapWriter::new(&mut output).await.unwrap();
+        writer.write_iface_header().await.unwrap();
+        for i in 0..3u8 {
+            if i == 1 {
+                writer.comment_next_packet("gap".to_string());
+            }
+            let msg = GsmtapMessage {
+                header: GsmtapHeader::new(GsmtapType::Abis),
+                payload: vec![i; 4],
+            };
+            let timestamp = Timestamp {
+                ts: 0x0000_dead_0000 + i as u64,
+            };
+            writer.write_gsmtap_message(msg, timestamp).await.unwrap();
+        }
+
+        let mut pcapng_reader = PcapNgReader::new(output.as_slice()).await.unwrap();
+        let mut comments = Vec::new();
+        while let Some(block) = pcapng_reader.next_block().await {
+            if let Block::EnhancedPacket(packet) = block.unwrap() {
+                comments.push(packet.options.iter().find_map(|option| match option {
+                    EnhancedPacketOption::Comment(comment) => Some(comment.to_string()),
+                    _ => None,
+                }));
+            }
+        }
+        assert_eq!(comments, [None, Some("gap".to_string()), None]);
+    }
+
+    #[tokio::test]
+    async fn test_redacted_pcap_zeroes_msin() {
+        use crate::analysis::information_element::{InformationElement, LteInformationElement};
+        use crate::analysis::test_support::{EPS_ATTACH, attach_request_payload};
+        use crate::qmdl::QmdlReader;
+        use crate::qmdl_fixture::{FixtureMessage, QmdlFixtureBuilder};
+        use pycrate_rs::nas::NASMessage;
+        use pycrate_rs::nas::emm::EMMMessage;
+
+        // an Attach Request identifying the UE by IMSI 001010123456789
+        let attach_request = attach_request_payload(EPS_ATTACH);
+        let qmdl = QmdlFixtureBuilder::new()
+            .message(FixtureMessage::Nas {
+                uplink: true,
+                payload: attach_request.clone(),
+            })
+            .build();
+
+        let mut output = Vec::new();
+        let mut writer = GsmtapPcapWriter::new_redacted(&mut output, PcapFormat::PcapNg)
+            .await
+            .unwrap();
+        writer.write_iface_header().await.unwrap();
+        let mut reader = QmdlReader::new(qmdl.as_slice(), Some(qmdl.len()));
+        while let Some(container) = reader.get_next_messages_container().await.unwrap() {
+            for msg in container.into_messages() {
+                let (timestamp, gsmtap_msg) =
+                    crate::gsmtap_parser::parse(msg.unwrap()).unwrap().unwrap();
+                writer
+                    .write_gsmtap_message(gsmtap_msg, timestamp)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let mut pcapng_reader = PcapNgReader::new(output.as_slice()).await.unwrap();
+        let comments: Vec<&str> = pcapng_reader
+            .section()
+            .options
+            .iter()
+            .filter_map(|option| match option {
+                SectionHeaderOption::Comment(comment) => Some(comment.as_ref()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(comments, [REDACTED_COMMENT]);
+        let mut packets = Vec::new();
+        while let Some(block) = pcapng_reader.next_block().await {
+            if let Block::EnhancedPacket(packet) = block.unwrap() {
+                packets.push(packet.data.into_owned());
+            }
+        }
+        assert_eq!(packets.len(), 1);
+
+        // after the IP, UDP and GSMTAP headers
+        let nas = &packets[0][(IP_HEADER_LEN + UDP_HEADER_LEN + 16) as usize..];
+        let mut expected = attach_request.clone();
+        expected[7..12].fill(0);
+        assert_eq!(nas, expected);
+
+        // and it's still an Attach Request
+        let redacted = GsmtapMessage {
+            header: GsmtapHeader::new(GsmtapType::LteNas(LteNasSubtype::Plain)),
+            payload: nas.to_vec(),
+        };
+        let InformationElement::LTE(lte) = InformationElement::try_from(&redacted).unwrap() else {
+            panic!("expected an LTE information element");
+        };
+        assert!(matches!(
+            *lte,
+            LteInformationElement::NAS(NASMessage::EMMMessage(EMMMessage::EMMAttachRequest(_)))
+        ));
+    }
+
+    #[test]
+    fn test_message_filter_from_str() {
+        assert_eq!("".parse(), Ok(MessageFilter::default()));
+        assert_eq!(
+            "lte_nas, lte_rrc,lte_nas".parse(),
+            Ok(MessageFilter::new(&[
+                MessageKind::LteNas,
+                MessageKind::LteRrc
+            ]))
+        );
+        assert_eq!(
+            "lte_nas,wifi".parse::<MessageFilter>(),
+            Err(UnknownMessageKind("wifi".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_message_filter_skips_other_messages() {
+        use crate::gsmtap::{LteRrcSubtype, UmSubtype};
+
+        let mut output = Vec::new();
+        let mut writer = GsmtapPcapWriter::new(&mut output).await.unwrap();
+        writer.set_message_filter("lte_nas,gsm".parse().unwrap());
+        writer.write_iface_header().await.unwrap();
+        let types = [
+            GsmtapType::LteRrc(LteRrcSubtype::PCCH),
+            GsmtapType::LteNas(LteNasSubtype::Plain),
+            GsmtapType::Um(UmSubtype::Bcch),
+            GsmtapType::QcDiag,
+        ];
+        for (i, gsmtap_type) in types.into_iter().enumerate() {
+            let msg = GsmtapMessage {
+                header: GsmtapHeader::new(gsmtap_type),
+                payload: vec![i as u8; 4],
+            };
+            let timestamp = Timestamp {
+                ts: 0x0000_dead_0000,
+            };
+            writer.write_gsmtap_message(msg, timestamp).await.unwrap();
+        }
+
+        let mut reader = PcapNgReader::new(output.as_slice()).await.unwrap();
+        let mut payloads = Vec::new();
+        while let Some(block) = reader.next_block().await {
+            if let Block::EnhancedPacket(packet) = block.unwrap() {
+                payloads.push(packet.data[packet.data.len() - 1]);
+            }
+        }
+        assert_eq!(payloads, [1, 2]);
+    }
+}
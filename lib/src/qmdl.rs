@@ -6,7 +6,7 @@
 use crate::diag::{DataType, HdlcEncapsulatedMessage, MESSAGE_TERMINATOR, MessagesContainer};
 
 use futures::TryStream;
-use log::error;
+use log::{error, warn};
 use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 
 pub struct QmdlWriter<T>
@@ -48,6 +48,7 @@ where
     reader: BufReader<T>,
     bytes_read: usize,
     max_bytes: Option<usize>,
+    truncated_bytes: usize,
 }
 
 impl<T> QmdlReader<T>
@@ -59,9 +60,23 @@ where
             reader: BufReader::new(reader),
             bytes_read: 0,
             max_bytes,
+            truncated_bytes: 0,
         }
     }
 
+    /// How far into the file the reader is, which is where the next message
+    /// starts.
+    pub fn bytes_read(&self) -> usize {
+        self.bytes_read
+    }
+
+    /// How many bytes of a partial message were discarded from the end of
+    /// the file, e.g. because the device lost power while it was being
+    /// written. This is only known once the reader has returned None.
+    pub fn truncated_bytes(&self) -> usize {
+        self.truncated_bytes
+    }
+
     pub fn as_stream(
         &mut self,
     ) -> impl TryStream<Ok = MessagesContainer, Error = std::io::Error> + '_ {
@@ -74,6 +89,9 @@ where
         })
     }
 
+    /// Reads the next message, or None at the end of the file. A partial
+    /// message at the end of the file is treated as the end rather than an
+    /// error, so interrupted recordings can still be read up to that point.
     pub async fn get_next_messages_container(
         &mut self,
     ) -> Result<Option<MessagesContainer>, std::io::Error> {
@@ -92,6 +110,18 @@ where
         let mut buf = Vec::new();
         let bytes_read = self.reader.read_until(MESSAGE_TERMINATOR, &mut buf).await?;
         self.bytes_read += bytes_read;
+        if bytes_read == 0 {
+            return Ok(None);
+        }
+        // read_until only stops short of the terminator at the end of the
+        // file, so this is what's left of a message which was never finished
+        if buf.last() != Some(&MESSAGE_TERMINATOR) {
+            warn!(
+                "discarding {bytes_read} bytes of a truncated message at the end of the QMDL file"
+            );
+            self.truncated_bytes = bytes_read;
+            return Ok(None);
+        }
 
         // Since QMDL is just a flat list of messages, we can't actually
         // reproduce the container structure they came from in the original
@@ -162,7 +192,9 @@ mod test {
         let mut buf = Cursor::new(get_test_message_bytes());
         let mut reader = QmdlReader::new(&mut buf, None);
         let expected_messages = get_test_messages();
+        let mut offset = 0;
         for message in expected_messages {
+            offset += message.len as usize;
             let expected_container = MessagesContainer {
                 data_type: DataType::UserSpace,
                 num_messages: 1,
@@ -172,6 +204,7 @@ mod test {
                 expected_container,
                 reader.get_next_messages_container().await.unwrap().unwrap()
             );
+            assert_eq!(reader.bytes_read(), offset);
         }
     }
 
@@ -201,6 +234,43 @@ mod test {
         ));
     }
 
+    #[tokio::test]
+    async fn test_truncated_qmdl_reader() {
+        let messages = get_test_messages();
+        let mut bytes = get_test_message_bytes();
+        // cut the last message off halfway through
+        let last_len = messages.last().unwrap().len as usize;
+        bytes.truncate(bytes.len() - last_len / 2);
+
+        let limit = Some(bytes.len());
+        let mut reader = QmdlReader::new(Cursor::new(bytes), limit);
+        for message in &messages[..messages.len() - 1] {
+            let container = reader.get_next_messages_container().await.unwrap().unwrap();
+            assert_eq!(container.messages, vec![message.clone()]);
+        }
+        assert!(matches!(
+            reader.get_next_messages_container().await,
+            Ok(None)
+        ));
+        assert_eq!(reader.truncated_bytes(), last_len - last_len / 2);
+    }
+
+    #[tokio::test]
+    async fn test_unbounded_qmdl_reader_stops_at_eof() {
+        let mut reader = QmdlReader::new(Cursor::new(get_test_message_bytes()), None);
+        let mut count = 0;
+        while reader
+            .get_next_messages_container()
+            .await
+            .unwrap()
+            .is_some()
+        {
+            count += 1;
+        }
+        assert_eq!(count, get_test_messages().len());
+        assert_eq!(reader.truncated_bytes(), 0);
+    }
+
     #[tokio::test]
     async fn test_qmdl_writer() {
         let mut buf = Vec::new();
@@ -0,0 +1,310 @@
+//! Builds QMDL recordings from a list of messages, for testing analyzers
+//! end to end without a capture from a real device.
+//!
+//! Each message is wrapped in the same diag log header the device writes for
+//! it and HDLC framed with [hdlc_encode], so the recording goes through
+//! [QmdlReader](crate::qmdl::QmdlReader), diag parsing and GSMTAP conversion
+//! just like a real one. Enable the `test-support` feature to use this
+//! outside of this crate's own tests.
+
+use crate::analysis::test_support::{
+    attach_reject_payload, authentication_response_payload, identity_request_payload,
+    rrc_connection_release_payload, rrc_connection_release_redirect_to_geran_payload,
+    security_mode_command_payload,
+};
+use crate::gsmtap::LteRrcSubtype;
+use crate::hdlc::hdlc_encode;
+
+/// The diag log type of a plain EMM NAS message sent by the network
+const LOG_NAS_EMM_DOWNLINK: u16 = 0xb0ec;
+/// The diag log type of a plain EMM NAS message sent by the UE
+const LOG_NAS_EMM_UPLINK: u16 = 0xb0ed;
+/// The diag log type of an LTE RRC message
+const LOG_LTE_RRC_OTA: u16 = 0xb0c0;
+
+/// The LTE RRC log header version we write, matching a recent modem
+const LTE_RRC_EXT_HEADER_VERSION: u8 = 20;
+
+/// A message to put in a fixture.
+///
+/// The named variants are canned examples of messages the analyzers look for.
+/// Anything else can be built from its encoded bytes with [Self::Nas] and
+/// [Self::LteRrc].
+#[derive(Debug, Clone, PartialEq)]
+pub enum FixtureMessage {
+    /// An EMM Identity Request for the IMSI (TS 24.301 8.2.18)
+    IdentityRequest,
+    /// An EMM Attach Reject with the given EMM cause (TS 24.301 8.2.3)
+    AttachReject { cause: u8 },
+    /// An EMM Authentication Response (TS 24.301 8.2.8)
+    AuthenticationResponse,
+    /// An EMM Security Mode Command selecting the given EPS encryption and
+    /// integrity algorithms (TS 24.301 8.2.20)
+    NasSecurityModeCommand { eea: u8, eia: u8 },
+    /// An RRC Connection Release with release cause "other" (TS 36.331 6.2.2)
+    RrcConnectionRelease,
+    /// An RRC Connection Release redirecting the UE to GERAN ARFCN 0 in the
+    /// DCS 1800 band (TS 36.331 6.2.2)
+    RrcConnectionReleaseRedirectToGeran,
+    /// A plain EMM NAS message, and whether the UE sent it
+    Nas { uplink: bool, payload: Vec<u8> },
+    /// A UPER-encoded LTE RRC message sent on the given channel
+    LteRrc {
+        subtype: LteRrcSubtype,
+        payload: Vec<u8>,
+    },
+}
+
+impl FixtureMessage {
+    fn nas(uplink: bool, payload: Vec<u8>) -> Self {
+        FixtureMessage::Nas { uplink, payload }
+    }
+
+    fn lte_rrc(subtype: LteRrcSubtype, payload: Vec<u8>) -> Self {
+        FixtureMessage::LteRrc { subtype, payload }
+    }
+
+    /// Resolves the named variants into [Self::Nas] or [Self::LteRrc], using
+    /// the same payloads as the [test_support](crate::analysis::test_support)
+    /// builders.
+    fn into_raw(self) -> Self {
+        match self {
+            FixtureMessage::IdentityRequest => Self::nas(false, identity_request_payload()),
+            FixtureMessage::AttachReject { cause } => {
+                Self::nas(false, attach_reject_payload(cause))
+            }
+            FixtureMessage::AuthenticationResponse => {
+                Self::nas(true, authentication_response_payload())
+            }
+            FixtureMessage::NasSecurityModeCommand { eea, eia } => {
+                Self::nas(false, security_mode_command_payload(eea, eia))
+            }
+            FixtureMessage::RrcConnectionRelease => {
+                Self::lte_rrc(LteRrcSubtype::DlDcch, rrc_connection_release_payload())
+            }
+            FixtureMessage::RrcConnectionReleaseRedirectToGeran => Self::lte_rrc(
+                LteRrcSubtype::DlDcch,
+                rrc_connection_release_redirect_to_geran_payload(),
+            ),
+            raw => raw,
+        }
+    }
+}
+
+/// Which PDU number the LTE RRC log header uses for each channel, at
+/// [LTE_RRC_EXT_HEADER_VERSION]
+fn lte_rrc_pdu_num(subtype: LteRrcSubtype) -> u8 {
+    match subtype {
+        LteRrcSubtype::BcchBch => 1,
+        LteRrcSubtype::BcchDlSch => 2,
+        LteRrcSubtype::MCCH => 4,
+        LteRrcSubtype::PCCH => 5,
+        LteRrcSubtype::DlCcch => 6,
+        LteRrcSubtype::DlDcch => 7,
+        LteRrcSubtype::UlCcch => 8,
+        LteRrcSubtype::UlDcch => 9,
+        other => panic!("no LTE RRC PDU number for {other:?}"),
+    }
+}
+
+/// Assembles a QMDL recording one message at a time, e.g.
+/// `QmdlFixtureBuilder::new().message(FixtureMessage::IdentityRequest).build()`
+#[derive(Debug, Clone, Default)]
+pub struct QmdlFixtureBuilder {
+    messages: Vec<FixtureMessage>,
+}
+
+impl QmdlFixtureBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a message to the recording.
+    pub fn message(mut self, message: FixtureMessage) -> Self {
+        self.messages.push(message);
+        self
+    }
+
+    /// Appends each of the given messages to the recording, in order.
+    pub fn messages(mut self, messages: impl IntoIterator<Item = FixtureMessage>) -> Self {
+        self.messages.extend(messages);
+        self
+    }
+
+    /// Encodes the recording. Messages are timestamped a second apart, so
+    /// building the same messages always gives the same bytes.
+    pub fn build(self) -> Vec<u8> {
+        let mut qmdl = Vec::new();
+        for (i, message) in self.messages.into_iter().enumerate() {
+            // the upper 48 bits of a diag timestamp count 1/800s ticks
+            let timestamp = (i as u64 + 1) * (800 << 16);
+            qmdl.extend(hdlc_encode(&log_message(message.into_raw(), timestamp)));
+        }
+        qmdl
+    }
+}
+
+/// Wraps a message in the diag log header the device writes for it.
+fn log_message(message: FixtureMessage, timestamp: u64) -> Vec<u8> {
+    let (log_type, body) = match message {
+        FixtureMessage::Nas { uplink, payload } => {
+            let log_type = if uplink {
+                LOG_NAS_EMM_UPLINK
+            } else {
+                LOG_NAS_EMM_DOWNLINK
+            };
+            // ext header version, RRC release, RRC version minor and major
+            let mut body = vec![0x01, 0x09, 0x05, 0x00];
+            body.extend(payload);
+            (log_type, body)
+        }
+        FixtureMessage::LteRrc { subtype, payload } => {
+            let mut body = vec![
+                LTE_RRC_EXT_HEADER_VERSION,
+                14, // RRC release major
+                48, // RRC release minor
+                0,  // bearer ID
+            ];
+            body.extend(160u16.to_le_bytes()); // physical cell ID
+            body.extend(2050u32.to_le_bytes()); // EARFCN
+            body.extend(0u16.to_le_bytes()); // SFN and subframe number
+            body.push(lte_rrc_pdu_num(subtype));
+            body.extend(0u32.to_le_bytes()); // SIB mask
+            body.extend((payload.len() as u16).to_le_bytes());
+            body.extend(payload);
+            (LOG_LTE_RRC_OTA, body)
+        }
+        named => unreachable!("{named:?} should have been resolved by into_raw"),
+    };
+
+    // both lengths cover themselves, the log type and the timestamp
+    let length = (12 + body.len()) as u16;
+    let mut log = vec![
+        0x10, // a log message
+        0x00, // with nothing pending
+    ];
+    log.extend(length.to_le_bytes()); // outer length
+    log.extend(length.to_le_bytes()); // inner length
+    log.extend(log_type.to_le_bytes());
+    log.extend(timestamp.to_le_bytes());
+    log.extend(body);
+    log
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::analyzer::{AnalyzerConfig, Event, Harness};
+    use crate::analysis::test_support::{EEA0, EIA2};
+    use crate::qmdl::QmdlReader;
+
+    /// A config with every analyzer off
+    fn nothing_enabled() -> AnalyzerConfig {
+        let mut config = AnalyzerConfig::default();
+        config.diagnostic_analyzer = false;
+        config.connection_redirect_2g_downgrade = false;
+        config.lte_sib6_and_7_downgrade = false;
+        config.null_cipher = false;
+        config.nas_null_cipher = false;
+        config.nas_integrity_downgrade = false;
+        config.wcdma_null_cipher = false;
+        config.incomplete_sib = false;
+        config.test_analyzer = false;
+        config.imsi_requested = false;
+        config.sfn_continuity = false;
+        config.otdoa_request = false;
+        config.sib3_reselection_anomaly = false;
+        config.sib_neighbor_list = false;
+        config.service_request_flood = false;
+        config.fault_injection.enabled = false;
+        config
+    }
+
+    /// Analyzes the recording, returning each event along with the number of
+    /// the message it was found in (starting from 1).
+    async fn analyze(qmdl: &[u8], config: &AnalyzerConfig) -> Vec<(usize, Event)> {
+        let mut harness = Harness::new_with_config(config);
+        let mut reader = QmdlReader::new(qmdl, Some(qmdl.len()));
+        let mut packet_num = 0;
+        let mut events = Vec::new();
+        while let Some(container) = reader.get_next_messages_container().await.unwrap() {
+            for row in harness.analyze_qmdl_messages(container) {
+                packet_num += 1;
+                assert_eq!(row.skipped_message_reason, None);
+                events.extend(row.events.into_iter().flatten().map(|e| (packet_num, e)));
+            }
+        }
+        events
+    }
+
+    fn codes(events: &[(usize, Event)]) -> Vec<(usize, &str)> {
+        events
+            .iter()
+            .map(|(packet_num, event)| {
+                let details = event.details.as_ref().expect("event without details");
+                (*packet_num, details.code.as_str())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_build_is_deterministic() {
+        let build = || {
+            QmdlFixtureBuilder::new()
+                .message(FixtureMessage::IdentityRequest)
+                .message(FixtureMessage::RrcConnectionRelease)
+                .build()
+        };
+        assert_eq!(build(), build());
+    }
+
+    #[tokio::test]
+    async fn test_identity_request_after_auth() {
+        let qmdl = QmdlFixtureBuilder::new()
+            .message(FixtureMessage::AuthenticationResponse)
+            .message(FixtureMessage::IdentityRequest)
+            .build();
+        let mut config = nothing_enabled();
+        config.imsi_requested = true;
+        let events = analyze(&qmdl, &config).await;
+        assert_eq!(codes(&events), vec![(2, "identity_after_auth")]);
+    }
+
+    #[tokio::test]
+    async fn test_identity_request_without_attach() {
+        let qmdl = QmdlFixtureBuilder::new()
+            .message(FixtureMessage::RrcConnectionRelease)
+            .message(FixtureMessage::IdentityRequest)
+            .build();
+        let mut config = nothing_enabled();
+        config.imsi_requested = true;
+        let events = analyze(&qmdl, &config).await;
+        assert_eq!(codes(&events), vec![(2, "identity_without_attach")]);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_to_geran() {
+        let qmdl = QmdlFixtureBuilder::new()
+            .message(FixtureMessage::RrcConnectionReleaseRedirectToGeran)
+            .build();
+        let mut config = nothing_enabled();
+        config.connection_redirect_2g_downgrade = true;
+        let events = analyze(&qmdl, &config).await;
+        assert_eq!(codes(&events), vec![(1, "geran_redirect")]);
+    }
+
+    #[tokio::test]
+    async fn test_nas_null_cipher() {
+        let qmdl = QmdlFixtureBuilder::new()
+            .message(FixtureMessage::NasSecurityModeCommand {
+                eea: EEA0,
+                eia: EIA2,
+            })
+            .build();
+        let mut config = nothing_enabled();
+        config.nas_null_cipher = true;
+        let events = analyze(&qmdl, &config).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].0, 1);
+    }
+}
@@ -0,0 +1,500 @@
+//! Removes subscriber identifiers from exported data, so captures can be
+//! shared without giving away whose phone they came from.
+//!
+//! IMSIs keep their MCC and MNC, which only say which operator issued them,
+//! and have their MSIN zeroed. IMEIs and the phone numbers (MSISDNs) in SMS
+//! addresses are zeroed entirely. Digits are overwritten in place, so every
+//! length field stays correct and the messages still decode. Analysis
+//! reports also leave out where the recording started.
+//!
+//! Only LTE NAS messages which aren't ciphered are rewritten. Identifiers in
+//! LTE RRC messages, such as paging by IMSI, are left alone, since UPER
+//! encoding doesn't keep them in whole bytes.
+
+use std::borrow::Cow;
+
+use serde_json::Value;
+
+use crate::analysis::analyzer::AnalysisRow;
+use crate::analysis::event_message::FieldValue;
+use crate::gsmtap::{GsmtapMessage, GsmtapType};
+use crate::util::mnc_digits;
+
+/// EMM's protocol discriminator (TS 24.007 11.2.3.1.1)
+const PD_EMM: u8 = 0x7;
+/// SMS's protocol discriminator, as used in CP messages (TS 24.011 8.1.3)
+const PD_SMS: u8 = 0x9;
+
+// EMM message types (TS 24.301 9.8)
+const ATTACH_REQUEST: u8 = 0x41;
+const DETACH_REQUEST: u8 = 0x45;
+const IDENTITY_RESPONSE: u8 = 0x56;
+const DOWNLINK_NAS_TRANSPORT: u8 = 0x62;
+const UPLINK_NAS_TRANSPORT: u8 = 0x63;
+
+// mobile identity types (TS 24.008 10.5.1.4 and TS 24.301 9.9.3.12)
+const IDENTITY_IMSI: u8 = 1;
+const IDENTITY_IMEI: u8 = 2;
+const IDENTITY_IMEISV: u8 = 3;
+
+/// CP-DATA's message type (TS 24.011 8.1.3)
+const CP_DATA: u8 = 0x01;
+// RP-DATA's message types (TS 24.011 8.2.2)
+const RP_DATA_MS_TO_NETWORK: u8 = 0x00;
+const RP_DATA_NETWORK_TO_MS: u8 = 0x01;
+// TP message types (TS 23.040 9.2.3.1)
+const SMS_DELIVER: u8 = 0x00;
+const SMS_SUBMIT: u8 = 0x01;
+/// The type of number for addresses in GSM 7 bit characters rather than
+/// digits (TS 23.040 9.1.2.5)
+const TON_ALPHANUMERIC: u8 = 0x5;
+
+/// Runs of at least this many digits in text are taken to be identifiers
+const MIN_IDENTIFIER_DIGITS: usize = 10;
+/// How many digits an IMSI has, which are the only runs in text which keep
+/// their MCC and MNC
+const IMSI_DIGITS: usize = 15;
+/// How many digits of an IMSI to keep when its MCC isn't valid
+const DEFAULT_PLMN_DIGITS: usize = 5;
+
+/// Zeroes BCD digits from `keep` onwards, where digit `i` is
+/// `digit_nibble(i)`. Filler nibbles (0xf) are left as they are.
+fn zero_digits(
+    bytes: &mut [u8],
+    keep: usize,
+    digit_nibble: impl Fn(usize) -> (usize, bool),
+) -> bool {
+    let mut changed = false;
+    let mut digit = keep;
+    loop {
+        let (index, high) = digit_nibble(digit);
+        let Some(byte) = bytes.get_mut(index) else {
+            return changed;
+        };
+        let (mask, nibble) = if high {
+            (0x0f, *byte >> 4)
+        } else {
+            (0xf0, *byte & 0x0f)
+        };
+        if nibble != 0x0 && nibble != 0xf {
+            *byte &= mask;
+            changed = true;
+        }
+        digit += 1;
+    }
+}
+
+/// Where digit `i` of a mobile identity is: the first is in the high nibble
+/// of the first octet, then they fill each following octet low nibble first.
+fn mobile_identity_nibble(digit: usize) -> (usize, bool) {
+    if digit == 0 {
+        (0, true)
+    } else {
+        (digit.div_ceil(2), digit.is_multiple_of(2))
+    }
+}
+
+/// How many leading digits of an IMSI make up its MCC and MNC
+fn imsi_plmn_digits(identity: &[u8]) -> usize {
+    let digit = |i: usize| {
+        let (index, high) = mobile_identity_nibble(i);
+        let byte = identity[index];
+        if high { byte >> 4 } else { byte & 0x0f }
+    };
+    if identity.len() < 3 {
+        return DEFAULT_PLMN_DIGITS;
+    }
+    let mcc: Vec<u8> = (0..3).map(digit).collect();
+    if mcc.iter().any(|&d| d > 9) {
+        return DEFAULT_PLMN_DIGITS;
+    }
+    let mcc = mcc.iter().fold(0u16, |mcc, &d| mcc * 10 + d as u16);
+    3 + mnc_digits(mcc)
+}
+
+/// Redacts the value of a mobile identity or EPS mobile identity IE. TMSIs
+/// and GUTIs are temporary, so they're left alone.
+fn redact_mobile_identity(identity: &mut [u8]) -> bool {
+    let Some(&first) = identity.first() else {
+        return false;
+    };
+    let keep = match first & 0x07 {
+        IDENTITY_IMSI => imsi_plmn_digits(identity),
+        IDENTITY_IMEI | IDENTITY_IMEISV => 0,
+        _ => return false,
+    };
+    zero_digits(identity, keep, mobile_identity_nibble)
+}
+
+/// Redacts the mobile identity LV starting at `offset`, if it fits in the
+/// message.
+fn redact_mobile_identity_lv(nas: &mut [u8], offset: usize) -> bool {
+    let Some(&len) = nas.get(offset) else {
+        return false;
+    };
+    let start = offset + 1;
+    match nas.get_mut(start..start + len as usize) {
+        Some(identity) => redact_mobile_identity(identity),
+        None => false,
+    }
+}
+
+/// Redacts an SMS address (TS 23.040 9.1.2.5): `digits` digits after a type
+/// of address octet. Alphanumeric addresses are zeroed as a whole.
+fn redact_sms_address(address: &mut [u8], digits: usize) -> bool {
+    let Some((&mut type_of_address, value)) = address.split_first_mut() else {
+        return false;
+    };
+    let len = digits.div_ceil(2).min(value.len());
+    let value = &mut value[..len];
+    if (type_of_address >> 4) & 0x07 == TON_ALPHANUMERIC {
+        let changed = value.iter().any(|&b| b != 0);
+        value.fill(0);
+        return changed;
+    }
+    zero_digits(value, 0, |digit| (digit / 2, digit % 2 == 1))
+}
+
+/// Redacts the sender or recipient of an SMS TPDU (TS 23.040 9.2.2)
+fn redact_tpdu(tpdu: &mut [u8], mti: u8) -> bool {
+    let address_offset = match (tpdu.first().map(|b| b & 0x03), mti) {
+        // TP-OA comes straight after the first octet
+        (Some(SMS_DELIVER), RP_DATA_NETWORK_TO_MS) => 1,
+        // TP-DA comes after the message reference
+        (Some(SMS_SUBMIT), RP_DATA_MS_TO_NETWORK) => 2,
+        _ => return false,
+    };
+    let Some(&digits) = tpdu.get(address_offset) else {
+        return false;
+    };
+    let start = address_offset + 1;
+    let end = (start + 1 + (digits as usize).div_ceil(2)).min(tpdu.len());
+    match tpdu.get_mut(start..end) {
+        Some(address) => redact_sms_address(address, digits as usize),
+        None => false,
+    }
+}
+
+/// Redacts the SMS in a CP-DATA message (TS 24.011 7.2.1), whose RP-DATA
+/// carries a TPDU after the originator and destination addresses.
+fn redact_cp_data(cp: &mut [u8]) -> bool {
+    if cp.len() < 3 || cp[0] & 0x0f != PD_SMS || cp[1] != CP_DATA {
+        return false;
+    }
+    let rpdu = &mut cp[3..];
+    let Some(mti) = rpdu.first().map(|b| b & 0x07) else {
+        return false;
+    };
+    if mti != RP_DATA_MS_TO_NETWORK && mti != RP_DATA_NETWORK_TO_MS {
+        return false;
+    }
+    // skip the message reference and both (service centre) addresses
+    let mut offset = 2;
+    for _ in 0..2 {
+        match rpdu.get(offset) {
+            Some(&len) => offset += 1 + len as usize,
+            None => return false,
+        }
+    }
+    let Some(&len) = rpdu.get(offset) else {
+        return false;
+    };
+    let start = offset + 1;
+    let end = (start + len as usize).min(rpdu.len());
+    match rpdu.get_mut(start..end) {
+        Some(tpdu) => redact_tpdu(tpdu, mti),
+        None => false,
+    }
+}
+
+/// Redacts a plain EMM message
+fn redact_plain_emm(nas: &mut [u8]) -> bool {
+    match nas.get(1) {
+        // the identity follows the NAS key set identifier and attach or
+        // detach type. A detach request from the network has no identity,
+        // but its optional cause IE won't fit as one.
+        Some(&ATTACH_REQUEST) | Some(&DETACH_REQUEST) => redact_mobile_identity_lv(nas, 3),
+        Some(&IDENTITY_RESPONSE) => redact_mobile_identity_lv(nas, 2),
+        Some(&UPLINK_NAS_TRANSPORT) | Some(&DOWNLINK_NAS_TRANSPORT) => {
+            let Some(&len) = nas.get(2) else {
+                return false;
+            };
+            let end = (3 + len as usize).min(nas.len());
+            redact_cp_data(&mut nas[3..end])
+        }
+        _ => false,
+    }
+}
+
+/// Redacts the identifiers in an LTE NAS message (TS 24.301), returning
+/// whether anything was changed. Security protected messages are redacted if
+/// what they contain isn't ciphered, or is "ciphered" with EEA0.
+pub fn redact_nas_message(nas: &mut [u8]) -> bool {
+    let Some(&first) = nas.first() else {
+        return false;
+    };
+    if first & 0x0f != PD_EMM {
+        return false;
+    }
+    match first >> 4 {
+        0 => redact_plain_emm(nas),
+        // integrity protected and maybe ciphered: a MAC and sequence number,
+        // then the message. Anything which actually is ciphered is very
+        // unlikely to look like one of the messages we redact.
+        1..=4 if nas.len() > 6 => redact_nas_message(&mut nas[6..]),
+        _ => false,
+    }
+}
+
+/// Redacts the identifiers in a GSMTAP message's payload, returning whether
+/// anything was changed. Only LTE NAS messages are redacted.
+pub fn redact_gsmtap_message(message: &mut GsmtapMessage) -> bool {
+    match message.header.gsmtap_type {
+        GsmtapType::LteNas(_) => redact_nas_message(&mut message.payload),
+        _ => false,
+    }
+}
+
+/// Redacts anything in `text` which looks like an IMSI, IMEI or phone
+/// number: runs of 10 or more digits. Runs of 15 digits are taken to be
+/// IMSIs and keep their MCC and MNC; every digit of any other run is zeroed.
+pub fn redact_text(text: &str) -> Cow<'_, str> {
+    let bytes = text.as_bytes();
+    let mut redacted: Option<Vec<u8>> = None;
+    let mut start = 0;
+    while start < bytes.len() {
+        let len = bytes[start..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        if len >= MIN_IDENTIFIER_DIGITS {
+            let keep = if len == IMSI_DIGITS {
+                let mcc: u16 = text[start..start + 3].parse().unwrap_or_default();
+                3 + mnc_digits(mcc)
+            } else {
+                0
+            };
+            let redacted = redacted.get_or_insert_with(|| bytes.to_vec());
+            redacted[start + keep..start + len].fill(b'0');
+        }
+        start += len.max(1);
+    }
+    match redacted {
+        // only ASCII digits were replaced, so this is still UTF-8
+        Some(redacted) => Cow::Owned(String::from_utf8(redacted).unwrap()),
+        None => Cow::Borrowed(text),
+    }
+}
+
+/// Redacts the events in an analysis row, returning whether anything was
+/// changed.
+pub fn redact_analysis_row(row: &mut AnalysisRow) -> bool {
+    let mut changed = false;
+    let mut redact = |text: &mut String| {
+        if let Cow::Owned(redacted) = redact_text(text) {
+            *text = redacted;
+            changed = true;
+        }
+    };
+    for event in row.events.iter_mut().flatten() {
+        redact(&mut event.message);
+        let fields = event.details.iter_mut().flat_map(|d| d.fields.values_mut());
+        for field in fields {
+            if let FieldValue::Text(text) = field {
+                redact(text);
+            }
+        }
+    }
+    changed
+}
+
+/// Redacts a line of an NDJSON analysis report. The metadata line loses its
+/// `start_location`, which would say where the recording was made. Other
+/// lines, and lines with nothing to redact, are returned as they are.
+pub fn redact_report_line(line: String) -> String {
+    let redacted = match serde_json::from_str::<AnalysisRow>(&line) {
+        Ok(mut row) if redact_analysis_row(&mut row) => serde_json::to_string(&row),
+        Ok(_) => return line,
+        Err(_) => match serde_json::from_str::<serde_json::Map<String, Value>>(&line) {
+            Ok(mut metadata) if metadata.remove("start_location").is_some() => {
+                serde_json::to_string(&metadata)
+            }
+            _ => return line,
+        },
+    };
+    let trailing_newline = line.ends_with('\n');
+    match redacted {
+        Ok(redacted) if trailing_newline => redacted + "\n",
+        Ok(redacted) => redacted,
+        // better not to serve anything unredacted
+        Err(_) => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::analyzer::{Event, EventType};
+    use crate::analysis::event_message::EventDetails;
+
+    /// An Attach Request identifying the UE by IMSI 001010123456789
+    fn attach_request() -> Vec<u8> {
+        let mut nas = vec![0x07, 0x41, 0x71];
+        nas.extend([0x08, 0x09, 0x10, 0x10, 0x10, 0x32, 0x54, 0x76, 0x98]);
+        nas.extend([0x02, 0xe0, 0xe0]);
+        nas
+    }
+
+    #[test]
+    fn test_attach_request_keeps_plmn() {
+        let mut nas = attach_request();
+        assert!(redact_nas_message(&mut nas));
+        // 001-01, then zeroes
+        let mut expected = vec![0x07, 0x41, 0x71];
+        expected.extend([0x08, 0x09, 0x10, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        expected.extend([0x02, 0xe0, 0xe0]);
+        assert_eq!(nas, expected);
+        // already redacted
+        assert!(!redact_nas_message(&mut nas));
+    }
+
+    #[test]
+    fn test_identity_response_three_digit_mnc() {
+        // IMSI 310260123456789, which keeps 310-260
+        let mut nas = vec![
+            0x07, 0x56, 0x08, 0x39, 0x01, 0x62, 0x10, 0x32, 0x54, 0x76, 0x98,
+        ];
+        assert!(redact_nas_message(&mut nas));
+        assert_eq!(
+            nas,
+            [
+                0x07, 0x56, 0x08, 0x39, 0x01, 0x62, 0x00, 0x00, 0x00, 0x00, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn test_imeisv_is_zeroed() {
+        // IMEISV 3534570123456789: 16 digits, so the last nibble is filler
+        let mut nas = vec![
+            0x07, 0x56, 0x09, 0x33, 0x35, 0x54, 0x07, 0x21, 0x43, 0x65, 0x87, 0xf9,
+        ];
+        assert!(redact_nas_message(&mut nas));
+        assert_eq!(
+            nas,
+            [
+                0x07, 0x56, 0x09, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0
+            ]
+        );
+    }
+
+    #[test]
+    fn test_temporary_identities_are_kept() {
+        // an Identity Response with a TMSI
+        let mut nas = vec![0x07, 0x56, 0x05, 0xf4, 0x12, 0x34, 0x56, 0x78];
+        assert!(!redact_nas_message(&mut nas));
+        // a Detach Request from the network, with an EMM cause
+        let mut nas = vec![0x07, 0x45, 0x01, 0x53, 0x02];
+        assert!(!redact_nas_message(&mut nas));
+    }
+
+    #[test]
+    fn test_integrity_protected() {
+        let mut nas = vec![0x17, 0xaa, 0xbb, 0xcc, 0xdd, 0x01];
+        nas.extend(attach_request());
+        assert!(redact_nas_message(&mut nas));
+        assert_eq!(&nas[..6], [0x17, 0xaa, 0xbb, 0xcc, 0xdd, 0x01]);
+        // the MSIN of the contained message's IMSI
+        assert_eq!(&nas[13..18], [0x00; 5]);
+    }
+
+    #[test]
+    fn test_truncated_messages() {
+        for len in 0..attach_request().len() {
+            let mut nas = attach_request();
+            nas.truncate(len);
+            redact_nas_message(&mut nas);
+        }
+    }
+
+    #[test]
+    fn test_sms_addresses() {
+        // Downlink NAS Transport carrying an SMS-DELIVER from +15551234567
+        let tpdu = [
+            0x04, // SMS-DELIVER
+            0x0b, // 11 digit originating address
+            0x91, // international number
+            0x51, 0x55, 0x21, 0x43, 0x65, 0xf7, // the digits
+            0x00, 0x00, // protocol identifier, data coding scheme
+        ];
+        let mut rpdu = vec![
+            0x01, // RP-DATA network to MS
+            0x2a, // message reference
+            0x04,
+            0x91,
+            0x21,
+            0x43,
+            0x65, // service centre address
+            0x00, // no destination address
+            tpdu.len() as u8,
+        ];
+        rpdu.extend(tpdu);
+        let mut cp = vec![0x09, 0x01, rpdu.len() as u8];
+        cp.extend(rpdu);
+        let mut nas = vec![0x07, 0x62, cp.len() as u8];
+        nas.extend(cp);
+
+        let mut expected = nas.clone();
+        let tpdu_start = nas.len() - tpdu.len();
+        expected[tpdu_start + 3..tpdu_start + 9]
+            .copy_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0xf0]);
+        assert!(redact_nas_message(&mut nas));
+        assert_eq!(nas, expected);
+    }
+
+    #[test]
+    fn test_redact_text() {
+        assert_eq!(
+            redact_text("IMSI 001010123456789 (packet 12)"),
+            "IMSI 001010000000000 (packet 12)"
+        );
+        assert_eq!(redact_text("MSISDN 3102609876"), "MSISDN 0000000000");
+        assert_eq!(
+            redact_text("IMEI 3548710123456789"),
+            "IMEI 0000000000000000"
+        );
+        assert!(matches!(
+            redact_text("EARFCN 2050, cell 123456789"),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_redact_report_line() {
+        let metadata = "{\"analyzers\":[],\"report_version\":2}\n".to_string();
+        assert_eq!(redact_report_line(metadata.clone()), metadata);
+
+        // where the recording started is left out
+        let located = "{\"analyzers\":[],\"report_version\":2,\"start_location\":\
+            {\"lat\":52.52,\"lon\":13.405,\"accuracy_m\":5.0}}\n";
+        assert_eq!(redact_report_line(located.to_string()), metadata);
+
+        let row = AnalysisRow {
+            packet_timestamp: None,
+            skipped_message_reason: None,
+            events: vec![
+                None,
+                Some(Event::new(
+                    EventType::Low,
+                    EventDetails::new("test", "test").with("imsi", "001010123456789"),
+                )),
+            ],
+            message_summary: None,
+        };
+        let line = serde_json::to_string(&row).unwrap() + "\n";
+        let redacted = redact_report_line(line);
+        assert!(redacted.ends_with('\n'));
+        assert!(redacted.contains("001010000000000"), "{redacted}");
+        assert!(!redacted.contains("123456789"), "{redacted}");
+    }
+}
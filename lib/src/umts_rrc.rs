@@ -0,0 +1,253 @@
+//! A minimal decoder for UMTS (WCDMA) RRC messages, as specified in 3GPP TS
+//! 25.331. Unlike LTE RRC, we don't have a generated parser for UMTS RRC in
+//! telcom-parser, so this decodes the unaligned PER encoding by hand and only
+//! as far into each message as our analyzers need. Anything we don't
+//! understand is reported as `Other` instead of failing.
+
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum UmtsRrcError {
+    #[error("UMTS RRC message ended unexpectedly at bit {0}")]
+    UnexpectedEnd(usize),
+    #[error("Invalid ciphering algorithm {0}")]
+    InvalidCipheringAlgorithm(u32),
+}
+
+// index of securityModeCommand in the DL-DCCH-MessageType CHOICE
+const DL_DCCH_SECURITY_MODE_COMMAND: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CipheringAlgorithm {
+    /// No ciphering
+    Uea0,
+    /// Kasumi
+    Uea1,
+    /// SNOW 3G
+    Uea2,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecurityModeCommand {
+    /// Whether the message carried integrityCheckInfo. A SecurityModeCommand
+    /// must always be integrity protected, and UMTS has no null integrity
+    /// algorithm, so this is the closest thing to UIA0.
+    pub integrity_protected: bool,
+    /// The algorithm the UE is told to start ciphering with, or None if the
+    /// command doesn't (re)start ciphering.
+    pub ciphering_algorithm: Option<CipheringAlgorithm>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DlDcchMessage {
+    SecurityModeCommand(SecurityModeCommand),
+    /// Any message type we don't decode, identified by its index in the
+    /// DL-DCCH-MessageType CHOICE
+    Other(u32),
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0 }
+    }
+
+    fn read_bit(&mut self) -> Result<bool, UmtsRrcError> {
+        let byte = self
+            .data
+            .get(self.pos / 8)
+            .ok_or(UmtsRrcError::UnexpectedEnd(self.pos))?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1 == 1;
+        self.pos += 1;
+        Ok(bit)
+    }
+
+    fn read_bits(&mut self, n: usize) -> Result<u32, UmtsRrcError> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | self.read_bit()? as u32;
+        }
+        Ok(value)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), UmtsRrcError> {
+        if self.pos + n > self.data.len() * 8 {
+            return Err(UmtsRrcError::UnexpectedEnd(self.data.len() * 8));
+        }
+        self.pos += n;
+        Ok(())
+    }
+}
+
+pub fn decode_dl_dcch(data: &[u8]) -> Result<DlDcchMessage, UmtsRrcError> {
+    let mut reader = BitReader::new(data);
+    // DL-DCCH-Message ::= SEQUENCE { integrityCheckInfo OPTIONAL, message }
+    let integrity_protected = reader.read_bit()?;
+    if integrity_protected {
+        // messageAuthenticationCode (32 bits), rrc-MessageSequenceNumber (0..15)
+        reader.skip(32 + 4)?;
+    }
+    let message_type = reader.read_bits(5)?;
+    if message_type != DL_DCCH_SECURITY_MODE_COMMAND {
+        return Ok(DlDcchMessage::Other(message_type));
+    }
+    let ciphering_algorithm = decode_ciphering_algorithm(&mut reader)?;
+    Ok(DlDcchMessage::SecurityModeCommand(SecurityModeCommand {
+        integrity_protected,
+        ciphering_algorithm,
+    }))
+}
+
+fn decode_ciphering_algorithm(
+    reader: &mut BitReader,
+) -> Result<Option<CipheringAlgorithm>, UmtsRrcError> {
+    // SecurityModeCommand ::= CHOICE { r3, later-than-r3 }
+    let algorithm_bits = if !reader.read_bit()? {
+        // r3 ::= SEQUENCE { securityModeCommand-r3, v7a0NonCriticalExtensions OPTIONAL }
+        reader.skip(1)?;
+        // CipheringAlgorithm ::= ENUMERATED { uea0, uea1 }
+        1
+    } else {
+        // later-than-r3 ::= SEQUENCE { rrc-TransactionIdentifier, criticalExtensions }
+        reader.skip(2)?;
+        // criticalExtensions ::= CHOICE { r7, criticalExtensions }
+        if reader.read_bit()? {
+            // a release we don't know how to decode
+            return Ok(None);
+        }
+        // r7 ::= SEQUENCE { securityModeCommand-r7, nonCriticalExtensions OPTIONAL }
+        reader.skip(1)?;
+        // CipheringAlgorithm-r7 ::= ENUMERATED { uea0, uea1, uea2 }
+        2
+    };
+
+    // SecurityModeCommand-r3-IEs and -r7-IEs start the same way, with three
+    // optional fields: cipheringModeInfo, integrityProtectionModeInfo and
+    // ue-SystemSpecificSecurityCap
+    let has_ciphering_mode_info = reader.read_bit()?;
+    reader.skip(2)?;
+    if algorithm_bits == 1 {
+        // the r3 IEs carry rrc-TransactionIdentifier themselves
+        reader.skip(2)?;
+    }
+    // securityCapability ::= SEQUENCE { cipheringAlgorithmCap BIT STRING (SIZE (16)),
+    //     integrityProtectionAlgorithmCap BIT STRING (SIZE (16)) }
+    reader.skip(32)?;
+    if !has_ciphering_mode_info {
+        return Ok(None);
+    }
+
+    // CipheringModeInfo ::= SEQUENCE { cipheringModeCommand,
+    //     activationTimeForDPCH OPTIONAL, rb-DL-CiphActivationTimeInfo OPTIONAL }
+    reader.skip(2)?;
+    // CipheringModeCommand ::= CHOICE { startRestart CipheringAlgorithm, dummy NULL }
+    if reader.read_bit()? {
+        return Ok(None);
+    }
+    let ciphering_algorithm = match reader.read_bits(algorithm_bits)? {
+        0 => CipheringAlgorithm::Uea0,
+        1 => CipheringAlgorithm::Uea1,
+        2 => CipheringAlgorithm::Uea2,
+        other => return Err(UmtsRrcError::InvalidCipheringAlgorithm(other)),
+    };
+    Ok(Some(ciphering_algorithm))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Packs a string of '0's and '1's (ignoring any other characters) into
+    /// bytes, padding the last byte with zeros.
+    pub(crate) fn bits(s: &str) -> Vec<u8> {
+        let bits: Vec<bool> = s
+            .chars()
+            .filter(|c| *c == '0' || *c == '1')
+            .map(|c| c == '1')
+            .collect();
+        bits.chunks(8)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (i, bit)| byte | ((*bit as u8) << (7 - i)))
+            })
+            .collect()
+    }
+
+    /// Builds an integrity protected r3 SecurityModeCommand which starts
+    /// ciphering with the given algorithm.
+    pub(crate) fn r3_security_mode_command(algorithm: &str) -> Vec<u8> {
+        bits(&format!(
+            "1 {mac} 0101 10000 0 0 1 1 0 00 {cap} 00 0 {algorithm} 000000",
+            mac = "1".repeat(32),
+            cap = "1100000000000000".repeat(2),
+        ))
+    }
+
+    #[test]
+    fn test_r3_security_mode_command() {
+        assert_eq!(
+            decode_dl_dcch(&r3_security_mode_command("0")),
+            Ok(DlDcchMessage::SecurityModeCommand(SecurityModeCommand {
+                integrity_protected: true,
+                ciphering_algorithm: Some(CipheringAlgorithm::Uea0),
+            }))
+        );
+        assert_eq!(
+            decode_dl_dcch(&r3_security_mode_command("1")),
+            Ok(DlDcchMessage::SecurityModeCommand(SecurityModeCommand {
+                integrity_protected: true,
+                ciphering_algorithm: Some(CipheringAlgorithm::Uea1),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_r7_security_mode_command() {
+        let data = bits(&format!(
+            "0 10000 1 10 0 0 1 1 0 {cap} 00 0 10 00",
+            cap = "1".repeat(32),
+        ));
+        assert_eq!(
+            decode_dl_dcch(&data),
+            Ok(DlDcchMessage::SecurityModeCommand(SecurityModeCommand {
+                integrity_protected: false,
+                ciphering_algorithm: Some(CipheringAlgorithm::Uea2),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_security_mode_command_without_ciphering() {
+        let data = bits(&format!("0 10000 0 0 0 0 0 00 {cap}", cap = "0".repeat(32)));
+        assert_eq!(
+            decode_dl_dcch(&data),
+            Ok(DlDcchMessage::SecurityModeCommand(SecurityModeCommand {
+                integrity_protected: false,
+                ciphering_algorithm: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_other_message() {
+        // downlinkDirectTransfer, without integrityCheckInfo
+        assert_eq!(
+            decode_dl_dcch(&bits("0 00101 00")),
+            Ok(DlDcchMessage::Other(5))
+        );
+    }
+
+    #[test]
+    fn test_truncated_message() {
+        assert!(matches!(
+            decode_dl_dcch(&bits("0 10000 0 0 1 1 0 00")),
+            Err(UmtsRrcError::UnexpectedEnd(_))
+        ));
+    }
+}
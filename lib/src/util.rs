@@ -13,6 +13,10 @@ pub struct RuntimeMetadata {
     pub system_os: String,
     /// The CPU architecture in use. e.g., "armv7l" or "arm".
     pub arch: String,
+    /// The git commit this binary was built from, if the build set
+    /// `CARGO_GIT_COMMIT`.
+    #[serde(default)]
+    pub build_git_hash: Option<String>,
 }
 
 impl Default for RuntimeMetadata {
@@ -21,6 +25,10 @@ impl Default for RuntimeMetadata {
     }
 }
 
+/// The git commit this binary was built from, if known. Release builds set
+/// `CARGO_GIT_COMMIT` in the environment.
+pub const BUILD_GIT_HASH: Option<&str> = option_env!("CARGO_GIT_COMMIT");
+
 impl RuntimeMetadata {
     /// Return the binary and system information, attempting to retrieve
     /// attributes from `uname(2)` and falling back to values from
@@ -30,6 +38,7 @@ impl RuntimeMetadata {
             rayhunter_version: env!("CARGO_PKG_VERSION").to_owned(),
             arch: std::env::consts::ARCH.to_string(),
             system_os: std::env::consts::OS.to_string(),
+            build_git_hash: BUILD_GIT_HASH.map(str::to_owned),
         };
 
         #[cfg(target_family = "windows")]
@@ -45,8 +54,55 @@ impl RuntimeMetadata {
                     utsname.sysname().to_string_lossy(),
                     utsname.release().to_string_lossy(),
                 ),
+                build_git_hash: BUILD_GIT_HASH.map(str::to_owned),
             },
             Err(_) => build_target,
         }
     }
 }
+
+/// MCCs of the countries whose operators use 3 digit MNCs, mostly those of
+/// the North American Numbering Plan, per ITU-T E.212.
+const THREE_DIGIT_MNC_MCCS: &[u16] = &[
+    302, 310, 311, 312, 313, 314, 315, 316, 334, 338, 342, 344, 346, 348, 352, 354, 356, 358, 360,
+    365, 366, 376, 708, 722, 732,
+];
+
+/// Formats a PLMN as "MCC-MNC", e.g. "310-260" or "262-01". The MCC is
+/// always 3 digits, and the MNC is zero-padded to 3 digits in countries
+/// whose operators use 3 digit MNCs, or 2 elsewhere.
+pub fn format_plmn(mcc: u16, mnc: u16) -> String {
+    let mnc_width = mnc_digits(mcc);
+    format!("{mcc:03}-{mnc:0mnc_width$}")
+}
+
+/// How many digits MNCs have in the country with the given MCC: 3 in
+/// countries whose operators use 3 digit MNCs, or 2 elsewhere.
+pub fn mnc_digits(mcc: u16) -> usize {
+    if THREE_DIGIT_MNC_MCCS.contains(&mcc) {
+        3
+    } else {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_plmn_mnc_width() {
+        assert_eq!(format_plmn(310, 260), "310-260");
+        assert_eq!(format_plmn(262, 1), "262-01");
+        assert_eq!(format_plmn(234, 15), "234-15");
+        // a 3 digit MNC is never truncated, whatever the country
+        assert_eq!(format_plmn(405, 854), "405-854");
+    }
+
+    #[test]
+    fn test_format_plmn_pads_leading_zeros() {
+        assert_eq!(format_plmn(310, 26), "310-026");
+        assert_eq!(format_plmn(302, 0), "302-000");
+        assert_eq!(format_plmn(1, 1), "001-01");
+    }
+}
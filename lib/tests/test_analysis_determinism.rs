@@ -0,0 +1,202 @@
+//! Analysis results should depend only on the recording and which analyzers
+//! are enabled: not on how many times the analysis has run in this process,
+//! nor on which other analyzers ran alongside.
+
+use std::collections::BTreeSet;
+
+use rayhunter::analysis::analyzer::{AnalyzerConfig, EventType, Harness};
+use rayhunter::analysis::test_support::{EEA0, EEA2, EIA0, EIA1, EIA2};
+use rayhunter::qmdl::QmdlReader;
+use rayhunter::qmdl_fixture::{FixtureMessage, QmdlFixtureBuilder};
+
+/// (packet number, analyzer name, severity, message)
+type FoundEvent = (usize, String, EventType, String);
+
+/// A recording with something in it for several analyzers to find
+fn recording() -> Vec<u8> {
+    let security_mode_command = FixtureMessage::NasSecurityModeCommand {
+        eea: EEA2,
+        eia: EIA2,
+    };
+    let sequence = [
+        security_mode_command.clone(),
+        FixtureMessage::IdentityRequest,
+        FixtureMessage::NasSecurityModeCommand {
+            eea: EEA0,
+            eia: EIA0,
+        },
+        FixtureMessage::AttachReject { cause: 3 },
+        FixtureMessage::AuthenticationResponse,
+        FixtureMessage::IdentityRequest,
+        security_mode_command,
+    ];
+    QmdlFixtureBuilder::new()
+        .messages(sequence.iter().cycle().take(sequence.len() * 4).cloned())
+        .build()
+}
+
+/// Runs a fresh harness over the recording, returning every event it found.
+async fn analyze(recording: &[u8], config: &AnalyzerConfig) -> Vec<FoundEvent> {
+    analyze_with(&mut Harness::new_with_config(config), recording).await
+}
+
+/// Runs the harness over the recording, returning every event it found.
+async fn analyze_with(harness: &mut Harness, recording: &[u8]) -> Vec<FoundEvent> {
+    let names: Vec<String> = harness
+        .get_metadata()
+        .analyzers
+        .into_iter()
+        .map(|analyzer| analyzer.name)
+        .collect();
+    let mut reader = QmdlReader::new(recording, Some(recording.len()));
+    let mut packet_num = 0;
+    let mut found = Vec::new();
+    while let Some(container) = reader.get_next_messages_container().await.unwrap() {
+        for row in harness.analyze_qmdl_messages(container) {
+            for (event, name) in row.events.iter().zip(&names) {
+                if let Some(event) = event {
+                    found.push((
+                        packet_num,
+                        name.clone(),
+                        event.event_type,
+                        event.message.clone(),
+                    ));
+                }
+            }
+            packet_num += 1;
+        }
+    }
+    found
+}
+
+fn everything_enabled() -> AnalyzerConfig {
+    let mut config = AnalyzerConfig::default();
+    config.test_analyzer = true;
+    config.sib_neighbor_list = true;
+    config.service_request_flood = true;
+    config
+}
+
+/// A config with only the analyzer `enable` turns on
+fn only(enable: fn(&mut AnalyzerConfig)) -> AnalyzerConfig {
+    let mut config = AnalyzerConfig::default();
+    config.diagnostic_analyzer = false;
+    config.connection_redirect_2g_downgrade = false;
+    config.lte_sib6_and_7_downgrade = false;
+    config.null_cipher = false;
+    config.nas_null_cipher = false;
+    config.nas_integrity_downgrade = false;
+    config.wcdma_null_cipher = false;
+    config.incomplete_sib = false;
+    config.test_analyzer = false;
+    config.imsi_requested = false;
+    config.sfn_continuity = false;
+    config.otdoa_request = false;
+    config.sib3_reselection_anomaly = false;
+    config.sib_neighbor_list = false;
+    config.service_request_flood = false;
+    config.fault_injection.enabled = false;
+    enable(&mut config);
+    config
+}
+
+#[tokio::test]
+async fn test_analysis_determinism() {
+    let recording = recording();
+    let config = everything_enabled();
+    let first = analyze(&recording, &config).await;
+    let second = analyze(&recording, &config).await;
+    assert_eq!(first, second);
+
+    // the recording is only useful if there's something to compare
+    let analyzers: BTreeSet<&str> = first.iter().map(|(_, name, _, _)| name.as_str()).collect();
+    assert!(analyzers.len() >= 2, "only {analyzers:?} found anything");
+}
+
+#[tokio::test]
+async fn test_reset_harness_matches_fresh_harness() {
+    let recording = recording();
+    let mut config = everything_enabled();
+    // counts messages across the whole run, so it'd fire elsewhere on a
+    // second pass that carried on counting
+    config.fault_injection.enabled = true;
+    config.fault_injection.every_messages = 5;
+    let fresh = analyze(&recording, &config).await;
+
+    let mut harness = Harness::new_with_config(&config);
+    analyze_with(&mut harness, &recording).await;
+    harness.reset();
+    assert_eq!(analyze_with(&mut harness, &recording).await, fresh);
+}
+
+#[tokio::test]
+async fn test_reset_between_recordings() {
+    // each of these only means something after what's in the first
+    // recording: an identity request after authenticating, and weaker
+    // integrity protection than before
+    let first = QmdlFixtureBuilder::new()
+        .message(FixtureMessage::AuthenticationResponse)
+        .message(FixtureMessage::NasSecurityModeCommand {
+            eea: EEA2,
+            eia: EIA2,
+        })
+        .build();
+    let second = QmdlFixtureBuilder::new()
+        .message(FixtureMessage::IdentityRequest)
+        .message(FixtureMessage::NasSecurityModeCommand {
+            eea: EEA2,
+            eia: EIA1,
+        })
+        .build();
+    let config = everything_enabled();
+    let fresh = analyze(&second, &config).await;
+
+    let mut harness = Harness::new_with_config(&config);
+    analyze_with(&mut harness, &first).await;
+    let carried_over = analyze_with(&mut harness, &second).await;
+    assert_ne!(
+        carried_over, fresh,
+        "the first recording made no difference"
+    );
+
+    let mut harness = Harness::new_with_config(&config);
+    analyze_with(&mut harness, &first).await;
+    harness.reset();
+    assert_eq!(analyze_with(&mut harness, &second).await, fresh);
+}
+
+#[tokio::test]
+async fn test_analyzers_are_independent() {
+    let recording = recording();
+    let combined: BTreeSet<FoundEvent> = analyze(&recording, &everything_enabled())
+        .await
+        .into_iter()
+        .collect();
+
+    let individually: [fn(&mut AnalyzerConfig); 15] = [
+        |c| c.diagnostic_analyzer = true,
+        |c| c.connection_redirect_2g_downgrade = true,
+        |c| c.lte_sib6_and_7_downgrade = true,
+        |c| c.null_cipher = true,
+        |c| c.nas_null_cipher = true,
+        |c| c.nas_integrity_downgrade = true,
+        |c| c.wcdma_null_cipher = true,
+        |c| c.incomplete_sib = true,
+        |c| c.test_analyzer = true,
+        |c| c.imsi_requested = true,
+        |c| c.sfn_continuity = true,
+        |c| c.otdoa_request = true,
+        |c| c.sib3_reselection_anomaly = true,
+        |c| c.sib_neighbor_list = true,
+        |c| c.service_request_flood = true,
+    ];
+    for enable in individually {
+        let alone = analyze(&recording, &only(enable)).await;
+        for event in alone {
+            assert!(
+                combined.contains(&event),
+                "{event:?} was only found with the analyzer running alone"
+            );
+        }
+    }
+}